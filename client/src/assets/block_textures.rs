@@ -1,13 +1,28 @@
-use std::{collections::HashMap, io::prelude::*};
+use std::{collections::HashMap, path::Path};
 
 use bevy::{
     image::{CompressedImageFormats, ImageSampler, ImageType},
     prelude::*,
     render::{
         render_asset::RenderAssetUsages,
-        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        render_resource::{
+            Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+        },
     },
 };
+use serde::Deserialize;
+
+use crate::{
+    networking::NetworkClient,
+    rendering::diagnostics::{RendererInfo, BLOCK_TEXTURE_SIZE},
+};
+
+const BLOCK_TEXTURE_PATH: &str = "server_assets/active/textures/blocks/";
+// Written by the server, the textures in the order they should be in the texture array.
+const MANIFEST_PATH: &str = "server_assets/active/textures/block_textures.json";
+// 16x16 -> 8x8 -> 4x4 -> 2x2 -> 1x1
+const MIP_LEVELS: u32 = BLOCK_TEXTURE_SIZE.ilog2() + 1;
+const PIXEL_SIZE: usize = 4;
 
 /// A lookup table for the texture array. Inserted as ressource. Used while loading the block
 /// configs.
@@ -17,93 +32,299 @@ pub struct BlockTextures {
     // XXX: Even though the id is stored as u32 the texture array only has 19 bits of indices
     // because of bit packing in the shaders.
     texture_array_indices: HashMap<String, u32>,
+    /// How many textures are along each side of a layer of the texture array, 1 unless the gpu
+    /// doesn't allow enough layers, see RendererInfo::block_atlas_columns.
+    pub atlas_columns: u32,
 }
 
 impl BlockTextures {
     pub fn get(&self, name: &str) -> Option<&u32> {
         return self.texture_array_indices.get(name);
     }
+
+    /// The pixels of a texture in the texture array, rgba8 row by row.
+    pub fn pixels(&self, texture_array: &Image, index: u32) -> Option<Vec<u8>> {
+        let columns = self.atlas_columns as usize;
+        let texture_size = BLOCK_TEXTURE_SIZE as usize;
+        let layer_size = texture_size * columns;
+        // Each layer is followed by its mip levels
+        let layer_length: usize = (0..MIP_LEVELS as usize)
+            .map(|level| (layer_size >> level) * (layer_size >> level) * PIXEL_SIZE)
+            .sum();
+
+        let index = index as usize;
+        let layer = index / (columns * columns);
+        let tile = index % (columns * columns);
+        let tile_x = tile % columns * texture_size;
+        let tile_y = tile / columns * texture_size;
+        let row_length = texture_size * PIXEL_SIZE;
+
+        let mut pixels = Vec::with_capacity(texture_size * row_length);
+        for row in 0..texture_size {
+            let start = layer * layer_length + ((tile_y + row) * layer_size + tile_x) * PIXEL_SIZE;
+            pixels.extend(texture_array.data.get(start..start + row_length)?);
+        }
+
+        return Some(pixels);
+    }
+}
+
+#[derive(Deserialize)]
+struct BlockTextureManifest {
+    size: u32,
+    textures: Vec<ManifestTexture>,
+}
+
+#[derive(Deserialize)]
+struct ManifestTexture {
+    name: String,
+    frames: u32,
 }
 
-// TODO: All error should lead to disconnect
-//
 /// Stiches all the textures used by blocks into a texture array.
-pub fn load_block_textures(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
-    // size of 16*16 png 8 bit indexed png
-    let mut image_buffer = Vec::with_capacity(256);
-    let textures_path = "server_assets/active/textures/blocks";
+pub fn load_block_textures(
+    mut commands: Commands,
+    net: Res<NetworkClient>,
+    mut images: ResMut<Assets<Image>>,
+    mut renderer_info: ResMut<RendererInfo>,
+) {
+    // Servers that don't send a manifest get the textures in the order of the directory.
+    let manifest: BlockTextureManifest = match std::fs::File::open(MANIFEST_PATH) {
+        Ok(file) => match serde_json::from_reader(file) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                net.disconnect(format!(
+                    "Misconfigured assets: Could not read the block texture manifest at '{}'\nError: {}",
+                    MANIFEST_PATH, e
+                ));
+                return;
+            }
+        },
+        Err(_) => {
+            let directory = match std::fs::read_dir(BLOCK_TEXTURE_PATH) {
+                Ok(directory) => directory,
+                Err(e) => {
+                    net.disconnect(format!(
+                        "Misconfigured assets: Could not read the block textures at '{}'\nError: {}",
+                        BLOCK_TEXTURE_PATH, e
+                    ));
+                    return;
+                }
+            };
+
+            let mut names: Vec<String> = directory
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+
+            BlockTextureManifest {
+                size: BLOCK_TEXTURE_SIZE,
+                // Read from the image
+                textures: names
+                    .into_iter()
+                    .map(|name| ManifestTexture { name, frames: 0 })
+                    .collect(),
+            }
+        }
+    };
+
+    if manifest.size != BLOCK_TEXTURE_SIZE {
+        net.disconnect(format!(
+            "Misconfigured assets: The block textures are {} pixels wide, only {} is supported",
+            manifest.size, BLOCK_TEXTURE_SIZE
+        ));
+        return;
+    }
 
     let mut texture_array_indices: HashMap<String, u32> = HashMap::new();
+    // Each frame of each texture
+    let mut frames = Vec::new();
 
-    let mut final_image_data = Vec::new();
-    let mut id = 0;
-    for dir_entry in std::fs::read_dir(textures_path).unwrap() {
-        let path = match dir_entry {
-            Ok(d) => d.path(),
-            Err(e) => panic!(
-                "Error reading file path while loading textures.\nError: {}",
-                e
-            ),
+    for texture in manifest.textures {
+        let path = Path::new(BLOCK_TEXTURE_PATH).join(&texture.name);
+        let image = match std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|buffer| {
+                Image::from_buffer(
+                    &buffer,
+                    ImageType::MimeType("image/png"),
+                    CompressedImageFormats::NONE,
+                    true,
+                    ImageSampler::Default,
+                    RenderAssetUsages::default(),
+                )
+                .map_err(|e| e.to_string())
+            }) {
+            Ok(image) => image,
+            Err(e) => {
+                net.disconnect(format!(
+                    "Misconfigured assets: Failed to read the block texture at '{}'\nError: {}",
+                    path.display(),
+                    e
+                ));
+                return;
+            }
         };
 
-        let mut file = match std::fs::File::open(&path) {
-            Ok(f) => f,
-            Err(e) => panic!(
-                "Failed to open texture at {}\nError: {}",
-                path.to_string_lossy(),
-                e
-            ),
-        };
-        // TODO: handle error
-        image_buffer.clear();
-        file.read_to_end(&mut image_buffer).ok();
-
-        let image = Image::from_buffer(
-            &image_buffer,
-            ImageType::MimeType("image/png"),
-            CompressedImageFormats::NONE,
-            true,
-            ImageSampler::Default,
-            RenderAssetUsages::default(),
-        )
-        .unwrap();
-
-        // TODO: Panic not allowed
-        assert!(image.size()[0] == 16);
-
-        let id_increment = image.height() / 16;
-        final_image_data.extend(image.data);
-
-        let name = path.file_name().unwrap().to_string_lossy();
-        texture_array_indices.insert(name.to_string(), id);
-
-        id += id_increment;
+        let frame_count = image.height() / BLOCK_TEXTURE_SIZE;
+        if image.width() != BLOCK_TEXTURE_SIZE
+            || image.height() % BLOCK_TEXTURE_SIZE != 0
+            || frame_count == 0
+            || (texture.frames != 0 && texture.frames != frame_count)
+        {
+            net.disconnect(format!(
+                "Misconfigured assets: The block texture at '{}' is {}x{}, block textures must be \
+                {} pixels wide and a multiple of {} tall",
+                path.display(),
+                image.width(),
+                image.height(),
+                BLOCK_TEXTURE_SIZE,
+                BLOCK_TEXTURE_SIZE
+            ));
+            return;
+        }
+
+        texture_array_indices.insert(texture.name, frames.len() as u32);
+
+        let frame_length = (BLOCK_TEXTURE_SIZE * BLOCK_TEXTURE_SIZE) as usize * PIXEL_SIZE;
+        for frame in image.data.chunks_exact(frame_length) {
+            frames.push(generate_mips(frame));
+        }
     }
 
-    let final_image = Image::new(
-        Extent3d {
-            width: 16,
-            height: 16,
-            depth_or_array_layers: id,
-        },
-        TextureDimension::D2,
-        final_image_data,
-        TextureFormat::Rgba8UnormSrgb,
-        RenderAssetUsages::default(),
-    );
-    //image::save_buffer(
-    //    "/tmp/foo.png",
-    //    final_image.data.as_ref(),
-    //    final_image.texture_descriptor.size.width,
-    //    final_image.texture_descriptor.size.height
-    //        * final_image.texture_descriptor.size.depth_or_array_layers,
-    //    image::ColorType::Rgba8,
-    //).unwrap();
+    let id = frames.len() as u32;
+
+    let atlas_columns = match renderer_info.block_atlas_columns(id) {
+        Some(columns) => columns,
+        None => {
+            // TODO: Should disconnect with an explanation
+            error!(
+                "There are {} block textures, more than the gpu can fit, the last ones will be \
+                left out",
+                id
+            );
+            (renderer_info.max_texture_dimension_2d / BLOCK_TEXTURE_SIZE).max(1)
+        }
+    };
+
+    if atlas_columns > 1 && !renderer_info.fallbacks.block_texture_atlas {
+        renderer_info.fallbacks.block_texture_atlas = true;
+        let reason = format!(
+            "Block texture atlas: {} block textures, the gpu allows {} layers",
+            id, renderer_info.max_texture_array_layers
+        );
+        info!("{}", reason);
+        renderer_info.fallbacks.reasons.push(reason);
+    }
+
+    let layers = id
+        .div_ceil(atlas_columns * atlas_columns)
+        .clamp(1, renderer_info.max_texture_array_layers);
+    let layer_size = BLOCK_TEXTURE_SIZE * atlas_columns;
+
+    let mut final_image = Image::default();
+    final_image.data = pack_layers(&frames, layers, atlas_columns);
+    final_image.texture_descriptor.size = Extent3d {
+        width: layer_size,
+        height: layer_size,
+        depth_or_array_layers: layers,
+    };
+    final_image.texture_descriptor.dimension = TextureDimension::D2;
+    final_image.texture_descriptor.format = TextureFormat::Rgba8UnormSrgb;
+    final_image.texture_descriptor.mip_level_count = MIP_LEVELS;
+    // The view would be a plain 2d texture if there's only one layer
+    final_image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
 
     let block_textures = BlockTextures {
         handle: images.add(final_image),
         texture_array_indices,
+        atlas_columns,
     };
 
     commands.insert_resource(block_textures);
 }
+
+// The mip levels of a texture, from the texture itself down to 1x1. Each texture is scaled down on
+// its own, so that when they are packed into an atlas the textures next to each other don't bleed
+// into each other at a distance. Transparent pixels don't count towards the color, else the
+// edges of textures like leaves would darken.
+fn generate_mips(texture: &[u8]) -> Vec<Vec<u8>> {
+    let to_linear = |value: u8| (value as f32 / 255.0).powf(2.2);
+    let to_srgb = |value: f32| (value.powf(1.0 / 2.2) * 255.0).round() as u8;
+
+    let mut mips = vec![texture.to_vec()];
+    let mut size = BLOCK_TEXTURE_SIZE as usize;
+
+    while size > 1 {
+        let previous = mips.last().unwrap();
+        let half = size / 2;
+        let mut mip = vec![0; half * half * PIXEL_SIZE];
+
+        for y in 0..half {
+            for x in 0..half {
+                let mut color = [0.0; 3];
+                let mut alpha = 0.0;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let index = ((y * 2 + dy) * size + x * 2 + dx) * PIXEL_SIZE;
+                    let pixel_alpha = previous[index + 3] as f32 / 255.0;
+                    for i in 0..3 {
+                        color[i] += to_linear(previous[index + i]) * pixel_alpha;
+                    }
+                    alpha += pixel_alpha;
+                }
+
+                let index = (y * half + x) * PIXEL_SIZE;
+                if alpha > 0.0 {
+                    for i in 0..3 {
+                        mip[index + i] = to_srgb(color[i] / alpha);
+                    }
+                }
+                mip[index + 3] = (alpha / 4.0 * 255.0).round() as u8;
+            }
+        }
+
+        mips.push(mip);
+        size = half;
+    }
+
+    return mips;
+}
+
+// Lays out the textures in the layers of the texture array, with 'columns' by 'columns' textures
+// in each layer, left to right, top to bottom. Each layer is followed by its mip levels. Textures
+// that don't fit in the layers are left out.
+fn pack_layers(frames: &[Vec<Vec<u8>>], layers: u32, columns: u32) -> Vec<u8> {
+    let columns = columns as usize;
+    let textures_per_layer = columns * columns;
+
+    let mut data = Vec::new();
+    for layer in 0..layers as usize {
+        for level in 0..MIP_LEVELS as usize {
+            let texture_size = BLOCK_TEXTURE_SIZE as usize >> level;
+            let level_size = texture_size * columns;
+            let row_length = texture_size * PIXEL_SIZE;
+
+            let start = data.len();
+            data.resize(start + level_size * level_size * PIXEL_SIZE, 0);
+
+            for tile in 0..textures_per_layer {
+                let Some(mips) = frames.get(layer * textures_per_layer + tile) else {
+                    break;
+                };
+                let tile_x = tile % columns * texture_size;
+                let tile_y = tile / columns * texture_size;
+
+                for (row, pixels) in mips[level].chunks_exact(row_length).enumerate() {
+                    let offset = start + ((tile_y + row) * level_size + tile_x) * PIXEL_SIZE;
+                    data[offset..offset + row_length].copy_from_slice(pixels);
+                }
+            }
+        }
+    }
+
+    return data;
+}
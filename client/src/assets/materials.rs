@@ -4,7 +4,9 @@ use bevy::{color::Color, prelude::*, render::render_resource::Face};
 use serde::Deserialize;
 
 use crate::{
-    assets::BlockTextures, networking::NetworkClient, rendering::materials::BlockMaterial,
+    assets::{BlockTextures, CustomShaders},
+    networking::NetworkClient,
+    rendering::materials::BlockMaterial,
 };
 
 /// Stores all the loaded material handles.
@@ -44,6 +46,8 @@ struct MaterialConfig {
     pub fog_enabled: bool,
     pub transparency: String,
     pub animation_frames: u32,
+    /// Name of a shader snippet in the shader directory, block materials only
+    pub shader: Option<String>,
 }
 
 impl Default for MaterialConfig {
@@ -67,6 +71,7 @@ impl Default for MaterialConfig {
             fog_enabled: true,
             transparency: "opaque".to_owned(),
             animation_frames: 1,
+            shader: None,
         }
     }
 }
@@ -77,6 +82,7 @@ pub fn load_materials(
     net: Res<NetworkClient>,
     mut commands: Commands,
     block_textures: Res<BlockTextures>,
+    custom_shaders: Res<CustomShaders>,
     asset_server: Res<AssetServer>,
     mut block_materials: ResMut<Assets<BlockMaterial>>,
     mut standard_materials: ResMut<Assets<StandardMaterial>>,
@@ -165,6 +171,22 @@ pub fn load_materials(
             }
         };
 
+        let custom_shader = match &config.shader {
+            Some(name) => match custom_shaders.get_block_shader(name) {
+                Some(handle) => Some(handle.clone()),
+                None => {
+                    net.disconnect(format!(
+                        "Failed to read material configuration, path: {}\nError: There is no \
+                        shader named '{}'",
+                        file_path.to_string_lossy(),
+                        name
+                    ));
+                    return;
+                }
+            },
+            None => None,
+        };
+
         let handle = if config.r#type == "block" {
             let material = BlockMaterial {
                 base_color: config.base_color.into(),
@@ -190,6 +212,9 @@ pub fn load_materials(
                 depth_bias: 0.0,
                 texture_array: Some(block_textures.handle.clone()),
                 animation_frames: config.animation_frames,
+                atlas_columns: block_textures.atlas_columns,
+                custom_shader,
+                water: None,
             };
             block_materials.add(material).untyped()
         } else if config.r#type == "standard" {
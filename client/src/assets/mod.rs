@@ -3,9 +3,11 @@ use bevy::prelude::*;
 mod block_textures;
 mod materials;
 pub mod models;
+mod shaders;
 
 pub use block_textures::BlockTextures;
 pub use materials::Materials;
+pub use shaders::CustomShaders;
 
 // Assets are downloaded at connection over in 'src/networking.rs'. It matches the asset hash from
 // the server config with the clients stored assets, and requests them if it doesn't have them.
@@ -25,7 +27,8 @@ pub struct AssetPlugin;
 impl Plugin for AssetPlugin {
     fn build(&self, app: &mut App) {
         app.init_state::<AssetState>()
-            .add_plugins(models::ModelPlugin);
+            .add_plugins(models::ModelPlugin)
+            .add_plugins(shaders::ShaderPlugin);
 
         app.add_systems(
             OnEnter(AssetState::Loading),
@@ -33,6 +36,7 @@ impl Plugin for AssetPlugin {
                 block_textures::load_block_textures,
                 models::load_models,
                 crate::ui::server::key_bindings::load_key_bindings,
+                shaders::load_shaders,
                 apply_deferred,
                 materials::load_materials,
                 apply_deferred,
@@ -40,6 +44,8 @@ impl Plugin for AssetPlugin {
                 apply_deferred,
                 crate::ui::server::items::load_items,
                 crate::ui::server::load_interfaces,
+                crate::audio::music::load_music,
+                crate::ui::captions::load_captions,
                 finish_loading,
             )
                 .chain(),
@@ -0,0 +1,273 @@
+use std::{collections::HashMap, path::Path, time::SystemTime};
+
+use bevy::prelude::*;
+
+use crate::{
+    game_state::GameState,
+    networking::NetworkClient,
+    rendering::{
+        materials::BLOCK_FRAGMENT_SOURCE,
+        post_processing::{POST_PROCESSING_SHADER, POST_PROCESSING_SOURCE},
+    },
+    settings::Settings,
+};
+
+const SHADER_PATH: &str = "server_assets/active/shaders";
+/// Name of the post processing snippet in the shader directory
+const POST_PROCESSING_FILE: &str = "post_processing.wgsl";
+
+/// Servers can't ship whole shaders, only snippets that are added to the client's own. The
+/// snippet defines a function with a fixed signature that the client's shader calls, it can only
+/// use what that shader has bound, so it can't declare bindings of its own.
+///
+/// Block material snippets define:
+/// ```wgsl
+/// fn block_color(color: vec4<f32>, world_position: vec3<f32>, normal: vec3<f32>, uv: vec2<f32>) -> vec4<f32>
+/// ```
+/// which is given the color of the block after lighting. The post processing snippet defines:
+/// ```wgsl
+/// fn post_process(color: vec4<f32>, uv: vec2<f32>) -> vec4<f32>
+/// ```
+/// which is given the color of each pixel on the screen, it can call `screen(uv)` to read others.
+/// Both can use `globals.time` for the seconds since startup.
+pub struct ShaderPlugin;
+impl Plugin for ShaderPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CustomShaders::default())
+            .add_systems(Update, hot_reload.run_if(in_state(GameState::Playing)));
+    }
+}
+
+/// The shaders made from the snippets the server sent
+#[derive(Resource, Default)]
+pub struct CustomShaders {
+    /// Block material shaders by the name of the snippet they were made from.
+    blocks: HashMap<String, Handle<Shader>>,
+    /// The post processing shader, if the server has one
+    pub post_processing: Option<Handle<Shader>>,
+    // When each snippet was last changed, for hot reloading
+    modified: HashMap<String, SystemTime>,
+}
+
+impl CustomShaders {
+    pub fn get_block_shader(&self, name: &str) -> Option<&Handle<Shader>> {
+        return self.blocks.get(name);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SnippetKind {
+    Block,
+    PostProcessing,
+}
+
+impl SnippetKind {
+    fn from_file_name(name: &str) -> Self {
+        if name == POST_PROCESSING_FILE {
+            return Self::PostProcessing;
+        } else {
+            return Self::Block;
+        }
+    }
+
+    fn hook(&self) -> &'static str {
+        return match self {
+            Self::Block => "block_color",
+            Self::PostProcessing => "post_process",
+        };
+    }
+
+    // The client shader the snippet is added to
+    fn template(&self) -> &'static str {
+        return match self {
+            Self::Block => BLOCK_FRAGMENT_SOURCE,
+            Self::PostProcessing => POST_PROCESSING_SOURCE,
+        };
+    }
+}
+
+// Snippets are inserted into shaders that already have all of their bindings, anything that
+// would give them access to more is refused.
+const FORBIDDEN: [&str; 10] = [
+    "@group",
+    "@binding",
+    "@vertex",
+    "@fragment",
+    "@compute",
+    "var<storage",
+    "var<uniform",
+    "var<workgroup",
+    "#import",
+    "#define",
+];
+
+/// Snippets longer than this are refused
+const MAX_SNIPPET_LENGTH: usize = 64 * 1024;
+
+fn validate_snippet(source: &str, kind: SnippetKind) -> Result<(), String> {
+    if source.len() > MAX_SNIPPET_LENGTH {
+        return Err(format!(
+            "it is longer than the limit of {} bytes",
+            MAX_SNIPPET_LENGTH
+        ));
+    }
+
+    for forbidden in FORBIDDEN {
+        if source.contains(forbidden) {
+            return Err(format!("it contains '{}', which is not allowed", forbidden));
+        }
+    }
+
+    let hook = format!("fn {}(", kind.hook());
+    if !source
+        .lines()
+        .any(|line| line.trim_start().starts_with(&hook))
+    {
+        return Err(format!("it does not define the function '{}'", kind.hook()));
+    }
+
+    return Ok(());
+}
+
+// The snippet goes after the template, declarations in wgsl can be used before they appear.
+fn build_shader(path: &Path, source: &str, kind: SnippetKind) -> Shader {
+    let source = format!("{}\n{}", kind.template(), source);
+    return Shader::from_wgsl(source, path.to_string_lossy().into_owned());
+}
+
+pub fn load_shaders(
+    net: Res<NetworkClient>,
+    mut custom_shaders: ResMut<CustomShaders>,
+    mut shaders: ResMut<Assets<Shader>>,
+) {
+    *custom_shaders = CustomShaders::default();
+
+    // The shaders are optional
+    let Ok(directory) = std::fs::read_dir(SHADER_PATH) else {
+        return;
+    };
+
+    for dir_entry in directory {
+        let path = match dir_entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                net.disconnect(format!(
+                    "Encountered error reading file entries in directory: {}\n Error: {}",
+                    SHADER_PATH, e
+                ));
+                return;
+            }
+        };
+
+        if path.extension().is_none_or(|extension| extension != "wgsl") {
+            continue;
+        }
+
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let kind = SnippetKind::from_file_name(&name);
+
+        let source = match std::fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) => {
+                net.disconnect(format!(
+                    "Failed to read shader at: {}\nError: {}",
+                    path.display(),
+                    e
+                ));
+                return;
+            }
+        };
+
+        if let Err(e) = validate_snippet(&source, kind) {
+            net.disconnect(format!(
+                "The server's shader at '{}' is not valid, {}",
+                path.display(),
+                e
+            ));
+            return;
+        }
+
+        let shader = build_shader(&path, &source, kind);
+        match kind {
+            SnippetKind::Block => {
+                custom_shaders
+                    .blocks
+                    .insert(name.clone(), shaders.add(shader));
+            }
+            SnippetKind::PostProcessing => {
+                // The post processing pipeline is made at startup, it waits for the shader to be
+                // inserted here.
+                shaders.insert(&POST_PROCESSING_SHADER, shader);
+                custom_shaders.post_processing = Some(POST_PROCESSING_SHADER);
+            }
+        }
+
+        if let Some(modified) = modified_time(&path) {
+            custom_shaders.modified.insert(name, modified);
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    return std::fs::metadata(path).ok()?.modified().ok();
+}
+
+// Snippets that are changed on disk are loaded again, so that they can be edited while playing.
+// Invalid changes are logged and the last valid version is kept.
+fn hot_reload(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut custom_shaders: ResMut<CustomShaders>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut timer: Local<Timer>,
+) {
+    if !settings.shader_hot_reload {
+        return;
+    }
+
+    timer.tick(time.delta());
+    if !timer.finished() {
+        return;
+    }
+    *timer = Timer::from_seconds(1.0, TimerMode::Once);
+
+    let custom_shaders = custom_shaders.as_mut();
+    for (name, last_modified) in custom_shaders.modified.iter_mut() {
+        let path = Path::new(SHADER_PATH).join(name);
+        let Some(modified) = modified_time(&path) else {
+            continue;
+        };
+
+        if modified == *last_modified {
+            continue;
+        }
+        *last_modified = modified;
+
+        let kind = SnippetKind::from_file_name(name);
+        let handle = match kind {
+            SnippetKind::Block => custom_shaders.blocks.get(name),
+            SnippetKind::PostProcessing => custom_shaders.post_processing.as_ref(),
+        };
+        let Some(handle) = handle else {
+            continue;
+        };
+
+        let source = match std::fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to reload shader '{}': {}", path.display(), e);
+                continue;
+            }
+        };
+
+        if let Err(e) = validate_snippet(&source, kind) {
+            error!("Shader '{}' was not reloaded, {}", path.display(), e);
+            continue;
+        }
+
+        // Pipelines that use the shader are rebuilt when it changes, errors from compiling it
+        // are logged by bevy.
+        shaders.insert(handle, build_shader(&path, &source, kind));
+        info!("Reloaded shader '{}'", path.display());
+    }
+}
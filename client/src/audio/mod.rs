@@ -0,0 +1,274 @@
+use std::time::Duration;
+
+use bevy::{
+    audio::{AddAudioSource, Decodable, Source, Volume},
+    math::DVec3,
+    prelude::*,
+    render::primitives::Aabb,
+};
+use fmc_protocol::messages;
+
+use crate::{
+    game_state::GameState,
+    player::{Head, Player},
+    rendering::lighting::LightMap,
+    world::{
+        blocks::{Blocks, Friction},
+        world_map::WorldMap,
+        Origin,
+    },
+};
+
+pub mod music;
+
+pub(crate) const AUDIO_PATH: &str = "server_assets/active/audio/";
+
+// Frequencies above this are cut while underwater
+const UNDERWATER_CUTOFF: u32 = 800;
+// Places with less sunlight than this count as caves
+const CAVE_SUNLIGHT: u8 = 8;
+const CAVE_ECHO_DELAY: Duration = Duration::from_millis(90);
+const CAVE_ECHO_VOLUME: f32 = 0.35;
+
+pub struct AudioPlugin;
+impl Plugin for AudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ClientSideAudio { enabled: true })
+            .init_resource::<AudioEnvironment>()
+            .add_audio_source::<FilteredAudio>()
+            .add_plugins(music::MusicPlugin)
+            .add_systems(
+                Update,
+                (
+                    update_audio_environment,
+                    (play_sounds, play_walking_sound, play_filtered_sounds).chain(),
+                    toggle_client_side_sound,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+// Sounds are filtered by where the listener is so that caves and water sound distinct. The
+// server only sends where sounds are played, which environment it is in is decided from the
+// chunk data around the player.
+#[derive(Resource, Default, Clone, Copy, PartialEq)]
+pub(crate) enum AudioEnvironment {
+    #[default]
+    Open,
+    Underwater,
+    /// Out of reach of the sky
+    Cave,
+}
+
+// A sound played through the filter of the environment it was started in.
+#[derive(Asset, TypePath)]
+struct FilteredAudio {
+    source: AudioSource,
+    environment: AudioEnvironment,
+}
+
+impl Decodable for FilteredAudio {
+    type DecoderItem = f32;
+    type Decoder = Box<dyn Source<Item = f32> + Send>;
+
+    fn decoder(&self) -> Self::Decoder {
+        let source = self.source.decoder().convert_samples::<f32>();
+        match self.environment {
+            AudioEnvironment::Open => Box::new(source),
+            AudioEnvironment::Underwater => Box::new(source.low_pass(UNDERWATER_CUTOFF)),
+            AudioEnvironment::Cave => {
+                Box::new(source.buffered().reverb(CAVE_ECHO_DELAY, CAVE_ECHO_VOLUME))
+            }
+        }
+    }
+}
+
+// Sounds waiting for their audio to load before they can have the filter applied.
+#[derive(Component)]
+struct PendingFilter {
+    audio: Handle<AudioSource>,
+    environment: AudioEnvironment,
+}
+
+fn update_audio_environment(
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    light_map: Res<LightMap>,
+    head_query: Query<&GlobalTransform, With<Head>>,
+    mut environment: ResMut<AudioEnvironment>,
+) {
+    let Ok(transform) = head_query.get_single() else {
+        return;
+    };
+
+    let block_position = origin.to_global(transform.translation()).floor().as_ivec3();
+
+    let is_underwater = world_map
+        .get_block(&block_position)
+        .is_some_and(
+            |block_id| match Blocks::get().get_config(block_id).friction() {
+                Friction::Drag(drag) => drag.y > 0.4,
+                Friction::Static { .. } => false,
+            },
+        );
+
+    let new_environment = if is_underwater {
+        AudioEnvironment::Underwater
+    } else if light_map
+        .get_light(block_position)
+        .is_some_and(|light| light.sunlight() < CAVE_SUNLIGHT)
+    {
+        AudioEnvironment::Cave
+    } else {
+        AudioEnvironment::Open
+    };
+
+    environment.set_if_neq(new_environment);
+}
+
+// Open air sounds are played as is, the rest wait for their audio to load so it can be filtered.
+pub(crate) fn spawn_sound(
+    commands: &mut Commands,
+    environment: AudioEnvironment,
+    audio: Handle<AudioSource>,
+    transform: Transform,
+    settings: PlaybackSettings,
+) {
+    if environment == AudioEnvironment::Open {
+        commands.spawn((transform, AudioPlayer::<AudioSource>(audio), settings));
+    } else {
+        commands.spawn((transform, PendingFilter { audio, environment }, settings));
+    }
+}
+
+fn play_filtered_sounds(
+    mut commands: Commands,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut filtered_audio: ResMut<Assets<FilteredAudio>>,
+    pending_query: Query<(Entity, &PendingFilter)>,
+) {
+    for (entity, pending) in pending_query.iter() {
+        let Some(source) = audio_sources.get(&pending.audio) else {
+            continue;
+        };
+
+        let handle = filtered_audio.add(FilteredAudio {
+            source: source.clone(),
+            environment: pending.environment,
+        });
+
+        commands
+            .entity(entity)
+            .remove::<PendingFilter>()
+            .insert(AudioPlayer::<FilteredAudio>(handle));
+    }
+}
+
+#[derive(Resource)]
+struct ClientSideAudio {
+    enabled: bool,
+}
+
+fn play_sounds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    origin: Res<Origin>,
+    environment: Res<AudioEnvironment>,
+    mut sound_events: EventReader<messages::Sound>,
+) {
+    for sound in sound_events.read() {
+        spawn_sound(
+            &mut commands,
+            *environment,
+            asset_server.load(AUDIO_PATH.to_owned() + &sound.sound),
+            Transform::from_translation(origin.to_local(sound.position.unwrap_or(DVec3::ZERO))),
+            PlaybackSettings::DESPAWN
+                .with_spatial(sound.position.is_some())
+                .with_speed(sound.speed)
+                .with_volume(Volume::new(sound.volume.clamp(0.0, 1.0))),
+        );
+    }
+}
+
+fn toggle_client_side_sound(
+    mut client_side_audio: ResMut<ClientSideAudio>,
+    mut toggle_events: EventReader<messages::EnableClientAudio>,
+) {
+    for event in toggle_events.read() {
+        client_side_audio.enabled = event.0;
+    }
+}
+
+// TODO: Try sending it from the server?
+// Walking sound for the player is the only sound that is handled client side. For better responsivness.
+fn play_walking_sound(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    environment: Res<AudioEnvironment>,
+    client_side_audio: Res<ClientSideAudio>,
+    player_position: Query<(&GlobalTransform, &Aabb), (With<Player>, Changed<GlobalTransform>)>,
+    mut last_position: Local<DVec3>,
+    mut distance: Local<f64>,
+    mut last_sound_index: Local<usize>,
+) {
+    if !client_side_audio.enabled {
+        return;
+    }
+
+    let Ok((global_transform, aabb)) = player_position.get_single() else {
+        return;
+    };
+
+    let position = origin.to_global(global_transform.translation());
+    *distance += position.distance(*last_position).abs();
+    *last_position = position;
+
+    if *distance < 2.3 {
+        return;
+    }
+
+    let mut center = aabb.half_extents;
+    center.y = -0.05;
+    // get block directly under player
+    let block_position = (position + center.as_dvec3()).floor().as_ivec3();
+
+    let Some(block_id) = world_map.get_block(&block_position) else {
+        return;
+    };
+
+    let blocks = Blocks::get();
+    let step_sounds = blocks.get_config(block_id).step_sounds();
+
+    if step_sounds.len() == 0 {
+        return;
+    }
+
+    let mut index = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as usize
+        % step_sounds.len();
+
+    // Don't play the same sound twice
+    if index == *last_sound_index {
+        index += 1;
+        index = index % step_sounds.len().max(1);
+    }
+
+    *last_sound_index = index;
+    *distance = 0.0;
+
+    spawn_sound(
+        &mut commands,
+        *environment,
+        asset_server.load(AUDIO_PATH.to_owned() + &step_sounds[index]),
+        Transform::from_translation(global_transform.translation() + Vec3::from(aabb.center)),
+        PlaybackSettings::DESPAWN
+            .with_spatial(false)
+            .with_volume(Volume::new(0.1)),
+    );
+}
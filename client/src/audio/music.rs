@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    audio::{AudioSinkPlayback, Volume},
+    prelude::*,
+};
+use fmc_protocol::messages;
+use serde::Deserialize;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, NetworkClient, ServerProperty},
+    settings::Settings,
+    utils::Rng,
+};
+
+const MUSIC_PATH: &str = "server_assets/active/audio/music/";
+// Seconds it takes to fade one track out and the next in
+const FADE_TIME: f32 = 3.0;
+// Seconds of silence after a track has finished before the next one starts
+const TRACK_GAP: f32 = 30.0;
+
+// The server ships the tracks and a "music.json" that maps each track to the tags of the
+// situations it fits, e.g. "day", "forest" or "combat". The server tells the client which tags
+// apply to the player through the "music" property, "day" or "night" is added from the time.
+// Tracks are played when the player is in all of their tags, the tracks with the most tags
+// first.
+pub struct MusicPlugin;
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Playlist>()
+            .init_resource::<MusicState>()
+            // Properties are sent as soon as the player joins
+            .add_systems(Update, handle_music_property)
+            .add_systems(OnExit(GameState::Playing), stop_music)
+            .add_systems(
+                Update,
+                (update_time_of_day, play_music, fade_tracks)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+#[derive(Deserialize)]
+struct MusicJson {
+    // Map from file name to tags
+    tracks: HashMap<String, Vec<String>>,
+}
+
+struct Track {
+    file: String,
+    tags: HashSet<String>,
+}
+
+// All the tracks the server has, sorted by file name so that they play in a consistent order when
+// not shuffled.
+#[derive(Resource, Default)]
+struct Playlist {
+    tracks: Vec<Track>,
+}
+
+#[derive(Resource)]
+struct MusicState {
+    // Tags sent by the server
+    server_tags: HashSet<String>,
+    is_day: bool,
+    // Index of the track that is playing, or played last
+    current: Option<usize>,
+    // Seconds left until the next track can start
+    gap: f32,
+    rng: Rng,
+}
+
+impl Default for MusicState {
+    fn default() -> Self {
+        Self {
+            server_tags: HashSet::new(),
+            is_day: true,
+            current: None,
+            gap: 0.0,
+            rng: Rng::new(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            ),
+        }
+    }
+}
+
+impl MusicState {
+    fn fits(&self, track: &Track) -> bool {
+        return track.tags.iter().all(|tag| match tag.as_str() {
+            "day" => self.is_day,
+            "night" => !self.is_day,
+            tag => self.server_tags.contains(tag),
+        });
+    }
+}
+
+#[derive(Component)]
+struct MusicTrack {
+    index: usize,
+    // Current volume of the fade, from 0 to 1
+    fade: f32,
+    is_fading_out: bool,
+}
+
+// Music is optional, servers without any just don't have the file.
+pub fn load_music(mut commands: Commands, net: Res<NetworkClient>) {
+    let path = MUSIC_PATH.to_owned() + "music.json";
+    let Ok(file) = std::fs::File::open(&path) else {
+        commands.insert_resource(Playlist::default());
+        return;
+    };
+
+    let json: MusicJson = match serde_json::from_reader(file) {
+        Ok(json) => json,
+        Err(e) => {
+            net.disconnect(&format!(
+                "Misconfigured assets: failed to read music config at: {}\nError: {}",
+                path, e
+            ));
+            return;
+        }
+    };
+
+    let mut tracks: Vec<Track> = json
+        .tracks
+        .into_iter()
+        .map(|(file, tags)| Track {
+            file,
+            tags: tags.into_iter().collect(),
+        })
+        .collect();
+    tracks.sort_by(|a, b| a.file.cmp(&b.file));
+
+    commands.insert_resource(Playlist { tracks });
+}
+
+fn handle_music_property(
+    mut music_state: ResMut<MusicState>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::MUSIC {
+            continue;
+        }
+
+        music_state.server_tags = property
+            .parse::<Vec<String>>()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|tag| !tag.is_empty())
+            .collect();
+    }
+}
+
+// The sun is above the horizon for the first half of the rotation, same as the sky.
+fn update_time_of_day(
+    mut music_state: ResMut<MusicState>,
+    mut time_events: EventReader<messages::Time>,
+) {
+    if let Some(time) = time_events.read().last() {
+        let is_day = time.angle.sin() >= 0.0;
+        if music_state.is_day != is_day {
+            music_state.is_day = is_day;
+        }
+    }
+}
+
+fn stop_music(
+    mut commands: Commands,
+    mut music_state: ResMut<MusicState>,
+    track_query: Query<Entity, With<MusicTrack>>,
+) {
+    for entity in track_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    *music_state = MusicState::default();
+}
+
+fn play_music(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+    playlist: Res<Playlist>,
+    mut music_state: ResMut<MusicState>,
+    mut track_query: Query<&mut MusicTrack>,
+) {
+    if playlist.tracks.is_empty() {
+        return;
+    }
+
+    let playing = track_query.iter_mut().find(|track| !track.is_fading_out);
+
+    // Fade out the track when the situation no longer fits it, a new one is started right away.
+    if let Some(mut playing) = playing {
+        if music_state.fits(&playlist.tracks[playing.index]) {
+            return;
+        }
+        playing.is_fading_out = true;
+        music_state.gap = 0.0;
+    } else if music_state.current.is_some() && music_state.gap > 0.0 {
+        music_state.gap -= time.delta_secs();
+        return;
+    }
+
+    // The most specific tracks that fit
+    let Some(most_tags) = playlist
+        .tracks
+        .iter()
+        .filter(|track| music_state.fits(track))
+        .map(|track| track.tags.len())
+        .max()
+    else {
+        return;
+    };
+    let candidates: Vec<usize> = (0..playlist.tracks.len())
+        .filter(|index| {
+            let track = &playlist.tracks[*index];
+            track.tags.len() == most_tags && music_state.fits(track)
+        })
+        .collect();
+
+    let index = if settings.music_shuffle {
+        let mut pick = candidates[music_state.rng.next_u32() as usize % candidates.len()];
+        // Avoid playing the same track twice in a row
+        if Some(pick) == music_state.current && candidates.len() > 1 {
+            pick = candidates
+                [(candidates.iter().position(|i| *i == pick).unwrap() + 1) % candidates.len()];
+        }
+        pick
+    } else {
+        // The next track after the one that played last
+        candidates
+            .iter()
+            .copied()
+            .find(|index| music_state.current.is_some_and(|current| *index > current))
+            .unwrap_or(candidates[0])
+    };
+
+    music_state.current = Some(index);
+    music_state.gap = TRACK_GAP;
+
+    commands.spawn((
+        MusicTrack {
+            index,
+            fade: 0.0,
+            is_fading_out: false,
+        },
+        AudioPlayer::<AudioSource>(
+            asset_server.load(MUSIC_PATH.to_owned() + &playlist.tracks[index].file),
+        ),
+        PlaybackSettings::DESPAWN.with_volume(Volume::new(0.0)),
+    ));
+}
+
+fn fade_tracks(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut track_query: Query<(Entity, &mut MusicTrack, Option<&AudioSink>)>,
+) {
+    let step = time.delta_secs() / FADE_TIME;
+
+    for (entity, mut track, sink) in track_query.iter_mut() {
+        if track.is_fading_out {
+            track.fade -= step;
+            if track.fade <= 0.0 {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        } else if track.fade < 1.0 {
+            track.fade = (track.fade + step).min(1.0);
+        }
+
+        // The sink is added once the audio has loaded
+        if let Some(sink) = sink {
+            sink.set_volume(track.fade * settings.music_volume * settings.volume);
+        }
+    }
+}
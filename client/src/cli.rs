@@ -1,10 +1,12 @@
-use std::io;
+use std::{io, path::PathBuf};
 
 use clap::Parser;
 
 use crate::modding::server::{Mod, ServerBuildConfig};
 
-pub fn parse() -> bool {
+/// Returns the arguments the client should be launched with, or None if a sub command was run
+/// instead.
+pub fn parse() -> Option<Cli> {
     let cli = Cli::parse();
 
     if let Some(sub_command) = cli.sub_command {
@@ -17,7 +19,7 @@ pub fn parse() -> bool {
                         Ok(s) => s,
                         Err(e) => {
                             println!("Encountered error reading server configuration:\n{e}");
-                            return true;
+                            return None;
                         }
                     };
 
@@ -26,15 +28,34 @@ pub fn parse() -> bool {
             }
         }
 
-        return true;
+        return None;
     } else {
-        return false;
+        return Some(cli);
     }
 }
+
 #[derive(clap::Parser)]
 pub struct Cli {
     #[command(subcommand)]
     sub_command: Option<SubCommands>,
+    #[arg(
+        long,
+        help = "Use the most compatible renderer settings, try this if the screen stays black"
+    )]
+    pub safe_mode: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "play_input",
+        help = "Record keyboard and mouse input to a file"
+    )]
+    pub record_input: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Play back recorded input, the client exits with an error if the menus don't end up as they were recorded"
+    )]
+    pub play_input: Option<PathBuf>,
 }
 
 #[derive(clap::Subcommand)]
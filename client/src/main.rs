@@ -2,6 +2,10 @@ use bevy::{
     audio::{AudioPlugin, SpatialScale, Volume},
     // diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
+    render::{
+        settings::{RenderCreation, WgpuSettings, WgpuSettingsPriority},
+        RenderPlugin,
+    },
     window::WindowFocused,
 };
 
@@ -21,9 +25,20 @@ mod utils;
 mod world;
 
 fn main() {
-    if cli::parse() {
+    let Some(cli) = cli::parse() else {
         return;
-    }
+    };
+
+    // Safe mode picks the adapter with the most compatible limits, instead of the one with the
+    // most features.
+    let wgpu_settings = if cli.safe_mode {
+        WgpuSettings {
+            priority: WgpuSettingsPriority::Compatibility,
+            ..default()
+        }
+    } else {
+        WgpuSettings::default()
+    };
 
     App::new()
         //.insert_resource(Msaa { samples: 4 })
@@ -35,6 +50,10 @@ fn main() {
                     ..default()
                 })
                 .set(ImagePlugin::default_nearest())
+                .set(RenderPlugin {
+                    render_creation: RenderCreation::Automatic(wgpu_settings),
+                    ..default()
+                })
                 .set(AudioPlugin {
                     global_volume: GlobalVolume {
                         volume: Volume::new(1.0),
@@ -49,11 +68,17 @@ fn main() {
         .add_plugins(audio::AudioPlugin)
         .add_plugins(particles::ParticlePlugin)
         .add_plugins(game_state::GameStatePlugin)
-        .add_plugins(rendering::RenderingPlugin)
+        .add_plugins(rendering::RenderingPlugin {
+            safe_mode: cli.safe_mode,
+        })
         .add_plugins(modding::ModPlugin)
         .add_plugins(player::PlayerPlugin)
         .add_plugins(world::WorldPlugin)
         .add_plugins(ui::UiPlugin)
+        .add_plugins(ui::input_recording::InputRecordingPlugin {
+            record: cli.record_input,
+            playback: cli.play_input,
+        })
         .add_plugins(settings::SettingsPlugin)
         .add_plugins(singleplayer::SinglePlayerPlugin)
         .add_systems(Update, fix_keys_not_released_on_focus_loss)
@@ -0,0 +1,164 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+};
+
+use crate::game_state::GameState;
+
+use super::{properties, NetworkClient, ServerProperty};
+
+/// Round trip time to the server in milliseconds
+pub const RTT: DiagnosticPath = DiagnosticPath::const_new("network/rtt");
+
+// Seconds between each time the client measures the round trip time
+const PING_INTERVAL: f64 = 1.0;
+// Answers that took this many times the jitter longer than usual are left out of the clock
+// estimate, they were held up on the way and would skew it.
+const OUTLIER_JITTERS: f64 = 2.0;
+
+// TODO: The protocol has no messages for measuring latency, until it does they are sent as
+// properties. Both sides send "ping" with the time on their own clock in seconds, and the other
+// side answers right away with "pong", the time from the ping and the time on its own clock.
+//
+// The server's clock is estimated from the answers, assuming the answer took half the round
+// trip to get back.
+pub(super) struct LatencyPlugin;
+impl Plugin for LatencyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Latency>()
+            .init_resource::<ServerClock>()
+            .register_diagnostic(Diagnostic::new(RTT).with_suffix(" ms"))
+            .add_systems(
+                OnExit(GameState::Playing),
+                |mut latency: ResMut<Latency>, mut server_clock: ResMut<ServerClock>| {
+                    *latency = Latency::default();
+                    *server_clock = ServerClock::default();
+                },
+            )
+            .add_systems(
+                PreUpdate,
+                (handle_latency_properties, send_pings)
+                    .after(super::read_messages)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// Round trip time to the server
+#[derive(Resource, Default, Debug)]
+pub struct Latency {
+    /// Smoothed round trip time in seconds
+    pub rtt: f64,
+    /// How much the round trip time varies, in seconds
+    pub jitter: f64,
+    samples: u32,
+}
+
+impl Latency {
+    // Same smoothing as TCP uses for its retransmission timer, RFC 6298.
+    fn add_sample(&mut self, rtt: f64) {
+        if self.samples == 0 {
+            self.rtt = rtt;
+            self.jitter = rtt / 2.0;
+        } else {
+            self.jitter = 0.75 * self.jitter + 0.25 * (self.rtt - rtt).abs();
+            self.rtt = 0.875 * self.rtt + 0.125 * rtt;
+        }
+        self.samples += 1;
+    }
+
+    /// Half the round trip, how long it takes a message from the server to arrive.
+    pub fn one_way(&self) -> f64 {
+        return self.rtt / 2.0;
+    }
+}
+
+/// Estimate of the server's clock, for timing things the server sends by when they happened on
+/// the server instead of when they arrived.
+#[derive(Resource, Default, Debug)]
+pub struct ServerClock {
+    // Added to the client's clock to get the server's
+    offset: f64,
+    synced: bool,
+}
+
+impl ServerClock {
+    /// The time on the server's clock in seconds. Until the first answer from the server, it is
+    /// the client's own clock.
+    pub fn now(&self, time: &Time<Real>) -> f64 {
+        return time.elapsed_secs_f64() + self.offset;
+    }
+
+    /// If the clock has been estimated from the server's answers yet
+    pub fn is_synced(&self) -> bool {
+        return self.synced;
+    }
+}
+
+fn handle_latency_properties(
+    net: Res<NetworkClient>,
+    time: Res<Time<Real>>,
+    mut latency: ResMut<Latency>,
+    mut server_clock: ResMut<ServerClock>,
+    mut diagnostics: Diagnostics,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    for property in property_events.read() {
+        match property.name.as_str() {
+            properties::PING => {
+                let Some(sent) = property.parse::<f64>() else {
+                    continue;
+                };
+
+                net.send_property(
+                    properties::PONG,
+                    &properties::Pong {
+                        sent,
+                        received: now,
+                    },
+                );
+            }
+            properties::PONG => {
+                let Some(properties::Pong {
+                    sent,
+                    received: server_time,
+                }) = property.parse()
+                else {
+                    continue;
+                };
+
+                let rtt = now - sent;
+                if rtt < 0.0 {
+                    continue;
+                }
+
+                let is_outlier =
+                    latency.samples > 0 && rtt > latency.rtt + OUTLIER_JITTERS * latency.jitter;
+
+                latency.add_sample(rtt);
+                diagnostics.add_measurement(&RTT, || rtt * 1000.0);
+
+                let offset = server_time + rtt / 2.0 - now;
+                if !server_clock.synced {
+                    server_clock.offset = offset;
+                    server_clock.synced = true;
+                } else if !is_outlier {
+                    server_clock.offset = 0.875 * server_clock.offset + 0.125 * offset;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn send_pings(net: Res<NetworkClient>, time: Res<Time<Real>>, mut last_ping: Local<Option<f64>>) {
+    let now = time.elapsed_secs_f64();
+    if last_ping.is_some_and(|last_ping| now - last_ping < PING_INTERVAL) {
+        return;
+    }
+    *last_ping = Some(now);
+
+    net.send_property(properties::PING, &now);
+}
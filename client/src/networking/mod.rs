@@ -0,0 +1,1316 @@
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    ecs::system::SystemParam,
+    prelude::*,
+    tasks::{futures_lite::future, AsyncComputeTaskPool, IoTaskPool, Task},
+};
+use fmc_protocol::{messages, MessageType, ServerBound};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{assets::AssetState, game_state::GameState, settings::Settings};
+
+mod latency;
+pub mod properties;
+
+pub use latency::{Latency, ServerClock, RTT};
+
+// Message length (4 bytes)
+const COMPRESSION_HEADER_SIZE: usize = 4;
+// MessageType (1 byte) + message length (4 bytes)
+const MESSAGE_HEADER_SIZE: usize = 5;
+// How many times the client tries to reconnect after losing the connection before giving up
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+// Seconds before the first reconnection attempt, doubled for each attempt after it
+const RECONNECT_BACKOFF: f32 = 1.0;
+
+/// Bytes per second received from the server, as sent, before they are decompressed.
+pub const BYTES_RECEIVED: DiagnosticPath = DiagnosticPath::const_new("network/bytes_received");
+/// Bytes per second sent to the server
+pub const BYTES_SENT: DiagnosticPath = DiagnosticPath::const_new("network/bytes_sent");
+// Asset files from all servers, stored by the hash of their content
+const ASSET_CACHE_PATH: &str = "./server_assets/cache";
+// Written to a server's asset directory when all of its files are there, a download that was cut
+// short leaves it without.
+const ASSETS_COMPLETE_PATH: &str = "./server_assets/active/.complete";
+
+pub struct ClientPlugin;
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(latency::LatencyPlugin)
+            .insert_resource(Identity::read_from_file())
+            .insert_resource(NetworkClient::new())
+            .add_event::<messages::AssetResponse>()
+            .add_event::<messages::Disconnect>()
+            .add_event::<messages::ServerConfig>()
+            .add_event::<messages::Time>()
+            .add_event::<messages::Chunk>()
+            .add_event::<messages::BlockUpdates>()
+            .add_event::<messages::DeleteModel>()
+            .add_event::<messages::ModelPlayAnimation>()
+            .add_event::<messages::ModelUpdateAsset>()
+            .add_event::<messages::ModelUpdateTransform>()
+            .add_event::<messages::NewModel>()
+            .add_event::<messages::SpawnCustomModel>()
+            .add_event::<messages::PlayerAabb>()
+            .add_event::<messages::PlayerCameraPosition>()
+            .add_event::<messages::PlayerCameraRotation>()
+            .add_event::<messages::PlayerPosition>()
+            .add_event::<messages::InterfaceItemBoxUpdate>()
+            .add_event::<messages::InterfaceNodeVisibilityUpdate>()
+            .add_event::<messages::InterfaceTextUpdate>()
+            .add_event::<messages::InterfaceVisibilityUpdate>()
+            .add_event::<messages::EnableClientAudio>()
+            .add_event::<messages::Sound>()
+            .add_event::<messages::ParticleEffect>()
+            .add_event::<ServerProperty>()
+            .register_diagnostic(Diagnostic::new(BYTES_RECEIVED).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(BYTES_SENT).with_suffix(" B/s"))
+            .add_systems(OnEnter(GameState::Playing), send_client_ready)
+            .add_systems(PostUpdate, measure_bandwidth)
+            .add_systems(
+                Update,
+                (
+                    start_transfer.run_if(resource_exists::<PendingTransfer>),
+                    reconnect.run_if(resource_exists::<Reconnecting>),
+                )
+                    .run_if(in_state(GameState::Launcher)),
+            )
+            .add_systems(OnEnter(GameState::Playing), |mut commands: Commands| {
+                commands.remove_resource::<Reconnecting>()
+            })
+            .add_systems(
+                PreUpdate,
+                (
+                    read_messages.run_if(in_state(GameState::Playing)),
+                    (connect, initialize_connection).run_if(in_state(GameState::Connecting)),
+                    (
+                        register_client_disconnect_events.before(disconnect),
+                        disconnect,
+                    )
+                        .run_if(not(in_state(GameState::Launcher))),
+                ),
+            );
+    }
+}
+
+struct ConcurrentQueue {
+    sender: crossbeam::Sender<String>,
+    receiver: crossbeam::Receiver<String>,
+}
+
+impl ConcurrentQueue {
+    fn new() -> Self {
+        let (sender, receiver) = crossbeam::bounded(1);
+        Self { sender, receiver }
+    }
+
+    fn push(&self, value: String) -> Result<(), String> {
+        self.sender.try_send(value).map_err(|e| e.into_inner())
+    }
+
+    fn pop(&self) -> Result<String, ()> {
+        self.receiver.try_recv().map_err(|e| ())
+    }
+}
+
+// TODO: Implement the buffers as some Read/Write impl it's too much to keep track of.
+#[derive(Resource)]
+pub struct NetworkClient {
+    // Address of the server that was last connected to
+    address: Option<SocketAddr>,
+    connection: Option<TcpStream>,
+    connection_task: Option<Task<std::io::Result<TcpStream>>>,
+    // Requests a join token for the server from the account service, the client identifies
+    // itself when it is done.
+    join_task: Option<Task<Result<String, String>>>,
+    disconnect_events: ConcurrentQueue,
+    // buffer for connection reads, compressed
+    read_buffer: Vec<u8>,
+    read_cursor: usize,
+    read_bytes: usize,
+    // Buffer for decompressed messages
+    message_buffer: Vec<u8>,
+    message_cursor: usize,
+    message_bytes: usize,
+    // Counted until they are measured, see 'measure_bandwidth'. Sending only borrows the client,
+    // so they are atomic.
+    bytes_received: AtomicUsize,
+    bytes_sent: AtomicUsize,
+}
+
+impl NetworkClient {
+    fn new() -> Self {
+        Self {
+            address: None,
+            connection: None,
+            connection_task: None,
+            join_task: None,
+            disconnect_events: ConcurrentQueue::new(),
+            read_buffer: vec![0; 1024 * 1024],
+            read_cursor: 0,
+            read_bytes: 0,
+            message_buffer: vec![0; 1024 * 1024],
+            message_cursor: 0,
+            message_bytes: 0,
+            bytes_received: AtomicUsize::new(0),
+            bytes_sent: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn connect(&mut self, addr: SocketAddr) {
+        if self.connection.is_some() || self.connection_task.is_some() {
+            panic!("Already connected");
+        }
+
+        self.address = Some(addr);
+
+        self.connection_task = Some(AsyncComputeTaskPool::get().spawn(async move {
+            TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(10)).and_then(|tcp| {
+                tcp.set_nonblocking(true)?;
+                tcp.set_nodelay(true)?;
+                Ok(tcp)
+            })
+        }));
+    }
+
+    pub fn send_message<T: ServerBound + Serialize>(&self, message: T) {
+        let size = bincode::serialized_size(&message).unwrap() as u32;
+        let mut serialized = vec![0; MESSAGE_HEADER_SIZE + size as usize];
+
+        serialized[0] = T::TYPE as u8;
+        serialized[1..5].copy_from_slice(&size.to_le_bytes());
+
+        bincode::serialize_into(&mut serialized[5..], &message).unwrap();
+
+        let mut connection = self.connection.as_ref().unwrap();
+        match connection.write(&serialized) {
+            Ok(size) => {
+                self.bytes_sent.fetch_add(size, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.connection_lost(e.kind().to_string());
+            }
+        }
+    }
+
+    // TODO: AssetRequest has no fields, the request is sent as its payload until the protocol
+    // has room for it. Servers that don't know of it send the whole archive.
+    fn send_asset_request(&self, request: AssetDownloadRequest) {
+        let payload = bincode::serialize(&request).unwrap();
+        let mut serialized = Vec::with_capacity(MESSAGE_HEADER_SIZE + payload.len());
+        serialized.push(MessageType::AssetRequest as u8);
+        serialized.extend((payload.len() as u32).to_le_bytes());
+        serialized.extend(payload);
+
+        let mut connection = self.connection.as_ref().unwrap();
+        if let Err(e) = connection.write(&serialized) {
+            self.connection_lost(e.kind().to_string());
+        }
+    }
+
+    /// Tell the server about a property of the player, e.g. that it is sneaking. See
+    /// `properties` for the names and their values.
+    pub fn send_property<T: Serialize + ?Sized>(&self, name: &str, value: &T) {
+        self.send_message(messages::InterfaceTextInput {
+            interface_path: PROPERTY_PREFIX.to_owned() + name,
+            text: serde_json::to_string(value).unwrap(),
+        });
+    }
+
+    pub fn disconnect<T: AsRef<str>>(&self, message: T) {
+        if self.connection.is_none() && self.connection_task.is_none() {
+            return;
+        }
+
+        dbg!(message.as_ref());
+        // Disconnect might be called many times as errors cascade, but only the first message will
+        // register as that will be the primary cause. The event queue is only has capacity for one
+        // element.
+        self.disconnect_events
+            .push(message.as_ref().to_string())
+            .ok();
+    }
+
+    // The connection failed, as opposed to the server or client closing it. The client will try to
+    // reconnect.
+    fn connection_lost<T: AsRef<str>>(&self, message: T) {
+        self.disconnect(CONNECTION_LOST_PREFIX.to_owned() + message.as_ref());
+    }
+
+    // Clear what was left over from the last connection
+    fn clear_buffers(&mut self) {
+        self.read_cursor = 0;
+        self.read_bytes = 0;
+        self.message_cursor = 0;
+        self.message_bytes = 0;
+    }
+
+    /// The address of the server that was last connected to.
+    pub fn address(&self) -> Option<SocketAddr> {
+        return self.address;
+    }
+
+    /// Directory for files kept separately for each server, e.g. waypoints. It is named after the
+    /// address of the server that was last connected to, None if there hasn't been one.
+    pub fn server_data_directory(&self) -> Option<std::path::PathBuf> {
+        // ':' is not allowed in windows paths
+        let server = self.address?.to_string().replace(':', "_");
+        return Some(std::path::PathBuf::from("./server_data").join(server));
+    }
+
+    fn is_connected(&self) -> bool {
+        return self.connection.is_some();
+    }
+
+    fn is_connecting(&self) -> bool {
+        return self.connection_task.is_some();
+    }
+
+    fn read_packets(&mut self) {
+        // Move the remaning content of the read buffer to the beginning.
+        self.read_buffer
+            .copy_within(self.read_cursor..self.read_bytes, 0);
+        self.read_bytes -= self.read_cursor;
+        self.read_cursor = 0;
+
+        let connection = self.connection.as_mut().unwrap();
+        let size = match connection.read(&mut self.read_buffer[self.read_bytes..]) {
+            Ok(size) => size,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+            Err(e) => {
+                self.connection_lost(e.kind().to_string());
+                return;
+            }
+        };
+        self.read_bytes += size;
+        *self.bytes_received.get_mut() += size;
+    }
+
+    // Try to decompress a packet if there aren't any messages already available
+    fn try_decompress_packet(&mut self) -> bool {
+        if self.message_bytes - self.message_cursor > MESSAGE_HEADER_SIZE {
+            let message_size = u32::from_le_bytes(
+                self.message_buffer
+                    [self.message_cursor + 1..self.message_cursor + MESSAGE_HEADER_SIZE]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+
+            // There is already a message available
+            if message_size < self.message_bytes - self.message_cursor + MESSAGE_HEADER_SIZE {
+                return true;
+            }
+        }
+
+        if self.read_bytes - self.read_cursor <= COMPRESSION_HEADER_SIZE {
+            return false;
+        }
+
+        let packet_length = u32::from_le_bytes(
+            self.read_buffer[self.read_cursor..self.read_cursor + COMPRESSION_HEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+
+        // Return if the packet hasn't arrived yet
+        if packet_length > self.read_bytes - self.read_cursor + COMPRESSION_HEADER_SIZE {
+            return false;
+        }
+
+        // Shift remaining message bytes to beginning to prepare for writing
+        self.message_buffer
+            .copy_within(self.message_cursor..self.message_bytes, 0);
+        self.message_bytes -= self.message_cursor;
+        self.message_cursor = 0;
+
+        self.read_cursor += COMPRESSION_HEADER_SIZE;
+
+        // This is a copy of zstd::stream::copy_decode, but it returns how many bytes were written
+        fn decode_all(from: &[u8], mut to: &mut [u8]) -> std::io::Result<usize> {
+            let mut decoder = zstd::Decoder::new(from)?;
+            let written = std::io::copy(&mut decoder, &mut to)?;
+            return Ok(written as usize);
+        }
+
+        let packet = &self.read_buffer[self.read_cursor..self.read_cursor + packet_length];
+        let message_buffer = &mut self.message_buffer[self.message_cursor..];
+        let decoded_size = match decode_all(packet, message_buffer) {
+            Ok(size) => size,
+            Err(e) => {
+                error!("{}", e);
+                self.disconnect("Corrupted network packet, failed to decompress");
+                return false;
+            }
+        };
+        self.read_cursor += packet_length;
+        self.message_bytes += decoded_size;
+
+        return true;
+    }
+
+    // Extract a message from the message buffer
+    fn extract_message<'a>(&'a mut self) -> Option<(MessageType, &'a [u8])> {
+        if self.message_bytes - self.message_cursor <= MESSAGE_HEADER_SIZE {
+            // Move the partial message to the beginning of the buffer to make room for more bytes.
+            self.message_buffer
+                .copy_within(self.message_cursor..self.message_bytes, 0);
+            self.message_bytes -= self.message_cursor;
+            self.message_cursor = 0;
+            return None;
+        }
+
+        let message_type = self.message_buffer[self.message_cursor];
+        if message_type >= MessageType::MAX as u8 {
+            // Received invalid message type, return invalid message to disconnect
+            return Some((MessageType::MAX, &[]));
+        }
+        let message_type: MessageType = unsafe { std::mem::transmute(message_type) };
+
+        let message_length = u32::from_le_bytes(
+            self.message_buffer[self.message_cursor + 1..self.message_cursor + MESSAGE_HEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+
+        if message_length > self.message_bytes - self.message_cursor + MESSAGE_HEADER_SIZE {
+            self.message_buffer
+                .copy_within(self.message_cursor..self.message_bytes, 0);
+            self.message_bytes -= self.message_cursor;
+            self.message_cursor = 0;
+            return None;
+        }
+
+        self.message_cursor += MESSAGE_HEADER_SIZE;
+
+        let message =
+            &self.message_buffer[self.message_cursor..self.message_cursor + message_length];
+        self.message_cursor += message_length;
+
+        return Some((message_type, message));
+    }
+
+    // Try to grab a message from the message buffer, if not possible, decompress and try again
+    fn next_message<'a>(&'a mut self) -> Option<(MessageType, &'a [u8])> {
+        if !self.try_decompress_packet() {
+            return None;
+        }
+
+        return self.extract_message();
+    }
+}
+
+fn measure_bandwidth(time: Res<Time<Real>>, net: Res<NetworkClient>, mut diagnostics: Diagnostics) {
+    let delta = time.delta_secs_f64();
+    if delta == 0.0 {
+        return;
+    }
+
+    let received = net.bytes_received.swap(0, Ordering::Relaxed);
+    let sent = net.bytes_sent.swap(0, Ordering::Relaxed);
+    diagnostics.add_measurement(&BYTES_RECEIVED, || received as f64 / delta);
+    diagnostics.add_measurement(&BYTES_SENT, || sent as f64 / delta);
+}
+
+fn send_client_ready(net: Res<NetworkClient>) {
+    net.send_message(messages::ClientReady);
+}
+
+fn connect(
+    mut net: ResMut<NetworkClient>,
+    mut identity: ResMut<Identity>,
+    settings: Res<Settings>,
+    reconnecting: Option<Res<Reconnecting>>,
+) {
+    if let Some(Some(result)) = net
+        .connection_task
+        .as_mut()
+        .map(|task| future::block_on(future::poll_once(task)))
+    {
+        match result {
+            Ok(tcp_stream) => {
+                net.connection = Some(tcp_stream);
+                if let Some(handoff_token) = identity.handoff_token.take() {
+                    // A server chose the address, it is never given a join token.
+                    let handoff_token = (!handoff_token.is_empty()).then_some(handoff_token);
+                    send_identification(&net, &identity, handoff_token.as_deref());
+                } else if let (Some(session_token), Some(url)) =
+                    (&identity.token, &settings.account_service)
+                {
+                    let server_id = net.address().unwrap().to_string();
+                    net.join_task = Some(AsyncComputeTaskPool::get().spawn(request_join_token(
+                        url.clone(),
+                        session_token.clone(),
+                        server_id,
+                    )));
+                } else {
+                    send_identification(&net, &identity, None);
+                }
+            }
+            Err(e) if reconnecting.is_some() => net.connection_lost(e.kind().to_string()),
+            Err(e) => net.disconnect(e.kind().to_string()),
+        };
+
+        net.connection_task.take();
+    }
+
+    if let Some(Some(result)) = net
+        .join_task
+        .as_mut()
+        .map(|task| future::block_on(future::poll_once(task)))
+    {
+        net.join_task.take();
+        match result {
+            Ok(join_token) => send_identification(&net, &identity, Some(&join_token)),
+            Err(reason) => net.disconnect(reason),
+        }
+    }
+}
+
+fn send_identification(net: &NetworkClient, identity: &Identity, token: Option<&str>) {
+    // TODO: The identification message only has a name, so the other fields are appended to it,
+    // one "key=value" per line. The server splits them off at the newlines.
+    let mut name = identity.username.clone();
+    name += &format!("\nproperties={}", PROPERTY_VERSION);
+    if let Some(token) = token {
+        name += &format!("\ntoken={}", token);
+    }
+    net.send_message(messages::ClientIdentification { name });
+}
+
+// The session token is only ever sent to the account service. Servers are given a join token
+// the account service issues for their server id, which is the address the client connects to.
+// This way a server can't use what it is given to join other servers as the player.
+//
+// The account service is expected to respond to {"token": "<session token>", "server": "<id>"}
+// with {"token": "<join token>"}.
+async fn request_join_token(
+    url: String,
+    session_token: String,
+    server_id: String,
+) -> Result<String, String> {
+    let body = serde_json::json!({
+        "token": session_token,
+        "server": server_id,
+    });
+
+    let response = match ureq::post(&format!("{}/join", url))
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+    {
+        Ok(response) => response,
+        Err(ureq::Error::Status(401 | 403, _)) => {
+            return Err("Your login has expired, log in again to join the server".to_owned())
+        }
+        Err(_) => return Err("Could not reach the account service".to_owned()),
+    };
+
+    return response
+        .into_string()
+        .ok()
+        .and_then(|body| serde_json::from_str::<serde_json::Value>(&body).ok())
+        .and_then(|json| json["token"].as_str().map(|token| token.to_owned()))
+        .ok_or("The account service sent an invalid response".to_owned());
+}
+
+// Mirror of the server's request, see the TODO on send_asset_request.
+#[derive(Serialize)]
+enum AssetDownloadRequest {
+    Manifest,
+    Files(Vec<String>),
+}
+
+// A file in the server's asset archive, the manifest is a list of them.
+#[derive(Deserialize)]
+struct AssetFile {
+    path: String,
+    // SHA-256 of the content
+    hash: [u8; 32],
+    size: u64,
+}
+
+impl AssetFile {
+    // The cache is shared by all servers. Entries are named by the hash of their content, and
+    // only written after the content has been checked against it, so a server can't replace a
+    // file another server uses.
+    fn cache_path(&self) -> PathBuf {
+        let name: String = self
+            .hash
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        return PathBuf::from(ASSET_CACHE_PATH).join(name);
+    }
+
+    // Where it goes in the server's asset directory. None if it would end up outside of it.
+    fn asset_path(&self) -> Option<PathBuf> {
+        let path = Path::new(&self.path);
+        if !path
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+        {
+            return None;
+        }
+        return Some(Path::new("./server_assets/active").join(path));
+    }
+}
+
+/// How far along the asset download is. It exists while the assets are being downloaded.
+#[derive(Resource, Default)]
+pub struct AssetDownloadProgress {
+    /// Bytes downloaded
+    pub downloaded: usize,
+    /// Size of the download, 0 until the server starts sending it
+    pub total: usize,
+    /// The download is done, and the files are being put in place
+    pub unpacking: bool,
+}
+
+#[derive(Default)]
+struct AssetDownload {
+    // Total size of the compressed assets, first thing the server sends
+    size: usize,
+    // How much has been downloaded
+    downloaded: usize,
+    // Buffer for downloaded data
+    data: Option<Vec<u8>>,
+    // The files that were missing from the cache and have been requested. None while waiting
+    // for the manifest.
+    requested: Option<Vec<AssetFile>>,
+    // Unpacks what was downloaded off the main thread, returns the files that still need to be
+    // downloaded.
+    task: Option<Task<Result<Vec<AssetFile>, String>>>,
+}
+
+// Copies the files that are in the cache to the server's asset directory, returns the files that
+// are not.
+fn copy_cached_assets(manifest: Vec<AssetFile>) -> Result<Vec<AssetFile>, String> {
+    let mut missing = Vec::new();
+
+    for file in manifest {
+        let Some(asset_path) = file.asset_path() else {
+            return Err(format!(
+                "The server sent an invalid asset path: {}",
+                file.path
+            ));
+        };
+
+        let cache_path = file.cache_path();
+        if !std::fs::metadata(&cache_path).is_ok_and(|metadata| metadata.len() == file.size) {
+            missing.push(file);
+            continue;
+        }
+
+        if let Some(parent) = asset_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::copy(&cache_path, &asset_path).map_err(|e| e.to_string())?;
+    }
+
+    return Ok(missing);
+}
+
+// Store downloaded files in the cache so they don't have to be downloaded again, by this server
+// or any other that uses the same file. Files that don't match the hash the server gave for them
+// are left out. Failing to cache is not an error, it will be downloaded again next time.
+fn cache_assets(files: &[AssetFile]) {
+    if std::fs::create_dir_all(ASSET_CACHE_PATH).is_err() {
+        return;
+    }
+
+    for file in files {
+        let Some(data) = file
+            .asset_path()
+            .and_then(|asset_path| std::fs::read(asset_path).ok())
+        else {
+            continue;
+        };
+
+        if <[u8; 32]>::from(Sha256::digest(&data)) != file.hash {
+            warn!(
+                "The server sent the asset '{}' with content that doesn't match its hash, it \
+                will not be cached.",
+                file.path
+            );
+            continue;
+        }
+
+        std::fs::write(file.cache_path(), data).ok();
+    }
+}
+
+// Puts the downloaded data in place, returns the files that still need to be downloaded.
+fn unpack_asset_download(
+    data: Vec<u8>,
+    requested: Option<Vec<AssetFile>>,
+) -> Result<Vec<AssetFile>, String> {
+    if requested.is_none() {
+        // Servers that don't send manifests respond with the whole archive, it won't
+        // deserialize.
+        if let Ok(manifest) = bincode::deserialize::<Vec<AssetFile>>(&data) {
+            let missing = copy_cached_assets(manifest)?;
+            if missing.is_empty() {
+                std::fs::write(ASSETS_COMPLETE_PATH, []).ok();
+            }
+            return Ok(missing);
+        }
+    }
+
+    let decoder =
+        zstd::Decoder::new(&data[..]).map_err(|_| "The server sent invalid assets".to_owned())?;
+    tar::Archive::new(decoder)
+        .unpack("./server_assets/active")
+        .map_err(|e| e.to_string())?;
+
+    if let Some(requested) = requested {
+        cache_assets(&requested);
+    }
+
+    std::fs::write(ASSETS_COMPLETE_PATH, []).ok();
+    return Ok(Vec::new());
+}
+
+// After the client identifies itself, the server will send a server config. If we already have the
+// assets the server config points to, we immediately start to load, else we ask the server which
+// files it has, and request the ones that aren't cached.
+fn initialize_connection(
+    mut commands: Commands,
+    reconnecting: Option<Res<Reconnecting>>,
+    mut net: ResMut<NetworkClient>,
+    mut asset_download: Local<AssetDownload>,
+    mut download_progress: Option<ResMut<AssetDownloadProgress>>,
+    mut asset_state: ResMut<NextState<AssetState>>,
+) {
+    if net.connection.is_none() {
+        // Anything left over from a connection that was lost in the middle of a download
+        if asset_download.data.is_some() || asset_download.task.is_some() {
+            *asset_download = AssetDownload::default();
+            commands.remove_resource::<AssetDownloadProgress>();
+        }
+        return;
+    }
+
+    if let Some(task) = asset_download.task.as_mut() {
+        let Some(result) = future::block_on(future::poll_once(task)) else {
+            return;
+        };
+        asset_download.task = None;
+
+        match result {
+            Ok(missing) if missing.is_empty() => {
+                commands.remove_resource::<AssetDownloadProgress>();
+                asset_state.set(AssetState::Loading);
+            }
+            Ok(missing) => {
+                net.send_asset_request(AssetDownloadRequest::Files(
+                    missing.iter().map(|file| file.path.clone()).collect(),
+                ));
+                asset_download.data = Some(Vec::new());
+                asset_download.requested = Some(missing);
+                commands.insert_resource(AssetDownloadProgress::default());
+            }
+            Err(e) => net.disconnect(e),
+        }
+        return;
+    }
+
+    net.read_packets();
+
+    if asset_download.data.is_some() {
+        let mut cursor = 0;
+        if asset_download.size == 0 {
+            if net.read_bytes < 4 {
+                return;
+            }
+            asset_download.size =
+                u32::from_le_bytes(net.read_buffer[..4].try_into().unwrap()) as usize;
+            cursor = 4;
+        }
+
+        asset_download
+            .data
+            .as_mut()
+            .unwrap()
+            .write(&net.read_buffer[cursor..net.read_bytes])
+            .unwrap();
+        asset_download.downloaded += net.read_bytes - cursor;
+        net.read_bytes = 0;
+
+        if let Some(progress) = download_progress.as_mut() {
+            progress.downloaded = asset_download.downloaded;
+            progress.total = asset_download.size;
+        }
+
+        if asset_download.size == asset_download.downloaded {
+            let data = asset_download.data.take().unwrap();
+            let requested = asset_download.requested.take();
+            asset_download.size = 0;
+            asset_download.downloaded = 0;
+
+            asset_download.task = Some(
+                AsyncComputeTaskPool::get()
+                    .spawn(async move { unpack_asset_download(data, requested) }),
+            );
+
+            if let Some(progress) = download_progress.as_mut() {
+                progress.unpacking = true;
+            }
+        } else if asset_download.size < asset_download.downloaded {
+            net.disconnect(format!(
+                "Server sent too much asset data, expected {} bytes, but got {}",
+                asset_download.size, asset_download.downloaded
+            ));
+        }
+    } else {
+        if net.read_bytes < MESSAGE_HEADER_SIZE {
+            return;
+        }
+
+        if let Some((message_type, message_data)) = net.next_message() {
+            // The server may refuse the connection before sending the config, e.g. if the
+            // account could not be verified.
+            if message_type == MessageType::Disconnect {
+                let message = match bincode::deserialize::<messages::Disconnect>(message_data) {
+                    Ok(disconnect) => disconnect.message,
+                    Err(_) => "The server refused the connection".to_owned(),
+                };
+                if reconnecting.is_some() {
+                    // The server might not have noticed that the old connection is gone yet, and
+                    // still consider the player connected.
+                    net.connection_lost(message);
+                } else {
+                    net.disconnect(message);
+                }
+                return;
+            }
+
+            let Ok(server_config) = bincode::deserialize::<messages::ServerConfig>(message_data)
+            else {
+                net.disconnect(format!(
+                    "The server sent a {:?} message, when it should have sent a server config.",
+                    message_type
+                ));
+                return;
+            };
+            net.read_bytes = 0;
+            net.read_cursor = 0;
+
+            // Convert u64 hash to hex so it's more manageable as a file path
+            let asset_hash_hex = format!("{:x}", server_config.assets_hash);
+            let path = PathBuf::from("./server_assets").join(&asset_hash_hex);
+
+            if path.join(".complete").exists() {
+                asset_state.set(AssetState::Loading);
+            } else {
+                asset_download.data = Some(Vec::new());
+                net.send_asset_request(AssetDownloadRequest::Manifest);
+                commands.insert_resource(AssetDownloadProgress::default());
+            }
+
+            // Create directories, silently fails if they already exist
+            std::fs::create_dir("./server_assets").ok();
+            std::fs::create_dir(&path).ok();
+
+            // We symlink the wanted asset path to "server_assets/active" so that all other parts
+            // of the program can assume the assets are located at a static location, even though
+            // they are switched out for each server we connect to. As opposed to registering the
+            // path as a variable and having to pass it around.
+            const LINK_PATH: &str = "./server_assets/active";
+            std::fs::remove_dir_all(LINK_PATH).ok();
+
+            #[cfg(target_family = "windows")]
+            {
+                std::os::windows::fs::symlink_dir(&asset_hash_hex, LINK_PATH);
+            }
+            #[cfg(target_family = "unix")]
+            {
+                std::os::unix::fs::symlink(&asset_hash_hex, LINK_PATH).unwrap();
+            }
+            #[cfg(target_family = "wasm")]
+            {
+                // This is available as a nightly api under std::os::wasi
+                compile_error!("Not implemented for wasm yet");
+            }
+
+            commands.insert_resource(server_config);
+        }
+    }
+}
+
+// Even though we disconnect through a client error we want to register the reason it
+// disconnected as an event so that it can be displayed to the user. We register it as a server
+// message to piggyback of the same code path as a disconnection initiated by the server.
+fn register_client_disconnect_events(
+    net: Res<NetworkClient>,
+    mut network_events: EventWriter<messages::Disconnect>,
+) {
+    if let Ok(message) = net.disconnect_events.pop() {
+        network_events.send(messages::Disconnect { message });
+    }
+}
+
+// TODO: There is no message for transfers, the server sends them as the "transfer" property. It
+// is passed on as a disconnect with a message that starts with this prefix, followed by the
+// property's value.
+const TRANSFER_PREFIX: &str = "\u{0}transfer:";
+
+/// Returns true if the disconnect message is the server telling the client to move to another
+/// server.
+pub fn is_transfer(disconnect_message: &str) -> bool {
+    disconnect_message.starts_with(TRANSFER_PREFIX)
+}
+
+// Disconnect messages that start with this are from connections that failed, the rest of the
+// message is the reason.
+const CONNECTION_LOST_PREFIX: &str = "\u{0}lost:";
+
+/// The reason for the disconnect, as it should be shown to the player.
+pub fn disconnect_reason(disconnect_message: &str) -> &str {
+    disconnect_message
+        .strip_prefix(CONNECTION_LOST_PREFIX)
+        .unwrap_or(disconnect_message)
+}
+
+/// Inserted while the client is trying to reconnect to a server it lost the connection to.
+#[derive(Resource)]
+pub struct Reconnecting {
+    address: SocketAddr,
+    /// Attempts made so far, the first attempt is 1.
+    pub attempt: u32,
+    /// Time until the next attempt
+    pub timer: Timer,
+    /// Why the connection was lost
+    pub reason: String,
+}
+
+/// Inserted while the client is moving to the server it was transferred to. It is connected to
+/// when the client is done disconnecting from the current one, and its address has been looked
+/// up.
+#[derive(Resource)]
+pub struct PendingTransfer {
+    address: String,
+    handoff_token: String,
+    lookup: Task<Option<SocketAddr>>,
+}
+
+fn disconnect(
+    mut commands: Commands,
+    mut net: ResMut<NetworkClient>,
+    current_game_state: Res<State<GameState>>,
+    mut reconnecting: Option<ResMut<Reconnecting>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut disconnect_events: EventReader<messages::Disconnect>,
+) {
+    for event in disconnect_events.read() {
+        if !net.is_connected() && !net.is_connecting() {
+            continue;
+        }
+
+        if let Some(reason) = event.message.strip_prefix(CONNECTION_LOST_PREFIX) {
+            if let Some(reconnecting) = reconnecting.as_mut() {
+                reconnecting.attempt += 1;
+                reconnecting.reason = reason.to_owned();
+                if reconnecting.attempt > MAX_RECONNECT_ATTEMPTS {
+                    commands.remove_resource::<Reconnecting>();
+                } else {
+                    let backoff = RECONNECT_BACKOFF * 2.0f32.powi(reconnecting.attempt as i32 - 1);
+                    reconnecting.timer = Timer::from_seconds(backoff, TimerMode::Once);
+                }
+            } else if *current_game_state.get() == GameState::Playing {
+                if let Some(address) = net.address() {
+                    commands.insert_resource(Reconnecting {
+                        address,
+                        attempt: 1,
+                        timer: Timer::from_seconds(RECONNECT_BACKOFF, TimerMode::Once),
+                        reason: reason.to_owned(),
+                    });
+                }
+            }
+        } else if reconnecting.is_some() {
+            // Canceled, or the server told the client to go away.
+            commands.remove_resource::<Reconnecting>();
+        }
+
+        // Checked when it was received, see 'read_messages'
+        if let Some(Ok(transfer)) = event
+            .message
+            .strip_prefix(TRANSFER_PREFIX)
+            .map(serde_json::from_str::<properties::Transfer>)
+        {
+            // Looking up the address can take a while, so it's done while the world is
+            // cleaned up.
+            let address = transfer.address.clone();
+            commands.insert_resource(PendingTransfer {
+                address: transfer.address,
+                handoff_token: transfer.handoff_token,
+                lookup: IoTaskPool::get().spawn(async move {
+                    address
+                        .to_socket_addrs()
+                        .ok()
+                        .and_then(|mut addresses| addresses.next())
+                }),
+            });
+        }
+
+        // The server holds on to players whose connection was lost, so it is told when the
+        // player leaves on purpose.
+        if net.is_connected() && !event.message.starts_with(CONNECTION_LOST_PREFIX) {
+            net.send_property(properties::QUIT, &());
+        }
+
+        if let Some(connection) = net.connection.take() {
+            connection.shutdown(Shutdown::Both).ok();
+        }
+
+        // Tasks are canceled when dropped (eventually)
+        net.connection_task.take();
+        net.join_task.take();
+
+        game_state.set(GameState::Launcher);
+    }
+}
+
+// The world has been cleaned up when the launcher state is entered, once the address has been
+// looked up the new server can be connected to like any other.
+fn start_transfer(
+    mut commands: Commands,
+    mut net: ResMut<NetworkClient>,
+    mut identity: ResMut<Identity>,
+    mut transfer: ResMut<PendingTransfer>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut disconnect_events: EventWriter<messages::Disconnect>,
+) {
+    let Some(address) = future::block_on(future::poll_once(&mut transfer.lookup)) else {
+        return;
+    };
+    commands.remove_resource::<PendingTransfer>();
+
+    let Some(address) = address else {
+        // Shown in place of the transfer, the client is already disconnected.
+        disconnect_events.send(messages::Disconnect {
+            message: format!(
+                "The server tried to transfer you to '{}', but the address could not be found",
+                transfer.address
+            ),
+        });
+        return;
+    };
+
+    identity.handoff_token = Some(std::mem::take(&mut transfer.handoff_token));
+    net.clear_buffers();
+    net.connect(address);
+    game_state.set(GameState::Connecting);
+}
+
+// Waits for the backoff of the attempt to run out and connects again. The connection might fail
+// again, then it comes back here through the disconnect with the next attempt.
+fn reconnect(
+    time: Res<Time>,
+    mut net: ResMut<NetworkClient>,
+    mut reconnecting: ResMut<Reconnecting>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    reconnecting.timer.tick(time.delta());
+    if !reconnecting.timer.just_finished() {
+        return;
+    }
+
+    info!(
+        "Reconnecting to {}, attempt {}/{}",
+        reconnecting.address, reconnecting.attempt, MAX_RECONNECT_ATTEMPTS
+    );
+    net.clear_buffers();
+    net.connect(reconnecting.address);
+    game_state.set(GameState::Connecting);
+}
+
+#[derive(Resource)]
+pub struct Identity {
+    pub username: String,
+    /// Session token received from the account service when logging in. It is never sent to
+    /// servers, they are given a join token made from it instead.
+    pub token: Option<String>,
+    /// Set when a server transfers the client to another server. The token it gave is presented
+    /// in place of a join token on the next connection, or no token if it is empty. The client
+    /// never requests a join token for an address a server chose.
+    pub handoff_token: Option<String>,
+}
+
+impl Identity {
+    fn read_from_file() -> Self {
+        let token = std::fs::read_to_string("./session.txt")
+            .ok()
+            .map(|token| token.trim().to_owned())
+            .filter(|token| !token.is_empty());
+
+        if let Ok(username) = std::fs::read_to_string("./identity.txt") {
+            Identity {
+                username: username.trim().to_owned(),
+                token,
+                handoff_token: None,
+            }
+        } else {
+            Identity {
+                username: String::new(),
+                token: None,
+                handoff_token: None,
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        std::fs::write("./identity.txt", &self.username).ok();
+        if let Some(token) = &self.token {
+            std::fs::write("./session.txt", token).ok();
+        } else {
+            std::fs::remove_file("./session.txt").ok();
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.username.is_empty()
+    }
+}
+
+// TODO: There are no messages for player state like the game mode. Until there are, the server
+// sends it as text updates to interface paths that start with this prefix, with the value as
+// json. The client tells the server it understands them when it connects, servers only send them
+// to clients that do.
+const PROPERTY_PREFIX: &str = "\u{0}property:";
+// Must match the server's version for it to send properties
+const PROPERTY_VERSION: &str = "2";
+
+/// A property of the player set by the server, e.g. if it is allowed to fly.
+#[derive(Event)]
+pub struct ServerProperty {
+    pub name: String,
+    pub value: String,
+}
+
+impl ServerProperty {
+    /// The value as the type the property has, see `properties`. None if the server sent
+    /// something else.
+    pub fn parse<T: serde::de::DeserializeOwned>(&self) -> Option<T> {
+        return serde_json::from_str(&self.value).ok();
+    }
+}
+
+#[derive(SystemParam)]
+struct EventWriters<'w> {
+    asset_response: EventWriter<'w, messages::AssetResponse>,
+    disconnect: EventWriter<'w, messages::Disconnect>,
+    server_config: EventWriter<'w, messages::ServerConfig>,
+    time: EventWriter<'w, messages::Time>,
+    chunk: EventWriter<'w, messages::Chunk>,
+    block_updates: EventWriter<'w, messages::BlockUpdates>,
+    delete_model: EventWriter<'w, messages::DeleteModel>,
+    model_play_animation: EventWriter<'w, messages::ModelPlayAnimation>,
+    model_update_asset: EventWriter<'w, messages::ModelUpdateAsset>,
+    model_update_transform: EventWriter<'w, messages::ModelUpdateTransform>,
+    new_model: EventWriter<'w, messages::NewModel>,
+    spawn_custom_model: EventWriter<'w, messages::SpawnCustomModel>,
+    player_aabb: EventWriter<'w, messages::PlayerAabb>,
+    player_camera_position: EventWriter<'w, messages::PlayerCameraPosition>,
+    player_camera_rotation: EventWriter<'w, messages::PlayerCameraRotation>,
+    player_position: EventWriter<'w, messages::PlayerPosition>,
+    interface_item_box_update: EventWriter<'w, messages::InterfaceItemBoxUpdate>,
+    interface_node_visibility_update: EventWriter<'w, messages::InterfaceNodeVisibilityUpdate>,
+    interface_text_update: EventWriter<'w, messages::InterfaceTextUpdate>,
+    interface_visibility_update: EventWriter<'w, messages::InterfaceVisibilityUpdate>,
+    enable_client_audio: EventWriter<'w, messages::EnableClientAudio>,
+    sound: EventWriter<'w, messages::Sound>,
+    particle_effect: EventWriter<'w, messages::ParticleEffect>,
+    server_property: EventWriter<'w, ServerProperty>,
+}
+
+fn read_messages(net: ResMut<NetworkClient>, mut event_writers: EventWriters) {
+    if !net.is_connected() {
+        return;
+    }
+
+    // For split borrows
+    let net = net.into_inner();
+
+    net.read_packets();
+
+    while let Some((message_type, message_data)) = net.next_message() {
+        match message_type {
+            MessageType::AssetResponse => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.asset_response.send(message);
+                    continue;
+                }
+            }
+            MessageType::Disconnect => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.disconnect.send(message);
+                    continue;
+                }
+            }
+            MessageType::ServerConfig => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.server_config.send(message);
+                    continue;
+                }
+            }
+            MessageType::Time => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.time.send(message);
+                    continue;
+                }
+            }
+            MessageType::Chunk => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.chunk.send(message);
+                    continue;
+                }
+            }
+            MessageType::BlockUpdates => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.block_updates.send(message);
+                    continue;
+                }
+            }
+            MessageType::DeleteModel => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.delete_model.send(message);
+                    continue;
+                }
+            }
+            MessageType::ModelPlayAnimation => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.model_play_animation.send(message);
+                    continue;
+                }
+            }
+            MessageType::ModelUpdateAsset => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.model_update_asset.send(message);
+                    continue;
+                }
+            }
+            MessageType::ModelUpdateTransform => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.model_update_transform.send(message);
+                    continue;
+                }
+            }
+            MessageType::NewModel => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.new_model.send(message);
+                    continue;
+                }
+            }
+            MessageType::SpawnCustomModel => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.spawn_custom_model.send(message);
+                    continue;
+                }
+            }
+            MessageType::PlayerAabb => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.player_aabb.send(message);
+                    continue;
+                }
+            }
+            MessageType::PlayerCameraPosition => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.player_camera_position.send(message);
+                    continue;
+                }
+            }
+            MessageType::PlayerCameraRotation => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.player_camera_rotation.send(message);
+                    continue;
+                }
+            }
+            MessageType::PlayerPosition => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.player_position.send(message);
+                    continue;
+                }
+            }
+            MessageType::InterfaceItemBoxUpdate => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.interface_item_box_update.send(message);
+                    continue;
+                }
+            }
+            MessageType::InterfaceNodeVisibilityUpdate => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.interface_node_visibility_update.send(message);
+                    continue;
+                }
+            }
+            MessageType::InterfaceTextUpdate => {
+                if let Ok(message) =
+                    bincode::deserialize::<messages::InterfaceTextUpdate>(message_data)
+                {
+                    if let Some(name) = message.interface_path.strip_prefix(PROPERTY_PREFIX) {
+                        if name == properties::TRANSFER {
+                            // The server closes the connection after sending it
+                            if serde_json::from_str::<properties::Transfer>(&message.text).is_ok() {
+                                net.disconnect(TRANSFER_PREFIX.to_owned() + &message.text);
+                            } else {
+                                net.disconnect(
+                                    "The server tried to transfer you, but sent an invalid address",
+                                );
+                            }
+                            continue;
+                        }
+                        event_writers.server_property.send(ServerProperty {
+                            name: name.to_owned(),
+                            value: message.text,
+                        });
+                    } else {
+                        event_writers.interface_text_update.send(message);
+                    }
+                    continue;
+                }
+            }
+            MessageType::InterfaceVisibilityUpdate => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.interface_visibility_update.send(message);
+                    continue;
+                }
+            }
+            MessageType::EnableClientAudio => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.enable_client_audio.send(message);
+                    continue;
+                }
+            }
+            MessageType::Sound => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.sound.send(message);
+                    continue;
+                }
+            }
+            MessageType::ParticleEffect => {
+                if let Ok(message) = bincode::deserialize(message_data) {
+                    event_writers.particle_effect.send(message);
+                    continue;
+                }
+            }
+            _ => {
+                net.disconnect(format!(
+                    "Corrupt network message, received invalid message type: {:?}",
+                    message_type
+                ));
+                break;
+            }
+        }
+
+        net.disconnect(format!("Corrupt network message, received message type {:?} but it did not correspond to the data.", message_type));
+        break;
+    }
+}
@@ -0,0 +1,270 @@
+//! The names and values of the properties, see `PROPERTY_PREFIX`. Values are sent as json, the
+//! type of each is listed with its name.
+//!
+//! The server keeps a copy of this module, the two have to be changed together. Changes older
+//! servers can't handle also need `PROPERTY_VERSION` to be bumped.
+
+use serde::{Deserialize, Serialize};
+
+// Sent by both
+
+/// `f64`, the time the sender sent it at, in its own clock.
+pub const PING: &str = "ping";
+/// `Pong`, the answer to a ping.
+pub const PONG: &str = "pong";
+/// `ItemUse`, the client starts and ends its use of the equipped item. The server cancels it
+/// when the use is not allowed.
+pub const ITEM_USE: &str = "item_use";
+
+// Sent by the server
+
+/// `Transfer`, the client is moved to another server.
+pub const TRANSFER: &str = "transfer";
+/// `Notification`
+pub const NOTIFICATION: &str = "notification";
+/// `bool`, if the player is allowed to fly.
+pub const CAN_FLY: &str = "can_fly";
+/// `[f64; 3]`, the acceleration of gravity.
+pub const GRAVITY: &str = "gravity";
+/// `f64`, the fastest the player can fall.
+pub const TERMINAL_VELOCITY: &str = "terminal_velocity";
+/// `f64`, how high a block the player walks up without jumping.
+pub const STEP_HEIGHT: &str = "step_height";
+/// `Vehicle`, what the player drives.
+pub const VEHICLE: &str = "vehicle";
+/// `bool`, if the player is riding something.
+pub const RIDING: &str = "riding";
+/// `String`, the name of the player's game mode.
+pub const GAME_MODE: &str = "game_mode";
+/// `Option<usize>`, how many hotbar slots are shown, all of them when null.
+pub const HOTBAR_SLOTS: &str = "hotbar_slots";
+/// `String`, the name of the crosshair.
+pub const CROSSHAIR: &str = "crosshair";
+/// `bool`, if the hand is drawn.
+pub const HAND: &str = "hand";
+/// `Vec<String>`, the hud elements that are hidden.
+pub const HIDDEN_HUD: &str = "hidden_hud";
+/// `Hunger`
+pub const HUNGER: &str = "hunger";
+/// `Vec<String>`, the tags of the music that should be played.
+pub const MUSIC: &str = "music";
+/// `CameraShake`
+pub const CAMERA_SHAKE: &str = "camera_shake";
+/// `BossBarUpdate`
+pub const BOSS_BAR: &str = "boss_bar";
+/// `MapTile`
+pub const MAP_TILE: &str = "map_tile";
+/// `BlockAnimation`
+pub const BLOCK_ANIMATION: &str = "block_animation";
+/// `Leash`
+pub const LEASH: &str = "leash";
+/// `u32`, the model id of the leashed model whose leash is removed.
+pub const UNLEASH: &str = "unleash";
+/// `ModelAmbience`
+pub const MODEL_AMBIENCE: &str = "model_ambience";
+/// `Option<PlayerModel>`, the player's own model, null when it has none.
+pub const PLAYER_MODEL: &str = "player_model";
+/// `u32`, the model id of a model that is a dropped item.
+pub const DROPPED_ITEM: &str = "dropped_item";
+/// `ChatChannels`
+pub const CHAT_CHANNELS: &str = "chat_channels";
+/// `Option<Dialogue>`, the dialogue node that is shown, null when the dialogue is closed.
+pub const DIALOGUE: &str = "dialogue";
+/// `InterfaceAnimation`
+pub const INTERFACE_ANIMATION: &str = "interface_animation";
+/// `[f64; 3]`, the player died at this position.
+pub const DEATH: &str = "death";
+
+// Sent by the client
+
+/// `null`, the player quit on purpose, the connection closing is not a lost connection.
+pub const QUIT: &str = "quit";
+/// `ClientInterfaces`, the interfaces the client has loaded.
+pub const INTERFACES: &str = "interfaces";
+/// `String`, the path of the interface node the player wants sorted.
+pub const SORT_INTERFACE: &str = "sort_interface";
+/// `bool`, if the player wants to see when others connect and disconnect.
+pub const CHAT_HIDE_CONNECTIONS: &str = "chat_hide_connections";
+/// `bool`, if the player wants chat to be filtered.
+pub const CHAT_FILTER: &str = "chat_filter";
+/// `String`, the name of the chat channel the player switches to.
+pub const CHAT_CHANNEL: &str = "chat_channel";
+/// `usize`, the index of the dialogue option the player chose.
+pub const DIALOGUE_OPTION: &str = "dialogue_option";
+/// `null`, the player left the dialogue.
+pub const DIALOGUE_CLOSE: &str = "dialogue_close";
+/// `u32`, the vertical render distance the player wants, in chunks.
+pub const VERTICAL_RENDER_DISTANCE: &str = "vertical_render_distance";
+/// `MountInput`
+pub const MOUNT_INPUT: &str = "mount_input";
+/// `null`, the player wants to get off its mount.
+pub const DISMOUNT: &str = "dismount";
+/// `bool`
+pub const SNEAKING: &str = "sneaking";
+/// `bool`
+pub const SPRINTING: &str = "sprinting";
+
+#[derive(Serialize, Deserialize)]
+pub struct Pong {
+    /// The time of the ping, as it was sent.
+    pub sent: f64,
+    /// When the ping was received, in the answerer's clock.
+    pub received: f64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemUse {
+    Start,
+    Cancel,
+    Finish,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Transfer {
+    /// "host:port" of the server
+    pub address: String,
+    /// Presented to the other server in place of a join token.
+    pub handoff_token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Notification {
+    /// Seconds it is shown for
+    pub duration: f32,
+    pub icon: Option<String>,
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Vehicle {
+    pub kind: String,
+    pub max_speed: f64,
+    /// Where the player sits, relative to the vehicle.
+    pub seat: [f64; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Hunger {
+    pub hunger: u32,
+    pub max: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CameraShake {
+    pub intensity: f32,
+    /// Seconds
+    pub duration: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BossBarUpdate {
+    pub name: String,
+    /// The bar is removed when it is null.
+    pub bar: Option<BossBar>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BossBar {
+    /// From 0 to 1
+    pub progress: f32,
+    /// Hex color, e.g. "#c83232"
+    pub color: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapTile {
+    /// Position of the block column in the tile's corner, the tile covers the chunk column it
+    /// is in.
+    pub x: i32,
+    pub z: i32,
+    /// The distinct colors of the tile, an alpha of zero is a column that hasn't been explored.
+    pub palette: Vec<[u8; 4]>,
+    /// Base64, 5 bytes for each block column, indexed by x * Chunk::SIZE + z. The index of its
+    /// color in the palette, followed by the height of its surface as a little endian i32.
+    pub columns: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BlockAnimation {
+    pub position: [i32; 3],
+    pub block_id: u16,
+    pub block_state: Option<u16>,
+    /// Seconds
+    pub duration: f32,
+    /// The point the block rotates around, relative to the position.
+    pub pivot: [f64; 3],
+    pub from: BlockPose,
+    pub to: BlockPose,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BlockPose {
+    pub translation: [f64; 3],
+    /// Quaternion, x, y, z, w
+    pub rotation: [f64; 4],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Leash {
+    /// Model id of the leashed model
+    pub id: u32,
+    /// Model id of what holds the leash, null when it is the client's own player, which it has
+    /// no model of.
+    pub holder: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ModelAmbience {
+    pub model_id: u32,
+    pub min_interval: f32,
+    pub max_interval: f32,
+    pub distance: f32,
+    pub volume: f32,
+    pub animations: Vec<u32>,
+    pub sounds: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlayerModel {
+    pub model_id: u32,
+    pub move_animation: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChatChannels {
+    pub active: String,
+    /// All the channels the player can talk in
+    pub channels: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Dialogue {
+    pub speaker: String,
+    pub text: String,
+    pub options: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InterfaceAnimation {
+    pub node_path: String,
+    pub animation: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientInterfaces {
+    /// File names of the interfaces
+    pub interfaces: Vec<String>,
+    /// Paths of the named nodes
+    pub nodes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MountInput {
+    /// Direction the player wants to go
+    pub x: f64,
+    pub z: f64,
+    pub jump: bool,
+}
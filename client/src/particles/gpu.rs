@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    core_pipeline::prepass::DepthPrepass,
+    math::DVec3,
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology, view::NoFrustumCulling},
+};
+
+use crate::{
+    game_state::GameState,
+    player::Head,
+    rendering::{
+        lighting::LightMap,
+        materials::{
+            GpuParticleMaterial, ATTRIBUTE_PARTICLE_PARAMETERS, ATTRIBUTE_PARTICLE_VELOCITY,
+        },
+    },
+    settings::{Settings, WaterQuality},
+    utils,
+    world::{MovesWithOrigin, Origin},
+};
+
+/// Effects with at least this many particles are simulated on the gpu. Below it, the particles
+/// are few enough that they can each have their own entity and collide with blocks.
+pub const GPU_PARTICLE_THRESHOLD: u32 = 100;
+
+pub(super) struct GpuParticlePlugin;
+impl Plugin for GpuParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (despawn_effects, update_lighting, toggle_depth_collision)
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+// All the particles of an explosion, drawn as one mesh
+#[derive(Component)]
+struct GpuParticleEffect {
+    // Lifetime of the longest living particle
+    lifetime: Timer,
+}
+
+pub(super) struct GpuExplosion<'a> {
+    pub position: DVec3,
+    pub spawn_offset: Vec3,
+    pub size_range: (f32, f32),
+    pub min_velocity: Vec3,
+    pub max_velocity: Vec3,
+    pub texture: Option<Handle<Image>>,
+    pub block_texture: bool,
+    pub base_color: Srgba,
+    pub lifetime: (f32, f32),
+    pub count: u32,
+    pub settings: &'a Settings,
+}
+
+pub(super) fn spawn_explosion(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    time: &Time,
+    origin: &Origin,
+    rng: &mut utils::Rng,
+    explosion: GpuExplosion,
+) {
+    let count = explosion.count as usize;
+    let mut positions = Vec::with_capacity(count * 4);
+    let mut uvs = Vec::with_capacity(count * 4);
+    let mut velocities = Vec::with_capacity(count * 4);
+    let mut parameters = Vec::with_capacity(count * 4);
+    let mut indices = Vec::with_capacity(count * 6);
+
+    let mut max_lifetime: f32 = 0.0;
+
+    for i in 0..count {
+        let rand_offset = Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32());
+        let offset = -explosion.spawn_offset + explosion.spawn_offset * 2.0 * rand_offset;
+
+        let velocity = explosion.min_velocity
+            + (explosion.max_velocity - explosion.min_velocity)
+                * Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32());
+
+        // Same size as the cpu particles, which are 0.5 wide quads that are scaled.
+        let (min, max) = explosion.size_range;
+        let size = (min + (max - min) * rng.next_f32()) * 0.5;
+
+        let lifetime =
+            explosion.lifetime.0 + (explosion.lifetime.1 - explosion.lifetime.0) * rng.next_f32();
+        max_lifetime = max_lifetime.max(lifetime);
+
+        let (uv_min, uv_max) = if explosion.block_texture {
+            // Particles can be between 2 and 4 pixels
+            let particle_size = 2 + rng.next_u32() % 3;
+            // Choose a random location on the texture
+            let offset = (rng.next_u32() % (16 - particle_size)) as f32 / 16.0;
+            (offset, offset + particle_size as f32 / 16.0)
+        } else {
+            (0.0, 1.0)
+        };
+
+        for (corner, uv) in [
+            ([-0.5, -0.5], [uv_min, uv_max]),
+            ([0.5, -0.5], [uv_max, uv_max]),
+            ([0.5, 0.5], [uv_max, uv_min]),
+            ([-0.5, 0.5], [uv_min, uv_min]),
+        ] {
+            positions.push(offset.to_array());
+            uvs.push(uv);
+            velocities.push(velocity.to_array());
+            parameters.push([corner[0], corner[1], size, lifetime]);
+        }
+
+        let first = i as u32 * 4;
+        indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    let mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+    .with_inserted_attribute(ATTRIBUTE_PARTICLE_VELOCITY, velocities)
+    .with_inserted_attribute(ATTRIBUTE_PARTICLE_PARAMETERS, parameters)
+    .with_inserted_indices(Indices::U32(indices));
+
+    commands.spawn((
+        GpuParticleEffect {
+            lifetime: Timer::new(Duration::from_secs_f32(max_lifetime), TimerMode::Once),
+        },
+        Mesh3d(asset_server.add(mesh)),
+        MeshMaterial3d(asset_server.add(GpuParticleMaterial {
+            texture: explosion.texture,
+            base_color: explosion.base_color,
+            gravity: -14.0,
+            spawn_time: time.elapsed_secs_wrapped(),
+            light: 1.0,
+            depth_collision: explosion.settings.particle_collision,
+        })),
+        Transform::from_translation(origin.to_local(explosion.position)),
+        // The particles move away from the mesh's bounds in the shader
+        NoFrustumCulling,
+        MovesWithOrigin,
+    ));
+}
+
+fn despawn_effects(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effects: Query<(Entity, &mut GpuParticleEffect)>,
+) {
+    for (entity, mut effect) in effects.iter_mut() {
+        effect.lifetime.tick(time.delta());
+        if effect.lifetime.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// The whole effect is lit by the light at where it was spawned.
+fn update_lighting(
+    origin: Res<Origin>,
+    light_map: Res<LightMap>,
+    ambient_light: Res<AmbientLight>,
+    mut materials: ResMut<Assets<GpuParticleMaterial>>,
+    effects: Query<(&GlobalTransform, &MeshMaterial3d<GpuParticleMaterial>)>,
+) {
+    for (transform, material_handle) in effects.iter() {
+        let position = origin.to_global(transform.translation()).floor().as_ivec3();
+        let Some(light) = light_map.get_light(position) else {
+            continue;
+        };
+
+        let sunlight = 0.8f32.powi(15 - light.sunlight() as i32) * ambient_light.brightness;
+        let artificial = 0.8f32.powi(15 - light.artificial() as i32);
+        // This makes the particles darker to increase contrast, same as the cpu particles.
+        let light = sunlight.max(artificial) * 0.7;
+
+        // Only take the material mutably when the light changes, it is reuploaded otherwise.
+        if materials
+            .get(material_handle)
+            .is_some_and(|material| material.light != light)
+        {
+            materials.get_mut(material_handle).unwrap().light = light;
+        }
+    }
+}
+
+// Depth collision needs the depth of the scene before the particles are drawn, which costs an
+// extra pass, so the camera only has it when the setting is on. Ambient occlusion and the
+// reflections of the best water quality need it too.
+fn toggle_depth_collision(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut materials: ResMut<Assets<GpuParticleMaterial>>,
+    camera_query: Query<(Entity, Has<DepthPrepass>), With<Head>>,
+) {
+    let Ok((camera_entity, has_prepass)) = camera_query.get_single() else {
+        return;
+    };
+
+    let wants_prepass = settings.particle_collision
+        || settings.ambient_occlusion
+        || settings.water_quality == WaterQuality::High;
+    if wants_prepass != has_prepass {
+        if wants_prepass {
+            commands.entity(camera_entity).insert(DepthPrepass);
+        } else {
+            commands.entity(camera_entity).remove::<DepthPrepass>();
+        }
+    }
+
+    if !settings.is_changed() {
+        return;
+    }
+
+    for (_, material) in materials.iter_mut() {
+        material.depth_collision = settings.particle_collision;
+    }
+}
@@ -7,7 +7,8 @@ use crate::{
     game_state::GameState,
     networking::NetworkClient,
     player::{Head, Player},
-    rendering::materials::ParticleMaterial,
+    rendering::{diagnostics::RendererInfo, materials::ParticleMaterial},
+    settings::Settings,
     utils,
     world::{
         blocks::{Blocks, Friction},
@@ -16,22 +17,25 @@ use crate::{
     },
 };
 
+mod gpu;
+
 pub struct ParticlePlugin;
 impl Plugin for ParticlePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            FixedUpdate,
-            simulate_physics.run_if(in_state(GameState::Playing)),
-        )
-        .add_systems(
-            Update,
-            (
-                handle_particles_from_server,
-                despawn_particles,
-                billboard_particles,
+        app.add_plugins(gpu::GpuParticlePlugin)
+            .add_systems(
+                FixedUpdate,
+                simulate_physics.run_if(in_state(GameState::Playing)),
             )
-                .run_if(in_state(GameState::Playing)),
-        );
+            .add_systems(
+                Update,
+                (
+                    handle_particles_from_server,
+                    despawn_particles,
+                    billboard_particles,
+                )
+                    .run_if(in_state(GameState::Playing)),
+            );
     }
 }
 
@@ -52,6 +56,9 @@ fn handle_particles_from_server(
     mut commands: Commands,
     net: Res<NetworkClient>,
     origin: Res<Origin>,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    renderer_info: Res<RendererInfo>,
     asset_server: Res<AssetServer>,
     mut new_effects: EventReader<messages::ParticleEffect>,
     mut rng: Local<utils::Rng>,
@@ -71,7 +78,53 @@ fn handle_particles_from_server(
                 lifetime,
                 count,
             } => {
-                for _ in 0..*count as usize {
+                let base_color = if let Some(hex_color) = color {
+                    let Ok(color) = Srgba::hex(hex_color) else {
+                        net.disconnect(format!(
+                            "Received malformed particle from server, '{}' is not a hex encoded color.", hex_color));
+                        return;
+                    };
+
+                    color
+                } else {
+                    Srgba::WHITE
+                };
+
+                // Large effects are drawn as a single mesh that is moved by the gpu, they don't
+                // collide with blocks.
+                let use_gpu = !renderer_info.fallbacks.simple_shaders;
+                if use_gpu && *count >= gpu::GPU_PARTICLE_THRESHOLD {
+                    gpu::spawn_explosion(
+                        &mut commands,
+                        &asset_server,
+                        &time,
+                        &origin,
+                        &mut rng,
+                        gpu::GpuExplosion {
+                            position: *position,
+                            spawn_offset: *spawn_offset,
+                            size_range: *size_range,
+                            min_velocity: *min_velocity,
+                            max_velocity: *max_velocity,
+                            texture: texture
+                                .as_ref()
+                                .map(|path| asset_server.load(TEXTURE_PATH.to_owned() + path)),
+                            block_texture: texture
+                                .as_ref()
+                                .is_some_and(|path| path.starts_with("blocks")),
+                            base_color,
+                            lifetime: *lifetime,
+                            count: *count,
+                            settings: &settings,
+                        },
+                    );
+                    continue;
+                }
+
+                // Without the gpu effects, large effects are cut down so they don't spawn too
+                // many entities.
+                let count = (*count).min(gpu::GPU_PARTICLE_THRESHOLD);
+                for _ in 0..count as usize {
                     let rand_offset = Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32());
                     let offset = -*spawn_offset + *spawn_offset * 2.0 * rand_offset;
                     let translation = origin.to_local(*position) + offset;
@@ -98,18 +151,6 @@ fn handle_particles_from_server(
                         uv[1] = uv[1] * particle_size as f32 / 16.0 + offset;
                     }
 
-                    let base_color = if let Some(hex_color) = color {
-                        let Ok(color) = Srgba::hex(hex_color) else {
-                            net.disconnect(format!(
-                                "Received malformed particle from server, '{}' is not a hex encoded color.", hex_color));
-                            return;
-                        };
-
-                        color
-                    } else {
-                        Srgba::WHITE
-                    };
-
                     let lifetime = lifetime.0 + (lifetime.1 - lifetime.0) * rng.next_f32();
 
                     commands.spawn((
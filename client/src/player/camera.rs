@@ -85,7 +85,7 @@ fn rotate_camera(
     settings: Res<Settings>,
     net: Res<NetworkClient>,
     mut mouse_events: EventReader<MouseMotion>,
-    mut camera_query: Query<&mut Transform, With<Camera>>,
+    mut camera_query: Query<&mut Transform, With<Head>>,
 ) {
     let window = window.single();
 
@@ -118,7 +118,7 @@ fn rotate_camera(
 // Forced camera rotation by the server.
 fn handle_camera_rotation_from_server(
     mut camera_rotation_events: EventReader<messages::PlayerCameraRotation>,
-    mut camera_q: Query<&mut Transform, With<Camera>>,
+    mut camera_q: Query<&mut Transform, With<Head>>,
 ) {
     for rotation_event in camera_rotation_events.read() {
         let mut transform = camera_q.single_mut();
@@ -129,7 +129,7 @@ fn handle_camera_rotation_from_server(
 // Forced camera position by the server
 fn handle_camera_position_from_server(
     mut camera_position_events: EventReader<messages::PlayerCameraPosition>,
-    mut camera_q: Query<&mut Transform, With<Camera>>,
+    mut camera_q: Query<&mut Transform, With<Head>>,
 ) {
     for position_event in camera_position_events.read() {
         let mut transform = camera_q.single_mut();
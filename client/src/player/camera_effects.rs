@@ -0,0 +1,239 @@
+use bevy::{
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    player::{Head, Player},
+    settings::Settings,
+};
+
+// How much wider the field of view is while sprinting
+const SPRINT_FOV_SCALE: f32 = 1.15;
+// How much narrower the field of view is while zooming
+const ZOOM_FOV_SCALE: f32 = 0.3;
+// Steps taken each second at walking speed
+const BOB_FREQUENCY: f32 = 1.8;
+// How far the camera moves up and to the sides with each step
+const BOB_HEIGHT: f32 = 0.04;
+const BOB_WIDTH: f32 = 0.02;
+// Horizontal speed at which the bobbing is at its fullest
+const BOB_FULL_SPEED: f32 = 4.3;
+// Largest rotation of the camera when shaking at an intensity of 1, in radians
+const MAX_SHAKE_ANGLE: f32 = 0.05;
+
+// Effects are added on top of the camera's transform. The camera's transform is what the player
+// and server control, so the effects are taken off again at the start of the next frame, before
+// anything reads or changes it.
+pub struct CameraEffectsPlugin;
+impl Plugin for CameraEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraEffects>()
+            .add_event::<CameraShake>()
+            .add_systems(
+                PreUpdate,
+                remove_camera_effects.run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (handle_shake_property, change_fov).run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                PostUpdate,
+                apply_camera_effects
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                OnExit(GameState::Playing),
+                |mut effects: ResMut<CameraEffects>| *effects = CameraEffects::default(),
+            );
+    }
+}
+
+/// Shake the camera, e.g. from an explosion or hurting. The server can do the same through the
+/// "camera_shake" property.
+#[derive(Event)]
+pub struct CameraShake {
+    /// Strength of the shake, 1 is a strong shake
+    pub intensity: f32,
+    /// Seconds the shake lasts, it fades out over this time
+    pub duration: f32,
+}
+
+struct Shake {
+    intensity: f32,
+    duration: f32,
+    elapsed: f32,
+}
+
+#[derive(Resource, Default)]
+struct CameraEffects {
+    // The offset that was added to the camera this frame, removed again next frame
+    applied_translation: Vec3,
+    applied_rotation: Quat,
+    // How far into the step cycle the bobbing is, in radians
+    bob_phase: f32,
+    // How strongly the camera bobs, it eases in and out as the player starts and stops walking
+    bob_strength: f32,
+    shakes: Vec<Shake>,
+    shake_time: f32,
+}
+
+fn handle_shake_property(
+    mut property_events: EventReader<ServerProperty>,
+    mut shake_events: EventWriter<CameraShake>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::CAMERA_SHAKE {
+            continue;
+        }
+
+        let Some(properties::CameraShake {
+            intensity,
+            duration,
+        }) = property.parse()
+        else {
+            continue;
+        };
+
+        shake_events.send(CameraShake {
+            intensity,
+            duration,
+        });
+    }
+}
+
+pub(super) fn remove_camera_effects(
+    mut effects: ResMut<CameraEffects>,
+    mut camera_query: Query<&mut Transform, With<Head>>,
+) {
+    let Ok(mut transform) = camera_query.get_single_mut() else {
+        return;
+    };
+
+    transform.translation -= effects.applied_translation;
+    transform.rotation = transform.rotation * effects.applied_rotation.inverse();
+
+    effects.applied_translation = Vec3::ZERO;
+    effects.applied_rotation = Quat::IDENTITY;
+}
+
+pub(super) fn apply_camera_effects(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut effects: ResMut<CameraEffects>,
+    mut shake_events: EventReader<CameraShake>,
+    player_query: Query<&Player>,
+    mut camera_query: Query<&mut Transform, With<Head>>,
+) {
+    let Ok(mut transform) = camera_query.get_single_mut() else {
+        return;
+    };
+    let player = player_query.single();
+    let delta = time.delta_secs();
+
+    if settings.reduce_motion {
+        shake_events.clear();
+        effects.shakes.clear();
+        return;
+    }
+
+    for shake in shake_events.read() {
+        effects.shakes.push(Shake {
+            intensity: shake.intensity,
+            duration: shake.duration.max(f32::EPSILON),
+            elapsed: 0.0,
+        });
+    }
+
+    // View bobbing
+    let is_walking = settings.view_bobbing
+        && player.is_grounded.y
+        && !player.is_flying
+        && !player.is_swimming
+        && !player.is_riding;
+    let speed = player.velocity.with_y(0.0).length();
+    let target_strength = if is_walking {
+        (speed / BOB_FULL_SPEED).min(1.0)
+    } else {
+        0.0
+    };
+    effects.bob_strength += (target_strength - effects.bob_strength) * (delta * 8.0).min(1.0);
+    effects.bob_phase = (effects.bob_phase
+        + delta * BOB_FREQUENCY * std::f32::consts::PI * (speed / BOB_FULL_SPEED).max(0.5))
+        % std::f32::consts::TAU;
+
+    // One step for each half of the cycle, the camera goes down when the foot lands and sways to
+    // the side of the foot.
+    let local_offset = Vec3::new(
+        effects.bob_phase.cos() * BOB_WIDTH,
+        effects.bob_phase.sin().abs() * BOB_HEIGHT,
+        0.0,
+    ) * effects.bob_strength;
+    let translation = transform.rotation * local_offset;
+
+    // Camera shake, the shakes add up and each fades out over its duration.
+    effects.shake_time += delta;
+    let mut shake_intensity = 0.0;
+    for shake in effects.shakes.iter_mut() {
+        shake.elapsed += delta;
+        shake_intensity += shake.intensity * (1.0 - shake.elapsed / shake.duration).max(0.0);
+    }
+    effects
+        .shakes
+        .retain(|shake| shake.elapsed < shake.duration);
+
+    // Sines of unrelated frequencies, so that it doesn't look like it repeats
+    let t = effects.shake_time;
+    let angle = shake_intensity.min(2.0) * MAX_SHAKE_ANGLE;
+    let rotation = Quat::from_euler(
+        EulerRot::YXZ,
+        (t * 31.0).sin() * angle,
+        (t * 37.0 + 1.0).sin() * angle,
+        (t * 23.0 + 2.0).sin() * angle * 0.5,
+    );
+
+    transform.translation += translation;
+    transform.rotation = transform.rotation * rotation;
+    effects.applied_translation = translation;
+    effects.applied_rotation = rotation;
+}
+
+// The field of view widens while sprinting and narrows while holding the zoom key.
+fn change_fov(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    keys: Res<ButtonInput<KeyCode>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    player_query: Query<&Player>,
+    mut projection_query: Query<&mut Projection, With<Head>>,
+) {
+    let player = player_query.single();
+    let mut projection = projection_query.single_mut();
+    let Projection::Perspective(projection) = &mut *projection else {
+        return;
+    };
+
+    let has_input = window.single().cursor_options.grab_mode != CursorGrabMode::None;
+
+    let target = if has_input && keys.pressed(KeyCode::KeyC) {
+        settings.fov * ZOOM_FOV_SCALE
+    } else if player.is_sprinting && !settings.reduce_motion {
+        settings.fov * SPRINT_FOV_SCALE
+    } else {
+        settings.fov
+    };
+
+    if projection.fov != target {
+        let step = (target - projection.fov) * (time.delta_secs() * 10.0).min(1.0);
+        // Zooming is still allowed with reduced motion, but without the transition.
+        projection.fov = if step.abs() < 0.001 || settings.reduce_motion {
+            target
+        } else {
+            projection.fov + step
+        };
+    }
+}
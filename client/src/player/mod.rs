@@ -1,10 +1,19 @@
 use bevy::{prelude::*, render::primitives::Aabb};
 use fmc_protocol::messages;
 
-use crate::{game_state::GameState, world::MovesWithOrigin};
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    world::MovesWithOrigin,
+};
 
 mod camera;
+mod camera_effects;
 mod movement;
+mod third_person;
+mod vehicle;
+
+pub use third_person::CameraMode;
 
 // Used at setup to set camera position and define the AABB, but should be changed by the server.
 const DEFAULT_PLAYER_WIDTH: f32 = 0.6;
@@ -16,11 +25,17 @@ impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(movement::MovementPlugin)
             .add_plugins(camera::CameraPlugin)
+            .add_plugins(camera_effects::CameraEffectsPlugin)
+            .add_plugins(third_person::ThirdPersonPlugin)
+            .init_resource::<movement::PhysicsConfig>()
             .add_systems(Startup, setup_player)
             .add_systems(
                 Update,
                 handle_aabb_update.run_if(in_state(GameState::Playing)),
-            );
+            )
+            // Properties are sent as soon as the player joins, before the client is done
+            // setting up.
+            .add_systems(Update, handle_server_properties);
     }
 }
 
@@ -36,7 +51,18 @@ pub struct Player {
     // Current acceleration
     pub acceleration: Vec3,
     pub is_flying: bool,
+    // If the server allows the player to fly
+    pub can_fly: bool,
     pub is_swimming: bool,
+    // If the player is inside a climbable block, like a ladder
+    pub is_climbing: bool,
+    // Sneaking players move slowly and can't walk off edges
+    pub is_sneaking: bool,
+    pub is_sprinting: bool,
+    // Riding players are moved by the server, input is sent to it instead
+    pub is_riding: bool,
+    // Players that ride a vehicle move it themselves
+    pub vehicle: Option<vehicle::Vehicle>,
     // If the player is against a block. (in any direction)
     pub is_grounded: BVec3,
 }
@@ -45,6 +71,7 @@ impl Player {
     pub fn new() -> Self {
         return Self {
             is_flying: true,
+            can_fly: true,
             ..Default::default()
         };
     }
@@ -99,3 +126,58 @@ fn handle_aabb_update(
         }
     }
 }
+
+fn handle_server_properties(
+    mut physics_config: ResMut<movement::PhysicsConfig>,
+    mut player_query: Query<&mut Player>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        let Ok(mut player) = player_query.get_single_mut() else {
+            continue;
+        };
+
+        match property.name.as_str() {
+            properties::CAN_FLY => {
+                player.can_fly = property.parse().unwrap_or_default();
+                if !player.can_fly {
+                    player.is_flying = false;
+                }
+            }
+            properties::GAME_MODE => {
+                if let Some(game_mode) = property.parse::<String>() {
+                    info!("Game mode set to {}", game_mode);
+                }
+            }
+            properties::RIDING => {
+                player.is_riding = property.parse().unwrap_or_default();
+                player.velocity = Vec3::ZERO;
+                if player.is_riding {
+                    player.is_flying = false;
+                } else {
+                    player.vehicle = None;
+                }
+            }
+            properties::VEHICLE => {
+                player.vehicle = property.parse().and_then(vehicle::Vehicle::from_property);
+            }
+            properties::GRAVITY => {
+                if let Some(gravity) = property.parse::<[f64; 3]>() {
+                    physics_config.gravity = Vec3::from_array(gravity.map(|v| v as f32));
+                }
+            }
+            properties::TERMINAL_VELOCITY => {
+                if let Some(terminal_velocity) = property.parse::<f64>() {
+                    physics_config.terminal_velocity = terminal_velocity as f32;
+                }
+            }
+            properties::STEP_HEIGHT => {
+                if let Some(step_height) = property.parse::<f64>() {
+                    physics_config.step_height = step_height as f32;
+                }
+            }
+            // Properties meant for newer clients
+            _ => (),
+        }
+    }
+}
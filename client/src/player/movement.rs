@@ -11,10 +11,10 @@ use fmc_protocol::messages;
 
 use crate::{
     game_state::GameState,
-    networking::NetworkClient,
-    player::Player,
+    networking::{properties, NetworkClient},
+    player::{vehicle, Head, Player},
     world::{
-        blocks::{Blocks, Friction},
+        blocks::{BlockId, Blocks, Friction},
         world_map::WorldMap,
         Origin,
     },
@@ -22,7 +22,9 @@ use crate::{
 
 // sqrt(2 * gravity * wanted height(1.4)) + some for air resistance
 const JUMP_VELOCITY: f32 = 9.0;
-const GRAVITY: Vec3 = Vec3::new(0.0, -32.0, 0.0);
+// Used until the server sends its own, see PhysicsConfig
+const DEFAULT_GRAVITY: Vec3 = Vec3::new(0.0, -32.0, 0.0);
+const DEFAULT_TERMINAL_VELOCITY: f32 = 78.0;
 // TODO: I think this should be a thing only if you hold space. If you are skilled you can press
 // space again as soon as you land if you have released it in the meantime.
 // TODO: It feels nice when you jump up a block, but when jumping down it does nothing, feels like
@@ -31,27 +33,72 @@ const GRAVITY: Vec3 = Vec3::new(0.0, -32.0, 0.0);
 //
 // This is needed so that whenever you land early you can't just instantly jump again.
 // v_t = v_0 * at => (v_t - v_0) / a = t
-const JUMP_TIME: f32 = JUMP_VELOCITY * 1.7 / -GRAVITY.y;
+const JUMP_TIME: f32 = JUMP_VELOCITY * 1.7 / -DEFAULT_GRAVITY.y;
+// Vertical speed when moving up a climbable block, and when sliding down it.
+const CLIMB_SPEED: f32 = 3.0;
+const CLIMB_SLIDE_SPEED: f32 = -2.0;
+// Gravity is reduced while swimming so that the player sinks slowly.
+const SWIM_GRAVITY: f32 = -10.0;
+// Speed multipliers of the movement states
+const SNEAK_SPEED: f32 = 0.3;
+const SPRINT_SPEED: f32 = 1.3;
+// How much lower the camera is while sneaking, the server does the same
+const SNEAK_CAMERA_OFFSET: f32 = 0.3;
 
 pub struct MovementPlugin;
 impl Plugin for MovementPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, toggle_flight.run_if(in_state(GameState::Playing)))
-            .add_systems(
-                FixedUpdate,
+        app.add_systems(
+            Update,
+            (
+                toggle_flight,
+                (update_movement_state, sneak_camera).chain(),
+                send_mount_input,
+            )
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            FixedUpdate,
+            (
                 (
                     change_player_acceleration,
                     simulate_player_physics,
                     swimming,
-                    send_position_to_server,
+                    climbing,
                 )
                     .chain()
-                    .run_if(in_state(GameState::Playing)),
+                    .run_if(is_not_riding),
+                vehicle::drive_vehicle.run_if(vehicle::is_driving),
+                send_position_to_server.run_if(is_not_riding.or(vehicle::is_driving)),
             )
-            // TODO: This is another one of the things the server just sends on connection.
-            // Workaround by just having it run all the time, but once the server can be notified
-            // that the client is actually ready to receive it should be moved above with the rest.
-            .add_systems(Update, handle_position_updates_from_server);
+                .chain()
+                .run_if(in_state(GameState::Playing)),
+        )
+        // TODO: This is another one of the things the server just sends on connection.
+        // Workaround by just having it run all the time, but once the server can be notified
+        // that the client is actually ready to receive it should be moved above with the rest.
+        .add_systems(Update, handle_position_updates_from_server);
+    }
+}
+
+/// Physics constants of the server's world, it sends them as the "gravity", "terminal_velocity"
+/// and "step_height" properties.
+#[derive(Resource)]
+pub(super) struct PhysicsConfig {
+    pub gravity: Vec3,
+    /// The fastest the player can fall along the direction of gravity
+    pub terminal_velocity: f32,
+    /// How high a ledge the player walks up onto without jumping, 0 turns it off.
+    pub step_height: f32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        return Self {
+            gravity: DEFAULT_GRAVITY,
+            terminal_velocity: DEFAULT_TERMINAL_VELOCITY,
+            step_height: 0.0,
+        };
     }
 }
 
@@ -79,6 +126,78 @@ fn handle_position_updates_from_server(
     }
 }
 
+fn is_not_riding(player_query: Query<&Player>) -> bool {
+    return player_query
+        .get_single()
+        .map_or(true, |player| !player.is_riding);
+}
+
+// While riding, the server moves the player along with its mount. The movement keys are sent to it
+// as a direction so that it can steer the mount, and shift gets off. Vehicles are steered by the
+// client itself, only getting off is sent.
+fn send_mount_input(
+    net: Res<NetworkClient>,
+    keys: Res<ButtonInput<KeyCode>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    player_query: Query<&Player>,
+    camera_query: Query<&Transform, With<Head>>,
+    mut last_input: Local<Option<(f32, f32, bool)>>,
+) {
+    let player = player_query.single();
+    if !player.is_riding {
+        *last_input = None;
+        return;
+    }
+
+    let has_input = window.single().cursor_options.grab_mode != CursorGrabMode::None;
+
+    if has_input && keys.just_pressed(KeyCode::ShiftLeft) {
+        net.send_property(properties::DISMOUNT, &());
+        return;
+    }
+
+    if player.vehicle.is_some() {
+        return;
+    }
+
+    let camera_forward = camera_query.single().forward();
+    let forward = Vec3::new(camera_forward.x, 0., camera_forward.z).normalize_or_zero();
+    let sideways = Vec3::new(-forward.z, 0., forward.x);
+
+    let mut direction = Vec3::ZERO;
+    if has_input {
+        for key in keys.get_pressed() {
+            match key {
+                KeyCode::KeyW => direction += forward,
+                KeyCode::KeyS => direction -= forward,
+                KeyCode::KeyA => direction -= sideways,
+                KeyCode::KeyD => direction += sideways,
+                _ => (),
+            }
+        }
+    }
+    let direction = direction.normalize_or_zero();
+    let jump = has_input && keys.pressed(KeyCode::Space);
+
+    // Rounded so that small turns of the camera don't flood the server
+    let input = (
+        (direction.x * 100.0).round() / 100.0,
+        (direction.z * 100.0).round() / 100.0,
+        jump,
+    );
+    if *last_input != Some(input) {
+        net.send_property(
+            properties::MOUNT_INPUT,
+            &properties::MountInput {
+                x: input.0 as f64,
+                z: input.1 as f64,
+                jump: input.2,
+            },
+        );
+        *last_input = Some(input);
+    }
+}
+
 // TODO: Hack until proper input handling, note pressing fast three times will put you back into
 // the original state.
 fn toggle_flight(
@@ -100,6 +219,9 @@ fn toggle_flight(
                 < 250
             {
                 let mut player = query.single_mut();
+                if !player.can_fly {
+                    return;
+                }
                 player.is_flying = !player.is_flying;
                 player.velocity = Vec3::ZERO;
             } else {
@@ -113,9 +235,10 @@ fn toggle_flight(
 /// Handles keyboard input and movement
 fn change_player_acceleration(
     keys: Res<ButtonInput<KeyCode>>,
+    physics_config: Res<PhysicsConfig>,
     window: Query<&Window, With<PrimaryWindow>>,
     mut player_query: Query<&mut Player>,
-    camera_query: Query<&Transform, With<Camera>>,
+    camera_query: Query<&Transform, With<Head>>,
     mut last_jump: Local<Timer>,
 ) {
     let mut player = player_query.single_mut();
@@ -129,6 +252,9 @@ fn change_player_acceleration(
 
     if player.is_flying {
         player.velocity.y = 0.0;
+    } else if player.is_climbing {
+        // Slide down unless told otherwise below
+        player.velocity.y = CLIMB_SLIDE_SPEED;
     }
 
     let mut horizontal_acceleration = Vec3::ZERO;
@@ -136,13 +262,21 @@ fn change_player_acceleration(
     for key in keys.get_pressed() {
         if window.cursor_options.grab_mode != CursorGrabMode::None {
             match key {
-                KeyCode::KeyW => horizontal_acceleration += forward,
+                KeyCode::KeyW => {
+                    horizontal_acceleration += forward;
+                    // Walking into a ladder climbs it
+                    if player.is_climbing && !player.is_flying {
+                        player.velocity.y = player.velocity.y.max(CLIMB_SPEED);
+                    }
+                }
                 KeyCode::KeyS => horizontal_acceleration -= forward,
                 KeyCode::KeyA => horizontal_acceleration -= sideways,
                 KeyCode::KeyD => horizontal_acceleration += sideways,
                 KeyCode::Space => {
                     if player.is_flying {
                         player.velocity.y = JUMP_VELOCITY * 2.0;
+                    } else if player.is_climbing {
+                        player.velocity.y = CLIMB_SPEED;
                     } else if player.is_swimming {
                         vertical_acceleration.y = 20.0
                     } else if player.is_grounded.y && last_jump.elapsed().as_secs_f32() > JUMP_TIME
@@ -154,6 +288,9 @@ fn change_player_acceleration(
                 KeyCode::ShiftLeft => {
                     if player.is_flying {
                         player.velocity.y = -JUMP_VELOCITY * 2.0;
+                    } else if player.is_climbing {
+                        // Hold on to the ladder
+                        player.velocity.y = player.velocity.y.max(0.0);
                     } else if player.is_swimming {
                         vertical_acceleration.y = -30.0
                     }
@@ -172,17 +309,23 @@ fn change_player_acceleration(
 
     if player.is_flying && keys.pressed(KeyCode::ControlLeft) {
         acceleration *= 10.0;
+    } else if player.is_sneaking {
+        acceleration.x *= SNEAK_SPEED;
+        acceleration.z *= SNEAK_SPEED;
+    } else if player.is_sprinting {
+        acceleration.x *= SPRINT_SPEED;
+        acceleration.z *= SPRINT_SPEED;
     }
 
     if player.is_flying {
         acceleration *= 140.0;
     } else if player.is_swimming {
         if acceleration.y == 0.0 {
-            acceleration.y = -10.0;
+            acceleration.y = SWIM_GRAVITY;
         }
         acceleration.x *= 40.0;
         acceleration.z *= 40.0;
-    } else if player.is_grounded.y {
+    } else if player.is_grounded.y || player.is_climbing {
         acceleration *= 100.0;
     } else if player.velocity.x.abs() > 2.0
         || player.velocity.z.abs() > 2.0
@@ -195,8 +338,8 @@ fn change_player_acceleration(
         acceleration *= 20.0;
     }
 
-    if !player.is_flying && !player.is_swimming {
-        acceleration += GRAVITY;
+    if !player.is_flying && !player.is_swimming && !player.is_climbing {
+        acceleration += physics_config.gravity;
     }
 
     player.acceleration = acceleration;
@@ -207,11 +350,15 @@ fn simulate_player_physics(
     origin: Res<Origin>,
     world_map: Res<WorldMap>,
     fixed_time: Res<Time>,
+    physics_config: Res<PhysicsConfig>,
     mut player: Query<(&mut Player, &mut Transform, &Aabb)>,
 ) {
     let (mut player, mut transform, player_aabb) = player.single_mut();
     let delta_time = fixed_time.delta_secs();
 
+    // Sneaking players stop at edges instead of walking off them
+    let guard_edges = player.is_sneaking && player.is_grounded.y;
+
     if player.velocity.x != 0.0 {
         player.is_grounded.x = false;
     }
@@ -224,6 +371,18 @@ fn simulate_player_physics(
 
     let accel = player.acceleration;
     player.velocity += accel * delta_time;
+    // Same as the server, only the speed along gravity is limited.
+    if let Some(down) = physics_config.gravity.try_normalize() {
+        let falling_speed = player.velocity.dot(down);
+        if falling_speed > physics_config.terminal_velocity {
+            player.velocity -= down * (falling_speed - physics_config.terminal_velocity);
+        }
+    }
+
+    let player_aabb_at = |translation: Vec3| Aabb {
+        center: player_aabb.center + Vec3A::from(translation),
+        half_extents: player_aabb.half_extents,
+    };
 
     let mut friction = Vec3::ZERO;
     for velocity in [
@@ -270,6 +429,25 @@ fn simulate_player_physics(
             }
         }
 
+        // Walk up onto ledges that are low enough instead of stopping at them. Only while on the
+        // ground, the vertical move that came first set it if the player is.
+        if velocity.y == 0.0
+            && player.is_grounded.y
+            && !player.is_flying
+            && physics_config.step_height > 0.0
+        {
+            if let Some(step) = step_up(
+                &world_map,
+                &origin,
+                &player_aabb,
+                &collisions,
+                physics_config.step_height,
+            ) {
+                transform.translation = pos_after_move + Vec3::Y * step;
+                continue;
+            }
+        }
+
         let mut move_back = Vec3::ZERO;
         let delta_time = Vec3::splat(delta_time);
 
@@ -351,8 +529,23 @@ fn simulate_player_physics(
             }
         }
 
-        if transform.translation != pos_after_move + move_back {
-            transform.translation = pos_after_move + move_back;
+        let new_translation = pos_after_move + move_back;
+
+        if guard_edges
+            && velocity.y == 0.0
+            && has_floor(&world_map, &origin, player_aabb_at(transform.translation))
+            && !has_floor(&world_map, &origin, player_aabb_at(new_translation))
+        {
+            if velocity.x != 0.0 {
+                player.velocity.x = 0.0;
+            } else {
+                player.velocity.z = 0.0;
+            }
+            continue;
+        }
+
+        if transform.translation != new_translation {
+            transform.translation = new_translation;
         }
     }
 
@@ -424,6 +617,173 @@ fn swimming(
     }
 }
 
+// How far up the aabb must be moved to stand on top of the blocks it collides with, if it is
+// within the step height and there is room for it there.
+fn step_up(
+    world_map: &WorldMap,
+    origin: &Origin,
+    aabb: &Aabb,
+    collisions: &[(Vec3, BlockId)],
+    step_height: f32,
+) -> Option<f32> {
+    let blocks = Blocks::get();
+
+    let mut step: f32 = 0.0;
+    for (overlap, block_id) in collisions {
+        if !matches!(
+            blocks.get_config(*block_id).friction(),
+            Friction::Static { .. }
+        ) {
+            continue;
+        }
+
+        // The overlap is negative for blocks whose center is above the aabb's, they're too high.
+        if overlap.y <= 0.0 {
+            return None;
+        }
+        step = step.max(overlap.y);
+    }
+
+    if step == 0.0 || step > step_height {
+        return None;
+    }
+
+    // Small epsilon so it doesn't collide with the blocks it stepped onto
+    let step = step + 0.001;
+    let stepped_aabb = Aabb {
+        center: aabb.center + Vec3A::Y * step,
+        half_extents: aabb.half_extents,
+    };
+
+    let start = stepped_aabb.min().floor().as_ivec3() + origin.0;
+    let stop = stepped_aabb.max().floor().as_ivec3() + origin.0;
+    for x in start.x..=stop.x {
+        for y in start.y..=stop.y {
+            for z in start.z..=stop.z {
+                let Some(block_id) = world_map.get_block(&IVec3::new(x, y, z)) else {
+                    return None;
+                };
+
+                if matches!(
+                    blocks.get_config(block_id).friction(),
+                    Friction::Static { .. }
+                ) {
+                    return None;
+                }
+            }
+        }
+    }
+
+    return Some(step);
+}
+
+// If there is a solid block right below the aabb. Must match the server's check, or the server
+// moves sneaking players back from edges the client let them walk out on.
+fn has_floor(world_map: &WorldMap, origin: &Origin, aabb: Aabb) -> bool {
+    let blocks = Blocks::get();
+    let min = aabb.min().floor().as_ivec3() + origin.0;
+    let max = aabb.max().floor().as_ivec3() + origin.0;
+    let y = (aabb.min().y - 0.05).floor() as i32 + origin.0.y;
+
+    for x in min.x..=max.x {
+        for z in min.z..=max.z {
+            match world_map.get_block(&IVec3::new(x, y, z)) {
+                Some(block_id) if blocks.get_config(block_id).is_solid() => return true,
+                Some(_) => (),
+                // Not loaded, same as the server
+                None => return true,
+            }
+        }
+    }
+
+    return false;
+}
+
+fn update_movement_state(
+    net: Res<NetworkClient>,
+    keys: Res<ButtonInput<KeyCode>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut player_query: Query<&mut Player>,
+) {
+    let mut player = player_query.single_mut();
+    let has_input = window.single().cursor_options.grab_mode != CursorGrabMode::None;
+
+    // Shift and control move the player up and down, and faster, in other movement modes.
+    let can_sneak_or_sprint =
+        !player.is_flying && !player.is_swimming && !player.is_climbing && !player.is_riding;
+
+    let is_sneaking = has_input && can_sneak_or_sprint && keys.pressed(KeyCode::ShiftLeft);
+    let is_sprinting = has_input
+        && can_sneak_or_sprint
+        && !is_sneaking
+        && keys.pressed(KeyCode::ControlLeft)
+        && keys.pressed(KeyCode::KeyW);
+
+    if player.is_sneaking != is_sneaking {
+        player.is_sneaking = is_sneaking;
+        net.send_property(properties::SNEAKING, &is_sneaking);
+    }
+
+    if player.is_sprinting != is_sprinting {
+        player.is_sprinting = is_sprinting;
+        net.send_property(properties::SPRINTING, &is_sprinting);
+    }
+}
+
+fn sneak_camera(
+    player_query: Query<&Player, Changed<Player>>,
+    mut camera_query: Query<&mut Transform, With<Head>>,
+    mut is_lowered: Local<bool>,
+) {
+    let Ok(player) = player_query.get_single() else {
+        return;
+    };
+
+    if player.is_sneaking == *is_lowered {
+        return;
+    }
+    *is_lowered = player.is_sneaking;
+
+    let mut transform = camera_query.single_mut();
+    if player.is_sneaking {
+        transform.translation.y -= SNEAK_CAMERA_OFFSET;
+    } else {
+        transform.translation.y += SNEAK_CAMERA_OFFSET;
+    }
+}
+
+fn climbing(
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    mut player: Query<(&mut Player, &Transform, &Aabb)>,
+) {
+    let (mut player, transform, player_aabb) = player.single_mut();
+
+    let player_aabb = Aabb {
+        center: player_aabb.center + Vec3A::from(transform.translation),
+        half_extents: player_aabb.half_extents,
+    };
+
+    let blocks = Blocks::get();
+
+    let start = player_aabb.min().floor().as_ivec3() + origin.0;
+    let stop = player_aabb.max().floor().as_ivec3() + origin.0;
+    let mut is_climbing = false;
+    for x in start.x..=stop.x {
+        for y in start.y..=stop.y {
+            for z in start.z..=stop.z {
+                if let Some(block_id) = world_map.get_block(&IVec3::new(x, y, z)) {
+                    is_climbing |= blocks.get_config(block_id).is_climbable();
+                }
+            }
+        }
+    }
+
+    if player.is_climbing != is_climbing {
+        player.is_climbing = is_climbing;
+    }
+}
+
 fn send_position_to_server(
     net: Res<NetworkClient>,
     origin: Res<Origin>,
@@ -0,0 +1,344 @@
+use bevy::{
+    animation::RepeatAnimation,
+    gltf::Gltf,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::{
+    assets::models::{ModelAssetId, Models},
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    player::{camera_effects, Head, Player},
+    world::{
+        blocks::{Blocks, Friction},
+        world_map::WorldMap,
+        Origin,
+    },
+};
+
+// How far from the head the camera is when nothing is in the way
+const CAMERA_DISTANCE: f32 = 4.0;
+// How close the camera can get to a block it is pushed in front of, keeps the near plane from
+// cutting into it.
+const BLOCK_MARGIN: f32 = 0.2;
+// Length of the steps taken between the head and the camera when looking for blocks in the way
+const RAY_STEP: f32 = 0.05;
+// Horizontal speed above which the model plays its move animation
+const MOVE_ANIMATION_SPEED: f32 = 0.5;
+
+// Like the camera effects, the third person offset is added to the camera's transform at the end
+// of the frame and taken off again at the start of the next, so the rest of the client always sees
+// the camera where the player's head is. It is added after the effects and removed before them.
+pub struct ThirdPersonPlugin;
+impl Plugin for ThirdPersonPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraMode>()
+            .init_resource::<ThirdPersonCamera>()
+            .init_resource::<PlayerModel>()
+            .add_systems(
+                PreUpdate,
+                remove_camera_offset
+                    .before(camera_effects::remove_camera_effects)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    switch_camera_mode,
+                    spawn_player_model.run_if(resource_changed::<PlayerModel>),
+                    update_player_model.after(spawn_player_model),
+                )
+                    .run_if(in_state(GameState::Playing)),
+            )
+            // The server sends the model as soon as the player joins
+            .add_systems(Update, handle_player_model_property)
+            .add_systems(
+                PostUpdate,
+                apply_camera_offset
+                    .after(camera_effects::apply_camera_effects)
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnExit(GameState::Playing), reset);
+    }
+}
+
+/// Where the camera is in relation to the player, cycled through with F4.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    FirstPerson,
+    /// Behind the player, looking the same way
+    Behind,
+    /// In front of the player, looking back at them
+    Front,
+}
+
+impl CameraMode {
+    fn next(&self) -> Self {
+        return match self {
+            Self::FirstPerson => Self::Behind,
+            Self::Behind => Self::Front,
+            Self::Front => Self::FirstPerson,
+        };
+    }
+
+    pub fn is_first_person(&self) -> bool {
+        return *self == Self::FirstPerson;
+    }
+}
+
+#[derive(Resource, Default)]
+struct ThirdPersonCamera {
+    // The offset that was added to the camera this frame, removed again next frame
+    applied_translation: Vec3,
+    applied_rotation: Quat,
+    // How far from the head the camera is, it moves in at once when a block gets in the way and
+    // eases back out when it's gone.
+    distance: f32,
+}
+
+/// The player's own model as told by the server through the "player_model" property. The server
+/// doesn't send it with the other models, it's only drawn in third person.
+#[derive(Resource, Default)]
+struct PlayerModel {
+    asset: Option<ModelAssetId>,
+    // Index of the animation played while moving
+    move_animation: Option<u32>,
+}
+
+/// Marks the entity of the player's own model
+#[derive(Component)]
+struct OwnModel;
+
+fn reset(
+    mut commands: Commands,
+    mut camera_mode: ResMut<CameraMode>,
+    mut third_person_camera: ResMut<ThirdPersonCamera>,
+    mut player_model: ResMut<PlayerModel>,
+    model_query: Query<Entity, With<OwnModel>>,
+) {
+    *camera_mode = CameraMode::default();
+    *third_person_camera = ThirdPersonCamera::default();
+    *player_model = PlayerModel::default();
+
+    for entity in model_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn switch_camera_mode(
+    keys: Res<ButtonInput<KeyCode>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut camera_mode: ResMut<CameraMode>,
+    mut third_person_camera: ResMut<ThirdPersonCamera>,
+) {
+    if window.single().cursor_options.grab_mode == CursorGrabMode::None {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::F4) {
+        *camera_mode = camera_mode.next();
+        // Starts at the head and moves out
+        third_person_camera.distance = 0.0;
+    }
+}
+
+fn handle_player_model_property(
+    mut property_events: EventReader<ServerProperty>,
+    mut player_model: ResMut<PlayerModel>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::PLAYER_MODEL {
+            continue;
+        }
+
+        let value = property
+            .parse::<Option<properties::PlayerModel>>()
+            .flatten();
+
+        *player_model = PlayerModel {
+            asset: value.as_ref().map(|value| value.model_id),
+            move_animation: value.and_then(|value| value.move_animation),
+        };
+    }
+}
+
+fn spawn_player_model(
+    mut commands: Commands,
+    models: Res<Models>,
+    gltf_assets: Res<Assets<Gltf>>,
+    player_model: Res<PlayerModel>,
+    player_query: Query<Entity, With<Player>>,
+    model_query: Query<Entity, With<OwnModel>>,
+) {
+    for entity in model_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let Some(model_config) = player_model
+        .asset
+        .and_then(|asset| models.get_config(&asset))
+    else {
+        return;
+    };
+
+    let Some(gltf) = gltf_assets.get(&model_config.gltf_handle) else {
+        return;
+    };
+
+    // It is not given the Model component, it's not one of the server's models and already has
+    // the player's shadow.
+    let model_entity = commands
+        .spawn((
+            SceneRoot(gltf.scenes[0].clone()),
+            AnimationGraphHandle(model_config.animation_graph.clone().unwrap()),
+            AnimationPlayer::default(),
+            Transform::default(),
+            Visibility::Hidden,
+            OwnModel,
+        ))
+        .id();
+
+    commands
+        .entity(player_query.single())
+        .add_child(model_entity);
+}
+
+// The model faces the way the head does, and plays its move animation while the player walks.
+fn update_player_model(
+    camera_mode: Res<CameraMode>,
+    models: Res<Models>,
+    player_model: Res<PlayerModel>,
+    player_query: Query<&Player>,
+    head_query: Query<&Transform, With<Head>>,
+    mut model_query: Query<
+        (&mut Transform, &mut Visibility, &mut AnimationPlayer),
+        (With<OwnModel>, Without<Head>),
+    >,
+) {
+    let Ok((mut transform, mut visibility, mut animation_player)) = model_query.get_single_mut()
+    else {
+        return;
+    };
+
+    let new_visibility = if camera_mode.is_first_person() {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    };
+    visibility.set_if_neq(new_visibility);
+
+    let (yaw, _, _) = head_query.single().rotation.to_euler(EulerRot::YXZ);
+    transform.rotation = Quat::from_rotation_y(yaw);
+
+    let Some(move_animation) = player_model
+        .move_animation
+        .zip(player_model.asset)
+        .and_then(|(index, asset)| models.get_config(&asset)?.animations.get(index as usize))
+    else {
+        return;
+    };
+
+    let player = player_query.single();
+    let is_moving = player.velocity.with_y(0.0).length() > MOVE_ANIMATION_SPEED;
+    if is_moving && !animation_player.is_playing_animation(*move_animation) {
+        animation_player
+            .play(*move_animation)
+            .set_repeat(RepeatAnimation::Forever);
+    } else if !is_moving && animation_player.is_playing_animation(*move_animation) {
+        animation_player.stop(*move_animation);
+    }
+}
+
+fn remove_camera_offset(
+    mut third_person_camera: ResMut<ThirdPersonCamera>,
+    mut camera_query: Query<&mut Transform, With<Head>>,
+) {
+    let Ok(mut transform) = camera_query.get_single_mut() else {
+        return;
+    };
+
+    transform.translation -= third_person_camera.applied_translation;
+    transform.rotation = transform.rotation * third_person_camera.applied_rotation.inverse();
+
+    third_person_camera.applied_translation = Vec3::ZERO;
+    third_person_camera.applied_rotation = Quat::IDENTITY;
+}
+
+fn apply_camera_offset(
+    time: Res<Time>,
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    camera_mode: Res<CameraMode>,
+    mut third_person_camera: ResMut<ThirdPersonCamera>,
+    player_query: Query<&Transform, (With<Player>, Without<Head>)>,
+    mut camera_query: Query<&mut Transform, With<Head>>,
+) {
+    let Ok(mut transform) = camera_query.get_single_mut() else {
+        return;
+    };
+
+    let (direction, rotation) = match *camera_mode {
+        CameraMode::FirstPerson => return,
+        CameraMode::Behind => (transform.rotation * Vec3::Z, Quat::IDENTITY),
+        CameraMode::Front => (
+            transform.rotation * Vec3::NEG_Z,
+            Quat::from_rotation_y(std::f32::consts::PI),
+        ),
+    };
+
+    // The head is a child of the player, which is never rotated
+    let head_position = player_query.single().translation + transform.translation;
+    let max_distance = obstructed_distance(
+        &world_map,
+        &origin,
+        head_position,
+        direction,
+        CAMERA_DISTANCE,
+    );
+
+    let distance = &mut third_person_camera.distance;
+    if max_distance < *distance {
+        *distance = max_distance;
+    } else {
+        *distance += (max_distance - *distance) * (time.delta_secs() * 5.0).min(1.0);
+    }
+
+    let translation = direction * *distance;
+    transform.translation += translation;
+    transform.rotation = transform.rotation * rotation;
+    third_person_camera.applied_translation = translation;
+    third_person_camera.applied_rotation = rotation;
+}
+
+// How far the camera can be moved from the head in the direction before it ends up inside a solid
+// block. Chunks that aren't loaded don't stop it.
+fn obstructed_distance(
+    world_map: &WorldMap,
+    origin: &Origin,
+    start: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> f32 {
+    let blocks = Blocks::get();
+
+    let mut distance = 0.0;
+    while distance < max_distance {
+        distance = (distance + RAY_STEP).min(max_distance);
+        let position = (start + direction * distance).floor().as_ivec3();
+        let Some(block_id) = world_map.get_block(&(origin.0 + position)) else {
+            continue;
+        };
+
+        if matches!(
+            blocks.get_config(block_id).friction(),
+            Friction::Static { .. }
+        ) {
+            return (distance - BLOCK_MARGIN).max(0.0);
+        }
+    }
+
+    return max_distance;
+}
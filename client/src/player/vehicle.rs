@@ -0,0 +1,310 @@
+use bevy::{
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::{
+    networking::properties,
+    player::{Head, Player},
+    world::{
+        blocks::{Blocks, Friction},
+        world_map::WorldMap,
+        Origin,
+    },
+};
+
+// The server validates where the vehicle goes, these only need to be close to what it expects.
+const GRAVITY: f32 = -32.0;
+// Blocks with more vertical drag than this are deep enough to float in
+const LIQUID_DRAG: f32 = 0.4;
+// How fast a boat moves towards the surface of the liquid it floats in
+const BUOYANCY: f32 = 6.0;
+const BOAT_ACCELERATION: f32 = 12.0;
+// Boats can be pushed over land, but slowly
+const BOAT_LAND_SPEED: f32 = 1.0;
+const MINECART_ACCELERATION: f32 = 8.0;
+// Fraction of the vehicle's speed that is left after a second of not accelerating
+const BOAT_DRAG: f32 = 0.3;
+const MINECART_DRAG: f32 = 0.8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VehicleKind {
+    Boat,
+    Minecart,
+}
+
+/// The vehicle the player is driving. The server sends it as the "vehicle" property when the
+/// player mounts it.
+#[derive(Clone, Copy)]
+pub struct Vehicle {
+    kind: VehicleKind,
+    max_speed: f32,
+    // Where the player sits relative to the vehicle
+    seat: Vec3,
+}
+
+impl Vehicle {
+    pub fn from_property(value: properties::Vehicle) -> Option<Self> {
+        let kind = match value.kind.as_str() {
+            "boat" => VehicleKind::Boat,
+            "minecart" => VehicleKind::Minecart,
+            _ => return None,
+        };
+
+        return Some(Self {
+            kind,
+            max_speed: value.max_speed as f32,
+            seat: Vec3::from_array(value.seat.map(|v| v as f32)),
+        });
+    }
+}
+
+pub(super) fn is_driving(player_query: Query<&Player>) -> bool {
+    return player_query
+        .get_single()
+        .is_ok_and(|player| player.is_riding && player.vehicle.is_some());
+}
+
+// The vehicle is simulated as the player's position minus the seat. Its position is sent to the
+// server as the player's, which corrects it if it goes somewhere it shouldn't.
+pub(super) fn drive_vehicle(
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    fixed_time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<&Transform, (With<Head>, Without<Player>)>,
+    mut player_query: Query<(&mut Player, &mut Transform)>,
+) {
+    let (mut player, mut transform) = player_query.single_mut();
+    let Some(vehicle) = player.vehicle else {
+        return;
+    };
+
+    let delta_time = fixed_time.delta_secs();
+
+    let camera_forward = camera_query.single().forward();
+    let forward = Vec3::new(camera_forward.x, 0., camera_forward.z).normalize_or_zero();
+    let sideways = Vec3::new(-forward.z, 0., forward.x);
+
+    let mut direction = Vec3::ZERO;
+    if window.single().cursor_options.grab_mode != CursorGrabMode::None {
+        for key in keys.get_pressed() {
+            match key {
+                KeyCode::KeyW => direction += forward,
+                KeyCode::KeyS => direction -= forward,
+                KeyCode::KeyA => direction -= sideways,
+                KeyCode::KeyD => direction += sideways,
+                _ => (),
+            }
+        }
+    }
+    let direction = direction.normalize_or_zero();
+
+    let mut position = transform.translation - vehicle.seat;
+    let mut velocity = player.velocity;
+
+    match vehicle.kind {
+        VehicleKind::Boat => {
+            let surface = liquid_surface(&world_map, &origin, position);
+
+            let max_speed = if surface.is_some() {
+                vehicle.max_speed
+            } else {
+                vehicle.max_speed.min(BOAT_LAND_SPEED)
+            };
+
+            let horizontal = (velocity.with_y(0.0) + direction * BOAT_ACCELERATION * delta_time)
+                .clamp_length_max(max_speed)
+                * BOAT_DRAG.powf(delta_time);
+            velocity.x = horizontal.x;
+            velocity.z = horizontal.z;
+
+            if let Some(surface) = surface {
+                velocity.y = (surface - position.y) * BUOYANCY;
+            } else {
+                velocity.y += GRAVITY * delta_time;
+            }
+
+            move_freely(
+                &world_map,
+                &origin,
+                &mut position,
+                &mut velocity,
+                delta_time,
+            );
+        }
+        VehicleKind::Minecart => {
+            if let Some((_, rail_direction)) = find_rail(&world_map, &origin, position) {
+                let speed = (velocity.dot(rail_direction)
+                    + direction.dot(rail_direction) * MINECART_ACCELERATION * delta_time)
+                    .clamp(-vehicle.max_speed, vehicle.max_speed)
+                    * MINECART_DRAG.powf(delta_time);
+
+                let (new_position, stopped) =
+                    move_along_rail(&world_map, &origin, position, speed * delta_time).unwrap();
+                position = new_position;
+                velocity = if stopped {
+                    Vec3::ZERO
+                } else {
+                    rail_direction * speed
+                };
+            } else {
+                velocity.y += GRAVITY * delta_time;
+                move_freely(
+                    &world_map,
+                    &origin,
+                    &mut position,
+                    &mut velocity,
+                    delta_time,
+                );
+            }
+        }
+    }
+
+    player.velocity = velocity;
+    transform.translation = position + vehicle.seat;
+}
+
+fn is_solid(world_map: &WorldMap, block_position: IVec3) -> bool {
+    let Some(block_id) = world_map.get_block(&block_position) else {
+        // Unloaded chunks are solid so the vehicle doesn't go into them
+        return true;
+    };
+
+    let config = Blocks::get().get_config(block_id);
+    return matches!(config.friction(), Friction::Static { .. }) && !config.is_rail();
+}
+
+// Move one axis at a time, stopping at solid blocks. Vehicles are treated as a point, only the
+// block they're in is checked.
+fn move_freely(
+    world_map: &WorldMap,
+    origin: &Origin,
+    position: &mut Vec3,
+    velocity: &mut Vec3,
+    delta_time: f32,
+) {
+    for axis in [Vec3::Y, Vec3::X, Vec3::Z] {
+        let new_position = *position + *velocity * axis * delta_time;
+        // Lift the point a little when moving sideways so the floor doesn't stop it
+        let lift = if axis == Vec3::Y { 0.0 } else { 0.1 };
+        let block_position = (new_position + Vec3::Y * lift).floor().as_ivec3() + origin.0;
+
+        if is_solid(world_map, block_position) {
+            if axis == Vec3::Y && velocity.y < 0.0 {
+                // Land on top of the block
+                position.y = (block_position.y + 1 - origin.0.y) as f32;
+            }
+            *velocity *= Vec3::ONE - axis;
+        } else {
+            *position = new_position;
+        }
+    }
+}
+
+fn is_liquid(world_map: &WorldMap, block_position: IVec3) -> bool {
+    let Some(block_id) = world_map.get_block(&block_position) else {
+        return false;
+    };
+
+    match Blocks::get().get_config(block_id).friction() {
+        Friction::Drag(drag) => return drag.y > LIQUID_DRAG,
+        Friction::Static { .. } => return false,
+    }
+}
+
+// Height of the surface of the liquid at the position, it can be within a block above it. Same
+// as `fmc::physics::liquid_surface`.
+fn liquid_surface(world_map: &WorldMap, origin: &Origin, position: Vec3) -> Option<f32> {
+    let mut block_position = position.floor().as_ivec3() + origin.0;
+
+    if !is_liquid(world_map, block_position) {
+        block_position.y -= 1;
+        if !is_liquid(world_map, block_position) {
+            return None;
+        }
+    }
+
+    while is_liquid(world_map, block_position + IVec3::Y) {
+        block_position.y += 1;
+    }
+
+    return Some((block_position.y + 1 - origin.0.y) as f32);
+}
+
+// The rail block at or below the position, in world coordinates, and the direction it goes in.
+// Same as `fmc::physics::find_rail`.
+fn find_rail(world_map: &WorldMap, origin: &Origin, position: Vec3) -> Option<(IVec3, Vec3)> {
+    let blocks = Blocks::get();
+    let block_position = position.floor().as_ivec3() + origin.0;
+
+    for block_position in [block_position, block_position - IVec3::Y] {
+        let Some(block_id) = world_map.get_block(&block_position) else {
+            continue;
+        };
+
+        if !blocks.get_config(block_id).is_rail() {
+            continue;
+        }
+
+        let rotation = world_map
+            .get_block_state(&block_position)
+            .map(|state| state.rotation().as_quat())
+            .unwrap_or_default();
+
+        return Some((block_position, (rotation * Vec3::Z).round()));
+    }
+
+    return None;
+}
+
+// Same as `fmc::physics::move_along_rail`.
+fn move_along_rail(
+    world_map: &WorldMap,
+    origin: &Origin,
+    position: Vec3,
+    distance: f32,
+) -> Option<(Vec3, bool)> {
+    let (mut rail_position, direction) = find_rail(world_map, origin, position)?;
+
+    let mut remaining = distance;
+    let mut position = position;
+    while remaining != 0.0 {
+        let step = remaining.clamp(-1.0, 1.0);
+        remaining -= step;
+
+        let next = position + direction * step;
+        match find_rail(world_map, origin, next)
+            .or_else(|| find_rail(world_map, origin, next + Vec3::Y))
+            .filter(|(_, next_direction)| next_direction.abs() == direction.abs())
+        {
+            Some((next_rail, _)) => {
+                rail_position = next_rail;
+                position = next;
+            }
+            None => {
+                let center = (rail_position - origin.0).as_vec3() + 0.5;
+                let end = center + direction * step.signum() * 0.5;
+                position = position + direction * (end - position).dot(direction);
+                return Some((
+                    snap_to_rail(origin, position, rail_position, direction),
+                    true,
+                ));
+            }
+        }
+    }
+
+    return Some((
+        snap_to_rail(origin, position, rail_position, direction),
+        false,
+    ));
+}
+
+fn snap_to_rail(origin: &Origin, position: Vec3, rail_position: IVec3, direction: Vec3) -> Vec3 {
+    let rail_position = rail_position - origin.0;
+    let center = rail_position.as_vec3() + 0.5;
+    let across = Vec3::ONE - direction.abs() - Vec3::Y;
+    let position = position - (position - center) * across;
+    return position.with_y(rail_position.y as f32);
+}
@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use fmc_protocol::messages;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    rendering::{
+        chunk,
+        lighting::{Light, LightMap},
+    },
+    utils,
+    world::{blocks::BlockState, Origin},
+};
+
+// How long an animated block is kept in its last pose after the animation is over, waiting for
+// the server to change the block in the world.
+const LINGER: f32 = 0.5;
+
+// The server sends "block_animation" when a block should be animated. The animated block is drawn separately from the chunks, the block in the world is changed by
+// the server when the animation is over.
+pub struct BlockAnimationPlugin;
+impl Plugin for BlockAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (handle_block_animation_properties, animate_blocks)
+                .chain()
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(OnExit(GameState::Playing), remove_animated_blocks);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BlockPose {
+    translation: Vec3,
+    rotation: Quat,
+}
+
+#[derive(Component)]
+struct AnimatedBlock {
+    position: IVec3,
+    pivot: Vec3,
+    from: BlockPose,
+    to: BlockPose,
+    duration: f32,
+    elapsed: f32,
+}
+
+fn parse_block_animation(
+    animation: properties::BlockAnimation,
+) -> (AnimatedBlock, u16, BlockState) {
+    let pose = |pose: properties::BlockPose| BlockPose {
+        translation: Vec3::from_array(pose.translation.map(|v| v as f32)),
+        rotation: Quat::from_array(pose.rotation.map(|v| v as f32)).normalize(),
+    };
+
+    let block_state = animation.block_state.map(BlockState).unwrap_or_default();
+
+    let animated_block = AnimatedBlock {
+        position: IVec3::from_array(animation.position),
+        duration: animation.duration.max(0.0),
+        pivot: Vec3::from_array(animation.pivot.map(|v| v as f32)),
+        from: pose(animation.from),
+        to: pose(animation.to),
+        elapsed: 0.0,
+    };
+
+    return (animated_block, animation.block_id, block_state);
+}
+
+fn handle_block_animation_properties(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    light_map: Res<LightMap>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::BLOCK_ANIMATION {
+            continue;
+        }
+
+        let Some((animated_block, block_id, block_state)) =
+            property.parse().map(parse_block_animation)
+        else {
+            continue;
+        };
+
+        // Full sunlight if the light isn't known
+        let light = light_map
+            .get_light(animated_block.position)
+            .unwrap_or(Light(0b1111_0000));
+        let Some((material, mesh)) = chunk::build_block_mesh(block_id, block_state, light) else {
+            continue;
+        };
+
+        // Its transform is set when it is animated
+        commands.spawn((
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(material),
+            Transform::default(),
+            Visibility::Hidden,
+            animated_block,
+        ));
+    }
+}
+
+fn animate_blocks(
+    mut commands: Commands,
+    time: Res<Time>,
+    origin: Res<Origin>,
+    mut block_update_events: EventReader<messages::BlockUpdates>,
+    mut animated_blocks: Query<(Entity, &mut AnimatedBlock, &mut Transform, &mut Visibility)>,
+) {
+    let updated_chunks: Vec<IVec3> = block_update_events
+        .read()
+        .map(|update| update.chunk_position)
+        .collect();
+
+    for (entity, mut animated_block, mut transform, mut visibility) in animated_blocks.iter_mut() {
+        animated_block.elapsed += time.delta_secs();
+
+        let is_over = animated_block.elapsed >= animated_block.duration;
+        let chunk_position = utils::world_position_to_chunk_pos(animated_block.position);
+        // Removed when the server changes the block it turns into, or when the server doesn't
+        // change anything.
+        if (is_over && updated_chunks.contains(&chunk_position))
+            || animated_block.elapsed >= animated_block.duration + LINGER
+        {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let progress = if animated_block.duration > 0.0 {
+            (animated_block.elapsed / animated_block.duration).min(1.0)
+        } else {
+            1.0
+        };
+        let translation = animated_block
+            .from
+            .translation
+            .lerp(animated_block.to.translation, progress);
+        let rotation = animated_block
+            .from
+            .rotation
+            .slerp(animated_block.to.rotation, progress);
+
+        let mut block_transform =
+            Transform::from_translation((animated_block.position - origin.0).as_vec3());
+        block_transform.rotate_around(block_transform.translation + animated_block.pivot, rotation);
+        block_transform.translation += translation;
+
+        *transform = block_transform;
+        *visibility = Visibility::Visible;
+    }
+}
+
+fn remove_animated_blocks(
+    mut commands: Commands,
+    animated_blocks: Query<Entity, With<AnimatedBlock>>,
+) {
+    for entity in animated_blocks.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
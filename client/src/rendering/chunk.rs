@@ -180,6 +180,7 @@ struct MeshBuilder {
     pub triangles: Vec<u32>,
     pub normals: Vec<[f32; 3]>,
     pub packed_bits: Vec<u32>,
+    pub emission: Vec<u32>,
     //pub texture_indices: Vec<i32>,
     pub face_count: u32,
 }
@@ -193,6 +194,7 @@ impl MeshBuilder {
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
         mesh.insert_attribute(materials::ATTRIBUTE_PACKED_BITS_0, self.packed_bits);
+        mesh.insert_attribute(materials::ATTRIBUTE_PACKED_BITS_1, self.emission);
 
         mesh.insert_indices(Indices::U32(self.triangles));
         return mesh;
@@ -203,8 +205,9 @@ impl MeshBuilder {
         position: [f32; 3],
         quad: &QuadPrimitive,
         light: Light,
-        block_state: BlockState,
+        rotation: BlockRotation,
         cull_delimiter: Option<(f32, f32)>,
+        emission: u32,
     ) {
         let mut vertices = quad.vertices.clone();
 
@@ -218,7 +221,7 @@ impl MeshBuilder {
 
         for (i, mut vertex) in vertices.into_iter().enumerate() {
             // TODO: Upside down
-            block_state.rotation().rotate_vertex(&mut vertex);
+            rotation.rotate_vertex(&mut vertex);
 
             vertex[0] += position[0];
             vertex[1] += position[1];
@@ -236,7 +239,8 @@ impl MeshBuilder {
                     // diagonal texture marker
                     | (quad.rotate_texture as u32) << 21
                     | (light.0 as u32) << 22,
-            )
+            );
+            self.emission.push(emission);
         }
         self.triangles
             .extend(TRIANGLES.iter().map(|x| x + 4 * self.face_count));
@@ -244,6 +248,57 @@ impl MeshBuilder {
     }
 }
 
+// Blocks that emit light are drawn glowing in their light color, no matter how dark it is around
+// them. Packed from right to left as 8 bits each of red, green and blue, and 4 bits of light
+// level. 0 if the block doesn't emit light.
+fn pack_emission(block_config: &Block) -> u32 {
+    let level = block_config.light_level();
+    if level == 0 {
+        return 0;
+    }
+
+    // Linear so the shader can multiply with it directly
+    let color = block_config.light_color().to_linear();
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u32;
+    return channel(color.red)
+        | channel(color.green) << 8
+        | channel(color.blue) << 16
+        | (level as u32) << 24;
+}
+
+/// The mesh of a single block, for drawing blocks outside of the chunks, e.g. when they are
+/// animated. The block's corner is at the origin. Returns None if the block isn't a cube, or has
+/// no faces.
+pub fn build_block_mesh(
+    block_id: BlockId,
+    block_state: BlockState,
+    light: Light,
+) -> Option<(Handle<materials::BlockMaterial>, Mesh)> {
+    let block_config = Blocks::get().get_config(block_id);
+    let Block::Cube(cube) = block_config else {
+        return None;
+    };
+
+    let block_state = if block_config.can_have_block_state() {
+        block_state
+    } else {
+        BlockState::default()
+    };
+
+    let emission = pack_emission(block_config);
+    let shape = cube.shape(block_state);
+    let mut builder = MeshBuilder::default();
+    for quad in shape.quads {
+        builder.add_face([0.0; 3], quad, light, shape.rotation, None, emission);
+    }
+
+    if builder.face_count == 0 {
+        return None;
+    }
+
+    return Some((cube.material_handle.clone(), builder.to_mesh()));
+}
+
 async fn build_mesh(
     chunk: ExpandedChunk,
     light_chunk: ExpandedLightChunk,
@@ -269,6 +324,7 @@ async fn build_mesh(
 
                 match block_config {
                     Block::Cube(cube) => {
+                        let emission = pack_emission(block_config);
                         let builder =
                             if let Some(builder) = mesh_builders.get_mut(&cube.material_handle) {
                                 builder
@@ -278,9 +334,10 @@ async fn build_mesh(
                                 mesh_builders.get_mut(&cube.material_handle).unwrap()
                             };
 
-                        for quad in &cube.quads {
+                        let shape = cube.shape(block_state);
+                        for quad in shape.quads {
                             let cull_delimiter = if let Some(mut cull_face) = quad.cull_face {
-                                cull_face = cull_face.rotate(block_state.rotation());
+                                cull_face = cull_face.rotate(shape.rotation);
 
                                 let (x, y, z) = match cull_face {
                                     BlockFace::Back => (x, y, z - 1),
@@ -307,11 +364,9 @@ async fn build_mesh(
                                             BlockState::default()
                                         };
 
-                                    match adjacent_block_config.cull_delimiter(
-                                        cull_face
-                                            .opposite()
-                                            .reverse_rotate(adjacent_block_state.rotation()),
-                                    ) {
+                                    match adjacent_block_config
+                                        .cull_delimiter(cull_face.opposite(), adjacent_block_state)
+                                    {
                                         Some(deli) => Some(deli),
                                         None => continue,
                                     }
@@ -325,7 +380,7 @@ async fn build_mesh(
                             let light = if block_config.is_transparent() {
                                 light_chunk.get_light(x, y, z)
                             } else {
-                                match quad.light_face.rotate(block_state.rotation()) {
+                                match quad.light_face.rotate(shape.rotation) {
                                     BlockFace::Right => light_chunk.get_light(x + 1, y, z),
                                     BlockFace::Left => light_chunk.get_light(x - 1, y, z),
                                     BlockFace::Front => light_chunk.get_light(x, y, z + 1),
@@ -339,8 +394,9 @@ async fn build_mesh(
                                 [x as f32 - 1.0, y as f32 - 1.0, z as f32 - 1.0],
                                 quad,
                                 light,
-                                block_state,
+                                shape.rotation,
                                 cull_delimiter,
+                                emission,
                             );
                         }
                     }
@@ -0,0 +1,202 @@
+use bevy::{
+    prelude::*,
+    render::renderer::{RenderAdapter, RenderAdapterInfo, RenderDevice},
+};
+
+use crate::settings::{Settings, ShadowQuality, WaterQuality};
+
+/// Width and height of the block textures
+pub const BLOCK_TEXTURE_SIZE: u32 = 16;
+
+pub(super) struct DiagnosticsPlugin {
+    pub safe_mode: bool,
+}
+
+impl Plugin for DiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SafeMode(self.safe_mode))
+            .add_systems(PreStartup, detect_capabilities)
+            .add_systems(
+                Update,
+                enforce_fallbacks.run_if(resource_changed::<Settings>),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct SafeMode(bool);
+
+/// What the renderer runs on and which features had to be turned off for it.
+#[derive(Resource)]
+pub struct RendererInfo {
+    /// Name of the gpu
+    pub adapter: String,
+    /// Graphics api, e.g. Vulkan
+    pub backend: String,
+    /// Integrated, discrete, cpu etc.
+    pub device_type: String,
+    pub driver: String,
+    pub max_texture_dimension_2d: u32,
+    pub max_texture_array_layers: u32,
+    pub max_bind_groups: u32,
+    pub max_vertex_attributes: u32,
+    /// If the adapter supports everything WebGPU requires, it is missing features otherwise.
+    pub webgpu_compliant: bool,
+    /// Started with --safe-mode
+    pub safe_mode: bool,
+    pub fallbacks: Fallbacks,
+}
+
+/// Features that are turned off when the gpu can't handle them.
+#[derive(Default)]
+pub struct Fallbacks {
+    /// Pack several block textures into each layer of the block texture array, instead of one
+    /// per layer. Used when there are more block textures than the gpu allows layers.
+    pub block_texture_atlas: bool,
+    /// Skip the shaders that are only there for looks, the depth prepass particles collide with,
+    /// ambient occlusion, sun shadows, water effects and the gpu simulated particle effects.
+    pub simple_shaders: bool,
+    /// Why each fallback was chosen, shown in the renderer info screen.
+    pub reasons: Vec<String>,
+}
+
+impl RendererInfo {
+    /// How many block textures fit along each side of a layer of the block texture array, 1 if
+    /// each texture gets its own layer. Returns None if there are too many textures for the gpu.
+    pub fn block_atlas_columns(&self, texture_count: u32) -> Option<u32> {
+        let use_atlas =
+            self.fallbacks.block_texture_atlas || texture_count > self.max_texture_array_layers;
+        if !use_atlas {
+            return Some(1);
+        }
+
+        // Safe mode puts them all in as few layers as possible, otherwise the layers are only
+        // made big enough to fit.
+        let fits = |columns: u32| {
+            if self.safe_mode {
+                columns * columns >= texture_count
+            } else {
+                texture_count.div_ceil(columns * columns) <= self.max_texture_array_layers
+            }
+        };
+
+        let max_columns = (self.max_texture_dimension_2d / BLOCK_TEXTURE_SIZE).max(1);
+        let mut columns = 2;
+        while !fits(columns) && columns * 2 <= max_columns {
+            columns *= 2;
+        }
+
+        if texture_count.div_ceil(columns * columns) > self.max_texture_array_layers {
+            return None;
+        }
+
+        return Some(columns);
+    }
+
+    /// Lines of text describing the renderer
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Adapter: {}", self.adapter),
+            format!("Backend: {}", self.backend),
+            format!("Device type: {}", self.device_type),
+            format!("Driver: {}", self.driver),
+            format!(
+                "Max texture size: {}, max texture layers: {}",
+                self.max_texture_dimension_2d, self.max_texture_array_layers
+            ),
+            format!(
+                "Max bind groups: {}, max vertex attributes: {}",
+                self.max_bind_groups, self.max_vertex_attributes
+            ),
+            format!(
+                "WebGPU compliant: {}",
+                if self.webgpu_compliant { "Yes" } else { "No" }
+            ),
+            format!("Safe mode: {}", if self.safe_mode { "On" } else { "Off" }),
+        ];
+
+        if self.fallbacks.reasons.is_empty() {
+            lines.push("No fallbacks are used".to_owned());
+        } else {
+            lines.push("Fallbacks:".to_owned());
+            for reason in self.fallbacks.reasons.iter() {
+                lines.push(format!("    {}", reason));
+            }
+        }
+
+        return lines;
+    }
+}
+
+fn detect_capabilities(
+    mut commands: Commands,
+    safe_mode: Res<SafeMode>,
+    render_device: Res<RenderDevice>,
+    adapter: Res<RenderAdapter>,
+    adapter_info: Res<RenderAdapterInfo>,
+) {
+    let limits = render_device.limits();
+    let webgpu_compliant = adapter.get_downlevel_capabilities().is_webgpu_compliant();
+    let software = adapter_info.device_type == wgpu::DeviceType::Cpu;
+
+    let mut fallbacks = Fallbacks::default();
+    if safe_mode.0 {
+        fallbacks.block_texture_atlas = true;
+        fallbacks.simple_shaders = true;
+        fallbacks
+            .reasons
+            .push("Safe mode: block texture atlas, simple shaders".to_owned());
+    } else {
+        // The block textures are only counted when they're loaded, the atlas is turned on then if
+        // there are more than the gpu allows layers, see assets::block_textures.
+        if !webgpu_compliant {
+            fallbacks.simple_shaders = true;
+            fallbacks
+                .reasons
+                .push("Simple shaders: the gpu doesn't support all of WebGPU".to_owned());
+        } else if software {
+            fallbacks.simple_shaders = true;
+            fallbacks
+                .reasons
+                .push("Simple shaders: rendering on the cpu".to_owned());
+        }
+    }
+
+    let info = RendererInfo {
+        adapter: adapter_info.name.clone(),
+        backend: format!("{:?}", adapter_info.backend),
+        device_type: format!("{:?}", adapter_info.device_type),
+        driver: format!("{} {}", adapter_info.driver, adapter_info.driver_info)
+            .trim()
+            .to_owned(),
+        max_texture_dimension_2d: limits.max_texture_dimension_2d,
+        max_texture_array_layers: limits.max_texture_array_layers,
+        max_bind_groups: limits.max_bind_groups,
+        max_vertex_attributes: limits.max_vertex_attributes,
+        webgpu_compliant,
+        safe_mode: safe_mode.0,
+        fallbacks,
+    };
+
+    // Logged so that it ends up in bug reports about black screens
+    for line in info.describe() {
+        info!("{}", line);
+    }
+
+    commands.insert_resource(info);
+}
+
+// Settings that depend on what the fallbacks turned off can't be turned back on.
+fn enforce_fallbacks(renderer_info: Res<RendererInfo>, mut settings: ResMut<Settings>) {
+    if renderer_info.fallbacks.simple_shaders
+        && (settings.particle_collision
+            || settings.ambient_occlusion
+            || settings.sun_shadows != ShadowQuality::Off
+            || settings.water_quality != WaterQuality::Off)
+    {
+        settings.particle_collision = false;
+        settings.ambient_occlusion = false;
+        settings.sun_shadows = ShadowQuality::Off;
+        settings.water_quality = WaterQuality::Off;
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    gltf::{Gltf, GltfMesh, GltfNode},
+    prelude::*,
+};
+use fmc_protocol::messages;
+
+use crate::{
+    assets::models::{Model, Models},
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+};
+
+use super::models::ModelEntities;
+
+// How high the items bob above where the server put them
+const BOB_HEIGHT: f32 = 0.1;
+// Bobs each second
+const BOB_SPEED: f32 = 0.5;
+// Radians each second
+const SPIN_SPEED: f32 = 1.0;
+// Identical items closer than this are drawn as one
+const MERGE_DISTANCE: f32 = 0.5;
+// Seconds between each time the merging is redone, items rarely move
+const MERGE_INTERVAL: f32 = 0.25;
+
+// The server tells the client which of its models are dropped items through the "dropped_item"
+// property. Instead of spawning the model's scene, which is a hierarchy of entities with an
+// animation player, each item is drawn with the meshes of the model directly. All items of the
+// same model share the mesh and material handles, so they are drawn with a single instanced draw
+// call.
+pub struct DroppedItemPlugin;
+impl Plugin for DroppedItemPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DroppedItems>()
+            .init_resource::<DroppedItemMeshes>()
+            .insert_resource(MergeTimer(Timer::from_seconds(
+                MERGE_INTERVAL,
+                TimerMode::Repeating,
+            )))
+            .add_systems(
+                OnExit(GameState::Playing),
+                |mut dropped_items: ResMut<DroppedItems>| dropped_items.clear(),
+            )
+            .add_systems(
+                Update,
+                (
+                    handle_dropped_item_properties,
+                    convert_models,
+                    merge_items,
+                    animate_items,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+// Model ids of the models that are dropped items
+#[derive(Resource, Deref, DerefMut, Default)]
+struct DroppedItems(HashSet<u32>);
+
+// The meshes and materials of the gltf nodes of each model asset, shared by all items
+#[derive(Resource, Deref, DerefMut, Default)]
+struct DroppedItemMeshes(
+    HashMap<u32, Vec<(Handle<Mesh>, Option<Handle<StandardMaterial>>, Transform)>>,
+);
+
+#[derive(Resource, Deref, DerefMut)]
+struct MergeTimer(Timer);
+
+// Inserted on the model entity once it is drawn as a dropped item
+#[derive(Component)]
+struct DroppedItem {
+    // The model asset the meshes were taken from
+    asset: u32,
+    // Offset into the bobbing and spinning so that items don't move in unison
+    phase: f32,
+    // The entity that holds the meshes, it is what is animated, the model entity itself is moved
+    // by the server.
+    visual: Entity,
+}
+
+fn handle_dropped_item_properties(
+    mut dropped_items: ResMut<DroppedItems>,
+    mut deleted_models: EventReader<messages::DeleteModel>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    // Model ids are reused, the server sends the property anew every time the model is sent.
+    for deleted_model in deleted_models.read() {
+        dropped_items.remove(&deleted_model.id);
+    }
+
+    for property in property_events.read() {
+        if property.name != properties::DROPPED_ITEM {
+            continue;
+        }
+
+        if let Some(id) = property.parse::<u32>() {
+            dropped_items.insert(id);
+        }
+    }
+}
+
+// Replace the scene of the item models with the meshes of the model, this is also redone if the
+// server changes the model's asset.
+fn convert_models(
+    mut commands: Commands,
+    models: Res<Models>,
+    gltf_assets: Res<Assets<Gltf>>,
+    gltf_nodes: Res<Assets<GltfNode>>,
+    gltf_meshes: Res<Assets<GltfMesh>>,
+    dropped_items: Res<DroppedItems>,
+    model_entities: Res<ModelEntities>,
+    mut item_meshes: ResMut<DroppedItemMeshes>,
+    model_query: Query<(&Model, Option<&DroppedItem>)>,
+) {
+    for id in dropped_items.iter() {
+        let Some(entity) = model_entities.get(id) else {
+            continue;
+        };
+
+        // The entity is spawned through commands, it is available the frame after the model
+        // arrives.
+        let Ok((model, maybe_dropped_item)) = model_query.get(*entity) else {
+            continue;
+        };
+
+        let Model::Asset(asset) = *model else {
+            continue;
+        };
+
+        if maybe_dropped_item.is_some_and(|dropped_item| dropped_item.asset == asset) {
+            continue;
+        }
+
+        let Some(meshes) = item_meshes.get(&asset).cloned().or_else(|| {
+            let gltf = gltf_assets.get(&models.get_config(&asset)?.gltf_handle)?;
+            // The hierarchy of the nodes is ignored, item models are expected to be flat.
+            let meshes: Vec<_> = gltf
+                .nodes
+                .iter()
+                .filter_map(|node| gltf_nodes.get(node))
+                .filter_map(|node| Some((gltf_meshes.get(node.mesh.as_ref()?)?, node.transform)))
+                .flat_map(|(mesh, transform)| {
+                    mesh.primitives.iter().map(move |primitive| {
+                        (
+                            primitive.mesh.clone(),
+                            primitive.material.clone(),
+                            transform,
+                        )
+                    })
+                })
+                .collect();
+            item_meshes.insert(asset, meshes.clone());
+            Some(meshes)
+        }) else {
+            continue;
+        };
+
+        let visual = commands
+            .spawn((Transform::default(), Visibility::default()))
+            .with_children(|parent| {
+                for (mesh, material, transform) in meshes {
+                    let mut entity = parent.spawn((Mesh3d(mesh), transform));
+                    if let Some(material) = material {
+                        entity.insert(MeshMaterial3d(material));
+                    }
+                }
+            })
+            .id();
+
+        commands
+            .entity(*entity)
+            .remove::<SceneRoot>()
+            .despawn_descendants()
+            .add_child(visual)
+            .insert(DroppedItem {
+                asset,
+                // Spread the items out by their id
+                phase: (*id % 64) as f32 / 64.0 * std::f32::consts::TAU,
+                visual,
+            });
+    }
+}
+
+// Identical items that lie close together are drawn as one. Each item is put in a grid cell the
+// size of the merge distance, and only the first item of each model in a cell is shown.
+fn merge_items(
+    time: Res<Time>,
+    mut merge_timer: ResMut<MergeTimer>,
+    item_query: Query<(&DroppedItem, &GlobalTransform)>,
+    mut visibility_query: Query<&mut Visibility>,
+) {
+    merge_timer.tick(time.delta());
+    if !merge_timer.just_finished() {
+        return;
+    }
+
+    let mut shown = HashSet::new();
+
+    for (dropped_item, transform) in item_query.iter() {
+        let cell = (transform.translation() / MERGE_DISTANCE)
+            .floor()
+            .as_ivec3();
+        let visibility = if shown.insert((dropped_item.asset, cell)) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+
+        if let Ok(mut current) = visibility_query.get_mut(dropped_item.visual) {
+            current.set_if_neq(visibility);
+        }
+    }
+}
+
+fn animate_items(
+    time: Res<Time>,
+    item_query: Query<&DroppedItem>,
+    mut transform_query: Query<&mut Transform>,
+) {
+    let elapsed = time.elapsed_secs();
+
+    for dropped_item in item_query.iter() {
+        let Ok(mut transform) = transform_query.get_mut(dropped_item.visual) else {
+            continue;
+        };
+
+        let bob = (elapsed * BOB_SPEED * std::f32::consts::TAU + dropped_item.phase).sin();
+        transform.translation.y = BOB_HEIGHT * (bob + 1.0) * 0.5;
+        transform.rotation = Quat::from_rotation_y(elapsed * SPIN_SPEED + dropped_item.phase);
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    player::Head,
+};
+
+use super::models::ModelEntities;
+
+// Number of line segments a leash is drawn with
+const SEGMENTS: usize = 8;
+// How far a leash sags at its middle, relative to its length
+const SAG: f32 = 0.1;
+// Where the leash is tied on a model, above its origin
+const MODEL_TIE_HEIGHT: f32 = 0.8;
+// Where the leash is held relative to the camera when the player holds it
+const HAND_OFFSET: Vec3 = Vec3::new(0.3, -0.5, -0.3);
+
+// The server sends "leash" with the model id of the leashed model and what holds it, which is
+// the player when it has no holder model. "unleash" with the model id removes it.
+pub struct LeashPlugin;
+impl Plugin for LeashPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Leashes>()
+            .add_systems(
+                OnExit(GameState::Playing),
+                |mut leashes: ResMut<Leashes>| leashes.clear(),
+            )
+            .add_systems(
+                Update,
+                (handle_leash_properties, draw_leashes)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+enum LeashHolder {
+    Model(u32),
+    Player,
+}
+
+// Map from leashed model id to what holds it
+#[derive(Resource, Deref, DerefMut, Default)]
+struct Leashes(HashMap<u32, LeashHolder>);
+
+fn handle_leash_properties(
+    mut leashes: ResMut<Leashes>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        match property.name.as_str() {
+            properties::LEASH => {
+                let Some(properties::Leash { id, holder }) = property.parse() else {
+                    continue;
+                };
+
+                let holder = match holder {
+                    Some(holder_id) => LeashHolder::Model(holder_id),
+                    None => LeashHolder::Player,
+                };
+
+                leashes.insert(id, holder);
+            }
+            properties::UNLEASH => {
+                if let Some(id) = property.parse::<u32>() {
+                    leashes.remove(&id);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn draw_leashes(
+    mut gizmos: Gizmos,
+    leashes: Res<Leashes>,
+    model_entities: Res<ModelEntities>,
+    model_query: Query<&GlobalTransform>,
+    camera_query: Query<&GlobalTransform, With<Head>>,
+) {
+    let model_tie = |id: &u32| -> Option<Vec3> {
+        let entity = model_entities.get(id)?;
+        let transform = model_query.get(*entity).ok()?;
+        return Some(transform.translation() + Vec3::Y * MODEL_TIE_HEIGHT);
+    };
+
+    for (id, holder) in leashes.iter() {
+        // Models that aren't loaded yet, or have gone out of view, are skipped.
+        let Some(start) = model_tie(id) else {
+            continue;
+        };
+
+        let end = match holder {
+            LeashHolder::Model(holder_id) => {
+                let Some(end) = model_tie(holder_id) else {
+                    continue;
+                };
+                end
+            }
+            LeashHolder::Player => camera_query.single().transform_point(HAND_OFFSET),
+        };
+
+        let sag = start.distance(end) * SAG;
+        let points = (0..=SEGMENTS).map(|segment| {
+            let t = segment as f32 / SEGMENTS as f32;
+            // Parabola that is 0 at the ends and 1 at the middle
+            let drop = 4.0 * t * (1.0 - t);
+            start.lerp(end, t) - Vec3::Y * drop * sag
+        });
+
+        gizmos.linestrip(points, Color::srgb_u8(112, 84, 52));
+    }
+}
@@ -41,7 +41,8 @@ impl Plugin for LightingPlugin {
                 )
                     .in_set(RenderSet::Light)
                     .run_if(in_state(GameState::Playing)),
-            );
+            )
+            .add_systems(OnExit(GameState::Playing), clear_light);
     }
 }
 
@@ -1021,6 +1022,12 @@ fn light_chunk_unloading(world_map: Res<WorldMap>, mut light_map: ResMut<LightMa
     }
 }
 
+// The world is cleared on disconnect, the light of the next server's chunks is computed anew.
+fn clear_light(mut light_map: ResMut<LightMap>, mut light_update_queues: ResMut<Queues>) {
+    light_map.chunks.clear();
+    light_update_queues.clear();
+}
+
 #[derive(Event, Hash, PartialEq, Eq)]
 struct TestFinishedLightingEvent(IVec3);
 
@@ -4,16 +4,23 @@ use bevy::{
 };
 
 mod block_material;
+mod gpu_particle_material;
 mod particle_material;
 mod pbr_material;
 mod sky_material;
 
-pub use block_material::BlockMaterial;
+pub use block_material::{BlockMaterial, BLOCK_FRAGMENT_SOURCE};
+pub use gpu_particle_material::{
+    GpuParticleMaterial, ATTRIBUTE_PARTICLE_PARAMETERS, ATTRIBUTE_PARTICLE_VELOCITY,
+};
 pub use particle_material::ParticleMaterial;
 pub use sky_material::SkyMaterial;
 
 pub const ATTRIBUTE_PACKED_BITS_0: MeshVertexAttribute =
     MeshVertexAttribute::new("Packed_bits_0", 10, VertexFormat::Uint32);
+/// The color and light level of blocks that emit light, see rendering::chunk
+pub const ATTRIBUTE_PACKED_BITS_1: MeshVertexAttribute =
+    MeshVertexAttribute::new("Packed_bits_1", 11, VertexFormat::Uint32);
 
 pub struct MaterialsPlugin;
 impl Plugin for MaterialsPlugin {
@@ -21,6 +28,7 @@ impl Plugin for MaterialsPlugin {
         app.add_plugins(block_material::BlockMaterialPlugin)
             .add_plugins(sky_material::SkyMaterialPlugin)
             .add_plugins(particle_material::ParticleMaterialPlugin)
+            .add_plugins(gpu_particle_material::GpuParticleMaterialPlugin)
             .add_plugins(pbr_material::PbrMaterialPlugin);
     }
 }
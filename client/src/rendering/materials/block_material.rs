@@ -10,19 +10,31 @@ use bevy::{
     },
 };
 
-use super::ATTRIBUTE_PACKED_BITS_0;
+use crate::settings::{Settings, WaterQuality};
+
+use super::{ATTRIBUTE_PACKED_BITS_0, ATTRIBUTE_PACKED_BITS_1};
 
 const BLOCK_MESH_SHADER: Handle<Shader> = Handle::weak_from_u128(182903180293810293);
 const BLOCK_FRAGMENT_SHADER: Handle<Shader> = Handle::weak_from_u128(234982304982304);
+const BLOCK_PREPASS_SHADER: Handle<Shader> = Handle::weak_from_u128(129387012983471023);
+/// Source of the block fragment shader, the server's block shaders are built from it, see
+/// assets::shaders.
+pub const BLOCK_FRAGMENT_SOURCE: &str = include_str!("../shaders/block.wgsl");
 
 pub struct BlockMaterialPlugin;
 impl Plugin for BlockMaterialPlugin {
     fn build(&self, app: &mut App) {
+        // The prepass is only drawn when the camera has one, for ambient occlusion. The shadow
+        // pass is only drawn when the sun light is spawned, see rendering::shadows.
         app.add_plugins(MaterialPlugin::<BlockMaterial> {
-            shadows_enabled: false,
-            prepass_enabled: false,
+            shadows_enabled: true,
+            prepass_enabled: true,
             ..default()
-        });
+        })
+        .add_systems(
+            Update,
+            update_water_quality.run_if(resource_changed::<Settings>),
+        );
 
         load_internal_asset!(
             app,
@@ -36,6 +48,12 @@ impl Plugin for BlockMaterialPlugin {
             "../shaders/block.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            BLOCK_PREPASS_SHADER,
+            "../shaders/block_prepass.wgsl",
+            Shader::from_wgsl
+        );
     }
 }
 
@@ -264,6 +282,16 @@ pub struct BlockMaterial {
     // TODO: Need a way to define the length of the animation too.
     /// Cycle through the n next textures in the texture array. Defaults to 1(no animation)
     pub animation_frames: u32,
+
+    /// How many textures there are along each side of a layer of the texture array. 1 when each
+    /// texture has its own layer, more when the gpu doesn't allow enough layers.
+    pub atlas_columns: u32,
+
+    /// Fragment shader built from a snippet the server sent, used instead of the default.
+    pub custom_shader: Option<Handle<Shader>>,
+
+    /// Set for the materials of blocks marked as water, how the surface should be drawn.
+    pub water: Option<WaterQuality>,
 }
 
 // TODO: This can be removed and moved back to StandardMaterialFlags
@@ -325,6 +353,8 @@ pub struct BlockMaterialUniform {
     pub alpha_cutoff: f32,
     /// How many textures from the texture array the material should cycle through.
     pub animation_frames: u32,
+    /// How many textures there are along each side of a layer of the texture array.
+    pub atlas_columns: u32,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -332,6 +362,8 @@ pub struct BlockMaterialKey {
     normal_map: bool,
     cull_mode: Option<Face>,
     depth_bias: i32,
+    custom_shader: Option<Handle<Shader>>,
+    water: Option<WaterQuality>,
 }
 
 impl From<&BlockMaterial> for BlockMaterialKey {
@@ -340,6 +372,8 @@ impl From<&BlockMaterial> for BlockMaterialKey {
             normal_map: material.normal_map_texture.is_some(),
             cull_mode: material.cull_mode,
             depth_bias: material.depth_bias as i32,
+            custom_shader: material.custom_shader.clone(),
+            water: material.water,
         }
     }
 }
@@ -355,10 +389,21 @@ impl Material for BlockMaterial {
             Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
             ATTRIBUTE_PACKED_BITS_0.at_shader_location(1),
             Mesh::ATTRIBUTE_NORMAL.at_shader_location(2),
+            ATTRIBUTE_PACKED_BITS_1.at_shader_location(7),
         ])?;
 
         descriptor.vertex.buffers = vec![vertex_layout];
 
+        descriptor.primitive.cull_mode = key.bind_group_data.cull_mode;
+        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+            depth_stencil.bias.constant = key.bind_group_data.depth_bias;
+        }
+
+        // The prepass has its own shaders, the rest only applies to the main pass.
+        if descriptor.vertex.shader == BLOCK_PREPASS_SHADER {
+            return Ok(());
+        }
+
         if key.bind_group_data.normal_map {
             if let Some(fragment) = descriptor.fragment.as_mut() {
                 fragment
@@ -367,17 +412,42 @@ impl Material for BlockMaterial {
             }
         }
 
-        descriptor.primitive.cull_mode = key.bind_group_data.cull_mode;
+        if let Some(shader) = &key.bind_group_data.custom_shader {
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader = shader.clone();
+                fragment.shader_defs.push("CUSTOM_BLOCK_SHADER".into());
+            }
+        }
+
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            match key.bind_group_data.water {
+                None | Some(WaterQuality::Off) => (),
+                Some(WaterQuality::Low) => fragment.shader_defs.push("WATER".into()),
+                Some(WaterQuality::Medium) => fragment
+                    .shader_defs
+                    .extend(["WATER".into(), "WATER_REFLECTIONS".into()]),
+                Some(WaterQuality::High) => fragment.shader_defs.extend([
+                    "WATER".into(),
+                    "WATER_REFLECTIONS".into(),
+                    "WATER_REFRACTION".into(),
+                ]),
+            }
+        }
 
         if let Some(label) = &mut descriptor.label {
             *label = format!("pbr_{}", *label).into();
         }
-        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
-            depth_stencil.bias.constant = key.bind_group_data.depth_bias;
-        }
         return Ok(());
     }
 
+    fn prepass_vertex_shader() -> ShaderRef {
+        BLOCK_PREPASS_SHADER.into()
+    }
+
+    fn prepass_fragment_shader() -> ShaderRef {
+        BLOCK_PREPASS_SHADER.into()
+    }
+
     fn vertex_shader() -> ShaderRef {
         BLOCK_MESH_SHADER.into()
     }
@@ -395,6 +465,26 @@ impl Material for BlockMaterial {
     fn depth_bias(&self) -> f32 {
         self.depth_bias
     }
+
+    // Refraction needs what's been drawn behind the water
+    fn reads_view_transmission_texture(&self) -> bool {
+        self.water == Some(WaterQuality::High)
+    }
+}
+
+// Changing the quality changes the shader defs, so the water materials are specialized again.
+fn update_water_quality(settings: Res<Settings>, mut materials: ResMut<Assets<BlockMaterial>>) {
+    let quality = settings.water_quality;
+
+    let changed: Vec<AssetId<BlockMaterial>> = materials
+        .iter()
+        .filter(|(_, material)| material.water.is_some_and(|water| water != quality))
+        .map(|(id, _)| id)
+        .collect();
+
+    for id in changed {
+        materials.get_mut(id).unwrap().water = Some(quality);
+    }
 }
 
 impl AsBindGroupShaderType<BlockMaterialUniform> for BlockMaterial {
@@ -463,6 +553,7 @@ impl AsBindGroupShaderType<BlockMaterialUniform> for BlockMaterial {
             flags: flags.bits(),
             alpha_cutoff,
             animation_frames: self.animation_frames,
+            atlas_columns: self.atlas_columns,
         }
     }
 }
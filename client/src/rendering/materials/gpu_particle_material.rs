@@ -0,0 +1,116 @@
+use bevy::{
+    asset::{load_internal_asset, Handle},
+    image::Image,
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef},
+        render_resource::*,
+    },
+};
+
+const GPU_PARTICLE_SHADER: Handle<Shader> = Handle::weak_from_u128(58201946377130298);
+
+/// Velocity of the particle when it was spawned
+pub const ATTRIBUTE_PARTICLE_VELOCITY: MeshVertexAttribute =
+    MeshVertexAttribute::new("Particle_velocity", 20, VertexFormat::Float32x3);
+/// Which corner of the particle the vertex is, its size and how many seconds it lives, packed as
+/// (corner x, corner y, size, lifetime)
+pub const ATTRIBUTE_PARTICLE_PARAMETERS: MeshVertexAttribute =
+    MeshVertexAttribute::new("Particle_parameters", 21, VertexFormat::Float32x4);
+
+pub struct GpuParticleMaterialPlugin;
+impl Plugin for GpuParticleMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<GpuParticleMaterial> {
+            shadows_enabled: false,
+            prepass_enabled: false,
+            ..default()
+        });
+
+        load_internal_asset!(
+            app,
+            GPU_PARTICLE_SHADER,
+            "../shaders/gpu_particles.wgsl",
+            Shader::from_wgsl
+        );
+    }
+}
+
+/// Material for particle effects that are simulated entirely in the vertex shader. The whole
+/// effect is a single mesh with one quad per particle, each quad moves along its own ballistic
+/// path from where it was spawned, it doesn't collide with blocks.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+#[uniform(0, GpuParticleMaterialUniform)]
+pub struct GpuParticleMaterial {
+    #[texture(1)]
+    #[sampler(2)]
+    /// Block textures are cut into a few pixels for each particle through the mesh's uvs.
+    pub texture: Option<Handle<Image>>,
+    pub base_color: Srgba,
+    pub gravity: f32,
+    /// The shader time the effect was spawned at, `Time::elapsed_secs_wrapped`
+    pub spawn_time: f32,
+    /// Brightness of the light at the effect
+    pub light: f32,
+    /// Hide particles that have gone behind what is drawn on screen. It is a coarse stand in for
+    /// collision, it only works when the camera has a depth prepass.
+    pub depth_collision: bool,
+}
+
+impl Material for GpuParticleMaterial {
+    fn vertex_shader() -> ShaderRef {
+        GPU_PARTICLE_SHADER.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        GPU_PARTICLE_SHADER.into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Mask(0.5)
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.0.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            ATTRIBUTE_PARTICLE_VELOCITY.at_shader_location(2),
+            ATTRIBUTE_PARTICLE_PARAMETERS.at_shader_location(3),
+        ])?;
+
+        descriptor.vertex.buffers = vec![vertex_layout];
+        // Quads are turned towards the camera in the shader, either winding can be seen
+        descriptor.primitive.cull_mode = None;
+
+        return Ok(());
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+struct GpuParticleMaterialUniform {
+    base_color: Vec4,
+    // 0 if false, 1 if true
+    depth_collision: u32,
+    gravity: f32,
+    spawn_time: f32,
+    light: f32,
+}
+
+impl From<&GpuParticleMaterial> for GpuParticleMaterialUniform {
+    fn from(material: &GpuParticleMaterial) -> Self {
+        Self {
+            base_color: material.base_color.to_vec4(),
+            depth_collision: material.depth_collision as u32,
+            gravity: material.gravity,
+            spawn_time: material.spawn_time,
+            light: material.light,
+        }
+    }
+}
@@ -24,7 +24,8 @@ impl Plugin for PbrMaterialPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(
             MaterialPlugin::<ExtendedMaterial<StandardMaterial, PbrLightExtension>> {
-                shadows_enabled: false,
+                // Models cast sun shadows with bevy's prepass shader
+                shadows_enabled: true,
                 prepass_enabled: false,
                 ..default()
             },
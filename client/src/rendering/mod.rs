@@ -3,19 +3,41 @@ use bevy::prelude::*;
 // TODO: This pub is needed for ExpandedChunk, move the struct to the chunk file and close this off.
 pub mod chunk;
 
-mod lighting;
+mod block_animations;
+pub mod diagnostics;
+mod dropped_items;
+mod leashes;
+pub mod lighting;
 pub mod materials;
+mod model_ambience;
 mod models;
+pub mod post_processing;
+mod screenshots;
+mod shadows;
 mod sky;
 
-pub struct RenderingPlugin;
+pub struct RenderingPlugin {
+    /// Force the most compatible renderer settings
+    pub safe_mode: bool,
+}
+
 impl Plugin for RenderingPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins(diagnostics::DiagnosticsPlugin {
+            safe_mode: self.safe_mode,
+        });
         app.add_plugins(materials::MaterialsPlugin)
             .add_plugins(chunk::ChunkMeshPlugin)
             .add_plugins(lighting::LightingPlugin)
             .add_plugins(sky::SkyPlugin)
-            .add_plugins(models::ModelPlugin);
+            .add_plugins(models::ModelPlugin)
+            .add_plugins(model_ambience::ModelAmbiencePlugin)
+            .add_plugins(leashes::LeashPlugin)
+            .add_plugins(block_animations::BlockAnimationPlugin)
+            .add_plugins(dropped_items::DroppedItemPlugin)
+            .add_plugins(screenshots::ScreenshotPlugin)
+            .add_plugins(post_processing::PostProcessingPlugin)
+            .add_plugins(shadows::ShadowPlugin);
         app.configure_sets(
             Update,
             (RenderSet::UpdateBlocks, RenderSet::Light, RenderSet::Mesh).chain(),
@@ -0,0 +1,175 @@
+use bevy::{audio::Volume, prelude::*};
+
+use crate::{
+    assets::models::{Model, Models},
+    audio::{self, AudioEnvironment},
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    player::Head,
+    utils::Rng,
+};
+
+use super::models::{self, ModelEntities};
+
+// The server sends "model_ambience" once for each model that idles on its own, right after the
+// model itself, with the indices of its idle animations and the paths of its sounds relative to
+// the audio directory. The client then plays them at random, so the server doesn't have to send anything for mobs
+// that are just standing around.
+pub struct ModelAmbiencePlugin;
+impl Plugin for ModelAmbiencePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AmbienceRng>().add_systems(
+            Update,
+            (
+                handle_ambience_properties.after(models::handle_model_add_delete),
+                play_ambience,
+            )
+                .chain()
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct AmbienceRng(Rng);
+
+impl Default for AmbienceRng {
+    fn default() -> Self {
+        Self(Rng::new(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        ))
+    }
+}
+
+#[derive(Component)]
+struct ModelAmbience {
+    // Seconds between each time something is played
+    min_interval: f32,
+    max_interval: f32,
+    // Sounds can't be heard further away than this, they fade out linearly with the distance
+    distance: f32,
+    volume: f32,
+    // Indices into the model's animations
+    animations: Vec<u32>,
+    sounds: Vec<String>,
+    // Seconds until the next time
+    countdown: f32,
+}
+
+impl ModelAmbience {
+    fn next_interval(&self, rng: &mut Rng) -> f32 {
+        return self.min_interval + (self.max_interval - self.min_interval) * rng.next_f32();
+    }
+}
+
+fn parse_ambience(value: properties::ModelAmbience) -> (u32, ModelAmbience) {
+    let min_interval = value.min_interval.max(0.0);
+    let ambience = ModelAmbience {
+        min_interval,
+        max_interval: value.max_interval.max(min_interval),
+        distance: value.distance.max(0.0),
+        volume: value.volume.clamp(0.0, 1.0),
+        animations: value.animations,
+        sounds: value
+            .sounds
+            .into_iter()
+            .filter(|sound| !sound.is_empty())
+            .collect(),
+        countdown: 0.0,
+    };
+
+    return (value.model_id, ambience);
+}
+
+fn handle_ambience_properties(
+    mut commands: Commands,
+    model_entities: Res<ModelEntities>,
+    mut rng: ResMut<AmbienceRng>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::MODEL_AMBIENCE {
+            continue;
+        }
+
+        let Some((model_id, mut ambience)) = property.parse().map(parse_ambience) else {
+            continue;
+        };
+
+        let Some(entity) = model_entities.get(&model_id) else {
+            continue;
+        };
+
+        // Models that are spawned together shouldn't idle in sync
+        ambience.countdown = ambience.next_interval(&mut rng.0);
+        commands.entity(*entity).insert(ambience);
+    }
+}
+
+fn play_ambience(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    models: Res<Models>,
+    environment: Res<AudioEnvironment>,
+    mut rng: ResMut<AmbienceRng>,
+    head_query: Query<&GlobalTransform, With<Head>>,
+    mut model_query: Query<(
+        &mut ModelAmbience,
+        &Model,
+        &GlobalTransform,
+        &mut AnimationPlayer,
+    )>,
+) {
+    let Ok(head_transform) = head_query.get_single() else {
+        return;
+    };
+
+    for (mut ambience, model, transform, mut animation_player) in model_query.iter_mut() {
+        ambience.countdown -= time.delta_secs();
+        if ambience.countdown > 0.0 {
+            continue;
+        }
+        ambience.countdown = ambience.next_interval(&mut rng.0);
+
+        // Idle animations would cut off whatever the server has it playing, like walking.
+        if !ambience.animations.is_empty() && animation_player.all_finished() {
+            let index = ambience.animations[rng.0.next_u32() as usize % ambience.animations.len()];
+            if let Model::Asset(asset_id) = model {
+                if let Some(animation) = models
+                    .get_config(asset_id)
+                    .and_then(|config| config.animations.get(index as usize))
+                {
+                    animation_player.start(*animation);
+                }
+            }
+        }
+
+        if ambience.sounds.is_empty() {
+            continue;
+        }
+
+        let distance = transform
+            .translation()
+            .distance(head_transform.translation());
+        if distance >= ambience.distance {
+            continue;
+        }
+
+        let volume = ambience.volume * (1.0 - distance / ambience.distance);
+        let sound = &ambience.sounds[rng.0.next_u32() as usize % ambience.sounds.len()];
+
+        audio::spawn_sound(
+            &mut commands,
+            *environment,
+            asset_server.load(audio::AUDIO_PATH.to_owned() + sound),
+            Transform::from_translation(transform.translation()),
+            PlaybackSettings::DESPAWN
+                .with_spatial(true)
+                .with_volume(Volume::new(volume)),
+        );
+    }
+}
@@ -13,7 +13,7 @@ use fmc_protocol::messages;
 use crate::{
     assets::models::{Model, Models},
     game_state::GameState,
-    networking::NetworkClient,
+    networking::{Latency, NetworkClient, ServerClock},
     world::{MovesWithOrigin, Origin},
 };
 
@@ -28,7 +28,7 @@ impl Plugin for ModelPlugin {
                 update_model_asset,
                 //render_aabb,
                 handle_transform_updates,
-                interpolate_to_new_transform,
+                interpolate_to_new_transform.after(handle_transform_updates),
                 play_animations.after(handle_model_add_delete),
             )
                 .run_if(in_state(GameState::Playing)),
@@ -38,9 +38,9 @@ impl Plugin for ModelPlugin {
 
 /// Map from server model id to entity
 #[derive(Resource, Deref, DerefMut, Default)]
-struct ModelEntities(HashMap<u32, Entity>);
+pub(super) struct ModelEntities(HashMap<u32, Entity>);
 
-fn handle_model_add_delete(
+pub(super) fn handle_model_add_delete(
     net: Res<NetworkClient>,
     mut commands: Commands,
     origin: Res<Origin>,
@@ -184,12 +184,16 @@ fn update_model_asset(
     model_entities: Res<ModelEntities>,
     models: Res<Models>,
     gltf_assets: Res<Assets<Gltf>>,
-    mut model_query: Query<(&mut SceneRoot, &mut AnimationGraphHandle, &mut Model)>,
+    mut model_query: Query<(
+        Option<&mut SceneRoot>,
+        &mut AnimationGraphHandle,
+        &mut Model,
+    )>,
     mut asset_updates: EventReader<messages::ModelUpdateAsset>,
 ) {
     for asset_update in asset_updates.read() {
         if let Some(entity) = model_entities.get(&asset_update.id) {
-            let (mut scene, mut animation_graph, mut model) = model_query.get_mut(*entity).unwrap();
+            let (scene, mut animation_graph, mut model) = model_query.get_mut(*entity).unwrap();
 
             let Some(model_config) = models.get_config(&asset_update.asset) else {
                 net.disconnect(format!(
@@ -199,84 +203,121 @@ fn update_model_asset(
                 return;
             };
 
-            *scene =
-                SceneRoot(gltf_assets.get(&model_config.gltf_handle).unwrap().scenes[0].clone());
+            // Dropped items don't have a scene, they are rebuilt from the new asset in
+            // dropped_items
+            if let Some(mut scene) = scene {
+                *scene = SceneRoot(
+                    gltf_assets.get(&model_config.gltf_handle).unwrap().scenes[0].clone(),
+                );
+            }
             *model = Model::Asset(asset_update.asset);
             *animation_graph = AnimationGraphHandle(model_config.animation_graph.clone().unwrap());
         }
     }
 }
 
+// A model takes as long to move to a new transform as it has been since the last one, so it
+// moves at the pace the server sends them. Models that haven't moved in a while take the shortest
+// time, they would lag behind otherwise.
+const MIN_INTERPOLATION: f64 = 1.0 / 60.0;
+const MAX_INTERPOLATION: f64 = 0.25;
+
 #[derive(Component)]
 struct TransformInterpolation {
-    progress: f32,
+    // Where the model was when the last update arrived
+    from: (DVec3, Quat, Vec3),
     translation: DVec3,
     rotation: Quat,
     scale: Vec3,
+    // Server time the last update was sent at
+    start: f64,
+    duration: f64,
+    finished: bool,
 }
 
 impl Default for TransformInterpolation {
     fn default() -> Self {
         Self {
-            progress: 1.0,
+            from: (DVec3::default(), Quat::default(), Vec3::default()),
             translation: DVec3::default(),
             rotation: Quat::default(),
             scale: Vec3::default(),
+            start: f64::NEG_INFINITY,
+            duration: MIN_INTERPOLATION,
+            finished: true,
         }
     }
 }
 
 fn handle_transform_updates(
+    time: Res<Time<Real>>,
+    server_clock: Res<ServerClock>,
+    latency: Res<Latency>,
+    origin: Res<Origin>,
     model_entities: Res<ModelEntities>,
     mut transform_updates: EventReader<messages::ModelUpdateTransform>,
-    mut model_query: Query<&mut TransformInterpolation, With<Model>>,
+    mut model_query: Query<(&Transform, &mut TransformInterpolation), With<Model>>,
 ) {
+    let sent_at = server_clock.now(&time) - latency.one_way();
+
     for transform_update in transform_updates.read() {
         if let Some(entity) = model_entities.get(&transform_update.id) {
             // TODO: I think this should be bug, server should not send model same tick it sends
             // transform updated. But there is 1-frame delay for model entity spawn for command
             // application. Should be disconnect I think, if bevy ever gets immediate command
             // application.
-            let mut interpolation = match model_query.get_mut(*entity) {
+            let (transform, mut interpolation) = match model_query.get_mut(*entity) {
                 Ok(m) => m,
                 Err(_) => continue,
             };
 
+            let since_last = sent_at - interpolation.start;
+            interpolation.duration = if since_last > MAX_INTERPOLATION {
+                MIN_INTERPOLATION
+            } else {
+                since_last.max(MIN_INTERPOLATION)
+            };
+            interpolation.start = sent_at;
+            interpolation.finished = false;
+
+            interpolation.from = (
+                origin.to_global(transform.translation),
+                transform.rotation,
+                transform.scale,
+            );
             interpolation.translation = transform_update.position;
             interpolation.rotation = transform_update.rotation;
             interpolation.scale = transform_update.scale;
-            interpolation.progress = 0.0;
         }
     }
 }
 
 fn interpolate_to_new_transform(
+    time: Res<Time<Real>>,
+    server_clock: Res<ServerClock>,
+    latency: Res<Latency>,
     origin: Res<Origin>,
-    mut model_query: Query<
-        (&mut Transform, &mut TransformInterpolation),
-        (
-            With<Model>,
-            Or<(Changed<GlobalTransform>, Changed<TransformInterpolation>)>,
-        ),
-    >,
+    mut model_query: Query<(&mut Transform, &mut TransformInterpolation), With<Model>>,
 ) {
+    let now = server_clock.now(&time) - latency.one_way();
+
     for (mut transform, mut interpolation) in model_query.iter_mut() {
-        interpolation.progress += 1.0 / 6.0;
-        if interpolation.progress > 1.0 {
+        if interpolation.finished {
             continue;
         }
 
-        let interpolation_transform = Transform {
-            translation: (interpolation.translation - origin.as_dvec3()).as_vec3(),
-            rotation: interpolation.rotation,
-            scale: interpolation.scale,
-        };
+        let progress = ((now - interpolation.start) / interpolation.duration).clamp(0.0, 1.0);
+        if progress == 1.0 {
+            interpolation.finished = true;
+        }
 
-        let new_transform = Animatable::interpolate(
-            &*transform,
-            &interpolation_transform,
-            interpolation.progress,
-        );
+        let (from_translation, from_rotation, from_scale) = interpolation.from;
+        let new_transform = Transform {
+            translation: origin
+                .to_local(from_translation.lerp(interpolation.translation, progress)),
+            rotation: from_rotation.slerp(interpolation.rotation, progress as f32),
+            scale: from_scale.lerp(interpolation.scale, progress as f32),
+        };
 
         transform.set_if_neq(new_transform);
     }
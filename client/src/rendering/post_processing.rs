@@ -0,0 +1,231 @@
+use bevy::{
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::query::QueryItem,
+    image::BevyDefault,
+    prelude::*,
+    render::{
+        extract_component::{
+            ComponentUniforms, DynamicUniformIndex, ExtractComponent, ExtractComponentPlugin,
+            UniformComponentPlugin,
+        },
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            binding_types::{sampler, texture_2d, uniform_buffer},
+            *,
+        },
+        renderer::{RenderContext, RenderDevice},
+        view::ViewTarget,
+        RenderApp,
+    },
+};
+
+use crate::{assets::CustomShaders, player::Head};
+
+/// The post processing shader the server sent is inserted at this handle, see assets::shaders.
+pub const POST_PROCESSING_SHADER: Handle<Shader> = Handle::weak_from_u128(90381270934580912);
+/// Source of the post processing shader, the server's snippet is appended to it.
+pub const POST_PROCESSING_SOURCE: &str = include_str!("shaders/post_processing.wgsl");
+
+// Time is wrapped so the shader doesn't lose precision
+const TIME_WRAP: f32 = 3600.0;
+
+/// Runs the server's post processing shader over the screen after tonemapping, when it has one.
+pub struct PostProcessingPlugin;
+impl Plugin for PostProcessingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            ExtractComponentPlugin::<PostProcessing>::default(),
+            UniformComponentPlugin::<PostProcessing>::default(),
+        ))
+        .add_systems(Update, toggle_post_processing);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<PostProcessingNode>>(
+                Core3d,
+                PostProcessingLabel,
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::Tonemapping,
+                    PostProcessingLabel,
+                    Node3d::EndMainPassPostProcessing,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<PostProcessingPipeline>();
+    }
+}
+
+/// Cameras with this are post processed. Doubles as the uniform of the shader.
+#[derive(Component, Default, Clone, Copy, ExtractComponent, ShaderType)]
+struct PostProcessing {
+    time: f32,
+}
+
+fn toggle_post_processing(
+    mut commands: Commands,
+    time: Res<Time>,
+    custom_shaders: Res<CustomShaders>,
+    mut camera_query: Query<(Entity, Option<&mut PostProcessing>), With<Head>>,
+) {
+    let Ok((camera_entity, post_processing)) = camera_query.get_single_mut() else {
+        return;
+    };
+
+    match (custom_shaders.post_processing.is_some(), post_processing) {
+        (true, Some(mut post_processing)) => {
+            post_processing.time = time.elapsed_secs_wrapped() % TIME_WRAP;
+        }
+        (true, None) => {
+            commands
+                .entity(camera_entity)
+                .insert(PostProcessing::default());
+        }
+        (false, Some(_)) => {
+            commands.entity(camera_entity).remove::<PostProcessing>();
+        }
+        (false, None) => (),
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct PostProcessingLabel;
+
+#[derive(Default)]
+struct PostProcessingNode;
+
+impl ViewNode for PostProcessingNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static PostProcessing,
+        &'static DynamicUniformIndex<PostProcessing>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, _post_processing, uniform_index): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let post_processing_pipeline = world.resource::<PostProcessingPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        // Not compiled yet, or the shader has errors, they are logged by bevy.
+        let Some(pipeline) =
+            pipeline_cache.get_render_pipeline(post_processing_pipeline.pipeline_id)
+        else {
+            return Ok(());
+        };
+
+        let uniforms = world.resource::<ComponentUniforms<PostProcessing>>();
+        let Some(uniform_binding) = uniforms.uniforms().binding() else {
+            return Ok(());
+        };
+
+        let post_process = view_target.post_process_write();
+
+        let bind_group = render_context.render_device().create_bind_group(
+            "post_processing_bind_group",
+            &post_processing_pipeline.layout,
+            &BindGroupEntries::sequential((
+                post_process.source,
+                &post_processing_pipeline.sampler,
+                uniform_binding.clone(),
+            )),
+        );
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("post_processing_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[uniform_index.index()]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+// The bind group layout is fixed, the server's shaders can only use these bindings.
+#[derive(Resource)]
+struct PostProcessingPipeline {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for PostProcessingPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(
+            "post_processing_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                    uniform_buffer::<PostProcessing>(true),
+                ),
+            ),
+        );
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor::default());
+
+        // Compiled once the server's shader is inserted at the handle, and again when it changes.
+        let pipeline_id =
+            world
+                .resource_mut::<PipelineCache>()
+                .queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("post_processing_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    vertex: fullscreen_shader_vertex_state(),
+                    fragment: Some(FragmentState {
+                        shader: POST_PROCESSING_SHADER,
+                        shader_defs: vec![],
+                        entry_point: "fragment".into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: TextureFormat::bevy_default(),
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                    zero_initialize_workgroup_memory: false,
+                });
+
+        Self {
+            layout,
+            sampler,
+            pipeline_id,
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    path::PathBuf,
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        view::screenshot::{save_to_disk, Screenshot, ScreenshotCaptured},
+    },
+    window::PrimaryWindow,
+};
+
+use crate::{game_state::GameState, player::Head, settings::Settings};
+
+// Width and height of each panorama face
+const PANORAMA_SIZE: u32 = 1024;
+
+// F2 saves a screenshot, shift+F2 saves a panorama. A panorama is six pictures that cover
+// everything around the camera, in the order front, right, back, left, up, down. They can be used
+// as the background of the main menu.
+//
+// Screenshots without the interface, and the panorama faces, are rendered by a separate camera that
+// follows the player's camera. The interface is only drawn to the window.
+pub struct ScreenshotPlugin;
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (take_screenshot, capture_offscreen_cameras).run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+// A camera that renders to an image, it is saved to the path when it has rendered.
+#[derive(Component)]
+struct OffscreenCapture {
+    image: Handle<Image>,
+    path: PathBuf,
+}
+
+fn screenshot_directory() -> PathBuf {
+    return dirs::data_dir()
+        .unwrap_or(PathBuf::from("."))
+        .join("fmc/screenshots");
+}
+
+// Current time as "year-month-day_hour-minute-second" in UTC
+fn timestamp() -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let days = seconds.div_euclid(86400);
+    let time_of_day = seconds.rem_euclid(86400);
+
+    // Days since 1970-01-01 to a civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_part = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_part + 2) / 5 + 1;
+    let month = if month_part < 10 {
+        month_part + 3
+    } else {
+        month_part - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return format!(
+        "{}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    );
+}
+
+fn render_target(images: &mut Assets<Image>, width: u32, height: u32) -> Handle<Image> {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Bgra8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST
+        | TextureUsages::RENDER_ATTACHMENT;
+
+    return images.add(image);
+}
+
+fn take_screenshot(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut images: ResMut<Assets<Image>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(Entity, &Parent, &Transform, &Projection, &DistanceFog), With<Head>>,
+) {
+    if !keys.just_pressed(KeyCode::F2) {
+        return;
+    }
+
+    let directory = screenshot_directory();
+    if let Err(e) = std::fs::create_dir_all(&directory) {
+        error!(
+            "Could not create the screenshot directory at {}, error: {}",
+            directory.display(),
+            e
+        );
+        return;
+    }
+
+    let (camera_entity, player_entity, camera_transform, projection, fog) = camera_query.single();
+
+    if keys.pressed(KeyCode::ShiftLeft) {
+        let directory = directory.join(format!("panorama_{}", timestamp()));
+        if let Err(e) = std::fs::create_dir_all(&directory) {
+            error!(
+                "Could not create the panorama directory at {}, error: {}",
+                directory.display(),
+                e
+            );
+            return;
+        }
+
+        let far = match projection {
+            Projection::Perspective(projection) => projection.far,
+            _ => unreachable!(),
+        };
+
+        // The faces are level with the horizon, only the direction the camera faces is kept.
+        let (yaw, _, _) = camera_transform.rotation.to_euler(EulerRot::YXZ);
+        let yaw = Quat::from_rotation_y(yaw);
+
+        let faces = [
+            Quat::IDENTITY,
+            Quat::from_rotation_y(-FRAC_PI_2),
+            Quat::from_rotation_y(PI),
+            Quat::from_rotation_y(FRAC_PI_2),
+            Quat::from_rotation_x(FRAC_PI_2),
+            Quat::from_rotation_x(-FRAC_PI_2),
+        ];
+
+        for (index, face) in faces.into_iter().enumerate() {
+            let image = render_target(&mut images, PANORAMA_SIZE, PANORAMA_SIZE);
+            let camera = commands
+                .spawn((
+                    Camera3d::default(),
+                    Camera {
+                        target: RenderTarget::Image(image.clone()),
+                        ..default()
+                    },
+                    Projection::Perspective(PerspectiveProjection {
+                        fov: FRAC_PI_2,
+                        aspect_ratio: 1.0,
+                        far,
+                        ..default()
+                    }),
+                    fog.clone(),
+                    Transform::from_translation(camera_transform.translation)
+                        .with_rotation(yaw * face),
+                    OffscreenCapture {
+                        image,
+                        path: directory.join(format!("panorama_{}.png", index)),
+                    },
+                ))
+                .id();
+            commands.entity(player_entity.get()).add_child(camera);
+        }
+
+        info!("Saving panorama to {}", directory.display());
+    } else if settings.screenshot_hide_ui {
+        let path = directory.join(timestamp() + ".png");
+        let window = window.single();
+        let image = render_target(
+            &mut images,
+            window.physical_width(),
+            window.physical_height(),
+        );
+        let camera = commands
+            .spawn((
+                Camera3d::default(),
+                Camera {
+                    target: RenderTarget::Image(image.clone()),
+                    ..default()
+                },
+                projection.clone(),
+                fog.clone(),
+                Transform::default(),
+                OffscreenCapture {
+                    image,
+                    path: path.clone(),
+                },
+            ))
+            .id();
+        commands.entity(camera_entity).add_child(camera);
+
+        info!("Saving screenshot to {}", path.display());
+    } else {
+        let path = directory.join(timestamp() + ".png");
+        info!("Saving screenshot to {}", path.display());
+        commands
+            .spawn(Screenshot::primary_window())
+            .observe(save_to_disk(path));
+    }
+}
+
+// The cameras are given a frame to render before the screenshot is taken, and removed once it has
+// been taken.
+fn capture_offscreen_cameras(
+    mut commands: Commands,
+    capture_query: Query<(Entity, &OffscreenCapture)>,
+) {
+    for (camera_entity, capture) in capture_query.iter() {
+        commands.entity(camera_entity).remove::<OffscreenCapture>();
+        commands
+            .spawn(Screenshot::image(capture.image.clone()))
+            .observe(save_to_disk(capture.path.clone()))
+            .observe(
+                move |_trigger: Trigger<ScreenshotCaptured>, mut commands: Commands| {
+                    commands.entity(camera_entity).despawn();
+                },
+            );
+    }
+}
@@ -14,6 +14,24 @@
     fog
 }
 #import bevy_pbr::mesh_view_types::{FOG_MODE_OFF, Fog}
+#import bevy_pbr::shadows::fetch_directional_shadow
+#import bevy_pbr::view_transformations::{
+    position_world_to_view,
+    position_world_to_ndc,
+    ndc_to_uv
+}
+#ifdef WATER_REFRACTION
+#import bevy_pbr::mesh_view_bindings::{
+    view_transmission_texture,
+    view_transmission_sampler
+}
+#ifdef DEPTH_PREPASS
+#import bevy_pbr::prepass_utils::prepass_depth
+#endif
+#endif
+#ifdef SCREEN_SPACE_AMBIENT_OCCLUSION
+#import bevy_pbr::mesh_view_bindings::screen_space_ambient_occlusion_texture
+#endif
 
 // This isn't the bevy's standard material, I just kept the name for some reason I don't remember.
 struct StandardMaterial {
@@ -26,6 +44,7 @@ struct StandardMaterial {
     flags: u32,
     alpha_cutoff: f32,
     animation_frames: u32,
+    atlas_columns: u32,
 };
 
 fn standard_material_new() -> StandardMaterial {
@@ -133,6 +152,123 @@ fn get_light(light: u32) -> f32 {
     }
 }
 
+// When the gpu doesn't allow enough layers, each layer holds a grid of textures.
+fn sample_block_texture(uv: vec2<f32>, texture_index: i32) -> vec4<f32> {
+    if material.atlas_columns <= 1u {
+        return textureSample(texture_array, texture_array_sampler, uv, texture_index);
+    }
+
+    let columns = i32(material.atlas_columns);
+    let tiles_per_layer = columns * columns;
+    let layer = texture_index / tiles_per_layer;
+    let tile = texture_index % tiles_per_layer;
+    let tile_position = vec2<f32>(f32(tile % columns), f32(tile / columns));
+    // Kept inside the tile so the edge doesn't sample from the texture next to it.
+    let tile_uv = clamp(uv, vec2<f32>(0.0), vec2<f32>(0.999));
+    return textureSample(
+        texture_array,
+        texture_array_sampler,
+        (tile_position + tile_uv) / f32(columns),
+        layer
+    );
+}
+
+#ifdef WATER
+// Same as the day color in sky.wgsl
+const SKY_COLOR: vec3<f32> = vec3<f32>(0.1, 0.4, 1.0);
+const WAVE_HEIGHT: f32 = 0.02;
+
+fn wave_slope(position: vec2<f32>, direction: vec2<f32>, frequency: f32, speed: f32) -> vec2<f32> {
+    let phase = dot(position, direction) * frequency + globals.time * speed;
+    return direction * cos(phase) * frequency * WAVE_HEIGHT;
+}
+
+// A few sine waves going in different directions, the normal is made from the slope of their sum.
+fn wave_normal(position: vec2<f32>) -> vec3<f32> {
+    var slope = wave_slope(position, vec2<f32>(0.8, 0.6), 2.1, 1.3);
+    slope += wave_slope(position, vec2<f32>(-0.6, 0.8), 3.3, 1.7);
+    slope += wave_slope(position, vec2<f32>(0.196, -0.981), 5.7, 2.3);
+    slope += wave_slope(position, vec2<f32>(-0.9, -0.436), 8.9, 3.1);
+    return normalize(vec3<f32>(-slope.x, 1.0, -slope.y));
+}
+
+#ifdef WATER_REFRACTION
+#ifdef DEPTH_PREPASS
+// Steps along the reflected ray until it goes behind something in the depth buffer, and returns
+// the color of the scene there. The alpha is how much of it to use, 0 when the ray left the
+// screen without hitting anything.
+fn screen_space_reflection(origin: vec3<f32>, direction: vec3<f32>) -> vec4<f32> {
+    for (var i = 1; i <= 24; i++) {
+        // The steps get longer further away, where precision matters less
+        let distance = 0.05 * f32(i * i) + 0.1 * f32(i);
+        let ndc = position_world_to_ndc(origin + direction * distance);
+        if abs(ndc.x) > 1.0 || abs(ndc.y) > 1.0 || ndc.z <= 0.0 {
+            return vec4<f32>(0.0);
+        }
+
+        let uv = ndc_to_uv(ndc.xy);
+        let pixel = view.viewport.xy + uv * view.viewport.zw;
+        // Reversed z, the scene is in front of the ray where its depth is larger.
+        let scene_depth = prepass_depth(vec4<f32>(pixel, 0.0, 0.0), 0u);
+        if scene_depth > ndc.z {
+            let color = textureSampleLevel(view_transmission_texture, view_transmission_sampler, uv, 0.0).rgb;
+            // Faded towards the edges so there's no hard line where the reflection ends.
+            let edge = max(abs(ndc.x), abs(ndc.y));
+            return vec4<f32>(color, 1.0 - smoothstep(0.8, 1.0, edge));
+        }
+    }
+
+    return vec4<f32>(0.0);
+}
+#endif
+#endif
+
+// The water surface, only the top faces have waves. What's done depends on the water quality, see
+// the WATER_* shader defs set by the block material.
+fn water_surface(
+    color: vec4<f32>,
+    frag_coord: vec4<f32>,
+    world_position: vec3<f32>,
+    world_normal: vec3<f32>,
+) -> vec4<f32> {
+    if world_normal.y < 0.5 {
+        return color;
+    }
+
+    let normal = wave_normal(world_position.xz);
+    var surface = color.rgb * (1.0 + (normal.x + normal.z) * 0.5);
+    var alpha = color.a;
+
+#ifdef WATER_REFRACTION
+    // The scene beneath, offset by the waves to look like it is bent by the surface. The water's
+    // own color is blended over it the same way it would be without refraction.
+    let screen_uv = (frag_coord.xy - view.viewport.xy) / view.viewport.zw + normal.xz * 0.05;
+    let beneath = textureSampleLevel(view_transmission_texture, view_transmission_sampler, screen_uv, 0.0).rgb;
+    surface = mix(beneath, surface, alpha);
+    alpha = 1.0;
+#endif
+
+#ifdef WATER_REFLECTIONS
+    let to_camera = normalize(view.world_position.xyz - world_position);
+    // Schlick's approximation, more is reflected when looking along the surface
+    let fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, to_camera), 0.0), 5.0);
+
+    var reflection = SKY_COLOR * lights.ambient_color.a;
+#ifdef WATER_REFRACTION
+#ifdef DEPTH_PREPASS
+    let scene = screen_space_reflection(world_position, reflect(-to_camera, normal));
+    reflection = mix(reflection, scene.rgb, scene.a);
+#endif
+#endif
+
+    surface = mix(surface, reflection, fresnel);
+    alpha = mix(alpha, 1.0, fresnel);
+#endif
+
+    return vec4<f32>(surface, alpha);
+}
+#endif
+
 @fragment
 fn fragment(
     //@builtin(front_facing) is_front: bool,
@@ -146,23 +282,48 @@ fn fragment(
     @location(4) world_tangent: vec4<f32>,
 #endif
     @location(5) light_packed: u32,
+    @location(6) emission: u32,
 ) -> @location(0) vec4<f32> {
     var output_color: vec4<f32> = material.base_color;
 
     // TODO: For some reason this refuses to take a u32 as the index
     let fps = 10.0;
     let texture_index_animation_offset: i32 = texture_index + i32(globals.time * fps) % i32(material.animation_frames);
-    output_color = output_color * textureSample(texture_array, texture_array_sampler, uv, texture_index_animation_offset);
+    output_color = output_color * sample_block_texture(uv, texture_index_animation_offset);
 
     let artificial_level = f32(light_packed & 0xFu);
     let sunlight_level = f32((light_packed >> 4u) & 0xFu);
 
     let artificial = (pow(0.8, 15.0 - artificial_level));
-    let sunlight = pow(0.8, 15.0 - sunlight_level) * lights.ambient_color.a;
-    let light = max(artificial, sunlight);
+    var sunlight = pow(0.8, 15.0 - sunlight_level) * lights.ambient_color.a;
+    // The sun is the only directional light, it's only there when sun shadows are turned on. Only
+    // the sunlight is shadowed, blocks that give off light aren't affected.
+    if lights.n_directional_lights > 0u {
+        let view_z = position_world_to_view(world_position.xyz).z;
+        let shadow = fetch_directional_shadow(0u, world_position, world_normal, view_z);
+        sunlight = sunlight * mix(0.5, 1.0, shadow);
+    }
+    var light = vec3(max(artificial, sunlight));
+
+    // Blocks that emit light glow in their color as bright as their light level, the light around
+    // them doesn't darken them.
+    let emission_level = f32((emission >> 24u) & 0xFu);
+    if emission_level > 0.0 {
+        let emission_color = vec3(
+            f32(emission & 0xFFu),
+            f32((emission >> 8u) & 0xFFu),
+            f32((emission >> 16u) & 0xFFu),
+        ) / 255.0;
+        light = max(light, emission_color * pow(0.8, 15.0 - emission_level));
+    }
 
     output_color = vec4(output_color.rgb * light, output_color.a);
 
+#ifdef SCREEN_SPACE_AMBIENT_OCCLUSION
+    let ambient_occlusion = textureLoad(screen_space_ambient_occlusion_texture, vec2<i32>(frag_coord.xy), 0i).r;
+    output_color = vec4(output_color.rgb * ambient_occlusion, output_color.a);
+#endif
+
     if abs(world_normal.z) == 1.0 {
         output_color = vec4(output_color.rgb * 0.8, output_color.a);
     } else if abs(world_normal.x) == 1.0 {
@@ -171,8 +332,17 @@ fn fragment(
         output_color = vec4(output_color.rgb * 0.3, output_color.a);
     }
 
+#ifdef CUSTOM_BLOCK_SHADER
+    // Defined by the server, see assets::shaders
+    output_color = block_color(output_color, world_position.xyz, world_normal, uv);
+#endif
+
     output_color = alpha_discard(material, output_color);
 
+#ifdef WATER
+    output_color = water_surface(output_color, frag_coord, world_position.xyz, world_normal);
+#endif
+
     // This is water depth, hard to figure out, don't know if useless, no delete.
     //if ((material.flags & STANDARD_MATERIAL_FLAGS_IS_WATER) != 0u) {
     //    let z_depth_ndc = prepass_depth(frag_coord, sample_index);
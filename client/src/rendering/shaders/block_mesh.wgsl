@@ -12,6 +12,8 @@ struct Vertex {
     @location(0) position: vec3<f32>,
     @location(1) packed_bits: u32,
     @location(2) normal: vec3<f32>,
+    // Color and level of light emitted by the block, 8 bits each of rgb then 4 bits level
+    @location(7) emission: u32,
     // This is bit packed, first 2 bits are uv, last 19 are block texture index
     //@location(2) uv: u32,
 #ifdef VERTEX_TANGENTS
@@ -36,6 +38,7 @@ struct VertexOutput {
     @location(4) world_tangent: vec4<f32>,
 #endif
     @location(5) light: u32,
+    @location(6) emission: u32,
 };
 
 // Note: 0,0 is top left corner
@@ -51,6 +54,7 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
     out.light = (vertex.packed_bits >> 22u) & 0xFFu;
+    out.emission = vertex.emission;
     out.texture_index = i32(vertex.packed_bits & 0x0007FFFFu);
 
     // TODO: Naga might allow indexing without const value in the future
@@ -0,0 +1,110 @@
+#import bevy_pbr::{
+    mesh_functions,
+    prepass_io::FragmentOutput,
+    view_transformations::position_world_to_clip,
+}
+
+// Blocks are drawn in the prepass for the effects that need the depth and normals of the scene,
+// e.g. ambient occlusion. It only needs the position and normal of the vertices, and the texture
+// for blocks that have holes in them.
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) packed_bits: u32,
+    @location(2) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) @interpolate(flat) texture_index: i32,
+};
+
+// Only the first fields of the material uniform are needed, see block.wgsl
+struct BlockMaterial {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    perceptual_roughness: f32,
+    metallic: f32,
+    reflectance: f32,
+    flags: u32,
+    alpha_cutoff: f32,
+    animation_frames: u32,
+    atlas_columns: u32,
+};
+
+@group(2) @binding(0)
+var<uniform> material: BlockMaterial;
+@group(2) @binding(11)
+var texture_array: texture_2d_array<f32>;
+@group(2) @binding(12)
+var texture_array_sampler: sampler;
+
+// Note: 0,0 is top left corner
+const UVS: array<vec2<f32>, 4> = array<vec2<f32>, 4>(
+    vec2<f32>(0.0, 0.0),
+    vec2<f32>(0.0, 1.0),
+    vec2<f32>(1.0, 0.0),
+    vec2<f32>(1.0, 1.0),
+);
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.texture_index = i32(vertex.packed_bits & 0x0007FFFFu);
+
+    let uv_index: u32 = (vertex.packed_bits & 0x180000u) >> 19u;
+    if uv_index == 0u {
+        out.uv = UVS[0];
+    } else if uv_index == 1u {
+        out.uv = UVS[1];
+    } else if uv_index == 2u {
+        out.uv = UVS[2];
+    } else if uv_index == 3u {
+        out.uv = UVS[3];
+    }
+
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    let world_position = mesh_functions::mesh_position_local_to_world(world_from_local, vec4<f32>(vertex.position, 1.0));
+    out.position = position_world_to_clip(world_position.xyz);
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+
+#ifdef MAY_DISCARD
+    var color: vec4<f32>;
+    if material.atlas_columns <= 1u {
+        color = textureSample(texture_array, texture_array_sampler, in.uv, in.texture_index);
+    } else {
+        let columns = i32(material.atlas_columns);
+        let tiles_per_layer = columns * columns;
+        let tile = in.texture_index % tiles_per_layer;
+        let tile_position = vec2<f32>(f32(tile % columns), f32(tile / columns));
+        let tile_uv = clamp(in.uv, vec2<f32>(0.0), vec2<f32>(0.999));
+        color = textureSample(
+            texture_array,
+            texture_array_sampler,
+            (tile_position + tile_uv) / f32(columns),
+            in.texture_index / tiles_per_layer
+        );
+    }
+
+    if color.a < material.alpha_cutoff {
+        discard;
+    }
+#endif
+
+#ifdef NORMAL_PREPASS
+    out.normal = vec4(normalize(in.world_normal) * 0.5 + vec3(0.5), 1.0);
+#endif
+
+    return out;
+}
@@ -0,0 +1,102 @@
+#import bevy_pbr::{
+    mesh_functions,
+    mesh_view_bindings::{globals, view},
+    view_transformations::position_world_to_clip,
+}
+
+#ifdef DEPTH_PREPASS
+#import bevy_pbr::prepass_utils
+#endif
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    // Clip position of the particle's center, tested against the depth prepass
+    @location(1) center: vec4<f32>,
+}
+
+struct GpuParticleMaterialUniform {
+    base_color: vec4<f32>,
+    depth_collision: u32,
+    gravity: f32,
+    spawn_time: f32,
+    light: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> material: GpuParticleMaterialUniform;
+@group(2) @binding(1)
+var texture: texture_2d<f32>;
+@group(2) @binding(2)
+var texture_sampler: sampler;
+
+// globals.time wraps around to zero every hour
+const TIME_WRAP: f32 = 3600.0;
+
+@vertex
+fn vertex(
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) velocity: vec3<f32>,
+    // corner x, corner y, size, lifetime
+    @location(3) parameters: vec4<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    var age = globals.time - material.spawn_time;
+    if age < 0.0 {
+        age += TIME_WRAP;
+    }
+
+    if age > parameters.w {
+        // Every corner is put at the same point so the quad has no area and isn't drawn.
+        out.position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        return out;
+    }
+
+    // The particle follows a ballistic path from where it was spawned
+    let local_position = position
+        + velocity * age
+        + vec3<f32>(0.0, 0.5 * material.gravity * age * age, 0.0);
+
+    let world_from_local = mesh_functions::get_world_from_local(instance_index);
+    let center = mesh_functions::mesh_position_local_to_world(
+        world_from_local,
+        vec4<f32>(local_position, 1.0)
+    ).xyz;
+
+    // Turn the quad towards the camera
+    let right = view.world_from_view[0].xyz;
+    let up = view.world_from_view[1].xyz;
+    let corner = parameters.xy * parameters.z;
+    let world_position = center + right * corner.x + up * corner.y;
+
+    out.position = position_world_to_clip(world_position);
+    out.center = position_world_to_clip(center);
+    out.uv = uv;
+
+    return out;
+}
+
+@fragment
+fn fragment(
+    in: VertexOutput
+) -> @location(0) vec4<f32> {
+    // Without a texture this samples bevy's white fallback image
+    let color = textureSample(texture, texture_sampler, in.uv) * material.base_color;
+
+#ifdef DEPTH_PREPASS
+    if material.depth_collision != 0u {
+        let ndc = in.center.xyz / in.center.w;
+        let screen_position = (ndc.xy * vec2<f32>(0.5, -0.5) + 0.5) * view.viewport.zw + view.viewport.xy;
+        // Depth is reversed, further away is smaller. The whole particle is hidden once its center
+        // is behind the scene.
+        if ndc.z < prepass_utils::prepass_depth(vec4<f32>(screen_position, 0.0, 0.0), 0u) {
+            discard;
+        }
+    }
+#endif
+
+    return vec4<f32>(color.rgb * material.light, color.a);
+}
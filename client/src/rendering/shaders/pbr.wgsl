@@ -18,6 +18,7 @@
 }
 #import bevy_pbr::parallax_mapping::parallaxed_uv
 #import bevy_pbr::mesh_view_bindings::lights
+#import bevy_pbr::shadows::fetch_directional_shadow
 
 #import bevy_pbr::prepass_utils
 
@@ -128,7 +129,13 @@ fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
     // TODO: The 1.2 is a scaling factor to make it look bright enough, idk if it's the models
     // themselves or something else in the shader that makes them darker than they should be.
     let artificial = (pow(0.8, 15.0 - artificial_level)) * 1.2;
-    let sunlight = pow(0.8, 15.0 - sunlight_level) * lights.ambient_color.a * 1.2;
+    var sunlight = pow(0.8, 15.0 - sunlight_level) * lights.ambient_color.a * 1.2;
+    // Sun shadows, same as for blocks
+    if lights.n_directional_lights > 0u {
+        let view_z = view_transformations::position_world_to_view(in.world_position.xyz).z;
+        let shadow = fetch_directional_shadow(0u, in.world_position, in.world_normal, view_z);
+        sunlight = sunlight * mix(0.5, 1.0, shadow);
+    }
     let light = max(artificial, sunlight);
 
     output_color = vec4(output_color.rgb * light, output_color.a);
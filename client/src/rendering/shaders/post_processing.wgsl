@@ -0,0 +1,26 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+// The server's snippet is appended to this shader, it defines 'post_process', see
+// assets::shaders. These are the only bindings it can use.
+
+struct PostProcessGlobals {
+    // Seconds since startup, wraps around every hour
+    time: f32,
+}
+
+@group(0) @binding(0)
+var screen_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var screen_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> globals: PostProcessGlobals;
+
+// Color of the screen at the uv
+fn screen(uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(screen_texture, screen_sampler, uv);
+}
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return post_process(screen(in.uv), in.uv);
+}
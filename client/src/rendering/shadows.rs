@@ -0,0 +1,342 @@
+use bevy::{
+    core_pipeline::prepass::NormalPrepass,
+    pbr::{
+        CascadeShadowConfigBuilder, DirectionalLightShadowMap, NotShadowCaster,
+        ScreenSpaceAmbientOcclusion,
+    },
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        view::Msaa,
+    },
+};
+
+use crate::{
+    assets::models::Model,
+    game_state::GameState,
+    networking::ServerClock,
+    player::{Head, Player},
+    rendering::diagnostics::RendererInfo,
+    settings::{Settings, ShadowQuality},
+    world::{
+        blocks::{Blocks, Friction},
+        world_map::{chunk::Chunk, WorldMap},
+        Origin,
+    },
+};
+
+use super::sky::{self, SunAngle};
+
+/// How far below a model its shadow can be
+const MAX_SHADOW_DISTANCE: i32 = 6;
+/// How dark the shadow is right under the model
+const SHADOW_OPACITY: f32 = 0.5;
+
+/// Blob shadows under models and the player, screen space ambient occlusion and shadows cast by
+/// the sun. The first two make it easier to tell how far away things are without the cost of
+/// shadow mapping, the sun's shadows are optional for gpus that can afford them.
+pub struct ShadowPlugin;
+impl Plugin for ShadowPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                add_blob_shadows,
+                move_blob_shadows.after(add_blob_shadows),
+                toggle_ambient_occlusion.run_if(resource_changed::<Settings>),
+                update_sun_light.after(sky::update_sun_angle),
+            )
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct BlobShadowAssets {
+    mesh: Handle<Mesh>,
+    texture: Handle<Image>,
+}
+
+/// A dark circle on the ground under the entity it belongs to
+#[derive(Component)]
+struct BlobShadow {
+    owner: Entity,
+    size: f32,
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    commands.insert_resource(BlobShadowAssets {
+        mesh: meshes.add(Plane3d::default().mesh().size(1.0, 1.0)),
+        texture: images.add(shadow_texture()),
+    });
+}
+
+// A circle that fades out towards the edge
+fn shadow_texture() -> Image {
+    const SIZE: u32 = 32;
+
+    let mut data = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let position = (Vec2::new(x as f32, y as f32) + 0.5) / SIZE as f32 * 2.0 - 1.0;
+            let alpha = (1.0 - position.length()).clamp(0.0, 1.0).powf(0.5);
+            data.extend([0, 0, 0, (alpha * 255.0) as u8]);
+        }
+    }
+
+    return Image::new(
+        Extent3d {
+            width: SIZE,
+            height: SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+}
+
+fn add_blob_shadows(
+    mut commands: Commands,
+    shadow_assets: Res<BlobShadowAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    new_entities: Query<(Entity, &Transform), Or<(Added<Model>, Added<Player>)>>,
+) {
+    for (entity, transform) in new_entities.iter() {
+        commands.spawn((
+            Mesh3d(shadow_assets.mesh.clone()),
+            // Each shadow has its own material so they can fade separately
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::BLACK.with_alpha(SHADOW_OPACITY),
+                base_color_texture: Some(shadow_assets.texture.clone()),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                fog_enabled: false,
+                ..default()
+            })),
+            Transform::default(),
+            Visibility::Hidden,
+            NotShadowCaster,
+            BlobShadow {
+                owner: entity,
+                size: 0.8 * transform.scale.x.max(transform.scale.z),
+            },
+        ));
+    }
+}
+
+// The shadows are put on top of the first solid block below their owner, and fade out the
+// further down it is.
+fn move_blob_shadows(
+    mut commands: Commands,
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    settings: Res<Settings>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    owners: Query<&GlobalTransform>,
+    mut shadows: Query<(
+        Entity,
+        &BlobShadow,
+        &MeshMaterial3d<StandardMaterial>,
+        &mut Transform,
+        &mut Visibility,
+    )>,
+) {
+    let blocks = Blocks::get();
+
+    for (entity, shadow, material_handle, mut transform, mut visibility) in shadows.iter_mut() {
+        let Ok(owner_transform) = owners.get(shadow.owner) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+
+        *visibility = Visibility::Hidden;
+        if !settings.blob_shadows {
+            continue;
+        }
+
+        let position = owner_transform.translation();
+        let block_position = position.floor().as_ivec3();
+
+        let mut ground = None;
+        for offset in 0..=MAX_SHADOW_DISTANCE {
+            let below = block_position - IVec3::Y * offset;
+            let Some(block_id) = world_map.get_block(&(origin.0 + below)) else {
+                break;
+            };
+
+            if matches!(
+                blocks.get_config(block_id).friction(),
+                Friction::Static { .. }
+            ) {
+                ground = Some(below.y as f32 + 1.0);
+                break;
+            }
+        }
+
+        let Some(ground) = ground else {
+            continue;
+        };
+
+        let height = (position.y - ground).max(0.0);
+        let fade = 1.0 - (height / MAX_SHADOW_DISTANCE as f32).min(1.0);
+
+        // Lifted a little so it doesn't fight with the block top
+        transform.translation = Vec3::new(position.x, ground + 0.01, position.z);
+        transform.scale = Vec3::splat(shadow.size * (0.5 + 0.5 * fade));
+        *visibility = Visibility::Visible;
+
+        // Only changed when needed, changing it uploads it to the gpu again
+        let alpha = SHADOW_OPACITY * fade;
+        if materials
+            .get(material_handle)
+            .is_some_and(|material| material.base_color.alpha() != alpha)
+        {
+            let material = materials.get_mut(material_handle).unwrap();
+            material.base_color = Color::BLACK.with_alpha(alpha);
+        }
+    }
+}
+
+fn toggle_ambient_occlusion(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    camera_query: Query<(Entity, Has<ScreenSpaceAmbientOcclusion>), With<Head>>,
+) {
+    let Ok((camera_entity, has_ambient_occlusion)) = camera_query.get_single() else {
+        return;
+    };
+
+    if settings.ambient_occlusion == has_ambient_occlusion {
+        return;
+    }
+
+    // The depth prepass it needs is shared with particle collision, see
+    // particles::gpu::toggle_depth_collision
+    if settings.ambient_occlusion {
+        // Doesn't work with msaa
+        commands.entity(camera_entity).insert((
+            ScreenSpaceAmbientOcclusion::default(),
+            NormalPrepass,
+            Msaa::Off,
+        ));
+    } else {
+        commands
+            .entity(camera_entity)
+            .remove::<(ScreenSpaceAmbientOcclusion, NormalPrepass)>();
+    }
+}
+
+/// The sun light is removed when the sun is lower than this, the shadows would stretch too far.
+const MIN_SUN_HEIGHT: f32 = 0.1;
+
+/// The directional light the sun's shadows are cast from. It's only used for its shadows, the
+/// block and model shaders do their own lighting.
+#[derive(Component)]
+struct SunLight;
+
+struct ShadowPreset {
+    cascades: usize,
+    map_size: usize,
+    /// How far from the camera shadows are drawn, in blocks
+    distance: f32,
+}
+
+fn shadow_preset(quality: ShadowQuality) -> Option<ShadowPreset> {
+    return match quality {
+        ShadowQuality::Off => None,
+        ShadowQuality::Low => Some(ShadowPreset {
+            cascades: 1,
+            map_size: 1024,
+            distance: 32.0,
+        }),
+        ShadowQuality::Medium => Some(ShadowPreset {
+            cascades: 2,
+            map_size: 2048,
+            distance: 64.0,
+        }),
+        ShadowQuality::High => Some(ShadowPreset {
+            cascades: 4,
+            map_size: 4096,
+            distance: 128.0,
+        }),
+    };
+}
+
+// The light follows the sun's position in the sky, see rendering::sky. Each cascade is culled
+// separately by bevy using the bounding boxes of the chunk meshes, so only the chunks inside a
+// cascade are drawn into it. The cascades never reach further than the render distance, there are
+// no chunks past it to cast shadows.
+fn update_sun_light(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    renderer_info: Res<RendererInfo>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+    time: Res<Time<Real>>,
+    server_clock: Res<ServerClock>,
+    sun_angle: Res<SunAngle>,
+    mut sun_light_query: Query<(Entity, &mut Transform), With<SunLight>>,
+) {
+    let angle = sun_angle.at(server_clock.now(&time)).unwrap_or(0.0);
+
+    let preset = if renderer_info.fallbacks.simple_shaders {
+        None
+    } else {
+        shadow_preset(settings.sun_shadows)
+    };
+
+    // Same rotation as the skybox, the sun starts out along the x axis.
+    let sun_direction = Quat::from_rotation_z(angle) * Vec3::X;
+
+    let Some(preset) = preset.filter(|_| sun_direction.y > MIN_SUN_HEIGHT) else {
+        if let Ok((entity, _)) = sun_light_query.get_single() {
+            commands.entity(entity).despawn();
+        }
+        return;
+    };
+
+    // The sun moves in the xy plane, z is never parallel to it.
+    let transform = Transform::default().looking_to(-sun_direction, Vec3::Z);
+
+    let max_distance = preset
+        .distance
+        .min((settings.render_distance as usize * Chunk::SIZE) as f32)
+        .max(1.0);
+
+    let cascades = CascadeShadowConfigBuilder {
+        num_cascades: preset.cascades,
+        minimum_distance: 0.1,
+        maximum_distance: max_distance,
+        first_cascade_far_bound: max_distance / 2f32.powi(preset.cascades as i32 - 1),
+        overlap_proportion: 0.2,
+    }
+    .build();
+
+    if shadow_map.size != preset.map_size {
+        shadow_map.size = preset.map_size;
+    }
+
+    if let Ok((entity, mut light_transform)) = sun_light_query.get_single_mut() {
+        light_transform.set_if_neq(transform);
+        if settings.is_changed() {
+            commands.entity(entity).insert(cascades);
+        }
+    } else {
+        commands.spawn((
+            DirectionalLight {
+                illuminance: 0.0,
+                shadows_enabled: true,
+                ..default()
+            },
+            cascades,
+            transform,
+            SunLight,
+        ));
+    }
+}
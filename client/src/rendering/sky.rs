@@ -11,22 +11,100 @@ use bevy::{
 use fmc_protocol::messages;
 
 use crate::{
-    assets::AssetState, game_state::GameState, player::Player, rendering::materials, utils,
+    assets::AssetState,
+    game_state::GameState,
+    networking::{Latency, ServerClock},
+    player::Player,
+    rendering::materials,
+    utils,
 };
 
 use super::materials::SkyMaterial;
 
 const RADIUS: f32 = 500.0;
+// The speed of the sun is measured over at least this many seconds of server time, the updates
+// can come every tick, which is too short to tell the speed from.
+const MIN_SPEED_INTERVAL: f64 = 1.0;
+// The sun stops if the server stops sending updates for this long, it might have stopped the time.
+const MAX_EXTRAPOLATION: f64 = 10.0;
 
 pub struct SkyPlugin;
 impl Plugin for SkyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(AssetState::Loading), setup)
-            .add_systems(OnEnter(GameState::Launcher), cleanup)
-            .add_systems(Update, pass_time.run_if(in_state(GameState::Playing)));
+        app.init_resource::<SunAngle>()
+            .add_systems(OnEnter(AssetState::Loading), setup)
+            .add_systems(
+                OnEnter(GameState::Launcher),
+                (cleanup, |mut sun_angle: ResMut<SunAngle>| {
+                    *sun_angle = SunAngle::default();
+                }),
+            )
+            .add_systems(
+                Update,
+                (update_sun_angle, pass_time)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
     }
 }
 
+/// The angle of the sun, which the server sends with the time of day. It is moved along between
+/// the updates at the speed the server passes time.
+#[derive(Resource, Default)]
+pub(super) struct SunAngle {
+    // Angle of the last update and the server time it was sent at
+    last_update: Option<(f32, f64)>,
+    // The update the speed is measured from
+    reference: Option<(f32, f64)>,
+    // Radians per second
+    speed: f32,
+}
+
+impl SunAngle {
+    /// The angle at the time on the server's clock, None until the server has sent the time.
+    pub fn at(&self, server_time: f64) -> Option<f32> {
+        let (angle, sent_at) = self.last_update?;
+        let elapsed = (server_time - sent_at).clamp(0.0, MAX_EXTRAPOLATION);
+        return Some((angle + self.speed * elapsed as f32) % TAU);
+    }
+}
+
+pub(super) fn update_sun_angle(
+    time: Res<Time<Real>>,
+    server_clock: Res<ServerClock>,
+    latency: Res<Latency>,
+    mut sun_angle: ResMut<SunAngle>,
+    mut server_time_events: EventReader<messages::Time>,
+) {
+    let Some(update) = server_time_events.read().last() else {
+        return;
+    };
+
+    // TODO: Should probably disconnect if above TAU to force the server to be compliant.
+    let angle = update.angle % TAU;
+    let sent_at = server_clock.now(&time) - latency.one_way();
+    sun_angle.last_update = Some((angle, sent_at));
+
+    let Some((reference_angle, reference_time)) = sun_angle.reference else {
+        sun_angle.reference = Some((angle, sent_at));
+        return;
+    };
+
+    let elapsed = sent_at - reference_time;
+    if elapsed < MIN_SPEED_INTERVAL {
+        return;
+    }
+
+    let distance = (angle - reference_angle).rem_euclid(TAU);
+    // Anything more than a small step is the server setting the time, not passing it.
+    sun_angle.speed = if distance < PI / 4.0 {
+        distance / elapsed as f32
+    } else {
+        0.0
+    };
+    sun_angle.reference = Some((angle, sent_at));
+}
+
 #[derive(Component)]
 struct SkyBox;
 
@@ -105,17 +183,16 @@ fn setup(
 
 fn pass_time(
     time: Res<Time>,
+    real_time: Res<Time<Real>>,
+    server_clock: Res<ServerClock>,
+    sun_angle: Res<SunAngle>,
     mut sky_materials: ResMut<Assets<SkyMaterial>>,
     mut ambient_light: ResMut<AmbientLight>,
     mut sky_box_query: Query<&mut Transform, With<SkyBox>>,
     mut sun_query: Query<&mut Transform, (With<Sun>, Without<SkyBox>, Without<Moon>)>,
     mut moon_query: Query<&mut Transform, (With<Moon>, Without<SkyBox>, Without<Sun>)>,
-    mut server_time_events: EventReader<messages::Time>,
 ) {
-    let angle = if let Some(t) = server_time_events.read().last() {
-        // TODO: Should probably disconnect if above TAU to force the server to be compliant.
-        t.angle % TAU
-    } else {
+    let Some(angle) = sun_angle.at(server_clock.now(&real_time)) else {
         return;
     };
 
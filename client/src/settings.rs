@@ -2,7 +2,10 @@ use bevy::prelude::*;
 
 use fmc_protocol::messages;
 
-use crate::{game_state::GameState, networking::NetworkClient};
+use crate::{
+    game_state::GameState,
+    networking::{properties, NetworkClient},
+};
 
 pub(super) struct SettingsPlugin;
 impl Plugin for SettingsPlugin {
@@ -23,16 +26,68 @@ impl Plugin for SettingsPlugin {
 pub struct Settings {
     /// Render distance in chunks
     pub render_distance: u32,
+    /// Render distance in chunks above and below the player
+    pub vertical_render_distance: u32,
     /// Field of view of camera
     pub fov: f32,
     /// Sound volume
     pub volume: f32,
+    /// Volume of the music, relative to the sound volume
+    pub music_volume: f32,
+    /// Play music tracks in random order
+    pub music_shuffle: bool,
+    /// Hide particles of large effects when they pass behind blocks, at the cost of an extra
+    /// depth pass
+    pub particle_collision: bool,
+    /// Draw dark circles on the ground under models and the player
+    pub blob_shadows: bool,
+    /// Darken corners and crevices with screen space ambient occlusion
+    pub ambient_occlusion: bool,
+    /// Quality of the shadows cast by the sun, they're not drawn when off
+    pub sun_shadows: ShadowQuality,
+    /// How water surfaces are drawn, see WaterQuality
+    pub water_quality: WaterQuality,
+    /// Move the camera up and down with the player's steps
+    pub view_bobbing: bool,
+    /// Turn off all camera motion that the player doesn't control, view bobbing, camera shake and
+    /// field of view changes, for players that get motion sick
+    pub reduce_motion: bool,
+    /// Leave the interface out of screenshots
+    pub screenshot_hide_ui: bool,
+    /// Colors used for highlights sent by the server, e.g. text and boss bars, and the client's
+    /// own, like durability bars.
+    pub color_palette: ColorPalette,
+    /// Draw text on darker backgrounds
+    pub high_contrast: bool,
+    /// Show captions for the sounds the server has captioned
+    pub captions: bool,
+    /// Show the time chat messages were received at
+    pub chat_timestamps: bool,
+    /// Hide the messages about players joining and leaving
+    pub chat_hide_connections: bool,
+    /// Ask the server to filter the messages of other players, e.g. for profanity
+    pub chat_filter: bool,
     /// Mouse sensitivity
     pub sensitivity: f32,
     /// Horizontal speed while flying
     pub flight_speed: f32,
     /// Fog that limits visibility
     pub fog: DistanceFog,
+    /// If the minimap should be shown
+    pub minimap: bool,
+    /// How many blocks each pixel of the minimap covers
+    pub minimap_zoom: f32,
+    /// Keep north up on the minimap instead of rotating it with the camera
+    pub minimap_rotation_locked: bool,
+    /// Keep the chunks of each server on disk, so that terrain that has been seen before shows up
+    /// immediately when rejoining.
+    pub chunk_cache: bool,
+    /// Load the server's shaders again when they are changed on disk, for developing them
+    pub shader_hot_reload: bool,
+    /// Url of the account service. Players log in at "<url>/login", and get a join token for
+    /// each server they connect to from "<url>/join". If not set, the player can only play on
+    /// servers that are in offline mode.
+    pub account_service: Option<String>,
 }
 
 impl Settings {
@@ -42,6 +97,15 @@ impl Settings {
 
         return settings;
     }
+    /// Background color of text, it is darkened when high contrast is turned on.
+    pub fn text_background(&self, color: Color) -> Color {
+        if !self.high_contrast {
+            return color;
+        }
+
+        return Color::BLACK.with_alpha(color.alpha().max(0.8));
+    }
+
     //fn save(&self) {
     //    let mut contents = "".to_owned()
     //    contents += "render_distance = " + &self.render_distance.to_string() + "\n"
@@ -54,15 +118,176 @@ impl Default for Settings {
     fn default() -> Self {
         Self {
             render_distance: 16,
+            vertical_render_distance: 8,
             fov: std::f32::consts::PI / 3.0,
             volume: 1.0,
+            music_volume: 0.5,
+            music_shuffle: true,
+            particle_collision: false,
+            blob_shadows: true,
+            ambient_occlusion: false,
+            sun_shadows: ShadowQuality::Off,
+            water_quality: WaterQuality::Medium,
+            view_bobbing: true,
+            reduce_motion: false,
+            screenshot_hide_ui: false,
+            color_palette: ColorPalette::Default,
+            high_contrast: false,
+            captions: false,
+            chat_timestamps: false,
+            chat_hide_connections: false,
+            chat_filter: false,
             sensitivity: 0.00005,
             flight_speed: 50.0,
             fog: DistanceFog {
                 color: Color::NONE,
                 ..default()
             },
+            minimap: true,
+            minimap_zoom: 1.0,
+            minimap_rotation_locked: false,
+            chunk_cache: true,
+            shader_hot_reload: cfg!(debug_assertions),
+            account_service: None,
+        }
+    }
+}
+
+/// Palettes for color blindness. Colors that are hard to tell apart are moved to hues that are
+/// easier to distinguish, grays and faint colors are left as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorPalette {
+    Default,
+    /// Red-green, weak green
+    Deuteranopia,
+    /// Red-green, weak red
+    Protanopia,
+    /// Blue-yellow
+    Tritanopia,
+}
+
+impl ColorPalette {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Self::Default => "Default",
+            Self::Deuteranopia => "Deuteranopia",
+            Self::Protanopia => "Protanopia",
+            Self::Tritanopia => "Tritanopia",
+        };
+    }
+
+    /// The palette after this one, for cycling through them
+    pub fn next(&self) -> Self {
+        return match self {
+            Self::Default => Self::Deuteranopia,
+            Self::Deuteranopia => Self::Protanopia,
+            Self::Protanopia => Self::Tritanopia,
+            Self::Tritanopia => Self::Default,
+        };
+    }
+
+    pub fn adjust(&self, color: Color) -> Color {
+        if *self == Self::Default {
+            return color;
+        }
+
+        let mut hsla = Hsla::from(color);
+        if hsla.saturation < 0.2 {
+            return color;
+        }
+
+        let hue = hsla.hue;
+        match self {
+            Self::Default => (),
+            // Greens become blue and reds orange, the two are then told apart by being
+            // blue/yellow instead.
+            Self::Deuteranopia | Self::Protanopia => {
+                if (75.0..165.0).contains(&hue) {
+                    hsla.hue = 210.0;
+                } else if hue < 15.0 || hue >= 345.0 {
+                    hsla.hue = 30.0;
+                    // Reds look dark with weak red cones
+                    if *self == Self::Protanopia {
+                        hsla.lightness = hsla.lightness.max(0.5);
+                    }
+                }
+            }
+            // Blues become cyan and yellows pink, told apart as red/cyan.
+            Self::Tritanopia => {
+                if (190.0..260.0).contains(&hue) {
+                    hsla.hue = 180.0;
+                } else if (45.0..75.0).contains(&hue) {
+                    hsla.hue = 330.0;
+                }
+            }
         }
+
+        return hsla.into();
+    }
+}
+
+/// Presets for the sun's shadow maps, see rendering::shadows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadowQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Self::Off => "Off",
+            Self::Low => "Low",
+            Self::Medium => "Medium",
+            Self::High => "High",
+        };
+    }
+
+    /// The quality after this one, for cycling through them
+    pub fn next(&self) -> Self {
+        return match self {
+            Self::Off => Self::Low,
+            Self::Low => Self::Medium,
+            Self::Medium => Self::High,
+            Self::High => Self::Off,
+        };
+    }
+}
+
+/// Each tier adds to the one before it, the blocks it applies to are marked as water in their
+/// configs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WaterQuality {
+    /// Drawn like any other block
+    Off,
+    /// Animated waves
+    Low,
+    /// Reflections of the sky
+    Medium,
+    /// The scene beneath is refracted through the surface, and the scene above is reflected in it
+    High,
+}
+
+impl WaterQuality {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Self::Off => "Off",
+            Self::Low => "Low",
+            Self::Medium => "Medium",
+            Self::High => "High",
+        };
+    }
+
+    /// The quality after this one, for cycling through them
+    pub fn next(&self) -> Self {
+        return match self {
+            Self::Off => Self::Low,
+            Self::Low => Self::Medium,
+            Self::Medium => Self::High,
+            Self::High => Self::Off,
+        };
     }
 }
 
@@ -83,6 +308,13 @@ fn set_render_distance(
 ) {
     if server_config.is_changed() {
         settings.render_distance = settings.render_distance.min(server_config.render_distance);
+        // TODO: The RenderDistance message has no vertical distance, it is sent as a property
+        // first so that the server has it when the render distance is changed. The server clamps
+        // it to its max.
+        net.send_property(
+            properties::VERTICAL_RENDER_DISTANCE,
+            &settings.vertical_render_distance,
+        );
         net.send_message(messages::RenderDistance {
             chunks: settings.render_distance,
         });
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    settings::Settings,
+};
+
+use super::{widgets::TextShadow, DEFAULT_FONT_HANDLE};
+
+// TODO: There is no message for boss bars, until there is they are sent as the "boss_bar"
+// property. A null bar removes it.
+//
+// Progress bars with a text above them at the top of the screen, for boss fights, countdowns and
+// other events. The bars are shown in the order they were added.
+pub struct BossBarPlugin;
+impl Plugin for BossBarPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BossBars>()
+            .add_systems(Startup, setup)
+            .add_systems(OnExit(GameState::Playing), remove_boss_bars)
+            .add_systems(
+                Update,
+                handle_boss_bar_properties
+                    .run_if(in_state(GameState::Playing).and(on_event::<ServerProperty>)),
+            );
+    }
+}
+
+struct BossBar {
+    entity: Entity,
+    text: Entity,
+    fill: Entity,
+}
+
+// The bars by their names
+#[derive(Resource, Default)]
+struct BossBars(HashMap<String, BossBar>);
+
+#[derive(Component)]
+struct BossBarContainer;
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        BossBarContainer,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(4.0),
+            width: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(2.0),
+            ..default()
+        },
+    ));
+}
+
+fn remove_boss_bars(mut commands: Commands, mut boss_bars: ResMut<BossBars>) {
+    for (_, boss_bar) in boss_bars.0.drain() {
+        commands.entity(boss_bar.entity).despawn_recursive();
+    }
+}
+
+fn handle_boss_bar_properties(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut boss_bars: ResMut<BossBars>,
+    container_query: Query<Entity, With<BossBarContainer>>,
+    mut text_query: Query<&mut Text>,
+    mut fill_query: Query<(&mut Node, &mut BackgroundColor)>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::BOSS_BAR {
+            continue;
+        }
+
+        let Some(properties::BossBarUpdate { name, bar }) = property.parse() else {
+            continue;
+        };
+
+        let Some(bar) = bar else {
+            if let Some(boss_bar) = boss_bars.0.remove(&name) {
+                commands.entity(boss_bar.entity).despawn_recursive();
+            }
+            continue;
+        };
+
+        let progress = bar.progress.clamp(0.0, 1.0);
+        let color = settings
+            .color_palette
+            .adjust(Srgba::hex(&bar.color).unwrap_or(Srgba::WHITE).into());
+        let text = bar.text;
+
+        if let Some(boss_bar) = boss_bars.0.get(&name) {
+            if let Ok(mut current_text) = text_query.get_mut(boss_bar.text) {
+                current_text.0 = text;
+            }
+            if let Ok((mut node, mut background)) = fill_query.get_mut(boss_bar.fill) {
+                node.width = Val::Percent(progress * 100.0);
+                *background = BackgroundColor(color);
+            }
+            continue;
+        }
+
+        let mut text_entity = Entity::PLACEHOLDER;
+        let mut fill_entity = Entity::PLACEHOLDER;
+        let entity = commands
+            .spawn(Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            })
+            .with_children(|parent| {
+                text_entity = parent
+                    .spawn((
+                        Text::new(text),
+                        TextFont {
+                            font: DEFAULT_FONT_HANDLE,
+                            font_size: 6.0,
+                            ..default()
+                        },
+                        TextShadow::default(),
+                    ))
+                    .id();
+                parent
+                    .spawn((
+                        Node {
+                            width: Val::Px(100.0),
+                            height: Val::Px(3.0),
+                            border: UiRect::all(Val::Px(1.0)),
+                            ..default()
+                        },
+                        BorderColor::from(Color::BLACK),
+                        BackgroundColor(Color::srgb_u8(33, 33, 33)),
+                    ))
+                    .with_children(|parent| {
+                        fill_entity = parent
+                            .spawn((
+                                Node {
+                                    width: Val::Percent(progress * 100.0),
+                                    height: Val::Percent(100.0),
+                                    ..default()
+                                },
+                                BackgroundColor(color),
+                            ))
+                            .id();
+                    });
+            })
+            .id();
+
+        commands.entity(container_query.single()).add_child(entity);
+
+        boss_bars.0.insert(
+            name,
+            BossBar {
+                entity,
+                text: text_entity,
+                fill: fill_entity,
+            },
+        );
+    }
+}
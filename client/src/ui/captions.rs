@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use fmc_protocol::messages;
+
+use crate::{
+    game_state::GameState, networking::NetworkClient, player::Head, settings::Settings,
+    world::Origin,
+};
+
+use super::{widgets::TextShadow, DEFAULT_FONT_HANDLE};
+
+const CAPTIONS_PATH: &str = "server_assets/active/audio/captions.json";
+// How long a caption is shown after the sound was last played
+const CAPTION_DURATION: f32 = 3.0;
+// Sounds closer to straight ahead or behind than this don't show a direction
+const DIRECTION_THRESHOLD: f32 = 0.3;
+
+// Text descriptions of the sounds that are played, for players that can't hear them. The server
+// decides which sounds are important enough to caption by listing them in its captions file, by
+// the same path they are played with, e.g. "mobs/zombie/growl.ogg": "Zombie growls".
+pub struct CaptionPlugin;
+impl Plugin for CaptionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Captions>()
+            .add_systems(Startup, setup)
+            .add_systems(OnExit(GameState::Playing), remove_captions)
+            .add_systems(
+                Update,
+                (show_captions, remove_expired_captions)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+// Captions by the path of the sound
+#[derive(Resource, Default)]
+pub struct Captions(HashMap<String, String>);
+
+#[derive(Component)]
+struct CaptionContainer;
+
+#[derive(Component)]
+struct Caption {
+    sound: String,
+    timer: Timer,
+}
+
+pub fn load_captions(mut commands: Commands, net: Res<NetworkClient>) {
+    let Ok(file) = std::fs::File::open(CAPTIONS_PATH) else {
+        commands.insert_resource(Captions::default());
+        return;
+    };
+
+    match serde_json::from_reader(file) {
+        Ok(captions) => commands.insert_resource(Captions(captions)),
+        Err(e) => {
+            net.disconnect(&format!(
+                "Misconfigured assets: failed to read captions at: {}\nError: {}",
+                CAPTIONS_PATH, e
+            ));
+        }
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        CaptionContainer,
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(40.0),
+            right: Val::Px(4.0),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::End,
+            ..default()
+        },
+    ));
+}
+
+fn remove_captions(mut commands: Commands, caption_query: Query<Entity, With<Caption>>) {
+    for entity in caption_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn show_captions(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    captions: Res<Captions>,
+    origin: Res<Origin>,
+    head_query: Query<&GlobalTransform, With<Head>>,
+    container_query: Query<Entity, With<CaptionContainer>>,
+    mut caption_query: Query<(&mut Caption, &Children)>,
+    mut text_query: Query<&mut Text>,
+    mut sound_events: EventReader<messages::Sound>,
+) {
+    if !settings.captions {
+        sound_events.clear();
+        return;
+    }
+
+    for sound in sound_events.read() {
+        let Some(caption) = captions.0.get(&sound.sound) else {
+            continue;
+        };
+
+        // Which side the sound came from, sounds without a position are heard everywhere.
+        let direction = sound
+            .position
+            .zip(head_query.get_single().ok())
+            .map(|(position, head)| {
+                let to_sound = (origin.to_local(position) - head.translation()).normalize_or_zero();
+                head.right().dot(to_sound)
+            })
+            .unwrap_or(0.0);
+        let text = if direction < -DIRECTION_THRESHOLD {
+            format!("< {}", caption)
+        } else if direction > DIRECTION_THRESHOLD {
+            format!("{} >", caption)
+        } else {
+            caption.clone()
+        };
+
+        // The same sound playing again refreshes its caption instead of adding another line.
+        if let Some((mut existing, children)) = caption_query
+            .iter_mut()
+            .find(|(existing, _)| existing.sound == sound.sound)
+        {
+            existing.timer.reset();
+            for child in children.iter() {
+                if let Ok(mut current_text) = text_query.get_mut(*child) {
+                    current_text.0 = text.clone();
+                }
+            }
+            continue;
+        }
+
+        let entity = commands
+            .spawn((
+                Caption {
+                    sound: sound.sound.clone(),
+                    timer: Timer::from_seconds(CAPTION_DURATION, TimerMode::Once),
+                },
+                Node {
+                    padding: UiRect::horizontal(Val::Px(2.0)),
+                    ..default()
+                },
+                BackgroundColor(settings.text_background(Color::srgba(0.0, 0.0, 0.0, 0.5))),
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    Text::new(text),
+                    TextFont {
+                        font: DEFAULT_FONT_HANDLE,
+                        font_size: 6.0,
+                        ..default()
+                    },
+                    TextShadow::default(),
+                ));
+            })
+            .id();
+
+        commands.entity(container_query.single()).add_child(entity);
+    }
+}
+
+fn remove_expired_captions(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut caption_query: Query<(Entity, &mut Caption)>,
+) {
+    for (entity, mut caption) in caption_query.iter_mut() {
+        caption.timer.tick(time.delta());
+        if caption.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
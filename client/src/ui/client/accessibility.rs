@@ -0,0 +1,176 @@
+use bevy::{color::palettes::css::DARK_GRAY, prelude::*};
+
+use super::{set_button_label, GuiState, Interface, Interfaces};
+use crate::{settings::Settings, ui::widgets::*};
+
+// Opened from the pause menu, returns to it when closed.
+pub struct AccessibilityPlugin;
+impl Plugin for AccessibilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                color_palette_button,
+                contrast_button,
+                reduce_motion_button,
+                captions_button,
+                back_button,
+                escape_key,
+            )
+                .run_if(in_state(GuiState::Accessibility)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct ColorPaletteButton;
+
+#[derive(Component)]
+struct ContrastButton;
+
+#[derive(Component)]
+struct ReduceMotionButton;
+
+#[derive(Component)]
+struct CaptionsButton;
+
+#[derive(Component)]
+struct BackButton;
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        return "On";
+    } else {
+        return "Off";
+    }
+}
+
+fn color_palette_label(settings: &Settings) -> String {
+    return format!("Colors: {}", settings.color_palette.name());
+}
+
+fn contrast_label(settings: &Settings) -> String {
+    if settings.high_contrast {
+        return "Contrast: High".to_owned();
+    } else {
+        return "Contrast: Normal".to_owned();
+    }
+}
+
+fn reduce_motion_label(settings: &Settings) -> String {
+    return format!("Reduce motion: {}", on_off(settings.reduce_motion));
+}
+
+fn captions_label(settings: &Settings) -> String {
+    return format!("Captions: {}", on_off(settings.captions));
+}
+
+fn setup(mut commands: Commands, settings: Res<Settings>, mut interfaces: ResMut<Interfaces>) {
+    let entity = commands
+        .spawn((
+            Interface,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                row_gap: Val::Px(4.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor::from(DARK_GRAY.with_alpha(0.5)),
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn_button(200.0, &color_palette_label(&settings))
+                .insert(ColorPaletteButton);
+            parent
+                .spawn_button(200.0, &contrast_label(&settings))
+                .insert(ContrastButton);
+            parent
+                .spawn_button(200.0, &reduce_motion_label(&settings))
+                .insert(ReduceMotionButton);
+            parent
+                .spawn_button(200.0, &captions_label(&settings))
+                .insert(CaptionsButton);
+            parent.spawn_button(200.0, "Back").insert(BackButton);
+        })
+        .id();
+    interfaces.insert(GuiState::Accessibility, entity);
+}
+
+fn color_palette_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<ColorPaletteButton>),
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.color_palette = settings.color_palette.next();
+            set_button_label(children, &mut text_query, color_palette_label(&settings));
+        }
+    }
+}
+
+fn contrast_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<ContrastButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.high_contrast = !settings.high_contrast;
+            set_button_label(children, &mut text_query, contrast_label(&settings));
+        }
+    }
+}
+
+fn reduce_motion_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<ReduceMotionButton>),
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.reduce_motion = !settings.reduce_motion;
+            set_button_label(children, &mut text_query, reduce_motion_label(&settings));
+        }
+    }
+}
+
+fn captions_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<CaptionsButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.captions = !settings.captions;
+            set_button_label(children, &mut text_query, captions_label(&settings));
+        }
+    }
+}
+
+fn back_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<BackButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::PauseMenu);
+        }
+    }
+}
+
+fn escape_key(mut gui_state: ResMut<NextState<GuiState>>, input: Res<ButtonInput<KeyCode>>) {
+    if input.just_pressed(KeyCode::Escape) {
+        gui_state.set(GuiState::PauseMenu);
+    }
+}
@@ -0,0 +1,153 @@
+use bevy::{color::palettes::css::DARK_GRAY, prelude::*};
+
+use super::{set_button_label, GuiState, Interface, Interfaces};
+use crate::{settings::Settings, ui::widgets::*};
+
+// Opened from the pause menu, returns to it when closed.
+pub struct ChatSettingsPlugin;
+impl Plugin for ChatSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                timestamps_button,
+                connection_messages_button,
+                filter_button,
+                back_button,
+                escape_key,
+            )
+                .run_if(in_state(GuiState::ChatSettings)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct TimestampsButton;
+
+#[derive(Component)]
+struct ConnectionMessagesButton;
+
+#[derive(Component)]
+struct FilterButton;
+
+#[derive(Component)]
+struct BackButton;
+
+fn timestamps_label(settings: &Settings) -> String {
+    if settings.chat_timestamps {
+        return "Timestamps: On".to_owned();
+    } else {
+        return "Timestamps: Off".to_owned();
+    }
+}
+
+fn connection_messages_label(settings: &Settings) -> String {
+    if settings.chat_hide_connections {
+        return "Join/leave messages: Hidden".to_owned();
+    } else {
+        return "Join/leave messages: Shown".to_owned();
+    }
+}
+
+fn filter_label(settings: &Settings) -> String {
+    if settings.chat_filter {
+        return "Chat filter: On".to_owned();
+    } else {
+        return "Chat filter: Off".to_owned();
+    }
+}
+
+fn setup(mut commands: Commands, settings: Res<Settings>, mut interfaces: ResMut<Interfaces>) {
+    let entity = commands
+        .spawn((
+            Interface,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                row_gap: Val::Px(4.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor::from(DARK_GRAY.with_alpha(0.5)),
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn_button(200.0, &timestamps_label(&settings))
+                .insert(TimestampsButton);
+            parent
+                .spawn_button(200.0, &connection_messages_label(&settings))
+                .insert(ConnectionMessagesButton);
+            parent
+                .spawn_button(200.0, &filter_label(&settings))
+                .insert(FilterButton);
+            parent.spawn_button(200.0, "Back").insert(BackButton);
+        })
+        .id();
+    interfaces.insert(GuiState::ChatSettings, entity);
+}
+
+fn timestamps_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<TimestampsButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.chat_timestamps = !settings.chat_timestamps;
+            set_button_label(children, &mut text_query, timestamps_label(&settings));
+        }
+    }
+}
+
+fn connection_messages_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<ConnectionMessagesButton>),
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.chat_hide_connections = !settings.chat_hide_connections;
+            set_button_label(
+                children,
+                &mut text_query,
+                connection_messages_label(&settings),
+            );
+        }
+    }
+}
+
+fn filter_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<FilterButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.chat_filter = !settings.chat_filter;
+            set_button_label(children, &mut text_query, filter_label(&settings));
+        }
+    }
+}
+
+fn back_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<BackButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::PauseMenu);
+        }
+    }
+}
+
+fn escape_key(mut gui_state: ResMut<NextState<GuiState>>, input: Res<ButtonInput<KeyCode>>) {
+    if input.just_pressed(KeyCode::Escape) {
+        gui_state.set(GuiState::PauseMenu);
+    }
+}
@@ -2,7 +2,15 @@ use bevy::prelude::*;
 use fmc_protocol::messages;
 
 use super::{GuiState, Interface, Interfaces};
-use crate::{assets::AssetState, game_state::GameState, networking::NetworkClient, ui::widgets::*};
+use crate::{
+    assets::AssetState,
+    game_state::GameState,
+    networking::{
+        self, AssetDownloadProgress, NetworkClient, PendingTransfer, Reconnecting,
+        MAX_RECONNECT_ATTEMPTS,
+    },
+    ui::widgets::*,
+};
 
 // TODO: I think this looks better as an event architecture. You have something you want to
 // show in the connection ui -> you send an event with the string you want shown -> the ui is
@@ -16,9 +24,15 @@ impl Plugin for ConnectingPlugin {
                 Update,
                 (
                     press_cancel.run_if(in_state(GuiState::Connecting)),
-                    downloading_assets_text.run_if(resource_added::<messages::ServerConfig>),
                     (disconnect_text, show_when_disconnected_for_reason)
                         .run_if(on_event::<messages::Disconnect>),
+                    reconnecting_text
+                        .after(disconnect_text)
+                        .run_if(resource_exists::<Reconnecting>),
+                    downloading_assets_text
+                        .after(reconnecting_text)
+                        .run_if(resource_exists::<AssetDownloadProgress>),
+                    hide_progress_bar.run_if(resource_removed::<AssetDownloadProgress>),
                 ),
             )
             .add_systems(OnEnter(GameState::Connecting), show_when_connecting)
@@ -33,6 +47,13 @@ struct CancelButton;
 #[derive(Component)]
 struct StatusText;
 
+// Shows how much of the assets have been downloaded, hidden when nothing is being downloaded.
+#[derive(Component)]
+struct ProgressBar;
+
+#[derive(Component)]
+struct ProgressBarFill;
+
 fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
     let entity = commands
         .spawn((
@@ -62,6 +83,28 @@ fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
                         .spawn_text("Connecting to server...")
                         .insert(StatusText);
                 });
+            parent
+                .spawn((
+                    ProgressBar,
+                    Node {
+                        width: Val::Px(400.0),
+                        height: Val::Px(8.0),
+                        display: Display::None,
+                        ..default()
+                    },
+                    BackgroundColor::from(Color::srgb_u8(66, 66, 66)),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        ProgressBarFill,
+                        Node {
+                            width: Val::Percent(0.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        BackgroundColor::from(Color::srgb_u8(220, 220, 220)),
+                    ));
+                });
             parent.spawn_button(200.0, "Cancel").insert(CancelButton);
         })
         .id();
@@ -69,23 +112,53 @@ fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
 }
 
 fn press_cancel(
+    mut commands: Commands,
     net: Res<NetworkClient>,
     mut game_state: ResMut<NextState<GuiState>>,
     button_query: Query<&Interaction, (Changed<Interaction>, With<CancelButton>)>,
 ) {
     if let Ok(interaction) = button_query.get_single() {
         if *interaction == Interaction::Pressed {
+            // Stops it from trying to reconnect while waiting for the next attempt, or from
+            // connecting to the server it is being transferred to.
+            commands.remove_resource::<Reconnecting>();
+            commands.remove_resource::<PendingTransfer>();
             net.disconnect("");
             game_state.set(GuiState::MainMenu);
         }
     }
 }
 
-// TODO: Needs to display progress, but there's no visibility into it at the moment it's a Local
-// over in 'src/networking.rs'.
-fn downloading_assets_text(mut status_text: Query<&mut Text, With<StatusText>>) {
+fn downloading_assets_text(
+    progress: Res<AssetDownloadProgress>,
+    mut status_text: Query<&mut Text, With<StatusText>>,
+    mut progress_bar: Query<&mut Node, (With<ProgressBar>, Without<ProgressBarFill>)>,
+    mut progress_bar_fill: Query<&mut Node, With<ProgressBarFill>>,
+) {
+    const MEGABYTE: f32 = 1024.0 * 1024.0;
+
     let mut text = status_text.single_mut();
-    *text = Text::new("Downloading assets...");
+    let fraction = if progress.unpacking {
+        *text = Text::new("Unpacking assets...");
+        1.0
+    } else if progress.total == 0 {
+        *text = Text::new("Downloading assets...");
+        0.0
+    } else {
+        *text = Text::new(format!(
+            "Downloading assets... {:.1}/{:.1} MB",
+            progress.downloaded as f32 / MEGABYTE,
+            progress.total as f32 / MEGABYTE
+        ));
+        progress.downloaded as f32 / progress.total as f32
+    };
+
+    progress_bar.single_mut().display = Display::Flex;
+    progress_bar_fill.single_mut().width = Val::Percent(fraction * 100.0);
+}
+
+fn hide_progress_bar(mut progress_bar: Query<&mut Node, With<ProgressBar>>) {
+    progress_bar.single_mut().display = Display::None;
 }
 
 fn loading_assets_text(mut status_text: Query<&mut Text, With<StatusText>>) {
@@ -99,7 +172,33 @@ fn disconnect_text(
 ) {
     for disconnect_event in disconnect_events.read() {
         let mut text = status_text.single_mut();
-        *text = Text::new(&disconnect_event.message);
+        if networking::is_transfer(&disconnect_event.message) {
+            *text = Text::new("Transferring to another server...");
+        } else {
+            *text = Text::new(networking::disconnect_reason(&disconnect_event.message));
+        }
+    }
+}
+
+fn reconnecting_text(
+    game_state: Res<State<GameState>>,
+    reconnecting: Res<Reconnecting>,
+    mut status_text: Query<&mut Text, With<StatusText>>,
+) {
+    let mut text = status_text.single_mut();
+    if *game_state.get() == GameState::Connecting {
+        *text = Text::new(format!(
+            "Reconnecting, attempt {}/{}...",
+            reconnecting.attempt, MAX_RECONNECT_ATTEMPTS
+        ));
+    } else {
+        *text = Text::new(format!(
+            "Lost connection to the server: {}\nReconnecting in {:.0} seconds, attempt {}/{}",
+            reconnecting.reason,
+            reconnecting.timer.remaining_secs().ceil(),
+            reconnecting.attempt,
+            MAX_RECONNECT_ATTEMPTS
+        ));
     }
 }
 
@@ -0,0 +1,216 @@
+use bevy::{color::palettes::css::DARK_GRAY, prelude::*};
+
+use super::{set_button_label, GuiState, Interface, Interfaces};
+use crate::{rendering::diagnostics::RendererInfo, settings::Settings, ui::widgets::*};
+
+// Opened from the pause menu, returns to it when closed.
+pub struct GraphicsPlugin;
+impl Plugin for GraphicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                blob_shadows_button,
+                ambient_occlusion_button,
+                sun_shadows_button,
+                water_quality_button,
+                particle_collision_button,
+                back_button,
+                escape_key,
+            )
+                .run_if(in_state(GuiState::Graphics)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct BlobShadowsButton;
+
+#[derive(Component)]
+struct AmbientOcclusionButton;
+
+#[derive(Component)]
+struct SunShadowsButton;
+
+#[derive(Component)]
+struct WaterQualityButton;
+
+#[derive(Component)]
+struct ParticleCollisionButton;
+
+#[derive(Component)]
+struct BackButton;
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        return "On";
+    } else {
+        return "Off";
+    }
+}
+
+fn blob_shadows_label(settings: &Settings) -> String {
+    return format!("Shadows: {}", on_off(settings.blob_shadows));
+}
+
+fn ambient_occlusion_label(settings: &Settings) -> String {
+    return format!("Ambient occlusion: {}", on_off(settings.ambient_occlusion));
+}
+
+fn sun_shadows_label(settings: &Settings) -> String {
+    return format!("Sun shadows: {}", settings.sun_shadows.name());
+}
+
+fn water_quality_label(settings: &Settings) -> String {
+    return format!("Water: {}", settings.water_quality.name());
+}
+
+fn particle_collision_label(settings: &Settings) -> String {
+    return format!(
+        "Particle collision: {}",
+        on_off(settings.particle_collision)
+    );
+}
+
+fn setup(mut commands: Commands, settings: Res<Settings>, mut interfaces: ResMut<Interfaces>) {
+    let entity = commands
+        .spawn((
+            Interface,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                row_gap: Val::Px(4.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor::from(DARK_GRAY.with_alpha(0.5)),
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn_button(200.0, &blob_shadows_label(&settings))
+                .insert(BlobShadowsButton);
+            parent
+                .spawn_button(200.0, &ambient_occlusion_label(&settings))
+                .insert(AmbientOcclusionButton);
+            parent
+                .spawn_button(200.0, &sun_shadows_label(&settings))
+                .insert(SunShadowsButton);
+            parent
+                .spawn_button(200.0, &water_quality_label(&settings))
+                .insert(WaterQualityButton);
+            parent
+                .spawn_button(200.0, &particle_collision_label(&settings))
+                .insert(ParticleCollisionButton);
+            parent.spawn_button(200.0, "Back").insert(BackButton);
+        })
+        .id();
+    interfaces.insert(GuiState::Graphics, entity);
+}
+
+fn blob_shadows_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<BlobShadowsButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.blob_shadows = !settings.blob_shadows;
+            set_button_label(children, &mut text_query, blob_shadows_label(&settings));
+        }
+    }
+}
+
+// The effects that need the depth prepass can't be turned on when the gpu can't handle them, see
+// RendererInfo::fallbacks
+fn ambient_occlusion_button(
+    renderer_info: Res<RendererInfo>,
+    mut settings: ResMut<Settings>,
+    button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<AmbientOcclusionButton>),
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed && !renderer_info.fallbacks.simple_shaders {
+            settings.ambient_occlusion = !settings.ambient_occlusion;
+            set_button_label(
+                children,
+                &mut text_query,
+                ambient_occlusion_label(&settings),
+            );
+        }
+    }
+}
+
+fn sun_shadows_button(
+    renderer_info: Res<RendererInfo>,
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<SunShadowsButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed && !renderer_info.fallbacks.simple_shaders {
+            settings.sun_shadows = settings.sun_shadows.next();
+            set_button_label(children, &mut text_query, sun_shadows_label(&settings));
+        }
+    }
+}
+
+fn water_quality_button(
+    renderer_info: Res<RendererInfo>,
+    mut settings: ResMut<Settings>,
+    button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<WaterQualityButton>),
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed && !renderer_info.fallbacks.simple_shaders {
+            settings.water_quality = settings.water_quality.next();
+            set_button_label(children, &mut text_query, water_quality_label(&settings));
+        }
+    }
+}
+
+fn particle_collision_button(
+    renderer_info: Res<RendererInfo>,
+    mut settings: ResMut<Settings>,
+    button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<ParticleCollisionButton>),
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed && !renderer_info.fallbacks.simple_shaders {
+            settings.particle_collision = !settings.particle_collision;
+            set_button_label(
+                children,
+                &mut text_query,
+                particle_collision_label(&settings),
+            );
+        }
+    }
+}
+
+fn back_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<BackButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::PauseMenu);
+        }
+    }
+}
+
+fn escape_key(mut gui_state: ResMut<NextState<GuiState>>, input: Res<ButtonInput<KeyCode>>) {
+    if input.just_pressed(KeyCode::Escape) {
+        gui_state.set(GuiState::PauseMenu);
+    }
+}
@@ -1,13 +1,16 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+use crossbeam::{Receiver, Sender};
 
 use super::{GuiState, Interface, Interfaces};
-use crate::{networking::Identity, ui::widgets::*};
+use crate::{networking::Identity, settings::Settings, ui::widgets::*};
 
 pub struct LoginPlugin;
 impl Plugin for LoginPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, interface_setup)
-            .add_systems(Update, (press_play.run_if(in_state(GuiState::Login)),));
+        app.add_systems(Startup, interface_setup).add_systems(
+            Update,
+            (press_play, handle_login_response).run_if(in_state(GuiState::Login)),
+        );
     }
 }
 
@@ -17,7 +20,28 @@ struct LoginButton;
 #[derive(Component)]
 struct Username;
 
-fn interface_setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
+#[derive(Component)]
+struct Password;
+
+#[derive(Component)]
+struct LoginStatusText;
+
+enum LoginStatus {
+    Success { token: String },
+    Failure(String),
+}
+
+#[derive(Component)]
+struct LoginReporter {
+    username: String,
+    receiver: Receiver<LoginStatus>,
+}
+
+fn interface_setup(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut interfaces: ResMut<Interfaces>,
+) {
     let entity = commands
         .spawn((
             Interface,
@@ -45,16 +69,49 @@ fn interface_setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
                     parent.spawn_text("Enter username:");
                 });
             parent.spawn_textbox(200.0, "").insert(Username);
+
+            // Without an account service there's nothing to log in to, the username is all that's
+            // needed to play on servers in offline mode.
+            if settings.account_service.is_some() {
+                parent
+                    .spawn(Node {
+                        width: Val::Percent(100.0),
+                        height: Val::Px(12.0),
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn_text("Enter password:");
+                    });
+                parent
+                    .spawn_textbox(200.0, "")
+                    .insert((Password, MaskedTextBox));
+            }
+
             parent.spawn_button(200.0, "Play").insert(LoginButton);
+            parent
+                .spawn(Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Px(12.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn_text("").insert(LoginStatusText);
+                });
         })
         .id();
     interfaces.insert(GuiState::Login, entity);
 }
 
 fn press_play(
+    mut commands: Commands,
     mut ui_state: ResMut<NextState<GuiState>>,
+    settings: Res<Settings>,
     mut identity: ResMut<Identity>,
     username: Query<&TextBox, With<Username>>,
+    password: Query<&TextBox, With<Password>>,
+    login_tasks: Query<(), With<LoginReporter>>,
     button_query: Query<&Interaction, (Changed<Interaction>, With<LoginButton>)>,
 ) {
     if let Ok(interaction) = button_query.get_single() {
@@ -68,10 +125,106 @@ fn press_play(
             return;
         }
 
-        identity.username = username.clone();
+        let Some(url) = settings.account_service.clone() else {
+            identity.username = username.clone();
+            identity.token = None;
+            identity.save();
 
-        std::fs::write("./identity.txt", &identity.username).ok();
+            ui_state.set(GuiState::MainMenu);
+            return;
+        };
 
-        ui_state.set(GuiState::MainMenu);
+        // Wait for the previous attempt to finish
+        if !login_tasks.is_empty() {
+            return;
+        }
+
+        let password = password.single().text.clone();
+
+        let (sender, receiver) = crossbeam::unbounded();
+        commands.spawn(LoginReporter {
+            username: username.clone(),
+            receiver,
+        });
+
+        AsyncComputeTaskPool::get()
+            .spawn(request_session_token(
+                url,
+                username.clone(),
+                password,
+                sender,
+            ))
+            .detach();
+    }
+}
+
+fn handle_login_response(
+    mut commands: Commands,
+    mut ui_state: ResMut<NextState<GuiState>>,
+    mut identity: ResMut<Identity>,
+    mut status_text: Query<&mut Text, With<LoginStatusText>>,
+    login_tasks: Query<(Entity, &LoginReporter)>,
+) {
+    for (entity, reporter) in login_tasks.iter() {
+        let Ok(status) = reporter.receiver.try_recv() else {
+            continue;
+        };
+
+        commands.entity(entity).despawn();
+
+        match status {
+            LoginStatus::Success { token } => {
+                identity.username = reporter.username.clone();
+                identity.token = Some(token);
+                identity.save();
+
+                status_text.single_mut().0.clear();
+                ui_state.set(GuiState::MainMenu);
+            }
+            LoginStatus::Failure(reason) => {
+                status_text.single_mut().0 = reason;
+            }
+        }
     }
 }
+
+// The account service is expected to respond to a json object with the username and password
+// with a json object containing the signed session token, {"token": "..."}. The session token
+// is only ever sent back to the account service, see `networking::request_join_token`.
+async fn request_session_token(
+    url: String,
+    username: String,
+    password: String,
+    reporter: Sender<LoginStatus>,
+) {
+    let body = serde_json::json!({
+        "username": username,
+        "password": password,
+    });
+
+    let status = match ureq::post(&format!("{}/login", url))
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+    {
+        Ok(response) => {
+            let token = response
+                .into_string()
+                .ok()
+                .and_then(|body| serde_json::from_str::<serde_json::Value>(&body).ok())
+                .and_then(|json| json["token"].as_str().map(|token| token.to_owned()));
+
+            match token {
+                Some(token) => LoginStatus::Success { token },
+                None => {
+                    LoginStatus::Failure("The account service sent an invalid response".to_owned())
+                }
+            }
+        }
+        Err(ureq::Error::Status(401 | 403, _)) => {
+            LoginStatus::Failure("Wrong username or password".to_owned())
+        }
+        Err(_) => LoginStatus::Failure("Could not reach the account service".to_owned()),
+    };
+
+    reporter.send(status).ok();
+}
@@ -21,6 +21,7 @@ impl Plugin for MainMenuPlugin {
                     press_singleplayer_button,
                     press_join_button,
                     goto_login,
+                    press_renderer_info_button,
                     download_progress_text,
                 )
                     .run_if(in_state(GuiState::MainMenu)),
@@ -37,6 +38,9 @@ struct ServerIp;
 #[derive(Component)]
 struct JoinButton;
 
+#[derive(Component)]
+struct RendererInfoButton;
+
 fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
     let entity = commands
         .spawn((
@@ -73,6 +77,9 @@ fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
 
             parent.spawn_textbox(200.0, "127.0.0.1").insert(ServerIp);
             parent.spawn_button(200.0, "Connect").insert(JoinButton);
+            parent
+                .spawn_button(200.0, "Renderer info")
+                .insert(RendererInfoButton);
         })
         .id();
     interfaces.insert(GuiState::MainMenu, entity);
@@ -118,6 +125,17 @@ fn press_join_button(
     }
 }
 
+fn press_renderer_info_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<RendererInfoButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::RendererInfo);
+        }
+    }
+}
+
 fn goto_login(identity: Res<Identity>, mut gui_state: ResMut<NextState<GuiState>>) {
     if !identity.is_valid() {
         gui_state.set(GuiState::Login);
@@ -2,11 +2,15 @@ use std::collections::HashMap;
 
 use bevy::{asset::embedded_asset, prelude::*, ui::FocusPolicy};
 
+mod accessibility;
+mod chat_settings;
 mod connecting;
+mod graphics;
 mod login;
 mod main_menu;
 mod multiplayer;
 mod pause_menu;
+mod renderer_info;
 
 pub struct GuiPlugin;
 impl Plugin for GuiPlugin {
@@ -18,6 +22,10 @@ impl Plugin for GuiPlugin {
                 main_menu::MainMenuPlugin,
                 connecting::ConnectingPlugin,
                 pause_menu::PauseMenuPlugin,
+                accessibility::AccessibilityPlugin,
+                chat_settings::ChatSettingsPlugin,
+                graphics::GraphicsPlugin,
+                renderer_info::RendererInfoPlugin,
             ))
             .add_systems(Startup, setup)
             .add_systems(Update, change_interface.run_if(state_changed::<GuiState>));
@@ -53,6 +61,10 @@ pub(super) enum GuiState {
     MainMenu,
     Connecting,
     PauseMenu,
+    Accessibility,
+    ChatSettings,
+    Graphics,
+    RendererInfo,
 }
 
 // To link the GuiState to the entity holding the layout it must be registered here.
@@ -77,3 +89,13 @@ fn change_interface(
         }
     }
 }
+
+// The label is the button's text child
+fn set_button_label(children: &Children, text_query: &mut Query<&mut Text>, label: String) {
+    for child in children.iter() {
+        if let Ok(mut text) = text_query.get_mut(*child) {
+            text.0 = label;
+            return;
+        }
+    }
+}
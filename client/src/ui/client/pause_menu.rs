@@ -1,7 +1,7 @@
 use bevy::{color::palettes::css::DARK_GRAY, prelude::*, window::WindowFocused};
 
-use super::{GuiState, Interface, Interfaces};
-use crate::{game_state::GameState, networking::NetworkClient, ui::widgets::*};
+use super::{set_button_label, GuiState, Interface, Interfaces};
+use crate::{game_state::GameState, networking::NetworkClient, settings::Settings, ui::widgets::*};
 
 pub struct PauseMenuPlugin;
 impl Plugin for PauseMenuPlugin {
@@ -9,7 +9,17 @@ impl Plugin for PauseMenuPlugin {
         app.add_systems(Startup, setup).add_systems(
             Update,
             (
-                (resume_button, quit_button, escape_key).run_if(in_state(GuiState::PauseMenu)),
+                (
+                    resume_button,
+                    accessibility_button,
+                    chat_settings_button,
+                    graphics_button,
+                    music_volume_button,
+                    music_shuffle_button,
+                    quit_button,
+                    escape_key,
+                )
+                    .run_if(in_state(GuiState::PauseMenu)),
                 (pause_when_unfocused).run_if(in_state(GameState::Playing)),
             ),
         );
@@ -22,7 +32,34 @@ struct ResumeButton;
 #[derive(Component)]
 struct QuitButton;
 
-fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
+#[derive(Component)]
+struct AccessibilityButton;
+
+#[derive(Component)]
+struct ChatSettingsButton;
+
+#[derive(Component)]
+struct GraphicsButton;
+
+#[derive(Component)]
+struct MusicVolumeButton;
+
+#[derive(Component)]
+struct MusicShuffleButton;
+
+fn music_volume_label(settings: &Settings) -> String {
+    return format!("Music: {}%", (settings.music_volume * 100.0).round());
+}
+
+fn music_shuffle_label(settings: &Settings) -> String {
+    if settings.music_shuffle {
+        return "Shuffle: On".to_owned();
+    } else {
+        return "Shuffle: Off".to_owned();
+    }
+}
+
+fn setup(mut commands: Commands, settings: Res<Settings>, mut interfaces: ResMut<Interfaces>) {
     let entity = commands
         .spawn((
             Interface,
@@ -40,6 +77,21 @@ fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
         ))
         .with_children(|parent| {
             parent.spawn_button(200.0, "Resume").insert(ResumeButton);
+            parent
+                .spawn_button(200.0, &music_volume_label(&settings))
+                .insert(MusicVolumeButton);
+            parent
+                .spawn_button(200.0, &music_shuffle_label(&settings))
+                .insert(MusicShuffleButton);
+            parent
+                .spawn_button(200.0, "Accessibility")
+                .insert(AccessibilityButton);
+            parent
+                .spawn_button(200.0, "Chat")
+                .insert(ChatSettingsButton);
+            parent
+                .spawn_button(200.0, "Graphics")
+                .insert(GraphicsButton);
             parent.spawn_button(200.0, "Quit").insert(QuitButton);
         })
         .id();
@@ -59,6 +111,40 @@ fn quit_button(
     }
 }
 
+// Steps through the volumes in quarters, from muted back to full.
+fn music_volume_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<MusicVolumeButton>)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.music_volume = if settings.music_volume >= 1.0 {
+                0.0
+            } else {
+                (settings.music_volume + 0.25).min(1.0)
+            };
+            set_button_label(children, &mut text_query, music_volume_label(&settings));
+        }
+    }
+}
+
+fn music_shuffle_button(
+    mut settings: ResMut<Settings>,
+    button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<MusicShuffleButton>),
+    >,
+    mut text_query: Query<&mut Text>,
+) {
+    if let Ok((interaction, children)) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            settings.music_shuffle = !settings.music_shuffle;
+            set_button_label(children, &mut text_query, music_shuffle_label(&settings));
+        }
+    }
+}
+
 fn resume_button(
     mut gui_state: ResMut<NextState<GuiState>>,
     button_query: Query<&Interaction, (Changed<Interaction>, With<ResumeButton>)>,
@@ -70,6 +156,39 @@ fn resume_button(
     }
 }
 
+fn accessibility_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<AccessibilityButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::Accessibility);
+        }
+    }
+}
+
+fn chat_settings_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<ChatSettingsButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::ChatSettings);
+        }
+    }
+}
+
+fn graphics_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<GraphicsButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::Graphics);
+        }
+    }
+}
+
 fn escape_key(
     gui_state: Res<State<GuiState>>,
     mut next_gui_state: ResMut<NextState<GuiState>>,
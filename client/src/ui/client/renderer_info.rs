@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+
+use super::{GuiState, Interface, Interfaces};
+use crate::{rendering::diagnostics::RendererInfo, ui::widgets::*};
+
+// Shows what the renderer runs on and which fallbacks it uses. Opened from the main menu, returns
+// to it when closed.
+pub struct RendererInfoPlugin;
+impl Plugin for RendererInfoPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                update_text.run_if(resource_changed::<RendererInfo>),
+                (back_button, escape_key).run_if(in_state(GuiState::RendererInfo)),
+            ),
+        );
+    }
+}
+
+#[derive(Component)]
+struct InfoText;
+
+#[derive(Component)]
+struct BackButton;
+
+fn setup(mut commands: Commands, mut interfaces: ResMut<Interfaces>) {
+    let entity = commands
+        .spawn((
+            Interface,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                row_gap: Val::Px(4.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor::from(Color::srgb_u8(33, 33, 33)),
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(Node {
+                    width: Val::Percent(80.0),
+                    margin: UiRect::bottom(Val::Px(20.0)),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    // The text is absolutely positioned, it is moved back into the layout so
+                    // that the lines take up space.
+                    parent.spawn_text("").insert((InfoText, Node::default()));
+                });
+            parent.spawn_button(200.0, "Back").insert(BackButton);
+        })
+        .id();
+    interfaces.insert(GuiState::RendererInfo, entity);
+}
+
+fn update_text(renderer_info: Res<RendererInfo>, mut text_query: Query<&mut Text, With<InfoText>>) {
+    for mut text in text_query.iter_mut() {
+        text.0 = renderer_info.describe().join("\n");
+    }
+}
+
+fn back_button(
+    mut gui_state: ResMut<NextState<GuiState>>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<BackButton>)>,
+) {
+    if let Ok(interaction) = button_query.get_single() {
+        if *interaction == Interaction::Pressed {
+            gui_state.set(GuiState::MainMenu);
+        }
+    }
+}
+
+fn escape_key(mut gui_state: ResMut<NextState<GuiState>>, input: Res<ButtonInput<KeyCode>>) {
+    if input.just_pressed(KeyCode::Escape) {
+        gui_state.set(GuiState::MainMenu);
+    }
+}
@@ -0,0 +1,306 @@
+use bevy::{
+    diagnostic::{
+        DiagnosticPath, DiagnosticsStore, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin,
+        SystemInformationDiagnosticsPlugin,
+    },
+    prelude::*,
+};
+
+use crate::{
+    game_state::GameState,
+    networking::{self, Latency},
+    player::{Head, Player},
+    world::{
+        blocks::Blocks,
+        world_map::{self, WorldMap},
+        Origin,
+    },
+};
+
+use super::widgets::{FocusedTextBox, Widgets};
+
+const TOGGLE_KEY: KeyCode = KeyCode::F3;
+// How far away the targeted block can be
+const TARGET_DISTANCE: f32 = 5.0;
+// One bar for each frame
+const GRAPH_BARS: usize = 120;
+const GRAPH_HEIGHT: f32 = 30.0;
+// Frame time at the top of the graph, in milliseconds. Slower frames are cut off.
+const GRAPH_MAX_FRAME_TIME: f64 = 50.0;
+
+// Information for finding out why the game runs slowly, or where you are. Everything that is
+// measured over time comes from the diagnostics, so it can also be logged with bevy's
+// LogDiagnosticsPlugin.
+pub struct DebugOverlayPlugin;
+impl Plugin for DebugOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            FrameTimeDiagnosticsPlugin,
+            EntityCountDiagnosticsPlugin,
+            SystemInformationDiagnosticsPlugin,
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(OnExit(GameState::Playing), hide_overlay)
+        .add_systems(
+            Update,
+            (
+                toggle_overlay,
+                (update_text, update_graph).run_if(overlay_visible),
+            )
+                .chain()
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct DebugOverlay;
+
+#[derive(Component)]
+struct DebugText;
+
+// The bars of the frame time graph, by how many frames ago they are, oldest first.
+#[derive(Component)]
+struct GraphBar(usize);
+
+fn setup(mut commands: Commands) {
+    commands
+        .spawn((
+            DebugOverlay,
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(4.0),
+                left: Val::Px(4.0),
+                padding: UiRect::all(Val::Px(2.0)),
+                row_gap: Val::Px(2.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            BackgroundColor::from(Color::srgba(0.0, 0.0, 0.0, 0.4)),
+            Visibility::Hidden,
+            // Above the hud
+            GlobalZIndex(1),
+        ))
+        .with_children(|parent| {
+            // The text is absolutely positioned, it is moved back into the layout so that the
+            // lines take up space.
+            parent.spawn_text("").insert((DebugText, Node::default()));
+
+            parent
+                .spawn(Node {
+                    height: Val::Px(GRAPH_HEIGHT),
+                    align_items: AlignItems::End,
+                    ..default()
+                })
+                .with_children(|graph| {
+                    for i in 0..GRAPH_BARS {
+                        graph.spawn((
+                            GraphBar(i),
+                            Node {
+                                width: Val::Px(0.5),
+                                height: Val::Px(0.0),
+                                ..default()
+                            },
+                            BackgroundColor::from(Color::WHITE),
+                        ));
+                    }
+                });
+        });
+}
+
+fn overlay_visible(overlay_query: Query<&Visibility, With<DebugOverlay>>) -> bool {
+    return overlay_query
+        .get_single()
+        .is_ok_and(|visibility| *visibility != Visibility::Hidden);
+}
+
+fn toggle_overlay(
+    keys: Res<ButtonInput<KeyCode>>,
+    focused_text_box: Query<(), With<FocusedTextBox>>,
+    mut overlay_query: Query<&mut Visibility, With<DebugOverlay>>,
+) {
+    // Typing in a text box
+    if !focused_text_box.is_empty() {
+        return;
+    }
+
+    if keys.just_pressed(TOGGLE_KEY) {
+        overlay_query.single_mut().toggle_visible_hidden();
+    }
+}
+
+fn hide_overlay(mut overlay_query: Query<&mut Visibility, With<DebugOverlay>>) {
+    *overlay_query.single_mut() = Visibility::Hidden;
+}
+
+fn update_text(
+    diagnostics: Res<DiagnosticsStore>,
+    latency: Res<Latency>,
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    head_query: Query<&GlobalTransform, With<Head>>,
+    mut text_query: Query<&mut Text, With<DebugText>>,
+) {
+    let (Ok(player_transform), Ok(head_transform)) =
+        (player_query.get_single(), head_query.get_single())
+    else {
+        return;
+    };
+
+    let measurement = |path: &DiagnosticPath| -> Option<f64> {
+        return diagnostics
+            .get(path)
+            .and_then(|diagnostic| diagnostic.smoothed());
+    };
+    let display = |value: Option<f64>, precision: usize| -> String {
+        return value
+            .map(|value| format!("{:.*}", precision, value))
+            .unwrap_or("-".to_owned());
+    };
+
+    let mut lines = Vec::new();
+
+    lines.push(format!(
+        "FPS: {} ({} ms)",
+        display(measurement(&FrameTimeDiagnosticsPlugin::FPS), 0),
+        display(measurement(&FrameTimeDiagnosticsPlugin::FRAME_TIME), 2)
+    ));
+
+    let position = origin.to_global(player_transform.translation());
+    let block_position = position.floor().as_ivec3();
+    let chunk_position = crate::utils::world_position_to_chunk_pos(block_position);
+    lines.push(format!(
+        "Position: {:.2} / {:.2} / {:.2}",
+        position.x, position.y, position.z
+    ));
+    lines.push(format!(
+        "Block: {} {} {}, in chunk: {} {} {}",
+        block_position.x,
+        block_position.y,
+        block_position.z,
+        chunk_position.x,
+        chunk_position.y,
+        chunk_position.z
+    ));
+
+    let (yaw, pitch, _) = head_transform
+        .compute_transform()
+        .rotation
+        .to_euler(EulerRot::YXZ);
+    lines.push(format!(
+        "Facing: {} (yaw {:.1}, pitch {:.1})",
+        facing(*head_transform.forward()),
+        yaw.to_degrees(),
+        pitch.to_degrees()
+    ));
+
+    match world_map.raycast_to_block(
+        &head_transform.compute_transform(),
+        origin.0,
+        TARGET_DISTANCE,
+    ) {
+        Some((block_position, block_id, block_face)) => {
+            let block_state = world_map
+                .get_block_state(&block_position)
+                .map(|state| format!("{:#06b}", state.0))
+                .unwrap_or("-".to_owned());
+            lines.push(format!(
+                "Targeted block: {} (id {}) at {} {} {}, state: {}, face: {:?}",
+                Blocks::get().get_config(block_id).name(),
+                block_id,
+                block_position.x,
+                block_position.y,
+                block_position.z,
+                block_state,
+                block_face
+            ));
+        }
+        None => lines.push("Targeted block: -".to_owned()),
+    }
+
+    lines.push(format!(
+        "Chunks: {}, entities: {}",
+        display(measurement(&world_map::LOADED_CHUNKS), 0),
+        display(measurement(&EntityCountDiagnosticsPlugin::ENTITY_COUNT), 0)
+    ));
+
+    let kilobytes = |path: &DiagnosticPath| measurement(path).map(|bytes| bytes / 1000.0);
+    lines.push(format!(
+        "Network: {} ms ping (jitter {} ms), {} KB/s in, {} KB/s out",
+        display(measurement(&networking::RTT), 0),
+        display(
+            measurement(&networking::RTT).map(|_| latency.jitter * 1000.0),
+            0
+        ),
+        display(kilobytes(&networking::BYTES_RECEIVED), 1),
+        display(kilobytes(&networking::BYTES_SENT), 1)
+    ));
+
+    lines.push(format!(
+        "Memory: {} GiB, system: {}%",
+        display(
+            measurement(&SystemInformationDiagnosticsPlugin::PROCESS_MEM_USAGE),
+            2
+        ),
+        display(
+            measurement(&SystemInformationDiagnosticsPlugin::SYSTEM_MEM_USAGE),
+            0
+        )
+    ));
+
+    for mut text in text_query.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
+
+// The horizontal direction the camera faces, -z is north like on the map.
+fn facing(forward: Vec3) -> &'static str {
+    if forward.x.abs() > forward.z.abs() {
+        if forward.x > 0.0 {
+            "east (+x)"
+        } else {
+            "west (-x)"
+        }
+    } else if forward.z > 0.0 {
+        "south (+z)"
+    } else {
+        "north (-z)"
+    }
+}
+
+fn update_graph(
+    diagnostics: Res<DiagnosticsStore>,
+    mut bar_query: Query<(&GraphBar, &mut Node, &mut BackgroundColor)>,
+) {
+    let Some(frame_times) = diagnostics.get(&FrameTimeDiagnosticsPlugin::FRAME_TIME) else {
+        return;
+    };
+
+    let frame_times: Vec<f64> = frame_times.values().copied().collect();
+    // Aligned to the right so the newest frame is always at the end
+    let offset = GRAPH_BARS.saturating_sub(frame_times.len());
+    let skipped = frame_times.len().saturating_sub(GRAPH_BARS);
+
+    for (bar, mut node, mut color) in bar_query.iter_mut() {
+        let Some(frame_time) = bar
+            .0
+            .checked_sub(offset)
+            .and_then(|index| frame_times.get(index + skipped))
+        else {
+            node.height = Val::Px(0.0);
+            continue;
+        };
+
+        let fraction = (frame_time / GRAPH_MAX_FRAME_TIME).min(1.0) as f32;
+        node.height = Val::Px(fraction * GRAPH_HEIGHT);
+        // Green at 60 fps and above, yellow down to 30 and red below that.
+        color.0 = if *frame_time <= 1000.0 / 60.0 {
+            Color::srgb(0.3, 0.9, 0.3)
+        } else if *frame_time <= 1000.0 / 30.0 {
+            Color::srgb(0.9, 0.9, 0.3)
+        } else {
+            Color::srgb(0.9, 0.3, 0.3)
+        };
+    }
+}
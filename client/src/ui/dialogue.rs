@@ -0,0 +1,303 @@
+use bevy::prelude::*;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, NetworkClient, ServerProperty},
+    settings::Settings,
+};
+
+use super::{
+    widgets::{FocusedTextBox, TextShadow},
+    CursorVisibility, UiState, DEFAULT_FONT_HANDLE,
+};
+
+// TODO: There is no message for dialogues, until there is they are sent as properties. The
+// server sends "dialogue" with the current node, and null to close it. The client answers with
+// "dialogue_option" and the index of the chosen option, or "dialogue_close" when the player
+// leaves.
+//
+// Conversations with npcs. The text is shown at the bottom of the screen with the options below
+// it, they can be chosen by clicking them, with the number keys, or by moving between them with
+// the arrow keys and pressing enter.
+pub struct DialoguePlugin;
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnExit(GameState::Playing), close_dialogue)
+            .add_systems(
+                Update,
+                (
+                    handle_dialogue_properties.run_if(on_event::<ServerProperty>),
+                    (keyboard_selection, mouse_selection, highlight_selected)
+                        .chain()
+                        .run_if(in_state(UiState::ServerInterfaces)),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+const OPTION_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.0);
+const SELECTED_OPTION_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.2);
+const NUMBER_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+#[derive(Component)]
+struct DialogueBox {
+    option_count: usize,
+    // The server sent no options, a single option to close the dialogue is shown instead.
+    only_close: bool,
+    selected: usize,
+}
+
+#[derive(Component)]
+struct DialogueOption(usize);
+
+fn close_dialogue(
+    mut commands: Commands,
+    mut cursor_visibility: ResMut<CursorVisibility>,
+    dialogue_query: Query<Entity, With<DialogueBox>>,
+) {
+    for entity in dialogue_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    cursor_visibility.dialogue = false;
+}
+
+fn handle_dialogue_properties(
+    mut commands: Commands,
+    net: Res<NetworkClient>,
+    settings: Res<Settings>,
+    mut cursor_visibility: ResMut<CursorVisibility>,
+    dialogue_query: Query<Entity, With<DialogueBox>>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::DIALOGUE {
+            continue;
+        }
+
+        for entity in dialogue_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        let node = match serde_json::from_str::<Option<properties::Dialogue>>(&property.value) {
+            Ok(Some(node)) => node,
+            Ok(None) => {
+                cursor_visibility.dialogue = false;
+                continue;
+            }
+            Err(e) => {
+                net.disconnect(format!(
+                    "Server sent a dialogue that could not be read: {}",
+                    e
+                ));
+                return;
+            }
+        };
+
+        let only_close = node.options.is_empty();
+        let options = if only_close {
+            vec!["Close".to_owned()]
+        } else {
+            node.options
+        };
+
+        let background_color = settings.text_background(Color::srgba_u8(33, 33, 33, 220));
+        let speaker_color = settings.color_palette.adjust(Color::srgb_u8(255, 255, 85));
+        let font = TextFont {
+            font: DEFAULT_FONT_HANDLE,
+            font_size: 7.0,
+            ..default()
+        };
+
+        commands
+            .spawn((
+                DialogueBox {
+                    option_count: options.len(),
+                    only_close,
+                    selected: 0,
+                },
+                Node {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(40.0),
+                    width: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+            ))
+            .with_children(|parent| {
+                parent
+                    .spawn((
+                        Node {
+                            width: Val::Px(200.0),
+                            padding: UiRect::all(Val::Px(4.0)),
+                            row_gap: Val::Px(2.0),
+                            flex_direction: FlexDirection::Column,
+                            border: UiRect::all(Val::Px(1.0)),
+                            ..default()
+                        },
+                        BorderColor::from(Color::BLACK),
+                        BackgroundColor(background_color),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            Text::new(node.speaker),
+                            font.clone(),
+                            TextColor(speaker_color),
+                            TextShadow::default(),
+                        ));
+                        parent.spawn((
+                            Text::new(node.text),
+                            font.clone(),
+                            TextShadow::default(),
+                            Node {
+                                margin: UiRect::bottom(Val::Px(2.0)),
+                                ..default()
+                            },
+                        ));
+
+                        for (index, option) in options.into_iter().enumerate() {
+                            parent
+                                .spawn((
+                                    DialogueOption(index),
+                                    Button,
+                                    Node {
+                                        padding: UiRect::horizontal(Val::Px(2.0)),
+                                        ..default()
+                                    },
+                                    BackgroundColor(OPTION_COLOR),
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn((
+                                        Text::new(format!("{}. {}", index + 1, option)),
+                                        font.clone(),
+                                        TextShadow::default(),
+                                    ));
+                                });
+                        }
+                    });
+            });
+
+        cursor_visibility.dialogue = true;
+    }
+}
+
+fn choose_option(
+    commands: &mut Commands,
+    net: &NetworkClient,
+    cursor_visibility: &mut CursorVisibility,
+    dialogue_entity: Entity,
+    dialogue_box: &DialogueBox,
+    index: usize,
+) {
+    if index >= dialogue_box.option_count {
+        return;
+    }
+
+    if dialogue_box.only_close {
+        net.send_property(properties::DIALOGUE_CLOSE, &());
+        commands.entity(dialogue_entity).despawn_recursive();
+        cursor_visibility.dialogue = false;
+    } else {
+        // The box stays until the server answers with the next node.
+        net.send_property(properties::DIALOGUE_OPTION, &index);
+    }
+}
+
+fn keyboard_selection(
+    mut commands: Commands,
+    net: Res<NetworkClient>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut cursor_visibility: ResMut<CursorVisibility>,
+    focused_text_box: Query<(), With<FocusedTextBox>>,
+    mut dialogue_query: Query<(Entity, &mut DialogueBox)>,
+) {
+    // Typing in a text box
+    if !focused_text_box.is_empty() {
+        return;
+    }
+
+    let Ok((dialogue_entity, mut dialogue_box)) = dialogue_query.get_single_mut() else {
+        return;
+    };
+
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        dialogue_box.selected = dialogue_box.selected.saturating_sub(1);
+    } else if keys.just_pressed(KeyCode::ArrowDown) {
+        dialogue_box.selected = (dialogue_box.selected + 1).min(dialogue_box.option_count - 1);
+    }
+
+    let chosen = if keys.just_pressed(KeyCode::Enter) {
+        Some(dialogue_box.selected)
+    } else {
+        NUMBER_KEYS.iter().position(|key| keys.just_pressed(*key))
+    };
+
+    if let Some(index) = chosen {
+        choose_option(
+            &mut commands,
+            &net,
+            &mut cursor_visibility,
+            dialogue_entity,
+            &dialogue_box,
+            index,
+        );
+    }
+}
+
+fn mouse_selection(
+    mut commands: Commands,
+    net: Res<NetworkClient>,
+    mut cursor_visibility: ResMut<CursorVisibility>,
+    mut dialogue_query: Query<(Entity, &mut DialogueBox)>,
+    option_query: Query<(&Interaction, &DialogueOption), Changed<Interaction>>,
+) {
+    let Ok((dialogue_entity, mut dialogue_box)) = dialogue_query.get_single_mut() else {
+        return;
+    };
+
+    for (interaction, option) in option_query.iter() {
+        match *interaction {
+            Interaction::Hovered => dialogue_box.selected = option.0,
+            Interaction::Pressed => {
+                choose_option(
+                    &mut commands,
+                    &net,
+                    &mut cursor_visibility,
+                    dialogue_entity,
+                    &dialogue_box,
+                    option.0,
+                );
+                return;
+            }
+            Interaction::None => (),
+        }
+    }
+}
+
+fn highlight_selected(
+    dialogue_query: Query<&DialogueBox, Changed<DialogueBox>>,
+    mut option_query: Query<(&DialogueOption, &mut BackgroundColor)>,
+) {
+    let Ok(dialogue_box) = dialogue_query.get_single() else {
+        return;
+    };
+
+    for (option, mut background_color) in option_query.iter_mut() {
+        background_color.0 = if option.0 == dialogue_box.selected {
+            SELECTED_OPTION_COLOR
+        } else {
+            OPTION_COLOR
+        };
+    }
+}
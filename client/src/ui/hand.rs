@@ -10,12 +10,15 @@ use crate::{
     assets::models::{ModelAssetId, Models},
     game_state::GameState,
     networking::NetworkClient,
-    player::Head,
+    player::{CameraMode, Head},
 };
 
-use super::server::{
-    items::{ItemBox, ItemBoxSection, Items, SelectedItemBox},
-    InterfaceNode,
+use super::{
+    hud::HudSettings,
+    server::{
+        items::{ItemBox, ItemBoxSection, Items, SelectedItemBox},
+        InterfaceNode,
+    },
 };
 
 pub struct HandPlugin;
@@ -25,18 +28,20 @@ impl Plugin for HandPlugin {
             Update,
             (
                 equip_item,
-                play_equip_animation,
-                play_use_animation,
+                switch_model.after(equip_item),
+                play_use_animation.after(switch_model),
+                animate_hand.after(play_use_animation),
                 //place_block,
                 send_clicks,
+                toggle_hand_visibility
+                    .run_if(resource_changed::<HudSettings>.or(resource_changed::<CameraMode>)),
                 // workarounds for https://github.com/bevyengine/bevy/issues/10832
                 //mark_animated_entity,
                 //set_correct_transform_after_animation_finished,
                 remove_finished_animations
                     //.after(set_correct_transform_after_animation_finished)
-                    .after(play_equip_animation)
-                    .after(play_use_animation)
-                    .after(equip_item),
+                    .after(switch_model)
+                    .after(play_use_animation),
             )
                 .run_if(in_state(GameState::Playing)),
         );
@@ -58,22 +63,71 @@ fn setup(mut commands: Commands, player_camera: Query<Entity, Added<Head>>) {
     });
 }
 
+// Names of the animations a model can have for when it is held. Models that don't have them are
+// animated by moving the whole model instead.
+const EQUIP_ANIMATION: &str = "equip";
+const LEFT_CLICK_ANIMATION: &str = "left_click";
+const RIGHT_CLICK_ANIMATION: &str = "right_click";
+const PLACE_ANIMATION: &str = "place";
+
+/// Seconds it takes to lower or raise the hand when switching items
+const SWITCH_TIME: f32 = 0.15;
+/// How far down the hand is moved when lowered out of view
+const LOWERED_DISTANCE: f32 = 0.25;
+
 #[derive(Component, Default)]
 struct Hand {
-    equipping: bool,
-    // If an item is equipped, it's model is stored here so we know to unequip it.
+    /// Model of the equipped item, it replaces the shown model once that has been lowered.
     equipped: Option<ModelAssetId>,
-    being_unequipped: Option<ModelAssetId>,
+    /// Model that is in the hand right now
+    shown: Option<ModelAssetId>,
+    /// If the equipped item places a block when used
+    places_blocks: bool,
+    /// How far the shown model is raised into view, 0 when out of view and 1 when fully raised.
+    raised: f32,
+    /// Animation played by moving the model when it doesn't have one of its own, and how many
+    /// seconds it has been playing.
+    fallback_animation: Option<(HandAction, f32)>,
+}
+
+#[derive(Clone, Copy)]
+enum HandAction {
+    /// Tools and most other items are swung
+    Swing,
+    /// Blocks are pushed forward
+    Place,
 }
 
+impl HandAction {
+    fn duration(&self) -> f32 {
+        return match self {
+            Self::Swing => 0.25,
+            Self::Place => 0.15,
+        };
+    }
+}
+
+// The hand is part of the first person view, the player's own model is shown in third person.
+fn toggle_hand_visibility(
+    hud_settings: Res<HudSettings>,
+    camera_mode: Res<CameraMode>,
+    mut hand_query: Query<&mut Visibility, With<Hand>>,
+) {
+    *hand_query.single_mut() = if hud_settings.show_hand && camera_mode.is_first_person() {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+}
+
+/// Marks the item box of the item that is equipped.
 #[derive(Component)]
-struct EquippedItem;
+pub struct EquippedItem;
 
 fn equip_item(
     mut commands: Commands,
     net: Res<NetworkClient>,
     items: Res<Items>,
-    models: Res<Models>,
     changed_interface_query: Query<
         (&InterfaceNode, &ItemBoxSection, &SelectedItemBox),
         Changed<SelectedItemBox>,
@@ -87,7 +141,7 @@ fn equip_item(
             With<EquippedItem>,
         ),
     >,
-    mut hand_query: Query<(&mut Hand, &mut AnimationPlayer)>,
+    mut hand_query: Query<&mut Hand>,
 ) {
     // equip and unequip when the equipment interface is hidden/shown or the selected box changes
     for (interface_node, item_box_section, selected) in changed_interface_query.iter() {
@@ -108,126 +162,98 @@ fn equip_item(
         commands.entity(selected.0).insert(EquippedItem);
     }
 
-    // equip new item when the selected item changes.
+    // The hand shows the item the server was told is equipped.
     for item_box in changed_equipped_item_query.iter() {
-        let (mut hand, mut animation_player) = hand_query.single_mut();
-
-        if let Some(item_id) = item_box.item_stack.item {
-            let item = items.get(&item_id);
-
-            // This prevents triggering the switch animation when switching between the same items.
-            // The server also sends a full interface update anytime an item is picked up that is
-            // caught by this.
-            if hand.equipped == Some(item.equip_model) {
-                // If switching between two stacks of the same item it should do nothing.
-                continue;
-            } else if hand.being_unequipped == Some(item.equip_model) {
-                // If the item that was just equipped is the same as the one being unequipped, just
-                // revert the animation.
-                let model = models.get_config(&item.equip_model).unwrap();
-                animation_player
-                    .play(model.named_animations["equip"])
-                    .set_speed(1.0);
-                hand.being_unequipped = None;
-            } else if hand.being_unequipped.is_none() {
-                // If a model is already being unequipped it should continue until finished, else
-                // start unequipping the currently equipped model.
-                if let Some(equipped) = hand.equipped.take() {
-                    // Start the unequip animation
-                    let model = models.get_config(&equipped).unwrap();
-                    animation_player
-                        .play(model.named_animations["equip"])
-                        .set_speed(-1.0)
-                        // XXX: Bug, reverse animations instantly complete because of wrong conditinon
-                        // check
-                        .set_repeat(bevy::animation::RepeatAnimation::Count(2));
-                    hand.being_unequipped = Some(equipped);
-                }
-            }
-
-            hand.equipping = true;
-
-            let model = models.get_config(&item.equip_model).unwrap();
-            if !model.named_animations.contains_key("equip") {
-                warn!("Missing equip animation, can't equip item");
-                continue;
-            };
+        let mut hand = hand_query.single_mut();
 
-            hand.equipped = Some(item.equip_model);
-        } else {
-            if hand.being_unequipped.is_none() {
-                if let Some(equipped) = hand.equipped.take() {
-                    let model = models.get_config(&equipped).unwrap();
-                    animation_player
-                        .play(model.named_animations["equip"])
-                        .set_speed(-1.0)
-                        // XXX: Bug, reverse animations instantly complete because of wrong conditinon
-                        // check
-                        .set_repeat(bevy::animation::RepeatAnimation::Count(2));
-                    hand.equipped = None;
-                    hand.being_unequipped = Some(equipped);
-                    hand.equipping = true;
-                }
-            }
-        }
+        let item = item_box.item_stack.item.map(|item_id| items.get(&item_id));
+        hand.equipped = item.map(|item| item.equip_model);
+        hand.places_blocks = item.is_some_and(|item| item.block.is_some());
     }
 }
 
-fn play_equip_animation(
+// Switching happens by lowering the model that is shown out of view, and raising the new one. The
+// model plays its equip animation when raised if it has one.
+fn switch_model(
+    time: Res<Time>,
     models: Res<Models>,
     gltfs: Res<Assets<Gltf>>,
     mut hand_query: Query<(
+        &mut Hand,
         &mut AnimationPlayer,
         &mut SceneRoot,
-        &mut Hand,
         &mut AnimationGraphHandle,
     )>,
 ) {
-    let (mut animation_player, mut scene_handle, mut hand, mut animation_graph) =
+    let (mut hand, mut animation_player, mut scene_handle, mut animation_graph) =
         hand_query.single_mut();
 
-    if !hand.equipping {
-        return;
-    }
+    let step = time.delta_secs() / SWITCH_TIME;
 
-    for (_index, active_animation) in animation_player.playing_animations() {
-        if active_animation.speed() == -1.0 {
-            if !active_animation.is_finished() {
-                return;
-            } else {
-                // If there is no new model, we set it to nothing here so that the model is
-                // despawned.
-                *scene_handle = SceneRoot::default();
-            }
-        }
+    if hand.shown == hand.equipped {
+        // When switching back to the item that is being lowered, it is raised again from
+        // wherever it is.
+        hand.raised = (hand.raised + step).min(1.0);
+        return;
     }
 
-    if hand.being_unequipped.take().is_some() {
-        animation_player.stop_all();
+    if hand.shown.is_some() && hand.raised > 0.0 {
+        hand.raised = (hand.raised - step).max(0.0);
+        return;
     }
 
-    if let Some(model_id) = &hand.equipped {
-        let model = models.get_config(model_id).unwrap();
-        let animation_index = model.named_animations["equip"];
+    // Need to remove the animations of the previous model or they will linger. Bevy doesn't
+    // remove finished animations.
+    animation_player.stop_all();
+    hand.fallback_animation = None;
+    hand.shown = hand.equipped;
+    hand.raised = 0.0;
 
-        if !animation_player.is_playing_animation(animation_index) {
-            // Need to remove unequip animation or it will linger. Bevy doesn't remove finished
-            // animations.
-            animation_player.stop_all();
+    let Some(model) = hand.shown.and_then(|model_id| models.get_config(&model_id)) else {
+        *scene_handle = SceneRoot::default();
+        return;
+    };
 
-            let gltf = gltfs.get(&model.gltf_handle).unwrap();
-            *scene_handle = SceneRoot(gltf.scenes[0].clone());
-            *animation_graph = AnimationGraphHandle(model.animation_graph.clone().unwrap());
-        }
+    let gltf = gltfs.get(&model.gltf_handle).unwrap();
+    *scene_handle = SceneRoot(gltf.scenes[0].clone());
+    if let Some(graph) = &model.animation_graph {
+        *animation_graph = AnimationGraphHandle(graph.clone());
+    }
 
-        let animation = animation_player.play(animation_index);
+    if let Some(equip) = model.named_animations.get(EQUIP_ANIMATION) {
+        animation_player.start(*equip);
+        // The model raises itself
+        hand.raised = 1.0;
+    }
+}
 
-        if !animation.is_finished() {
-            return;
+// Moves the whole model for switching and for the animations the model doesn't have.
+fn animate_hand(mut hand_query: Query<(&Hand, &mut Transform)>) {
+    let (hand, mut transform) = hand_query.single_mut();
+
+    // Eased so it slows down at the ends
+    let raised = hand.raised * hand.raised * (3.0 - 2.0 * hand.raised);
+    let mut new_transform =
+        Transform::from_translation(Vec3::NEG_Y * LOWERED_DISTANCE * (1.0 - raised));
+
+    if let Some((action, elapsed)) = hand.fallback_animation {
+        let progress = (elapsed / action.duration()).min(1.0);
+        // Out and back
+        let amount = (progress * std::f32::consts::PI).sin();
+        match action {
+            HandAction::Swing => {
+                // The model is in front of the camera, rotating around the camera swings it down
+                // and towards the center.
+                new_transform.rotation =
+                    Quat::from_rotation_x(-0.4 * amount) * Quat::from_rotation_y(0.2 * amount);
+            }
+            HandAction::Place => {
+                new_transform.translation += Vec3::new(-0.01, 0.02, -0.06) * amount;
+            }
         }
     }
 
-    hand.equipping = false;
+    transform.set_if_neq(new_transform);
 }
 
 // #[derive(Component)]
@@ -339,11 +365,21 @@ fn remove_finished_animations(mut animation_player: Query<&mut AnimationPlayer,
 }
 
 fn play_use_animation(
+    time: Res<Time>,
     models: Res<Models>,
     window: Query<&Window, With<PrimaryWindow>>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    mut hand_query: Query<(&mut AnimationPlayer, &Hand)>,
+    mut hand_query: Query<(&mut AnimationPlayer, &mut Hand)>,
 ) {
+    let (mut animation_player, mut hand) = hand_query.single_mut();
+
+    if let Some((action, elapsed)) = hand.fallback_animation.as_mut() {
+        *elapsed += time.delta_secs();
+        if *elapsed >= action.duration() {
+            hand.fallback_animation = None;
+        }
+    }
+
     // TODO: Needs a robust way to see if interface is open
     //
     // Only play if not in interface
@@ -351,32 +387,60 @@ fn play_use_animation(
         return;
     }
 
-    let (mut animation_player, hand) = hand_query.single_mut();
-
-    let Some(model) = &hand.equipped else {
+    // Not while switching
+    let Some(model) = hand.shown else {
         return;
     };
+    if hand.equipped != Some(model) {
+        return;
+    }
 
-    let model_config = models.get_config(model).unwrap();
+    let model_config = models.get_config(&model).unwrap();
 
-    let Some(left_click) = model_config.named_animations.get("left_click").cloned() else {
-        return;
-    };
+    // Blocks have their own animation for being placed, other items fall back to the left click
+    // animation when used.
+    let held = !mouse_button_input.just_pressed(MouseButton::Left)
+        && mouse_button_input.pressed(MouseButton::Left);
+    let (action, animation_names): (HandAction, &[&str]) =
+        if mouse_button_input.pressed(MouseButton::Left) {
+            (HandAction::Swing, &[LEFT_CLICK_ANIMATION])
+        } else if mouse_button_input.just_pressed(MouseButton::Right) {
+            if hand.places_blocks {
+                (HandAction::Place, &[PLACE_ANIMATION])
+            } else {
+                (
+                    HandAction::Swing,
+                    &[RIGHT_CLICK_ANIMATION, LEFT_CLICK_ANIMATION],
+                )
+            }
+        } else {
+            return;
+        };
+
+    let animation = animation_names
+        .iter()
+        .find_map(|name| model_config.named_animations.get(*name))
+        .cloned();
 
-    if mouse_button_input.just_pressed(MouseButton::Left) {
+    match animation {
+        // Keep playing from current position if the mouse buttton is held
+        Some(animation) if held => {
+            let animation = animation_player.play(animation);
+            if animation.is_finished() {
+                animation.replay();
+            }
+        }
         // TODO: Transition
         // Play from beginning even if in the middle of an animation.
-        animation_player.stop(left_click);
-        animation_player.start(left_click);
-    } else if mouse_button_input.pressed(MouseButton::Left) {
-        // Keep playing from current position if the mouse buttton is held
-        let animation = animation_player.play(left_click);
-        if animation.is_finished() {
-            animation.replay();
+        Some(animation) => {
+            animation_player.stop(animation);
+            animation_player.start(animation);
+        }
+        None => {
+            if !held || hand.fallback_animation.is_none() {
+                hand.fallback_animation = Some((action, 0.0));
+            }
         }
-    } else if mouse_button_input.just_pressed(MouseButton::Right) {
-        animation_player.stop(left_click);
-        animation_player.start(left_click);
     }
 }
 
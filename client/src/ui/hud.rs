@@ -0,0 +1,253 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+};
+
+use super::{
+    server::items::{ItemBox, ItemBoxSection, SelectedItemBox},
+    UiState,
+};
+
+// TODO: The GuiSetting message can't carry these, until it can they are sent as properties:
+//     "hotbar_slots" how many of the hotbar's item boxes can be used, null for all of them
+//     "crosshair"    "none", "dot" or "cross"
+//     "hand"         false to hide the equipped item
+//     "hidden_hud"   the client's hud elements that are hidden, "hunger" and "minimap". The
+//                    server's own interfaces, like a health bar, are hidden by changing their
+//                    visibility.
+//
+// Parts of the hud the server can configure. They are reset when leaving the server.
+pub struct HudPlugin;
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HudSettings>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                OnExit(GameState::Playing),
+                |mut hud_settings: ResMut<HudSettings>| {
+                    *hud_settings = HudSettings::default();
+                },
+            )
+            .add_systems(
+                Update,
+                (
+                    handle_hud_properties.run_if(on_event::<ServerProperty>),
+                    limit_hotbar_slots,
+                    update_crosshair,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+#[derive(Resource)]
+pub struct HudSettings {
+    /// How many of the item boxes in the hotbar can be used, the rest are hidden. None for all of
+    /// them.
+    pub hotbar_slots: Option<usize>,
+    pub crosshair: CrosshairStyle,
+    /// If the equipped item is shown in the player's hand
+    pub show_hand: bool,
+    /// Hud elements that are hidden
+    pub hidden: HashSet<String>,
+}
+
+impl HudSettings {
+    pub fn is_hidden(&self, element: &str) -> bool {
+        return self.hidden.contains(element);
+    }
+
+    /// If the item box at this index of the hotbar can be used
+    pub fn is_usable_slot(&self, index: usize) -> bool {
+        return self.hotbar_slots.map_or(true, |slots| index < slots);
+    }
+}
+
+impl Default for HudSettings {
+    fn default() -> Self {
+        Self {
+            hotbar_slots: None,
+            // Servers usually draw their own as part of their interfaces.
+            crosshair: CrosshairStyle::None,
+            show_hand: true,
+            hidden: HashSet::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrosshairStyle {
+    None,
+    Dot,
+    Cross,
+}
+
+// Both bars are the same size for the dot.
+const CROSSHAIR_SIZE: f32 = 7.0;
+
+#[derive(Component)]
+struct Crosshair;
+
+#[derive(Component)]
+enum CrosshairBar {
+    Horizontal,
+    Vertical,
+}
+
+fn setup(mut commands: Commands) {
+    commands
+        .spawn((
+            Crosshair,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(50.0),
+                top: Val::Percent(50.0),
+                width: Val::Px(CROSSHAIR_SIZE),
+                height: Val::Px(CROSSHAIR_SIZE),
+                margin: UiRect::all(Val::Px(-CROSSHAIR_SIZE / 2.0)),
+                ..default()
+            },
+            Visibility::Hidden,
+        ))
+        .with_children(|parent| {
+            for bar in [CrosshairBar::Horizontal, CrosshairBar::Vertical] {
+                parent.spawn((
+                    bar,
+                    Node {
+                        position_type: PositionType::Absolute,
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgba(1.0, 1.0, 1.0, 0.8)),
+                ));
+            }
+        });
+}
+
+fn handle_hud_properties(
+    mut hud_settings: ResMut<HudSettings>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        match property.name.as_str() {
+            properties::HOTBAR_SLOTS => {
+                hud_settings.hotbar_slots = property.parse().flatten();
+            }
+            properties::CROSSHAIR => {
+                hud_settings.crosshair = match property.parse::<String>().as_deref() {
+                    Some("dot") => CrosshairStyle::Dot,
+                    Some("cross") => CrosshairStyle::Cross,
+                    _ => CrosshairStyle::None,
+                };
+            }
+            properties::HAND => hud_settings.show_hand = property.parse().unwrap_or(true),
+            properties::HIDDEN_HUD => {
+                hud_settings.hidden = property
+                    .parse::<Vec<String>>()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+            }
+            _ => (),
+        }
+    }
+}
+
+// Hides the item boxes of the hotbar past the slot count, and moves the selection off them.
+fn limit_hotbar_slots(
+    hud_settings: Res<HudSettings>,
+    added_item_boxes: Query<(), Added<ItemBox>>,
+    mut item_box_section_query: Query<(&ItemBoxSection, &Children, Option<&mut SelectedItemBox>)>,
+    mut item_box_query: Query<(&ItemBox, &mut Node)>,
+) {
+    if !hud_settings.is_changed() && added_item_boxes.is_empty() {
+        return;
+    }
+
+    for (item_box_section, children, selected) in item_box_section_query.iter_mut() {
+        if !item_box_section.is_equipment {
+            continue;
+        }
+
+        for child in children.iter() {
+            let Ok((item_box, mut node)) = item_box_query.get_mut(*child) else {
+                continue;
+            };
+
+            let display = if hud_settings.is_usable_slot(item_box.index) {
+                Display::Flex
+            } else {
+                Display::None
+            };
+            if node.display != display {
+                node.display = display;
+            }
+        }
+
+        let Some(mut selected) = selected else {
+            continue;
+        };
+
+        let is_usable = item_box_query
+            .get(selected.0)
+            .is_ok_and(|(item_box, _)| hud_settings.is_usable_slot(item_box.index));
+        if !is_usable {
+            if let Some(first) = children.first() {
+                selected.0 = *first;
+            }
+        }
+    }
+}
+
+fn update_crosshair(
+    hud_settings: Res<HudSettings>,
+    ui_state: Res<State<UiState>>,
+    mut crosshair_query: Query<&mut Visibility, With<Crosshair>>,
+    mut bar_query: Query<(&CrosshairBar, &mut Node)>,
+) {
+    if !hud_settings.is_changed() && !ui_state.is_changed() {
+        return;
+    }
+
+    // Hidden while the client's menus are open
+    *crosshair_query.single_mut() = if hud_settings.crosshair == CrosshairStyle::None
+        || *ui_state.get() != UiState::ServerInterfaces
+    {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    };
+
+    let center = (CROSSHAIR_SIZE / 2.0).floor();
+    for (bar, mut node) in bar_query.iter_mut() {
+        let length = if hud_settings.crosshair == CrosshairStyle::Cross {
+            CROSSHAIR_SIZE
+        } else {
+            1.0
+        };
+        let offset = if hud_settings.crosshair == CrosshairStyle::Cross {
+            0.0
+        } else {
+            center
+        };
+
+        match bar {
+            CrosshairBar::Horizontal => {
+                node.width = Val::Px(length);
+                node.height = Val::Px(1.0);
+                node.left = Val::Px(offset);
+                node.top = Val::Px(center);
+            }
+            CrosshairBar::Vertical => {
+                node.width = Val::Px(1.0);
+                node.height = Val::Px(length);
+                node.left = Val::Px(center);
+                node.top = Val::Px(offset);
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+};
+
+use super::hud::HudSettings;
+
+// Shows the player's hunger when the server has it enabled. The server sends it as the "hunger"
+// property.
+pub struct HungerPlugin;
+impl Plugin for HungerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(OnExit(GameState::Playing), hide_hunger_bar)
+            .add_systems(
+                Update,
+                (
+                    update_hunger_bar.run_if(on_event::<ServerProperty>),
+                    hide_from_hud.run_if(resource_changed::<HudSettings>),
+                ),
+            );
+    }
+}
+
+#[derive(Component)]
+struct HungerBar;
+
+#[derive(Component)]
+struct HungerBarFill;
+
+fn setup(mut commands: Commands) {
+    commands
+        .spawn((
+            HungerBar,
+            Node {
+                position_type: PositionType::Absolute,
+                right: Val::Px(4.0),
+                bottom: Val::Px(4.0),
+                width: Val::Px(40.0),
+                height: Val::Px(3.0),
+                border: UiRect::all(Val::Px(1.0)),
+                ..default()
+            },
+            BorderColor::from(Color::BLACK),
+            BackgroundColor(Color::srgb_u8(33, 33, 33)),
+            Visibility::Hidden,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                HungerBarFill,
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb_u8(196, 132, 48)),
+            ));
+        });
+}
+
+fn hide_hunger_bar(mut bar_query: Query<&mut Visibility, With<HungerBar>>) {
+    *bar_query.single_mut() = Visibility::Hidden;
+}
+
+// Visibility is used for if the server has hunger enabled, the server can also hide it from the
+// hud without disabling it.
+fn hide_from_hud(hud_settings: Res<HudSettings>, mut bar_query: Query<&mut Node, With<HungerBar>>) {
+    bar_query.single_mut().display = if hud_settings.is_hidden("hunger") {
+        Display::None
+    } else {
+        Display::Flex
+    };
+}
+
+fn update_hunger_bar(
+    mut bar_query: Query<&mut Visibility, With<HungerBar>>,
+    mut fill_query: Query<&mut Node, With<HungerBarFill>>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::HUNGER {
+            continue;
+        }
+
+        let Some(properties::Hunger { hunger, max }) = property.parse() else {
+            continue;
+        };
+
+        *bar_query.single_mut() = Visibility::Inherited;
+        fill_query.single_mut().width = Val::Percent(hunger as f32 / max.max(1) as f32 * 100.0);
+    }
+}
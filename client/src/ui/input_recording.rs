@@ -0,0 +1,329 @@
+use std::{
+    collections::VecDeque,
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        mouse::{MouseButtonInput, MouseScrollUnit, MouseWheel},
+        ButtonState, InputSystem,
+    },
+    prelude::*,
+    window::PrimaryWindow,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{client::GuiState, UiState};
+
+// How long playback waits for the gui to reach a recorded state before it fails.
+const STATE_TIMEOUT: f64 = 10.0;
+
+// Records the keyboard and mouse input of a session to a file so that it can be played back
+// later, e.g. to run through the menus in CI. The recording is one json object per line. Changes
+// to the gui are recorded along with the input, and during playback the input is held back until
+// the gui has reached the same state, so that it doesn't matter how long screens take to load.
+// If it never gets there the client exits with an error.
+pub struct InputRecordingPlugin {
+    /// File to record the input to
+    pub record: Option<PathBuf>,
+    /// File to play the input back from
+    pub playback: Option<PathBuf>,
+}
+
+impl Plugin for InputRecordingPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(path) = &self.record {
+            let file = match std::fs::File::create(path) {
+                Ok(f) => f,
+                Err(e) => panic!(
+                    "Failed to create input recording at: {}\nError: {}",
+                    path.display(),
+                    e
+                ),
+            };
+
+            app.insert_resource(Recording {
+                file: std::io::BufWriter::new(file),
+            })
+            .add_systems(
+                PreUpdate,
+                (record_input, record_states).chain().after(InputSystem),
+            );
+        }
+
+        if let Some(path) = &self.playback {
+            let file = match std::fs::File::open(path) {
+                Ok(f) => f,
+                Err(e) => panic!(
+                    "Failed to open input recording at: {}\nError: {}",
+                    path.display(),
+                    e
+                ),
+            };
+
+            let mut steps = VecDeque::new();
+            for (line_number, line) in std::io::BufReader::new(file).lines().enumerate() {
+                let line = line.unwrap();
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                match serde_json::from_str(&line) {
+                    Ok(step) => steps.push_back(step),
+                    Err(e) => panic!(
+                        "Failed to parse input recording at: {}\nError on line {}: {}",
+                        path.display(),
+                        line_number + 1,
+                        e
+                    ),
+                }
+            }
+
+            app.insert_resource(Playback {
+                steps,
+                delay: 0.0,
+                waiting_since: None,
+            })
+            .add_systems(PreUpdate, play_input.before(InputSystem));
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Step {
+    /// Seconds since the client started
+    time: f64,
+    action: Action,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Key {
+        key_code: KeyCode,
+        logical_key: Key,
+        pressed: bool,
+    },
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    CursorMoved {
+        position: Vec2,
+    },
+    MouseWheel {
+        unit: MouseScrollUnit,
+        x: f32,
+        y: f32,
+    },
+    /// The gui state the client should be in before playback continues
+    GuiState(String),
+    /// The ui state the client should be in before playback continues
+    UiState(String),
+}
+
+#[derive(Resource)]
+struct Recording {
+    file: std::io::BufWriter<std::fs::File>,
+}
+
+impl Recording {
+    fn write(&mut self, time: f64, action: Action) {
+        let line = serde_json::to_string(&Step { time, action }).unwrap();
+        // Flushed every line so the recording isn't lost if the client crashes.
+        if let Err(e) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            error!("Failed to write input recording: {}", e);
+        }
+    }
+}
+
+#[derive(Resource)]
+struct Playback {
+    steps: VecDeque<Step>,
+    // How many seconds playback is behind the recording from waiting on states.
+    delay: f64,
+    // When playback started waiting on the current state step.
+    waiting_since: Option<f64>,
+}
+
+fn record_input(
+    time: Res<Time<Real>>,
+    mut recording: ResMut<Recording>,
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut mouse_button_events: EventReader<MouseButtonInput>,
+    mut cursor_events: EventReader<CursorMoved>,
+    mut wheel_events: EventReader<MouseWheel>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    for event in keyboard_events.read() {
+        recording.write(
+            now,
+            Action::Key {
+                key_code: event.key_code,
+                logical_key: event.logical_key.clone(),
+                pressed: event.state == ButtonState::Pressed,
+            },
+        );
+    }
+
+    for event in mouse_button_events.read() {
+        recording.write(
+            now,
+            Action::MouseButton {
+                button: event.button,
+                pressed: event.state == ButtonState::Pressed,
+            },
+        );
+    }
+
+    for event in cursor_events.read() {
+        recording.write(
+            now,
+            Action::CursorMoved {
+                position: event.position,
+            },
+        );
+    }
+
+    for event in wheel_events.read() {
+        recording.write(
+            now,
+            Action::MouseWheel {
+                unit: event.unit,
+                x: event.x,
+                y: event.y,
+            },
+        );
+    }
+}
+
+fn record_states(
+    time: Res<Time<Real>>,
+    gui_state: Res<State<GuiState>>,
+    ui_state: Res<State<UiState>>,
+    mut recording: ResMut<Recording>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    // The states are also recorded on the first frame, playback starts by waiting for them.
+    if gui_state.is_changed() {
+        recording.write(now, Action::GuiState(format!("{:?}", gui_state.get())));
+    }
+
+    if ui_state.is_changed() {
+        recording.write(now, Action::UiState(format!("{:?}", ui_state.get())));
+    }
+}
+
+fn play_input(
+    time: Res<Time<Real>>,
+    gui_state: Res<State<GuiState>>,
+    ui_state: Res<State<UiState>>,
+    mut playback: ResMut<Playback>,
+    mut window_query: Query<(Entity, &mut Window), With<PrimaryWindow>>,
+    mut keyboard_events: EventWriter<KeyboardInput>,
+    mut mouse_button_events: EventWriter<MouseButtonInput>,
+    mut cursor_events: EventWriter<CursorMoved>,
+    mut wheel_events: EventWriter<MouseWheel>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    let Ok((window_entity, mut window)) = window_query.get_single_mut() else {
+        return;
+    };
+
+    // Playback has already finished
+    if playback.steps.is_empty() {
+        return;
+    }
+
+    let now = time.elapsed_secs_f64();
+    let playback = &mut *playback;
+
+    while let Some(step) = playback.steps.front() {
+        if step.time + playback.delay > now {
+            return;
+        }
+
+        let (expected, current) = match &step.action {
+            Action::GuiState(state) => (Some(state), format!("{:?}", gui_state.get())),
+            Action::UiState(state) => (Some(state), format!("{:?}", ui_state.get())),
+            _ => (None, String::new()),
+        };
+
+        if let Some(expected) = expected {
+            if *expected != current {
+                let waiting_since = *playback.waiting_since.get_or_insert(now);
+                if now - waiting_since > STATE_TIMEOUT {
+                    error!(
+                        "Input playback failed at {:.2}s, expected the state to be '{}' but it \
+                        was '{}'",
+                        step.time, expected, current
+                    );
+                    app_exit.send(AppExit::error());
+                    playback.steps.clear();
+                }
+                return;
+            }
+
+            if let Some(waiting_since) = playback.waiting_since.take() {
+                playback.delay += now - waiting_since;
+            }
+        }
+
+        let step = playback.steps.pop_front().unwrap();
+        let state = |pressed| {
+            if pressed {
+                ButtonState::Pressed
+            } else {
+                ButtonState::Released
+            }
+        };
+
+        match step.action {
+            Action::Key {
+                key_code,
+                logical_key,
+                pressed,
+            } => {
+                keyboard_events.send(KeyboardInput {
+                    key_code,
+                    logical_key,
+                    state: state(pressed),
+                    repeat: false,
+                    window: window_entity,
+                });
+            }
+            Action::MouseButton { button, pressed } => {
+                mouse_button_events.send(MouseButtonInput {
+                    button,
+                    state: state(pressed),
+                    window: window_entity,
+                });
+            }
+            Action::CursorMoved { position } => {
+                // The ui reads the cursor position from the window
+                window.set_cursor_position(Some(position));
+                cursor_events.send(CursorMoved {
+                    window: window_entity,
+                    position,
+                    delta: None,
+                });
+            }
+            Action::MouseWheel { unit, x, y } => {
+                wheel_events.send(MouseWheel {
+                    unit,
+                    x,
+                    y,
+                    window: window_entity,
+                });
+            }
+            Action::GuiState(_) | Action::UiState(_) => (),
+        }
+    }
+
+    info!("Input playback finished");
+    app_exit.send(AppExit::Success);
+}
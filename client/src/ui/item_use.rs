@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, NetworkClient, ServerProperty},
+};
+
+use super::{
+    hand::EquippedItem,
+    server::items::{ItemBox, ItemId, Items},
+};
+
+// Items with a use duration are used by holding down the right mouse button. The server is told
+// when the use starts, and when it is cancelled or finished, it decides what the use does.
+pub struct ItemUsePlugin;
+impl Plugin for ItemUsePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ItemUse>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                OnExit(GameState::Playing),
+                |mut item_use: ResMut<ItemUse>| {
+                    *item_use = ItemUse::default();
+                },
+            )
+            .add_systems(
+                Update,
+                (handle_server_cancel, use_items, update_progress_indicator)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+struct ItemUse {
+    // The item being used and when it was started
+    current: Option<(ItemId, f32)>,
+    // When the items can be used again
+    cooldowns: HashMap<ItemId, f32>,
+}
+
+// Bar below the crosshair showing how far the item has gotten into its use.
+#[derive(Component)]
+struct ProgressIndicator;
+
+#[derive(Component)]
+struct ProgressIndicatorFill;
+
+fn setup(mut commands: Commands) {
+    commands
+        .spawn((
+            ProgressIndicator,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(50.0),
+                top: Val::Percent(50.0),
+                width: Val::Px(16.0),
+                height: Val::Px(2.0),
+                margin: UiRect {
+                    left: Val::Px(-8.0),
+                    top: Val::Px(6.0),
+                    ..default()
+                },
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.5)),
+            Visibility::Hidden,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                ProgressIndicatorFill,
+                Node {
+                    width: Val::Percent(0.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                BackgroundColor(Color::WHITE),
+            ));
+        });
+}
+
+// The server cancels uses it doesn't accept, e.g. if the item is still cooling down.
+fn handle_server_cancel(
+    mut item_use: ResMut<ItemUse>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name == properties::ITEM_USE
+            && property.parse() == Some(properties::ItemUse::Cancel)
+        {
+            item_use.current = None;
+        }
+    }
+}
+
+fn use_items(
+    net: Res<NetworkClient>,
+    time: Res<Time>,
+    items: Res<Items>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    equipped_query: Query<&ItemBox, With<EquippedItem>>,
+    mut item_use: ResMut<ItemUse>,
+) {
+    let now = time.elapsed_secs();
+    let equipped = equipped_query
+        .get_single()
+        .ok()
+        .and_then(|item_box| item_box.item_stack.item);
+    let is_held = mouse_button_input.pressed(MouseButton::Right)
+        && window.single().cursor_options.grab_mode != CursorGrabMode::None;
+
+    let Some((item_id, started)) = item_use.current else {
+        let Some(item_id) = equipped else {
+            return;
+        };
+
+        let is_cooling_down = item_use
+            .cooldowns
+            .get(&item_id)
+            .is_some_and(|ready_at| *ready_at > now);
+
+        if is_held && items.get(&item_id).usage.is_some() && !is_cooling_down {
+            item_use.current = Some((item_id, now));
+            net.send_property(properties::ITEM_USE, &properties::ItemUse::Start);
+        }
+        return;
+    };
+
+    // Switching items cancels the use
+    if equipped != Some(item_id) {
+        item_use.current = None;
+        net.send_property(properties::ITEM_USE, &properties::ItemUse::Cancel);
+        return;
+    }
+
+    let usage = items.get(&item_id).usage.unwrap();
+    let is_done = now - started >= usage.duration;
+
+    if (!is_held && usage.chargeable) || (is_done && !usage.chargeable) {
+        item_use.current = None;
+        item_use.cooldowns.retain(|_, ready_at| *ready_at > now);
+        if usage.cooldown > 0.0 {
+            item_use.cooldowns.insert(item_id, now + usage.cooldown);
+        }
+        net.send_property(properties::ITEM_USE, &properties::ItemUse::Finish);
+    } else if !is_held {
+        item_use.current = None;
+        net.send_property(properties::ITEM_USE, &properties::ItemUse::Cancel);
+    }
+}
+
+fn update_progress_indicator(
+    time: Res<Time>,
+    items: Res<Items>,
+    item_use: Res<ItemUse>,
+    mut indicator_query: Query<&mut Visibility, With<ProgressIndicator>>,
+    mut fill_query: Query<&mut Node, With<ProgressIndicatorFill>>,
+) {
+    let mut visibility = indicator_query.single_mut();
+
+    let Some((item_id, started)) = item_use.current else {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    };
+
+    let usage = items.get(&item_id).usage.unwrap();
+    let progress = if usage.duration > 0.0 {
+        ((time.elapsed_secs() - started) / usage.duration).min(1.0)
+    } else {
+        1.0
+    };
+
+    visibility.set_if_neq(Visibility::Inherited);
+    fill_query.single_mut().width = Val::Percent(progress * 100.0);
+}
@@ -0,0 +1,576 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use bevy::{
+    math::DVec3,
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use fmc_protocol::messages;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, NetworkClient, ServerProperty},
+    player::{Head, Player},
+    rendering::RenderSet,
+    settings::Settings,
+    world::{
+        blocks::Blocks,
+        world_map::{chunk::Chunk, NewChunkEvent, WorldMap},
+        Origin,
+    },
+};
+
+use super::{hud::HudSettings, widgets::FocusedTextBox, UiState};
+
+// Size of the minimap image in pixels, each pixel is one block at zoom 1.
+const MINIMAP_SIZE: u32 = 128;
+const FULLSCREEN_MAP_SIZE: u32 = 256;
+
+const FULLSCREEN_MAP_KEY: KeyCode = KeyCode::Tab;
+const WAYPOINT_KEY: KeyCode = KeyCode::Comma;
+const ROTATION_LOCK_KEY: KeyCode = KeyCode::Period;
+const ZOOM_IN_KEY: KeyCode = KeyCode::Equal;
+const ZOOM_OUT_KEY: KeyCode = KeyCode::Minus;
+
+// The minimap is built from the chunks the server sends, it keeps the surface of every block
+// column it has seen so that it can also show the parts of the world that are no longer loaded.
+// Servers that keep map tiles also send those, for the parts of the world the player hasn't seen.
+pub struct MinimapPlugin;
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MapColumns>()
+            .init_resource::<Waypoints>()
+            .add_systems(Startup, setup)
+            .add_systems(OnEnter(GameState::Playing), load_waypoints)
+            .add_systems(OnExit(GameState::Playing), (save_waypoints, clear_columns))
+            .add_systems(
+                Update,
+                (
+                    add_chunk_columns,
+                    update_columns_from_block_updates.after(RenderSet::UpdateBlocks),
+                    add_map_tiles.run_if(on_event::<ServerProperty>),
+                    add_death_waypoint.run_if(on_event::<ServerProperty>),
+                    (handle_map_keys, draw_maps).chain(),
+                    toggle_minimap_visibility,
+                )
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+#[derive(Component)]
+struct Minimap;
+
+#[derive(Component)]
+struct FullscreenMap;
+
+// Map images
+#[derive(Resource)]
+struct MapImages {
+    minimap: Handle<Image>,
+    fullscreen: Handle<Image>,
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let mut new_image = |size: u32| {
+        images.add(Image::new_fill(
+            Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 0],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        ))
+    };
+
+    let map_images = MapImages {
+        minimap: new_image(MINIMAP_SIZE),
+        fullscreen: new_image(FULLSCREEN_MAP_SIZE),
+    };
+
+    commands.spawn((
+        Minimap,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(4.0),
+            right: Val::Px(4.0),
+            width: Val::Px(MINIMAP_SIZE as f32 / 2.0),
+            height: Val::Px(MINIMAP_SIZE as f32 / 2.0),
+            border: UiRect::all(Val::Px(1.0)),
+            ..default()
+        },
+        BorderColor::from(Color::BLACK),
+        ImageNode::new(map_images.minimap.clone()),
+        Visibility::Hidden,
+    ));
+
+    commands.spawn((
+        FullscreenMap,
+        Node {
+            position_type: PositionType::Absolute,
+            height: Val::Percent(90.0),
+            aspect_ratio: Some(1.0),
+            margin: UiRect::all(Val::Auto),
+            border: UiRect::all(Val::Px(1.0)),
+            ..default()
+        },
+        BorderColor::from(Color::BLACK),
+        BackgroundColor::from(Color::srgb_u8(33, 33, 33)),
+        ImageNode::new(map_images.fullscreen.clone()),
+        Visibility::Hidden,
+    ));
+
+    commands.insert_resource(map_images);
+}
+
+fn toggle_minimap_visibility(
+    settings: Res<Settings>,
+    hud_settings: Res<HudSettings>,
+    ui_state: Res<State<UiState>>,
+    fullscreen_query: Query<&Visibility, (With<FullscreenMap>, Without<Minimap>)>,
+    mut minimap_query: Query<&mut Visibility, With<Minimap>>,
+) {
+    let fullscreen_visibility = fullscreen_query.single();
+    let mut visibility = minimap_query.single_mut();
+
+    let new_visibility = if settings.minimap
+        && !hud_settings.is_hidden("minimap")
+        && *ui_state.get() == UiState::ServerInterfaces
+        && *fullscreen_visibility == Visibility::Hidden
+    {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+
+    if *visibility != new_visibility {
+        *visibility = new_visibility;
+    }
+}
+
+// The surface of all block columns that have been seen. The color is of the topmost block that
+// has a map color, and the height is the y coordinate of that block.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct MapColumns(HashMap<IVec2, (Srgba, i32)>);
+
+fn clear_columns(mut columns: ResMut<MapColumns>) {
+    columns.clear();
+}
+
+// Find the topmost block with a map color in the chunk's block column.
+fn chunk_column_surface(chunk: &Chunk, chunk_y: i32, x: usize, z: usize) -> Option<(Srgba, i32)> {
+    let blocks = Blocks::get();
+
+    for y in (0..Chunk::SIZE).rev() {
+        if let Some(color) = blocks.get_config(chunk[[x, y, z]]).map_color() {
+            return Some((color.to_srgba(), chunk_y + y as i32));
+        }
+
+        if chunk.is_uniform() {
+            break;
+        }
+    }
+
+    return None;
+}
+
+fn add_chunk_columns(
+    world_map: Res<WorldMap>,
+    mut columns: ResMut<MapColumns>,
+    mut new_chunk_events: EventReader<NewChunkEvent>,
+) {
+    for event in new_chunk_events.read() {
+        let Some(chunk) = world_map.get_chunk(&event.position) else {
+            continue;
+        };
+
+        for x in 0..Chunk::SIZE {
+            for z in 0..Chunk::SIZE {
+                let Some((color, height)) = chunk_column_surface(chunk, event.position.y, x, z)
+                else {
+                    continue;
+                };
+
+                let column_position =
+                    IVec2::new(event.position.x + x as i32, event.position.z + z as i32);
+                let column = columns.entry(column_position).or_insert((color, height));
+
+                // If a chunk below is received after the one above it, it is hidden.
+                if column.1 <= height {
+                    *column = (color, height);
+                }
+            }
+        }
+    }
+}
+
+fn add_map_tiles(
+    mut columns: ResMut<MapColumns>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::MAP_TILE {
+            continue;
+        }
+
+        let tile: properties::MapTile = match serde_json::from_str(&property.value) {
+            Ok(tile) => tile,
+            Err(e) => {
+                error!("The server sent an invalid map tile: {}", e);
+                continue;
+            }
+        };
+
+        let bytes = match base64::engine::general_purpose::STANDARD.decode(&tile.columns) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("The server sent an invalid map tile: {}", e);
+                continue;
+            }
+        };
+
+        if bytes.len() != Chunk::SIZE.pow(2) * 5
+            || bytes
+                .chunks(5)
+                .any(|column| column[0] as usize >= tile.palette.len())
+        {
+            error!("The server sent an invalid map tile");
+            continue;
+        }
+
+        for x in 0..Chunk::SIZE {
+            for z in 0..Chunk::SIZE {
+                let column = &bytes[(x * Chunk::SIZE + z) * 5..][..5];
+                let [red, green, blue, alpha] = tile.palette[column[0] as usize];
+                if alpha == 0 {
+                    continue;
+                }
+
+                let height = i32::from_le_bytes([column[1], column[2], column[3], column[4]]);
+                let column_position = IVec2::new(tile.x + x as i32, tile.z + z as i32);
+                columns.insert(
+                    column_position,
+                    (Srgba::rgba_u8(red, green, blue, alpha), height),
+                );
+            }
+        }
+    }
+}
+
+fn update_columns_from_block_updates(
+    world_map: Res<WorldMap>,
+    mut columns: ResMut<MapColumns>,
+    mut block_update_events: EventReader<messages::BlockUpdates>,
+) {
+    let blocks = Blocks::get();
+
+    for event in block_update_events.read() {
+        for (index, block_id, _) in event.blocks.iter() {
+            let position = event.chunk_position + crate::utils::block_index_to_position(*index);
+            let column_position = IVec2::new(position.x, position.z);
+            let current_height = columns.get(&column_position).map(|(_, height)| *height);
+
+            if let Some(color) = blocks.get_config(*block_id).map_color() {
+                if current_height.is_none_or(|height| height <= position.y) {
+                    columns.insert(column_position, (color.to_srgba(), position.y));
+                }
+            } else if current_height == Some(position.y) {
+                // The surface was removed, look for the new one in the loaded chunks below.
+                columns.remove(&column_position);
+                let mut block_position = position - IVec3::Y;
+                while let Some(block_id) = world_map.get_block(&block_position) {
+                    if let Some(color) = blocks.get_config(block_id).map_color() {
+                        columns.insert(column_position, (color.to_srgba(), block_position.y));
+                        break;
+                    }
+                    block_position.y -= 1;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum WaypointKind {
+    Waypoint,
+    // Where the player last died
+    Death,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Waypoint {
+    kind: WaypointKind,
+    position: DVec3,
+}
+
+// Markers shown on the maps. They are saved for each server separately.
+#[derive(Resource, Default)]
+struct Waypoints {
+    waypoints: Vec<Waypoint>,
+}
+
+impl Waypoints {
+    fn path(net: &NetworkClient) -> Option<std::path::PathBuf> {
+        return Some(net.server_data_directory()?.join("waypoints.json"));
+    }
+}
+
+fn load_waypoints(net: Res<NetworkClient>, mut waypoints: ResMut<Waypoints>) {
+    waypoints.waypoints.clear();
+
+    let Some(path) = Waypoints::path(&net) else {
+        return;
+    };
+
+    let Ok(file) = std::fs::File::open(&path) else {
+        return;
+    };
+
+    match serde_json::from_reader(file) {
+        Ok(w) => waypoints.waypoints = w,
+        Err(e) => error!(
+            "Failed to read waypoints from '{}', Error: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn save_waypoints(net: Res<NetworkClient>, waypoints: Res<Waypoints>) {
+    let Some(path) = Waypoints::path(&net) else {
+        return;
+    };
+
+    std::fs::create_dir_all(path.parent().unwrap()).ok();
+
+    let file = match std::fs::File::create(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(
+                "Failed to save waypoints to '{}', Error: {}",
+                path.display(),
+                e
+            );
+            return;
+        }
+    };
+
+    if let Err(e) = serde_json::to_writer(file, &waypoints.waypoints) {
+        error!(
+            "Failed to save waypoints to '{}', Error: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn add_death_waypoint(
+    mut waypoints: ResMut<Waypoints>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::DEATH {
+            continue;
+        }
+
+        let Some(position) = property.parse::<[f64; 3]>() else {
+            error!("The server sent an invalid death position");
+            continue;
+        };
+
+        // Only the last death is marked
+        waypoints
+            .waypoints
+            .retain(|waypoint| waypoint.kind != WaypointKind::Death);
+        waypoints.waypoints.push(Waypoint {
+            kind: WaypointKind::Death,
+            position: DVec3::from_array(position),
+        });
+    }
+}
+
+fn handle_map_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<State<UiState>>,
+    origin: Res<Origin>,
+    focused_text_box: Query<(), With<FocusedTextBox>>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    mut fullscreen_query: Query<&mut Visibility, With<FullscreenMap>>,
+    mut settings: ResMut<Settings>,
+    mut waypoints: ResMut<Waypoints>,
+) {
+    // Typing in a text box
+    if !focused_text_box.is_empty() {
+        return;
+    }
+
+    let mut fullscreen_visibility = fullscreen_query.single_mut();
+
+    if *ui_state.get() != UiState::ServerInterfaces {
+        *fullscreen_visibility = Visibility::Hidden;
+        return;
+    }
+
+    if keys.just_pressed(FULLSCREEN_MAP_KEY) {
+        fullscreen_visibility.toggle_visible_hidden();
+    }
+
+    if keys.just_pressed(ROTATION_LOCK_KEY) {
+        settings.minimap_rotation_locked = !settings.minimap_rotation_locked;
+    }
+
+    if keys.just_pressed(ZOOM_IN_KEY) {
+        settings.minimap_zoom = (settings.minimap_zoom / 2.0).max(0.25);
+    } else if keys.just_pressed(ZOOM_OUT_KEY) {
+        settings.minimap_zoom = (settings.minimap_zoom * 2.0).min(8.0);
+    }
+
+    if keys.just_pressed(WAYPOINT_KEY) {
+        let transform = player_query.single();
+        waypoints.waypoints.push(Waypoint {
+            kind: WaypointKind::Waypoint,
+            position: origin.to_global(transform.translation()),
+        });
+    }
+}
+
+fn draw_maps(
+    time: Res<Time>,
+    origin: Res<Origin>,
+    settings: Res<Settings>,
+    columns: Res<MapColumns>,
+    waypoints: Res<Waypoints>,
+    map_images: Res<MapImages>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    camera_query: Query<&GlobalTransform, With<Head>>,
+    fullscreen_query: Query<&Visibility, With<FullscreenMap>>,
+    mut images: ResMut<Assets<Image>>,
+    mut timer: Local<Timer>,
+) {
+    // Redrawing is too expensive to do every frame
+    timer.tick(time.delta());
+    if !timer.finished() && !settings.is_changed() {
+        return;
+    }
+    *timer = Timer::from_seconds(0.2, TimerMode::Once);
+
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+    let center = origin.to_global(player_transform.translation());
+
+    if *fullscreen_query.single() != Visibility::Hidden {
+        let image = images.get_mut(&map_images.fullscreen).unwrap();
+        draw_map(
+            image,
+            &columns,
+            &waypoints,
+            center,
+            Vec2::NEG_Y,
+            settings.minimap_zoom * 2.0,
+        );
+    } else if settings.minimap {
+        let forward = if settings.minimap_rotation_locked {
+            Vec2::NEG_Y
+        } else {
+            let camera_forward = camera_query.single().forward();
+            Vec2::new(camera_forward.x, camera_forward.z).normalize_or(Vec2::NEG_Y)
+        };
+
+        let image = images.get_mut(&map_images.minimap).unwrap();
+        draw_map(
+            image,
+            &columns,
+            &waypoints,
+            center,
+            forward,
+            settings.minimap_zoom,
+        );
+    }
+}
+
+// Draws the map centered on the player. The 'forward' direction is drawn as up. The zoom is how
+// many blocks there are per pixel.
+fn draw_map(
+    image: &mut Image,
+    columns: &MapColumns,
+    waypoints: &Waypoints,
+    center: DVec3,
+    forward: Vec2,
+    zoom: f32,
+) {
+    let size = image.width() as i32;
+    // 'forward' rotated clockwise
+    let right = Vec2::new(-forward.y, forward.x);
+
+    let to_pixel = |position: DVec3| -> Option<(i32, i32)> {
+        let offset = (position - center).as_vec3().xz() / zoom;
+        let x = offset.dot(right).round() as i32 + size / 2;
+        let y = -offset.dot(forward).round() as i32 + size / 2;
+        if x < 0 || y < 0 || x >= size || y >= size {
+            return None;
+        } else {
+            return Some((x, y));
+        }
+    };
+
+    let mut set_pixel = |x: i32, y: i32, color: [u8; 4]| {
+        let index = (y * size + x) as usize * 4;
+        image.data[index..index + 4].copy_from_slice(&color);
+    };
+
+    for y in 0..size {
+        for x in 0..size {
+            let offset = Vec2::new((x - size / 2) as f32, (y - size / 2) as f32) * zoom;
+            let position = center.xz().as_vec2() + right * offset.x - forward * offset.y;
+            let column_position = position.floor().as_ivec2();
+
+            let color = match columns.get(&column_position) {
+                Some((color, height)) => {
+                    // Shade by height relative to the player so that terrain is distinguishable.
+                    let brightness =
+                        (1.0 + (*height as f32 - center.y as f32) / 64.0).clamp(0.6, 1.3);
+                    [
+                        (color.red * brightness * 255.0).clamp(0.0, 255.0) as u8,
+                        (color.green * brightness * 255.0).clamp(0.0, 255.0) as u8,
+                        (color.blue * brightness * 255.0).clamp(0.0, 255.0) as u8,
+                        255,
+                    ]
+                }
+                None => [0, 0, 0, 0],
+            };
+
+            set_pixel(x, y, color);
+        }
+    }
+
+    for waypoint in waypoints.waypoints.iter() {
+        let Some((x, y)) = to_pixel(waypoint.position) else {
+            continue;
+        };
+
+        let color = match waypoint.kind {
+            WaypointKind::Waypoint => [40, 120, 255, 255],
+            WaypointKind::Death => [220, 30, 30, 255],
+        };
+
+        for (offset_x, offset_y) in [(0, 0), (1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let (x, y) = (x + offset_x, y + offset_y);
+            if x >= 0 && y >= 0 && x < size && y < size {
+                set_pixel(x, y, color);
+            }
+        }
+    }
+
+    // Player
+    set_pixel(size / 2, size / 2, [255, 255, 255, 255]);
+}
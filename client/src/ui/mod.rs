@@ -12,7 +12,17 @@ use bevy::{
 // TODO: This should not be part of the ui module, remnant from not wanting to expose the server module.
 mod hand;
 
+mod boss_bars;
+pub mod captions;
 mod client;
+mod debug_overlay;
+mod dialogue;
+mod hud;
+mod hunger;
+pub mod input_recording;
+mod item_use;
+mod minimap;
+mod notifications;
 pub mod server;
 // Common widgets used by both ui systems.
 mod widgets;
@@ -27,8 +37,17 @@ impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
             widgets::WidgetPlugin,
+            boss_bars::BossBarPlugin,
+            captions::CaptionPlugin,
             client::GuiPlugin,
+            debug_overlay::DebugOverlayPlugin,
+            dialogue::DialoguePlugin,
             hand::HandPlugin,
+            hud::HudPlugin,
+            hunger::HungerPlugin,
+            item_use::ItemUsePlugin,
+            minimap::MinimapPlugin,
+            notifications::NotificationPlugin,
             server::ServerInterfacesPlugin,
         ))
         .add_systems(Startup, scaling_setup)
@@ -116,13 +135,15 @@ fn change_ui_state(
 struct CursorVisibility {
     gui: bool,
     server: bool,
+    dialogue: bool,
 }
 
 fn cursor_visibiltiy(
     mut window: Query<&mut Window, With<PrimaryWindow>>,
     cursor_visibility: Res<CursorVisibility>,
 ) {
-    let should_be_visible = cursor_visibility.gui || cursor_visibility.server;
+    let should_be_visible =
+        cursor_visibility.gui || cursor_visibility.server || cursor_visibility.dialogue;
     let mut window = window.single_mut();
 
     if should_be_visible && !window.cursor_options.visible {
@@ -0,0 +1,221 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    settings::Settings,
+};
+
+use super::{widgets::TextShadow, DEFAULT_FONT_HANDLE};
+
+// TODO: There is no message for notifications, until there is they are sent as properties named
+// "notification". The icon is a path relative to the server's texture directory.
+//
+// Short messages in the top right corner of the screen, for achievements, players joining,
+// warnings and such. They are shown a few at a time and disappear after their duration, the rest
+// wait in a queue.
+pub struct NotificationPlugin;
+impl Plugin for NotificationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NotificationQueue>()
+            .add_systems(Startup, setup)
+            .add_systems(OnExit(GameState::Playing), remove_notifications)
+            .add_systems(
+                Update,
+                (
+                    handle_notification_properties.run_if(on_event::<ServerProperty>),
+                    show_notifications,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+const TEXTURE_PATH: &str = "server_assets/active/textures/";
+// How many notifications are shown at the same time
+const MAX_SHOWN: usize = 3;
+// Used when the server sends a duration that can't be used
+const DEFAULT_DURATION: f32 = 5.0;
+// Notifications that have not been shown yet are dropped past this, so a server that sends too
+// many doesn't keep the corner busy for minutes.
+const MAX_QUEUED: usize = 32;
+
+struct Notification {
+    duration: Duration,
+    icon: Option<String>,
+    title: String,
+    body: String,
+}
+
+#[derive(Resource, Default)]
+struct NotificationQueue(VecDeque<Notification>);
+
+#[derive(Component)]
+struct NotificationContainer;
+
+#[derive(Component)]
+struct Toast(Timer);
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        NotificationContainer,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(4.0),
+            right: Val::Px(4.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::End,
+            row_gap: Val::Px(2.0),
+            ..default()
+        },
+    ));
+}
+
+fn remove_notifications(
+    mut commands: Commands,
+    mut queue: ResMut<NotificationQueue>,
+    toast_query: Query<Entity, With<Toast>>,
+) {
+    queue.0.clear();
+    for entity in toast_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn handle_notification_properties(
+    mut queue: ResMut<NotificationQueue>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::NOTIFICATION {
+            continue;
+        }
+
+        let Some(properties::Notification {
+            duration,
+            icon,
+            title,
+            body,
+        }) = property.parse()
+        else {
+            continue;
+        };
+
+        let duration = if duration.is_finite() && duration > 0.0 {
+            duration
+        } else {
+            DEFAULT_DURATION
+        };
+        let icon = icon.filter(|icon| !icon.is_empty());
+
+        if title.is_empty() && body.is_empty() {
+            continue;
+        }
+
+        if queue.0.len() >= MAX_QUEUED {
+            queue.0.pop_front();
+        }
+
+        queue.0.push_back(Notification {
+            duration: Duration::from_secs_f32(duration),
+            icon,
+            title,
+            body,
+        });
+    }
+}
+
+fn show_notifications(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    mut queue: ResMut<NotificationQueue>,
+    container_query: Query<Entity, With<NotificationContainer>>,
+    mut toast_query: Query<(Entity, &mut Toast)>,
+) {
+    let mut shown = 0;
+    for (entity, mut toast) in toast_query.iter_mut() {
+        toast.0.tick(time.delta());
+        if toast.0.finished() {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            shown += 1;
+        }
+    }
+
+    while shown < MAX_SHOWN {
+        let Some(notification) = queue.0.pop_front() else {
+            break;
+        };
+        shown += 1;
+
+        let entity = commands
+            .spawn((
+                Toast(Timer::new(notification.duration, TimerMode::Once)),
+                Node {
+                    width: Val::Px(100.0),
+                    padding: UiRect::all(Val::Px(3.0)),
+                    column_gap: Val::Px(3.0),
+                    align_items: AlignItems::Center,
+                    border: UiRect::all(Val::Px(1.0)),
+                    ..default()
+                },
+                BorderColor::from(Color::BLACK),
+                BackgroundColor(settings.text_background(Color::srgba_u8(33, 33, 33, 220))),
+            ))
+            .with_children(|parent| {
+                if let Some(icon) = &notification.icon {
+                    parent.spawn((
+                        ImageNode::new(asset_server.load(TEXTURE_PATH.to_owned() + icon)),
+                        Node {
+                            width: Val::Px(16.0),
+                            height: Val::Px(16.0),
+                            flex_shrink: 0.0,
+                            ..default()
+                        },
+                    ));
+                }
+
+                parent
+                    .spawn(Node {
+                        flex_direction: FlexDirection::Column,
+                        flex_grow: 1.0,
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        if !notification.title.is_empty() {
+                            parent.spawn((
+                                Text::new(notification.title),
+                                TextFont {
+                                    font: DEFAULT_FONT_HANDLE,
+                                    font_size: 6.0,
+                                    ..default()
+                                },
+                                TextColor(
+                                    settings.color_palette.adjust(Color::srgb_u8(255, 255, 85)),
+                                ),
+                                TextShadow::default(),
+                            ));
+                        }
+                        if !notification.body.is_empty() {
+                            parent.spawn((
+                                Text::new(notification.body),
+                                TextFont {
+                                    font: DEFAULT_FONT_HANDLE,
+                                    font_size: 6.0,
+                                    ..default()
+                                },
+                                TextShadow::default(),
+                            ));
+                        }
+                    });
+            })
+            .id();
+
+        commands.entity(container_query.single()).add_child(entity);
+    }
+}
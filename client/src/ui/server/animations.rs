@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, ServerProperty},
+    settings::Settings,
+};
+
+use super::{items::ItemBox, InterfaceConfig, InterfaceNode, InterfacePaths};
+
+// TODO: There is no message for animations, until there is the server plays them through the
+// "interface_animation" property.
+//
+// Interface nodes can be configured with animations that are played by the client, so that
+// interfaces can react to being opened or hovered without the server sending an update for each
+// frame. Animations are configured by name, some names are played automatically:
+//     "open"        when the interface the node is part of is opened
+//     "hover"       when the cursor enters the node
+//     "press"       when the node is clicked
+//     "item_change" on the item boxes of an item section, when their item changes
+// Any other name is only played when the server asks for it.
+pub struct AnimationPlugin;
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayAnimation>().add_systems(
+            Update,
+            (
+                (
+                    handle_animation_properties.run_if(on_event::<ServerProperty>),
+                    play_open_animations,
+                    play_interaction_animations,
+                    play_item_change_animations,
+                ),
+                start_animations,
+                animate,
+            )
+                .chain()
+                .after(super::handle_toggle_events)
+                .after(super::handle_node_visibility_updates)
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+const OPEN: &str = "open";
+pub(super) const HOVER: &str = "hover";
+pub(super) const PRESS: &str = "press";
+const ITEM_CHANGE: &str = "item_change";
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub(super) enum Animation {
+    /// Slides the node into place, starting the offset in pixels away from where it is.
+    Slide { offset: Vec2, duration: f32 },
+    /// Fades the node and its children in from transparent.
+    Fade { duration: f32 },
+    /// Scales the node up to `scale` and back down.
+    Pulse { scale: f32, duration: f32 },
+    /// Sets the background color to `color` and fades it back to the node's own color.
+    Flash { color: Color, duration: f32 },
+}
+
+impl Animation {
+    fn duration(&self) -> f32 {
+        return match self {
+            Self::Slide { duration, .. } => *duration,
+            Self::Fade { duration } => *duration,
+            Self::Pulse { duration, .. } => *duration,
+            Self::Flash { duration, .. } => *duration,
+        };
+    }
+}
+
+// The animations of a node, by name
+#[derive(Component, Deref)]
+pub(super) struct NodeAnimations(pub HashMap<String, Animation>);
+
+#[derive(Event)]
+struct PlayAnimation {
+    entity: Entity,
+    animation: Animation,
+}
+
+#[derive(Component)]
+struct PlayingAnimation {
+    animation: Animation,
+    timer: Timer,
+    // What the animation changes, so that it can be put back when it finishes.
+    original: Original,
+}
+
+enum Original {
+    Position {
+        left: Val,
+        right: Val,
+        top: Val,
+        bottom: Val,
+    },
+    Scale(Vec3),
+    Colors(Vec<OriginalColors>),
+    Background(Color),
+}
+
+struct OriginalColors {
+    entity: Entity,
+    background: Option<Color>,
+    image: Option<Color>,
+    text: Option<Color>,
+}
+
+fn handle_animation_properties(
+    interface_paths: Res<InterfacePaths>,
+    animation_query: Query<&NodeAnimations>,
+    mut property_events: EventReader<ServerProperty>,
+    mut play_events: EventWriter<PlayAnimation>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::INTERFACE_ANIMATION {
+            continue;
+        }
+
+        let Some(properties::InterfaceAnimation {
+            node_path,
+            animation: name,
+        }) = property.parse()
+        else {
+            continue;
+        };
+
+        let Some(entities) = interface_paths.get(&node_path) else {
+            warn!(
+                "Server asked to play the animation '{}' of the interface node '{}', but there \
+                is no node by that name.",
+                name, node_path
+            );
+            continue;
+        };
+
+        for entity in entities.iter().cloned() {
+            if let Some(animation) = animation_query
+                .get(entity)
+                .ok()
+                .and_then(|animations| animations.get(&name))
+            {
+                play_events.send(PlayAnimation {
+                    entity,
+                    animation: animation.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn play_open_animations(
+    opened_query: Query<
+        (Entity, &Visibility),
+        (
+            Changed<Visibility>,
+            Or<(With<InterfaceConfig>, With<InterfaceNode>)>,
+        ),
+    >,
+    children_query: Query<&Children>,
+    animation_query: Query<&NodeAnimations>,
+    mut play_events: EventWriter<PlayAnimation>,
+) {
+    for (entity, visibility) in opened_query.iter() {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        for entity in std::iter::once(entity).chain(children_query.iter_descendants(entity)) {
+            if let Some(animation) = animation_query
+                .get(entity)
+                .ok()
+                .and_then(|animations| animations.get(OPEN))
+            {
+                play_events.send(PlayAnimation {
+                    entity,
+                    animation: animation.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn play_interaction_animations(
+    interaction_query: Query<(Entity, &Interaction, &NodeAnimations), Changed<Interaction>>,
+    mut play_events: EventWriter<PlayAnimation>,
+) {
+    for (entity, interaction, animations) in interaction_query.iter() {
+        let name = match interaction {
+            Interaction::Hovered => HOVER,
+            Interaction::Pressed => PRESS,
+            Interaction::None => continue,
+        };
+
+        if let Some(animation) = animations.get(name) {
+            play_events.send(PlayAnimation {
+                entity,
+                animation: animation.clone(),
+            });
+        }
+    }
+}
+
+fn play_item_change_animations(
+    item_box_query: Query<(Entity, Ref<ItemBox>, &Parent), Changed<ItemBox>>,
+    animation_query: Query<&NodeAnimations>,
+    mut play_events: EventWriter<PlayAnimation>,
+) {
+    for (entity, item_box, parent) in item_box_query.iter() {
+        // Item boxes are added when the interface is first filled, that isn't a change.
+        if item_box.is_added() {
+            continue;
+        }
+
+        if let Some(animation) = animation_query
+            .get(parent.get())
+            .ok()
+            .and_then(|animations| animations.get(ITEM_CHANGE))
+        {
+            play_events.send(PlayAnimation {
+                entity,
+                animation: animation.clone(),
+            });
+        }
+    }
+}
+
+fn start_animations(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut playing_query: Query<&mut PlayingAnimation>,
+    mut node_query: Query<(&mut Node, &mut Transform)>,
+    mut color_query: Query<(
+        Option<&mut BackgroundColor>,
+        Option<&mut ImageNode>,
+        Option<&mut TextColor>,
+    )>,
+    children_query: Query<&Children>,
+    mut play_events: EventReader<PlayAnimation>,
+) {
+    for event in play_events.read() {
+        if settings.reduce_motion
+            && matches!(
+                event.animation,
+                Animation::Slide { .. } | Animation::Pulse { .. }
+            )
+        {
+            continue;
+        }
+
+        // An animation that is already playing is cut short, the new one starts from how the node
+        // looks without it.
+        if let Ok(playing) = playing_query.get(event.entity) {
+            restore(
+                &playing.original,
+                event.entity,
+                &mut node_query,
+                &mut color_query,
+            );
+        }
+
+        let Ok((node, transform)) = node_query.get(event.entity) else {
+            continue;
+        };
+
+        let original = match &event.animation {
+            Animation::Slide { .. } => Original::Position {
+                left: node.left,
+                right: node.right,
+                top: node.top,
+                bottom: node.bottom,
+            },
+            Animation::Pulse { .. } => Original::Scale(transform.scale),
+            Animation::Fade { .. } => Original::Colors(
+                std::iter::once(event.entity)
+                    .chain(children_query.iter_descendants(event.entity))
+                    .filter_map(|entity| {
+                        let (background, image, text) = color_query.get(entity).ok()?;
+                        Some(OriginalColors {
+                            entity,
+                            background: background.map(|background| background.0),
+                            image: image.map(|image| image.color),
+                            text: text.map(|text| text.0),
+                        })
+                    })
+                    .collect(),
+            ),
+            Animation::Flash { .. } => Original::Background(
+                color_query
+                    .get(event.entity)
+                    .ok()
+                    .and_then(|(background, _, _)| background.map(|background| background.0))
+                    .unwrap_or(Color::NONE),
+            ),
+        };
+
+        let playing = PlayingAnimation {
+            timer: Timer::from_seconds(event.animation.duration().max(0.0), TimerMode::Once),
+            animation: event.animation.clone(),
+            original,
+        };
+
+        if let Ok(mut current) = playing_query.get_mut(event.entity) {
+            *current = playing;
+        } else {
+            commands.entity(event.entity).insert(playing);
+        }
+    }
+}
+
+fn animate(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut playing_query: Query<(Entity, &mut PlayingAnimation)>,
+    mut node_query: Query<(&mut Node, &mut Transform)>,
+    mut color_query: Query<(
+        Option<&mut BackgroundColor>,
+        Option<&mut ImageNode>,
+        Option<&mut TextColor>,
+    )>,
+) {
+    for (entity, mut playing) in playing_query.iter_mut() {
+        playing.timer.tick(time.delta());
+
+        if playing.timer.finished() {
+            restore(&playing.original, entity, &mut node_query, &mut color_query);
+            commands.entity(entity).remove::<PlayingAnimation>();
+            continue;
+        }
+
+        let progress = playing.timer.fraction();
+
+        match (&playing.animation, &playing.original) {
+            (
+                Animation::Slide { offset, .. },
+                Original::Position {
+                    left,
+                    right,
+                    top,
+                    bottom,
+                },
+            ) => {
+                let Ok((mut node, _)) = node_query.get_mut(entity) else {
+                    continue;
+                };
+                // Ease out, it moves fast at first and slows down as it comes into place.
+                let offset = *offset * (1.0 - progress).powi(3);
+                // Whichever side the node is positioned from is moved.
+                if *left == Val::Auto && *right != Val::Auto {
+                    node.right = offset_val(*right, -offset.x);
+                } else {
+                    node.left = offset_val(*left, offset.x);
+                }
+                if *top == Val::Auto && *bottom != Val::Auto {
+                    node.bottom = offset_val(*bottom, -offset.y);
+                } else {
+                    node.top = offset_val(*top, offset.y);
+                }
+            }
+            (Animation::Pulse { scale, .. }, Original::Scale(original)) => {
+                let Ok((_, mut transform)) = node_query.get_mut(entity) else {
+                    continue;
+                };
+                let factor = 1.0 + (scale - 1.0) * (progress * std::f32::consts::PI).sin();
+                transform.scale = *original * Vec3::new(factor, factor, 1.0);
+            }
+            (Animation::Fade { .. }, Original::Colors(originals)) => {
+                for original in originals.iter() {
+                    let Ok((background, image, text)) = color_query.get_mut(original.entity) else {
+                        continue;
+                    };
+                    if let (Some(mut background), Some(color)) = (background, original.background) {
+                        background.0 = color.with_alpha(color.alpha() * progress);
+                    }
+                    if let (Some(mut image), Some(color)) = (image, original.image) {
+                        image.color = color.with_alpha(color.alpha() * progress);
+                    }
+                    if let (Some(mut text), Some(color)) = (text, original.text) {
+                        text.0 = color.with_alpha(color.alpha() * progress);
+                    }
+                }
+            }
+            (Animation::Flash { color, .. }, Original::Background(original)) => {
+                let Ok((Some(mut background), _, _)) = color_query.get_mut(entity) else {
+                    continue;
+                };
+                background.0 = original
+                    .to_srgba()
+                    .mix(&color.to_srgba(), 1.0 - progress)
+                    .into();
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn restore(
+    original: &Original,
+    entity: Entity,
+    node_query: &mut Query<(&mut Node, &mut Transform)>,
+    color_query: &mut Query<(
+        Option<&mut BackgroundColor>,
+        Option<&mut ImageNode>,
+        Option<&mut TextColor>,
+    )>,
+) {
+    match original {
+        Original::Position {
+            left,
+            right,
+            top,
+            bottom,
+        } => {
+            if let Ok((mut node, _)) = node_query.get_mut(entity) {
+                node.left = *left;
+                node.right = *right;
+                node.top = *top;
+                node.bottom = *bottom;
+            }
+        }
+        Original::Scale(scale) => {
+            if let Ok((_, mut transform)) = node_query.get_mut(entity) {
+                transform.scale = *scale;
+            }
+        }
+        Original::Colors(originals) => {
+            for original in originals.iter() {
+                let Ok((background, image, text)) = color_query.get_mut(original.entity) else {
+                    continue;
+                };
+                if let (Some(mut background), Some(color)) = (background, original.background) {
+                    background.0 = color;
+                }
+                if let (Some(mut image), Some(color)) = (image, original.image) {
+                    image.color = color;
+                }
+                if let (Some(mut text), Some(color)) = (text, original.text) {
+                    text.0 = color;
+                }
+            }
+        }
+        Original::Background(color) => {
+            if let Ok((Some(mut background), _, _)) = color_query.get_mut(entity) {
+                background.0 = *color;
+            }
+        }
+    }
+}
+
+// Only nodes positioned in pixels can be moved, other units are left as they are.
+fn offset_val(val: Val, offset: f32) -> Val {
+    return match val {
+        Val::Auto => Val::Px(offset),
+        Val::Px(px) => Val::Px(px + offset),
+        other => other,
+    };
+}
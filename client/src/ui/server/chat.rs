@@ -0,0 +1,325 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+
+use crate::{
+    game_state::GameState,
+    networking::{properties, NetworkClient, ServerProperty},
+    settings::Settings,
+    ui::widgets::*,
+};
+
+use super::InterfaceNode;
+
+// Most inputs remembered for each text box
+const MAX_HISTORY: usize = 100;
+// Lines scrolled by page up/down
+const PAGE_LINES: usize = 10;
+// Switches to the next chat channel while typing
+const NEXT_CHANNEL_KEY: KeyCode = KeyCode::Tab;
+
+// Text containers that are configured as chat get scrollback, timestamps and highlighting of the
+// player's name. The text boxes of the interfaces remember what was sent from them, for each
+// server, so that earlier messages and commands can be brought back with the arrow keys. Tab
+// switches between the chat channels the server says the player can talk in.
+pub struct ChatPlugin;
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InputHistory>()
+            .init_resource::<ChatChannels>()
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (load_input_history, send_chat_preferences),
+            )
+            .add_systems(
+                OnExit(GameState::Playing),
+                (save_input_history, clear_chat_channels),
+            )
+            .add_systems(
+                Update,
+                (
+                    browse_input_history,
+                    scroll_chat,
+                    handle_channel_properties.run_if(on_event::<ServerProperty>),
+                    next_chat_channel,
+                    send_chat_preferences.run_if(resource_changed::<Settings>),
+                )
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// Marks a text container as a chat. Lines are expected to be added to the start of the
+/// container, newest first, and it should clip its overflow so that older lines are cut off.
+#[derive(Component, Default)]
+pub struct ChatHistory {
+    // How many of the newest lines are scrolled past
+    offset: usize,
+    // How many lines there were when the offset was last updated, to keep the view in place when
+    // new lines are added.
+    line_count: usize,
+}
+
+/// The text each text box has sent, by the path of the text box. Oldest first.
+#[derive(Resource, Default)]
+pub struct InputHistory {
+    entries: HashMap<String, Vec<String>>,
+    // Position in the history of the focused text box while browsing it, 0 is the newest.
+    browsing: Option<usize>,
+}
+
+impl InputHistory {
+    pub fn push(&mut self, interface_path: &str, text: String) {
+        self.browsing = None;
+
+        let entries = self.entries.entry(interface_path.to_owned()).or_default();
+        // Sending the same thing several times in a row only adds it once
+        if entries.last() == Some(&text) {
+            return;
+        }
+        entries.push(text);
+        if entries.len() > MAX_HISTORY {
+            entries.remove(0);
+        }
+    }
+
+    fn path(net: &NetworkClient) -> Option<std::path::PathBuf> {
+        return Some(net.server_data_directory()?.join("input_history.json"));
+    }
+}
+
+// The channels the player can talk in, as told by the server
+#[derive(Resource, Default)]
+struct ChatChannels {
+    active: String,
+    names: Vec<String>,
+}
+
+// TODO: There's no way to get the local time zone without another dependency, the time is UTC.
+/// The time of day a chat line was received, as "hh:mm"
+pub fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    return format!("{:02}:{:02}", seconds / 3600 % 24, seconds / 60 % 60);
+}
+
+fn load_input_history(net: Res<NetworkClient>, mut input_history: ResMut<InputHistory>) {
+    *input_history = InputHistory::default();
+
+    let Some(path) = InputHistory::path(&net) else {
+        return;
+    };
+
+    let Ok(file) = std::fs::File::open(&path) else {
+        return;
+    };
+
+    match serde_json::from_reader(file) {
+        Ok(entries) => input_history.entries = entries,
+        Err(e) => error!(
+            "Failed to read the input history at '{}', Error: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn save_input_history(net: Res<NetworkClient>, input_history: Res<InputHistory>) {
+    let Some(path) = InputHistory::path(&net) else {
+        return;
+    };
+
+    std::fs::create_dir_all(path.parent().unwrap()).ok();
+
+    let file = match std::fs::File::create(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(
+                "Failed to save the input history to '{}', Error: {}",
+                path.display(),
+                e
+            );
+            return;
+        }
+    };
+
+    if let Err(e) = serde_json::to_writer(file, &input_history.entries) {
+        error!(
+            "Failed to save the input history to '{}', Error: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn browse_input_history(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut input_history: ResMut<InputHistory>,
+    mut focused_text_box: Query<(&mut TextBox, &InterfaceNode), With<FocusedTextBox>>,
+) {
+    let Ok((mut text_box, interface_node)) = focused_text_box.get_single_mut() else {
+        input_history.browsing = None;
+        return;
+    };
+
+    let up = keyboard.just_pressed(KeyCode::ArrowUp);
+    let down = keyboard.just_pressed(KeyCode::ArrowDown);
+    if !up && !down {
+        return;
+    }
+
+    let count = input_history
+        .entries
+        .get(&interface_node.path)
+        .map_or(0, |entries| entries.len());
+    if count == 0 {
+        return;
+    }
+
+    let browsing = match (input_history.browsing, up) {
+        (None, true) => Some(0),
+        (None, false) => None,
+        (Some(position), true) => Some((position + 1).min(count - 1)),
+        // Going past the newest entry goes back to an empty text box
+        (Some(0), false) => None,
+        (Some(position), false) => Some(position - 1),
+    };
+    input_history.browsing = browsing;
+
+    text_box.text = match browsing {
+        Some(position) => input_history.entries[&interface_node.path][count - 1 - position].clone(),
+        None => String::new(),
+    };
+}
+
+// Page up/down and the mouse wheel scroll through the lines while a text box is focused, which is
+// when the chat is open. It jumps back to the newest line when the chat closes.
+fn scroll_chat(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    focused_text_box: Query<(), With<FocusedTextBox>>,
+    mut chat_query: Query<(&mut ChatHistory, &InheritedVisibility, Option<&Children>)>,
+    mut line_query: Query<&mut Node>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+) {
+    let mut scroll: i32 = 0;
+    if !focused_text_box.is_empty() {
+        if keyboard.just_pressed(KeyCode::PageUp) {
+            scroll += PAGE_LINES as i32;
+        }
+        if keyboard.just_pressed(KeyCode::PageDown) {
+            scroll -= PAGE_LINES as i32;
+        }
+        for event in mouse_wheel_events.read() {
+            scroll += match event.unit {
+                MouseScrollUnit::Line => event.y.round() as i32,
+                MouseScrollUnit::Pixel => (event.y / 16.0).round() as i32,
+            };
+        }
+    } else {
+        mouse_wheel_events.clear();
+    }
+
+    for (mut chat, visibility, children) in chat_query.iter_mut() {
+        let Some(children) = children else {
+            continue;
+        };
+
+        let line_count = children.len();
+        let mut offset = chat.offset;
+        if !visibility.get() {
+            offset = 0;
+        } else {
+            if offset > 0 && line_count > chat.line_count {
+                offset += line_count - chat.line_count;
+            }
+            offset =
+                (offset as i32 + scroll).clamp(0, line_count.saturating_sub(1) as i32) as usize;
+        }
+
+        if chat.line_count != line_count {
+            chat.line_count = line_count;
+        }
+
+        if offset == chat.offset {
+            continue;
+        }
+        chat.offset = offset;
+
+        for (index, child) in children.iter().enumerate() {
+            let Ok(mut node) = line_query.get_mut(*child) else {
+                continue;
+            };
+            let display = if index < offset {
+                Display::None
+            } else {
+                Display::Flex
+            };
+            if node.display != display {
+                node.display = display;
+            }
+        }
+    }
+}
+
+// TODO: There are no messages for chat preferences, they are sent as the "chat_hide_connections"
+// and "chat_filter" properties.
+fn send_chat_preferences(net: Res<NetworkClient>, settings: Res<Settings>) {
+    net.send_property(
+        properties::CHAT_HIDE_CONNECTIONS,
+        &settings.chat_hide_connections,
+    );
+    net.send_property(properties::CHAT_FILTER, &settings.chat_filter);
+}
+
+fn clear_chat_channels(mut chat_channels: ResMut<ChatChannels>) {
+    *chat_channels = ChatChannels::default();
+}
+
+// TODO: There are no messages for chat channels. The server sends the "chat_channels" property,
+// with the active channel and all the channels the player can talk in. The client switches
+// channel with the "chat_channel" property.
+fn handle_channel_properties(
+    mut chat_channels: ResMut<ChatChannels>,
+    mut property_events: EventReader<ServerProperty>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::CHAT_CHANNELS {
+            continue;
+        }
+
+        let Some(properties::ChatChannels { active, channels }) = property.parse() else {
+            continue;
+        };
+        chat_channels.active = active;
+        chat_channels.names = channels;
+    }
+}
+
+fn next_chat_channel(
+    net: Res<NetworkClient>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    chat_channels: Res<ChatChannels>,
+    focused_text_box: Query<(), With<FocusedTextBox>>,
+) {
+    if focused_text_box.is_empty()
+        || !keyboard.just_pressed(NEXT_CHANNEL_KEY)
+        || chat_channels.names.len() < 2
+    {
+        return;
+    }
+
+    let next = chat_channels
+        .names
+        .iter()
+        .position(|name| *name == chat_channels.active)
+        .map_or(0, |position| (position + 1) % chat_channels.names.len());
+    // The server answers with the new list of channels, the active channel is only changed then.
+    net.send_property(properties::CHAT_CHANNEL, &chat_channels.names[next]);
+}
@@ -0,0 +1,383 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use fmc_protocol::messages;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game_state::GameState,
+    networking::NetworkClient,
+    ui::server::{chat::ChatHistory, InterfaceNode},
+};
+
+use super::{CursorItemBox, ItemBox, ItemBoxSection, Items};
+
+/// Hold while clicking an item box to lock or unlock it
+pub const LOCK_KEY: KeyCode = KeyCode::ControlLeft;
+// Tint of the item boxes that are locked
+const LOCKED_COLOR: Color = Color::srgba(1.0, 0.8, 0.2, 0.35);
+
+// The layout of the hotbar can be saved under a name and brought back later, the items are moved
+// back into place from wherever they are stored. Item boxes can also be locked so that their
+// items aren't moved by accident. Both are saved for each server.
+//
+// Loadouts are managed by typing "/loadout save|load|delete <name>" or "/loadout list" into a
+// text box, the client handles it itself instead of sending it to the server.
+pub struct LoadoutPlugin;
+impl Plugin for LoadoutPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Loadouts>()
+            .add_event::<LoadoutCommand>()
+            .add_systems(OnEnter(GameState::Playing), load_loadouts)
+            .add_systems(OnExit(GameState::Playing), save_loadouts)
+            .add_systems(
+                Update,
+                (toggle_lock, handle_loadout_commands, show_locked_item_boxes)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// Text sent from a text box that starts with "/loadout"
+#[derive(Event)]
+pub struct LoadoutCommand(pub String);
+
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Loadouts {
+    // The item in each slot of the hotbar, by the name of the item's config file since the ids
+    // can change when the server restarts.
+    loadouts: HashMap<String, Vec<Option<String>>>,
+    // Item boxes that can't be taken from or placed into, by interface path and index
+    locked: HashSet<(String, usize)>,
+}
+
+impl Loadouts {
+    pub fn is_locked(&self, interface_path: &str, index: usize) -> bool {
+        return self.locked.contains(&(interface_path.to_owned(), index));
+    }
+
+    fn path(net: &NetworkClient) -> Option<std::path::PathBuf> {
+        return Some(net.server_data_directory()?.join("loadouts.json"));
+    }
+}
+
+fn load_loadouts(net: Res<NetworkClient>, mut loadouts: ResMut<Loadouts>) {
+    *loadouts = Loadouts::default();
+
+    let Some(path) = Loadouts::path(&net) else {
+        return;
+    };
+
+    let Ok(file) = std::fs::File::open(&path) else {
+        return;
+    };
+
+    match serde_json::from_reader(file) {
+        Ok(l) => *loadouts = l,
+        Err(e) => error!(
+            "Failed to read loadouts from '{}', Error: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn save_loadouts(net: Res<NetworkClient>, loadouts: Res<Loadouts>) {
+    let Some(path) = Loadouts::path(&net) else {
+        return;
+    };
+
+    std::fs::create_dir_all(path.parent().unwrap()).ok();
+
+    let file = match std::fs::File::create(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(
+                "Failed to save loadouts to '{}', Error: {}",
+                path.display(),
+                e
+            );
+            return;
+        }
+    };
+
+    if let Err(e) = serde_json::to_writer(file, &*loadouts) {
+        error!(
+            "Failed to save loadouts to '{}', Error: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn toggle_lock(
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut loadouts: ResMut<Loadouts>,
+    item_box_section_query: Query<&InterfaceNode, With<ItemBoxSection>>,
+    item_box_query: Query<(&ItemBox, &Interaction, &Parent)>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Left) || !keyboard_input.pressed(LOCK_KEY) {
+        return;
+    }
+
+    let Some((item_box, _, parent)) = item_box_query
+        .iter()
+        .find(|(_, interaction, _)| **interaction != Interaction::None)
+    else {
+        return;
+    };
+
+    let Ok(interface_node) = item_box_section_query.get(parent.get()) else {
+        return;
+    };
+
+    let key = (interface_node.path.clone(), item_box.index);
+    if !loadouts.locked.remove(&key) {
+        loadouts.locked.insert(key);
+    }
+}
+
+fn show_locked_item_boxes(
+    mut commands: Commands,
+    loadouts: Res<Loadouts>,
+    item_box_section_query: Query<&InterfaceNode, With<ItemBoxSection>>,
+    item_box_query: Query<(Entity, &ItemBox, &Parent)>,
+    added_item_boxes: Query<(), Added<ItemBox>>,
+) {
+    if !loadouts.is_changed() && added_item_boxes.is_empty() {
+        return;
+    }
+
+    for (entity, item_box, parent) in item_box_query.iter() {
+        let Ok(interface_node) = item_box_section_query.get(parent.get()) else {
+            continue;
+        };
+
+        let color = if loadouts.is_locked(&interface_node.path, item_box.index) {
+            LOCKED_COLOR
+        } else {
+            Color::NONE
+        };
+        commands.entity(entity).insert(BackgroundColor(color));
+    }
+}
+
+fn handle_loadout_commands(
+    net: Res<NetworkClient>,
+    items: Res<Items>,
+    mut loadouts: ResMut<Loadouts>,
+    chat_query: Query<&InterfaceNode, With<ChatHistory>>,
+    item_box_section_query: Query<(&ItemBoxSection, &InterfaceNode, Option<&Children>)>,
+    mut item_box_query: Query<&mut ItemBox>,
+    mut cursor_item_box_query: Query<&mut CursorItemBox>,
+    mut loadout_commands: EventReader<LoadoutCommand>,
+    mut item_box_update_events: EventWriter<messages::InterfaceItemBoxUpdate>,
+    mut text_update_events: EventWriter<messages::InterfaceTextUpdate>,
+) {
+    // Answers are shown in the chat, as if the server sent them
+    let mut reply = |text: String| {
+        if let Ok(interface_node) = chat_query.get_single() {
+            text_update_events.send(messages::InterfaceTextUpdate {
+                interface_path: interface_node.path.clone(),
+                index: i32::MAX,
+                text,
+                font_size: 8.0,
+                color: "#ffffff".to_owned(),
+            });
+        }
+    };
+
+    for command in loadout_commands.read() {
+        let args: Vec<&str> = command.0.split_whitespace().skip(1).collect();
+
+        let hotbar = item_box_section_query
+            .iter()
+            .find(|(section, _, _)| section.is_equipment);
+
+        match args.as_slice() {
+            ["list"] => {
+                let mut names: Vec<&String> = loadouts.loadouts.keys().collect();
+                names.sort();
+                if names.is_empty() {
+                    reply("There are no saved loadouts".to_owned());
+                } else {
+                    reply(format!(
+                        "Loadouts: {}",
+                        names
+                            .iter()
+                            .map(|name| name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+            }
+            ["delete", name] => {
+                if loadouts.loadouts.remove(*name).is_some() {
+                    reply(format!("Deleted the loadout '{}'", name));
+                } else {
+                    reply(format!("There is no loadout named '{}'", name));
+                }
+            }
+            ["save", name] => {
+                let Some((_, _, Some(children))) = hotbar else {
+                    reply("There is no hotbar to save".to_owned());
+                    continue;
+                };
+
+                let mut slots = vec![None; children.len()];
+                for item_box in item_box_query.iter_many(children) {
+                    let Some(slot) = slots.get_mut(item_box.index) else {
+                        continue;
+                    };
+                    *slot = item_box
+                        .item_stack
+                        .item
+                        .and_then(|item_id| items.get_filename(&item_id))
+                        .map(|filename| filename.to_owned());
+                }
+
+                loadouts.loadouts.insert(name.to_string(), slots);
+                reply(format!("Saved the loadout '{}'", name));
+            }
+            ["load", name] => {
+                let Some(loadout) = loadouts.loadouts.get(*name) else {
+                    reply(format!("There is no loadout named '{}'", name));
+                    continue;
+                };
+
+                let Some((_, hotbar_node, Some(hotbar_children))) = hotbar else {
+                    reply("There is no hotbar to load the loadout into".to_owned());
+                    continue;
+                };
+
+                let mut cursor_box = cursor_item_box_query.single_mut();
+                if !cursor_box.is_empty() {
+                    reply("Put down the held item before loading a loadout".to_owned());
+                    continue;
+                }
+
+                // Items are taken from the sections that items can be quick placed into, which is
+                // where the player stores them, and from the rest of the hotbar. Sections that
+                // are shown in several interfaces are only included once.
+                let mut source_paths = HashSet::new();
+                let mut sources = Vec::new();
+                for (section, interface_node, children) in item_box_section_query.iter() {
+                    let Some(children) = children else {
+                        continue;
+                    };
+
+                    if (section.allow_quick_place && section.movable_items
+                        || interface_node.path == hotbar_node.path)
+                        && source_paths.insert(interface_node.path.clone())
+                    {
+                        for entity in children.iter() {
+                            sources.push((interface_node.path.clone(), *entity));
+                        }
+                    }
+                }
+
+                let mut item_box_update = messages::InterfaceItemBoxUpdate::default();
+
+                for (slot, wanted) in loadout.iter().enumerate() {
+                    // Slots that were empty when saved are left as they are
+                    let Some(wanted) = wanted.as_ref().and_then(|name| items.get_id(name)) else {
+                        continue;
+                    };
+                    let Some(slot_entity) = hotbar_children.get(slot) else {
+                        break;
+                    };
+
+                    if loadouts.is_locked(&hotbar_node.path, slot)
+                        || item_box_query.get(*slot_entity).unwrap().item_stack.item == Some(wanted)
+                    {
+                        continue;
+                    }
+
+                    // The biggest stack of the item that isn't already in place
+                    let Some((source_path, source_entity)) = sources
+                        .iter()
+                        .filter_map(|(path, entity)| {
+                            let item_box = item_box_query.get(*entity).ok()?;
+                            let in_place = *path == hotbar_node.path && item_box.index <= slot;
+                            (!in_place
+                                && !loadouts.is_locked(path, item_box.index)
+                                && item_box.item_stack.item == Some(wanted))
+                            .then_some((path, *entity, item_box.item_stack.size))
+                        })
+                        .max_by_key(|(_, _, size)| *size)
+                        .map(|(path, entity, _)| (path, entity))
+                    else {
+                        continue;
+                    };
+
+                    // Take the stack, put it in the slot, and put whatever was in the slot where
+                    // the stack was.
+                    let mut source = item_box_query.get_mut(source_entity).unwrap();
+                    let size = source.item_stack.size;
+                    let transfered = source
+                        .item_stack
+                        .transfer_to(&mut cursor_box.item_stack, size);
+                    net.send_message(messages::InterfaceInteraction::TakeItem {
+                        interface_path: source_path.clone(),
+                        index: source.index as u32,
+                        quantity: transfered,
+                    });
+                    let source_index = source.index;
+
+                    let mut slot_box = item_box_query.get_mut(*slot_entity).unwrap();
+                    let size = cursor_box.item_stack.size;
+                    let transfered = cursor_box
+                        .item_stack
+                        .transfer_to(&mut slot_box.item_stack, size);
+                    net.send_message(messages::InterfaceInteraction::PlaceItem {
+                        interface_path: hotbar_node.path.clone(),
+                        index: slot as u32,
+                        quantity: transfered,
+                    });
+                    add_item_box(&mut item_box_update, &hotbar_node.path, &slot_box);
+
+                    let mut source = item_box_query.get_mut(source_entity).unwrap();
+                    if !cursor_box.is_empty() {
+                        let size = cursor_box.item_stack.size;
+                        let transfered = cursor_box
+                            .item_stack
+                            .transfer_to(&mut source.item_stack, size);
+                        net.send_message(messages::InterfaceInteraction::PlaceItem {
+                            interface_path: source_path.clone(),
+                            index: source_index as u32,
+                            quantity: transfered,
+                        });
+                    }
+                    add_item_box(&mut item_box_update, source_path, &source);
+                }
+
+                // Shows the change in every interface the sections are in, see
+                // left_click_item_box.
+                item_box_update_events.send(item_box_update);
+                reply(format!("Loaded the loadout '{}'", name));
+            }
+            _ => reply("Usage: /loadout <save|load|delete> <name>, /loadout list".to_owned()),
+        }
+    }
+}
+
+fn add_item_box(
+    item_box_update: &mut messages::InterfaceItemBoxUpdate,
+    interface_path: &str,
+    item_box: &ItemBox,
+) {
+    if item_box.item_stack.is_empty() {
+        item_box_update.add_empty_itembox(interface_path, item_box.index as u32);
+    } else {
+        item_box_update.add_itembox(
+            interface_path,
+            item_box.index as u32,
+            item_box.item_stack.item.unwrap(),
+            item_box.item_stack.size,
+            item_box.item_stack.durability,
+            item_box.item_stack.description.as_deref(),
+        );
+    }
+}
@@ -8,20 +8,29 @@ use serde::{Deserialize, Serialize};
 use crate::{
     assets::models::{ModelAssetId, Models},
     game_state::GameState,
-    networking::NetworkClient,
+    networking::{properties, NetworkClient},
+    settings::Settings,
+    ui::{hud::HudSettings, widgets::TextBox},
     world::blocks::{BlockId, Blocks},
 };
 
 use super::{InterfaceNode, InterfacePaths};
 
+mod loadouts;
+
+pub use loadouts::LoadoutCommand;
+use loadouts::{Loadouts, LOCK_KEY};
+
 pub type ItemId = u32;
 
 const ITEM_IMAGE_PATH: &str = "server_assets/active/textures/items/";
+// Color of the item images that don't match the search
+const UNMATCHED_ITEM_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.2);
 
 pub struct ItemPlugin;
 impl Plugin for ItemPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
+        app.add_plugins(loadouts::LoadoutPlugin).add_systems(
             Update,
             (
                 handle_item_box_updates,
@@ -31,7 +40,10 @@ impl Plugin for ItemPlugin {
                 right_click_item_box,
                 update_cursor_image.after(left_click_item_box),
                 update_cursor_item_stack_position,
+                update_item_tooltip,
                 keyboard_select_item_box,
+                request_sort,
+                search_item_boxes.after(handle_item_box_updates),
             )
                 .run_if(in_state(GameState::Playing)),
         );
@@ -51,6 +63,23 @@ pub struct ItemConfig {
     pub categories: Option<HashSet<String>>,
     /// Block that is placed when the item is used on a surface.
     pub block: Option<BlockId>,
+    /// Max durability of the item, items without it don't break.
+    pub durability: Option<u32>,
+    /// Present if the item is used by holding down the use button.
+    pub usage: Option<ItemUsage>,
+}
+
+/// How long an item takes to use, e.g. eating food or charging a bow.
+#[derive(Deserialize, Clone, Copy)]
+pub struct ItemUsage {
+    /// Seconds it takes to use the item
+    pub duration: f32,
+    /// If the item can be released early, the server decides what to do with the charge
+    #[serde(default)]
+    pub chargeable: bool,
+    /// Seconds after a use before the item can be used again
+    #[serde(default)]
+    pub cooldown: f32,
 }
 
 #[derive(Deserialize)]
@@ -61,6 +90,8 @@ struct ItemConfigJson {
     stack_size: u32,
     categories: Option<HashSet<String>>,
     block: Option<String>,
+    durability: Option<u32>,
+    usage: Option<ItemUsage>,
     //properties: serde_json::Map<String, serde_json::Value>,
 }
 
@@ -68,6 +99,8 @@ struct ItemConfigJson {
 #[derive(Resource)]
 pub struct Items {
     pub configs: HashMap<ItemId, ItemConfig>,
+    // Map from the filename of the item's config to its id
+    ids: HashMap<String, ItemId>,
 }
 
 impl Items {
@@ -77,6 +110,20 @@ impl Items {
     pub fn get(&self, id: &ItemId) -> &ItemConfig {
         return self.configs.get(id).unwrap();
     }
+
+    pub fn get_id(&self, filename: &str) -> Option<ItemId> {
+        return self.ids.get(filename).cloned();
+    }
+
+    /// The filename of the item's config, unlike the id it stays the same when the server
+    /// restarts.
+    pub fn get_filename(&self, id: &ItemId) -> Option<&str> {
+        return self
+            .ids
+            .iter()
+            .find(|(_, item_id)| *item_id == id)
+            .map(|(filename, _)| filename.as_str());
+    }
 }
 
 // TODO: Need to validate that the models used for equipping have a "left_click" and (maybe) a
@@ -154,6 +201,8 @@ pub fn load_items(
             stack_size: json_config.stack_size,
             categories: json_config.categories,
             block: block_id,
+            durability: json_config.durability,
+            usage: json_config.usage,
         };
 
         if !std::path::Path::new(&config.image_path).exists() {
@@ -168,7 +217,10 @@ pub fn load_items(
         configs.insert(*id, config);
     }
 
-    commands.insert_resource(Items { configs });
+    commands.insert_resource(Items {
+        configs,
+        ids: server_config.item_ids.clone(),
+    });
 }
 
 /// ItemStacks are used to represent the data part of an item box in an interface.
@@ -180,6 +232,10 @@ pub struct ItemStack {
     max_size: Option<u32>,
     // Current stack size.
     pub size: u32,
+    // Remaining durability of the item, as sent by the server.
+    pub durability: Option<u32>,
+    // Name and modifiers of the item, one per line, when they differ from the item's config.
+    pub description: Option<String>,
 }
 
 impl ItemStack {
@@ -188,6 +244,8 @@ impl ItemStack {
             item: Some(item),
             max_size: Some(max_size),
             size,
+            durability: None,
+            description: None,
         };
     }
 
@@ -200,6 +258,8 @@ impl ItemStack {
         if self.size == 0 {
             self.item = None;
             self.max_size = None;
+            self.durability = None;
+            self.description = None;
         }
     }
 
@@ -217,6 +277,8 @@ impl ItemStack {
         } else if other.is_empty() {
             other.item = self.item.clone();
             other.max_size = self.max_size.clone();
+            other.durability = self.durability;
+            other.description = self.description.clone();
 
             amount = std::cmp::min(amount, self.size);
 
@@ -272,6 +334,8 @@ pub struct ItemBoxSection {
     /// Whether items should be equipped by the hand on selection.
     #[serde(rename = "equipment")]
     pub is_equipment: bool,
+    /// If the player can ask the server to sort the items by middle clicking the section.
+    sortable: bool,
 }
 
 impl ItemBoxSection {
@@ -299,6 +363,7 @@ impl Default for ItemBoxSection {
             allowed_item_types: None,
             movable_items: true,
             is_equipment: false,
+            sortable: false,
         }
     }
 }
@@ -308,6 +373,7 @@ fn handle_item_box_updates(
     mut commands: Commands,
     net: Res<NetworkClient>,
     asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
     interface_paths: Res<InterfacePaths>,
     items: Res<Items>,
     interface_item_box_query: Query<Option<&Children>, With<ItemBoxSection>>,
@@ -359,11 +425,14 @@ fn handle_item_box_updates(
                                 return;
                             }
                         };
-                        ItemStack::new(
+                        let mut item_stack = ItemStack::new(
                             *item_id,
                             item_config.stack_size,
                             item_box.item_stack.quantity,
-                        )
+                        );
+                        item_stack.durability = item_box.item_stack.durability;
+                        item_stack.description = item_box.item_stack.description.clone();
+                        item_stack
                     } else {
                         ItemStack::default()
                     };
@@ -384,6 +453,46 @@ fn handle_item_box_updates(
                         entity_commands
                     };
 
+                    // Durability bar, shown once the item has taken damage.
+                    let durability_fraction = item_stack.item.and_then(|item_id| {
+                        let max = items.get(&item_id).durability?;
+                        let durability = item_stack.durability?;
+                        (durability < max).then(|| durability as f32 / max.max(1) as f32)
+                    });
+                    if let Some(fraction) = durability_fraction {
+                        entity_commands.with_children(|parent| {
+                            parent
+                                .spawn((
+                                    Node {
+                                        position_type: PositionType::Absolute,
+                                        left: Val::Px(1.0),
+                                        bottom: Val::Px(1.0),
+                                        width: Val::Px(12.0),
+                                        height: Val::Px(1.0),
+                                        ..default()
+                                    },
+                                    BackgroundColor(Color::BLACK),
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn((
+                                        Node {
+                                            width: Val::Percent(fraction * 100.0),
+                                            height: Val::Percent(100.0),
+                                            ..default()
+                                        },
+                                        // Goes from green to red as the durability runs out
+                                        BackgroundColor(
+                                            settings.color_palette.adjust(Color::srgb(
+                                                1.0 - fraction,
+                                                fraction,
+                                                0.0,
+                                            )),
+                                        ),
+                                    ));
+                                });
+                        });
+                    }
+
                     // Item count text
                     entity_commands.with_children(|parent| {
                         parent.spawn((
@@ -513,6 +622,7 @@ fn handle_item_box_updates(
 fn left_click_item_box(
     net: Res<NetworkClient>,
     items: Res<Items>,
+    loadouts: Res<Loadouts>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     item_box_section_query: Query<(&ItemBoxSection, &InterfaceNode)>,
@@ -528,6 +638,13 @@ fn left_click_item_box(
         let mut cursor_box = cursor_item_box_query.single_mut();
         let (item_box_section, interface_node) = item_box_section_query.get(parent.get()).unwrap();
 
+        // Clicking while holding the lock key locks the box instead
+        if keyboard_input.pressed(LOCK_KEY)
+            || loadouts.is_locked(&interface_node.path, item_box.index)
+        {
+            continue;
+        }
+
         if mouse_button_input.just_pressed(MouseButton::Left)
             && !keyboard_input.pressed(KeyCode::ShiftLeft)
         {
@@ -576,8 +693,8 @@ fn left_click_item_box(
                 item_box.index as u32,
                 item_box.item_stack.item.unwrap(),
                 item_box.item_stack.size,
-                None,
-                None,
+                item_box.item_stack.durability,
+                item_box.item_stack.description.as_deref(),
             );
         }
 
@@ -594,6 +711,7 @@ fn left_click_item_box(
 fn right_click_item_box(
     net: Res<NetworkClient>,
     items: Res<Items>,
+    loadouts: Res<Loadouts>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
     item_box_section_query: Query<(&ItemBoxSection, &InterfaceNode)>,
     mut item_box_query: Query<(&mut ItemBox, &Interaction, &Parent)>,
@@ -618,6 +736,10 @@ fn right_click_item_box(
     let mut cursor_box = cursor_item_box_query.single_mut();
     let (item_box_section, interface_node) = item_box_section_query.get(parent.get()).unwrap();
 
+    if loadouts.is_locked(&interface_node.path, item_box.index) {
+        return;
+    }
+
     if cursor_box.is_empty() && !item_box.is_empty() {
         // TODO: This is a special condition for item boxes that are considered
         // output-only. e.g. crafting output. Given all the different actions that can
@@ -692,8 +814,8 @@ fn right_click_item_box(
             item_box.index as u32,
             item_box.item_stack.item.unwrap(),
             item_box.item_stack.size,
-            None,
-            None,
+            item_box.item_stack.durability,
+            item_box.item_stack.description.as_deref(),
         );
     }
 
@@ -706,6 +828,90 @@ fn right_click_item_box(
     item_box_update_events.send(item_box_update);
 }
 
+// The server sorts the items and sends the section back, the client doesn't change its copy.
+//
+// TODO: There is no message for this, it is sent as the "sort_interface" property.
+fn request_sort(
+    net: Res<NetworkClient>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    item_box_section_query: Query<(&ItemBoxSection, &InterfaceNode)>,
+    item_box_query: Query<(&Interaction, &Parent), With<ItemBox>>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Middle) {
+        return;
+    }
+
+    let Some((_, parent)) = item_box_query
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Hovered)
+    else {
+        return;
+    };
+
+    let Ok((item_box_section, interface_node)) = item_box_section_query.get(parent.get()) else {
+        return;
+    };
+
+    if item_box_section.sortable {
+        net.send_property(properties::SORT_INTERFACE, &interface_node.path);
+    }
+}
+
+/// Text box that dims the items in an item box section that don't match what is typed into it.
+/// Items are matched by the name shown in their tooltip.
+#[derive(Component)]
+pub struct ItemSearch {
+    /// Interface path of the item box section that is searched
+    pub section_path: String,
+}
+
+fn search_item_boxes(
+    net: Res<NetworkClient>,
+    items: Res<Items>,
+    interface_paths: Res<InterfacePaths>,
+    search_query: Query<(Ref<TextBox>, &ItemSearch)>,
+    item_box_section_query: Query<&Children, With<ItemBoxSection>>,
+    mut item_box_query: Query<(Ref<ItemBox>, &mut ImageNode)>,
+) {
+    for (text_box, item_search) in search_query.iter() {
+        let Some(section_entities) = interface_paths.get(&item_search.section_path) else {
+            net.disconnect(&format!(
+                "Misconfigured assets: An item search is configured to search the '{}' interface, \
+                but there is no interface by that name.",
+                &item_search.section_path
+            ));
+            return;
+        };
+
+        let search = text_box.text.to_lowercase();
+
+        for children in item_box_section_query.iter_many(section_entities) {
+            let mut item_boxes = item_box_query.iter_many_mut(children);
+            while let Some((item_box, mut image)) = item_boxes.fetch_next() {
+                // Updates from the server replace the color
+                if !text_box.is_changed() && !item_box.is_changed() {
+                    continue;
+                }
+
+                let Some(item_id) = item_box.item_stack.item else {
+                    continue;
+                };
+
+                let name = match &item_box.item_stack.description {
+                    Some(description) => description.to_lowercase(),
+                    None => items.get(&item_id).name.to_lowercase(),
+                };
+
+                image.color = if name.contains(&search) {
+                    Color::WHITE
+                } else {
+                    UNMATCHED_ITEM_COLOR
+                };
+            }
+        }
+    }
+}
+
 fn update_cursor_item_stack_position(
     ui_scale: Res<UiScale>,
     mut cursor_move_event: EventReader<CursorMoved>,
@@ -718,6 +924,125 @@ fn update_cursor_item_stack_position(
     }
 }
 
+/// Text box that shows the name of the item box the cursor hovers, or of the equipped item when
+/// it changes.
+#[derive(Component)]
+pub struct ItemTooltip;
+
+// Seconds the name of the equipped item is shown for after it changes
+const EQUIPPED_TOOLTIP_DURATION: f32 = 2.0;
+
+// The equipped item the tooltip was last shown for
+#[derive(Default)]
+struct EquippedTooltip {
+    item_box: Option<Entity>,
+    text: String,
+    shown_at: f32,
+}
+
+fn update_item_tooltip(
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    ui_scale: Res<UiScale>,
+    items: Res<Items>,
+    item_box_query: Query<(
+        &ItemBox,
+        &Interaction,
+        &InheritedVisibility,
+        &GlobalTransform,
+        &ComputedNode,
+    )>,
+    item_box_section_query: Query<(&ItemBoxSection, &SelectedItemBox, &InheritedVisibility)>,
+    cursor_item_box_query: Query<&CursorItemBox>,
+    mut cursor_move_event: EventReader<CursorMoved>,
+    mut cursor_position: Local<Vec2>,
+    mut equipped: Local<EquippedTooltip>,
+    mut tooltip_query: Query<
+        (&mut Text, &mut TextFont, &mut Node, &mut Visibility),
+        With<ItemTooltip>,
+    >,
+) {
+    let Ok((mut text, mut font, mut node, mut visibility)) = tooltip_query.get_single_mut() else {
+        return;
+    };
+
+    for cursor_movement in cursor_move_event.read() {
+        *cursor_position = cursor_movement.position / ui_scale.0 as f32;
+    }
+
+    let tooltip_text = |item_stack: &ItemStack| match &item_stack.description {
+        Some(description) => description.clone(),
+        None => items.get(&item_stack.item.unwrap()).name.clone(),
+    };
+
+    // The equipped item is the selected item box of the visible equipment section.
+    let equipped_box = item_box_section_query
+        .iter()
+        .find(|(section, _, visibility)| section.is_equipment && visibility.get())
+        .and_then(|(_, selected, _)| {
+            let (item_box, _, _, transform, computed_node) = item_box_query.get(selected.0).ok()?;
+            Some((selected.0, item_box, transform, computed_node))
+        });
+
+    let equipped_text = equipped_box
+        .filter(|(_, item_box, _, _)| !item_box.is_empty())
+        .map(|(_, item_box, _, _)| tooltip_text(&item_box.item_stack))
+        .unwrap_or_default();
+    let equipped_entity = equipped_box.map(|(entity, _, _, _)| entity);
+    if equipped.item_box != equipped_entity || equipped.text != equipped_text {
+        *equipped = EquippedTooltip {
+            item_box: equipped_entity,
+            text: equipped_text,
+            shown_at: time.elapsed_secs(),
+        };
+    }
+
+    // No tooltip while an item is held, it would cover the item boxes it's placed in.
+    let hovered = if cursor_item_box_query.single().is_empty() {
+        item_box_query
+            .iter()
+            .find(|(item_box, interaction, visibility, _, _)| {
+                **interaction == Interaction::Hovered && visibility.get() && !item_box.is_empty()
+            })
+            .map(|(item_box, _, _, _, _)| &item_box.item_stack)
+    } else {
+        None
+    };
+
+    let (tooltip, position) = if let Some(item_stack) = hovered {
+        (
+            tooltip_text(item_stack),
+            *cursor_position + Vec2::new(8.0, -8.0),
+        )
+    } else if let Some((_, _, transform, computed_node)) = equipped_box.filter(|_| {
+        !equipped.text.is_empty()
+            && time.elapsed_secs() - equipped.shown_at < EQUIPPED_TOOLTIP_DURATION
+    }) {
+        // Above the item box, the node's position is in physical pixels.
+        let top_left = (transform.translation().truncate() - computed_node.size() / 2.0)
+            * computed_node.inverse_scale_factor();
+        (equipped.text.clone(), top_left - Vec2::new(0.0, 14.0))
+    } else {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    };
+
+    if node.left != Val::Px(position.x) || node.top != Val::Px(position.y) {
+        node.left = Val::Px(position.x);
+        node.top = Val::Px(position.y);
+    }
+
+    if text.0 != tooltip {
+        text.0 = tooltip;
+        *font = TextFont {
+            font: asset_server.load("server_assets/active/font.otf"),
+            font_size: 8.0,
+            font_smoothing: FontSmoothing::None,
+        };
+    }
+    visibility.set_if_neq(Visibility::Visible);
+}
+
 fn update_cursor_image(
     asset_server: Res<AssetServer>,
     items: Res<Items>,
@@ -786,56 +1111,43 @@ fn initial_select_item_box(
 
 fn keyboard_select_item_box(
     keyboard: Res<ButtonInput<KeyCode>>,
-    mut item_box_section_query: Query<
-        (&Children, &Visibility, &mut SelectedItemBox),
-        With<ItemBoxSection>,
-    >,
+    hud_settings: Res<HudSettings>,
+    mut item_box_section_query: Query<(
+        &ItemBoxSection,
+        &Children,
+        &Visibility,
+        &mut SelectedItemBox,
+    )>,
 ) {
     for key in keyboard.get_just_pressed() {
-        for (children, visibility, mut selected) in item_box_section_query.iter_mut() {
+        let index = match key {
+            KeyCode::Digit1 => 0,
+            KeyCode::Digit2 => 1,
+            KeyCode::Digit3 => 2,
+            KeyCode::Digit4 => 3,
+            KeyCode::Digit5 => 4,
+            KeyCode::Digit6 => 5,
+            KeyCode::Digit7 => 6,
+            KeyCode::Digit8 => 7,
+            KeyCode::Digit9 => 8,
+            _ => continue,
+        };
+
+        for (item_box_section, children, visibility, mut selected) in
+            item_box_section_query.iter_mut()
+        {
             if visibility == Visibility::Hidden {
                 continue;
             }
 
-            *selected = match key {
-                KeyCode::Digit1 => match children.get(0) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit2 => match children.get(1) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit3 => match children.get(2) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit4 => match children.get(3) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit5 => match children.get(4) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit6 => match children.get(5) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit7 => match children.get(6) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit8 => match children.get(7) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                KeyCode::Digit9 => match children.get(8) {
-                    Some(entity) => SelectedItemBox(*entity),
-                    None => continue,
-                },
-                _ => continue,
-            };
+            // The server can limit how many hotbar slots there are
+            if item_box_section.is_equipment && !hud_settings.is_usable_slot(index) {
+                continue;
+            }
+
+            if let Some(entity) = children.get(index) {
+                *selected = SelectedItemBox(*entity);
+            }
         }
     }
 }
@@ -846,6 +1158,7 @@ fn keyboard_select_item_box(
 fn return_cursor_item(
     net: Res<NetworkClient>,
     items: Res<Items>,
+    loadouts: Res<Loadouts>,
     visibility_changed: Query<(), (Changed<Visibility>, With<InterfaceNode>)>,
     item_box_section_query: Query<(
         &ItemBoxSection,
@@ -877,6 +1190,10 @@ fn return_cursor_item(
             if let Some(children) = children {
                 for item_box_entity in children.iter() {
                     let mut item_box = item_box_query.get_mut(*item_box_entity).unwrap();
+                    if loadouts.is_locked(&interface_node.path, item_box.index) {
+                        continue;
+                    }
+
                     if item_box.item_stack.item == cursor_box.item_stack.item {
                         let transfered = item_box
                             .item_stack
@@ -897,7 +1214,9 @@ fn return_cursor_item(
                 // stacks before it begins on empty stacks.
                 for item_box_entity in children.iter() {
                     let mut item_box = item_box_query.get_mut(*item_box_entity).unwrap();
-                    if item_box.is_empty() {
+                    if item_box.is_empty()
+                        && !loadouts.is_locked(&interface_node.path, item_box.index)
+                    {
                         let transfered = item_box
                             .item_stack
                             .transfer_to(&mut cursor_box.item_stack, u32::MAX);
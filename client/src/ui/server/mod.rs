@@ -6,26 +6,33 @@ use bevy::{
     prelude::*,
     render::render_asset::RenderAssetUsages,
     text::FontSmoothing,
+    ui::widget::NodeImageMode,
 };
 use fmc_protocol::messages;
 use serde::Deserialize;
 
 use crate::{
     game_state::GameState,
-    networking::NetworkClient,
+    networking::{properties, NetworkClient},
     ui::{
         widgets::{TextBox, TextShadow},
         DEFAULT_FONT_HANDLE,
     },
 };
 
-use self::items::{CursorItemBox, ItemBoxSection};
+use self::{
+    items::{CursorItemBox, ItemBoxSection, ItemSearch, ItemTooltip},
+    theme::{ImageSlices, Theme, ThemeColor, ThemeFontSize},
+};
 
 use super::{CursorVisibility, UiState};
 
+mod animations;
+mod chat;
 pub mod items;
 pub mod key_bindings;
 mod text;
+mod theme;
 
 const INTERFACE_CONFIG_PATH: &str = "server_assets/active/interfaces/";
 const INTERFACE_TEXTURE_PATH: &str = "server_assets/active/textures/interfaces/";
@@ -36,6 +43,8 @@ impl Plugin for ServerInterfacesPlugin {
         app.add_event::<InterfaceToggleEvent>()
             .insert_resource(InterfaceStack::default())
             .add_plugins((
+                animations::AnimationPlugin,
+                chat::ChatPlugin,
                 items::ItemPlugin,
                 text::TextPlugin,
                 key_bindings::KeyBindingsPlugin,
@@ -81,6 +90,19 @@ pub fn load_interfaces(
     let mut interfaces = Interfaces::default();
     let mut interface_paths = InterfacePaths::default();
 
+    let theme = match Theme::load() {
+        Ok(t) => t,
+        Err(e) => {
+            net.disconnect(&format!(
+                "Misconfigured assets: Failed to read the interface theme at: '{}'\n\
+                Error: {}",
+                theme::THEME_PATH,
+                e
+            ));
+            return;
+        }
+    };
+
     let directory = match std::fs::read_dir(INTERFACE_CONFIG_PATH) {
         Ok(dir) => dir,
         Err(e) => {
@@ -166,6 +188,7 @@ pub fn load_interfaces(
             config: &NodeConfig,
             interface_paths: &mut InterfacePaths,
             asset_server: &AssetServer,
+            theme: &Theme,
         ) {
             let node_path = if let Some(path) = &config.path {
                 let node_path = if parent_path == "" {
@@ -188,25 +211,52 @@ pub fn load_interfaces(
                 parent_path
             };
 
-            let node: Node = if let Some(image_path) = &config.image {
-                let dimensions = read_image_dimensions(&image_path);
+            let image = config.image.as_ref().map(|path| {
+                let (path, theme_slices) = theme.image(path);
+                (path, config.image_slices.as_ref().or(theme_slices))
+            });
+
+            let node: Node = if let Some((image_path, None)) = image {
+                let dimensions = read_image_dimensions(image_path);
                 let mut node: Node = config.style.clone().into();
                 node.width = Val::Px(dimensions.x);
                 node.height = Val::Px(dimensions.y);
                 node
             } else {
+                // Sliced images are scaled to the size of the node instead.
                 config.style.clone().into()
             };
 
             entity_commands.insert((
                 node,
-                BackgroundColor::from(config.background_color.unwrap_or(Color::NONE)),
-                BorderColor::from(config.border_color.unwrap_or(Color::NONE)),
+                BackgroundColor::from(
+                    config
+                        .background_color
+                        .as_ref()
+                        .map_or(Color::NONE, |color| theme.color(color)),
+                ),
+                BorderColor::from(
+                    config
+                        .border_color
+                        .as_ref()
+                        .map_or(Color::NONE, |color| theme.color(color)),
+                ),
             ));
 
-            if let Some(path) = &config.image {
+            if !config.animations.is_empty() {
+                // Hovering is only registered for nodes that can be interacted with.
+                if config.animations.contains_key(animations::HOVER)
+                    || config.animations.contains_key(animations::PRESS)
+                {
+                    entity_commands.insert(Interaction::default());
+                }
+                entity_commands.insert(animations::NodeAnimations(config.animations.clone()));
+            }
+
+            if let Some((path, slices)) = image {
                 entity_commands.insert(ImageNode {
-                    image: asset_server.load(INTERFACE_TEXTURE_PATH.to_owned() + &path),
+                    image: asset_server.load(INTERFACE_TEXTURE_PATH.to_owned() + path),
+                    image_mode: slices.map_or(NodeImageMode::Auto, |slices| slices.into()),
                     ..default()
                 });
             }
@@ -222,6 +272,7 @@ pub fn load_interfaces(
                                 child_config,
                                 interface_paths,
                                 asset_server,
+                                theme,
                             )
                         }
                     });
@@ -240,6 +291,7 @@ pub fn load_interfaces(
                                 child_config,
                                 interface_paths,
                                 asset_server,
+                                theme,
                             )
                         }
                     });
@@ -247,18 +299,33 @@ pub fn load_interfaces(
                 NodeContent::TextContainer {
                     text_background_color,
                     fade,
+                    chat,
                 } => {
                     entity_commands.insert(text::TextContainer {
-                        text_background_color: text_background_color.unwrap_or(Color::NONE),
+                        text_background_color: text_background_color
+                            .as_ref()
+                            .map_or(Color::NONE, |color| theme.color(color)),
                     });
 
                     if *fade {
                         entity_commands.insert(text::FadeLines);
                     }
+
+                    if *chat {
+                        entity_commands.insert(chat::ChatHistory::default());
+                    }
                 }
                 NodeContent::TextBox => {
                     entity_commands.insert(TextBox::default());
                 }
+                NodeContent::ItemSearch(section_path) => {
+                    entity_commands.insert((
+                        TextBox::default(),
+                        ItemSearch {
+                            section_path: section_path.clone(),
+                        },
+                    ));
+                }
                 NodeContent::Text {
                     text,
                     font_size,
@@ -272,11 +339,11 @@ pub fn load_interfaces(
                             },
                             Text::new(text),
                             TextFont {
-                                font_size: *font_size,
+                                font_size: theme.font_size(font_size),
                                 font: DEFAULT_FONT_HANDLE,
                                 font_smoothing: FontSmoothing::None,
                             },
-                            TextColor(*color),
+                            TextColor(theme.color(color)),
                             TextShadow::default(),
                         ));
                     });
@@ -307,6 +374,7 @@ pub fn load_interfaces(
                     &node_config,
                     &mut interface_paths,
                     &asset_server,
+                    &theme,
                 );
 
                 entity_commands.insert(());
@@ -319,6 +387,14 @@ pub fn load_interfaces(
         interfaces.insert(interface_name, interface_entity);
     }
 
+    // Lets the server know which interfaces it can use, those it doesn't know we have won't be
+    // updated.
+    let loaded = properties::ClientInterfaces {
+        interfaces: interfaces.keys().cloned().collect(),
+        nodes: interface_paths.keys().cloned().collect(),
+    };
+    net.send_property(properties::INTERFACES, &loaded);
+
     commands.insert_resource(interface_paths);
     commands.insert_resource(interfaces);
 
@@ -346,12 +422,34 @@ pub fn load_interfaces(
                 },
             ));
         });
+
+    commands.spawn((
+        Text::default(),
+        TextColor(Color::WHITE),
+        Node {
+            position_type: PositionType::Absolute,
+            padding: UiRect::all(Val::Px(2.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.8)),
+        ZIndex(2),
+        Visibility::Hidden,
+        ItemTooltip,
+    ));
 }
 
-fn cleanup(mut commands: Commands, cursor_item_box: Query<Entity, With<CursorItemBox>>) {
+fn cleanup(
+    mut commands: Commands,
+    cursor_item_box: Query<Entity, With<CursorItemBox>>,
+    item_tooltip: Query<Entity, With<ItemTooltip>>,
+) {
     if let Ok(entity) = cursor_item_box.get_single() {
         commands.entity(entity).despawn_recursive();
     }
+
+    if let Ok(entity) = item_tooltip.get_single() {
+        commands.entity(entity).despawn_recursive();
+    }
 }
 
 /// Event used by keybindings to toggle an interface open or closed.
@@ -371,10 +469,16 @@ struct NodeConfig {
     content: NodeContent,
     /// Image displayed in the node.
     image: Option<String>,
+    /// Scales the image by its slices instead of stretching it, overrides the slices the theme
+    /// defines for it.
+    image_slices: Option<ImageSlices>,
     /// Fill color, can be used to tint image
-    background_color: Option<Color>,
+    background_color: Option<ThemeColor>,
     /// Color used for borders
-    border_color: Option<Color>,
+    border_color: Option<ThemeColor>,
+    /// Animations played by the client, by name. See the animations module for the names that
+    /// are played automatically.
+    animations: HashMap<String, animations::Animation>,
     /// If it should overlap(false) or replace(true) interfaces when opened, only
     /// applicable to interface roots.
     exclusive: bool,
@@ -554,19 +658,29 @@ enum NodeContent {
         //
         // If you do not want the container itself to have color, this allows you to set the
         // background color of the lines themselves.
-        text_background_color: Option<Color>,
+        text_background_color: Option<ThemeColor>,
         // If true, new lines will be set to visible when received and then faded out after a short
         // interval.
         #[serde(default)]
         fade: bool,
+        // If true, the container is a chat. It can be scrolled through while a text box is
+        // focused, and lines get timestamps and highlighting when they mention the player.
+        #[serde(default)]
+        chat: bool,
     },
     // Text input
     TextBox,
+    // Text input that dims the items that don't match it in the item box section at the given
+    // interface path. The search is done by the client, it should not be given a path of its own
+    // or the text is sent to the server when enter is pressed.
+    ItemSearch(String),
     // A text field
     Text {
         text: String,
-        font_size: f32,
-        color: Color,
+        // Uses the theme's default size if not set
+        #[serde(default)]
+        font_size: Option<ThemeFontSize>,
+        color: ThemeColor,
     },
 }
 
@@ -3,14 +3,22 @@ use fmc_protocol::messages;
 
 use crate::{
     game_state::GameState,
-    networking::NetworkClient,
+    networking::{Identity, NetworkClient},
+    settings::Settings,
     ui::{
         widgets::{FocusedTextBox, TextBox, TextShadow},
         DEFAULT_FONT_HANDLE,
     },
 };
 
-use super::{InterfaceNode, InterfacePaths};
+use super::{
+    chat::{self, ChatHistory, InputHistory},
+    items::LoadoutCommand,
+    InterfaceNode, InterfacePaths,
+};
+
+// Background of chat lines that mention the player
+const MENTION_COLOR: Color = Color::srgba(1.0, 0.85, 0.2, 0.35);
 
 pub struct TextPlugin;
 impl Plugin for TextPlugin {
@@ -48,8 +56,15 @@ pub struct FadeLines;
 fn handle_text_updates(
     mut commands: Commands,
     net: Res<NetworkClient>,
+    settings: Res<Settings>,
+    identity: Res<Identity>,
     interface_paths: Res<InterfacePaths>,
-    text_container_query: Query<(Option<&Children>, &TextContainer, Has<FadeLines>)>,
+    text_container_query: Query<(
+        Option<&Children>,
+        &TextContainer,
+        Has<FadeLines>,
+        Has<ChatHistory>,
+    )>,
     mut text_update_events: EventReader<messages::InterfaceTextUpdate>,
 ) {
     for text_update in text_update_events.read() {
@@ -65,7 +80,7 @@ fn handle_text_updates(
         };
 
         for interface_entity in interface_entities.iter() {
-            let (children, text_container, should_fade) = match text_container_query
+            let (children, text_container, should_fade, is_chat) = match text_container_query
                 .get(*interface_entity)
             {
                 Ok(c) => c,
@@ -100,6 +115,18 @@ fn handle_text_updates(
                 commands.entity(entity)
             };
 
+            let is_mention = is_chat
+                && !identity.username.is_empty()
+                && text_update
+                    .text
+                    .to_lowercase()
+                    .contains(&identity.username.to_lowercase());
+            let background_color = if is_mention {
+                settings.color_palette.adjust(MENTION_COLOR)
+            } else {
+                settings.text_background(text_container.text_background_color)
+            };
+
             entity_commands.insert((
                 Node {
                     // XXX: Since the fake shadow text extends a little farther it
@@ -108,10 +135,16 @@ fn handle_text_updates(
                     width: Val::Percent(98.0),
                     ..default()
                 },
-                BackgroundColor::from(text_container.text_background_color),
+                BackgroundColor::from(background_color),
                 Line,
             ));
 
+            let text = if is_chat && settings.chat_timestamps {
+                format!("[{}] {}", chat::timestamp(), text_update.text)
+            } else {
+                text_update.text.clone()
+            };
+
             let color: Color = match Srgba::hex(&text_update.color) {
                 Ok(c) => c.into(),
                 Err(_) => {
@@ -124,8 +157,8 @@ fn handle_text_updates(
                 }
             };
             entity_commands.with_child((
-                Text::new(&text_update.text),
-                TextColor::from(color),
+                Text::new(text),
+                TextColor::from(settings.color_palette.adjust(color)),
                 TextFont {
                     font: DEFAULT_FONT_HANDLE,
                     font_size: text_update.font_size,
@@ -180,8 +213,10 @@ fn fade_lines(
 
 fn send_text(
     net: Res<NetworkClient>,
+    mut input_history: ResMut<InputHistory>,
     mut focused_text_box: Query<(&mut TextBox, &InterfaceNode), With<FocusedTextBox>>,
     keyboard: Res<ButtonInput<KeyCode>>,
+    mut loadout_commands: EventWriter<LoadoutCommand>,
 ) {
     if !keyboard.just_pressed(KeyCode::Enter) {
         return;
@@ -192,10 +227,17 @@ fn send_text(
             return;
         }
 
+        // Handled by the client, see the loadouts
+        if text_box.text == "/loadout" || text_box.text.starts_with("/loadout ") {
+            loadout_commands.send(LoadoutCommand(text_box.text.clone()));
+            input_history.push(&interface_node.path, std::mem::take(&mut text_box.text));
+            return;
+        }
+
         net.send_message(messages::InterfaceTextInput {
             interface_path: interface_node.path.clone(),
             text: text_box.text.clone(),
         });
-        text_box.text.clear();
+        input_history.push(&interface_node.path, std::mem::take(&mut text_box.text));
     }
 }
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    sprite::{BorderRect, SliceScaleMode, TextureSlicer},
+    ui::widget::NodeImageMode,
+};
+use serde::Deserialize;
+
+pub(super) const THEME_PATH: &str = "server_assets/active/interface_theme.json";
+
+// Colors, font sizes and images that interface configs can refer to by name, so that the look of
+// all the interfaces can be changed in one place. Names are referred to with a "$" in front,
+// e.g. "background_color": "$panel" or "image": "$item_slot". The theme file is optional.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct Theme {
+    colors: HashMap<String, Color>,
+    font_sizes: HashMap<String, f32>,
+    images: HashMap<String, ThemeImage>,
+    /// Size of text nodes that don't set one
+    default_font_size: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            colors: HashMap::new(),
+            font_sizes: HashMap::new(),
+            images: HashMap::new(),
+            default_font_size: 8.0,
+        }
+    }
+}
+
+impl Theme {
+    pub fn load() -> Result<Self, String> {
+        let file = match std::fs::File::open(THEME_PATH) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        return serde_json::from_reader(&file).map_err(|e| e.to_string());
+    }
+
+    pub fn color(&self, color: &ThemeColor) -> Color {
+        return match color {
+            ThemeColor::Color(color) => *color,
+            ThemeColor::Named(name) => {
+                let Some(color) = name
+                    .strip_prefix('$')
+                    .and_then(|name| self.colors.get(name))
+                else {
+                    warn!("The interface theme has no color named '{}'", name);
+                    return Color::NONE;
+                };
+                *color
+            }
+        };
+    }
+
+    pub fn font_size(&self, font_size: &Option<ThemeFontSize>) -> f32 {
+        return match font_size {
+            None => self.default_font_size,
+            Some(ThemeFontSize::Size(size)) => *size,
+            Some(ThemeFontSize::Named(name)) => {
+                let Some(size) = name
+                    .strip_prefix('$')
+                    .and_then(|name| self.font_sizes.get(name))
+                else {
+                    warn!("The interface theme has no font size named '{}'", name);
+                    return self.default_font_size;
+                };
+                *size
+            }
+        };
+    }
+
+    /// The path of the image, and how it is sliced. Paths that don't start with a "$" are not
+    /// part of the theme and returned as they are.
+    pub fn image<'a>(&'a self, path: &'a str) -> (&'a str, Option<&'a ImageSlices>) {
+        let Some(name) = path.strip_prefix('$') else {
+            return (path, None);
+        };
+
+        let Some(image) = self.images.get(name) else {
+            warn!("The interface theme has no image named '{}'", name);
+            return (path, None);
+        };
+
+        return (&image.path, image.slices.as_ref());
+    }
+}
+
+/// A color, or the name of one in the theme
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub(super) enum ThemeColor {
+    Color(Color),
+    Named(String),
+}
+
+/// A font size, or the name of one in the theme
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub(super) enum ThemeFontSize {
+    Size(f32),
+    Named(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeImage {
+    path: String,
+    slices: Option<ImageSlices>,
+}
+
+// 9-slice scaling, the image is cut into corners, sides and a center. The corners keep their size
+// while the sides and center are stretched or tiled to fill the node, so borders look the same no
+// matter the size of the node.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ImageSlices {
+    /// Width of the image's borders in pixels
+    border: SliceBorder,
+    /// How the sides fill the space between the corners
+    sides: SliceMode,
+    /// How the center fills the space between the sides
+    center: SliceMode,
+    /// How much the corners can be scaled up when the node is scaled
+    max_corner_scale: f32,
+}
+
+impl Default for ImageSlices {
+    fn default() -> Self {
+        Self {
+            border: SliceBorder::default(),
+            sides: SliceMode::Stretch,
+            center: SliceMode::Stretch,
+            max_corner_scale: 1.0,
+        }
+    }
+}
+
+impl From<&ImageSlices> for NodeImageMode {
+    fn from(value: &ImageSlices) -> Self {
+        NodeImageMode::Sliced(TextureSlicer {
+            border: BorderRect {
+                left: value.border.left,
+                right: value.border.right,
+                top: value.border.top,
+                bottom: value.border.bottom,
+            },
+            center_scale_mode: value.center.into(),
+            sides_scale_mode: value.sides.into(),
+            max_corner_scale: value.max_corner_scale,
+        })
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct SliceBorder {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+enum SliceMode {
+    Stretch,
+    Tile,
+}
+
+impl From<SliceMode> for SliceScaleMode {
+    fn from(value: SliceMode) -> Self {
+        match value {
+            SliceMode::Stretch => SliceScaleMode::Stretch,
+            SliceMode::Tile => SliceScaleMode::Tile { stretch_value: 1.0 },
+        }
+    }
+}
@@ -152,16 +152,36 @@ pub struct TextBox {
     pub text: String,
 }
 
+/// Add to a `TextBox` to show each character as '*', e.g. for passwords.
+#[derive(Component)]
+pub struct MaskedTextBox;
+
+impl TextBox {
+    // The text as it should be displayed
+    fn display_text(&self, masked: bool) -> String {
+        if masked {
+            return "*".repeat(self.text.chars().count());
+        } else {
+            return self.text.clone();
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct FocusedTextBox;
 
 #[derive(Component)]
 struct TextBoxText;
 
-fn textbox_setup(mut commands: Commands, input_query: Query<(Entity, &TextBox), Added<TextBox>>) {
-    for (entity, text_box) in input_query.iter() {
+fn textbox_setup(
+    mut commands: Commands,
+    input_query: Query<(Entity, &TextBox, Has<MaskedTextBox>), Added<TextBox>>,
+) {
+    for (entity, text_box, masked) in input_query.iter() {
         commands.entity(entity).with_children(|parent| {
-            parent.spawn_text(&text_box.text).insert(TextBoxText);
+            parent
+                .spawn_text(&text_box.display_text(masked))
+                .insert(TextBoxText);
         });
     }
 }
@@ -233,12 +253,12 @@ fn edit_text_box(
 
 fn update_textbox_text(
     mut text_query: Query<&mut Text>,
-    text_box_query: Query<(&TextBox, &Children), Changed<TextBox>>,
+    text_box_query: Query<(&TextBox, &Children, Has<MaskedTextBox>), Changed<TextBox>>,
 ) {
-    for (text_box, children) in text_box_query.iter() {
+    for (text_box, children, masked) in text_box_query.iter() {
         for child in children {
             if let Ok(mut text) = text_query.get_mut(*child) {
-                *text = Text::new(&text_box.text);
+                *text = Text::new(text_box.display_text(masked));
             }
         }
     }
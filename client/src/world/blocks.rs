@@ -1,4 +1,7 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use bevy::prelude::*;
 use fmc_protocol::messages;
@@ -8,6 +11,7 @@ use crate::{
     assets,
     networking::NetworkClient,
     rendering::materials::{self, BlockMaterial},
+    settings::Settings,
 };
 
 pub type BlockId = u16;
@@ -96,7 +100,9 @@ pub fn load_blocks(
     server_config: Res<messages::ServerConfig>,
     block_textures: Res<assets::BlockTextures>,
     material_handles: Res<assets::Materials>,
-    materials: Res<Assets<BlockMaterial>>,
+    settings: Res<Settings>,
+    mut materials: ResMut<Assets<BlockMaterial>>,
+    images: Res<Assets<Image>>,
 ) {
     if server_config.block_ids.len() > u16::MAX as usize {
         net.disconnect(&format!(
@@ -109,6 +115,8 @@ pub fn load_blocks(
 
     let mut block_ids = server_config.block_ids.clone();
     let mut maybe_blocks = Vec::new();
+    // Water blocks get their own copy of the material they use, by material name.
+    let mut water_materials: HashMap<String, Handle<BlockMaterial>> = HashMap::new();
     maybe_blocks.resize_with(block_ids.len(), Option::default);
 
     // Recursively walk block configuration directory
@@ -144,9 +152,22 @@ pub fn load_blocks(
         }
     };
 
+    let templates = match BlockConfigTemplates::load(&files) {
+        Ok(t) => t,
+        Err(e) => {
+            net.disconnect(&format!("Misconfigured assets: {}", e));
+            return;
+        }
+    };
+
     for file_path in files {
-        let block_config_json = match BlockConfig::read_as_json(&file_path) {
-            Ok(c) => c,
+        // Templates are only used by other configs
+        if templates.is_template(&file_path) {
+            continue;
+        }
+
+        let block_config_json = match templates.resolve(&file_path, &mut Vec::new()) {
+            Ok(c) => serde_json::Value::Object(c),
             Err(e) => {
                 net.disconnect(&format!(
                     "Misconfigured assets: failed to read block config at {}\nError: {}",
@@ -185,9 +206,14 @@ pub fn load_blocks(
                 interactable,
                 light_attenuation,
                 light,
+                light_color,
                 fog,
                 sound,
                 placement,
+                climbable,
+                rail,
+                water,
+                variants,
             } => {
                 let material_handle = if let Some(m) = material_handles.get(&material) {
                     m.clone().typed()
@@ -199,139 +225,84 @@ pub fn load_blocks(
                     ));
                     return;
                 };
+                // The copy is drawn with waves and reflections, blocks that use the same material
+                // without being water are left as they are.
+                let material_handle = if water {
+                    water_materials
+                        .entry(material)
+                        .or_insert_with(|| {
+                            let mut water_material =
+                                materials.get(&material_handle).unwrap().clone();
+                            water_material.water = Some(settings.water_quality);
+                            materials.add(water_material)
+                        })
+                        .clone()
+                } else {
+                    material_handle
+                };
                 let material = materials.get(&material_handle).unwrap();
 
-                let mut mesh_primitives = Vec::new();
-
-                if let Some(faces) = faces {
-                    for (i, face_name) in [
-                        &faces.top,
-                        &faces.front,
-                        &faces.left,
-                        &faces.right,
-                        &faces.back,
-                        &faces.bottom,
-                    ]
-                    .iter()
-                    .enumerate()
-                    {
-                        let texture_array_id = match block_textures.get(face_name) {
-                            Some(id) => *id,
-                            None => {
-                                net.disconnect(format!(
-                                    "Misconfigured assets: failed to read block at: {}, no block texture with the name {}",
-                                    file_path.display(),
-                                    face_name
-                                ));
-                                return;
-                            }
-                        };
-
-                        let face = match i {
-                            0 => BlockFace::Top,
-                            1 => BlockFace::Back,
-                            2 => BlockFace::Left,
-                            3 => BlockFace::Right,
-                            4 => BlockFace::Front,
-                            5 => BlockFace::Bottom,
-                            _ => unreachable!(),
-                        };
-
-                        let square = QuadPrimitive {
-                            vertices: FACE_VERTICES[i],
-                            normals: [FACE_NORMALS[i], FACE_NORMALS[i]],
-                            texture_array_id,
-                            cull_face: Some(face),
-                            light_face: face,
-                            rotate_texture: false,
-                        };
-
-                        mesh_primitives.push(square);
+                let (mesh_primitives, cull_delimiters) = match build_quads(
+                    &file_path,
+                    faces.as_ref(),
+                    quads.as_ref(),
+                    &block_textures,
+                ) {
+                    Ok(q) => q,
+                    Err(e) => {
+                        net.disconnect(e);
+                        return;
                     }
-                }
+                };
 
-                let mut cull_delimiters = [None, None, None, None];
-
-                if let Some(quads) = quads {
-                    for quad in quads.iter() {
-                        let texture_array_id = match block_textures.get(&quad.texture) {
-                            Some(id) => *id,
-                            None => {
-                                net.disconnect(format!(
-                                    "Misconfigured assets: failed to read block at: {}, no block texture with the name {}",
-                                    file_path.display(),
-                                    &quad.texture
-                                ));
+                let mut cube_variants = Vec::with_capacity(variants.len());
+                for variant in variants {
+                    let rotation = match variant.rotation {
+                        Some(rotation) if rotation > 3 => {
+                            net.disconnect(format!(
+                                "Misconfigured assets: failed to read block at: {}, the rotation \
+                                of a variant must be 0-3 quarter turns",
+                                file_path.display()
+                            ));
+                            return;
+                        }
+                        Some(rotation) => Some(BlockRotation::from(rotation)),
+                        None => None,
+                    };
+
+                    let quads = if variant.faces.is_some() || variant.quads.is_some() {
+                        match build_quads(
+                            &file_path,
+                            variant.faces.as_ref(),
+                            variant.quads.as_ref(),
+                            &block_textures,
+                        ) {
+                            Ok(q) => Some(q),
+                            Err(e) => {
+                                net.disconnect(e);
                                 return;
                             }
-                        };
-
-                        let normals = [
-                            (Vec3::from_array(quad.vertices[1])
-                                - Vec3::from_array(quad.vertices[0]))
-                            .cross(
-                                Vec3::from_array(quad.vertices[2])
-                                    - Vec3::from_array(quad.vertices[1]),
-                            )
-                            .to_array(),
-                            (Vec3::from_array(quad.vertices[3])
-                                - Vec3::from_array(quad.vertices[1]))
-                            .cross(
-                                Vec3::from_array(quad.vertices[2])
-                                    - Vec3::from_array(quad.vertices[1]),
-                            )
-                            .to_array(),
-                        ];
-
-                        let normal = Vec3::from(normals[0]);
-                        let normal_max =
-                            normal.abs().cmpeq(Vec3::splat(normal.abs().max_element()));
-                        let light_face = if normal_max.x {
-                            if normal.x.is_sign_positive() {
-                                BlockFace::Right
-                            } else {
-                                BlockFace::Left
-                            }
-                        } else if normal_max.y {
-                            if normal.y.is_sign_positive() {
-                                BlockFace::Top
-                            } else {
-                                BlockFace::Bottom
-                            }
-                        } else if normal_max.z {
-                            if normal.z.is_sign_positive() {
-                                BlockFace::Front
-                            } else {
-                                BlockFace::Back
-                            }
-                        } else {
-                            unreachable!();
-                        };
-
-                        match quad.cull_face {
-                            Some(BlockFace::Top) | Some(BlockFace::Bottom) => (),
-                            Some(b) => {
-                                if quad.vertices[0][1] != 1.0 || quad.vertices[2][1] != 1.0 {
-                                    // Top left -> top right and vice versa to mirror it to how a
-                                    // facing block would see it.
-                                    cull_delimiters[b as usize] =
-                                        Some((quad.vertices[2][1], quad.vertices[0][1]));
-                                }
-                            }
-                            None => (),
                         }
-
-                        mesh_primitives.push(QuadPrimitive {
-                            vertices: quad.vertices,
-                            normals,
-                            texture_array_id,
-                            cull_face: quad.cull_face,
-                            light_face,
-                            rotate_texture: quad.rotate_texture,
-                        });
-                    }
+                    } else {
+                        None
+                    };
+
+                    cube_variants.push(CubeVariant {
+                        when: variant.when,
+                        quads,
+                        rotation,
+                    });
                 }
 
+                let Some(light_color) = parse_light_color(light_color.as_deref()) else {
+                    net.disconnect(&format!(
+                        "Misconfigured assets: the block '{}' has an invalid light color, it \
+                        should be a hex color like '#ffaa00'",
+                        name
+                    ));
+                    return;
+                };
+
                 let cull_method = if only_cull_self {
                     CullMethod::OnlySelf
                 } else {
@@ -342,6 +313,30 @@ pub fn load_blocks(
                     }
                 };
 
+                // The map color is the average color of the texture on top of the block, tinted by
+                // the material.
+                let map_color = images
+                    .get(&block_textures.handle)
+                    .and_then(|texture_array| {
+                        mesh_primitives
+                            .iter()
+                            .find(|quad| quad.light_face == BlockFace::Top)
+                            .or(mesh_primitives.first())
+                            .and_then(|quad| {
+                                block_textures.pixels(texture_array, quad.texture_array_id)
+                            })
+                            .and_then(|pixels| average_texture_color(&pixels))
+                    })
+                    .map(|color| {
+                        let tint = material.base_color;
+                        Color::from(LinearRgba::new(
+                            color.red * tint.red,
+                            color.green * tint.green,
+                            color.blue * tint.blue,
+                            color.alpha * tint.alpha,
+                        ))
+                    });
+
                 let fog_settings = if let Some(fog) = fog {
                     Some(DistanceFog {
                         color: fog.color,
@@ -359,15 +354,20 @@ pub fn load_blocks(
                     name,
                     material_handle,
                     quads: mesh_primitives,
+                    variants: cube_variants,
                     friction,
                     interactable,
                     cull_method,
                     cull_delimiters,
                     light_attenuation: light_attenuation.unwrap_or(15).min(15),
                     light: light.min(15),
+                    light_color,
                     fog_settings,
                     sound,
                     placement,
+                    map_color,
+                    climbable,
+                    rail,
                 })
             }
 
@@ -378,8 +378,20 @@ pub fn load_blocks(
                 interactable,
                 sound,
                 light,
+                light_color,
                 placement,
+                climbable,
+                rail,
             } => {
+                let Some(light_color) = parse_light_color(light_color.as_deref()) else {
+                    net.disconnect(&format!(
+                        "Misconfigured assets: the block '{}' has an invalid light color, it \
+                        should be a hex color like '#ffaa00'",
+                        name
+                    ));
+                    return;
+                };
+
                 // TODO: model must cause a disconnect if not found
                 let model = {
                     let path = MODEL_PATH.to_owned() + &model + ".glb#Scene0";
@@ -393,7 +405,10 @@ pub fn load_blocks(
                     interactable,
                     sound,
                     light: light.min(15),
+                    light_color,
                     placement,
+                    climbable,
+                    rail,
                 })
             }
         };
@@ -463,6 +478,8 @@ pub struct Cube {
     pub material_handle: Handle<materials::BlockMaterial>,
     // List of squares meshes that make up the block.
     pub quads: Vec<QuadPrimitive>,
+    // Replacements for the quads and rotation depending on the block state, see 'CubeVariant'.
+    variants: Vec<CubeVariant>,
     // Friction value for player contact.
     friction: Friction,
     // If when the player uses their equipped item on this block it should count as an
@@ -491,8 +508,16 @@ pub struct Cube {
     sound: Sound,
     // Light emitted by the block
     light: u8,
+    // Color of the block's faces when it emits light
+    light_color: Color,
     // How the block can be placed
     placement: BlockPlacement,
+    // Color used to represent the block when seen from above on maps.
+    map_color: Option<Color>,
+    // If the player can climb the block, e.g. ladders
+    climbable: bool,
+    // If vehicles like minecarts can ride along the block
+    rail: bool,
 }
 
 // TODO: This was made before the Models collection was made. This could hold model ids instead of
@@ -515,8 +540,95 @@ pub struct BlockModel {
     sound: Sound,
     // Light emitted by the block
     light: u8,
+    // Color of the block when it emits light
+    light_color: Color,
     // How the block can be placed
     placement: BlockPlacement,
+    // If the player can climb the block, e.g. ladders
+    climbable: bool,
+    // If vehicles like minecarts can ride along the block
+    rail: bool,
+}
+
+impl Cube {
+    /// The quads the block is drawn with when it has the block state, and how they are rotated.
+    pub fn shape(&self, block_state: BlockState) -> CubeShape {
+        let variant = self
+            .variants
+            .iter()
+            .find(|variant| variant.when.matches(block_state));
+
+        let (quads, cull_delimiters) = match variant.and_then(|v| v.quads.as_ref()) {
+            Some((quads, cull_delimiters)) => (quads, cull_delimiters),
+            None => (&self.quads, &self.cull_delimiters),
+        };
+
+        return CubeShape {
+            quads,
+            cull_delimiters,
+            rotation: variant
+                .and_then(|v| v.rotation)
+                .unwrap_or(block_state.rotation()),
+        };
+    }
+}
+
+pub struct CubeShape<'a> {
+    pub quads: &'a [QuadPrimitive],
+    cull_delimiters: &'a [Option<(f32, f32)>; 4],
+    pub rotation: BlockRotation,
+}
+
+// Cubes can look different depending on their block state, like a lamp that is on or off, by
+// listing variants in their config. The first variant the block state matches is used, and it
+// replaces the faces/quads, the rotation, or both. The rotation is in quarter turns.
+//
+// "variants": [
+//     { "when": { "data": 1 }, "faces": { ... } },
+//     { "when": { "upside_down": true }, "quads": [ ... ], "rotation": 2 }
+// ]
+//
+// Model blocks can have variants too, but they are resolved by the server.
+#[derive(Debug)]
+struct CubeVariant {
+    when: BlockStateCondition,
+    quads: Option<(Vec<QuadPrimitive>, [Option<(f32, f32)>; 4])>,
+    rotation: Option<BlockRotation>,
+}
+
+#[derive(Deserialize)]
+struct CubeVariantJson {
+    #[serde(default)]
+    when: BlockStateCondition,
+    faces: Option<CubeMeshTextureNames>,
+    quads: Option<Vec<QuadPrimitiveJson>>,
+    rotation: Option<u16>,
+}
+
+// The parts of a block state a variant is for, those that aren't set match anything. This must
+// match how the server selects variants.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct BlockStateCondition {
+    // Quarter turns, never matches centered blocks
+    rotation: Option<u16>,
+    centered: Option<bool>,
+    upside_down: Option<bool>,
+    data: Option<u16>,
+}
+
+impl BlockStateCondition {
+    fn matches(&self, block_state: BlockState) -> bool {
+        return self.rotation.map_or(true, |rotation| {
+            block_state.uses_side_model() && block_state.rotation() as u16 == rotation
+        }) && self
+            .centered
+            .map_or(true, |centered| centered != block_state.uses_side_model())
+            && self.upside_down.map_or(true, |upside_down| {
+                upside_down == block_state.is_upside_down()
+            })
+            && self.data.map_or(true, |data| data == block_state.data());
+    }
 }
 
 #[derive(Debug)]
@@ -526,12 +638,21 @@ pub enum Block {
 }
 
 impl Block {
-    pub fn cull_delimiter(&self, block_face: BlockFace) -> Option<(f32, f32)> {
+    /// The cull delimiter of the face as seen from outside, `block_face` is the face after the
+    /// block has been rotated by its block state.
+    pub fn cull_delimiter(
+        &self,
+        block_face: BlockFace,
+        block_state: BlockState,
+    ) -> Option<(f32, f32)> {
         match self {
-            Block::Cube(cube) => match block_face {
-                BlockFace::Top | BlockFace::Bottom => None,
-                b => cube.cull_delimiters[b as usize],
-            },
+            Block::Cube(cube) => {
+                let shape = cube.shape(block_state);
+                match block_face.reverse_rotate(shape.rotation) {
+                    BlockFace::Top | BlockFace::Bottom => None,
+                    b => shape.cull_delimiters[b as usize],
+                }
+            }
             Block::Model(_) => None,
         }
     }
@@ -572,7 +693,9 @@ impl Block {
     pub fn can_have_block_state(&self) -> bool {
         match self {
             Block::Cube(cube) => {
-                cube.placement.rotatable || cube.placement.side_transform.is_some()
+                cube.placement.rotatable
+                    || cube.placement.side_transform.is_some()
+                    || !cube.variants.is_empty()
             }
             // Block models aren't handled by the client, but sent as separate models by the
             // server.
@@ -587,6 +710,15 @@ impl Block {
         }
     }
 
+    /// If entities collide with the block. Same as the server's, so they agree on what can be
+    /// stood on.
+    pub fn is_solid(&self) -> bool {
+        match self.friction() {
+            Friction::Static { .. } => true,
+            Friction::Drag(_) => false,
+        }
+    }
+
     pub fn light_attenuation(&self) -> u8 {
         match self {
             Block::Cube(c) => c.light_attenuation,
@@ -601,6 +733,15 @@ impl Block {
         }
     }
 
+    /// The color the block glows with when it emits light. The light it spreads to the blocks
+    /// around it is white.
+    pub fn light_color(&self) -> Color {
+        match self {
+            Block::Cube(c) => c.light_color,
+            Block::Model(m) => m.light_color,
+        }
+    }
+
     pub fn name(&self) -> &str {
         match self {
             Block::Cube(c) => &c.name,
@@ -615,6 +756,31 @@ impl Block {
         }
     }
 
+    /// The color used to draw the block on maps, None if it should not be drawn.
+    pub fn map_color(&self) -> Option<Color> {
+        match self {
+            Block::Cube(c) => c.map_color,
+            // TODO: Models are not drawn. Could use the material color of the model.
+            Block::Model(_) => None,
+        }
+    }
+
+    /// If the player can climb up and down the block when inside it.
+    pub fn is_climbable(&self) -> bool {
+        match self {
+            Block::Cube(c) => c.climbable,
+            Block::Model(m) => m.climbable,
+        }
+    }
+
+    /// If rail vehicles can ride along the block. Rails go along the block's front/back axis.
+    pub fn is_rail(&self) -> bool {
+        match self {
+            Block::Cube(c) => c.rail,
+            Block::Model(m) => m.rail,
+        }
+    }
+
     pub fn step_sounds(&self) -> &Vec<String> {
         // Random index, don't know if correct
         match self {
@@ -624,8 +790,43 @@ impl Block {
     }
 }
 
+// None if the color isn't a valid hex color, white if there is no color.
+fn parse_light_color(color: Option<&str>) -> Option<Color> {
+    match color {
+        Some(color) => Srgba::hex(color).ok().map(Color::from),
+        None => Some(Color::WHITE),
+    }
+}
+
+// Average color of the non-transparent pixels of a texture, rgba8
+fn average_texture_color(pixels: &[u8]) -> Option<LinearRgba> {
+    let mut sum = [0u32; 4];
+    let mut count = 0;
+    for pixel in pixels.chunks_exact(4) {
+        if pixel[3] == 0 {
+            continue;
+        }
+        for i in 0..4 {
+            sum[i] += pixel[i] as u32;
+        }
+        count += 1;
+    }
+
+    if count == 0 {
+        return None;
+    }
+
+    let color = Srgba::rgba_u8(
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+        (sum[3] / count) as u8,
+    );
+    return Some(color.into());
+}
+
 // bits:
-//     0000 0000 0000 unused
+//     0000 0000 0000 data, free for the server to use
 //     0000
 //       ^^-north/south/east/west
 //      ^---centered, overrides previous rotation, 1 = centered
@@ -659,6 +860,11 @@ impl BlockState {
     pub fn is_upside_down(&self) -> bool {
         return self.0 & 0b1000 != 0;
     }
+
+    /// The 12 bits the server is free to use for the block, e.g. to select variants.
+    pub fn data(&self) -> u16 {
+        return self.0 >> 4;
+    }
 }
 
 // Clockwise rotation
@@ -671,6 +877,12 @@ pub enum BlockRotation {
     Thrice,
 }
 
+impl From<u16> for BlockRotation {
+    fn from(value: u16) -> Self {
+        return unsafe { std::mem::transmute(value & 0b11) };
+    }
+}
+
 impl BlockRotation {
     // Bevy's coordinate system has +z out of the screen, +y up, +x right so if you do a
     // normal rotation it would look like it's moving clockwise when viewing it from above. Since
@@ -696,6 +908,137 @@ impl BlockRotation {
     }
 }
 
+// Builds the quads of a cube from the 'faces' and 'quads' of its config, along with the cull
+// delimiters of its sides.
+fn build_quads(
+    file_path: &Path,
+    faces: Option<&CubeMeshTextureNames>,
+    quads: Option<&Vec<QuadPrimitiveJson>>,
+    block_textures: &assets::BlockTextures,
+) -> Result<(Vec<QuadPrimitive>, [Option<(f32, f32)>; 4]), String> {
+    let mut mesh_primitives = Vec::new();
+
+    if let Some(faces) = faces {
+        for (i, face_name) in [
+            &faces.top,
+            &faces.front,
+            &faces.left,
+            &faces.right,
+            &faces.back,
+            &faces.bottom,
+        ]
+        .iter()
+        .enumerate()
+        {
+            let texture_array_id = match block_textures.get(face_name) {
+                Some(id) => *id,
+                None => {
+                    return Err(format!(
+                            "Misconfigured assets: failed to read block at: {}, no block texture with the name {}",
+                            file_path.display(),
+                            face_name
+                        ));
+                }
+            };
+
+            let face = match i {
+                0 => BlockFace::Top,
+                1 => BlockFace::Back,
+                2 => BlockFace::Left,
+                3 => BlockFace::Right,
+                4 => BlockFace::Front,
+                5 => BlockFace::Bottom,
+                _ => unreachable!(),
+            };
+
+            let square = QuadPrimitive {
+                vertices: FACE_VERTICES[i],
+                normals: [FACE_NORMALS[i], FACE_NORMALS[i]],
+                texture_array_id,
+                cull_face: Some(face),
+                light_face: face,
+                rotate_texture: false,
+            };
+
+            mesh_primitives.push(square);
+        }
+    }
+
+    let mut cull_delimiters = [None, None, None, None];
+
+    if let Some(quads) = quads {
+        for quad in quads.iter() {
+            let texture_array_id = match block_textures.get(&quad.texture) {
+                Some(id) => *id,
+                None => {
+                    return Err(format!(
+                            "Misconfigured assets: failed to read block at: {}, no block texture with the name {}",
+                            file_path.display(),
+                            &quad.texture
+                        ));
+                }
+            };
+
+            let normals = [
+                (Vec3::from_array(quad.vertices[1]) - Vec3::from_array(quad.vertices[0]))
+                    .cross(Vec3::from_array(quad.vertices[2]) - Vec3::from_array(quad.vertices[1]))
+                    .to_array(),
+                (Vec3::from_array(quad.vertices[3]) - Vec3::from_array(quad.vertices[1]))
+                    .cross(Vec3::from_array(quad.vertices[2]) - Vec3::from_array(quad.vertices[1]))
+                    .to_array(),
+            ];
+
+            let normal = Vec3::from(normals[0]);
+            let normal_max = normal.abs().cmpeq(Vec3::splat(normal.abs().max_element()));
+            let light_face = if normal_max.x {
+                if normal.x.is_sign_positive() {
+                    BlockFace::Right
+                } else {
+                    BlockFace::Left
+                }
+            } else if normal_max.y {
+                if normal.y.is_sign_positive() {
+                    BlockFace::Top
+                } else {
+                    BlockFace::Bottom
+                }
+            } else if normal_max.z {
+                if normal.z.is_sign_positive() {
+                    BlockFace::Front
+                } else {
+                    BlockFace::Back
+                }
+            } else {
+                unreachable!();
+            };
+
+            match quad.cull_face {
+                Some(BlockFace::Top) | Some(BlockFace::Bottom) => (),
+                Some(b) => {
+                    if quad.vertices[0][1] != 1.0 || quad.vertices[2][1] != 1.0 {
+                        // Top left -> top right and vice versa to mirror it to how a
+                        // facing block would see it.
+                        cull_delimiters[b as usize] =
+                            Some((quad.vertices[2][1], quad.vertices[0][1]));
+                    }
+                }
+                None => (),
+            }
+
+            mesh_primitives.push(QuadPrimitive {
+                vertices: quad.vertices,
+                normals,
+                texture_array_id,
+                cull_face: quad.cull_face,
+                light_face,
+                rotate_texture: quad.rotate_texture,
+            });
+        }
+    }
+
+    return Ok((mesh_primitives, cull_delimiters));
+}
+
 /// Block config that is stored on file.
 #[derive(Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
@@ -726,6 +1069,8 @@ enum BlockConfig {
         /// Light emitted by the block
         #[serde(default)]
         light: u8,
+        /// Hex color of the block's faces when it emits light, white if not set.
+        light_color: Option<String>,
         /// If fog should be rendered when the player camera is inside the block.
         fog: Option<FogJson>,
         /// Sounds played when walking on/in block
@@ -734,6 +1079,19 @@ enum BlockConfig {
         /// Block placement rules
         #[serde(default)]
         placement: BlockPlacement,
+        /// If the player can climb the block
+        #[serde(default)]
+        climbable: bool,
+        /// If minecarts and other rail vehicles can ride along the block
+        #[serde(default)]
+        rail: bool,
+        /// Draw the top of the block as a water surface, with waves, reflections and refraction
+        /// depending on the water quality setting.
+        #[serde(default)]
+        water: bool,
+        /// Other faces/quads and rotations for some block states
+        #[serde(default)]
+        variants: Vec<CubeVariantJson>,
     },
     Model {
         /// Name of the block, must be unique
@@ -751,45 +1109,146 @@ enum BlockConfig {
         /// Light emitted by the block
         #[serde(default)]
         light: u8,
+        /// Hex color of the block when it emits light, white if not set.
+        light_color: Option<String>,
         /// Block placement rules
         #[serde(default)]
         placement: BlockPlacement,
+        /// If the player can climb the block
+        #[serde(default)]
+        climbable: bool,
+        /// If minecarts and other rail vehicles can ride along the block
+        #[serde(default)]
+        rail: bool,
     },
 }
 
-impl BlockConfig {
-    fn read_as_json(
-        path: &std::path::Path,
-    ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-        let file = std::fs::File::open(&path)?;
+// Block configs can inherit the fields of other configs. "parent" is the path of a config
+// relative to the block directory, and "extends" is the name of a template or a list of names.
+// Templates are configs with a "template" field that names them, they are not blocks. The fields
+// a config sets itself replace those it inherits, later templates replace the fields of earlier
+// ones, and "parent" is applied first. This must match how the server reads them.
+struct BlockConfigTemplates {
+    configs: HashMap<PathBuf, serde_json::Value>,
+    templates: HashMap<String, PathBuf>,
+}
 
-        let mut config: serde_json::Value = serde_json::from_reader(&file)?;
+impl BlockConfigTemplates {
+    fn load(file_paths: &[PathBuf]) -> Result<Self, String> {
+        let mut configs = HashMap::new();
+        let mut templates = HashMap::new();
 
-        // recursively read parent configs
-        if let Some(parent) = config["parent"].as_str() {
-            let parent_path = std::path::Path::new(BLOCK_CONFIG_PATH).join(parent);
-            let mut parent: serde_json::Value = match Self::read_as_json(&parent_path) {
-                Ok(c) => c,
-                Err(e) => {
+        for path in file_paths {
+            let config = Self::read(path).map_err(|e| {
+                format!(
+                    "failed to read block config at {}\nError: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+
+            if let Some(name) = config.get("template") {
+                let Some(name) = name.as_str() else {
                     return Err(format!(
-                        "Failed to read parent block config at {}: {}",
-                        parent_path.display(),
-                        e
-                    )
-                    .into())
+                        "the 'template' field of the block config at {} must be a name",
+                        path.display()
+                    ));
+                };
+                if templates.insert(name.to_owned(), path.clone()).is_some() {
+                    return Err(format!("there are two block templates named '{}'", name));
                 }
+            }
+
+            configs.insert(path.clone(), config);
+        }
+
+        return Ok(Self { configs, templates });
+    }
+
+    fn read(path: &Path) -> Result<serde_json::Value, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let config: serde_json::Value =
+            serde_json::from_reader(&file).map_err(|e| e.to_string())?;
+        if !config.is_object() {
+            return Err("The config must be a json object".to_owned());
+        }
+        return Ok(config);
+    }
+
+    fn is_template(&self, path: &Path) -> bool {
+        return self
+            .configs
+            .get(path)
+            .is_some_and(|config| config.get("template").is_some());
+    }
+
+    // 'chain' is the configs that are being resolved, to find configs that inherit from themselves.
+    fn resolve(
+        &self,
+        path: &Path,
+        chain: &mut Vec<PathBuf>,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+        if chain.iter().any(|p| p == path) {
+            return Err(format!(
+                "The config at {} inherits from itself",
+                path.display()
+            ));
+        }
+
+        let config = match self.configs.get(path) {
+            Some(config) => config.clone(),
+            None => Self::read(path)?,
+        };
+        let serde_json::Value::Object(mut fields) = config else {
+            unreachable!();
+        };
+
+        chain.push(path.to_path_buf());
+
+        let mut resolved = serde_json::Map::new();
+
+        if let Some(parent) = fields.remove("parent") {
+            let Some(parent) = parent.as_str() else {
+                return Err("'parent' must be a path".to_owned());
             };
+            let parent_path = Path::new(BLOCK_CONFIG_PATH).join(parent);
+            let mut parent = self.resolve(&parent_path, chain).map_err(|e| {
+                format!(
+                    "Failed to read parent block config at {}: {}",
+                    parent_path.display(),
+                    e
+                )
+            })?;
+            resolved.append(&mut parent);
+        }
 
-            // Append to parent to replace the values it shares with the child.
-            parent
-                .as_object_mut()
-                .unwrap()
-                .append(&mut config.as_object_mut().unwrap());
+        let extends = match fields.remove("extends") {
+            None => Vec::new(),
+            Some(serde_json::Value::String(name)) => vec![name],
+            Some(serde_json::Value::Array(names)) => names
+                .into_iter()
+                .map(|name| match name {
+                    serde_json::Value::String(name) => Ok(name),
+                    _ => Err("'extends' must be a template name or a list of them".to_owned()),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err("'extends' must be a template name or a list of them".to_owned()),
+        };
 
-            return Ok(parent);
+        for name in extends {
+            let Some(template_path) = self.templates.get(&name) else {
+                return Err(format!("There is no block template named '{}'", name));
+            };
+            let mut template = self.resolve(template_path, chain)?;
+            resolved.append(&mut template);
         }
 
-        return Ok(config);
+        chain.pop();
+
+        fields.remove("template");
+        resolved.append(&mut fields);
+
+        return Ok(resolved);
     }
 }
 
@@ -84,6 +84,15 @@ impl Chunk {
     pub fn iter_blocks(&self) -> std::iter::Enumerate<std::slice::Iter<BlockId>> {
         self.blocks.iter().enumerate()
     }
+
+    /// The blocks of the chunk, a single block if it is uniform.
+    pub fn blocks(&self) -> &[BlockId] {
+        return &self.blocks;
+    }
+
+    pub fn block_states(&self) -> &HashMap<usize, BlockState> {
+        return &self.block_state;
+    }
 }
 
 impl Index<usize> for Chunk {
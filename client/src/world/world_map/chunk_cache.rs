@@ -0,0 +1,429 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{futures_lite::future, AsyncComputeTaskPool, IoTaskPool, Task},
+};
+use fmc_protocol::messages;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game_state::GameState,
+    networking::NetworkClient,
+    settings::Settings,
+    utils,
+    world::{
+        blocks::{BlockId, BlockState, Blocks},
+        world_map::{chunk::Chunk, chunk_manager, NewChunkEvent, WorldMap},
+        Origin,
+    },
+};
+
+// How often the chunks that have changed are written to disk, in seconds
+const SAVE_INTERVAL: f32 = 10.0;
+// Cached chunks that the server hasn't sent within this many seconds of them being shown are
+// removed. The server only sends the chunks the player can see, so they are probably gone.
+const CONFIRM_TIMEOUT: f32 = 15.0;
+// Most chunks kept for a server, the ones furthest from the player are dropped first.
+const MAX_CACHED_CHUNKS: usize = 100_000;
+
+// TODO: There is no message for the server to tell which chunks have changed since the client was
+// last connected, so the cache can't know which of its chunks are still valid. They are shown
+// until the server sends the real chunks, and the differences are applied as block updates.
+//
+// Keeps the chunks the server has sent on disk, so that the terrain around the player can be shown
+// right away when rejoining instead of waiting for the server to send it. The chunks are stored
+// by the hash of their content, most chunks are only air or stone, and are stored once.
+//
+// server_data/<server>/chunk_cache/<cache key>/
+//     index        which chunk is at each position
+//     blobs/<hash> content of the chunks
+pub struct ChunkCachePlugin;
+impl Plugin for ChunkCachePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Playing), open_cache)
+            .add_systems(
+                Update,
+                (load_cached_chunks, confirm_chunks)
+                    .chain()
+                    .before(chunk_manager::handle_new_chunks)
+                    .run_if(in_state(GameState::Playing).and(resource_exists::<ChunkCache>)),
+            )
+            .add_systems(
+                PostUpdate,
+                (save_changed_chunks, remove_unconfirmed_chunks)
+                    .before(chunk_manager::unload_chunks)
+                    .run_if(in_state(GameState::Playing).and(resource_exists::<ChunkCache>)),
+            )
+            .add_systems(
+                OnExit(GameState::Playing),
+                close_cache.before(chunk_manager::remove_all_chunks),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedChunk {
+    blocks: Vec<BlockId>,
+    // Sorted, so that the same chunk always serializes to the same bytes.
+    block_state: Vec<(usize, u16)>,
+}
+
+impl CachedChunk {
+    fn new(chunk: &Chunk) -> Self {
+        let mut block_state: Vec<(usize, u16)> = chunk
+            .block_states()
+            .iter()
+            .map(|(index, state)| (*index, state.0))
+            .collect();
+        block_state.sort_unstable();
+
+        return Self {
+            blocks: chunk.blocks().to_vec(),
+            block_state,
+        };
+    }
+
+    // The cache is a file on disk, it shouldn't be able to crash the client.
+    fn is_valid(&self) -> bool {
+        let blocks = Blocks::get();
+        return (self.blocks.len() == 1 || self.blocks.len() == Chunk::SIZE.pow(3))
+            && self
+                .blocks
+                .iter()
+                .all(|block_id| blocks.contains(*block_id))
+            && self
+                .block_state
+                .iter()
+                .all(|(index, _)| *index < Chunk::SIZE.pow(3));
+    }
+}
+
+#[derive(Resource)]
+struct ChunkCache {
+    directory: PathBuf,
+    // Hash of the chunk at each position, the chunk is stored in a file named by it.
+    index: HashMap<IVec3, u64>,
+    // Chunks that have been received or changed since the last save
+    changed: HashSet<IVec3>,
+    // Chunks that were shown from the cache, but haven't been sent by the server yet.
+    unconfirmed: HashSet<IVec3>,
+    // Reads the cached chunks around the player when joining
+    task: Option<Task<Vec<(IVec3, CachedChunk)>>>,
+    // If the cached chunks have been read, it is only done once.
+    loaded: bool,
+    confirm_timer: Timer,
+    save_timer: Timer,
+}
+
+impl ChunkCache {
+    fn save(&mut self, world_map: &WorldMap, origin: IVec3) {
+        if self.changed.is_empty() {
+            return;
+        }
+
+        let mut blobs = Vec::new();
+        for position in std::mem::take(&mut self.changed) {
+            let Some(chunk) = world_map.get_chunk(&position) else {
+                continue;
+            };
+
+            let data = bincode::serialize(&CachedChunk::new(chunk)).unwrap();
+            let hash = hash(&data);
+            self.index.insert(position, hash);
+            blobs.push((hash, data));
+        }
+
+        if self.index.len() > MAX_CACHED_CHUNKS {
+            let mut positions: Vec<IVec3> = self.index.keys().copied().collect();
+            positions.sort_unstable_by_key(|position| (*position - origin).abs().max_element());
+            for position in positions.drain(MAX_CACHED_CHUNKS..) {
+                self.index.remove(&position);
+            }
+        }
+
+        let index = bincode::serialize(&self.index).unwrap();
+        let directory = self.directory.clone();
+
+        IoTaskPool::get()
+            .spawn(async move {
+                let blob_directory = directory.join("blobs");
+                if let Err(e) = std::fs::create_dir_all(&blob_directory) {
+                    error!(
+                        "Could not create the chunk cache at '{}', Error: {}",
+                        directory.display(),
+                        e
+                    );
+                    return;
+                }
+
+                for (hash, data) in blobs {
+                    let path = blob_directory.join(format!("{:x}", hash));
+                    // The file is named by its content, if it exists it's the same.
+                    if path.exists() {
+                        continue;
+                    }
+
+                    if let Err(e) = std::fs::write(&path, zstd::encode_all(&data[..], 3).unwrap()) {
+                        error!("Could not write '{}', Error: {}", path.display(), e);
+                    }
+                }
+
+                // Written to a temporary file first so the index isn't lost if the client exits
+                // while writing it.
+                let path = directory.join("index");
+                let temporary = directory.join("index.tmp");
+                if let Err(e) = std::fs::write(&temporary, &index)
+                    .and_then(|_| std::fs::rename(&temporary, &path))
+                {
+                    error!("Could not write '{}', Error: {}", path.display(), e);
+                }
+            })
+            .detach();
+    }
+}
+
+// FNV-1a, std's hasher is not guaranteed to be the same between releases and the files are kept
+// between updates.
+fn hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    return hash;
+}
+
+// The block ids are only valid for the assets they were sent with, the cache is not used if either
+// change.
+fn cache_key(server_config: &messages::ServerConfig) -> u64 {
+    let mut block_ids: Vec<(&String, &BlockId)> = server_config.block_ids.iter().collect();
+    block_ids.sort_unstable();
+
+    let mut data = server_config.assets_hash.to_le_bytes().to_vec();
+    for (name, block_id) in block_ids {
+        data.extend(name.as_bytes());
+        data.extend(block_id.to_le_bytes());
+    }
+
+    return hash(&data);
+}
+
+fn read_chunk(directory: &Path, hash: u64) -> Option<CachedChunk> {
+    let data = std::fs::read(directory.join("blobs").join(format!("{:x}", hash))).ok()?;
+    let data = zstd::decode_all(&data[..]).ok()?;
+    return bincode::deserialize(&data).ok();
+}
+
+// Removes the caches that were made for other assets, and the chunks that are no longer in the
+// index.
+fn remove_stale_files(root: &Path, directory: &Path, hashes: HashSet<u64>) {
+    if let Ok(entries) = std::fs::read_dir(root) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path != directory {
+                std::fs::remove_dir_all(&path).ok();
+            }
+        }
+    }
+
+    if let Ok(entries) = std::fs::read_dir(directory.join("blobs")) {
+        for entry in entries.flatten() {
+            let is_used = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| u64::from_str_radix(name, 16).ok())
+                .is_some_and(|hash| hashes.contains(&hash));
+            if !is_used {
+                std::fs::remove_file(entry.path()).ok();
+            }
+        }
+    }
+}
+
+fn open_cache(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    net: Res<NetworkClient>,
+    server_config: Res<messages::ServerConfig>,
+) {
+    if !settings.chunk_cache {
+        return;
+    }
+
+    let Some(server_directory) = net.server_data_directory() else {
+        return;
+    };
+
+    let root = server_directory.join("chunk_cache");
+    let directory = root.join(format!("{:x}", cache_key(&server_config)));
+
+    let index: HashMap<IVec3, u64> = std::fs::read(directory.join("index"))
+        .ok()
+        .and_then(|data| bincode::deserialize(&data).ok())
+        .unwrap_or_default();
+
+    let hashes = index.values().copied().collect();
+    let cleanup_directory = directory.clone();
+    IoTaskPool::get()
+        .spawn(async move { remove_stale_files(&root, &cleanup_directory, hashes) })
+        .detach();
+
+    commands.insert_resource(ChunkCache {
+        directory,
+        index,
+        changed: HashSet::new(),
+        unconfirmed: HashSet::new(),
+        task: None,
+        loaded: false,
+        confirm_timer: Timer::from_seconds(CONFIRM_TIMEOUT, TimerMode::Once),
+        save_timer: Timer::from_seconds(SAVE_INTERVAL, TimerMode::Repeating),
+    });
+}
+
+fn load_cached_chunks(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    origin: Res<Origin>,
+    mut cache: ResMut<ChunkCache>,
+    mut world_map: ResMut<WorldMap>,
+    mut position_events: EventReader<messages::PlayerPosition>,
+    mut new_chunk_events: EventWriter<NewChunkEvent>,
+) {
+    if !cache.loaded {
+        // Which chunks to show depends on where the player is, the server sends the position
+        // when the player joins.
+        let Some(player_position) = position_events.read().last() else {
+            return;
+        };
+        cache.loaded = true;
+
+        let center =
+            utils::world_position_to_chunk_pos(player_position.position.floor().as_ivec3());
+        let distance = IVec3::new(
+            settings.render_distance as i32,
+            settings.vertical_render_distance as i32,
+            settings.render_distance as i32,
+        ) * Chunk::SIZE as i32;
+        let chunks: Vec<(IVec3, u64)> = cache
+            .index
+            .iter()
+            .filter(|(position, _)| (**position - center).abs().cmple(distance).all())
+            .map(|(position, hash)| (*position, *hash))
+            .collect();
+
+        let directory = cache.directory.clone();
+        cache.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+            chunks
+                .into_iter()
+                .filter_map(|(position, hash)| Some((position, read_chunk(&directory, hash)?)))
+                .collect()
+        }));
+
+        return;
+    }
+
+    let Some(task) = cache.task.as_mut() else {
+        return;
+    };
+
+    let Some(chunks) = future::block_on(future::poll_once(task)) else {
+        return;
+    };
+    cache.task = None;
+
+    for (position, cached_chunk) in chunks {
+        // The server was faster
+        if world_map.contains_chunk(&position) || !cached_chunk.is_valid() {
+            continue;
+        }
+
+        chunk_manager::insert_chunk(
+            &mut commands,
+            &origin,
+            &mut world_map,
+            position,
+            cached_chunk.blocks,
+            cached_chunk
+                .block_state
+                .into_iter()
+                .map(|(index, state)| (index, BlockState(state)))
+                .collect(),
+        );
+        new_chunk_events.send(NewChunkEvent { position });
+        cache.unconfirmed.insert(position);
+    }
+
+    cache.confirm_timer.reset();
+}
+
+fn confirm_chunks(
+    mut cache: ResMut<ChunkCache>,
+    mut chunk_events: EventReader<messages::Chunk>,
+    mut block_updates_events: EventReader<messages::BlockUpdates>,
+) {
+    for chunk in chunk_events.read() {
+        cache.unconfirmed.remove(&chunk.position);
+        cache.changed.insert(chunk.position);
+    }
+
+    for block_updates in block_updates_events.read() {
+        if !cache.unconfirmed.contains(&block_updates.chunk_position) {
+            cache.changed.insert(block_updates.chunk_position);
+        }
+    }
+}
+
+fn save_changed_chunks(
+    time: Res<Time>,
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    mut cache: ResMut<ChunkCache>,
+) {
+    cache.save_timer.tick(time.delta());
+    if cache.save_timer.just_finished() {
+        cache.save(&world_map, origin.0);
+    }
+}
+
+// Run in PostUpdate for the same reason as chunk unloading, see the chunk manager.
+fn remove_unconfirmed_chunks(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut cache: ResMut<ChunkCache>,
+    mut world_map: ResMut<WorldMap>,
+) {
+    if cache.unconfirmed.is_empty() {
+        return;
+    }
+
+    cache.confirm_timer.tick(time.delta());
+    if !cache.confirm_timer.finished() {
+        return;
+    }
+
+    for position in std::mem::take(&mut cache.unconfirmed) {
+        if let Some(chunk) = world_map.chunks.remove(&position) {
+            if let Some(entity) = chunk.entity {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+fn close_cache(
+    mut commands: Commands,
+    origin: Res<Origin>,
+    world_map: Res<WorldMap>,
+    cache: Option<ResMut<ChunkCache>>,
+) {
+    let Some(mut cache) = cache else {
+        return;
+    };
+
+    cache.save(&world_map, origin.0);
+    commands.remove_resource::<ChunkCache>();
+}
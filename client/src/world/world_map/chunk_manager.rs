@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use fmc_protocol::messages;
 
@@ -10,7 +10,7 @@ use crate::{
     rendering::RenderSet,
     settings::Settings,
     world::{
-        blocks::{Block, BlockState, Blocks},
+        blocks::{Block, BlockId, BlockState, Blocks},
         world_map::{
             chunk::{Chunk, ChunkFace, ChunkMarker},
             WorldMap,
@@ -58,7 +58,8 @@ impl Plugin for ChunkManagerPlugin {
                 // kept in Update.
                 PostUpdate,
                 unload_chunks.run_if(resource_changed::<Origin>),
-            );
+            )
+            .add_systems(OnExit(GameState::Playing), remove_all_chunks);
     }
 }
 
@@ -78,7 +79,7 @@ fn pause_system(mut pause: ResMut<Pause>, keyboard_input: Res<ButtonInput<KeyCod
 }
 
 // Removes chunks that are outside the render distance of the player.
-fn unload_chunks(
+pub(super) fn unload_chunks(
     origin: Res<Origin>,
     mut world_map: ResMut<WorldMap>,
     settings: Res<Settings>,
@@ -86,10 +87,12 @@ fn unload_chunks(
 ) {
     world_map.chunks.retain(|chunk_pos, chunk| {
         let distance = (*chunk_pos - origin.0).abs() / IVec3::splat(Chunk::SIZE as i32);
-        if distance
-            .cmpgt(IVec3::splat(settings.render_distance as i32) + 1)
-            .any()
-        {
+        let render_distance = IVec3::new(
+            settings.render_distance as i32,
+            settings.vertical_render_distance as i32,
+            settings.render_distance as i32,
+        );
+        if distance.cmpgt(render_distance + 1).any() {
             if let Some(entity) = chunk.entity {
                 commands.entity(entity).despawn_recursive();
             }
@@ -282,12 +285,13 @@ fn prepare_for_frustum_culling(
 // lookup can't be that bad.
 //
 /// Handles chunks sent from the server.
-fn handle_new_chunks(
+pub(super) fn handle_new_chunks(
     mut commands: Commands,
     net: Res<NetworkClient>,
     origin: Res<Origin>,
     mut world_map: ResMut<WorldMap>,
     mut new_chunk_events: EventWriter<NewChunkEvent>,
+    mut block_updates_events: EventWriter<messages::BlockUpdates>,
     mut received_chunks: EventReader<messages::Chunk>,
 ) {
     for chunk in received_chunks.read() {
@@ -304,52 +308,103 @@ fn handle_new_chunks(
             }
         }
 
+        // The chunk is already known, either from the chunk cache, or because the server sent it
+        // again before it was unloaded. Only the blocks that are different are changed, so that
+        // the light and mesh are updated the same way as when blocks are placed.
+        if let Some(existing) = world_map.get_chunk(&chunk.position) {
+            let block_updates = chunk_difference(existing, &chunk.blocks, &chunk.block_state);
+            if !block_updates.is_empty() {
+                block_updates_events.send(messages::BlockUpdates {
+                    chunk_position: chunk.position,
+                    blocks: block_updates,
+                });
+            }
+            continue;
+        }
+
         new_chunk_events.send(NewChunkEvent {
             position: chunk.position,
         });
 
-        // TODO: Only handles uniform air chunks. These ifs can be collapsed, handle uniformity
-        // in Chunk::new, skip entity like now if the chunk won't have a mesh.
-        if chunk.blocks.len() == 1
-            && match blocks.get_config(chunk.blocks[0]) {
-                Block::Cube(b) if b.quads.len() == 0 => true,
-                _ => false,
-            }
-        {
-            world_map.insert(
-                chunk.position,
-                Chunk::new_air(
-                    chunk.blocks.clone(),
-                    chunk
-                        .block_state
-                        .iter()
-                        .map(|(&k, &v)| (k, BlockState(v)))
-                        .collect(),
-                ),
-            );
+        insert_chunk(
+            &mut commands,
+            &origin,
+            &mut world_map,
+            chunk.position,
+            chunk.blocks.clone(),
+            chunk
+                .block_state
+                .iter()
+                .map(|(&k, &v)| (k, BlockState(v)))
+                .collect(),
+        );
+    }
+}
+
+/// Add a chunk to the world map, the blocks must have been validated.
+pub(super) fn insert_chunk(
+    commands: &mut Commands,
+    origin: &Origin,
+    world_map: &mut WorldMap,
+    position: IVec3,
+    chunk_blocks: Vec<BlockId>,
+    block_state: HashMap<usize, BlockState>,
+) {
+    let blocks = Blocks::get();
+
+    // TODO: Only handles uniform air chunks. These ifs can be collapsed, handle uniformity
+    // in Chunk::new, skip entity like now if the chunk won't have a mesh.
+    if chunk_blocks.len() == 1
+        && match blocks.get_config(chunk_blocks[0]) {
+            Block::Cube(b) if b.quads.len() == 0 => true,
+            _ => false,
+        }
+    {
+        world_map.insert(position, Chunk::new_air(chunk_blocks, block_state));
+    } else {
+        let entity = commands
+            .spawn(TransformBundle {
+                local: Transform::from_translation((position - origin.0).as_vec3()),
+                ..default()
+            })
+            .insert(VisibilityBundle::default())
+            .insert(MovesWithOrigin)
+            .insert(ChunkMarker)
+            .id();
+
+        world_map.insert(position, Chunk::new(entity, chunk_blocks, block_state));
+    }
+}
+
+// The block updates that change the chunk into the received blocks.
+fn chunk_difference(
+    chunk: &Chunk,
+    blocks: &[BlockId],
+    block_state: &HashMap<usize, u16>,
+) -> Vec<(usize, BlockId, Option<u16>)> {
+    let mut block_updates = Vec::new();
+
+    for index in 0..Chunk::SIZE.pow(3) {
+        let block_id = if blocks.len() == 1 {
+            blocks[0]
         } else {
-            let entity = commands
-                .spawn(TransformBundle {
-                    local: Transform::from_translation((chunk.position - origin.0).as_vec3()),
-                    ..default()
-                })
-                .insert(VisibilityBundle::default())
-                .insert(MovesWithOrigin)
-                .insert(ChunkMarker)
-                .id();
+            blocks[index]
+        };
+        let state = block_state.get(&index).copied();
 
-            world_map.insert(
-                chunk.position,
-                Chunk::new(
-                    entity,
-                    chunk.blocks.clone(),
-                    chunk
-                        .block_state
-                        .iter()
-                        .map(|(&k, &v)| (k, BlockState(v)))
-                        .collect(),
-                ),
-            );
+        if chunk[index] != block_id || chunk.block_states().get(&index).map(|s| s.0) != state {
+            block_updates.push((index, block_id, state));
+        }
+    }
+
+    return block_updates;
+}
+
+// Chunks are not kept between servers.
+pub(super) fn remove_all_chunks(mut commands: Commands, mut world_map: ResMut<WorldMap>) {
+    for (_, chunk) in world_map.chunks.drain() {
+        if let Some(entity) = chunk.entity {
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
@@ -1,29 +1,43 @@
 use std::collections::HashMap;
 
-use bevy::prelude::*;
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+};
 
 use crate::{
     rendering::chunk::ExpandedChunk,
     utils,
     world::{
-        blocks::{BlockFace, BlockId, Blocks, Friction},
+        blocks::{BlockFace, BlockId, BlockState, Blocks, Friction},
         world_map::chunk::Chunk,
     },
 };
 
 pub mod chunk;
+mod chunk_cache;
 mod chunk_manager;
 
 pub use chunk_manager::NewChunkEvent;
 
+/// How many chunks the world map holds
+pub const LOADED_CHUNKS: DiagnosticPath = DiagnosticPath::const_new("world/loaded_chunks");
+
 pub struct WorldMapPlugin;
 impl Plugin for WorldMapPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(chunk_manager::ChunkManagerPlugin)
-            .init_resource::<WorldMap>();
+            .add_plugins(chunk_cache::ChunkCachePlugin)
+            .init_resource::<WorldMap>()
+            .register_diagnostic(Diagnostic::new(LOADED_CHUNKS))
+            .add_systems(PostUpdate, measure_loaded_chunks);
     }
 }
 
+fn measure_loaded_chunks(world_map: Res<WorldMap>, mut diagnostics: Diagnostics) {
+    diagnostics.add_measurement(&LOADED_CHUNKS, || world_map.chunks.len() as f64);
+}
+
 /// Map of all chunks that have been received from the server.
 #[derive(Resource, Default)]
 pub struct WorldMap {
@@ -108,6 +122,18 @@ impl WorldMap {
         }
     }
 
+    pub fn get_block_state(&self, position: &IVec3) -> Option<BlockState> {
+        let (chunk_position, block_index) =
+            utils::world_position_to_chunk_position_and_block_index(*position);
+        let chunk = self.get_chunk(&chunk_position)?;
+        let block_position = utils::block_index_to_position(block_index);
+        return chunk.get_block_state(
+            block_position.x as usize,
+            block_position.y as usize,
+            block_position.z as usize,
+        );
+    }
+
     /// Find which block the transform is looking at, if any.
     pub fn raycast_to_block(
         &self,
@@ -0,0 +1,675 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::tasks::{futures_lite::future, IoTaskPool, Task};
+use fmc_protocol::messages;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blocks::Blocks,
+    database::Database,
+    interfaces::ClientInterfaces,
+    items::{ItemCraft, ItemPickup, Items},
+    networking::Server,
+    players::Player,
+    prelude::*,
+    world::{BlockPermissions, PlayerBlockUpdate, WorldMap},
+};
+
+pub const ADVANCEMENT_PATH: &str = "./assets/server/advancements/";
+
+/// Criterion event counted from `items::ItemPickup`, the target is the item name.
+pub const ITEM_PICKUP: &str = "item_pickup";
+/// Criterion event counted from each `PlayerBlockUpdate` that replaces a block, the target is the
+/// name of the replaced block.
+pub const BLOCK_BREAK: &str = "block_break";
+/// Criterion event counted from each `PlayerBlockUpdate` that places a block, the target is the
+/// name of the placed block.
+pub const BLOCK_PLACE: &str = "block_place";
+/// Criterion event counted from `items::ItemCraft`, the target is the item name.
+pub const ITEM_CRAFT: &str = "item_craft";
+/// Criterion event sent when a player kills an entity, the target is the kind of entity, e.g.
+/// "zombie".
+pub const ENTITY_KILL: &str = "entity_kill";
+
+const TOAST_FONT_SIZE: f32 = 8.0;
+const TOAST_TITLE_COLOR: &str = "#ffff55";
+const TOAST_DURATION: f32 = 5.0;
+
+const COMPLETED_COLOR: &str = "#55ff55";
+const IN_PROGRESS_COLOR: &str = "#ffffff";
+const LOCKED_COLOR: &str = "#aaaaaa";
+
+// Advancements are defined in trees, one json file per tree. Players progress through a tree by
+// completing the criteria of its advancements. An advancement's criteria only count after its
+// parent has been completed.
+pub struct AdvancementPlugin;
+impl Plugin for AdvancementPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AdvancementSettings>()
+            .init_resource::<SavingProgress>()
+            .add_event::<AdvancementCriterion>()
+            .add_event::<AdvancementGranted>()
+            .add_systems(PreStartup, load_advancements)
+            .add_systems(
+                PostUpdate,
+                // The block is read before it is replaced.
+                count_block_changes.before(crate::world::handle_block_updates),
+            )
+            .add_systems(
+                Update,
+                (
+                    load_player_progress,
+                    (count_item_pickups, count_item_crafts),
+                    update_progress,
+                    (send_toasts, send_viewer_updates, save_player_progress),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Where advancements are shown on the client, insert it to change the defaults. Nothing is sent
+/// to clients whose assets don't include the interfaces.
+#[derive(Resource)]
+pub struct AdvancementSettings {
+    /// Interface shown for a little while when the player completes an advancement.
+    pub toast_interface: String,
+    /// Text container in the toast interface, its first line is set to the title and description
+    /// of the advancement.
+    pub toast_text: String,
+    /// Text container that lists all advancements and the player's progress.
+    pub viewer_text: String,
+}
+
+impl Default for AdvancementSettings {
+    fn default() -> Self {
+        Self {
+            toast_interface: "advancement_toast".to_owned(),
+            toast_text: "advancement_toast/text".to_owned(),
+            viewer_text: "advancements/list".to_owned(),
+        }
+    }
+}
+
+/// Send this to advance a player's progress. Criteria in the advancement configs listen for
+/// events by name. Some common names are defined as constants in this module, but any name can be
+/// used for custom game events.
+#[derive(Event)]
+pub struct AdvancementCriterion {
+    pub player_entity: Entity,
+    /// Name of the event, e.g. "block_break"
+    pub event: String,
+    /// What the event was applied to, e.g. the name of the broken block. Criteria without a target
+    /// match all targets of the event.
+    pub target: Option<String>,
+    /// How much the event counts towards the criteria it matches.
+    pub amount: u32,
+}
+
+impl AdvancementCriterion {
+    pub fn new(player_entity: Entity, event: &str) -> Self {
+        Self {
+            player_entity,
+            event: event.to_owned(),
+            target: None,
+            amount: 1,
+        }
+    }
+
+    pub fn with_target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_owned());
+        self
+    }
+
+    pub fn with_amount(mut self, amount: u32) -> Self {
+        self.amount = amount;
+        self
+    }
+}
+
+/// Sent when a player completes an advancement.
+#[derive(Event)]
+pub struct AdvancementGranted {
+    pub player_entity: Entity,
+    /// Identifier of the advancement, "tree_name/advancement_name"
+    pub advancement: String,
+}
+
+#[derive(Deserialize)]
+struct AdvancementTreeJson {
+    advancements: HashMap<String, AdvancementJson>,
+}
+
+#[derive(Deserialize)]
+struct AdvancementJson {
+    title: String,
+    description: String,
+    /// Name of an advancement in the same tree.
+    parent: Option<String>,
+    criteria: HashMap<String, Criterion>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Criterion {
+    /// Name of the event that counts towards the criterion.
+    pub event: String,
+    /// Only events with this target count, if not set all targets count.
+    pub target: Option<String>,
+    /// How many times the event has to happen.
+    #[serde(default = "default_count")]
+    pub count: u32,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+impl Criterion {
+    fn matches(&self, criterion_event: &AdvancementCriterion) -> bool {
+        if self.event != criterion_event.event {
+            return false;
+        }
+
+        match &self.target {
+            Some(target) => criterion_event.target.as_ref() == Some(target),
+            None => true,
+        }
+    }
+}
+
+pub struct Advancement {
+    /// Name shown in interfaces
+    pub title: String,
+    pub description: String,
+    /// Identifier of the parent advancement
+    pub parent: Option<String>,
+    /// All criteria must be fulfilled for the advancement to be granted
+    pub criteria: HashMap<String, Criterion>,
+}
+
+/// All advancements, by "tree_name/advancement_name"
+#[derive(Resource, Default)]
+pub struct Advancements {
+    advancements: HashMap<String, Advancement>,
+    // Ordered so the trees are listed the same way every time. Parents are always listed before
+    // their children.
+    order: Vec<String>,
+}
+
+impl Advancements {
+    pub fn get(&self, advancement: &str) -> Option<&Advancement> {
+        return self.advancements.get(advancement);
+    }
+
+    /// Iterate over the advancements, parents are always yielded before their children.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Advancement)> {
+        return self
+            .order
+            .iter()
+            .map(|name| (name, &self.advancements[name]));
+    }
+}
+
+fn load_advancements(mut commands: Commands) {
+    let mut advancements = Advancements::default();
+
+    // Servers are not required to have any advancements.
+    let Ok(directory) = std::fs::read_dir(ADVANCEMENT_PATH) else {
+        commands.insert_resource(advancements);
+        return;
+    };
+
+    let mut file_paths: Vec<_> = directory
+        .map(|entry| {
+            entry
+                .expect("Failed to read the filenames of the advancement configs")
+                .path()
+        })
+        .collect();
+    file_paths.sort();
+
+    for file_path in file_paths {
+        let tree_name = file_path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+
+        let file = match std::fs::File::open(&file_path) {
+            Ok(f) => f,
+            Err(e) => panic!(
+                "Failed to open advancement config at: {}\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        let json: AdvancementTreeJson = match serde_json::from_reader(&file) {
+            Ok(c) => c,
+            Err(e) => panic!(
+                "Couldn't read advancement config from '{}'\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        for (name, advancement) in json.advancements.iter() {
+            if let Some(parent) = &advancement.parent {
+                if !json.advancements.contains_key(parent) {
+                    panic!(
+                        "Failed to parse advancement config at: {}\nError: The advancement '{}' has the parent '{}', but there is no advancement by that name in the tree.",
+                        file_path.display(), name, parent
+                    );
+                }
+            }
+
+            if advancement.criteria.is_empty() {
+                panic!(
+                    "Failed to parse advancement config at: {}\nError: The advancement '{}' has no criteria.",
+                    file_path.display(), name
+                );
+            }
+        }
+
+        // Order the tree so that parents come before their children. Whatever is left when no
+        // more advancements can be placed is part of a cycle.
+        let mut remaining: Vec<_> = json.advancements.into_iter().collect();
+        remaining.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut placed = HashSet::new();
+        while !remaining.is_empty() {
+            let before = remaining.len();
+
+            remaining.retain(|(name, json)| {
+                if json
+                    .parent
+                    .as_ref()
+                    .is_some_and(|parent| !placed.contains(parent))
+                {
+                    return true;
+                }
+
+                let identifier = tree_name.clone() + "/" + name;
+                advancements.order.push(identifier.clone());
+                advancements.advancements.insert(
+                    identifier,
+                    Advancement {
+                        title: json.title.clone(),
+                        description: json.description.clone(),
+                        parent: json
+                            .parent
+                            .as_ref()
+                            .map(|parent| tree_name.clone() + "/" + parent),
+                        criteria: json.criteria.clone(),
+                    },
+                );
+                placed.insert(name.clone());
+                return false;
+            });
+
+            if remaining.len() == before {
+                panic!(
+                    "Failed to parse advancement config at: {}\nError: The parents of the advancement '{}' form a cycle.",
+                    file_path.display(), remaining[0].0
+                );
+            }
+        }
+    }
+
+    commands.insert_resource(advancements);
+}
+
+/// The advancement progress of a player, inserted when the player joins.
+#[derive(Component, Serialize, Deserialize, Default, Clone)]
+pub struct AdvancementProgress {
+    completed: HashSet<String>,
+    // Map from advancement to how far each of its criteria has come.
+    criteria: HashMap<String, HashMap<String, u32>>,
+}
+
+impl AdvancementProgress {
+    pub fn is_completed(&self, advancement: &str) -> bool {
+        return self.completed.contains(advancement);
+    }
+
+    /// How many times the event of the criterion has happened
+    pub fn criterion_progress(&self, advancement: &str, criterion: &str) -> u32 {
+        return self
+            .criteria
+            .get(advancement)
+            .and_then(|criteria| criteria.get(criterion))
+            .copied()
+            .unwrap_or(0);
+    }
+
+    // An advancement can be progressed once its parent is done.
+    fn is_unlocked(&self, advancement: &Advancement) -> bool {
+        return advancement
+            .parent
+            .as_ref()
+            .map_or(true, |parent| self.is_completed(parent));
+    }
+}
+
+// Stops the next toast from overwriting the current one before the player has had time to read it.
+#[derive(Component, Default)]
+struct ToastQueue {
+    queue: VecDeque<String>,
+    timer: Option<Timer>,
+}
+
+fn load_player_progress(
+    mut commands: Commands,
+    database: Res<Database>,
+    player_query: Query<(Entity, &Player), Added<Player>>,
+) {
+    for (entity, player) in player_query.iter() {
+        let progress = database
+            .load_advancement_progress(&player.account_id)
+            .unwrap_or_default();
+        commands
+            .entity(entity)
+            .insert((progress, ToastQueue::default()));
+    }
+}
+
+fn count_block_changes(
+    world_map: Res<WorldMap>,
+    permissions: BlockPermissions,
+    mut block_updates: EventReader<PlayerBlockUpdate>,
+    mut criterion_events: EventWriter<AdvancementCriterion>,
+) {
+    let blocks = Blocks::get();
+    let air = blocks.get_id("air");
+
+    for update in block_updates.read() {
+        // Same as the updates that are applied
+        if !permissions.can_modify(update.player_entity, update.position) {
+            continue;
+        }
+        let Some(old_block) = world_map.get_block(update.position) else {
+            continue;
+        };
+
+        if old_block != air {
+            criterion_events.send(
+                AdvancementCriterion::new(update.player_entity, BLOCK_BREAK)
+                    .with_target(&blocks.get_config(&old_block).name),
+            );
+        }
+
+        if update.block_id != air {
+            criterion_events.send(
+                AdvancementCriterion::new(update.player_entity, BLOCK_PLACE)
+                    .with_target(&blocks.get_config(&update.block_id).name),
+            );
+        }
+    }
+}
+
+fn count_item_pickups(
+    items: Res<Items>,
+    mut pickup_events: EventReader<ItemPickup>,
+    mut criterion_events: EventWriter<AdvancementCriterion>,
+) {
+    for pickup in pickup_events.read() {
+        criterion_events.send(
+            AdvancementCriterion::new(pickup.player_entity, ITEM_PICKUP)
+                .with_target(&items.get_config(&pickup.item_id).name)
+                .with_amount(pickup.amount),
+        );
+    }
+}
+
+fn count_item_crafts(
+    items: Res<Items>,
+    mut craft_events: EventReader<ItemCraft>,
+    mut criterion_events: EventWriter<AdvancementCriterion>,
+) {
+    for craft in craft_events.read() {
+        criterion_events.send(
+            AdvancementCriterion::new(craft.player_entity, ITEM_CRAFT)
+                .with_target(&items.get_config(&craft.item_id).name)
+                .with_amount(craft.amount),
+        );
+    }
+}
+
+fn update_progress(
+    advancements: Res<Advancements>,
+    mut player_query: Query<&mut AdvancementProgress>,
+    mut criterion_events: EventReader<AdvancementCriterion>,
+    mut granted_events: EventWriter<AdvancementGranted>,
+) {
+    for criterion_event in criterion_events.read() {
+        let Ok(mut progress) = player_query.get_mut(criterion_event.player_entity) else {
+            continue;
+        };
+
+        // Completing an advancement unlocks its children, they are visited after it by the
+        // iterator so the same event can count towards both.
+        for (name, advancement) in advancements.iter() {
+            if progress.is_completed(name) || !progress.is_unlocked(advancement) {
+                continue;
+            }
+
+            let mut changed = false;
+            for (criterion_name, criterion) in advancement.criteria.iter() {
+                if !criterion.matches(criterion_event) {
+                    continue;
+                }
+
+                let count = progress
+                    .criteria
+                    .entry(name.clone())
+                    .or_default()
+                    .entry(criterion_name.clone())
+                    .or_default();
+                if *count < criterion.count {
+                    *count = (*count + criterion_event.amount).min(criterion.count);
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                continue;
+            }
+
+            let done = advancement
+                .criteria
+                .iter()
+                .all(|(criterion_name, criterion)| {
+                    progress.criterion_progress(name, criterion_name) >= criterion.count
+                });
+
+            if done {
+                progress.criteria.remove(name);
+                progress.completed.insert(name.clone());
+                granted_events.send(AdvancementGranted {
+                    player_entity: criterion_event.player_entity,
+                    advancement: name.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn send_toasts(
+    net: Res<Server>,
+    time: Res<Time>,
+    settings: Res<AdvancementSettings>,
+    advancements: Res<Advancements>,
+    mut toast_query: Query<(Entity, &mut ToastQueue, Option<&ClientInterfaces>)>,
+    mut granted_events: EventReader<AdvancementGranted>,
+) {
+    for granted in granted_events.read() {
+        if let Ok((_, mut toasts, _)) = toast_query.get_mut(granted.player_entity) {
+            toasts.queue.push_back(granted.advancement.clone());
+        }
+    }
+
+    for (player_entity, mut toasts, client_interfaces) in toast_query.iter_mut() {
+        // The toasts wait in the queue until the client has said it has the interface.
+        if !client_interfaces.is_some_and(|interfaces| {
+            interfaces.has_interface(&settings.toast_interface)
+                && interfaces.has_node(&settings.toast_text)
+        }) {
+            continue;
+        }
+
+        if let Some(timer) = &mut toasts.timer {
+            timer.tick(time.delta());
+            if !timer.finished() {
+                continue;
+            }
+
+            toasts.timer = None;
+
+            if toasts.queue.is_empty() {
+                net.send_one(
+                    player_entity,
+                    messages::InterfaceVisibilityUpdate {
+                        interface_path: settings.toast_interface.clone(),
+                        visible: false,
+                    },
+                );
+                continue;
+            }
+        }
+
+        let Some(name) = toasts.queue.pop_front() else {
+            continue;
+        };
+        let advancement = advancements.get(&name).unwrap();
+
+        // The title and description are sent as one line. The client doesn't guarantee the
+        // order of lines that are added in the same tick.
+        net.send_one(
+            player_entity,
+            messages::InterfaceTextUpdate {
+                interface_path: settings.toast_text.clone(),
+                index: 0,
+                text: format!("{}\n{}", advancement.title, advancement.description),
+                font_size: TOAST_FONT_SIZE,
+                color: TOAST_TITLE_COLOR.to_owned(),
+            },
+        );
+        net.send_one(
+            player_entity,
+            messages::InterfaceVisibilityUpdate {
+                interface_path: settings.toast_interface.clone(),
+                visible: true,
+            },
+        );
+
+        toasts.timer = Some(Timer::from_seconds(TOAST_DURATION, TimerMode::Once));
+    }
+}
+
+// TODO: The whole list is sent every time the progress changes. Only the changed lines need to
+// be sent.
+fn send_viewer_updates(
+    net: Res<Server>,
+    settings: Res<AdvancementSettings>,
+    advancements: Res<Advancements>,
+    progress_query: Query<
+        (Entity, &AdvancementProgress, &ClientInterfaces),
+        Or<(Changed<AdvancementProgress>, Changed<ClientInterfaces>)>,
+    >,
+) {
+    for (player_entity, progress, client_interfaces) in progress_query.iter() {
+        if !client_interfaces.has_node(&settings.viewer_text) {
+            continue;
+        }
+
+        for (index, (name, advancement)) in advancements.iter().enumerate() {
+            let (text, color) = if progress.is_completed(name) {
+                (
+                    format!("{} - {}", advancement.title, advancement.description),
+                    COMPLETED_COLOR,
+                )
+            } else if progress.is_unlocked(advancement) {
+                let done: u32 = advancement
+                    .criteria
+                    .iter()
+                    .map(|(criterion_name, criterion)| {
+                        progress
+                            .criterion_progress(name, criterion_name)
+                            .min(criterion.count)
+                    })
+                    .sum();
+                let total: u32 = advancement
+                    .criteria
+                    .values()
+                    .map(|criterion| criterion.count)
+                    .sum();
+                (
+                    format!(
+                        "{} ({}/{}) - {}",
+                        advancement.title, done, total, advancement.description
+                    ),
+                    IN_PROGRESS_COLOR,
+                )
+            } else {
+                (advancement.title.clone(), LOCKED_COLOR)
+            };
+
+            net.send_one(
+                player_entity,
+                messages::InterfaceTextUpdate {
+                    interface_path: settings.viewer_text.clone(),
+                    index: index as i32,
+                    text,
+                    font_size: TOAST_FONT_SIZE,
+                    color: color.to_owned(),
+                },
+            );
+        }
+    }
+}
+
+// Progress that is being written to the database, by account id. Each account is written one at
+// a time, so that an older save can't finish after a newer one. Only the latest progress waits
+// for its turn.
+#[derive(Resource, Default)]
+struct SavingProgress {
+    tasks: HashMap<String, Task<()>>,
+    waiting: HashMap<String, AdvancementProgress>,
+}
+
+fn save_player_progress(
+    database: Res<Database>,
+    mut saving: ResMut<SavingProgress>,
+    progress_query: Query<(&Player, Ref<AdvancementProgress>)>,
+) {
+    for (player, progress) in progress_query.iter() {
+        // Freshly loaded progress is already in the database
+        if !progress.is_changed() || progress.is_added() {
+            continue;
+        }
+
+        saving
+            .waiting
+            .insert(player.account_id.clone(), progress.clone());
+    }
+
+    saving
+        .tasks
+        .retain(|_, task| future::block_on(future::poll_once(task)).is_none());
+
+    // Reborrow to make split borrowing work.
+    let saving = saving.into_inner();
+    saving.waiting.retain(|account_id, progress| {
+        if saving.tasks.contains_key(account_id) {
+            return true;
+        }
+
+        let database = database.clone();
+        let task_account_id = account_id.clone();
+        let progress = progress.clone();
+        let task = IoTaskPool::get()
+            .spawn(async move { database.save_advancement_progress(&task_account_id, &progress) });
+        saving.tasks.insert(account_id.clone(), task);
+
+        return false;
+    });
+}
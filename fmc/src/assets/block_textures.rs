@@ -0,0 +1,144 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{blocks, prelude::*};
+
+const BLOCK_TEXTURE_PATH: &str = "./assets/client/textures/blocks/";
+const MANIFEST_PATH: &str = "./assets/client/textures/block_textures.json";
+// Width of the block textures, they can be several of these tall to make animations.
+const BLOCK_TEXTURE_SIZE: u32 = 16;
+
+// The clients put the block textures into a texture array in the order of the manifest, so that
+// every client gives a texture the same index. Textures that are taller than they are wide are
+// split into frames, one for each square, which get their own index after each other.
+#[derive(Serialize)]
+struct BlockTextureManifest {
+    size: u32,
+    textures: Vec<ManifestTexture>,
+}
+
+#[derive(Serialize)]
+struct ManifestTexture {
+    name: String,
+    frames: u32,
+}
+
+// Checks that the block textures and the textures the block configs use are valid, and writes the
+// manifest. Failing here is better than every client failing when it loads them.
+pub(super) fn write_block_texture_manifest() {
+    let directory = std::fs::read_dir(BLOCK_TEXTURE_PATH).expect(&format!(
+        "Could not read the block textures, make sure the directory is present at '{}'",
+        BLOCK_TEXTURE_PATH
+    ));
+
+    // Sorted so the manifest doesn't change unless the textures do
+    let mut textures = BTreeMap::new();
+    for dir_entry in directory {
+        let path = match dir_entry {
+            Ok(d) => d.path(),
+            Err(e) => panic!("Failed to read the path of a block texture\nError: {}", e),
+        };
+
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "png")
+        {
+            panic!(
+                "The block texture at '{}' is not a png file, block textures must be png",
+                path.display()
+            );
+        }
+
+        let (width, height) = match image::image_dimensions(&path) {
+            Ok(dimensions) => dimensions,
+            Err(e) => panic!(
+                "Failed to read the block texture at '{}'\nError: {}",
+                path.display(),
+                e
+            ),
+        };
+
+        if width != BLOCK_TEXTURE_SIZE || height == 0 || height % BLOCK_TEXTURE_SIZE != 0 {
+            panic!(
+                "The block texture at '{}' is {}x{}, block textures must be {} pixels wide and a \
+                multiple of {} tall",
+                path.display(),
+                width,
+                height,
+                BLOCK_TEXTURE_SIZE,
+                BLOCK_TEXTURE_SIZE
+            );
+        }
+
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        textures.insert(name, height / BLOCK_TEXTURE_SIZE);
+    }
+
+    for (config_path, config) in blocks::read_block_configs() {
+        for texture in referenced_textures(&config) {
+            if !textures.contains_key(&texture) {
+                panic!(
+                    "The block config at '{}' uses the texture '{}', but there is no block texture \
+                    by that name in '{}'",
+                    config_path.display(),
+                    texture,
+                    BLOCK_TEXTURE_PATH
+                );
+            }
+        }
+    }
+
+    let manifest = BlockTextureManifest {
+        size: BLOCK_TEXTURE_SIZE,
+        textures: textures
+            .into_iter()
+            .map(|(name, frames)| ManifestTexture { name, frames })
+            .collect(),
+    };
+
+    let path = PathBuf::from(MANIFEST_PATH);
+    if let Err(e) = std::fs::write(&path, serde_json::to_vec(&manifest).unwrap()) {
+        panic!(
+            "Could not write the block texture manifest to '{}'\nError: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+// The textures of the faces and quads of a block config and its variants
+fn referenced_textures(config: &serde_json::Map<String, serde_json::Value>) -> Vec<String> {
+    let mut textures = Vec::new();
+
+    if let Some(faces) = config.get("faces").and_then(|faces| faces.as_object()) {
+        textures.extend(
+            faces
+                .values()
+                .filter_map(|texture| texture.as_str())
+                .map(|texture| texture.to_owned()),
+        );
+    }
+
+    if let Some(quads) = config.get("quads").and_then(|quads| quads.as_array()) {
+        textures.extend(
+            quads
+                .iter()
+                .filter_map(|quad| quad.get("texture"))
+                .filter_map(|texture| texture.as_str())
+                .map(|texture| texture.to_owned()),
+        );
+    }
+
+    // Variants can have their own faces and quads
+    if let Some(variants) = config
+        .get("variants")
+        .and_then(|variants| variants.as_array())
+    {
+        for variant in variants.iter().filter_map(|variant| variant.as_object()) {
+            textures.extend(referenced_textures(variant));
+        }
+    }
+
+    return textures;
+}
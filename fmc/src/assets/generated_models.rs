@@ -0,0 +1,404 @@
+use std::{
+    borrow::Cow,
+    mem,
+    path::{Path, PathBuf},
+};
+
+use gltf::json::{self, validation::Checked::Valid};
+
+use crate::{blocks, models::MODEL_PATH, prelude::*};
+
+const TEXTURE_PATH: &str = "./assets/client/textures/";
+// The models that were generated the last time the server started, they are removed before they
+// are generated again so that models of blocks that were removed don't linger.
+const GENERATED_MODELS_RECORD: &str = "./assets/generated_models.json";
+
+// Block configs can have a "generate_item_model" field, to get a model for the item that places
+// the block without having to make one by hand. It is written to the model directory as
+// "<block name>_item" every time the server starts, so it is always made from the current
+// textures.
+//
+// "generate_item_model": true
+//     A cube with the faces of the block
+// "generate_item_model": "items/torch.png"
+//     A flat model of the texture, like an item held in the hand. The path is relative to the
+//     texture directory.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum GenerateItemModel {
+    Cube(bool),
+    Flat(String),
+}
+
+#[derive(serde::Deserialize)]
+struct BlockFaces {
+    top: String,
+    bottom: String,
+    left: String,
+    right: String,
+    front: String,
+    back: String,
+}
+
+/// Generates the item models of the blocks that ask for one. Must run before the model ids are
+/// saved.
+pub(crate) fn generate_models() {
+    let previous: Vec<PathBuf> = std::fs::read(GENERATED_MODELS_RECORD)
+        .ok()
+        .and_then(|record| serde_json::from_slice(&record).ok())
+        .unwrap_or_default();
+    for path in previous {
+        std::fs::remove_file(path).ok();
+    }
+
+    let mut generated = Vec::new();
+
+    for (config_path, config) in blocks::read_block_configs() {
+        let Some(generate) = config.get("generate_item_model") else {
+            continue;
+        };
+
+        let generate: GenerateItemModel = match serde_json::from_value(generate.clone()) {
+            Ok(g) => g,
+            Err(_) => panic!(
+                "Failed to read block config at {}: 'generate_item_model' must be true or the path of a texture",
+                config_path.display()
+            ),
+        };
+
+        if let GenerateItemModel::Cube(false) = generate {
+            continue;
+        }
+
+        // Checked when the block is loaded
+        let name = config["name"].as_str().unwrap();
+        let model_name = format!("{}_item", name);
+
+        for extension in ["json", "glb", "gltf"] {
+            let path = Path::new(MODEL_PATH).join(format!("{}.{}", model_name, extension));
+            if path.exists() {
+                panic!(
+                    "The block '{}' generates the model '{}', but there is already a model by that name at '{}'",
+                    name,
+                    model_name,
+                    path.display()
+                );
+            }
+        }
+
+        let (path, data) = match generate {
+            GenerateItemModel::Cube(_) => {
+                let faces: BlockFaces = match config
+                    .get("faces")
+                    .and_then(|faces| serde_json::from_value(faces.clone()).ok())
+                {
+                    Some(faces) => faces,
+                    None => panic!(
+                        "Failed to read block config at {}: a cube model can only be generated for blocks with 'faces'",
+                        config_path.display()
+                    ),
+                };
+
+                // Json block models are built into gltf models by the clients.
+                let model = serde_json::json!({
+                    "block": {
+                        "top": faces.top,
+                        "bottom": faces.bottom,
+                        "left": faces.left,
+                        "right": faces.right,
+                        "front": faces.front,
+                        "back": faces.back,
+                    }
+                });
+                (
+                    Path::new(MODEL_PATH).join(model_name + ".json"),
+                    serde_json::to_vec(&model).unwrap(),
+                )
+            }
+            GenerateItemModel::Flat(texture) => {
+                let texture_path = Path::new(TEXTURE_PATH).join(&texture);
+                let image = match image::open(&texture_path) {
+                    Ok(image) => image.to_rgba8(),
+                    Err(e) => panic!(
+                        "Failed to generate the item model of the block '{}' from the texture at '{}'\nError: {}",
+                        name,
+                        texture_path.display(),
+                        e
+                    ),
+                };
+
+                let mut glb = Vec::new();
+                flat_model(&image).to_writer(&mut glb).unwrap();
+                (Path::new(MODEL_PATH).join(model_name + ".glb"), glb)
+            }
+        };
+
+        if let Err(e) = std::fs::write(&path, data) {
+            panic!(
+                "Could not write the generated model to '{}'\nError: {}",
+                path.display(),
+                e
+            );
+        }
+        generated.push(path);
+    }
+
+    if let Err(e) = std::fs::write(
+        GENERATED_MODELS_RECORD,
+        serde_json::to_vec(&generated).unwrap(),
+    ) {
+        panic!(
+            "Could not save the list of generated models to '{}'\nError: {}",
+            GENERATED_MODELS_RECORD, e
+        );
+    }
+
+    info!("Generated {} item models", generated.len());
+}
+
+// The faces of a voxel, two triangles each
+const VOXEL_FACES: [[[f32; 3]; 6]; 6] = [
+    // Top
+    [
+        [0.0, 1.0, 0.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 1.0, 1.0],
+    ],
+    // Bottom
+    [
+        [0.0, 0.0, 1.0],
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 1.0],
+        [1.0, 0.0, 1.0],
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+    ],
+    // Left
+    [
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 0.0],
+        [0.0, 1.0, 1.0],
+        [0.0, 1.0, 0.0],
+    ],
+    // Right
+    [
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 0.0, 1.0],
+        [1.0, 0.0, 1.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 1.0, 1.0],
+    ],
+    // Front
+    [
+        [0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+    ],
+    // Back
+    [
+        [1.0, 0.0, 1.0],
+        [1.0, 1.0, 1.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 0.0, 1.0],
+        [1.0, 1.0, 1.0],
+        [0.0, 1.0, 1.0],
+    ],
+];
+
+// A pixel is a sixteenth of a block, the same as the block textures.
+const PIXEL_SIZE: f32 = 1.0 / 16.0;
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct Vertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+// Makes a voxel for every pixel that isn't transparent, colored by the pixel, with only the faces
+// that can be seen. The model is centered on the origin.
+fn flat_model(image: &image::RgbaImage) -> gltf::binary::Glb<'static> {
+    let mut vertices = Vec::new();
+
+    let is_transparent = |x: i64, y: i64| {
+        x < 0
+            || y < 0
+            || x >= image.width() as i64
+            || y >= image.height() as i64
+            || image.get_pixel(x as u32, y as u32)[3] == 0
+    };
+
+    for (x, y, color) in image.enumerate_pixels() {
+        if color[3] == 0 {
+            continue;
+        }
+
+        // Compensate for the image starting in the top left corner, which is the minimum x value
+        // and the maximum y value.
+        let offset = [
+            x as f32 - image.width() as f32 / 2.0,
+            image.height() as f32 / 2.0 - y as f32 - 1.0,
+            -0.5,
+        ];
+        // Gltf vertex colors are linear
+        let color = [0, 1, 2].map(|i| (color[i] as f32 / 255.0).powf(2.2));
+
+        let (x, y) = (x as i64, y as i64);
+        let visible_faces = [
+            is_transparent(x, y - 1),
+            is_transparent(x, y + 1),
+            is_transparent(x - 1, y),
+            is_transparent(x + 1, y),
+            true,
+            true,
+        ];
+
+        for (face, visible) in VOXEL_FACES.iter().zip(visible_faces) {
+            if !visible {
+                continue;
+            }
+
+            for position in face {
+                vertices.push(Vertex {
+                    position: [
+                        (position[0] + offset[0]) * PIXEL_SIZE,
+                        (position[1] + offset[1]) * PIXEL_SIZE,
+                        (position[2] + offset[2]) * PIXEL_SIZE,
+                    ],
+                    color,
+                });
+            }
+        }
+    }
+
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for vertex in vertices.iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(vertex.position[i]);
+            max[i] = max[i].max(vertex.position[i]);
+        }
+    }
+    if vertices.is_empty() {
+        min = [0.0; 3];
+        max = [0.0; 3];
+    }
+
+    let vertex_count = vertices.len();
+    let buffer_length = vertex_count * mem::size_of::<Vertex>();
+
+    let accessor = |offset: usize, bounds: Option<([f32; 3], [f32; 3])>| json::Accessor {
+        buffer_view: Some(json::Index::new(0)),
+        byte_offset: Some(offset.into()),
+        count: vertex_count.into(),
+        component_type: Valid(json::accessor::GenericComponentType(
+            json::accessor::ComponentType::F32,
+        )),
+        extensions: Default::default(),
+        extras: Default::default(),
+        type_: Valid(json::accessor::Type::Vec3),
+        min: bounds.map(|(min, _)| json::Value::from(Vec::from(min))),
+        max: bounds.map(|(_, max)| json::Value::from(Vec::from(max))),
+        name: None,
+        normalized: false,
+        sparse: None,
+    };
+
+    let primitive = json::mesh::Primitive {
+        attributes: {
+            let mut map = std::collections::BTreeMap::new();
+            map.insert(Valid(json::mesh::Semantic::Positions), json::Index::new(0));
+            map.insert(Valid(json::mesh::Semantic::Colors(0)), json::Index::new(1));
+            map
+        },
+        extensions: Default::default(),
+        extras: Default::default(),
+        indices: None,
+        material: None,
+        mode: Valid(json::mesh::Mode::Triangles),
+        targets: None,
+    };
+
+    let root = json::Root {
+        accessors: vec![
+            accessor(0, Some((min, max))),
+            accessor(3 * mem::size_of::<f32>(), None),
+        ],
+        buffers: vec![json::Buffer {
+            byte_length: buffer_length.into(),
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            uri: None,
+        }],
+        buffer_views: vec![json::buffer::View {
+            buffer: json::Index::new(0),
+            byte_length: buffer_length.into(),
+            byte_offset: None,
+            byte_stride: Some(json::buffer::Stride(mem::size_of::<Vertex>())),
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            target: Some(Valid(json::buffer::Target::ArrayBuffer)),
+        }],
+        meshes: vec![json::Mesh {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            primitives: vec![primitive],
+            weights: None,
+        }],
+        nodes: vec![json::Node {
+            camera: None,
+            children: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+            matrix: None,
+            mesh: Some(json::Index::new(0)),
+            name: None,
+            rotation: None,
+            scale: None,
+            translation: None,
+            skin: None,
+            weights: None,
+        }],
+        scenes: vec![json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes: vec![json::Index::new(0)],
+        }],
+        ..Default::default()
+    };
+
+    let json_string = json::serialize::to_string(&root).unwrap();
+
+    let mut bin = Vec::with_capacity(buffer_length);
+    for vertex in vertices {
+        for value in vertex.position.iter().chain(vertex.color.iter()) {
+            bin.extend(value.to_le_bytes());
+        }
+    }
+
+    let glb = gltf::binary::Glb {
+        header: gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            // Set by to_writer
+            length: 0,
+        },
+        bin: Some(Cow::Owned(bin)),
+        json: Cow::Owned(json_string.into_bytes()),
+    };
+
+    return glb;
+}
@@ -0,0 +1,177 @@
+use std::{path::Path, sync::Arc};
+
+use bevy::{
+    prelude::*,
+    tasks::{IoTaskPool, Task},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+mod block_textures;
+mod generated_models;
+
+pub(crate) use generated_models::generate_models;
+
+const ASSET_DIRECTORY: &str = "assets/client";
+
+pub struct AssetPlugin;
+impl Plugin for AssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreStartup, make_asset_tarball);
+    }
+}
+
+#[derive(Resource)]
+pub struct Assets {
+    /// Hash of all the files and their content, it changes if any of them change.
+    pub hash: u64,
+    /// The whole archive, sent to clients that don't ask for a manifest.
+    pub asset_message: Arc<Vec<u8>>,
+    /// The files of the archive, paths are relative to the client asset directory.
+    pub files: Arc<Vec<AssetFile>>,
+}
+
+impl Assets {
+    /// The manifest of the files, for the client to check against the files it has cached.
+    pub fn manifest_message(&self) -> Vec<u8> {
+        return bincode::serialize(&self.files).unwrap();
+    }
+
+    /// An archive of only some of the files, in the same format as the whole archive. Paths that
+    /// are not in the manifest are ignored. The files are read and compressed off the main thread.
+    pub fn partial_archive(&self, paths: Vec<String>) -> Task<Vec<u8>> {
+        let files = self.files.clone();
+        return IoTaskPool::get().spawn(async move {
+            let mut archive = tar::Builder::new(Vec::new());
+            for path in paths {
+                if !files.iter().any(|file| file.path == path) {
+                    continue;
+                }
+
+                if let Err(e) =
+                    archive.append_path_with_name(Path::new(ASSET_DIRECTORY).join(&path), &path)
+                {
+                    error!("Could not add '{}' to the asset archive: {}", path, e);
+                }
+            }
+
+            let archive = archive.into_inner().unwrap();
+            zstd::encode_all(&archive[..], 5).unwrap()
+        });
+    }
+}
+
+/// A file in the asset archive
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AssetFile {
+    pub path: String,
+    /// SHA-256 of the content. Clients cache the files by it, and check that what they download
+    /// matches it before it is cached.
+    pub hash: [u8; 32],
+    pub size: u64,
+}
+
+// TODO: AssetRequest has no fields, so the request is sent as its payload. Clients that send it
+// empty get the whole archive. The client has a copy of this, it should be moved to the protocol
+// together with AssetFile when it is updated.
+//
+/// What a client asks for when it requests assets
+#[derive(Serialize, Deserialize, Debug)]
+pub enum AssetDownloadRequest {
+    /// The list of files, to know which are missing from its cache
+    Manifest,
+    /// An archive of only these files
+    Files(Vec<String>),
+}
+
+fn make_asset_tarball(mut commands: Commands) {
+    block_textures::write_block_texture_manifest();
+
+    let files = read_asset_files();
+    let possibly_changed_assets = build_asset_archive();
+
+    if let Ok(saved_assets) = std::fs::read("assets/assets.tar.zstd") {
+        if hash(&saved_assets) != hash(&possibly_changed_assets) {
+            // Tarball doesn't match the asset directory (something added since last run)
+            std::fs::write("assets/assets.tar.zstd", &possibly_changed_assets).unwrap();
+        }
+    } else {
+        // Assets haven't been saved to a tarball yet
+        std::fs::write("assets/assets.tar.zstd", &possibly_changed_assets).unwrap();
+    }
+
+    let mut manifest = Vec::new();
+    for file in files.iter() {
+        manifest.extend(file.path.as_bytes());
+        manifest.extend(file.hash);
+    }
+
+    commands.insert_resource(Assets {
+        hash: hash(&manifest),
+        asset_message: Arc::new(possibly_changed_assets),
+        files: Arc::new(files),
+    });
+}
+
+// FNV-1a, std's hasher is not guaranteed to be stable between releases, and clients keep their
+// cache between server updates.
+fn hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    return hash;
+}
+
+// All the files in the client asset directory, sorted by path
+fn read_asset_files() -> Vec<AssetFile> {
+    fn walk_dir(root: &Path, dir: &Path, files: &mut Vec<AssetFile>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk_dir(root, &path, files);
+                continue;
+            }
+
+            let data = std::fs::read(&path).unwrap();
+            let relative = path.strip_prefix(root).unwrap();
+            // Forward slashes on all platforms, the paths are compared on the client.
+            let relative = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            files.push(AssetFile {
+                path: relative,
+                hash: Sha256::digest(&data).into(),
+                size: data.len() as u64,
+            });
+        }
+    }
+
+    let mut files = Vec::new();
+    walk_dir(
+        Path::new(ASSET_DIRECTORY),
+        Path::new(ASSET_DIRECTORY),
+        &mut files,
+    );
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    return files;
+}
+
+/// Creates an archive from all the assets in the client assets directory
+fn build_asset_archive() -> Vec<u8> {
+    let mut archive = tar::Builder::new(Vec::new());
+    archive.append_dir_all(".", ASSET_DIRECTORY).unwrap();
+
+    let archive = archive.into_inner().unwrap();
+
+    // TODO: This needs to be max compressed, as it will probably reach hundreds of megabytes.
+    // Compressing it would take several seconds each startup. Need to store uncrompressed archive
+    // hash so that we know whether we need to rebuild, avoid compressing.
+    let compressed = zstd::encode_all(&archive[..], 5).unwrap();
+
+    compressed
+}
@@ -4,7 +4,7 @@
 //       missing, and update the database if a config has been changed.
 use std::{
     collections::{HashMap, HashSet},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use bevy::{
@@ -12,7 +12,7 @@ use bevy::{
     math::{DQuat, DVec3},
 };
 use rand::{distributions::WeightedIndex, prelude::Distribution};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     database::Database,
@@ -49,30 +49,29 @@ impl Plugin for BlockPlugin {
     }
 }
 
-fn load_blocks_to_resource(mut commands: Commands, database: Res<Database>, models: Res<Models>) {
-    fn walk_dir<P: AsRef<std::path::Path>>(dir: P) -> Vec<std::path::PathBuf> {
-        let mut files = Vec::new();
+fn walk_dir<P: AsRef<std::path::Path>>(dir: P) -> Vec<std::path::PathBuf> {
+    let mut files = Vec::new();
 
-        let directory = std::fs::read_dir(dir).expect(
-            "Could not read files from block configuration directory, make sure it is present",
-        );
+    let directory = std::fs::read_dir(dir)
+        .expect("Could not read files from block configuration directory, make sure it is present");
 
-        for entry in directory {
-            let file_path = entry
-                .expect("Failed to read a path while loading the block configs")
-                .path();
+    for entry in directory {
+        let file_path = entry
+            .expect("Failed to read a path while loading the block configs")
+            .path();
 
-            if file_path.is_dir() {
-                let sub_files = walk_dir(&file_path);
-                files.extend(sub_files);
-            } else {
-                files.push(file_path);
-            }
+        if file_path.is_dir() {
+            let sub_files = walk_dir(&file_path);
+            files.extend(sub_files);
+        } else {
+            files.push(file_path);
         }
-
-        files
     }
 
+    files
+}
+
+fn load_blocks_to_resource(mut commands: Commands, database: Res<Database>, models: Res<Models>) {
     let mut blocks = Blocks {
         blocks: Vec::new(),
         ids: database.load_block_ids(),
@@ -86,8 +85,11 @@ fn load_blocks_to_resource(mut commands: Commands, database: Res<Database>, mode
 
     let block_materials = load_block_materials();
 
-    for file_path in walk_dir(&BLOCK_CONFIG_PATH) {
-        let block_config_json = match BlockConfigJson::from_file(&file_path) {
+    let file_paths = walk_dir(&BLOCK_CONFIG_PATH);
+    let templates = BlockConfigTemplates::load(&file_paths);
+
+    for file_path in file_paths {
+        let block_config_json = match BlockConfigJson::from_file(&file_path, &templates) {
             Some(b) => b,
             None => continue,
         };
@@ -124,6 +126,31 @@ fn load_blocks_to_resource(mut commands: Commands, database: Res<Database>, mode
             None
         };
 
+        let mut variants = Vec::with_capacity(block_config_json.variants.len());
+        for variant in block_config_json.variants {
+            if variant.rotation.is_some_and(|rotation| rotation > 3) {
+                panic!(
+                    "Failed to read block config at {}: the rotation of a variant must be 0-3 quarter turns",
+                    file_path.display()
+                );
+            }
+
+            let model = match variant.model {
+                Some(_) if model_id.is_none() => panic!(
+                    "Failed to read block config at {}: only blocks with a 'model' can have variants with models",
+                    file_path.display()
+                ),
+                Some(model_name) => Some(models.get_by_name(&model_name).id),
+                None => None,
+            };
+
+            variants.push(BlockVariant {
+                when: variant.when,
+                model,
+                rotation: variant.rotation.map(BlockRotation::from),
+            });
+        }
+
         let hitbox = if let Some(hitbox) = block_config_json.hitbox {
             Some(hitbox.to_collider())
         } else if let Some(model_name) = block_config_json.model {
@@ -177,9 +204,20 @@ fn load_blocks_to_resource(mut commands: Commands, database: Res<Database>, mode
             let block_config = BlockConfig {
                 name: block_config_json.name,
                 model: model_id,
+                variants,
                 friction: block_config_json.friction,
                 hardness: block_config_json.hardness,
                 replaceable: block_config_json.replaceable,
+                climbable: block_config_json.climbable,
+                hazards: block_config_json.hazards,
+                rail: block_config_json.rail,
+                light: block_config_json.light.min(15),
+                // Same as the client, model blocks don't block light
+                light_attenuation: if model_id.is_some() {
+                    0
+                } else {
+                    block_config_json.light_attenuation.unwrap_or(15).min(15)
+                },
                 tools: block_config_json.tools,
                 drop,
                 material,
@@ -205,7 +243,7 @@ fn load_blocks_to_resource(mut commands: Commands, database: Res<Database>, mode
     commands.insert_resource(blocks);
 }
 
-fn move_blocks_resource_to_static(mut commands: Commands, mut blocks: ResMut<Blocks>) {
+pub(crate) fn move_blocks_resource_to_static(mut commands: Commands, mut blocks: ResMut<Blocks>) {
     let blocks = std::mem::replace(&mut *blocks, Blocks::default());
     BLOCKS.set(blocks).ok();
     commands.remove_resource::<Blocks>();
@@ -467,6 +505,32 @@ struct BlockVerticesJson {
     vertices: [[f32; 3]; 4],
 }
 
+/// An effect a block has on the entities that touch it or come within range of it. Entities
+/// need the `physics::HazardImmunity` component to be affected.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BlockHazard {
+    /// What the hazard does, e.g. "burn", "prick" or "freeze". The game decides what it means.
+    pub effect: String,
+    /// How strong the effect is, e.g. how much damage it does.
+    #[serde(default = "default_hazard_amount")]
+    pub amount: f32,
+    /// How far away from the block the entity is affected. At 0.0 it has to touch the block.
+    #[serde(default)]
+    pub range: f64,
+    /// Seconds after being affected before the entity can be affected by the same effect
+    /// again.
+    #[serde(default = "default_invulnerability")]
+    pub invulnerability: f64,
+}
+
+fn default_hazard_amount() -> f32 {
+    1.0
+}
+
+fn default_invulnerability() -> f64 {
+    0.5
+}
+
 #[derive(Debug, Deserialize, Default)]
 pub struct Sounds {
     #[serde(default)]
@@ -523,6 +587,20 @@ struct BlockConfigJson {
     hardness: Option<f32>,
     #[serde(default)]
     replaceable: bool,
+    // Lets entities climb up the block, e.g. ladders and vines.
+    #[serde(default)]
+    climbable: bool,
+    // Effects on entities that touch or are near the block.
+    #[serde(default)]
+    hazards: Vec<BlockHazard>,
+    // Rail vehicles can ride along the block.
+    #[serde(default)]
+    rail: bool,
+    // Light emitted by the block, 0-15
+    #[serde(default)]
+    light: u8,
+    // How many levels light decreases when passing through the block, opaque if not set.
+    light_attenuation: Option<u8>,
     // Which tool categories will break this block faster.
     #[serde(default)]
     tools: HashSet<String>,
@@ -539,6 +617,9 @@ struct BlockConfigJson {
     model: Option<String>,
     quads: Option<Vec<BlockVerticesJson>>,
     faces: Option<BlockFaceTextures>,
+    // Models to use instead of 'model' depending on the block state, see 'BlockVariant'.
+    #[serde(default)]
+    variants: Vec<BlockVariantJson>,
     // Rules for how the block can be placed by the player.
     #[serde(default)]
     placement: BlockPlacement,
@@ -550,48 +631,26 @@ struct BlockConfigJson {
 }
 
 impl BlockConfigJson {
-    fn from_file(path: &Path) -> Option<Self> {
-        fn read_as_json_value(
-            path: &std::path::Path,
-        ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-            let file = std::fs::File::open(&path)?;
-
-            let mut config: serde_json::Value = serde_json::from_reader(&file)?;
-
-            // recursively read parent configs
-            if let Some(parent) = config["parent"].as_str() {
-                let parent_path = std::path::Path::new(BLOCK_CONFIG_PATH).join(parent);
-                let mut parent: serde_json::Value = match read_as_json_value(&parent_path) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        return Err(format!(
-                            "Failed to read parent block config at {}: {}",
-                            parent_path.display(),
-                            e
-                        )
-                        .into())
-                    }
-                };
-                parent
-                    .as_object_mut()
-                    .unwrap()
-                    .append(&mut config.as_object_mut().unwrap());
-
-                config = parent;
-            }
-
-            Ok(config)
+    fn from_file(path: &Path, templates: &BlockConfigTemplates) -> Option<Self> {
+        // Templates are not blocks
+        if templates.is_template(path) {
+            return None;
         }
 
-        let json = match read_as_json_value(path) {
+        let json = match templates.resolve(path, &mut Vec::new()) {
             Ok(j) => j,
             Err(e) => panic!("Failed to read block config at {}: {}", path.display(), e),
         };
 
         // This filters out parent configs
         if json.get("name").is_some_and(|name| name.is_string()) {
+            debug!(
+                "Resolved block config at {}: {}",
+                path.display(),
+                serde_json::Value::Object(json.clone())
+            );
             // TODO: When this fails, theres no way to know which field made it panic.
-            return match serde_json::from_value(json) {
+            return match serde_json::from_value(serde_json::Value::Object(json)) {
                 Ok(b) => Some(b),
                 Err(e) => panic!("Failed to read block config at {}: {}", path.display(), e),
             };
@@ -601,6 +660,194 @@ impl BlockConfigJson {
     }
 }
 
+/// The block configs in the block directory, with the fields they inherit from templates and
+/// parents. Templates and configs that are only parents are left out.
+pub(crate) fn read_block_configs() -> Vec<(PathBuf, serde_json::Map<String, serde_json::Value>)> {
+    let file_paths = walk_dir(&BLOCK_CONFIG_PATH);
+    let templates = BlockConfigTemplates::load(&file_paths);
+
+    let mut configs = Vec::new();
+    for file_path in file_paths {
+        if templates.is_template(&file_path) {
+            continue;
+        }
+
+        let json = match templates.resolve(&file_path, &mut Vec::new()) {
+            Ok(j) => j,
+            Err(e) => panic!(
+                "Failed to read block config at {}: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        if json.get("name").is_some_and(|name| name.is_string()) {
+            configs.push((file_path, json));
+        }
+    }
+
+    return configs;
+}
+
+// Block configs can inherit the fields of other configs so that blocks that are alike don't have
+// to repeat them. "parent" is the path of a config relative to the block directory, and "extends"
+// is the name of a template, or a list of names. Templates are configs with a "template" field
+// that names them, they are not blocks themselves and can extend other templates.
+//
+// {
+//     "template": "stone_like",
+//     "hardness": 1.5,
+//     "tools": ["pickaxe"]
+// }
+//
+// {
+//     "name": "granite",
+//     "extends": "stone_like",
+//     "faces": { ... }
+// }
+//
+// Fields the config sets itself replace the ones it inherits. With several templates, fields of
+// later templates replace those of earlier ones, and "parent" is applied before them.
+struct BlockConfigTemplates {
+    configs: HashMap<PathBuf, serde_json::Value>,
+    // Path of each template by name
+    templates: HashMap<String, PathBuf>,
+}
+
+impl BlockConfigTemplates {
+    fn load(file_paths: &[PathBuf]) -> Self {
+        let mut configs = HashMap::new();
+        let mut templates: HashMap<String, PathBuf> = HashMap::new();
+
+        for path in file_paths {
+            let config = match Self::read(path) {
+                Ok(config) => config,
+                Err(e) => panic!("Failed to read block config at {}: {}", path.display(), e),
+            };
+
+            if let Some(name) = config.get("template") {
+                let Some(name) = name.as_str() else {
+                    panic!(
+                        "The 'template' field of the block config at {} must be a name",
+                        path.display()
+                    );
+                };
+                if let Some(other) = templates.insert(name.to_owned(), path.clone()) {
+                    panic!(
+                        "There are two block templates named '{}', at {} and {}",
+                        name,
+                        other.display(),
+                        path.display()
+                    );
+                }
+            }
+
+            configs.insert(path.clone(), config);
+        }
+
+        info!(
+            "Loaded {} block configs, {} of them templates",
+            configs.len(),
+            templates.len()
+        );
+
+        return Self { configs, templates };
+    }
+
+    fn read(path: &Path) -> Result<serde_json::Value, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let config: serde_json::Value =
+            serde_json::from_reader(&file).map_err(|e| e.to_string())?;
+        if !config.is_object() {
+            return Err("The config must be a json object".to_owned());
+        }
+        return Ok(config);
+    }
+
+    fn is_template(&self, path: &Path) -> bool {
+        return self
+            .configs
+            .get(path)
+            .is_some_and(|config| config.get("template").is_some());
+    }
+
+    // The config with all the fields it inherits. 'chain' is the configs that are being resolved,
+    // to find configs that inherit from themselves.
+    fn resolve(
+        &self,
+        path: &Path,
+        chain: &mut Vec<PathBuf>,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+        if chain.iter().any(|p| p == path) {
+            let cycle: Vec<String> = chain
+                .iter()
+                .chain(std::iter::once(&path.to_path_buf()))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format!(
+                "The config inherits from itself: {}",
+                cycle.join(" -> ")
+            ));
+        }
+
+        let config = match self.configs.get(path) {
+            Some(config) => config.clone(),
+            // Parents can be outside the block directory
+            None => Self::read(path)?,
+        };
+        let serde_json::Value::Object(mut fields) = config else {
+            unreachable!();
+        };
+
+        chain.push(path.to_path_buf());
+
+        let mut resolved = serde_json::Map::new();
+
+        if let Some(parent) = fields.remove("parent") {
+            let Some(parent) = parent.as_str() else {
+                return Err("'parent' must be a path".to_owned());
+            };
+            let parent_path = Path::new(BLOCK_CONFIG_PATH).join(parent);
+            let mut parent = self.resolve(&parent_path, chain).map_err(|e| {
+                format!(
+                    "Failed to read parent block config at {}: {}",
+                    parent_path.display(),
+                    e
+                )
+            })?;
+            resolved.append(&mut parent);
+        }
+
+        let extends = match fields.remove("extends") {
+            None => Vec::new(),
+            Some(serde_json::Value::String(name)) => vec![name],
+            Some(serde_json::Value::Array(names)) => names
+                .into_iter()
+                .map(|name| match name {
+                    serde_json::Value::String(name) => Ok(name),
+                    _ => Err("'extends' must be a template name or a list of them".to_owned()),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err("'extends' must be a template name or a list of them".to_owned()),
+        };
+
+        for name in extends {
+            let Some(template_path) = self.templates.get(&name) else {
+                return Err(format!("There is no block template named '{}'", name));
+            };
+            let mut template = self.resolve(template_path, chain)?;
+            resolved.append(&mut template);
+        }
+
+        chain.pop();
+
+        fields.remove("template");
+        resolved.append(&mut fields);
+
+        return Ok(resolved);
+    }
+}
+
 // TODO: 'hardness' 'tools' 'drop' 'particle_textures' are too specific. They should be handled
 // outside of library. Add a new field 'properties' with serde(flatten) on it to capture everything
 // not needed. The server implementor should then make their own 'Blocks' and 'BlockConfig'. Parse
@@ -611,6 +858,8 @@ pub struct BlockConfig {
     pub name: String,
     /// If a model is used to represent this block, this contains its model id
     pub model: Option<ModelId>,
+    /// Models used instead of `model` for some block states
+    pub variants: Vec<BlockVariant>,
     /// The friction or drag.
     pub friction: Friction,
     // TODO: Not needed
@@ -619,6 +868,17 @@ pub struct BlockConfig {
     pub hardness: Option<f32>,
     /// Makes it possible to replace the block by placing another in its position.
     pub replaceable: bool,
+    /// Players inside the block can climb up and down it, e.g. ladders and vines.
+    pub climbable: bool,
+    /// Effects the block has on entities that touch it or come near it, e.g. lava burning.
+    pub hazards: Vec<BlockHazard>,
+    /// Rail vehicles can ride along the block, in the direction of its front face.
+    pub rail: bool,
+    /// Light emitted by the block, 0-15. Its color is only used by the clients.
+    pub light: u8,
+    /// How many levels light decreases when passing through the block, 0 lets sunlight through
+    /// unimpeded, 15 blocks all light.
+    pub light_attenuation: u8,
     // TODO: Not needed
     // Which tool categories will break this block faster.
     pub tools: HashSet<String>,
@@ -707,6 +967,93 @@ impl BlockConfig {
         return Some(block_state);
     }
 
+    /// The block state the block should be placed with, following its `BlockPlacement`. It is
+    /// placed against the `against_block_face` of the block that was clicked at `hit_position`,
+    /// by a player looking in the `look_direction`. None if it can't be placed against the face,
+    /// or if it doesn't need a block state.
+    pub fn placement_state(
+        &self,
+        against_block_face: BlockFace,
+        hit_position: DVec3,
+        look_direction: DVec3,
+    ) -> Option<BlockState> {
+        if !self.is_placeable(against_block_face) {
+            return None;
+        }
+
+        if !self.placement.rotatable && !self.placement.upside_down {
+            return None;
+        }
+
+        let mut block_state = BlockState::new();
+
+        if !self.placement.rotatable {
+            block_state.set_centered(true);
+        } else if self.placement.face_player {
+            block_state.set_rotation(BlockRotation::facing(-look_direction));
+        } else if (against_block_face == BlockFace::Bottom || against_block_face == BlockFace::Top)
+            && self.placement.centered
+        {
+            block_state.set_centered(true);
+        } else if against_block_face == BlockFace::Bottom || against_block_face == BlockFace::Top {
+            block_state.set_rotation(BlockRotation::facing(-look_direction));
+        } else {
+            block_state.set_rotation(against_block_face.to_rotation());
+        }
+
+        if self.placement.upside_down {
+            let upper_half = match against_block_face {
+                BlockFace::Bottom => true,
+                BlockFace::Top => false,
+                _ => hit_position.y.rem_euclid(1.0) > 0.5,
+            };
+            block_state.set_upside_down(upper_half);
+        }
+
+        return Some(block_state);
+    }
+
+    /// The model the block is shown as and its transform when it is at the position, None if it
+    /// isn't a model block.
+    pub fn model_transform(
+        &self,
+        position: IVec3,
+        block_state: Option<BlockState>,
+    ) -> Option<(ModelId, Transform)> {
+        let Some(model_id) = self.model else {
+            return None;
+        };
+
+        // The clients treat blocks without a block state as centered
+        let block_state = block_state.unwrap_or(BlockState(0b100));
+        let variant = self
+            .variants
+            .iter()
+            .find(|variant| variant.when.matches(block_state));
+
+        let model_id = variant.and_then(|v| v.model).unwrap_or(model_id);
+        let rotation = match variant.and_then(|v| v.rotation) {
+            Some(rotation) => Some((
+                rotation,
+                self.placement.rotation_transform.unwrap_or_default(),
+            )),
+            None => block_state
+                .rotation()
+                .zip(self.placement.rotation_transform),
+        };
+
+        let mut transform =
+            Transform::from_translation(position.as_dvec3() + DVec3::new(0.5, 0.0, 0.5));
+        if let Some((rotation, mut rotation_transform)) = rotation {
+            rotation_transform.rotate_around(DVec3::ZERO, rotation.as_quat());
+            transform.translation += rotation_transform.translation;
+            transform.rotation *= rotation_transform.rotation;
+            transform.scale *= rotation_transform.scale;
+        }
+
+        return Some((model_id, transform));
+    }
+
     pub fn particle_texture(&self, block_face: BlockFace) -> Option<&str> {
         if let Some(paths) = &self.particle_textures {
             let path = match block_face {
@@ -739,6 +1086,83 @@ impl BlockConfig {
 
         return Some(format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a));
     }
+
+    /// The color the block is drawn with when viewed from above on a map, None if the block
+    /// should not show up, e.g. air.
+    pub fn map_color(&self) -> Option<[u8; 4]> {
+        let Some(material) = &self.material else {
+            return None;
+        };
+
+        let Some(color) = &material.base_color else {
+            return None;
+        };
+
+        if color.alpha <= 0.0 {
+            return None;
+        }
+
+        return Some([
+            (color.red.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (color.green.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (color.blue.clamp(0.0, 1.0) * 255.0).round() as u8,
+            (color.alpha.clamp(0.0, 1.0) * 255.0).round() as u8,
+        ]);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockVariantJson {
+    #[serde(default)]
+    when: BlockStateCondition,
+    model: Option<String>,
+    rotation: Option<u16>,
+}
+
+/// A model block can look different depending on its block state, like a lever that is on or
+/// off, by listing variants in its config. The first variant the block state matches is used.
+///
+/// ```json
+/// "variants": [
+///     { "when": { "data": 1 }, "model": "lever_on" },
+///     { "when": { "centered": true }, "rotation": 2 }
+/// ]
+/// ```
+///
+/// The rotation is in quarter turns and replaces the rotation of the block state. Cube blocks
+/// can have variants with their own "faces" or "quads" in the same way, they are drawn by the
+/// clients.
+#[derive(Debug)]
+pub struct BlockVariant {
+    pub when: BlockStateCondition,
+    /// Used instead of the block's model
+    pub model: Option<ModelId>,
+    pub rotation: Option<BlockRotation>,
+}
+
+/// The parts of a block state a variant is for, those that aren't set match anything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BlockStateCondition {
+    /// Quarter turns, 0-3, never matches centered blocks
+    pub rotation: Option<u16>,
+    pub centered: Option<bool>,
+    pub upside_down: Option<bool>,
+    pub data: Option<u16>,
+}
+
+impl BlockStateCondition {
+    pub fn matches(&self, block_state: BlockState) -> bool {
+        return self.rotation.map_or(true, |rotation| {
+            block_state.rotation().is_some_and(|r| r as u16 == rotation)
+        }) && self
+            .centered
+            .map_or(true, |centered| centered == block_state.is_centered())
+            && self.upside_down.map_or(true, |upside_down| {
+                upside_down == block_state.is_upside_down()
+            })
+            && self.data.map_or(true, |data| data == block_state.data());
+    }
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -755,6 +1179,12 @@ pub struct BlockPlacement {
     /// If 'rotatable' is set, this allows a block to be placed without rotation if it is placed on
     /// the Top or Bottom face of a block.
     pub centered: bool,
+    /// If 'rotatable' is set, the block is turned to face the player when placed, instead of
+    /// facing away from the block it was placed against, e.g. stairs and furnaces.
+    pub face_player: bool,
+    /// Set if the block should be upside down when placed against the bottom face of a block, or
+    /// the upper half of its sides, e.g. slabs and stairs.
+    pub upside_down: bool,
     /// Set if a transform should be applied when rotated.
     pub rotation_transform: Option<Transform>,
 }
@@ -767,6 +1197,8 @@ impl Default for BlockPlacement {
             sides: true,
             rotatable: false,
             centered: true,
+            face_player: false,
+            upside_down: false,
             rotation_transform: None,
         }
     }
@@ -806,7 +1238,7 @@ impl BlockFace {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum Friction {
     /// For solid blocks.
@@ -822,11 +1254,13 @@ pub enum Friction {
     Drag(DVec3),
 }
 
-#[derive(Component, Deref, DerefMut)]
+/// Data stored with a block, e.g. the items in a chest. Block entities are spawned with the data
+/// that was saved for them, and changes to it are saved along with the block.
+#[derive(Component, Deref, DerefMut, Clone)]
 pub struct BlockData(pub Vec<u8>);
 
 // bits:
-//     0000 0000 0000 unused
+//     0000 0000 0000 data, free for the block to use, e.g. to select variants
 //     0000
 //       ^^-north/south/east/west
 //      ^---centered, overrides rotation, 1 = centered
@@ -852,6 +1286,30 @@ impl BlockState {
         self.0 & 0b100 != 0
     }
 
+    pub fn set_upside_down(&mut self, upside_down: bool) {
+        self.0 &= !0b1000;
+        self.0 |= (upside_down as u16) << 3;
+    }
+
+    pub fn is_upside_down(&self) -> bool {
+        self.0 & 0b1000 != 0
+    }
+
+    /// Set the 12 bits of the block state that are free to use, higher bits are ignored.
+    pub fn set_data(&mut self, data: u16) {
+        self.0 &= 0b1111;
+        self.0 |= data << 4;
+    }
+
+    pub fn with_data(mut self, data: u16) -> Self {
+        self.set_data(data);
+        self
+    }
+
+    pub fn data(&self) -> u16 {
+        self.0 >> 4
+    }
+
     pub fn set_rotation(&mut self, rotation: BlockRotation) {
         self.0 &= !0b11;
         self.0 |= rotation as u16;
@@ -888,7 +1346,7 @@ impl BlockPosition {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum BlockRotation {
     None = 0,
@@ -905,6 +1363,37 @@ impl From<u16> for BlockRotation {
 }
 
 impl BlockRotation {
+    /// The rotation that turns the front of a block in the horizontal direction closest to the
+    /// given direction.
+    pub fn facing(direction: DVec3) -> Self {
+        if direction.x.abs() > direction.z.abs() {
+            if direction.x > 0.0 {
+                Self::Once
+            } else {
+                Self::Thrice
+            }
+        } else if direction.z > 0.0 {
+            Self::None
+        } else {
+            Self::Twice
+        }
+    }
+
+    /// The face the front of the block is turned to
+    pub fn front_face(self) -> BlockFace {
+        match self {
+            Self::None => BlockFace::Front,
+            Self::Once => BlockFace::Right,
+            Self::Twice => BlockFace::Back,
+            Self::Thrice => BlockFace::Left,
+        }
+    }
+
+    /// Turn the rotation counter-clockwise by a number of quarter turns, seen from above.
+    pub fn turn(self, quarter_turns: u16) -> Self {
+        return Self::from(self as u16 + quarter_turns);
+    }
+
     pub fn as_quat(self) -> DQuat {
         match self {
             Self::None => DQuat::from_rotation_y(0.0),
@@ -938,3 +1427,88 @@ impl Default for BlockMaterial {
         }
     }
 }
+
+/// The shape of a stair, which depends on the stairs next to it. Stairs are expected to be
+/// rotated so their front is the low step, like with `BlockPlacement::face_player`. Left and right
+/// are as seen by someone standing in front of the stair, looking at it.
+///
+/// It can be stored in the data of the block state so variants can show the right model.
+///
+/// ```ignore
+/// let shape = StairShape::from_neighbours(block_state, |face| {
+///     let position = face.shift_position(position);
+///     world_map
+///         .get_block(position)
+///         .filter(|block_id| is_stair(*block_id))
+///         .and_then(|_| world_map.get_block_state(position))
+/// });
+/// block_state.set_data(shape as u16);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum StairShape {
+    Straight = 0,
+    /// Inside corner, with an extra step in the front left quarter
+    InnerLeft,
+    /// Inside corner, with an extra step in the front right quarter
+    InnerRight,
+    /// Outside corner, only the back left quarter is raised
+    OuterLeft,
+    /// Outside corner, only the back right quarter is raised
+    OuterRight,
+}
+
+impl StairShape {
+    /// Find the shape of a stair from the stairs around it. `neighbour` gives the block state of
+    /// the block next to the stair at the face, if it is also a stair.
+    pub fn from_neighbours(
+        block_state: BlockState,
+        neighbour: impl Fn(BlockFace) -> Option<BlockState>,
+    ) -> Self {
+        let Some(rotation) = block_state.rotation() else {
+            return Self::Straight;
+        };
+
+        // Corners only connect stairs of the same half, that are turned a quarter from it.
+        let connects = |other: &BlockState| {
+            other.is_upside_down() == block_state.is_upside_down()
+                && other
+                    .rotation()
+                    .is_some_and(|other| (other as u16 + rotation as u16) % 2 == 1)
+        };
+        // A corner is not made if the stair on the side it would bend towards continues straight.
+        let is_straight_at = |face: BlockFace| {
+            neighbour(face).is_some_and(|other| {
+                other.rotation() == Some(rotation)
+                    && other.is_upside_down() == block_state.is_upside_down()
+            })
+        };
+
+        // The stair it connects to is turned this way for the corner to bend to the left
+        let left = rotation.turn(1);
+
+        if let Some(behind) = neighbour(rotation.turn(2).front_face()).filter(connects) {
+            let behind = behind.rotation().unwrap();
+            if !is_straight_at(behind.front_face()) {
+                return if behind == left {
+                    Self::OuterLeft
+                } else {
+                    Self::OuterRight
+                };
+            }
+        }
+
+        if let Some(in_front) = neighbour(rotation.front_face()).filter(connects) {
+            let in_front = in_front.rotation().unwrap();
+            if !is_straight_at(in_front.turn(2).front_face()) {
+                return if in_front == left {
+                    Self::InnerLeft
+                } else {
+                    Self::InnerRight
+                };
+            }
+        }
+
+        return Self::Straight;
+    }
+}
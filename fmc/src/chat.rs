@@ -1,76 +1,934 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
 use bevy::prelude::*;
 use fmc_protocol::messages;
 
 use crate::{
-    networking::{NetworkEvent, NetworkMessage, Server},
+    networking::{
+        properties, ClientProperty, NetworkEvent, NetworkMessage, ResumedSession, Server,
+    },
     players::Player,
+    utils,
+    world::chunk::Chunk,
 };
 
 pub const CHAT_FONT_SIZE: f32 = 8.0;
 pub const CHAT_TEXT_COLOR: &str = "#ffffff";
+/// The channel all players are in, messages are sent here unless the player switches channel.
+pub const GLOBAL_CHANNEL: &str = "global";
+/// The channel for the players close to each other
+pub const LOCAL_CHANNEL: &str = "local";
+
+// Longest name a player can give a channel they create with /join
+const MAX_CHANNEL_NAME_LENGTH: usize = 16;
 
 pub struct ChatPlugin;
 impl Plugin for ChatPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (handle_chat_messages, send_connection_messages));
+        app.add_event::<ChatCommand>()
+            .add_event::<ChatViolation>()
+            .init_resource::<ChatFilter>()
+            .init_resource::<ChatSettings>()
+            .init_resource::<PendingChatMessages>()
+            .init_resource::<ChatChannels>()
+            .configure_sets(
+                Update,
+                (ChatSet::Receive, ChatSet::Filter, ChatSet::Send).chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    (insert_chat_preferences, update_chat_preferences).chain(),
+                    (
+                        handle_chat_messages,
+                        handle_channel_commands,
+                        handle_channel_properties,
+                    )
+                        .chain()
+                        .in_set(ChatSet::Receive),
+                    (strip_links, mask_blocked_words)
+                        .chain()
+                        .in_set(ChatSet::Filter),
+                    send_chat_messages.in_set(ChatSet::Send),
+                    (remove_channel_members, send_channel_lists)
+                        .chain()
+                        .after(ChatSet::Receive),
+                    send_connection_messages,
+                ),
+            );
+    }
+}
+
+/// Chat messages go through these in order. Add systems to `ChatSet::Filter` to change or cancel
+/// messages through `PendingChatMessages` before they are sent.
+#[derive(SystemSet, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum ChatSet {
+    /// Messages are read from the players
+    Receive,
+    /// Messages can be changed or cancelled
+    Filter,
+    /// Messages are sent to all players
+    Send,
+}
+
+/// How the built in filters treat chat messages.
+#[derive(Resource)]
+pub struct ChatSettings {
+    /// Most messages and commands a player can send within `rate_interval`, the rest are
+    /// dropped. None for no limit.
+    pub rate_limit: Option<u32>,
+    /// Seconds the rate limit counts messages over
+    pub rate_interval: f32,
+    /// Replace links in messages
+    pub strip_links: bool,
+    /// Words that are masked with '*'s, in lowercase. They are only matched as whole words.
+    pub blocked_words: HashSet<String>,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            rate_limit: Some(5),
+            rate_interval: 5.0,
+            strip_links: false,
+            blocked_words: HashSet::new(),
+        }
+    }
+}
+
+/// A chat message written by a player, on its way to the other players.
+pub struct ChatMessage {
+    /// The player that wrote the message
+    pub player_entity: Entity,
+    /// The text as it will be shown, without the player's name
+    pub text: String,
+    /// Who the message is sent to
+    pub target: ChatTarget,
+    cancelled: bool,
+}
+
+/// Where a chat message is sent
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChatTarget {
+    /// Everyone that can hear the channel, by name
+    Channel(String),
+    /// A single player, sent with "/msg <player> <text>"
+    Whisper(Entity),
+}
+
+/// Who hears the messages sent to a channel
+pub enum ChannelKind {
+    /// All players
+    Global,
+    /// The players within this many chunks of the player that sent the message
+    Local { chunk_distance: u32 },
+    /// Only the players that have joined the channel
+    Members(HashSet<Entity>),
+}
+
+pub struct ChatChannel {
+    pub kind: ChannelKind,
+    /// If players can join and leave the channel with /join and /leave. Channels the game manages
+    /// itself, like team chats, should be closed so players can't let themselves in.
+    pub open: bool,
+}
+
+impl ChatChannel {
+    /// A channel only its members can talk in and hear
+    pub fn members(open: bool) -> Self {
+        return Self {
+            kind: ChannelKind::Members(HashSet::new()),
+            open,
+        };
+    }
+
+    /// If the player can send messages to the channel
+    pub fn can_talk(&self, player_entity: Entity) -> bool {
+        return match &self.kind {
+            ChannelKind::Members(members) => members.contains(&player_entity),
+            _ => true,
+        };
+    }
+}
+
+/// The channels players can talk in, by name. There is a global and a local channel by default,
+/// games can add their own.
+///
+/// ```ignore
+/// fn create_team_channels(mut channels: ResMut<ChatChannels>) {
+///     channels.create("red", ChatChannel::members(false));
+///     channels.create("blue", ChatChannel::members(false));
+/// }
+/// ```
+///
+/// Players are then added to them with `ChatChannels::join`. The list of channels a player can
+/// talk in is sent to their client whenever it changes, so it can switch between them.
+#[derive(Resource)]
+pub struct ChatChannels(HashMap<String, ChatChannel>);
+
+impl Default for ChatChannels {
+    fn default() -> Self {
+        let mut channels = HashMap::new();
+        channels.insert(
+            GLOBAL_CHANNEL.to_owned(),
+            ChatChannel {
+                kind: ChannelKind::Global,
+                open: false,
+            },
+        );
+        channels.insert(
+            LOCAL_CHANNEL.to_owned(),
+            ChatChannel {
+                kind: ChannelKind::Local { chunk_distance: 2 },
+                open: false,
+            },
+        );
+        return Self(channels);
+    }
+}
+
+impl ChatChannels {
+    /// Add a channel, replacing any channel with the same name
+    pub fn create(&mut self, name: impl Into<String>, channel: ChatChannel) {
+        self.0.insert(name.into(), channel);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<ChatChannel> {
+        return self.0.remove(name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ChatChannel> {
+        return self.0.get(name);
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut ChatChannel> {
+        return self.0.get_mut(name);
+    }
+
+    /// Add the player to the members of a channel. Returns false if there is no channel by that
+    /// name, or if it doesn't have members.
+    pub fn join(&mut self, name: &str, player_entity: Entity) -> bool {
+        let Some(ChatChannel {
+            kind: ChannelKind::Members(members),
+            ..
+        }) = self.0.get_mut(name)
+        else {
+            return false;
+        };
+        members.insert(player_entity);
+        return true;
+    }
+
+    /// Remove the player from the members of a channel. Returns false if they were not a member.
+    pub fn leave(&mut self, name: &str, player_entity: Entity) -> bool {
+        let Some(ChatChannel {
+            kind: ChannelKind::Members(members),
+            ..
+        }) = self.0.get_mut(name)
+        else {
+            return false;
+        };
+        return members.remove(&player_entity);
+    }
+
+    /// Names of the channels the player can talk in, sorted by name
+    pub fn channels_of(&self, player_entity: Entity) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .0
+            .iter()
+            .filter(|(_, channel)| channel.can_talk(player_entity))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        return names;
+    }
+
+    // Open channels are created by the players, they are removed when nobody is left in them.
+    fn remove_if_empty(&mut self, name: &str) {
+        let is_empty = self.0.get(name).is_some_and(|channel| {
+            channel.open
+                && matches!(&channel.kind, ChannelKind::Members(members) if members.is_empty())
+        });
+        if is_empty {
+            self.0.remove(name);
+        }
+    }
+}
+
+/// The channel the player's messages are sent to
+#[derive(Component)]
+pub struct ActiveChatChannel(pub String);
+
+impl Default for ActiveChatChannel {
+    fn default() -> Self {
+        Self(GLOBAL_CHANNEL.to_owned())
+    }
+}
+
+impl ChatMessage {
+    /// Stop the message from being sent
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled;
+    }
+}
+
+/// The chat messages that will be sent this tick. Filters read and change them in
+/// `ChatSet::Filter`.
+///
+/// ```ignore
+/// fn no_shouting(mut messages: ResMut<PendingChatMessages>) {
+///     for message in messages.iter_mut() {
+///         message.text = message.text.to_lowercase();
+///     }
+/// }
+/// app.add_systems(Update, no_shouting.in_set(ChatSet::Filter));
+/// ```
+#[derive(Resource, Default)]
+pub struct PendingChatMessages(Vec<ChatMessage>);
+
+impl PendingChatMessages {
+    /// The messages that haven't been cancelled
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut ChatMessage> {
+        return self.0.iter_mut().filter(|message| !message.cancelled);
+    }
+}
+
+/// Sent when a filter changes or cancels a message. For moderation tools that want to log what
+/// players write.
+#[derive(Event)]
+pub struct ChatViolation {
+    pub player_entity: Entity,
+    /// Name of the filter, e.g. "rate_limit", "links" or "blocked_words"
+    pub filter: String,
+    /// The text before the filter changed it
+    pub text: String,
+    /// If the message was cancelled instead of changed
+    pub cancelled: bool,
+}
+
+/// Sent when a player writes a chat message that starts with '/'. It is not shown to the other
+/// players.
+#[derive(Event)]
+pub struct ChatCommand {
+    pub player_entity: Entity,
+    /// The first word of the message, without the '/'
+    pub name: String,
+    /// The rest of the words in the message
+    pub args: Vec<String>,
+}
+
+impl ChatCommand {
+    /// Send a chat message only visible to the player that issued the command.
+    pub fn reply(&self, net: &Server, text: impl Into<String>) {
+        send_private_message(net, self.player_entity, text);
+    }
+}
+
+/// What the player has chosen to see in the chat, set by the client through the
+/// "chat_hide_connections" and "chat_filter" properties.
+#[derive(Component, Default)]
+pub struct ChatPreferences {
+    /// Don't show when other players join or leave
+    pub hide_connection_messages: bool,
+    /// Pass the messages of other players through the `ChatFilter`
+    pub filter: bool,
+}
+
+/// A function the messages of players are passed through before they are sent to the players
+/// that have turned the chat filter on, e.g. to mask profanity. There is no filter by default,
+/// the messages are sent as they are.
+#[derive(Resource, Default)]
+pub struct ChatFilter {
+    filter: Option<fn(&str) -> String>,
+}
+
+impl ChatFilter {
+    pub fn set(&mut self, filter: fn(&str) -> String) {
+        self.filter = Some(filter);
+    }
+
+    pub fn clear(&mut self) {
+        self.filter = None;
+    }
+
+    /// The text as it should be shown to a player with the given preferences
+    pub fn apply(&self, text: &str, preferences: &ChatPreferences) -> String {
+        return match self.filter {
+            Some(filter) if preferences.filter => filter(text),
+            _ => text.to_owned(),
+        };
+    }
+}
+
+/// Send a chat message to a single player.
+pub fn send_private_message(net: &Server, player_entity: Entity, text: impl Into<String>) {
+    net.send_one(
+        player_entity,
+        messages::InterfaceTextUpdate {
+            interface_path: "chat/history".to_owned(),
+            index: i32::MAX,
+            text: text.into(),
+            font_size: CHAT_FONT_SIZE,
+            color: CHAT_TEXT_COLOR.to_owned(),
+        },
+    );
+}
+
+fn insert_chat_preferences(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
+    for player_entity in player_query.iter() {
+        commands.entity(player_entity).insert((
+            ChatPreferences::default(),
+            ActiveChatChannel::default(),
+            RecentMessages::default(),
+        ));
+    }
+}
+
+fn update_chat_preferences(
+    mut preferences_query: Query<&mut ChatPreferences>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+) {
+    for property in property_events.read() {
+        let Ok(mut preferences) = preferences_query.get_mut(property.player_entity) else {
+            continue;
+        };
+
+        match property.name.as_str() {
+            properties::CHAT_HIDE_CONNECTIONS => {
+                preferences.hide_connection_messages = property.parse().unwrap_or_default();
+            }
+            properties::CHAT_FILTER => preferences.filter = property.parse().unwrap_or_default(),
+            _ => (),
+        }
     }
 }
 
 fn handle_chat_messages(
     net: Res<Server>,
-    player_query: Query<&Player>,
+    time: Res<Time>,
+    settings: Res<ChatSettings>,
+    channels: Res<ChatChannels>,
+    mut player_query: Query<(&mut ActiveChatChannel, &mut RecentMessages), With<Player>>,
+    mut pending_messages: ResMut<PendingChatMessages>,
     mut chat_message_query: EventReader<NetworkMessage<messages::InterfaceTextInput>>,
+    mut command_events: EventWriter<ChatCommand>,
+    mut violation_events: EventWriter<ChatViolation>,
 ) {
     for chat_message in chat_message_query.read() {
         if &chat_message.interface_path != "chat/input" {
             continue;
         }
-        let Ok(player) = player_query.get(chat_message.player_entity) else {
+        let Ok((mut active_channel, mut recent)) = player_query.get_mut(chat_message.player_entity)
+        else {
             // TODO: Should probably disconnect
             continue;
         };
 
-        net.broadcast(messages::InterfaceTextUpdate {
-            interface_path: "chat/history".to_owned(),
-            index: i32::MAX,
-            text: format!("[{}] {}", &player.username, &chat_message.text),
-            font_size: CHAT_FONT_SIZE,
-            color: CHAT_TEXT_COLOR.to_owned(),
+        // Commands are limited too, they can be just as spammy, e.g. "/msg".
+        if !recent.record(&settings, time.elapsed_secs()) {
+            send_private_message(
+                &net,
+                chat_message.player_entity,
+                "You are sending messages too quickly",
+            );
+            violation_events.send(ChatViolation {
+                player_entity: chat_message.player_entity,
+                filter: "rate_limit".to_owned(),
+                text: chat_message.text.clone(),
+                cancelled: true,
+            });
+            continue;
+        }
+
+        if let Some(command) = chat_message.text.strip_prefix('/') {
+            let mut words = command.split_whitespace().map(str::to_owned);
+            if let Some(name) = words.next() {
+                command_events.send(ChatCommand {
+                    player_entity: chat_message.player_entity,
+                    name,
+                    args: words.collect(),
+                });
+            }
+            continue;
+        }
+
+        // The channel may have been removed, or the player removed from it, since they switched to
+        // it. The message is not sent anywhere else since it might not be meant for everyone.
+        if !channels
+            .get(&active_channel.0)
+            .is_some_and(|channel| channel.can_talk(chat_message.player_entity))
+        {
+            send_private_message(
+                &net,
+                chat_message.player_entity,
+                format!(
+                    "You are no longer in the channel '{}', switched to '{}'.",
+                    active_channel.0, GLOBAL_CHANNEL
+                ),
+            );
+            *active_channel = ActiveChatChannel::default();
+            continue;
+        }
+
+        pending_messages.0.push(ChatMessage {
+            player_entity: chat_message.player_entity,
+            text: chat_message.text.clone(),
+            target: ChatTarget::Channel(active_channel.0.clone()),
+            cancelled: false,
+        });
+    }
+}
+
+// "/msg <player> <text>", "/join <channel>", "/leave <channel>" and "/channel <channel>"
+fn handle_channel_commands(
+    net: Res<Server>,
+    mut channels: ResMut<ChatChannels>,
+    mut player_query: Query<(Entity, &Player, &mut ActiveChatChannel)>,
+    mut pending_messages: ResMut<PendingChatMessages>,
+    mut command_events: EventReader<ChatCommand>,
+) {
+    for command in command_events.read() {
+        match command.name.as_str() {
+            "msg" => {
+                if command.args.len() < 2 {
+                    command.reply(&net, "Usage: /msg <player> <text>");
+                    continue;
+                }
+                let username = &command.args[0];
+
+                let Some((receiver, _, _)) = player_query
+                    .iter()
+                    .find(|(_, player, _)| &player.username == username)
+                else {
+                    command.reply(&net, "No player by that name.");
+                    continue;
+                };
+
+                pending_messages.0.push(ChatMessage {
+                    player_entity: command.player_entity,
+                    text: command.args[1..].join(" "),
+                    target: ChatTarget::Whisper(receiver),
+                    cancelled: false,
+                });
+            }
+            "join" => {
+                let Some(name) = command.args.first().map(|name| name.to_lowercase()) else {
+                    command.reply(&net, "Usage: /join <channel>");
+                    continue;
+                };
+
+                match channels.get(&name) {
+                    Some(channel) if !channel.open => {
+                        command.reply(&net, "That channel can't be joined.");
+                        continue;
+                    }
+                    Some(_) => (),
+                    None => {
+                        if name.len() > MAX_CHANNEL_NAME_LENGTH
+                            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                        {
+                            command.reply(
+                                &net,
+                                format!(
+                                    "Channel names can only have letters, numbers and '_', and \
+                                    be at most {} long.",
+                                    MAX_CHANNEL_NAME_LENGTH
+                                ),
+                            );
+                            continue;
+                        }
+                        channels.create(name.clone(), ChatChannel::members(true));
+                    }
+                }
+
+                channels.join(&name, command.player_entity);
+                if let Ok((_, _, mut active_channel)) = player_query.get_mut(command.player_entity)
+                {
+                    active_channel.0 = name.clone();
+                }
+                command.reply(&net, format!("Joined the channel '{}'.", name));
+            }
+            "leave" => {
+                let Some(name) = command.args.first().map(|name| name.to_lowercase()) else {
+                    command.reply(&net, "Usage: /leave <channel>");
+                    continue;
+                };
+
+                if !channels.get(&name).is_some_and(|channel| channel.open)
+                    || !channels.leave(&name, command.player_entity)
+                {
+                    command.reply(&net, "You are not in a channel you can leave by that name.");
+                    continue;
+                }
+                channels.remove_if_empty(&name);
+
+                if let Ok((_, _, mut active_channel)) = player_query.get_mut(command.player_entity)
+                {
+                    if active_channel.0 == name {
+                        *active_channel = ActiveChatChannel::default();
+                    }
+                }
+                command.reply(&net, format!("Left the channel '{}'.", name));
+            }
+            "channel" => {
+                let Some(name) = command.args.first() else {
+                    command.reply(&net, "Usage: /channel <channel>");
+                    continue;
+                };
+                if let Ok((player_entity, _, mut active_channel)) =
+                    player_query.get_mut(command.player_entity)
+                {
+                    switch_channel(&net, &channels, player_entity, &mut active_channel, name);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// TODO: There is no message for switching channel, the client sends the "chat_channel" property.
+fn handle_channel_properties(
+    net: Res<Server>,
+    channels: Res<ChatChannels>,
+    mut player_query: Query<&mut ActiveChatChannel>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::CHAT_CHANNEL {
+            continue;
+        }
+
+        let Some(name) = property.parse::<String>() else {
+            continue;
+        };
+
+        let Ok(mut active_channel) = player_query.get_mut(property.player_entity) else {
+            continue;
+        };
+
+        switch_channel(
+            &net,
+            &channels,
+            property.player_entity,
+            &mut active_channel,
+            &name,
+        );
+    }
+}
+
+fn switch_channel(
+    net: &Server,
+    channels: &ChatChannels,
+    player_entity: Entity,
+    active_channel: &mut ActiveChatChannel,
+    name: &str,
+) {
+    let name = name.to_lowercase();
+    if !channels
+        .get(&name)
+        .is_some_and(|channel| channel.can_talk(player_entity))
+    {
+        send_private_message(net, player_entity, "You are not in a channel by that name.");
+        return;
+    }
+
+    if active_channel.0 != name {
+        send_private_message(net, player_entity, format!("Now talking in '{}'.", name));
+        active_channel.0 = name;
+    }
+}
+
+fn remove_channel_members(
+    mut channels: ResMut<ChatChannels>,
+    mut removed_players: RemovedComponents<Player>,
+) {
+    for player_entity in removed_players.read() {
+        let mut left = Vec::new();
+        for (name, channel) in channels.0.iter_mut() {
+            if let ChannelKind::Members(members) = &mut channel.kind {
+                if members.remove(&player_entity) {
+                    left.push(name.clone());
+                }
+            }
+        }
+        for name in left {
+            channels.remove_if_empty(&name);
+        }
+    }
+}
+
+// TODO: There is no message for chat channels, they are sent as the "chat_channels" property.
+fn send_channel_lists(
+    net: Res<Server>,
+    channels: Res<ChatChannels>,
+    player_query: Query<(Entity, Ref<ActiveChatChannel>)>,
+    resumed_query: Query<(), Added<ResumedSession>>,
+) {
+    for (player_entity, active_channel) in player_query.iter() {
+        if !channels.is_changed()
+            && !active_channel.is_changed()
+            && !resumed_query.contains(player_entity)
+        {
+            continue;
+        }
+
+        let value = properties::ChatChannels {
+            active: active_channel.0.clone(),
+            channels: channels
+                .channels_of(player_entity)
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+        };
+        net.send_property(player_entity, properties::CHAT_CHANNELS, &value);
+    }
+}
+
+// When the player's recent messages were sent, in seconds since startup
+#[derive(Component, Default)]
+struct RecentMessages(VecDeque<f32>);
+
+impl RecentMessages {
+    // Remembers the message, false if the player has sent too many and it should be dropped.
+    fn record(&mut self, settings: &ChatSettings, now: f32) -> bool {
+        let Some(rate_limit) = settings.rate_limit else {
+            return true;
+        };
+
+        while self
+            .0
+            .front()
+            .is_some_and(|sent| now - sent > settings.rate_interval)
+        {
+            self.0.pop_front();
+        }
+
+        if self.0.len() >= rate_limit as usize {
+            return false;
+        }
+
+        self.0.push_back(now);
+        return true;
+    }
+}
+
+fn strip_links(
+    settings: Res<ChatSettings>,
+    mut pending_messages: ResMut<PendingChatMessages>,
+    mut violation_events: EventWriter<ChatViolation>,
+) {
+    if !settings.strip_links {
+        return;
+    }
+
+    fn is_link(word: &str) -> bool {
+        let word = word.to_lowercase();
+        return word.contains("://") || word.starts_with("www.");
+    }
+
+    for message in pending_messages.iter_mut() {
+        if !message.text.split_whitespace().any(is_link) {
+            continue;
+        }
+
+        let stripped = message
+            .text
+            .split_whitespace()
+            .map(|word| if is_link(word) { "[link]" } else { word })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        violation_events.send(ChatViolation {
+            player_entity: message.player_entity,
+            filter: "links".to_owned(),
+            text: std::mem::replace(&mut message.text, stripped),
+            cancelled: false,
         });
     }
 }
 
+fn mask_blocked_words(
+    settings: Res<ChatSettings>,
+    mut pending_messages: ResMut<PendingChatMessages>,
+    mut violation_events: EventWriter<ChatViolation>,
+) {
+    if settings.blocked_words.is_empty() {
+        return;
+    }
+
+    for message in pending_messages.iter_mut() {
+        let mut masked = String::with_capacity(message.text.len());
+        let mut word = String::new();
+        let mut changed = false;
+
+        // Words are split on anything that isn't a letter or a number, so punctuation next to a
+        // word doesn't hide it.
+        let mut push_word = |word: &mut String, masked: &mut String| {
+            if settings.blocked_words.contains(&word.to_lowercase()) {
+                masked.extend(std::iter::repeat('*').take(word.chars().count()));
+                changed = true;
+            } else {
+                masked.push_str(word);
+            }
+            word.clear();
+        };
+
+        for character in message.text.chars() {
+            if character.is_alphanumeric() {
+                word.push(character);
+            } else {
+                push_word(&mut word, &mut masked);
+                masked.push(character);
+            }
+        }
+        push_word(&mut word, &mut masked);
+
+        if changed {
+            violation_events.send(ChatViolation {
+                player_entity: message.player_entity,
+                filter: "blocked_words".to_owned(),
+                text: std::mem::replace(&mut message.text, masked),
+                cancelled: false,
+            });
+        }
+    }
+}
+
+fn send_chat_messages(
+    net: Res<Server>,
+    chat_filter: Res<ChatFilter>,
+    channels: Res<ChatChannels>,
+    player_query: Query<&Player>,
+    receiver_query: Query<(Entity, &ChatPreferences, &Transform)>,
+    mut pending_messages: ResMut<PendingChatMessages>,
+) {
+    for message in pending_messages.0.drain(..) {
+        if message.cancelled {
+            continue;
+        }
+
+        let Ok(player) = player_query.get(message.player_entity) else {
+            continue;
+        };
+
+        let channel_name = match &message.target {
+            ChatTarget::Whisper(receiver) => {
+                let (Ok(receiver_player), Ok((_, preferences, _))) =
+                    (player_query.get(*receiver), receiver_query.get(*receiver))
+                else {
+                    continue;
+                };
+                send_private_message(
+                    &net,
+                    message.player_entity,
+                    format!("[to {}] {}", &receiver_player.username, &message.text),
+                );
+                send_private_message(
+                    &net,
+                    *receiver,
+                    format!(
+                        "[from {}] {}",
+                        &player.username,
+                        chat_filter.apply(&message.text, preferences)
+                    ),
+                );
+                continue;
+            }
+            ChatTarget::Channel(name) => name,
+        };
+
+        let Some(channel) = channels.get(channel_name) else {
+            continue;
+        };
+
+        let prefix = if channel_name == GLOBAL_CHANNEL {
+            format!("[{}]", &player.username)
+        } else {
+            format!("[{}] [{}]", channel_name, &player.username)
+        };
+
+        let sender_chunk = receiver_query
+            .get(message.player_entity)
+            .map(|(_, _, transform)| {
+                utils::world_position_to_chunk_position(transform.translation.floor().as_ivec3())
+            })
+            .unwrap_or_default();
+
+        for (receiver, preferences, transform) in receiver_query.iter() {
+            let can_hear = match &channel.kind {
+                ChannelKind::Global => true,
+                ChannelKind::Local { chunk_distance } => {
+                    let chunk = utils::world_position_to_chunk_position(
+                        transform.translation.floor().as_ivec3(),
+                    );
+                    let distance = (chunk - sender_chunk).abs().max_element() / Chunk::SIZE as i32;
+                    distance as u32 <= *chunk_distance
+                }
+                ChannelKind::Members(members) => members.contains(&receiver),
+            };
+            if !can_hear {
+                continue;
+            }
+
+            let text = if receiver == message.player_entity {
+                message.text.clone()
+            } else {
+                chat_filter.apply(&message.text, preferences)
+            };
+            send_private_message(&net, receiver, format!("{} {}", prefix, text));
+        }
+    }
+}
+
 // TODO: Maybe players should be passed the chat history too.
 // TODO: The "joined game" message sometimes shows for the player that joined. Intermitent problem,
 // the message should arrive before the client finishes setup. In which case it should be
 // discarded after two event buffer switches.
+//
+// Players that reconnect after losing their connection never left as far as the others can tell.
 fn send_connection_messages(
     net: Res<Server>,
-    player_query: Query<&Player>,
+    player_query: Query<(&Player, Has<ResumedSession>)>,
+    receiver_query: Query<(Entity, &ChatPreferences)>,
     mut network_events: EventReader<NetworkEvent>,
 ) {
     for event in network_events.read() {
         match event {
             NetworkEvent::Connected { entity } => {
-                let player = &player_query.get(*entity).unwrap();
-                net.broadcast(messages::InterfaceTextUpdate {
-                    interface_path: "chat/history".to_owned(),
-                    index: i32::MAX,
-                    text: format!("{} joined the game", player.username),
-                    font_size: CHAT_FONT_SIZE,
-                    color: CHAT_TEXT_COLOR.to_owned(),
-                });
+                let (player, resumed) = player_query.get(*entity).unwrap();
+                if resumed {
+                    continue;
+                }
+                send_connection_message(
+                    &net,
+                    &receiver_query,
+                    format!("{} joined the game", player.username),
+                );
             }
             NetworkEvent::Disconnected { entity } => {
-                let player = player_query.get(*entity).unwrap();
-                net.broadcast(messages::InterfaceTextUpdate {
-                    interface_path: "chat/history".to_owned(),
-                    index: i32::MAX,
-                    text: format!("{} left the game", player.username),
-                    font_size: CHAT_FONT_SIZE,
-                    color: CHAT_TEXT_COLOR.to_owned(),
-                });
+                let (player, _) = player_query.get(*entity).unwrap();
+                send_connection_message(
+                    &net,
+                    &receiver_query,
+                    format!("{} left the game", player.username),
+                );
             }
         }
     }
 }
+
+fn send_connection_message(
+    net: &Server,
+    receiver_query: &Query<(Entity, &ChatPreferences)>,
+    text: String,
+) {
+    for (receiver, preferences) in receiver_query.iter() {
+        if !preferences.hide_connection_messages {
+            send_private_message(net, receiver, text.clone());
+        }
+    }
+}
@@ -1,34 +1,59 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use bevy::prelude::*;
 use indexmap::IndexSet;
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    advancements::AdvancementProgress,
     blocks::{BlockData, BlockId, BlockState},
     items::ItemId,
-    world::chunk::Chunk,
+    players::PlayerSave,
+    world::{chunk::Chunk, MapTile},
 };
 
+/// Sets up the world database. It is a sqlite file by default, use `in_memory` for servers that
+/// shouldn't save anything, or `new` to keep the sqlite database somewhere else.
 pub struct DatabasePlugin {
-    path: String,
+    backend: Arc<dyn DatabaseBackend>,
 }
 
 impl Default for DatabasePlugin {
     fn default() -> Self {
+        Self::new(SqliteFile::new("./world.sqlite"))
+    }
+}
+
+impl DatabasePlugin {
+    pub fn new(backend: impl DatabaseBackend + 'static) -> Self {
         Self {
-            path: "./world.sqlite".to_owned(),
+            backend: Arc::new(backend),
         }
     }
+
+    /// Keep the world in memory, it is gone when the server stops.
+    pub fn in_memory() -> Self {
+        Self::new(InMemory::new())
+    }
 }
 
 impl Plugin for DatabasePlugin {
     fn build(&self, app: &mut App) {
-        let database = Database::new(self.path.clone());
+        let database = Database(Arc::new(DatabaseInner {
+            backend: self.backend.clone(),
+        }));
 
         database.build();
-        database.save_block_ids();
-        database.save_items();
-        database.save_models();
+        // Block, item and model ids are saved after the registration schedule, so that blocks and
+        // items registered from code get ids too, and the models generated for them. See
+        // registration::RegistrationPlugin
         //    setup_new_world_database(&settings.world_database_path);
         //} else if rusqlite::Connection::open(&settings.world_database_path).is_err() {
         //    panic!("Could not open the world file at '{}', make sure it is the correct file, else it might be corrupt", settings.world_database_path);
@@ -38,6 +63,81 @@ impl Plugin for DatabasePlugin {
     }
 }
 
+/// Makes the sqlite connections the database uses. This is not a storage abstraction, the world
+/// is always stored in a sqlite database with the same tables, the backend only decides where it
+/// is kept and how connections to it are made.
+pub trait DatabaseBackend: Send + Sync {
+    fn connect(&self) -> rusqlite::Connection;
+
+    /// Run once when the server starts, before any tables are created.
+    fn initialize(&self, _connection: &rusqlite::Connection) {}
+}
+
+/// A sqlite file on disk, the default backend.
+pub struct SqliteFile {
+    path: String,
+}
+
+impl SqliteFile {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl DatabaseBackend for SqliteFile {
+    fn connect(&self) -> rusqlite::Connection {
+        return rusqlite::Connection::open(&self.path).unwrap();
+    }
+
+    fn initialize(&self, connection: &rusqlite::Connection) {
+        connection
+            .pragma_update(None, "journal_mode", "wal")
+            .unwrap();
+    }
+}
+
+/// A database that is only kept in memory, nothing is saved when the server stops. For tests and
+/// servers that start from a fresh world every time, like minigames.
+///
+/// Its connections share the database through sqlite's shared cache. It locks tables instead of
+/// the whole database, a connection that needs a table another is writing to waits for the other's
+/// transaction to finish.
+pub struct InMemory {
+    uri: String,
+    // The database is deleted when its last connection is closed, this one keeps it alive.
+    _connection: Mutex<rusqlite::Connection>,
+}
+
+impl InMemory {
+    pub fn new() -> Self {
+        // Each gets its own name, so that they don't share tables when there are several in the
+        // same process, e.g. in tests.
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let uri = format!(
+            "file:fmc-in-memory-{}?mode=memory&cache=shared",
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+
+        Self {
+            _connection: Mutex::new(rusqlite::Connection::open(&uri).unwrap()),
+            uri,
+        }
+    }
+}
+
+impl Default for InMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DatabaseBackend for InMemory {
+    fn connect(&self) -> rusqlite::Connection {
+        // Uris are understood by default
+        return rusqlite::Connection::open(&self.uri).unwrap();
+    }
+}
+
 #[derive(Resource, Deref, Clone)]
 pub struct Database(Arc<DatabaseInner>);
 
@@ -46,43 +146,86 @@ pub struct Database(Arc<DatabaseInner>);
 //       chunks. For large servers it is preferable to save cpu at the cost of storage.
 // TODO: Implement connection pool
 pub struct DatabaseInner {
-    path: String,
-    //pub pool: Mutex<Vec<rusqlite::Connection>>
+    backend: Arc<dyn DatabaseBackend>,
 }
 
-//pub struct Connection {
-//    pool: Arc<Database>,
-//    conn: rusqlite::Connection
-//}
-//
-//impl Drop for Connection {
-//    fn drop(&mut self) {
-//        self.pool.put_back(self.conn);
-//    }
-//}
-
 // TODO: Extract functions and have them take a connection instead?
 impl Database {
+    /// A database stored in a sqlite file at the path
     pub fn new(path: String) -> Self {
-        return Self(Arc::new(DatabaseInner { path }));
+        return Self::with_backend(SqliteFile::new(path));
+    }
+
+    pub fn with_backend(backend: impl DatabaseBackend + 'static) -> Self {
+        return Self(Arc::new(DatabaseInner {
+            backend: Arc::new(backend),
+        }));
     }
 
     pub fn get_connection(&self) -> rusqlite::Connection {
-        return rusqlite::Connection::open(&self.path).unwrap();
-        //return rusqlite::Connection::open_with_flags(
-        //    MEMORY_DATABASE_PATH,
-        //    rusqlite::OpenFlags::default() | rusqlite::OpenFlags::SQLITE_OPEN_SHARED_CACHE,
-        //)
-        //.unwrap();
+        return self.backend.connect();
+    }
+
+    /// Create or update the tables of a game or plugin. The migrations are sql statements that are
+    /// run in order, each only once for the lifetime of the database. When the tables need to
+    /// change, add a migration at the end, the ones that have been released should not be
+    /// changed.
+    ///
+    /// ```ignore
+    /// database.register_schema("shops", &[
+    ///     "create table shops (name TEXT PRIMARY KEY, owner TEXT NOT NULL)",
+    ///     "alter table shops add column open INTEGER NOT NULL DEFAULT 1",
+    /// ]);
+    /// ```
+    pub fn register_schema(&self, name: &str, migrations: &[&str]) {
+        let mut conn = self.get_connection();
+        let tx = conn.transaction().unwrap();
+
+        let version = tx
+            .query_row(
+                "SELECT version FROM schema_versions WHERE name = ?",
+                [name],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .unwrap()
+            .unwrap_or(0) as usize;
+
+        if version > migrations.len() {
+            panic!(
+                "The '{}' tables of the database are at version {}, but only {} migrations are \
+                known. It was made by a newer version of the server.",
+                name,
+                version,
+                migrations.len()
+            );
+        }
+
+        for (index, migration) in migrations.iter().enumerate().skip(version) {
+            if let Err(e) = tx.execute_batch(migration) {
+                panic!(
+                    "Could not apply migration {} of the '{}' tables: {}",
+                    index + 1,
+                    name,
+                    e
+                );
+            }
+        }
+
+        tx.execute(
+            "INSERT OR REPLACE INTO schema_versions VALUES (?,?)",
+            rusqlite::params![name, migrations.len() as i64],
+        )
+        .unwrap();
+        tx.commit()
+            .expect("Failed to save the schema version to the database");
     }
 
     pub fn build(&self) {
         let conn = self.get_connection();
-        conn.pragma_update(None, "journal_mode", "wal").unwrap();
+        self.backend.initialize(&conn);
 
         //conn.execute("drop table if exists blocks", []).unwrap();
-        conn.execute("drop table if exists block_ids", []).unwrap();
-        conn.execute("drop table if exists item_ids", []).unwrap();
         conn.execute("drop table if exists model_ids", []).unwrap();
         //conn.execute("drop table if exists players", []).unwrap();
         //conn.execute("drop table if exists storage", []).unwrap();
@@ -140,6 +283,42 @@ impl Database {
         )
         .expect("Could not create players table");
 
+        // Top-down color summaries of chunk columns, see world::map_tiles
+        conn.execute(
+            "create table if not exists map_tiles (
+                x INTEGER,
+                z INTEGER,
+                tile BLOB NOT NULL,
+                PRIMARY KEY (x,z)
+                )",
+            [],
+        )
+        .expect("Could not create map_tiles table");
+
+        // Which advancements each player has completed, by account id, see
+        // advancements::AdvancementProgress
+        conn.execute(
+            "create table if not exists advancements (
+                account_id TEXT PRIMARY KEY,
+                progress BLOB NOT NULL
+                )",
+            [],
+        )
+        .expect("Could not create advancements table");
+
+        // Data the game keeps about each player between sessions, by account id. The username is
+        // the one they last played with, so players can be looked up by name while offline. See
+        // players::PlayerSave
+        conn.execute(
+            "create table if not exists player_saves (
+                account_id TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                save TEXT NOT NULL
+                )",
+            [],
+        )
+        .expect("Could not create player_saves table");
+
         // General persistent storage
         conn.execute(
             "create table if not exists storage (
@@ -149,6 +328,89 @@ impl Database {
             [],
         )
         .expect("Could not create struct storage table");
+
+        // Block changes made by players, see world::BlockAudit
+        conn.execute(
+            "create table if not exists block_audit (
+                x INTEGER NOT NULL,
+                y INTEGER NOT NULL,
+                z INTEGER NOT NULL,
+                account_id TEXT NOT NULL,
+                username TEXT NOT NULL,
+                time INTEGER NOT NULL,
+                from_id INTEGER NOT NULL,
+                from_state INTEGER,
+                to_id INTEGER NOT NULL,
+                to_state INTEGER
+                )",
+            [],
+        )
+        .expect("Could not create block_audit table");
+        conn.execute(
+            "create index if not exists block_audit_position on block_audit (x,y,z)",
+            [],
+        )
+        .expect("Could not create block_audit position index");
+        conn.execute(
+            "create index if not exists block_audit_time on block_audit (time)",
+            [],
+        )
+        .expect("Could not create block_audit time index");
+
+        // Chunks that have been changed, see ChunkRecord
+        conn.execute(
+            "create table if not exists chunks (
+                x INTEGER NOT NULL,
+                y INTEGER NOT NULL,
+                z INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                PRIMARY KEY (x,y,z)
+                )",
+            [],
+        )
+        .expect("Could not create chunks table");
+
+        // Versions of the tables registered through Database::register_schema
+        conn.execute(
+            "create table if not exists schema_versions (
+                name TEXT PRIMARY KEY,
+                version INTEGER NOT NULL
+                )",
+            [],
+        )
+        .expect("Could not create schema_versions table");
+
+        drop(conn);
+        self.register_schema(
+            "ids",
+            &[
+                // The block ids were saved again every time the server started, and were read as
+                // their order in the table from 0. They're now the id column.
+                "update block_ids set id = -id;
+                update block_ids set id = -id - 1;
+                alter table item_ids add column removed INTEGER NOT NULL DEFAULT 0;",
+            ],
+        );
+
+        // Chunks players have changed blocks in, see `is_changed_by_players`. Players' changes
+        // were not tracked before, all the chunks that were already saved count as changed.
+        let chunk_mask = !(Chunk::SIZE as i32 - 1);
+        let create_player_changed_chunks = format!(
+            "create table player_changed_chunks (
+                x INTEGER NOT NULL,
+                y INTEGER NOT NULL,
+                z INTEGER NOT NULL,
+                PRIMARY KEY (x,y,z)
+            );
+            insert or ignore into player_changed_chunks
+                select x, y, z from chunks
+                union select x & {0}, y & {0}, z & {0} from blocks;",
+            chunk_mask
+        );
+        self.register_schema(
+            "player_changed_chunks",
+            &[create_player_changed_chunks.as_str()],
+        );
     }
 
     // TODO: rusqlite doesn't drop stuff correctly so there's all kinds of errors when you don't
@@ -258,58 +520,191 @@ impl Database {
     //    }
     //}
 
+    /// The blocks of the chunk that have been changed from what was generated, by block index.
     pub fn load_chunk_blocks(
         &self,
         position: &IVec3,
     ) -> HashMap<usize, (BlockId, Option<BlockState>, Option<BlockData>)> {
+        let mut conn = self.get_connection();
+        // Compaction moves the block rows into the chunk record, if it happened between the two
+        // reads the rows would be missed. A transaction makes both read the same snapshot.
+        let tx = conn.transaction().unwrap();
+
+        let mut blocks = load_chunk_record(&tx, position);
+        blocks.extend(load_block_rows(&tx, position));
+
+        return blocks;
+    }
+
+    /// Merge the block updates saved for the chunk into its chunk record. Block updates are saved
+    /// one row per block, which is quick to write, but takes up a lot of space and is slow to load
+    /// when a chunk has been changed a lot.
+    pub fn compact_chunk(&self, position: &IVec3) {
+        self.merge_chunk_blocks(position, HashMap::new());
+    }
+
+    /// Save blocks of the chunk directly to its chunk record, overwriting any changes already
+    /// saved for the same blocks. For writing many blocks at once, e.g. when importing a world.
+    /// The chunk counts as changed by players, see `is_changed_by_players`.
+    pub fn save_chunk_blocks(
+        &self,
+        position: &IVec3,
+        blocks: HashMap<usize, (BlockId, Option<BlockState>, Option<BlockData>)>,
+    ) {
+        self.merge_chunk_blocks(position, blocks);
+
         let conn = self.get_connection();
+        conn.execute(
+            "INSERT OR IGNORE INTO player_changed_chunks VALUES (?,?,?)",
+            [position.x, position.y, position.z],
+        )
+        .expect("Failed to save the chunk to the database");
+    }
 
-        let mut block_stmt = conn
-            .prepare(
-                r#"
-            select
-                x, z, y, block_id, block_state, block_data
-            from
-                blocks
-            where
-                (x between ? and ?)
-            and
-                (y between ? and ?)
-            and
-                (z between ? and ?)"#,
+    /// If a player has changed a block in the chunk. Chunks that were saved before this was
+    /// tracked count as changed.
+    pub fn is_changed_by_players(&self, position: &IVec3) -> bool {
+        let conn = self.get_connection();
+        return conn
+            .query_row(
+                "SELECT 1 FROM player_changed_chunks WHERE x = ? AND y = ? AND z = ?",
+                [position.x, position.y, position.z],
+                |_| Ok(()),
             )
+            .optional()
+            .unwrap()
+            .is_some();
+    }
+
+    fn merge_chunk_blocks(&self, position: &IVec3, new_blocks: ChangedBlocks) {
+        let mut conn = self.get_connection();
+        // Block updates that are saved while it is compacting would be deleted with the rest, it
+        // has to hold the write lock from the start.
+        let tx = conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
             .unwrap();
 
+        let rows = load_block_rows(&tx, position);
+        if rows.is_empty() && new_blocks.is_empty() {
+            return;
+        }
+
+        let mut blocks = load_chunk_record(&tx, position);
+        blocks.extend(rows);
+        blocks.extend(new_blocks);
+
+        tx.execute(
+            "INSERT OR REPLACE INTO chunks VALUES (?,?,?,?)",
+            rusqlite::params![
+                position.x,
+                position.y,
+                position.z,
+                ChunkRecord::new(blocks).encode()
+            ],
+        )
+        .unwrap();
+
         const OFFSET: i32 = Chunk::SIZE as i32 - 1;
-        let mut rows = block_stmt
-            .query([
-                &position.x,
-                &(position.x + OFFSET),
-                &position.y,
-                &(position.y + OFFSET),
-                &position.z,
-                &(position.z + OFFSET),
-            ])
-            .unwrap();
+        tx.execute(
+            "DELETE FROM blocks WHERE (x BETWEEN ? AND ?) AND (y BETWEEN ? AND ?) AND (z BETWEEN ? AND ?)",
+            [
+                position.x,
+                position.x + OFFSET,
+                position.y,
+                position.y + OFFSET,
+                position.z,
+                position.z + OFFSET,
+            ],
+        )
+        .unwrap();
 
-        let mut blocks = HashMap::new();
+        tx.commit()
+            .expect("Failed to save the chunk record to the database");
+    }
 
-        while let Some(row) = rows.next().unwrap() {
-            let index = (((row.get::<_, i32>(0).unwrap() & OFFSET) << 8)
-                | ((row.get::<_, i32>(1).unwrap() & OFFSET) << 4)
-                | (row.get::<_, i32>(2).unwrap() & OFFSET)) as usize;
-
-            blocks.insert(
-                index,
-                (
-                    row.get::<_, BlockId>(3).unwrap(),
-                    row.get::<_, u16>(4).ok().map(BlockState),
-                    row.get::<_, Vec<u8>>(5).ok().map(BlockData),
-                ),
-            );
+    /// Compact all chunks that have block updates, see `compact_chunk`.
+    pub fn compact_chunks(&self) {
+        let positions: Vec<IVec3> = {
+            let conn = self.get_connection();
+            let mut stmt = conn
+                .prepare("SELECT DISTINCT x & ?1, y & ?1, z & ?1 FROM blocks")
+                .unwrap();
+            let rows = stmt
+                .query_map([!(Chunk::SIZE as i32 - 1)], |row| {
+                    Ok(IVec3::new(row.get(0)?, row.get(1)?, row.get(2)?))
+                })
+                .unwrap();
+            rows.filter_map(Result::ok).collect()
+        };
+
+        for position in positions {
+            self.compact_chunk(&position);
         }
+    }
 
-        return blocks;
+    /// Positions of all the chunks that have saved blocks
+    pub fn saved_chunks(&self) -> Vec<IVec3> {
+        let conn = self.get_connection();
+        let mut stmt = conn
+            .prepare("SELECT x, y, z FROM chunks UNION SELECT x & ?1, y & ?1, z & ?1 FROM blocks")
+            .unwrap();
+        let rows = stmt
+            .query_map([!(Chunk::SIZE as i32 - 1)], |row| {
+                Ok(IVec3::new(row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        return rows.filter_map(Result::ok).collect();
+    }
+
+    /// Delete the saved blocks of the chunk and the block audit entries inside it. It is
+    /// generated again the next time it is loaded.
+    pub fn delete_chunk(&self, position: &IVec3) {
+        let mut conn = self.get_connection();
+        let tx = conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .unwrap();
+
+        tx.execute(
+            "DELETE FROM chunks WHERE x = ? AND y = ? AND z = ?",
+            [position.x, position.y, position.z],
+        )
+        .unwrap();
+        tx.execute(
+            "DELETE FROM player_changed_chunks WHERE x = ? AND y = ? AND z = ?",
+            [position.x, position.y, position.z],
+        )
+        .unwrap();
+
+        const OFFSET: i32 = Chunk::SIZE as i32 - 1;
+        let bounds = [
+            position.x,
+            position.x + OFFSET,
+            position.y,
+            position.y + OFFSET,
+            position.z,
+            position.z + OFFSET,
+        ];
+        tx.execute(
+            "DELETE FROM blocks WHERE (x BETWEEN ? AND ?) AND (y BETWEEN ? AND ?) AND (z BETWEEN ? AND ?)",
+            bounds,
+        )
+        .unwrap();
+        tx.execute(
+            "DELETE FROM block_audit WHERE (x BETWEEN ? AND ?) AND (y BETWEEN ? AND ?) AND (z BETWEEN ? AND ?)",
+            bounds,
+        )
+        .unwrap();
+
+        tx.commit()
+            .expect("Failed to delete the chunk from the database");
+    }
+
+    /// Rebuild the database file so that it doesn't keep the space of deleted data. It can take
+    /// a long time for large worlds, and blocks everything else that uses the database.
+    pub fn vacuum(&self) {
+        let conn = self.get_connection();
+        conn.execute("VACUUM", [])
+            .expect("Failed to vacuum the database");
     }
 
     //pub async fn save_chunk(&self, position: &IVec3, chunk: &Chunk) {
@@ -428,8 +823,247 @@ impl Database {
     //    .unwrap();
     //}
 
-    /// Add new block ids to the database. The ids will be constant and cannot change.
-    pub fn save_block_ids(&self) {
+    pub fn load_map_tile(&self, position: &IVec2) -> Option<MapTile> {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare("SELECT tile FROM map_tiles WHERE x = ? AND z = ?")
+            .unwrap();
+        let mut rows = stmt.query([position.x, position.y]).unwrap();
+
+        if let Some(row) = rows.next().unwrap() {
+            let bytes: Vec<u8> = row.get(0).unwrap();
+            return bincode::deserialize(&bytes).ok();
+        } else {
+            return None;
+        }
+    }
+
+    pub fn delete_map_tile(&self, position: &IVec2) {
+        let conn = self.get_connection();
+        conn.execute(
+            "DELETE FROM map_tiles WHERE x = ? AND z = ?",
+            [position.x, position.y],
+        )
+        .expect("Failed to delete the map tile from the database");
+    }
+
+    pub fn save_map_tiles(&self, tiles: Vec<(IVec2, MapTile)>) {
+        let mut conn = self.get_connection();
+        let tx = conn.transaction().unwrap();
+
+        let mut stmt = tx
+            .prepare("INSERT OR REPLACE INTO map_tiles (x, z, tile) VALUES (?,?,?)")
+            .unwrap();
+
+        for (position, tile) in tiles {
+            stmt.execute(rusqlite::params![
+                position.x,
+                position.y,
+                bincode::serialize(&tile).unwrap()
+            ])
+            .unwrap();
+        }
+
+        stmt.finalize().unwrap();
+        tx.commit()
+            .expect("Failed to save map tiles to the database");
+    }
+
+    pub fn load_advancement_progress(&self, account_id: &str) -> Option<AdvancementProgress> {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare("SELECT progress FROM advancements WHERE account_id = ?")
+            .unwrap();
+        let mut rows = stmt.query([account_id]).unwrap();
+
+        if let Some(row) = rows.next().unwrap() {
+            let bytes: Vec<u8> = row.get(0).unwrap();
+            return bincode::deserialize(&bytes).ok();
+        } else {
+            return None;
+        }
+    }
+
+    pub fn save_advancement_progress(&self, account_id: &str, progress: &AdvancementProgress) {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare("INSERT OR REPLACE INTO advancements VALUES (?,?)")
+            .unwrap();
+        stmt.execute(rusqlite::params![
+            account_id,
+            bincode::serialize(progress).unwrap()
+        ])
+        .expect("Failed to save advancement progress to the database");
+    }
+
+    /// The player's save and the username they last played with
+    pub fn load_player_save(&self, account_id: &str) -> Option<(String, PlayerSave)> {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare("SELECT username, save FROM player_saves WHERE account_id = ?")
+            .unwrap();
+        let mut rows = stmt.query([account_id]).unwrap();
+
+        if let Some(row) = rows.next().unwrap() {
+            let username: String = row.get(0).unwrap();
+            let json: String = row.get(1).unwrap();
+            return serde_json::from_str(&json)
+                .ok()
+                .map(|save| (username, save));
+        } else {
+            return None;
+        }
+    }
+
+    pub fn save_player_save(&self, account_id: &str, username: &str, save: &PlayerSave) {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare("INSERT OR REPLACE INTO player_saves VALUES (?,?,?)")
+            .unwrap();
+        stmt.execute(rusqlite::params![
+            account_id,
+            username,
+            serde_json::to_string(save).unwrap()
+        ])
+        .expect("Failed to save player to the database");
+    }
+
+    /// The account id of the player that last played with the username. Usernames are not unique
+    /// over time, if several accounts have used it the one that was saved last is returned.
+    pub fn find_player_account(&self, username: &str) -> Option<String> {
+        let conn = self.get_connection();
+
+        // Replacing a row gives it a new rowid, so the highest is the latest save.
+        return conn
+            .query_row(
+                "SELECT account_id FROM player_saves WHERE username = ? ORDER BY rowid DESC LIMIT 1",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap();
+    }
+
+    /// Load data saved with `save_storage`
+    pub fn load_storage(&self, name: &str) -> Option<String> {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare("SELECT data FROM storage WHERE name = ?")
+            .unwrap();
+        let mut rows = stmt.query([name]).unwrap();
+
+        if let Some(row) = rows.next().unwrap() {
+            return row.get(0).ok();
+        } else {
+            return None;
+        }
+    }
+
+    /// Save arbitrary data by name, it will overwrite any data that already uses the name.
+    pub fn save_storage(&self, name: &str, data: &str) {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare("INSERT OR REPLACE INTO storage VALUES (?,?)")
+            .unwrap();
+        stmt.execute(rusqlite::params![name, data])
+            .expect("Failed to save to the storage table");
+    }
+
+    pub fn save_block_audit(&self, entries: Vec<BlockAuditEntry>) {
+        let mut conn = self.get_connection();
+        let tx = conn.transaction().unwrap();
+
+        let mut stmt = tx
+            .prepare("INSERT INTO block_audit VALUES (?,?,?,?,?,?,?,?,?,?)")
+            .unwrap();
+
+        for entry in entries {
+            stmt.execute(rusqlite::params![
+                entry.position.x,
+                entry.position.y,
+                entry.position.z,
+                entry.account_id,
+                entry.username,
+                entry.time,
+                entry.from.0,
+                entry.from.1.map(|state| state.0),
+                entry.to.0,
+                entry.to.1.map(|state| state.0),
+            ])
+            .unwrap();
+        }
+
+        stmt.finalize().unwrap();
+        tx.commit()
+            .expect("Failed to save the block audit to the database");
+    }
+
+    /// The most recent changes to a block, newest first.
+    pub fn load_block_audit_at(&self, position: IVec3, limit: usize) -> Vec<BlockAuditEntry> {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT * FROM block_audit WHERE x = ? AND y = ? AND z = ?
+                ORDER BY time DESC, rowid DESC LIMIT ?",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map(
+                rusqlite::params![position.x, position.y, position.z, limit],
+                BlockAuditEntry::from_row,
+            )
+            .unwrap();
+
+        return rows.filter_map(Result::ok).collect();
+    }
+
+    /// The changes an account made inside an area since a point in time, oldest first.
+    pub fn load_block_audit_by(
+        &self,
+        account_id: &str,
+        since: i64,
+        min: IVec3,
+        max: IVec3,
+    ) -> Vec<BlockAuditEntry> {
+        let conn = self.get_connection();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT * FROM block_audit WHERE account_id = ? AND time >= ?
+                AND x BETWEEN ? AND ? AND y BETWEEN ? AND ? AND z BETWEEN ? AND ?
+                ORDER BY time ASC, rowid ASC",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map(
+                rusqlite::params![account_id, since, min.x, max.x, min.y, max.y, min.z, max.z],
+                BlockAuditEntry::from_row,
+            )
+            .unwrap();
+
+        return rows.filter_map(Result::ok).collect();
+    }
+
+    /// Remove block audit entries older than the time
+    pub fn prune_block_audit(&self, before: i64) {
+        let conn = self.get_connection();
+        conn.execute("DELETE FROM block_audit WHERE time < ?", [before])
+            .expect("Failed to prune the block audit");
+    }
+
+    /// Save the ids of the block configs. Blocks keep their ids between restarts, if blocks are
+    /// removed the ids of the blocks after them change and the saved chunks are updated to match.
+    /// Fails if blocks that were removed are still in the world, unless `remove_missing` is set,
+    /// then they are removed from it.
+    pub fn save_block_ids(&self, remove_missing: bool) -> Result<(), String> {
         fn walk_dir<P: AsRef<std::path::Path>>(dir: P) -> Vec<std::path::PathBuf> {
             let mut files = Vec::new();
 
@@ -466,6 +1100,11 @@ impl Database {
                 ),
             };
 
+            // Templates are only used by other configs, see blocks::BlockConfigTemplates
+            if config.get("template").is_some() {
+                continue;
+            }
+
             let block_name = match config.get("name").and_then(|name| name.as_str()) {
                 Some(n) => n,
                 // Blocks that don't have names are used as parent blocks and are not saved.
@@ -475,34 +1114,107 @@ impl Database {
             block_names.push(block_name.to_owned());
         }
 
+        // The directory is not read in any particular order, sorted so that new blocks get their
+        // ids in the same order every time.
+        block_names.sort();
+        for pair in block_names.windows(2) {
+            if pair[0] == pair[1] {
+                panic!(
+                    "There are several block configs with the name '{}', block names must be unique",
+                    pair[0]
+                );
+            }
+        }
+
+        let saved_ids = self.load_block_ids();
+
+        // Blocks that are still there keep their order, new blocks are added at the end. Block
+        // ids must have no gaps, so when a block is removed the ones after it move down.
+        let mut kept: Vec<(BlockId, &String)> = block_names
+            .iter()
+            .filter_map(|name| Some((*saved_ids.get(name)?, name)))
+            .collect();
+        kept.sort();
+
+        let mut new_ids: Vec<&String> = kept.into_iter().map(|(_, name)| name).collect();
+        new_ids.extend(
+            block_names
+                .iter()
+                .filter(|name| !saved_ids.contains_key(*name)),
+        );
+
+        let mut remap = HashMap::new();
+        for (new_id, name) in new_ids.iter().enumerate() {
+            if let Some(old_id) = saved_ids.get(*name) {
+                remap.insert(*old_id, new_id as BlockId);
+            }
+        }
+
+        let mut removed: Vec<(&String, BlockId)> = saved_ids
+            .iter()
+            .filter(|(name, _)| !block_names.contains(*name))
+            .map(|(name, id)| (name, *id))
+            .collect();
+        removed.sort();
+
         let mut conn = self.get_connection();
         let tx = conn.transaction().unwrap();
 
+        if !removed.is_empty() || remap.iter().any(|(old_id, new_id)| old_id != new_id) {
+            let unresolved = remap_block_ids(&tx, &remap, remove_missing);
+            if !unresolved.is_empty() {
+                let mut message = String::from(
+                    "The world has blocks that no longer have a config, they can't be loaded:\n",
+                );
+                for (name, id) in removed.iter() {
+                    if let Some(count) = unresolved.get(id) {
+                        message += &format!("    '{}' is used {} times\n", name, count);
+                    }
+                }
+                message += "Add the configs back, or start the server with \
+                    --remove-missing-blocks to remove them from the world. They are generated \
+                    again as if they had never been changed.";
+                return Err(message);
+            }
+
+            for (name, _) in removed.iter() {
+                info!("The block '{}' was removed", name);
+            }
+        }
+
+        tx.execute("DELETE FROM block_ids", []).unwrap();
         let mut stmt = tx
-            .prepare("INSERT INTO block_ids (name) VALUES (?)")
+            .prepare("INSERT INTO block_ids (id, name) VALUES (?,?)")
             .unwrap();
 
-        for name in block_names.into_iter() {
-            stmt.execute(rusqlite::params![name]).unwrap();
+        for (id, name) in new_ids.into_iter().enumerate() {
+            stmt.execute(rusqlite::params![id, name]).unwrap();
         }
 
         stmt.finalize().unwrap();
         tx.commit().expect("Failed to update block ids in database");
+
+        return Ok(());
     }
 
+    /// The id of each block by name
     pub fn load_block_ids(&self) -> HashMap<String, BlockId> {
         let conn = self.get_connection();
-        let mut stmt = conn.prepare("SELECT * FROM block_ids").unwrap();
+        let mut stmt = conn.prepare("SELECT id, name FROM block_ids").unwrap();
         let mut rows = stmt.query([]).unwrap();
 
         let mut blocks = HashMap::new();
         while let Some(row) = rows.next().unwrap() {
-            blocks.insert(row.get(1).unwrap(), blocks.len() as BlockId);
+            blocks.insert(row.get(1).unwrap(), row.get(0).unwrap());
         }
 
         return blocks;
     }
 
+    /// Save the ids of the item configs. Items are saved inside whatever the game stores, so
+    /// their ids can't be changed like the block ids are. Once an item has an id it keeps it, if its
+    /// config is removed the id is kept as a tombstone so that it is not given to another item, and
+    /// the item gets it back if the config returns.
     pub fn save_items(&self) {
         let mut item_names = Vec::new();
 
@@ -529,25 +1241,68 @@ impl Database {
             );
         }
 
+        item_names.sort();
+
         let mut conn = self.get_connection();
         let tx = conn.transaction().unwrap();
 
-        let mut stmt = tx
-            .prepare("INSERT INTO item_ids (name) VALUES (?)")
-            .unwrap();
+        let mut saved_items: HashMap<String, bool> = HashMap::new();
+        let mut stmt = tx.prepare("SELECT name, removed FROM item_ids").unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            saved_items.insert(row.get(0).unwrap(), row.get(1).unwrap());
+        }
+        drop(rows);
+        stmt.finalize().unwrap();
 
-        for name in item_names {
-            stmt.execute(rusqlite::params![name]).unwrap();
+        for name in item_names.iter() {
+            match saved_items.get(name) {
+                Some(false) => continue,
+                Some(true) => {
+                    info!("The item '{}' was added back, it has its old id", name);
+                    tx.execute("UPDATE item_ids SET removed = 0 WHERE name = ?", [name])
+                        .unwrap();
+                }
+                None => {
+                    // Ids are one more than the largest, tombstones included, so they're never
+                    // reused.
+                    tx.execute("INSERT INTO item_ids (name) VALUES (?)", [name])
+                        .unwrap();
+                }
+            }
+        }
+
+        let mut removed: Vec<&String> = saved_items
+            .iter()
+            .filter(|(name, removed)| !**removed && !item_names.contains(*name))
+            .map(|(name, _)| name)
+            .collect();
+        removed.sort();
+        for name in removed.iter() {
+            tx.execute("UPDATE item_ids SET removed = 1 WHERE name = ?", [name])
+                .unwrap();
+        }
+        if !removed.is_empty() {
+            warn!(
+                "These items no longer have configs: {}. Saved items of them can't be loaded, add \
+                the configs back if the world still has any.",
+                removed
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
         }
 
-        stmt.finalize().unwrap();
         tx.commit()
             .expect("Failed to save item ids to the database");
     }
 
     pub fn load_item_ids(&self) -> HashMap<String, ItemId> {
         let conn = self.get_connection();
-        let mut stmt = conn.prepare("SELECT * FROM item_ids").unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id, name FROM item_ids WHERE removed = 0")
+            .unwrap();
         let mut rows = stmt.query([]).unwrap();
 
         let mut blocks = HashMap::new();
@@ -610,3 +1365,318 @@ impl Database {
         return models;
     }
 }
+
+/// A block change made by a player, see `world::BlockAudit`
+/// The block of an audit entry whose config has been removed. The history is kept, but the block
+/// can't be restored.
+pub const REMOVED_BLOCK_ID: BlockId = BlockId::MAX;
+
+pub struct BlockAuditEntry {
+    pub position: IVec3,
+    pub account_id: String,
+    /// Username at the time of the change
+    pub username: String,
+    /// Unix time in seconds
+    pub time: i64,
+    pub from: (BlockId, Option<BlockState>),
+    pub to: (BlockId, Option<BlockState>),
+}
+
+impl BlockAuditEntry {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        return Ok(Self {
+            position: IVec3::new(row.get(0)?, row.get(1)?, row.get(2)?),
+            account_id: row.get(3)?,
+            username: row.get(4)?,
+            time: row.get(5)?,
+            from: (row.get(6)?, row.get::<_, Option<u16>>(7)?.map(BlockState)),
+            to: (row.get(8)?, row.get::<_, Option<u16>>(9)?.map(BlockState)),
+        });
+    }
+}
+
+type ChangedBlocks = HashMap<usize, (BlockId, Option<BlockState>, Option<BlockData>)>;
+
+fn load_chunk_record(conn: &rusqlite::Connection, position: &IVec3) -> ChangedBlocks {
+    let record = conn
+        .query_row(
+            "SELECT data FROM chunks WHERE x = ? AND y = ? AND z = ?",
+            [position.x, position.y, position.z],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .optional()
+        .unwrap();
+
+    let Some(record) = record else {
+        return HashMap::new();
+    };
+
+    match ChunkRecord::decode(&record) {
+        Some(record) => return record.blocks(),
+        None => {
+            error!(
+                "The saved blocks of the chunk at {} are corrupt, it will be regenerated.",
+                position
+            );
+            return HashMap::new();
+        }
+    }
+}
+
+// Change the block ids of the saved blocks from the keys of the remap to its values. Blocks with
+// ids that aren't in it are removed if `remove_missing` is set. Returns how many times each of the
+// missing ids was found, if they were not removed nothing is changed.
+//
+// The block audit is history, not part of the world. Its ids are remapped too, but ids that are
+// missing are changed to REMOVED_BLOCK_ID instead of being removed or counted.
+fn remap_block_ids(
+    tx: &rusqlite::Transaction,
+    remap: &HashMap<BlockId, BlockId>,
+    remove_missing: bool,
+) -> HashMap<BlockId, usize> {
+    let mut unresolved: HashMap<BlockId, usize> = HashMap::new();
+
+    let mut chunks = Vec::new();
+    let mut stmt = tx.prepare("SELECT x, y, z, data FROM chunks").unwrap();
+    let mut rows = stmt.query([]).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        let position = IVec3::new(
+            row.get(0).unwrap(),
+            row.get(1).unwrap(),
+            row.get(2).unwrap(),
+        );
+        chunks.push((position, row.get::<_, Vec<u8>>(3).unwrap()));
+    }
+    drop(rows);
+    stmt.finalize().unwrap();
+
+    for (position, data) in chunks {
+        // Corrupt chunks are regenerated when they're loaded, there is nothing to remap.
+        let Some(record) = ChunkRecord::decode(&data) else {
+            continue;
+        };
+
+        let mut blocks = record.blocks();
+        blocks.retain(|_, (block_id, _, _)| match remap.get(block_id) {
+            Some(new_id) => {
+                *block_id = *new_id;
+                true
+            }
+            None => {
+                *unresolved.entry(*block_id).or_default() += 1;
+                false
+            }
+        });
+
+        tx.execute(
+            "UPDATE chunks SET data = ? WHERE x = ? AND y = ? AND z = ?",
+            rusqlite::params![
+                ChunkRecord::new(blocks).encode(),
+                position.x,
+                position.y,
+                position.z
+            ],
+        )
+        .unwrap();
+    }
+
+    // Rows are changed one at a time, so that ids that are swapped aren't changed twice.
+    for (table, column, is_audit) in [
+        ("blocks", "block_id", false),
+        ("block_audit", "from_id", true),
+        ("block_audit", "to_id", true),
+    ] {
+        let mut rows_to_change = Vec::new();
+        let mut stmt = tx
+            .prepare(&format!("SELECT rowid, {} FROM {}", column, table))
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            rows_to_change.push((
+                row.get::<_, i64>(0).unwrap(),
+                row.get::<_, BlockId>(1).unwrap(),
+            ));
+        }
+        drop(rows);
+        stmt.finalize().unwrap();
+
+        for (rowid, block_id) in rows_to_change {
+            let new_id = match remap.get(&block_id) {
+                Some(new_id) => *new_id,
+                None if is_audit => REMOVED_BLOCK_ID,
+                None => {
+                    *unresolved.entry(block_id).or_default() += 1;
+                    tx.execute(&format!("DELETE FROM {} WHERE rowid = ?", table), [rowid])
+                        .unwrap();
+                    continue;
+                }
+            };
+
+            if new_id != block_id {
+                tx.execute(
+                    &format!("UPDATE {} SET {} = ? WHERE rowid = ?", table, column),
+                    rusqlite::params![new_id, rowid],
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    if remove_missing {
+        return HashMap::new();
+    }
+
+    return unresolved;
+}
+
+// Blocks that have been saved one by one, since the chunk was last compacted.
+fn load_block_rows(conn: &rusqlite::Connection, position: &IVec3) -> ChangedBlocks {
+    let mut block_stmt = conn
+        .prepare(
+            r#"
+        select
+            x, z, y, block_id, block_state, block_data
+        from
+            blocks
+        where
+            (x between ? and ?)
+        and
+            (y between ? and ?)
+        and
+            (z between ? and ?)"#,
+        )
+        .unwrap();
+
+    const OFFSET: i32 = Chunk::SIZE as i32 - 1;
+    let mut rows = block_stmt
+        .query([
+            &position.x,
+            &(position.x + OFFSET),
+            &position.y,
+            &(position.y + OFFSET),
+            &position.z,
+            &(position.z + OFFSET),
+        ])
+        .unwrap();
+
+    let mut blocks = HashMap::new();
+
+    while let Some(row) = rows.next().unwrap() {
+        let index = (((row.get::<_, i32>(0).unwrap() & OFFSET) << 8)
+            | ((row.get::<_, i32>(1).unwrap() & OFFSET) << 4)
+            | (row.get::<_, i32>(2).unwrap() & OFFSET)) as usize;
+
+        blocks.insert(
+            index,
+            (
+                row.get::<_, BlockId>(3).unwrap(),
+                row.get::<_, u16>(4).ok().map(BlockState),
+                row.get::<_, Vec<u8>>(5).ok().map(BlockData),
+            ),
+        );
+    }
+
+    return blocks;
+}
+
+// The changed blocks of a chunk stored together. Each block of the chunk is an index into a
+// palette of the block ids that are used, packed into as few bits as the palette needs. Index 0 is
+// left for blocks that haven't changed, they are generated when the chunk is loaded.
+#[derive(Serialize, Deserialize)]
+struct ChunkRecord {
+    palette: Vec<BlockId>,
+    bits: u8,
+    // Blocks that don't fit in a word are moved to the start of the next.
+    packed: Vec<u64>,
+    block_states: Vec<(u16, u16)>,
+    block_data: Vec<(u16, Vec<u8>)>,
+}
+
+impl ChunkRecord {
+    fn new(blocks: ChangedBlocks) -> Self {
+        let mut palette = Vec::new();
+        let mut indices = vec![0u32; Chunk::SIZE.pow(3)];
+        let mut block_states = Vec::new();
+        let mut block_data = Vec::new();
+
+        for (index, (block_id, block_state, data)) in blocks {
+            let palette_index = match palette.iter().position(|id| *id == block_id) {
+                Some(position) => position,
+                None => {
+                    palette.push(block_id);
+                    palette.len() - 1
+                }
+            };
+            indices[index] = palette_index as u32 + 1;
+
+            if let Some(block_state) = block_state {
+                block_states.push((index as u16, block_state.0));
+            }
+            if let Some(data) = data {
+                block_data.push((index as u16, data.0));
+            }
+        }
+
+        let bits = (u32::BITS - (palette.len() as u32).leading_zeros()).max(1) as u8;
+        let per_word = 64 / bits as usize;
+
+        let mut packed = vec![0u64; indices.len().div_ceil(per_word)];
+        for (i, palette_index) in indices.into_iter().enumerate() {
+            packed[i / per_word] |= (palette_index as u64) << ((i % per_word) * bits as usize);
+        }
+
+        return Self {
+            palette,
+            bits,
+            packed,
+            block_states,
+            block_data,
+        };
+    }
+
+    fn blocks(self) -> ChangedBlocks {
+        let per_word = 64 / self.bits as usize;
+        let mask = (1u64 << self.bits) - 1;
+
+        let mut blocks = HashMap::new();
+        for index in 0..Chunk::SIZE.pow(3) {
+            let Some(word) = self.packed.get(index / per_word) else {
+                break;
+            };
+            let palette_index = (word >> ((index % per_word) * self.bits as usize)) & mask;
+            if palette_index == 0 {
+                continue;
+            }
+            let Some(block_id) = self.palette.get(palette_index as usize - 1) else {
+                continue;
+            };
+            blocks.insert(index, (*block_id, None, None));
+        }
+
+        for (index, block_state) in self.block_states {
+            if let Some(block) = blocks.get_mut(&(index as usize)) {
+                block.1 = Some(BlockState(block_state));
+            }
+        }
+
+        for (index, data) in self.block_data {
+            if let Some(block) = blocks.get_mut(&(index as usize)) {
+                block.2 = Some(BlockData(data));
+            }
+        }
+
+        return blocks;
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        return bincode::serialize(self).unwrap();
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let record: Self = bincode::deserialize(data).ok()?;
+        if record.bits == 0 || record.bits > 32 {
+            return None;
+        }
+        return Some(record);
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+
+use crate::{
+    advancements::AdvancementProgress,
+    networking::{properties, ClientProperty, NetworkMessage, ResumedSession, Server},
+    players::PlayerSave,
+    prelude::*,
+};
+
+pub const DIALOGUE_PATH: &str = "./assets/server/dialogues/";
+// Entry in the player save the dialogue flags are stored in.
+const FLAGS_ENTRY: &str = "dialogue_flags";
+
+// TODO: There are no messages for dialogues, until there are they are sent as properties. The
+// server sends "dialogue" with the current node, or null when the dialogue is closed. The client
+// answers with "dialogue_option" and the index of the option that was chosen, or
+// "dialogue_close" when the player leaves the dialogue.
+//
+// Dialogues are trees of nodes defined in json, one file per dialogue. Each node has text and
+// options leading to other nodes. Options can be hidden behind conditions, and both nodes and
+// options can have actions that are run when they are reached.
+pub struct DialoguePlugin;
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<OpenDialogue>()
+            .add_event::<CloseDialogue>()
+            .add_event::<DialogueEvent>()
+            .add_systems(PreStartup, load_dialogues)
+            .add_systems(
+                Update,
+                (
+                    (open_dialogues, resend_dialogues),
+                    handle_dialogue_properties,
+                    close_dialogues,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Show a dialogue to a player from its start, e.g. when they click an npc. Replaces any
+/// dialogue they are already in.
+#[derive(Event)]
+pub struct OpenDialogue {
+    pub player_entity: Entity,
+    /// Name of the dialogue, its filename without the extension
+    pub dialogue: String,
+}
+
+#[derive(Event)]
+pub struct CloseDialogue {
+    pub player_entity: Entity,
+}
+
+/// Sent by the "event" action of a dialogue, for the game to react to, e.g. by starting a quest
+/// or opening a trade.
+#[derive(Event)]
+pub struct DialogueEvent {
+    pub player_entity: Entity,
+    /// The dialogue the action is part of
+    pub dialogue: String,
+    /// Name given to the action
+    pub event: String,
+}
+
+/// Dialogue config
+///
+/// ```json
+/// {
+///     "speaker": "Bob",
+///     "start": "greeting",
+///     "nodes": {
+///         "greeting": {
+///             "text": "Hello there",
+///             "options": [
+///                 {"text": "Got any work?", "next": "work", "conditions": [{"missing_flag": "bob_work"}]},
+///                 {"text": "Bye"}
+///             ]
+///         },
+///         "work": {
+///             "text": "Bring me some wood",
+///             "actions": [{"set_flag": "bob_work"}, {"event": "bob_wood_quest"}]
+///         }
+///     }
+/// }
+/// ```
+#[derive(Deserialize)]
+pub struct Dialogue {
+    /// Name shown above the text
+    pub speaker: String,
+    /// The node the dialogue starts at
+    pub start: String,
+    pub nodes: HashMap<String, DialogueNode>,
+}
+
+#[derive(Deserialize)]
+pub struct DialogueNode {
+    pub text: String,
+    /// Run every time the node is shown
+    #[serde(default)]
+    pub actions: Vec<DialogueAction>,
+    /// If none of the options can be shown the client only lets the player close the dialogue.
+    #[serde(default)]
+    pub options: Vec<DialogueOption>,
+}
+
+#[derive(Deserialize)]
+pub struct DialogueOption {
+    pub text: String,
+    /// All must be fulfilled for the option to be shown
+    #[serde(default)]
+    pub conditions: Vec<DialogueCondition>,
+    /// Run when the option is chosen, before moving to the next node.
+    #[serde(default)]
+    pub actions: Vec<DialogueAction>,
+    /// Name of the node the option leads to, the dialogue is closed if not set.
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DialogueCondition {
+    /// The player has the flag
+    Flag(String),
+    /// The player does not have the flag
+    MissingFlag(String),
+    /// The player has completed the advancement, "tree_name/advancement_name"
+    Advancement(String),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DialogueAction {
+    /// Give the player a flag, flags are kept between sessions.
+    SetFlag(String),
+    RemoveFlag(String),
+    /// Send a `DialogueEvent` with the name
+    Event(String),
+}
+
+/// All dialogues, by name
+#[derive(Resource, Default)]
+pub struct Dialogues(HashMap<String, Dialogue>);
+
+impl Dialogues {
+    pub fn get(&self, name: &str) -> Option<&Dialogue> {
+        return self.0.get(name);
+    }
+}
+
+/// Inserted on players while they are in a dialogue.
+#[derive(Component)]
+pub struct ActiveDialogue {
+    dialogue: String,
+    node: String,
+    // Indices of the node's options that were shown to the player, in the order they were shown.
+    options: Vec<usize>,
+}
+
+impl ActiveDialogue {
+    /// Name of the dialogue
+    pub fn dialogue(&self) -> &str {
+        return &self.dialogue;
+    }
+
+    fn send(&self, net: &Server, player_entity: Entity, dialogue: &Dialogue) {
+        let node = &dialogue.nodes[&self.node];
+        let value = properties::Dialogue {
+            speaker: dialogue.speaker.clone(),
+            text: node.text.clone(),
+            options: self
+                .options
+                .iter()
+                .map(|index| node.options[*index].text.clone())
+                .collect(),
+        };
+        net.send_property(player_entity, properties::DIALOGUE, &Some(value));
+    }
+}
+
+fn load_dialogues(mut commands: Commands) {
+    let mut dialogues = Dialogues::default();
+
+    // Servers are not required to have any dialogues.
+    let Ok(directory) = std::fs::read_dir(DIALOGUE_PATH) else {
+        commands.insert_resource(dialogues);
+        return;
+    };
+
+    for entry in directory {
+        let file_path = entry
+            .expect("Failed to read the filenames of the dialogue configs")
+            .path();
+        let name = file_path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+
+        let file = match std::fs::File::open(&file_path) {
+            Ok(f) => f,
+            Err(e) => panic!(
+                "Failed to open dialogue config at: {}\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        let dialogue: Dialogue = match serde_json::from_reader(&file) {
+            Ok(c) => c,
+            Err(e) => panic!(
+                "Couldn't read dialogue config from '{}'\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        if !dialogue.nodes.contains_key(&dialogue.start) {
+            panic!(
+                "Failed to parse dialogue config at: {}\nError: The dialogue starts at '{}', but there is no node by that name.",
+                file_path.display(), dialogue.start
+            );
+        }
+
+        for (node_name, node) in dialogue.nodes.iter() {
+            for next in node
+                .options
+                .iter()
+                .filter_map(|option| option.next.as_ref())
+            {
+                if !dialogue.nodes.contains_key(next) {
+                    panic!(
+                        "Failed to parse dialogue config at: {}\nError: An option of the node '{}' leads to '{}', but there is no node by that name.",
+                        file_path.display(), node_name, next
+                    );
+                }
+            }
+        }
+
+        dialogues.0.insert(name, dialogue);
+    }
+
+    commands.insert_resource(dialogues);
+}
+
+fn has_flag(save: &PlayerSave, flag: &str) -> bool {
+    return save
+        .get::<HashSet<String>>(FLAGS_ENTRY)
+        .is_some_and(|flags| flags.contains(flag));
+}
+
+fn is_fulfilled(
+    condition: &DialogueCondition,
+    save: &PlayerSave,
+    progress: Option<&AdvancementProgress>,
+) -> bool {
+    return match condition {
+        DialogueCondition::Flag(flag) => has_flag(save, flag),
+        DialogueCondition::MissingFlag(flag) => !has_flag(save, flag),
+        DialogueCondition::Advancement(advancement) => {
+            progress.is_some_and(|progress| progress.is_completed(advancement))
+        }
+    };
+}
+
+fn run_actions(
+    player_entity: Entity,
+    dialogue: &str,
+    actions: &[DialogueAction],
+    save: &mut Mut<PlayerSave>,
+    dialogue_events: &mut EventWriter<DialogueEvent>,
+) {
+    for action in actions {
+        match action {
+            DialogueAction::SetFlag(flag) => {
+                if !has_flag(save, flag) {
+                    let mut flags: HashSet<String> = save.get(FLAGS_ENTRY).unwrap_or_default();
+                    flags.insert(flag.clone());
+                    save.set(FLAGS_ENTRY, &flags);
+                }
+            }
+            DialogueAction::RemoveFlag(flag) => {
+                if has_flag(save, flag) {
+                    let mut flags: HashSet<String> = save.get(FLAGS_ENTRY).unwrap_or_default();
+                    flags.remove(flag);
+                    save.set(FLAGS_ENTRY, &flags);
+                }
+            }
+            DialogueAction::Event(event) => {
+                dialogue_events.send(DialogueEvent {
+                    player_entity,
+                    dialogue: dialogue.to_owned(),
+                    event: event.clone(),
+                });
+            }
+        }
+    }
+}
+
+// Runs the node's actions and finds the options that can be shown to the player.
+fn enter_node(
+    player_entity: Entity,
+    dialogue_name: &str,
+    dialogue: &Dialogue,
+    node_name: &str,
+    save: &mut Mut<PlayerSave>,
+    progress: Option<&AdvancementProgress>,
+    dialogue_events: &mut EventWriter<DialogueEvent>,
+) -> ActiveDialogue {
+    let node = &dialogue.nodes[node_name];
+    run_actions(
+        player_entity,
+        dialogue_name,
+        &node.actions,
+        save,
+        dialogue_events,
+    );
+
+    let options: Vec<usize> = node
+        .options
+        .iter()
+        .enumerate()
+        .filter(|(_, option)| {
+            option
+                .conditions
+                .iter()
+                .all(|condition| is_fulfilled(condition, save, progress))
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    return ActiveDialogue {
+        dialogue: dialogue_name.to_owned(),
+        node: node_name.to_owned(),
+        options,
+    };
+}
+
+fn open_dialogues(
+    mut commands: Commands,
+    net: Res<Server>,
+    dialogues: Res<Dialogues>,
+    mut player_query: Query<(&mut PlayerSave, Option<&AdvancementProgress>)>,
+    mut open_events: EventReader<OpenDialogue>,
+    mut dialogue_events: EventWriter<DialogueEvent>,
+) {
+    for open in open_events.read() {
+        let Some(dialogue) = dialogues.get(&open.dialogue) else {
+            error!(
+                "Tried to open the dialogue '{}', but there is no dialogue by that name.",
+                open.dialogue
+            );
+            continue;
+        };
+
+        let Ok((mut save, progress)) = player_query.get_mut(open.player_entity) else {
+            continue;
+        };
+
+        let active_dialogue = enter_node(
+            open.player_entity,
+            &open.dialogue,
+            dialogue,
+            &dialogue.start,
+            &mut save,
+            progress,
+            &mut dialogue_events,
+        );
+        active_dialogue.send(&net, open.player_entity, dialogue);
+        commands.entity(open.player_entity).insert(active_dialogue);
+    }
+}
+
+// A resumed session has a new client that isn't showing the dialogue the player was in.
+fn resend_dialogues(
+    net: Res<Server>,
+    dialogues: Res<Dialogues>,
+    player_query: Query<(Entity, &ActiveDialogue), Added<ResumedSession>>,
+) {
+    for (player_entity, active_dialogue) in player_query.iter() {
+        if let Some(dialogue) = dialogues.get(&active_dialogue.dialogue) {
+            active_dialogue.send(&net, player_entity, dialogue);
+        }
+    }
+}
+
+fn handle_dialogue_properties(
+    mut commands: Commands,
+    net: Res<Server>,
+    dialogues: Res<Dialogues>,
+    mut player_query: Query<(
+        &mut ActiveDialogue,
+        &mut PlayerSave,
+        Option<&AdvancementProgress>,
+    )>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+    mut close_events: EventWriter<CloseDialogue>,
+    mut dialogue_events: EventWriter<DialogueEvent>,
+) {
+    for property in property_events.read() {
+        match property.name.as_str() {
+            properties::DIALOGUE_OPTION => (),
+            properties::DIALOGUE_CLOSE => {
+                close_events.send(CloseDialogue {
+                    player_entity: property.player_entity,
+                });
+                continue;
+            }
+            _ => continue,
+        }
+
+        // The dialogue might have been closed by the server before the answer arrived.
+        let Ok((mut active_dialogue, mut save, progress)) =
+            player_query.get_mut(property.player_entity)
+        else {
+            continue;
+        };
+
+        let dialogue = &dialogues.0[&active_dialogue.dialogue];
+        let node = &dialogue.nodes[&active_dialogue.node];
+
+        let Some(option) = property
+            .parse::<usize>()
+            .and_then(|index| active_dialogue.options.get(index))
+            .map(|index| &node.options[*index])
+        else {
+            continue;
+        };
+
+        // What the player has may have changed since the option was shown
+        if !option
+            .conditions
+            .iter()
+            .all(|condition| is_fulfilled(condition, &save, progress))
+        {
+            continue;
+        }
+
+        run_actions(
+            property.player_entity,
+            &active_dialogue.dialogue,
+            &option.actions,
+            &mut save,
+            &mut dialogue_events,
+        );
+
+        if let Some(next) = &option.next {
+            *active_dialogue = enter_node(
+                property.player_entity,
+                &active_dialogue.dialogue,
+                dialogue,
+                next,
+                &mut save,
+                progress,
+                &mut dialogue_events,
+            );
+            active_dialogue.send(&net, property.player_entity, dialogue);
+        } else {
+            net.send_property(
+                property.player_entity,
+                properties::DIALOGUE,
+                &None::<properties::Dialogue>,
+            );
+            commands
+                .entity(property.player_entity)
+                .remove::<ActiveDialogue>();
+        }
+    }
+}
+
+fn close_dialogues(
+    mut commands: Commands,
+    net: Res<Server>,
+    player_query: Query<(), With<ActiveDialogue>>,
+    mut close_events: EventReader<CloseDialogue>,
+) {
+    for close in close_events.read() {
+        if !player_query.contains(close.player_entity) {
+            continue;
+        }
+
+        net.send_property(
+            close.player_entity,
+            properties::DIALOGUE,
+            &None::<properties::Dialogue>,
+        );
+        commands
+            .entity(close.player_entity)
+            .remove::<ActiveDialogue>();
+    }
+}
@@ -0,0 +1,484 @@
+use bevy::ecs::system::SystemParam;
+use rusqlite::OptionalExtension;
+
+use crate::{
+    chat::{self, ChatCommand},
+    database::Database,
+    networking::Server,
+    players::{Operator, Player},
+    prelude::*,
+    quests::{QuestCompleted, Quests},
+};
+
+// A single currency that players keep a balance of. Balances are stored in the database by
+// account id, so they can be changed while the player is offline, and every change is made in a
+// database transaction so money is never lost or duplicated halfway through a transfer.
+//
+// It is not part of the DefaultPlugins, games that want a currency add the EconomyPlugin.
+pub struct EconomyPlugin;
+impl Plugin for EconomyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EconomySettings>()
+            .add_event::<Transaction>()
+            .add_systems(Startup, setup_database)
+            .add_systems(
+                Update,
+                (
+                    insert_starting_balances,
+                    (handle_economy_commands, give_quest_rewards),
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Resource)]
+pub struct EconomySettings {
+    /// Name of the currency, used in chat messages
+    pub currency_name: String,
+    /// The balance players are given the first time they join
+    pub starting_balance: u64,
+}
+
+impl Default for EconomySettings {
+    fn default() -> Self {
+        Self {
+            currency_name: "coins".to_owned(),
+            starting_balance: 0,
+        }
+    }
+}
+
+/// Sent whenever money is moved. Money that is put on hold is not part of a transaction until
+/// the hold is settled.
+#[derive(Event, Debug, Clone)]
+pub struct Transaction {
+    /// The account the money was taken from, None when it was created, e.g. by a reward.
+    pub from: Option<String>,
+    /// The account the money was given to, None when it was removed, e.g. by a purchase.
+    pub to: Option<String>,
+    pub amount: u64,
+    /// Why the money was moved, e.g. "pay" or "trade"
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EconomyError {
+    /// The account doesn't have enough money
+    InsufficientFunds,
+    /// The balance would become larger than what can be stored
+    Overflow,
+    /// The hold has already been released or settled
+    UnknownHold,
+}
+
+impl std::fmt::Display for EconomyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Self::InsufficientFunds => write!(f, "Not enough money"),
+            Self::Overflow => write!(f, "The balance would be too large"),
+            Self::UnknownHold => write!(f, "The money is no longer on hold"),
+        };
+    }
+}
+
+/// Money taken out of an account and kept aside until it is released back to the account or
+/// settled to another, e.g. what a player has offered in a trade. Holds that are left when the
+/// server stops are released when it starts again.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Hold {
+    id: i64,
+    account_id: String,
+    amount: u64,
+}
+
+impl Hold {
+    pub fn account_id(&self) -> &str {
+        return &self.account_id;
+    }
+
+    pub fn amount(&self) -> u64 {
+        return self.amount;
+    }
+}
+
+/// Read and change balances.
+///
+/// ```ignore
+/// fn reward_kills(mut economy: Economy, player_query: Query<&Player>, ..) {
+///     economy.deposit(&player.account_id, 10, "kill_reward").unwrap();
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct Economy<'w> {
+    database: Res<'w, Database>,
+    transactions: EventWriter<'w, Transaction>,
+}
+
+impl<'w> Economy<'w> {
+    /// The balance of the account, not counting what it has on hold.
+    pub fn balance(&self, account_id: &str) -> u64 {
+        let conn = self.database.get_connection();
+        return balance(&conn, account_id).unwrap();
+    }
+
+    /// How much the account has on hold
+    pub fn held(&self, account_id: &str) -> u64 {
+        let conn = self.database.get_connection();
+        return conn
+            .query_row(
+                "SELECT COALESCE(SUM(amount), 0) FROM balance_holds WHERE account_id = ?",
+                [account_id],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap() as u64;
+    }
+
+    /// Add money to the account, returns the new balance.
+    pub fn deposit(
+        &mut self,
+        account_id: &str,
+        amount: u64,
+        reason: &str,
+    ) -> Result<u64, EconomyError> {
+        let mut conn = self.database.get_connection();
+        let tx = conn.transaction().unwrap();
+        let balance = change_balance(&tx, account_id, amount, true)?;
+        tx.commit().expect("Failed to save balance to the database");
+
+        self.transactions.send(Transaction {
+            from: None,
+            to: Some(account_id.to_owned()),
+            amount,
+            reason: reason.to_owned(),
+        });
+
+        return Ok(balance);
+    }
+
+    /// Remove money from the account, returns the new balance.
+    pub fn withdraw(
+        &mut self,
+        account_id: &str,
+        amount: u64,
+        reason: &str,
+    ) -> Result<u64, EconomyError> {
+        let mut conn = self.database.get_connection();
+        let tx = conn.transaction().unwrap();
+        let balance = change_balance(&tx, account_id, amount, false)?;
+        tx.commit().expect("Failed to save balance to the database");
+
+        self.transactions.send(Transaction {
+            from: Some(account_id.to_owned()),
+            to: None,
+            amount,
+            reason: reason.to_owned(),
+        });
+
+        return Ok(balance);
+    }
+
+    /// Move money between two accounts. Either both balances change or neither does.
+    pub fn transfer(
+        &mut self,
+        from: &str,
+        to: &str,
+        amount: u64,
+        reason: &str,
+    ) -> Result<(), EconomyError> {
+        let mut conn = self.database.get_connection();
+        let tx = conn.transaction().unwrap();
+        change_balance(&tx, from, amount, false)?;
+        change_balance(&tx, to, amount, true)?;
+        tx.commit()
+            .expect("Failed to save balances to the database");
+
+        self.transactions.send(Transaction {
+            from: Some(from.to_owned()),
+            to: Some(to.to_owned()),
+            amount,
+            reason: reason.to_owned(),
+        });
+
+        return Ok(());
+    }
+
+    /// Take money out of the account and keep it aside until it is released or settled.
+    pub fn hold(&mut self, account_id: &str, amount: u64) -> Result<Hold, EconomyError> {
+        let mut conn = self.database.get_connection();
+        let tx = conn.transaction().unwrap();
+        change_balance(&tx, account_id, amount, false)?;
+        tx.execute(
+            "INSERT INTO balance_holds (account_id, amount) VALUES (?,?)",
+            rusqlite::params![account_id, amount as i64],
+        )
+        .unwrap();
+        let id = tx.last_insert_rowid();
+        tx.commit().expect("Failed to save hold to the database");
+
+        return Ok(Hold {
+            id,
+            account_id: account_id.to_owned(),
+            amount,
+        });
+    }
+
+    /// Give the money on hold back to the account it was taken from.
+    pub fn release(&mut self, hold: Hold) -> Result<(), EconomyError> {
+        let mut conn = self.database.get_connection();
+        let tx = conn.transaction().unwrap();
+        remove_hold(&tx, &hold)?;
+        change_balance(&tx, &hold.account_id, hold.amount, true)?;
+        tx.commit().expect("Failed to save balance to the database");
+
+        return Ok(());
+    }
+
+    /// Give the money on hold to another account. Sends a transaction from the account the hold
+    /// was taken from.
+    pub fn settle(&mut self, hold: Hold, to: &str, reason: &str) -> Result<(), EconomyError> {
+        let mut conn = self.database.get_connection();
+        let tx = conn.transaction().unwrap();
+        remove_hold(&tx, &hold)?;
+        change_balance(&tx, to, hold.amount, true)?;
+        tx.commit().expect("Failed to save balance to the database");
+
+        self.transactions.send(Transaction {
+            from: Some(hold.account_id),
+            to: Some(to.to_owned()),
+            amount: hold.amount,
+            reason: reason.to_owned(),
+        });
+
+        return Ok(());
+    }
+}
+
+fn balance(conn: &rusqlite::Connection, account_id: &str) -> rusqlite::Result<u64> {
+    return conn
+        .query_row(
+            "SELECT balance FROM balances WHERE account_id = ?",
+            [account_id],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()
+        .map(|balance| balance.unwrap_or(0) as u64);
+}
+
+// Balances are stored as sqlite integers, so they can't be larger than i64::MAX.
+fn change_balance(
+    conn: &rusqlite::Connection,
+    account_id: &str,
+    amount: u64,
+    add: bool,
+) -> Result<u64, EconomyError> {
+    let current = balance(conn, account_id).unwrap();
+
+    let new = if add {
+        current
+            .checked_add(amount)
+            .filter(|balance| *balance <= i64::MAX as u64)
+            .ok_or(EconomyError::Overflow)?
+    } else {
+        current
+            .checked_sub(amount)
+            .ok_or(EconomyError::InsufficientFunds)?
+    };
+
+    conn.execute(
+        "INSERT OR REPLACE INTO balances VALUES (?,?)",
+        rusqlite::params![account_id, new as i64],
+    )
+    .unwrap();
+
+    return Ok(new);
+}
+
+fn remove_hold(conn: &rusqlite::Connection, hold: &Hold) -> Result<(), EconomyError> {
+    let removed = conn
+        .execute("DELETE FROM balance_holds WHERE id = ?", [hold.id])
+        .unwrap();
+    if removed == 0 {
+        return Err(EconomyError::UnknownHold);
+    }
+    return Ok(());
+}
+
+fn setup_database(database: Res<Database>) {
+    database.register_schema(
+        "economy",
+        &["create table balances (
+            account_id TEXT PRIMARY KEY,
+            balance INTEGER NOT NULL
+        );
+        create table balance_holds (
+            id INTEGER PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            amount INTEGER NOT NULL
+        );"],
+    );
+
+    // Whatever the holds were kept for, e.g. a trade, did not survive the restart.
+    let mut conn = database.get_connection();
+    let tx = conn.transaction().unwrap();
+    tx.execute_batch(
+        "UPDATE balances SET balance = balance + (
+            SELECT SUM(amount) FROM balance_holds WHERE balance_holds.account_id = balances.account_id
+        ) WHERE account_id IN (SELECT account_id FROM balance_holds);
+        DELETE FROM balance_holds;",
+    )
+    .unwrap();
+    tx.commit()
+        .expect("Failed to release held balances in the database");
+}
+
+fn insert_starting_balances(
+    database: Res<Database>,
+    settings: Res<EconomySettings>,
+    player_query: Query<&Player, Added<Player>>,
+) {
+    for player in player_query.iter() {
+        let conn = database.get_connection();
+        conn.execute(
+            "INSERT OR IGNORE INTO balances VALUES (?,?)",
+            rusqlite::params![player.account_id, settings.starting_balance as i64],
+        )
+        .expect("Failed to save balance to the database");
+    }
+}
+
+// "/balance [player]" and "/pay <player> <amount>". Only operators can see the balance of other
+// players. Offline players can be paid by the name they last played with.
+fn handle_economy_commands(
+    net: Res<Server>,
+    settings: Res<EconomySettings>,
+    mut economy: Economy,
+    operator_query: Query<(), With<Operator>>,
+    player_query: Query<(Entity, &Player)>,
+    mut command_events: EventReader<ChatCommand>,
+) {
+    for command in command_events.read() {
+        let Ok((_, sender)) = player_query.get(command.player_entity) else {
+            continue;
+        };
+
+        let find_account = |username: &str| -> Option<String> {
+            return player_query
+                .iter()
+                .find(|(_, player)| player.username == username)
+                .map(|(_, player)| player.account_id.clone())
+                .or_else(|| economy.database.find_player_account(username));
+        };
+
+        match command.name.as_str() {
+            "balance" => {
+                let Some(username) = command.args.first() else {
+                    command.reply(
+                        &net,
+                        format!(
+                            "You have {} {}",
+                            economy.balance(&sender.account_id),
+                            settings.currency_name
+                        ),
+                    );
+                    continue;
+                };
+
+                if !operator_query.contains(command.player_entity) {
+                    command.reply(&net, "You are not allowed to see the balance of others.");
+                    continue;
+                }
+
+                let Some(account_id) = find_account(username) else {
+                    command.reply(&net, "No player by that name.");
+                    continue;
+                };
+
+                command.reply(
+                    &net,
+                    format!(
+                        "{} has {} {}",
+                        username,
+                        economy.balance(&account_id),
+                        settings.currency_name
+                    ),
+                );
+            }
+            "pay" => {
+                let (Some(username), Some(Ok(amount))) = (
+                    command.args.first(),
+                    command.args.get(1).map(|amount| amount.parse::<u64>()),
+                ) else {
+                    command.reply(&net, "Usage: /pay <player> <amount>");
+                    continue;
+                };
+
+                if amount == 0 {
+                    command.reply(&net, "The amount must be more than 0.");
+                    continue;
+                }
+
+                let Some(account_id) = find_account(username) else {
+                    command.reply(&net, "No player by that name.");
+                    continue;
+                };
+
+                if account_id == sender.account_id {
+                    command.reply(&net, "You can't pay yourself.");
+                    continue;
+                }
+
+                if let Err(e) = economy.transfer(&sender.account_id, &account_id, amount, "pay") {
+                    command.reply(&net, format!("Could not pay {}: {}", username, e));
+                    continue;
+                }
+
+                command.reply(
+                    &net,
+                    format!("Paid {} {} to {}", amount, settings.currency_name, username),
+                );
+
+                for (player_entity, player) in player_query.iter() {
+                    if player.account_id == account_id {
+                        chat::send_private_message(
+                            &net,
+                            player_entity,
+                            format!(
+                                "{} paid you {} {}",
+                                sender.username, amount, settings.currency_name
+                            ),
+                        );
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn give_quest_rewards(
+    quests: Res<Quests>,
+    mut economy: Economy,
+    player_query: Query<&Player>,
+    mut completed_events: EventReader<QuestCompleted>,
+) {
+    for completed in completed_events.read() {
+        let Some(quest) = quests.get(&completed.quest) else {
+            continue;
+        };
+        let Ok(player) = player_query.get(completed.player_entity) else {
+            continue;
+        };
+
+        if quest.rewards.currency == 0 {
+            continue;
+        }
+
+        if let Err(e) = economy.deposit(&player.account_id, quest.rewards.currency, "quest") {
+            error!(
+                "Could not give {} the currency reward of the quest '{}': {}",
+                player.username, completed.quest, e
+            );
+        }
+    }
+}
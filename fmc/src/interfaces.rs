@@ -1,11 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use bevy::prelude::*;
 use fmc_protocol::messages;
 
 use crate::{
     items::ItemStack,
-    networking::{NetworkMessage, Server},
+    networking::{properties, ClientProperty, NetworkMessage, Server},
     players::Player,
 };
 
@@ -13,8 +13,25 @@ pub struct InterfacePlugin;
 impl Plugin for InterfacePlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<RegisterInterfaceProvider>()
-            .add_systems(Update, sort_item_updates.in_set(InterfaceEventRegistration))
-            .add_systems(Update, (insert_held_item, register_item_interfaces));
+            .add_event::<PlayInterfaceAnimation>()
+            .add_event::<InterfaceSortRequest>()
+            .add_systems(
+                Update,
+                (
+                    sort_item_updates,
+                    handle_sort_requests,
+                    handle_client_interfaces,
+                )
+                    .in_set(InterfaceEventRegistration),
+            )
+            .add_systems(
+                Update,
+                (
+                    insert_held_item,
+                    register_item_interfaces,
+                    send_interface_animations,
+                ),
+            );
     }
 }
 
@@ -37,6 +54,28 @@ pub struct HeldInterfaceStack {
 #[derive(Component, Deref, DerefMut, Default)]
 pub(crate) struct InterfaceNodes(HashMap<String, Entity>);
 
+/// The interfaces the player's client loaded from the server's assets. Updates to interfaces the
+/// client doesn't have disconnect it, so check with this before sending to interfaces a game
+/// might not include. It is inserted when the client reports them after loading its assets,
+/// clients that don't understand properties never do.
+#[derive(Component)]
+pub struct ClientInterfaces {
+    interfaces: HashSet<String>,
+    nodes: HashSet<String>,
+}
+
+impl ClientInterfaces {
+    /// If the client has the interface, by its file name, e.g. "inventory".
+    pub fn has_interface(&self, name: &str) -> bool {
+        return self.interfaces.contains(name);
+    }
+
+    /// If the client has a node at the path, e.g. "inventory/equipment".
+    pub fn has_node(&self, path: &str) -> bool {
+        return self.nodes.contains(path);
+    }
+}
+
 #[derive(Event)]
 pub struct RegisterInterfaceProvider {
     /// The player the item node should be registered for.
@@ -47,6 +86,33 @@ pub struct RegisterInterfaceProvider {
     pub node_entity: Entity,
 }
 
+/// Play one of the animations an interface node is configured with, e.g. to flash a button when
+/// something happens. Animations that are played when the node is opened or hovered are played
+/// by the client on its own.
+// TODO: There is no message for this, it is sent as the "interface_animation" property.
+#[derive(Event)]
+pub struct PlayInterfaceAnimation {
+    pub player_entity: Entity,
+    /// The node path. E.g. "inventory/crafting_table"
+    pub node_path: String,
+    /// Name the animation is configured under in the node's config
+    pub animation: String,
+}
+
+/// Sent when a player asks for the items in an item box section to be sorted. The items are
+/// stored by whoever provides the section, they should be sorted with `items::sort_item_stacks`
+/// and sent back to the player.
+// TODO: There is no message for this, it is sent as the "sort_interface" property with the
+// interface path as its value.
+#[derive(Event)]
+pub struct InterfaceSortRequest {
+    pub player_entity: Entity,
+    /// The path of the item box section. E.g. "inventory/storage"
+    pub interface_path: String,
+    /// The entity registered to provide the section, see `RegisterInterfaceProvider`
+    pub node_entity: Entity,
+}
+
 #[derive(Component)]
 pub struct InterfaceInteractionEvents(pub Vec<NetworkMessage<messages::InterfaceInteraction>>);
 
@@ -105,6 +171,60 @@ fn sort_item_updates(
     }
 }
 
+fn handle_sort_requests(
+    interface_nodes_query: Query<&InterfaceNodes>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+    mut sort_events: EventWriter<InterfaceSortRequest>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::SORT_INTERFACE {
+            continue;
+        }
+
+        let Some(interface_path) = property.parse::<String>() else {
+            continue;
+        };
+
+        // The client can't know which sections the server lets it sort, those that aren't
+        // registered are ignored.
+        let Some(node_entity) = interface_nodes_query
+            .get(property.player_entity)
+            .ok()
+            .and_then(|interface_nodes| interface_nodes.get(&interface_path))
+        else {
+            continue;
+        };
+
+        sort_events.send(InterfaceSortRequest {
+            player_entity: property.player_entity,
+            interface_path,
+            node_entity: *node_entity,
+        });
+    }
+}
+
+fn handle_client_interfaces(
+    mut commands: Commands,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::INTERFACES {
+            continue;
+        }
+
+        let Some(loaded) = property.parse::<properties::ClientInterfaces>() else {
+            continue;
+        };
+
+        commands
+            .entity(property.player_entity)
+            .insert(ClientInterfaces {
+                interfaces: loaded.interfaces.into_iter().collect(),
+                nodes: loaded.nodes.into_iter().collect(),
+            });
+    }
+}
+
 fn insert_held_item(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
     for entity in player_query.iter() {
         commands.entity(entity).insert(HeldInterfaceStack {
@@ -112,3 +232,19 @@ fn insert_held_item(mut commands: Commands, player_query: Query<Entity, Added<Pl
         });
     }
 }
+
+fn send_interface_animations(
+    net: Res<Server>,
+    mut animation_events: EventReader<PlayInterfaceAnimation>,
+) {
+    for event in animation_events.read() {
+        net.send_property(
+            event.player_entity,
+            properties::INTERFACE_ANIMATION,
+            &properties::InterfaceAnimation {
+                node_path: event.node_path.clone(),
+                animation: event.animation.clone(),
+            },
+        );
+    }
+}
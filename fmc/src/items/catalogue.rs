@@ -0,0 +1,358 @@
+use std::collections::BTreeSet;
+
+use fmc_protocol::messages;
+
+use crate::{
+    interfaces::{
+        HeldInterfaceStack, InterfaceEventRegistration, InterfaceInteractionEvents, InterfaceNodes,
+        RegisterInterfaceProvider,
+    },
+    networking::{NetworkMessage, ResumedSession, Server},
+    prelude::*,
+};
+
+use super::{Item, ItemId, ItemStack, Items};
+
+/// Interface the catalogue is shown in. Server assets must provide it, with the nodes below.
+pub const CATALOGUE_INTERFACE: &str = "creative";
+// Item box section the items are listed in.
+const ITEMS_PATH: &str = "creative/items";
+// Text input used to search by item name.
+const SEARCH_PATH: &str = "creative/search";
+// Text showing the current category and page.
+const CATEGORY_PATH: &str = "creative/category";
+const NEXT_CATEGORY_PATH: &str = "creative/next_category";
+const PREVIOUS_CATEGORY_PATH: &str = "creative/previous_category";
+const NEXT_PAGE_PATH: &str = "creative/next_page";
+const PREVIOUS_PAGE_PATH: &str = "creative/previous_page";
+
+// How many item boxes are filled per page.
+const PAGE_SIZE: usize = 45;
+
+// Lists every item in the game, players with access can take as many as they want of them.
+// Items placed into the catalogue are deleted.
+pub struct CataloguePlugin;
+impl Plugin for CataloguePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, build_catalogue).add_systems(
+            Update,
+            (
+                (
+                    add_catalogue_nodes,
+                    remove_catalogue_nodes,
+                    resend_catalogue_pages,
+                ),
+                (handle_searches, handle_interactions).after(InterfaceEventRegistration),
+                send_catalogue_pages,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Gives the player access to the creative catalogue. Remove it to take access away.
+#[derive(Component, Default)]
+pub struct CreativeCatalogue;
+
+// All items sorted by name, and the categories they can be filtered by.
+#[derive(Resource)]
+struct Catalogue {
+    items: Vec<ItemId>,
+    categories: Vec<String>,
+}
+
+// What the player is currently looking at. Lives on the entity the catalogue's interface
+// events are sent to.
+#[derive(Component)]
+struct CatalogueView {
+    player_entity: Entity,
+    // Index into the catalogue categories, None shows all items.
+    category: Option<usize>,
+    search: String,
+    page: usize,
+}
+
+impl CatalogueView {
+    fn items<'a>(
+        &'a self,
+        catalogue: &'a Catalogue,
+        items: &'a Items,
+    ) -> impl Iterator<Item = ItemId> + 'a {
+        let category = self
+            .category
+            .map(|index| catalogue.categories[index].as_str());
+        let search = self.search.to_lowercase();
+
+        catalogue.items.iter().cloned().filter(move |item_id| {
+            let config = items.get_config(item_id);
+            category.map_or(true, |category| config.categories.contains(category))
+                && config.name.to_lowercase().contains(&search)
+        })
+    }
+
+    fn page_count(&self, catalogue: &Catalogue, items: &Items) -> usize {
+        return self
+            .items(catalogue, items)
+            .count()
+            .div_ceil(PAGE_SIZE)
+            .max(1);
+    }
+
+    fn item_at(&self, catalogue: &Catalogue, items: &Items, index: usize) -> Option<ItemId> {
+        return self
+            .items(catalogue, items)
+            .skip(self.page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .nth(index);
+    }
+}
+
+// Marks views that need to be resent to the player.
+#[derive(Component)]
+struct CatalogueChanged;
+
+fn build_catalogue(mut commands: Commands, items: Res<Items>) {
+    let mut item_ids: Vec<ItemId> = items.configs.keys().cloned().collect();
+    item_ids.sort_by(|a, b| items.get_config(a).name.cmp(&items.get_config(b).name));
+
+    let categories: BTreeSet<String> = items
+        .configs
+        .values()
+        .flat_map(|config| config.categories.iter().cloned())
+        .collect();
+
+    commands.insert_resource(Catalogue {
+        items: item_ids,
+        categories: categories.into_iter().collect(),
+    });
+}
+
+fn add_catalogue_nodes(
+    mut commands: Commands,
+    player_query: Query<Entity, Added<CreativeCatalogue>>,
+    mut registration_events: EventWriter<RegisterInterfaceProvider>,
+) {
+    for player_entity in player_query.iter() {
+        let node_entity = commands
+            .spawn((
+                CatalogueView {
+                    player_entity,
+                    category: None,
+                    search: String::new(),
+                    page: 0,
+                },
+                CatalogueChanged,
+            ))
+            .set_parent(player_entity)
+            .id();
+
+        for node_path in [
+            ITEMS_PATH,
+            NEXT_CATEGORY_PATH,
+            PREVIOUS_CATEGORY_PATH,
+            NEXT_PAGE_PATH,
+            PREVIOUS_PAGE_PATH,
+        ] {
+            registration_events.send(RegisterInterfaceProvider {
+                player_entity,
+                node_path: node_path.to_owned(),
+                node_entity,
+            });
+        }
+    }
+}
+
+// TODO: There's no way to stop the client from opening the interface, if the player interacts
+// with it after access has been removed they will be disconnected.
+fn remove_catalogue_nodes(
+    mut commands: Commands,
+    net: Res<Server>,
+    view_query: Query<(Entity, &CatalogueView)>,
+    mut interface_nodes_query: Query<&mut InterfaceNodes>,
+    mut removed: RemovedComponents<CreativeCatalogue>,
+) {
+    for player_entity in removed.read() {
+        let Some((node_entity, _)) = view_query
+            .iter()
+            .find(|(_, view)| view.player_entity == player_entity)
+        else {
+            continue;
+        };
+
+        // The player might have disconnected
+        if let Ok(mut interface_nodes) = interface_nodes_query.get_mut(player_entity) {
+            interface_nodes.retain(|_, entity| *entity != node_entity);
+
+            net.send_one(
+                player_entity,
+                messages::InterfaceVisibilityUpdate {
+                    interface_path: CATALOGUE_INTERFACE.to_owned(),
+                    visible: false,
+                },
+            );
+        }
+
+        commands.entity(node_entity).despawn_recursive();
+    }
+}
+
+// A resumed session has a new client that hasn't been sent the page the player was on.
+fn resend_catalogue_pages(
+    mut commands: Commands,
+    resumed_query: Query<(), Added<ResumedSession>>,
+    view_query: Query<(Entity, &CatalogueView)>,
+) {
+    for (node_entity, view) in view_query.iter() {
+        if resumed_query.contains(view.player_entity) {
+            commands.entity(node_entity).insert(CatalogueChanged);
+        }
+    }
+}
+
+fn handle_searches(
+    mut commands: Commands,
+    mut view_query: Query<(Entity, &mut CatalogueView)>,
+    mut text_input_events: EventReader<NetworkMessage<messages::InterfaceTextInput>>,
+) {
+    for text_input in text_input_events.read() {
+        if text_input.interface_path != SEARCH_PATH {
+            continue;
+        }
+
+        let Some((node_entity, mut view)) = view_query
+            .iter_mut()
+            .find(|(_, view)| view.player_entity == text_input.player_entity)
+        else {
+            continue;
+        };
+
+        view.search = text_input.text.clone();
+        view.page = 0;
+        commands.entity(node_entity).insert(CatalogueChanged);
+    }
+}
+
+fn handle_interactions(
+    mut commands: Commands,
+    items: Res<Items>,
+    catalogue: Res<Catalogue>,
+    mut held_item_query: Query<&mut HeldInterfaceStack>,
+    mut view_query: Query<(Entity, &mut CatalogueView, &mut InterfaceInteractionEvents)>,
+) {
+    for (node_entity, mut view, mut interface_events) in view_query.iter_mut() {
+        let Ok(mut held_item) = held_item_query.get_mut(view.player_entity) else {
+            continue;
+        };
+
+        let category_count = catalogue.categories.len();
+        let page_count = view.page_count(&catalogue, &items);
+
+        let mut changed = false;
+
+        for interaction in interface_events.read() {
+            match &*interaction {
+                messages::InterfaceInteraction::TakeItem {
+                    index, quantity, ..
+                } => {
+                    let Some(item_id) = view.item_at(&catalogue, &items, *index as usize) else {
+                        continue;
+                    };
+                    let capacity = items.get_config(&item_id).max_stack_size;
+                    held_item.item_stack =
+                        ItemStack::new(Item::new(item_id), (*quantity).min(capacity), capacity);
+                }
+                messages::InterfaceInteraction::PlaceItem { .. } => {
+                    held_item.item_stack = ItemStack::default();
+                }
+                messages::InterfaceInteraction::Button { interface_path } => {
+                    match interface_path.as_str() {
+                        NEXT_CATEGORY_PATH => {
+                            view.category = match view.category {
+                                None if category_count > 0 => Some(0),
+                                Some(index) if index + 1 < category_count => Some(index + 1),
+                                _ => None,
+                            };
+                            view.page = 0;
+                        }
+                        PREVIOUS_CATEGORY_PATH => {
+                            view.category = match view.category {
+                                None => category_count.checked_sub(1),
+                                Some(index) => index.checked_sub(1),
+                            };
+                            view.page = 0;
+                        }
+                        NEXT_PAGE_PATH => view.page = (view.page + 1) % page_count,
+                        PREVIOUS_PAGE_PATH => {
+                            view.page = view.page.checked_sub(1).unwrap_or(page_count - 1)
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+
+            // The client changes its copy of the interface when items are taken or placed, it
+            // is resent to undo it.
+            changed = true;
+        }
+
+        if changed {
+            commands.entity(node_entity).insert(CatalogueChanged);
+        }
+    }
+}
+
+fn send_catalogue_pages(
+    mut commands: Commands,
+    net: Res<Server>,
+    items: Res<Items>,
+    catalogue: Res<Catalogue>,
+    view_query: Query<(Entity, &CatalogueView), With<CatalogueChanged>>,
+) {
+    for (node_entity, view) in view_query.iter() {
+        commands.entity(node_entity).remove::<CatalogueChanged>();
+
+        let page: Vec<ItemId> = view
+            .items(&catalogue, &items)
+            .skip(view.page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .collect();
+
+        let mut item_box_update = messages::InterfaceItemBoxUpdate::default();
+        for index in 0..PAGE_SIZE {
+            if let Some(item_id) = page.get(index) {
+                let config = items.get_config(item_id);
+                item_box_update.add_itembox(
+                    ITEMS_PATH,
+                    index as u32,
+                    *item_id,
+                    config.max_stack_size,
+                    config.durability,
+                    None,
+                );
+            } else {
+                item_box_update.add_empty_itembox(ITEMS_PATH, index as u32);
+            }
+        }
+        net.send_one(view.player_entity, item_box_update);
+
+        let category = match view.category {
+            Some(index) => catalogue.categories[index].as_str(),
+            None => "All items",
+        };
+        net.send_one(
+            view.player_entity,
+            messages::InterfaceTextUpdate {
+                interface_path: CATEGORY_PATH.to_owned(),
+                index: 0,
+                text: format!(
+                    "{} ({}/{})",
+                    category,
+                    view.page + 1,
+                    view.page_count(&catalogue, &items)
+                ),
+                font_size: 8.0,
+                color: "#ffffff".to_owned(),
+            },
+        );
+    }
+}
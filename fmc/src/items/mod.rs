@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -9,13 +9,30 @@ use crate::{
     models::ModelId,
 };
 
+mod catalogue;
+mod trade;
+mod usage;
+
+pub use catalogue::{CreativeCatalogue, CATALOGUE_INTERFACE};
+pub use trade::{CancelTrade, OpenTrade, TradeEnded, Trading, TRADE_INTERFACE};
+pub use usage::{EquippedItem, ItemUsage, ItemUseCancelled, ItemUseFinished, ItemUseStarted};
+
 pub type ItemId = u32;
 pub const ITEM_CONFIG_PATH: &str = "assets/client/items/configurations/";
 
 pub struct ItemPlugin;
 impl Plugin for ItemPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(PreStartup, load_items);
+        app.add_plugins((
+            catalogue::CataloguePlugin,
+            trade::TradePlugin,
+            usage::ItemUsePlugin,
+        ))
+        .init_resource::<DamageModifiers>()
+        .add_event::<ItemBreak>()
+        .add_event::<ItemPickup>()
+        .add_event::<ItemCraft>()
+        .add_systems(PreStartup, load_items);
     }
 }
 
@@ -78,6 +95,8 @@ fn load_items(mut commands: Commands, database: Res<Database>) {
                 max_stack_size: json.stack_size,
                 categories: json.categories,
                 tool: json.tool,
+                durability: json.durability,
+                usage: json.usage,
                 properties: json.properties,
             },
         );
@@ -100,6 +119,10 @@ pub struct ItemConfig {
     pub categories: HashSet<String>,
     /// Present if the item is used as a tool to break blocks
     pub tool: Option<Tool>,
+    /// How much damage the item can take before it breaks. Items without it never break.
+    pub durability: Option<u32>,
+    /// Present if the item is used by holding down the use button, e.g. food.
+    pub usage: Option<ItemUsage>,
     /// Properties unique to the item
     pub properties: serde_json::Map<String, serde_json::Value>,
 }
@@ -131,6 +154,8 @@ struct ItemConfigJson {
     #[serde(default)]
     properties: serde_json::Map<String, serde_json::Value>,
     tool: Option<Tool>,
+    durability: Option<u32>,
+    usage: Option<ItemUsage>,
 }
 
 /// Names and configs of all the items in the game.
@@ -162,6 +187,16 @@ pub struct Item {
     pub id: ItemId,
     /// Unique properties of the item. Separate from the shared properties of the ItemConfig.
     pub properties: serde_json::Value,
+    /// Remaining durability, only set once the item has taken damage. Use `Item::durability` to
+    /// read it.
+    #[serde(default)]
+    pub durability: Option<u32>,
+    /// Name shown instead of the name in the ItemConfig.
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Modifiers applied to the item, e.g. enchantments.
+    #[serde(default)]
+    pub modifiers: Vec<ItemModifier>,
 }
 
 impl Item {
@@ -169,8 +204,45 @@ impl Item {
         return Self {
             id,
             properties: serde_json::Value::default(),
+            durability: None,
+            display_name: None,
+            modifiers: Vec::new(),
         };
     }
+
+    /// The name the item should be shown with.
+    pub fn name<'a>(&'a self, config: &'a ItemConfig) -> &'a str {
+        return self.display_name.as_deref().unwrap_or(&config.name);
+    }
+
+    /// Get the level of a modifier, None if the item doesn't have it.
+    pub fn modifier_level(&self, name: &str) -> Option<u32> {
+        return self
+            .modifiers
+            .iter()
+            .find(|modifier| modifier.name == name)
+            .map(|modifier| modifier.level);
+    }
+
+    /// The remaining durability of the item, None if the item can't break.
+    pub fn durability(&self, config: &ItemConfig) -> Option<u32> {
+        let max = config.durability?;
+        return Some(self.durability.unwrap_or(max).min(max));
+    }
+
+    /// If the item has taken any damage.
+    pub fn is_damaged(&self, config: &ItemConfig) -> bool {
+        return self.durability(config) != config.durability;
+    }
+
+    // Full durability is stored as None so that undamaged items stack with new ones.
+    fn set_durability(&mut self, config: &ItemConfig, durability: u32) {
+        if config.durability.is_some_and(|max| durability >= max) {
+            self.durability = None;
+        } else {
+            self.durability = Some(durability);
+        }
+    }
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -270,6 +342,271 @@ impl ItemStack {
     pub fn is_empty(&self) -> bool {
         return self.item.is_none();
     }
+
+    /// Rename the item in the stack, None restores its original name.
+    pub fn set_display_name(&mut self, name: Option<String>) {
+        if let Some(item) = self.item.as_mut() {
+            item.display_name = name;
+        }
+    }
+
+    /// Add a modifier to the item in the stack. If the item already has a modifier by the same
+    /// name, its level is replaced.
+    pub fn add_modifier(&mut self, modifier: ItemModifier) {
+        let Some(item) = self.item.as_mut() else {
+            return;
+        };
+
+        if let Some(existing) = item
+            .modifiers
+            .iter_mut()
+            .find(|existing| existing.name == modifier.name)
+        {
+            existing.level = modifier.level;
+        } else {
+            item.modifiers.push(modifier);
+        }
+    }
+
+    /// Remove a modifier from the item in the stack.
+    pub fn remove_modifier(&mut self, name: &str) {
+        if let Some(item) = self.item.as_mut() {
+            item.modifiers.retain(|modifier| modifier.name != name);
+        }
+    }
+
+    /// Text describing the item, sent to clients in item box updates to be shown in tooltips.
+    /// The first line is the name of the item, followed by a line for each modifier. None if
+    /// the item has neither a display name nor modifiers, the client then shows the name from
+    /// the item's config.
+    pub fn description(&self, items: &Items) -> Option<String> {
+        let item = self.item.as_ref()?;
+        if item.display_name.is_none() && item.modifiers.is_empty() {
+            return None;
+        }
+
+        let mut description = item.name(items.get_config(&item.id)).to_owned();
+        for modifier in item.modifiers.iter() {
+            description.push('\n');
+            description.push_str(&modifier.to_string());
+        }
+
+        return Some(description);
+    }
+
+    /// Remaining durability of the item in the stack. This is what should be sent to clients
+    /// in item box updates so they can draw durability bars.
+    pub fn durability(&self, items: &Items) -> Option<u32> {
+        let item = self.item.as_ref()?;
+        return item.durability(items.get_config(&item.id));
+    }
+
+    /// Reduce the durability of the item. If it runs out, one item is removed from the stack and
+    /// returned, the rest of the stack is restored to full durability. Prefer
+    /// `ItemDurability::damage`, it applies the `DamageModifiers` and sends `ItemBreak` events.
+    pub fn damage(&mut self, items: &Items, amount: u32) -> Option<Item> {
+        let item = self.item.as_mut()?;
+        let config = items.get_config(&item.id);
+        let durability = item.durability(config)?;
+
+        if amount < durability {
+            item.set_durability(config, durability - amount);
+            return None;
+        }
+
+        let broken = self.take(1).item;
+        if let Some(item) = self.item.as_mut() {
+            item.durability = None;
+        }
+        return broken;
+    }
+
+    /// Restore durability to the item, it can't exceed the max durability of the item.
+    pub fn repair(&mut self, items: &Items, amount: u32) {
+        let Some(item) = self.item.as_mut() else {
+            return;
+        };
+        let config = items.get_config(&item.id);
+        if let Some(durability) = item.durability(config) {
+            item.set_durability(config, durability.saturating_add(amount));
+        }
+    }
+
+    /// Combine two damaged items of the same type into one, like an anvil would. The result
+    /// has the remaining durability of both, plus a bonus of a twentieth of the max
+    /// durability. Returns None if the stacks can't be combined. The stacks themselves are left
+    /// untouched, so the result can be shown before the player takes it.
+    pub fn combine_for_repair(&self, other: &ItemStack, items: &Items) -> Option<ItemStack> {
+        if self.size != 1 || other.size != 1 {
+            return None;
+        }
+
+        let (item, other_item) = (self.item.as_ref()?, other.item.as_ref()?);
+        if item.id != other_item.id {
+            return None;
+        }
+
+        let config = items.get_config(&item.id);
+        let max = config.durability?;
+        if !item.is_damaged(config) {
+            return None;
+        }
+
+        let mut combined = item.clone();
+        combined.set_durability(
+            config,
+            item.durability(config)? + other_item.durability(config)? + max / 20,
+        );
+
+        return Some(ItemStack::new(combined, 1, self.capacity));
+    }
+}
+
+/// Sort the item stacks of a container, e.g. when a player asks for it through the interface.
+/// Stacks of the same item are merged, then they are ordered by category and name, with the empty
+/// stacks at the end. Items are sorted by the first of their categories in alphabetical order,
+/// items without categories come last.
+pub fn sort_item_stacks(item_stacks: &mut [ItemStack], items: &Items) {
+    let mut merged: Vec<ItemStack> = Vec::new();
+
+    for item_stack in item_stacks.iter_mut() {
+        let mut item_stack = std::mem::take(item_stack);
+        if item_stack.is_empty() {
+            continue;
+        }
+
+        for existing in merged.iter_mut() {
+            if existing.item == item_stack.item && existing.size < existing.capacity {
+                item_stack = existing.add(item_stack);
+                if item_stack.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        if !item_stack.is_empty() {
+            merged.push(item_stack);
+        }
+    }
+
+    // The sort is stable, the partially filled stack of an item stays after its full stacks.
+    let sort_key = |item_stack: &ItemStack| {
+        let item = item_stack.item.as_ref().unwrap();
+        let config = items.get_config(&item.id);
+        let category = config.categories.iter().min();
+        (
+            category.is_none(),
+            category.cloned(),
+            item.name(config).to_owned(),
+        )
+    };
+    merged.sort_by_cached_key(sort_key);
+
+    for (slot, item_stack) in item_stacks.iter_mut().zip(merged) {
+        *slot = item_stack;
+    }
+}
+
+/// A named effect on an item, what it does is up to the server implementation.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ItemModifier {
+    pub name: String,
+    pub level: u32,
+}
+
+impl ItemModifier {
+    pub fn new(name: impl Into<String>, level: u32) -> Self {
+        return Self {
+            name: name.into(),
+            level,
+        };
+    }
+}
+
+impl std::fmt::Display for ItemModifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.level > 1 {
+            write!(f, "{} {}", self.name, self.level)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+/// Changes how much damage an item takes, e.g. an unbreaking enchantment that lets the item
+/// ignore some of it. Receives the damage left after the previous modifiers.
+pub type DamageModifier = fn(item: &Item, config: &ItemConfig, damage: u32) -> u32;
+
+/// Modifiers applied in order when items are damaged through `ItemDurability`.
+#[derive(Resource, Default)]
+pub struct DamageModifiers(Vec<DamageModifier>);
+
+impl DamageModifiers {
+    pub fn add(&mut self, modifier: DamageModifier) {
+        self.0.push(modifier);
+    }
+}
+
+/// Sent when an item runs out of durability.
+#[derive(Event)]
+pub struct ItemBreak {
+    /// The entity that held the item, e.g. a player.
+    pub entity: Entity,
+    /// The item that broke, it has already been removed from its stack.
+    pub item: Item,
+}
+
+/// Send when a player picks up items, e.g. from the ground. It counts towards advancements and
+/// quests as `advancements::ITEM_PICKUP`.
+#[derive(Event)]
+pub struct ItemPickup {
+    pub player_entity: Entity,
+    pub item_id: ItemId,
+    /// How many of the item were picked up.
+    pub amount: u32,
+}
+
+/// Send when a player crafts items. It counts towards advancements as `advancements::ITEM_CRAFT`.
+#[derive(Event)]
+pub struct ItemCraft {
+    pub player_entity: Entity,
+    pub item_id: ItemId,
+    /// How many of the item were crafted.
+    pub amount: u32,
+}
+
+/// Damages items while applying the `DamageModifiers`.
+#[derive(SystemParam)]
+pub struct ItemDurability<'w> {
+    items: Res<'w, Items>,
+    modifiers: Res<'w, DamageModifiers>,
+    break_events: EventWriter<'w, ItemBreak>,
+}
+
+impl<'w> ItemDurability<'w> {
+    /// Damage the item in the stack held by the entity. Returns true if the item broke.
+    pub fn damage(&mut self, entity: Entity, item_stack: &mut ItemStack, amount: u32) -> bool {
+        let Some(item) = item_stack.item() else {
+            return false;
+        };
+        let config = self.items.get_config(&item.id);
+
+        let mut amount = amount;
+        for modifier in self.modifiers.0.iter() {
+            amount = modifier(item, config, amount);
+        }
+
+        if amount == 0 {
+            return false;
+        }
+
+        if let Some(item) = item_stack.damage(&self.items, amount) {
+            self.break_events.send(ItemBreak { entity, item });
+            return true;
+        } else {
+            return false;
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -0,0 +1,460 @@
+use std::collections::HashSet;
+
+use fmc_protocol::messages;
+
+use crate::{
+    interfaces::{
+        HeldInterfaceStack, InterfaceEventRegistration, InterfaceInteractionEvents, InterfaceNodes,
+        RegisterInterfaceProvider,
+    },
+    networking::{AwaitingReconnect, NetworkEvent, Server},
+    players::Player,
+    prelude::*,
+};
+
+use super::{ItemStack, Items};
+
+/// Interface the trade is shown in. Server assets must provide it, with the nodes below.
+pub const TRADE_INTERFACE: &str = "trade";
+// Item box section the player stages the items they offer in.
+const OFFER_PATH: &str = "trade/offer";
+// Item box section showing what the other player offers, it can't be changed.
+const PARTNER_OFFER_PATH: &str = "trade/partner_offer";
+const ACCEPT_PATH: &str = "trade/accept";
+const CANCEL_PATH: &str = "trade/cancel";
+// Text showing if the players have accepted, the player's own status on the first line and the
+// other player's on the second.
+const STATUS_PATH: &str = "trade/status";
+
+// How many item boxes each player can stage items in.
+const OFFER_SIZE: usize = 9;
+
+const STATUS_FONT_SIZE: f32 = 8.0;
+const STATUS_COLOR: &str = "#ffffff";
+
+// Two players stage items, both see each other's offer as it changes. When both have accepted
+// they have to accept once more to confirm, any change to the offers in between takes the
+// acceptance back. On completion both offers are handed over in the same tick.
+//
+// The players' inventories belong to the game, the trade only holds what has been staged.
+// Items that leave the trade, either the other player's offer or the player's own when it is
+// cancelled, are handed to the game through `TradeEnded`.
+pub struct TradePlugin;
+impl Plugin for TradePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<OpenTrade>()
+            .add_event::<CancelTrade>()
+            .add_event::<TradeEnded>()
+            // Trades of disconnected players are cancelled before Update, so the game can give
+            // the items back before the player is saved.
+            .add_systems(PreUpdate, cancel_trades)
+            .add_systems(
+                Update,
+                (
+                    open_trades,
+                    handle_interactions.after(InterfaceEventRegistration),
+                    send_trade_updates,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Open the trade interface for two players. Ignored if one of them is already trading, check
+/// for the `Trading` component first.
+#[derive(Event)]
+pub struct OpenTrade {
+    pub players: [Entity; 2],
+}
+
+/// Cancel the trade the player is in, their staged items are returned through `TradeEnded`.
+/// Trades are also cancelled when either player presses the cancel button or disconnects.
+#[derive(Event)]
+pub struct CancelTrade {
+    pub player_entity: Entity,
+}
+
+/// Sent for both players when a trade ends. The items must be given to the player, e.g. put in
+/// their inventory and dropped if they don't fit.
+///
+/// When the player disconnected this is sent in PreUpdate, while the player entity is still
+/// there, give the items back before the player is saved.
+#[derive(Event)]
+pub struct TradeEnded {
+    pub player_entity: Entity,
+    /// If both players confirmed the trade, else it was cancelled.
+    pub completed: bool,
+    /// The other player's offer if the trade was completed, the player's own if it was
+    /// cancelled.
+    pub items: Vec<ItemStack>,
+}
+
+/// Inserted on players while they are trading.
+#[derive(Component)]
+pub struct Trading {
+    trade_entity: Entity,
+}
+
+#[derive(Component)]
+struct Trade {
+    parties: [TradeParty; 2],
+    // Both have accepted once, they now have to confirm.
+    confirming: bool,
+}
+
+impl Trade {
+    fn party_index(&self, player_entity: Entity) -> Option<usize> {
+        return self
+            .parties
+            .iter()
+            .position(|party| party.player_entity == player_entity);
+    }
+
+    fn reset_acceptance(&mut self) {
+        self.confirming = false;
+        for party in self.parties.iter_mut() {
+            party.accepted = false;
+        }
+    }
+
+    // Returns true when the trade is complete
+    fn accept(&mut self, party_index: usize) -> bool {
+        self.parties[party_index].accepted = true;
+
+        if !self.parties.iter().all(|party| party.accepted) {
+            return false;
+        } else if self.confirming {
+            return true;
+        }
+
+        self.confirming = true;
+        for party in self.parties.iter_mut() {
+            party.accepted = false;
+        }
+        return false;
+    }
+}
+
+struct TradeParty {
+    player_entity: Entity,
+    username: String,
+    offer: Vec<ItemStack>,
+    accepted: bool,
+}
+
+impl TradeParty {
+    fn status(&self, confirming: bool) -> &'static str {
+        return match (confirming, self.accepted) {
+            (false, false) => "Not accepted",
+            (false, true) => "Accepted",
+            (true, false) => "Waiting for confirmation",
+            (true, true) => "Confirmed",
+        };
+    }
+}
+
+// Marks trades that need to be resent to the players.
+#[derive(Component)]
+struct TradeChanged;
+
+fn open_trades(
+    mut commands: Commands,
+    net: Res<Server>,
+    player_query: Query<&Player, (Without<Trading>, Without<AwaitingReconnect>)>,
+    mut open_events: EventReader<OpenTrade>,
+    mut registration_events: EventWriter<RegisterInterfaceProvider>,
+) {
+    // Trading is inserted at the end of the tick
+    let mut trading = HashSet::new();
+
+    for open in open_events.read() {
+        let [first, second] = open.players;
+        let (Ok(first_player), Ok(second_player)) =
+            (player_query.get(first), player_query.get(second))
+        else {
+            continue;
+        };
+
+        if first == second || trading.contains(&first) || trading.contains(&second) {
+            continue;
+        }
+        trading.extend(open.players);
+
+        let party = |player_entity: Entity, player: &Player| TradeParty {
+            player_entity,
+            username: player.username.clone(),
+            offer: (0..OFFER_SIZE).map(|_| ItemStack::default()).collect(),
+            accepted: false,
+        };
+
+        let trade_entity = commands
+            .spawn((
+                Trade {
+                    parties: [party(first, first_player), party(second, second_player)],
+                    confirming: false,
+                },
+                TradeChanged,
+            ))
+            .id();
+
+        for player_entity in open.players {
+            commands
+                .entity(player_entity)
+                .insert(Trading { trade_entity });
+
+            for node_path in [OFFER_PATH, PARTNER_OFFER_PATH, ACCEPT_PATH, CANCEL_PATH] {
+                registration_events.send(RegisterInterfaceProvider {
+                    player_entity,
+                    node_path: node_path.to_owned(),
+                    node_entity: trade_entity,
+                });
+            }
+
+            net.send_one(
+                player_entity,
+                messages::InterfaceVisibilityUpdate {
+                    interface_path: TRADE_INTERFACE.to_owned(),
+                    visible: true,
+                },
+            );
+        }
+    }
+}
+
+fn handle_interactions(
+    mut commands: Commands,
+    net: Res<Server>,
+    mut held_item_query: Query<&mut HeldInterfaceStack>,
+    mut interface_nodes_query: Query<&mut InterfaceNodes>,
+    mut trade_query: Query<(Entity, &mut Trade, &mut InterfaceInteractionEvents)>,
+    mut ended_events: EventWriter<TradeEnded>,
+) {
+    for (trade_entity, mut trade, mut interface_events) in trade_query.iter_mut() {
+        let mut changed = false;
+        let mut ended = None;
+
+        for interaction in interface_events.read() {
+            let Some(party_index) = trade.party_index(interaction.player_entity) else {
+                continue;
+            };
+
+            // The client changes its copy of the interface when items are taken or placed, it
+            // is always resent in case the server didn't agree.
+            changed = true;
+
+            match &*interaction {
+                messages::InterfaceInteraction::TakeItem {
+                    interface_path,
+                    index,
+                    quantity,
+                } => {
+                    if interface_path != OFFER_PATH {
+                        continue;
+                    }
+                    let (Ok(mut held_item), Some(item_stack)) = (
+                        held_item_query.get_mut(interaction.player_entity),
+                        trade.parties[party_index].offer.get_mut(*index as usize),
+                    ) else {
+                        continue;
+                    };
+                    item_stack.transfer_to(&mut held_item.item_stack, *quantity);
+                    trade.reset_acceptance();
+                }
+                messages::InterfaceInteraction::PlaceItem {
+                    interface_path,
+                    index,
+                    quantity,
+                } => {
+                    if interface_path != OFFER_PATH {
+                        continue;
+                    }
+                    let (Ok(mut held_item), Some(item_stack)) = (
+                        held_item_query.get_mut(interaction.player_entity),
+                        trade.parties[party_index].offer.get_mut(*index as usize),
+                    ) else {
+                        continue;
+                    };
+                    held_item.item_stack.transfer_to(item_stack, *quantity);
+                    trade.reset_acceptance();
+                }
+                messages::InterfaceInteraction::Button { interface_path } => {
+                    match interface_path.as_str() {
+                        ACCEPT_PATH => {
+                            if trade.accept(party_index) {
+                                ended = Some(true);
+                                break;
+                            }
+                        }
+                        CANCEL_PATH => {
+                            ended = Some(false);
+                            break;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        if let Some(completed) = ended {
+            end_trade(
+                &mut commands,
+                &net,
+                trade_entity,
+                &mut trade,
+                completed,
+                &mut interface_nodes_query,
+                &mut ended_events,
+            );
+        } else if changed {
+            commands.entity(trade_entity).insert(TradeChanged);
+        }
+    }
+}
+
+fn cancel_trades(
+    mut commands: Commands,
+    net: Res<Server>,
+    trading_query: Query<&Trading>,
+    reconnect_query: Query<&Trading, Added<AwaitingReconnect>>,
+    mut trade_query: Query<&mut Trade>,
+    mut interface_nodes_query: Query<&mut InterfaceNodes>,
+    mut network_events: EventReader<NetworkEvent>,
+    mut cancel_events: EventReader<CancelTrade>,
+    mut ended_events: EventWriter<TradeEnded>,
+) {
+    let disconnected = network_events.read().filter_map(|event| match event {
+        NetworkEvent::Disconnected { entity } => Some(*entity),
+        _ => None,
+    });
+    let cancelled = cancel_events.read().map(|cancel| cancel.player_entity);
+
+    // Both players might leave at the same time
+    let trade_entities: HashSet<Entity> = disconnected
+        .chain(cancelled)
+        .filter_map(|player_entity| trading_query.get(player_entity).ok())
+        .chain(reconnect_query.iter())
+        .map(|trading| trading.trade_entity)
+        .collect();
+
+    for trade_entity in trade_entities {
+        let Ok(mut trade) = trade_query.get_mut(trade_entity) else {
+            continue;
+        };
+
+        end_trade(
+            &mut commands,
+            &net,
+            trade_entity,
+            &mut trade,
+            false,
+            &mut interface_nodes_query,
+            &mut ended_events,
+        );
+    }
+}
+
+fn end_trade(
+    commands: &mut Commands,
+    net: &Server,
+    trade_entity: Entity,
+    trade: &mut Trade,
+    completed: bool,
+    interface_nodes_query: &mut Query<&mut InterfaceNodes>,
+    ended_events: &mut EventWriter<TradeEnded>,
+) {
+    let offers = trade
+        .parties
+        .each_mut()
+        .map(|party| std::mem::take(&mut party.offer));
+    let [first_offer, second_offer] = offers;
+    let items = if completed {
+        [second_offer, first_offer]
+    } else {
+        [first_offer, second_offer]
+    };
+
+    for (party, items) in trade.parties.iter().zip(items) {
+        ended_events.send(TradeEnded {
+            player_entity: party.player_entity,
+            completed,
+            items: items
+                .into_iter()
+                .filter(|stack| !stack.is_empty())
+                .collect(),
+        });
+
+        if let Ok(mut interface_nodes) = interface_nodes_query.get_mut(party.player_entity) {
+            interface_nodes.retain(|_, entity| *entity != trade_entity);
+        }
+
+        if let Some(mut entity_commands) = commands.get_entity(party.player_entity) {
+            entity_commands.remove::<Trading>();
+        }
+
+        net.send_one(
+            party.player_entity,
+            messages::InterfaceVisibilityUpdate {
+                interface_path: TRADE_INTERFACE.to_owned(),
+                visible: false,
+            },
+        );
+    }
+
+    // Removed in the same tick, so that nothing can be done to the trade after it has been
+    // handed out.
+    commands.entity(trade_entity).despawn();
+}
+
+fn send_trade_updates(
+    mut commands: Commands,
+    net: Res<Server>,
+    items: Res<Items>,
+    trade_query: Query<(Entity, &Trade), With<TradeChanged>>,
+) {
+    for (trade_entity, trade) in trade_query.iter() {
+        commands.entity(trade_entity).remove::<TradeChanged>();
+
+        for (index, party) in trade.parties.iter().enumerate() {
+            let partner = &trade.parties[1 - index];
+
+            let mut item_box_update = messages::InterfaceItemBoxUpdate::default();
+            for (path, offer) in [
+                (OFFER_PATH, &party.offer),
+                (PARTNER_OFFER_PATH, &partner.offer),
+            ] {
+                for (index, item_stack) in offer.iter().enumerate() {
+                    if let Some(item) = item_stack.item() {
+                        item_box_update.add_itembox(
+                            path,
+                            index as u32,
+                            item.id,
+                            item_stack.size(),
+                            item_stack.durability(&items),
+                            item_stack.description(&items).as_deref(),
+                        );
+                    } else {
+                        item_box_update.add_empty_itembox(path, index as u32);
+                    }
+                }
+            }
+            net.send_one(party.player_entity, item_box_update);
+
+            let lines = [
+                format!("You: {}", party.status(trade.confirming)),
+                format!("{}: {}", partner.username, partner.status(trade.confirming)),
+            ];
+            for (index, text) in lines.into_iter().enumerate() {
+                net.send_one(
+                    party.player_entity,
+                    messages::InterfaceTextUpdate {
+                        interface_path: STATUS_PATH.to_owned(),
+                        index: index as i32,
+                        text,
+                        font_size: STATUS_FONT_SIZE,
+                        color: STATUS_COLOR.to_owned(),
+                    },
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    networking::{properties, ClientProperty, NetworkMessage, Server},
+    players::Player,
+    prelude::*,
+};
+
+use super::{ItemId, Items};
+
+// How much earlier than its duration the client may finish using an item. Covers the latency
+// between the client starting the use and the server receiving it.
+const LATENCY_TOLERANCE: f64 = 0.2;
+
+// Items are used by holding down the use button. The client sends when the use starts, and
+// when it is cancelled or finished. The server times it, and rejects uses that finish before the
+// item's use duration has passed.
+pub struct ItemUsePlugin;
+impl Plugin for ItemUsePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ItemUseStarted>()
+            .add_event::<ItemUseCancelled>()
+            .add_event::<ItemUseFinished>()
+            .add_systems(
+                Update,
+                (
+                    insert_item_use,
+                    cancel_unequipped_uses,
+                    handle_use_properties,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// How an item is used by holding down the use button, e.g. eating or charging a bow.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct ItemUsage {
+    /// Seconds it takes to use the item.
+    pub duration: f64,
+    /// If the item can be released before the duration has passed, it will finish with the
+    /// charge it has gathered. Other items are cancelled when released early.
+    #[serde(default)]
+    pub chargeable: bool,
+    /// Seconds after a finished use before the item can be used again.
+    #[serde(default)]
+    pub cooldown: f64,
+}
+
+/// The item the player uses when it holds down the use button. Games must keep it up to date
+/// with the item the player has equipped, items can't be used while it is None.
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct EquippedItem(pub Option<ItemId>);
+
+/// Sent when a player starts using an item.
+#[derive(Event)]
+pub struct ItemUseStarted {
+    pub player_entity: Entity,
+    pub item_id: ItemId,
+}
+
+/// Sent when a player stops using an item before it finished, or when it switches items.
+#[derive(Event)]
+pub struct ItemUseCancelled {
+    pub player_entity: Entity,
+    pub item_id: ItemId,
+}
+
+/// Sent when a player has finished using an item. This is where food is eaten and arrows fired.
+#[derive(Event)]
+pub struct ItemUseFinished {
+    pub player_entity: Entity,
+    pub item_id: ItemId,
+    /// How far into its use duration the item got, from 0.0 to 1.0. Only chargeable items
+    /// finish with less than 1.0.
+    pub charge: f64,
+}
+
+#[derive(Component, Default)]
+struct ItemUse {
+    // The item being used and when it was started
+    current: Option<(ItemId, f64)>,
+    // When the items can be used again
+    cooldowns: HashMap<ItemId, f64>,
+}
+
+fn insert_item_use(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
+    for player_entity in player_query.iter() {
+        commands
+            .entity(player_entity)
+            .insert_if_new(EquippedItem::default())
+            .insert(ItemUse::default());
+    }
+}
+
+// Tells the client to stop showing the use of the item.
+fn send_cancel(net: &Server, player_entity: Entity) {
+    net.send_property(
+        player_entity,
+        properties::ITEM_USE,
+        &properties::ItemUse::Cancel,
+    );
+}
+
+fn cancel_unequipped_uses(
+    net: Res<Server>,
+    mut player_query: Query<(Entity, &EquippedItem, &mut ItemUse), Changed<EquippedItem>>,
+    mut cancel_events: EventWriter<ItemUseCancelled>,
+) {
+    for (player_entity, equipped, mut item_use) in player_query.iter_mut() {
+        let Some((item_id, _)) = item_use.current else {
+            continue;
+        };
+
+        if equipped.0 == Some(item_id) {
+            continue;
+        }
+
+        item_use.current = None;
+        send_cancel(&net, player_entity);
+        cancel_events.send(ItemUseCancelled {
+            player_entity,
+            item_id,
+        });
+    }
+}
+
+fn handle_use_properties(
+    net: Res<Server>,
+    time: Res<Time>,
+    items: Res<Items>,
+    mut player_query: Query<(&EquippedItem, &mut ItemUse)>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+    mut start_events: EventWriter<ItemUseStarted>,
+    mut cancel_events: EventWriter<ItemUseCancelled>,
+    mut finish_events: EventWriter<ItemUseFinished>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    for property in property_events.read() {
+        if property.name != properties::ITEM_USE {
+            continue;
+        }
+
+        let Some(action) = property.parse::<properties::ItemUse>() else {
+            continue;
+        };
+
+        let player_entity = property.player_entity;
+        let Ok((equipped, mut item_use)) = player_query.get_mut(player_entity) else {
+            continue;
+        };
+
+        match action {
+            properties::ItemUse::Start => {
+                let Some(item_id) = equipped.0 else {
+                    send_cancel(&net, player_entity);
+                    continue;
+                };
+
+                let is_usable = items.get_config(&item_id).usage.is_some();
+                let is_cooling_down = item_use
+                    .cooldowns
+                    .get(&item_id)
+                    .is_some_and(|ready_at| *ready_at > now + LATENCY_TOLERANCE);
+
+                if !is_usable || is_cooling_down {
+                    send_cancel(&net, player_entity);
+                    continue;
+                }
+
+                // A new use replaces the old if the client didn't end it.
+                if let Some((previous_id, _)) = item_use.current.replace((item_id, now)) {
+                    cancel_events.send(ItemUseCancelled {
+                        player_entity,
+                        item_id: previous_id,
+                    });
+                }
+
+                start_events.send(ItemUseStarted {
+                    player_entity,
+                    item_id,
+                });
+            }
+            properties::ItemUse::Cancel => {
+                if let Some((item_id, _)) = item_use.current.take() {
+                    cancel_events.send(ItemUseCancelled {
+                        player_entity,
+                        item_id,
+                    });
+                }
+            }
+            properties::ItemUse::Finish => {
+                let Some((item_id, started)) = item_use.current.take() else {
+                    continue;
+                };
+                let Some(usage) = items.get_config(&item_id).usage else {
+                    continue;
+                };
+
+                let elapsed = now - started;
+                if !usage.chargeable && elapsed + LATENCY_TOLERANCE < usage.duration {
+                    send_cancel(&net, player_entity);
+                    cancel_events.send(ItemUseCancelled {
+                        player_entity,
+                        item_id,
+                    });
+                    continue;
+                }
+
+                let charge = if usage.duration > 0.0 {
+                    (elapsed / usage.duration).clamp(0.0, 1.0)
+                } else {
+                    1.0
+                };
+
+                item_use.cooldowns.retain(|_, ready_at| *ready_at > now);
+                if usage.cooldown > 0.0 {
+                    item_use.cooldowns.insert(item_id, now + usage.cooldown);
+                }
+
+                finish_events.send(ItemUseFinished {
+                    player_entity,
+                    item_id,
+                    charge: if usage.chargeable { charge } else { 1.0 },
+                });
+            }
+        }
+    }
+}
@@ -1,15 +1,22 @@
+pub mod advancements;
 pub mod assets;
 pub mod blocks;
 pub mod chat;
 pub mod database;
+pub mod dialogue;
+pub mod economy;
 pub mod interfaces;
 pub mod items;
 pub mod models;
 pub mod networking;
 pub mod physics;
 pub mod players;
+pub mod quests;
+pub mod registration;
+pub mod spawners;
 pub mod utils;
 pub mod world;
+pub mod world_events;
 
 pub use fmc_noise as noise;
 pub use fmc_protocol as protocol;
@@ -73,6 +80,7 @@ impl PluginGroup for DefaultPlugins {
             .add(bevy::transform::TransformPlugin)
             .add(assets::AssetPlugin)
             .add(database::DatabasePlugin::default())
+            .add(registration::RegistrationPlugin)
             .add(networking::ServerPlugin)
             .add(world::WorldPlugin)
             .add(blocks::BlockPlugin)
@@ -82,5 +90,10 @@ impl PluginGroup for DefaultPlugins {
             .add(players::PlayersPlugin)
             .add(interfaces::InterfacePlugin)
             .add(chat::ChatPlugin)
+            .add(advancements::AdvancementPlugin)
+            .add(dialogue::DialoguePlugin)
+            .add(quests::QuestPlugin)
+            .add(spawners::SpawnerPlugin)
+            .add(world_events::WorldEventPlugin)
     }
 }
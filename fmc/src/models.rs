@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
 use bevy::{math::DVec3, prelude::*};
 use fmc_protocol::messages;
@@ -7,7 +10,7 @@ use indexmap::IndexMap;
 use crate::{
     bevy_extensions::f64_transform::{GlobalTransform, Transform, TransformSystem},
     database::Database,
-    networking::Server,
+    networking::{properties, ResumedSession, Server},
     physics::{shapes::Aabb, PhysicsSystems, Velocity},
     players::Player,
     utils,
@@ -41,6 +44,11 @@ impl Plugin for ModelPlugin {
                     update_model_transform,
                     // Wait for propagation so GlobalTransform is updated
                     update_visibility.after(TransformSystem::TransformPropagate),
+                    send_dropped_items
+                        .after(update_visibility)
+                        .after(send_models_on_chunk_subscription),
+                    send_player_model_to_owner,
+                    send_changed_ambience.after(update_visibility),
                 ),
             );
     }
@@ -116,6 +124,10 @@ pub(crate) fn load_models(mut commands: Commands, database: Res<Database>) {
 
             config.aabb = Aabb::from_min_max(min.as_dvec3(), max.as_dvec3());
 
+            if let Err(e) = validate_gltf(&path, &gltf) {
+                panic!("Invalid gltf model at: {}\nError: {}", path.display(), e);
+            }
+
             for animation in gltf.document.animations() {
                 if let Some(name) = animation.name() {
                     config
@@ -153,6 +165,126 @@ pub(crate) fn load_models(mut commands: Commands, database: Res<Database>) {
     commands.insert_resource(model_configs);
 }
 
+// Models with more vertices than this are probably not meant for a game and will slow down the
+// clients when there are many of them.
+const MAX_MODEL_VERTICES: usize = 20_000;
+
+// The gltf crate only checks that the file is well formed, this checks the things that would
+// make the clients fail when they load the model, and warns about models that are too heavy.
+fn validate_gltf(path: &Path, gltf: &gltf::Gltf) -> Result<(), String> {
+    let Some(scene) = gltf.document.scenes().next() else {
+        return Err("The model doesn't have a scene.".to_owned());
+    };
+
+    // The clients only spawn the first scene, anything outside of it can't be animated or drawn.
+    let mut scene_nodes = HashSet::new();
+    let mut stack: Vec<gltf::Node> = scene.nodes().collect();
+    while let Some(node) = stack.pop() {
+        if scene_nodes.insert(node.index()) {
+            stack.extend(node.children());
+        }
+    }
+
+    let node_name = |node: &gltf::Node| -> String {
+        node.name()
+            .map(|name| format!("'{}'", name))
+            .unwrap_or(format!("with index {}", node.index()))
+    };
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let file_exists = |uri: &str| uri.starts_with("data:") || directory.join(uri).exists();
+
+    for buffer in gltf.document.buffers() {
+        if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+            if !file_exists(uri) {
+                return Err(format!("The buffer file '{}' is missing.", uri));
+            }
+        }
+    }
+
+    for image in gltf.document.images() {
+        if let gltf::image::Source::Uri { uri, .. } = image.source() {
+            if !file_exists(uri) {
+                return Err(format!("The texture '{}' is missing.", uri));
+            }
+        }
+    }
+
+    let mut vertex_count = 0;
+    for node in gltf.document.nodes() {
+        if !scene_nodes.contains(&node.index()) {
+            continue;
+        }
+
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+
+        for primitive in mesh.primitives() {
+            if let Some(positions) = primitive.get(&gltf::Semantic::Positions) {
+                vertex_count += positions.count();
+            }
+
+            if primitive.get(&gltf::Semantic::Joints(0)).is_some() && node.skin().is_none() {
+                return Err(format!(
+                    "The node {} has a mesh that is bound to bones, but it doesn't have a skin.",
+                    node_name(&node)
+                ));
+            }
+        }
+
+        if let Some(skin) = node.skin() {
+            for joint in skin.joints() {
+                if !scene_nodes.contains(&joint.index()) {
+                    return Err(format!(
+                        "The skin of node {} uses the bone {}, which isn't part of the scene.",
+                        node_name(&node),
+                        node_name(&joint)
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut animation_names = HashSet::new();
+    for animation in gltf.document.animations() {
+        let Some(name) = animation.name() else {
+            warn!(
+                "The animation with index {} in the model at '{}' doesn't have a name, it can only be played by its index.",
+                animation.index(),
+                path.display()
+            );
+            continue;
+        };
+
+        if !animation_names.insert(name) {
+            return Err(format!("There are several animations named '{}'.", name));
+        }
+
+        for channel in animation.channels() {
+            let node = channel.target().node();
+            if !scene_nodes.contains(&node.index()) {
+                return Err(format!(
+                    "The animation '{}' animates the node {}, which isn't part of the scene.",
+                    name,
+                    node_name(&node)
+                ));
+            }
+        }
+    }
+
+    if vertex_count > MAX_MODEL_VERTICES {
+        warn!(
+            "The model at '{}' has {} vertices, models with more than {} can slow down the clients.",
+            path.display(),
+            vertex_count,
+            MAX_MODEL_VERTICES
+        );
+    }
+
+    return Ok(());
+}
+
 // TODO: Setting the default move animation is almost always something you want to do, but only on
 // initial spawn. Maybe introduce a transient component in this bundle that can be removed when
 // added.
@@ -204,6 +336,56 @@ impl Default for ModelVisibility {
     }
 }
 
+/// Marks a model as an item lying on the ground. Clients draw these through a cheaper path than
+/// other models, bob and spin them on their own, and visually merge identical items that lie close
+/// together. The server should not animate or rotate them.
+#[derive(Component)]
+pub struct DroppedItemModel;
+
+/// Cosmetic idling for a model, like a sheep that bleats and grazes now and then. It is sent to
+/// the players once with the model, and they play the animations and sounds at random on their
+/// own, so nothing has to be sent for each time.
+#[derive(Component, Clone, Debug)]
+pub struct ModelAmbience {
+    /// Indices of the animations that are played when the model isn't playing anything else.
+    pub animations: Vec<u32>,
+    /// Sounds that are played from the model, relative to the audio directory.
+    pub sounds: Vec<String>,
+    /// Seconds between each time an animation and a sound are played, picked at random.
+    pub min_interval: f32,
+    pub max_interval: f32,
+    /// How far away the sounds can be heard, they get quieter the further away the player is.
+    pub distance: f32,
+    pub volume: f32,
+}
+
+impl Default for ModelAmbience {
+    fn default() -> Self {
+        Self {
+            animations: Vec::new(),
+            sounds: Vec::new(),
+            min_interval: 5.0,
+            max_interval: 15.0,
+            distance: 16.0,
+            volume: 1.0,
+        }
+    }
+}
+
+impl ModelAmbience {
+    fn to_property(&self, model_entity: Entity) -> properties::ModelAmbience {
+        return properties::ModelAmbience {
+            model_id: model_entity.index(),
+            min_interval: self.min_interval,
+            max_interval: self.max_interval,
+            distance: self.distance,
+            volume: self.volume,
+            animations: self.animations.clone(),
+            sounds: self.sounds.clone(),
+        };
+    }
+}
+
 enum Animation {
     Play(u32),
     StopRepeating(u32),
@@ -238,6 +420,8 @@ impl ModelAnimations {
             self.animation_queue.push(Animation::StopRepeating(prev));
         }
 
+        // Restarts on the next move if it was playing
+        self.playing_move_animation = false;
         self.move_animation = animation_index;
     }
 }
@@ -448,11 +632,17 @@ fn update_visibility(
     net: Res<Server>,
     chunk_subscriptions: Res<ChunkSubscriptions>,
     model_query: Query<
-        (Entity, &Model, &ModelVisibility, &GlobalTransform),
+        (
+            Entity,
+            &Model,
+            &ModelVisibility,
+            &GlobalTransform,
+            Option<&ModelAmbience>,
+        ),
         Or<(Changed<ModelVisibility>, Changed<Model>)>,
     >,
 ) {
-    for (entity, model, visibility, transform) in model_query.iter() {
+    for (entity, model, visibility, transform, ambience) in model_query.iter() {
         let transform = transform.compute_transform();
 
         let chunk_pos = utils::world_position_to_chunk_position(transform.translation.as_ivec3());
@@ -509,12 +699,53 @@ fn update_visibility(
                     },
                 ),
             }
+
+            if let Some(ambience) = ambience {
+                let property = ambience.to_property(entity);
+                for player_entity in subs.iter() {
+                    net.send_property(*player_entity, properties::MODEL_AMBIENCE, &property);
+                }
+            }
         } else {
             net.send_many(subs, messages::DeleteModel { id: entity.index() });
         }
     }
 }
 
+// Ambience is sent with the model, this is for when it is changed afterwards.
+fn send_changed_ambience(
+    net: Res<Server>,
+    chunk_subscriptions: Res<ChunkSubscriptions>,
+    model_query: Query<
+        (
+            Entity,
+            Ref<Model>,
+            Ref<ModelVisibility>,
+            &GlobalTransform,
+            &ModelAmbience,
+        ),
+        Changed<ModelAmbience>,
+    >,
+) {
+    for (entity, model, visibility, transform, ambience) in model_query.iter() {
+        // Already sent with the model
+        if !visibility.is_visible || visibility.is_changed() || model.is_changed() {
+            continue;
+        }
+
+        let chunk_position =
+            utils::world_position_to_chunk_position(transform.translation().floor().as_ivec3());
+        let Some(subs) = chunk_subscriptions.get_subscribers(&chunk_position) else {
+            continue;
+        };
+
+        let property = ambience.to_property(entity);
+        for player_entity in subs.iter() {
+            net.send_property(*player_entity, properties::MODEL_AMBIENCE, &property);
+        }
+    }
+}
+
 fn send_models_on_chunk_subscription(
     net: Res<Server>,
     model_map: Res<ModelMap>,
@@ -525,13 +756,14 @@ fn send_models_on_chunk_subscription(
         &ModelAnimations,
         &GlobalTransform,
         &ModelVisibility,
+        Option<&ModelAmbience>,
     )>,
     mut chunk_sub_events: EventReader<ChunkSubscriptionEvent>,
 ) {
     for chunk_sub in chunk_sub_events.read() {
         if let Some(model_entities) = model_map.get_entities(&chunk_sub.chunk_position) {
             for entity in model_entities.iter() {
-                let Ok((maybe_player_parent, model, animations, transform, visibility)) =
+                let Ok((maybe_player_parent, model, animations, transform, visibility, ambience)) =
                     model_query.get(*entity)
                 else {
                     continue;
@@ -598,6 +830,14 @@ fn send_models_on_chunk_subscription(
                     ),
                 }
 
+                if let Some(ambience) = ambience {
+                    net.send_property(
+                        chunk_sub.player_entity,
+                        properties::MODEL_AMBIENCE,
+                        &ambience.to_property(*entity),
+                    );
+                }
+
                 if animations.playing_move_animation {
                     let animation_index = animations.move_animation.unwrap();
                     net.send_one(
@@ -625,6 +865,46 @@ fn send_models_on_chunk_subscription(
     }
 }
 
+// Players aren't sent their own model with the other models, see
+// send_models_on_chunk_subscription. It is sent as the "player_model" property instead, so the
+// client can show it in third person. It is null when the player has no model.
+fn send_player_model_to_owner(
+    net: Res<Server>,
+    player_query: Query<(), With<Player>>,
+    resumed_query: Query<(Entity, &Children), (With<Player>, Added<ResumedSession>)>,
+    model_query: Query<(&Model, &ModelAnimations, &ModelVisibility)>,
+    changed_query: Query<(Entity, &Parent), Or<(Changed<Model>, Changed<ModelVisibility>)>>,
+) {
+    let send = |player_entity: Entity, model_entity: Entity| {
+        let Ok((model, animations, visibility)) = model_query.get(model_entity) else {
+            return;
+        };
+
+        let value = match model {
+            Model::Asset(model_id) if visibility.is_visible => Some(properties::PlayerModel {
+                model_id: *model_id,
+                move_animation: animations.move_animation,
+            }),
+            _ => None,
+        };
+
+        net.send_property(player_entity, properties::PLAYER_MODEL, &value);
+    };
+
+    for (model_entity, parent) in changed_query.iter() {
+        if player_query.contains(parent.get()) {
+            send(parent.get(), model_entity);
+        }
+    }
+
+    // A resumed session has a new client that hasn't been told of its model.
+    for (player_entity, children) in resumed_query.iter() {
+        for child in children.iter() {
+            send(player_entity, *child);
+        }
+    }
+}
+
 fn send_animations(
     net: Res<Server>,
     chunk_subscriptions: Res<ChunkSubscriptions>,
@@ -672,3 +952,52 @@ fn send_animations(
         }
     }
 }
+
+// Clients are told which models are dropped items with the "dropped_item" property, its value is
+// the model id. It is sent every time the model is sent.
+fn send_dropped_items(
+    net: Res<Server>,
+    chunk_subscriptions: Res<ChunkSubscriptions>,
+    dropped_item_query: Query<(
+        Entity,
+        Ref<Model>,
+        Ref<ModelVisibility>,
+        &GlobalTransform,
+        Ref<DroppedItemModel>,
+    )>,
+    mut chunk_sub_events: EventReader<ChunkSubscriptionEvent>,
+) {
+    for (entity, model, visibility, transform, marker) in dropped_item_query.iter() {
+        if !visibility.is_visible
+            || !(marker.is_added() || model.is_changed() || visibility.is_changed())
+        {
+            continue;
+        }
+
+        let chunk_position =
+            utils::world_position_to_chunk_position(transform.translation().as_ivec3());
+        let Some(subscribers) = chunk_subscriptions.get_subscribers(&chunk_position) else {
+            continue;
+        };
+
+        for player_entity in subscribers.iter() {
+            net.send_property(*player_entity, properties::DROPPED_ITEM, &entity.index());
+        }
+    }
+
+    for chunk_sub in chunk_sub_events.read() {
+        for (entity, _, visibility, transform, _) in dropped_item_query.iter() {
+            let chunk_position =
+                utils::world_position_to_chunk_position(transform.translation().as_ivec3());
+            if !visibility.is_visible || chunk_position != chunk_sub.chunk_position {
+                continue;
+            }
+
+            net.send_property(
+                chunk_sub.player_entity,
+                properties::DROPPED_ITEM,
+                &entity.index(),
+            );
+        }
+    }
+}
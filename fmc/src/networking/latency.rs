@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use crate::{
+    networking::{properties, ClientProperty, NetworkEvent, NetworkMessage, Server},
+    players::Player,
+    prelude::*,
+};
+
+// Seconds between each time the server measures the round trip time to the players
+const PING_INTERVAL: f64 = 2.0;
+
+// TODO: The protocol has no messages for measuring latency, until it does they are sent as
+// properties. Both sides send "ping" with the time on their own clock in seconds, and the other
+// side answers right away with "pong", the time from the ping and the time on its own clock.
+// The server's clock is the time since it started, the clients use the answers to estimate it.
+pub(super) struct LatencyPlugin;
+impl Plugin for LatencyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Latencies>().add_systems(
+            Update,
+            (handle_latency_properties, send_pings, remove_latencies),
+        );
+    }
+}
+
+/// The round trip time to each player
+#[derive(Resource, Default)]
+pub struct Latencies(HashMap<Entity, Latency>);
+
+impl Latencies {
+    /// None until the player has answered its first ping
+    pub fn get(&self, player_entity: Entity) -> Option<&Latency> {
+        return self.0.get(&player_entity);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Latency {
+    /// Smoothed round trip time in seconds
+    pub rtt: f64,
+    /// How much the round trip time varies, in seconds
+    pub jitter: f64,
+}
+
+impl Latency {
+    fn new(rtt: f64) -> Self {
+        Self {
+            rtt,
+            jitter: rtt / 2.0,
+        }
+    }
+
+    // Same smoothing as TCP uses for its retransmission timer, RFC 6298.
+    fn add_sample(&mut self, rtt: f64) {
+        self.jitter = 0.75 * self.jitter + 0.25 * (self.rtt - rtt).abs();
+        self.rtt = 0.875 * self.rtt + 0.125 * rtt;
+    }
+}
+
+fn handle_latency_properties(
+    net: Res<Server>,
+    time: Res<Time<Real>>,
+    mut latencies: ResMut<Latencies>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    for property in property_events.read() {
+        match property.name.as_str() {
+            properties::PING => {
+                let Some(sent) = property.parse::<f64>() else {
+                    continue;
+                };
+
+                net.send_property(
+                    property.player_entity,
+                    properties::PONG,
+                    &properties::Pong {
+                        sent,
+                        received: now,
+                    },
+                );
+            }
+            properties::PONG => {
+                let Some(properties::Pong { sent, .. }) = property.parse() else {
+                    continue;
+                };
+
+                // Answers to pings the server didn't send are ignored
+                let rtt = now - sent;
+                if !(0.0..=PING_INTERVAL * 10.0).contains(&rtt) {
+                    continue;
+                }
+
+                latencies
+                    .0
+                    .entry(property.player_entity)
+                    .and_modify(|latency| latency.add_sample(rtt))
+                    .or_insert(Latency::new(rtt));
+            }
+            _ => (),
+        }
+    }
+}
+
+fn send_pings(
+    net: Res<Server>,
+    time: Res<Time<Real>>,
+    player_query: Query<Entity, With<Player>>,
+    mut last_ping: Local<f64>,
+) {
+    let now = time.elapsed_secs_f64();
+    if now - *last_ping < PING_INTERVAL {
+        return;
+    }
+    *last_ping = now;
+
+    for player_entity in player_query.iter() {
+        net.send_property(player_entity, properties::PING, &now);
+    }
+}
+
+fn remove_latencies(
+    mut latencies: ResMut<Latencies>,
+    mut network_events: EventReader<NetworkEvent>,
+) {
+    for network_event in network_events.read() {
+        if let NetworkEvent::Disconnected { entity } = network_event {
+            latencies.0.remove(entity);
+        }
+    }
+}
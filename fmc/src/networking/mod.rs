@@ -0,0 +1,1461 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    ops::{Range, RangeFrom, RangeTo},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bevy::{
+    ecs::system::SystemParam,
+    tasks::{futures_lite::future, Task},
+    utils::syncunsafecell::SyncUnsafeCell,
+};
+use concurrent_queue::ConcurrentQueue;
+use fmc_protocol::{messages, ClientBound, MessageType};
+use serde::Serialize;
+
+use crate::{
+    assets::{AssetDownloadRequest, Assets},
+    blocks::Blocks,
+    items::Items,
+    models::Models,
+    players::{DefaultPlayerBundle, Player},
+    prelude::*,
+    world::RenderDistance,
+};
+
+mod latency;
+pub mod properties;
+
+pub use latency::{Latencies, Latency};
+
+// Size of each connection's read/write buffer
+const MESSAGE_BUFFER_SIZE: usize = 1024 * 1024;
+// MessageType (1 byte) + message length (4 bytes)
+const HEADER_SIZE: usize = 5;
+// Most asset data written to a connection each tick, so that a large download doesn't hold up the
+// tick, or starve the other connections.
+const ASSET_UPLOAD_RATE: usize = 1024 * 1024;
+
+pub struct ServerPlugin;
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(latency::LatencyPlugin)
+            .add_systems(Startup, server_setup)
+            .init_resource::<Authentication>()
+            .init_resource::<NetworkSettings>()
+            .add_event::<NetworkEvent>()
+            .add_event::<NetworkMessage<messages::LeftClick>>()
+            .add_event::<NetworkMessage<messages::RightClick>>()
+            .add_event::<NetworkMessage<messages::RenderDistance>>()
+            .add_event::<NetworkMessage<messages::PlayerCameraRotation>>()
+            .add_event::<NetworkMessage<messages::PlayerPosition>>()
+            .add_event::<NetworkMessage<messages::InterfaceEquipItem>>()
+            .add_event::<NetworkMessage<messages::InterfaceInteraction>>()
+            .add_event::<NetworkMessage<messages::InterfaceTextInput>>()
+            .add_event::<NetworkMessage<ClientProperty>>()
+            .add_event::<TransferPlayer>()
+            .add_systems(First, read_messages)
+            .add_systems(PostUpdate, transfer_players)
+            .add_systems(
+                PreUpdate,
+                (
+                    // XXX: Remember that new connnections should always be added after messages are
+                    // read so that the server has one tick to register components to the player it
+                    // needs to handle messages.
+                    handle_new_connections,
+                    log_connections,
+                ),
+            )
+            .add_systems(
+                Last,
+                (
+                    // Chained for these properties:
+                    // 1. Player entities are removed a tick after they are disconnected. Lets you
+                    //    save player data.
+                    // 2. Disconnecting before sending so accumulated buffers are ignored. Not
+                    //    really important, but saves some execution time.
+                    // 3. Unless the client should get what was queued, e.g. the reason it was
+                    //    disconnected, then it is disconnected after sending.
+                    remove_disconnected_player_entities,
+                    disconnect_players,
+                    release_held_players,
+                    send_messages,
+                    disconnect_players_after_send,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Insert before adding the `ServerPlugin` to change the defaults.
+#[derive(Resource)]
+pub struct NetworkSettings {
+    /// Expect all connections to start with a PROXY protocol v2 header. Enable when the server is
+    /// behind a proxy that sends it, e.g. HAProxy with 'send-proxy-v2'. The address of the
+    /// connection will then be the address of the client instead of the proxy.
+    ///
+    /// Connections that don't send the header will be refused, so only enable it when the server
+    /// can't be reached without going through the proxy.
+    pub proxy_protocol: bool,
+    /// How long a player is kept in the world after its connection is lost, so that it can
+    /// reconnect without leaving the game. Players that quit are removed immediately.
+    pub reconnect_grace: Duration,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            proxy_protocol: false,
+            reconnect_grace: Duration::from_secs(30),
+        }
+    }
+}
+
+fn server_setup(mut commands: Commands) {
+    let socket_address: SocketAddr = "127.0.0.1:42069".parse().unwrap();
+
+    let listener = std::net::TcpListener::bind(socket_address).unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    let server = Server {
+        listener,
+        connections: HashMap::new(),
+        to_disconnect: ConcurrentQueue::unbounded(),
+        lost: ConcurrentQueue::unbounded(),
+        to_disconnect_after_send: ConcurrentQueue::unbounded(),
+        compression_buffer: vec![0; MESSAGE_BUFFER_SIZE],
+        safe: AtomicBool::new(false),
+    };
+
+    commands.insert_resource(server);
+
+    info!("Started listening for new connections!");
+}
+
+#[derive(Resource)]
+pub struct Server {
+    listener: std::net::TcpListener,
+    connections: HashMap<Entity, Connection>,
+    // TODO: Rust's mpsc Receiver is !sync, but there's an rfc for
+    // mpmc's(https://github.com/rust-lang/rust/pull/126839) when available this can be replaced
+    // and the dependency removed.
+    to_disconnect: ConcurrentQueue<Entity>,
+    // Connections that failed, as opposed to being closed by the client.
+    lost: ConcurrentQueue<Entity>,
+    // Disconnected after the messages queued for them have been sent.
+    to_disconnect_after_send: ConcurrentQueue<Entity>,
+    compression_buffer: Vec<u8>,
+    safe: AtomicBool,
+}
+
+impl Server {
+    /// Send a message to one client
+    #[track_caller]
+    pub fn send_one<T: ClientBound + Serialize>(&self, connection_entity: Entity, message: T) {
+        let Some(connection) = self.connections.get(&connection_entity) else {
+            return;
+        };
+
+        if self.safe.load(Ordering::Relaxed) != true {
+            panic!();
+        }
+
+        if connection.write_message(&message).is_err() {
+            error!(
+                "Failed to send message, the player's message buffer is at capacity. Server is \
+                sending too much, or the connection is too slow. Disconnecting to prevent the \
+                client from being left in an unsynchronised state."
+            );
+            self.disconnect(connection_entity);
+        };
+    }
+
+    /// Send a message to many clients
+    #[track_caller]
+    pub fn send_many<'a, T: ClientBound + Serialize>(
+        &self,
+        connection_entities: impl IntoIterator<Item = &'a Entity>,
+        message: T,
+    ) {
+        let mut connection_entities = connection_entities.into_iter();
+
+        if self.safe.load(Ordering::Relaxed) != true {
+            panic!();
+        }
+
+        // We serialize and write the message to the first connection, then just copy all the bytes
+        // to the other connections.
+        while let Some(connection_entity) = connection_entities.next() {
+            let Some(first_connection) = self.connections.get(connection_entity) else {
+                continue;
+            };
+
+            if let Ok((start, end)) = first_connection.write_message(&message) {
+                let size = end - start;
+
+                while let Some(entity) = connection_entities.next() {
+                    // Players that are awaiting reconnection have no connection
+                    let Some(connection) = self.connections.get(entity) else {
+                        continue;
+                    };
+
+                    let cursor = connection.write_cursor.fetch_add(size, Ordering::Relaxed);
+
+                    if cursor + size > connection.message_buffer.len() {
+                        error!("Failed to send message, the player's message buffer is at capacity. Disconnecting \
+                            to prevent the client from being left in an unsynchronised state.");
+                        self.disconnect(*entity);
+                        continue;
+                    }
+
+                    connection
+                        .message_buffer
+                        .range(cursor..cursor + size)
+                        .copy_from_slice(&first_connection.message_buffer.range(start..end));
+                }
+
+                return;
+            } else {
+                error!("Failed to send message, the player's message buffer is at capacity. Disconnecting \
+                    to prevent the client from being left in an unsynchronised state.");
+                self.disconnect(*connection_entity);
+            };
+        }
+    }
+
+    #[track_caller]
+    pub fn broadcast<'a, T: ClientBound + Serialize>(&self, message: T) {
+        if self.safe.load(Ordering::Relaxed) != true {
+            panic!();
+        }
+        self.send_many(self.connections.keys(), message);
+    }
+
+    pub fn disconnect(&self, connection_entity: Entity) {
+        self.to_disconnect.push(connection_entity).unwrap();
+    }
+
+    /// Disconnect the client and show it the reason. The messages that were queued for it
+    /// during the tick are sent before the reason, the connection is closed after them.
+    pub fn disconnect_with_message(&self, connection_entity: Entity, message: String) {
+        self.send_one(connection_entity, messages::Disconnect { message });
+        self.disconnect_after_send(connection_entity);
+    }
+
+    // Disconnect at the end of the tick, when the queued messages have been sent.
+    fn disconnect_after_send(&self, connection_entity: Entity) {
+        self.to_disconnect_after_send
+            .push(connection_entity)
+            .unwrap();
+    }
+
+    /// Set a property of the client's player, see `properties` for the names and their values.
+    /// Clients that did not say they understand properties when they connected are not sent them.
+    pub fn send_property<T: Serialize + ?Sized>(
+        &self,
+        connection_entity: Entity,
+        name: &str,
+        value: &T,
+    ) {
+        if !self.supports_properties(connection_entity) {
+            return;
+        }
+
+        self.send_one(
+            connection_entity,
+            messages::InterfaceTextUpdate {
+                interface_path: PROPERTY_PREFIX.to_owned() + name,
+                index: 0,
+                text: serde_json::to_string(value).unwrap(),
+                font_size: 0.0,
+                color: String::new(),
+            },
+        );
+    }
+
+    /// If the client said it understands properties when it connected, see `PROPERTY_PREFIX`.
+    pub fn supports_properties(&self, connection_entity: Entity) -> bool {
+        return self
+            .connections
+            .get(&connection_entity)
+            .is_some_and(|connection| connection.properties);
+    }
+
+    /// The address of the client, None if it is not connected.
+    pub fn address(&self, connection_entity: Entity) -> Option<SocketAddr> {
+        return self
+            .connections
+            .get(&connection_entity)
+            .map(|connection| connection.address);
+    }
+}
+
+// TODO: I'm undetermined whether this was a good idea.
+// Pros:
+//  1. Small allocation size per connection
+//  2. Single syscall even though there are many messages sent
+//  3. Collecting many messages together probably gives better compression
+// Cons:
+//  1. Very easy to mess up, someone who doesn't know the system might end up writing when it is
+//     illegal. Could alleviate with an AtomicBool to signal, but that starts to look dumb.
+//  2. Complexity
+//  3. Messages won't be sent until the tick is over, latency
+//
+// To save memory each connection gets only one buffer allocation for read/writes.
+// Since it is shared, we need to be careful of when we read and write as they cannot be allowed to
+// happen at the same time. Reads will therefore only happen in the First schedule, you are able to
+// write to the buffer in PreUpdate, Update, and PostUpdate, and messages are sent to the server in
+// Last. This is not enforced in any way, if you write to the buffer in Last you will corrupt the
+// buffer.
+//
+// 1. The buffer is only used to read from the socket in the First schedule
+// 2. The buffer is only written to in the PreUpdate, Update or PostUpdate schedule. An AtomicUsize
+//    is used to track the spans that can be written to.
+// 3. The messages are written to socket in the PostUpdate schedule
+struct MessageBuffer(SyncUnsafeCell<Vec<u8>>);
+
+impl MessageBuffer {
+    fn new() -> Self {
+        Self(SyncUnsafeCell::new(vec![0; MESSAGE_BUFFER_SIZE]))
+    }
+
+    fn len(&self) -> usize {
+        unsafe { (*(self.0.get())).len() }
+    }
+
+    // TODO: This could be implemented with SliceIndex I think, but it requires an unstable flag
+    // and I somehwat prefer having all the methods, makes you aware you are doing something
+    // dangerous.
+    fn index(&self, index: usize) -> &mut u8 {
+        unsafe { &mut (*self.0.get())[index] }
+    }
+
+    fn range(&self, index: Range<usize>) -> &mut [u8] {
+        unsafe { &mut (*self.0.get())[index] }
+    }
+
+    fn range_to(&self, index: RangeTo<usize>) -> &mut [u8] {
+        unsafe { &mut (*self.0.get())[index] }
+    }
+
+    fn range_from(&self, index: RangeFrom<usize>) -> &mut [u8] {
+        unsafe { &mut (*self.0.get())[index] }
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    address: SocketAddr,
+    // If the client understands properties, see `PROPERTY_PREFIX`
+    properties: bool,
+    // The client said it is leaving, when the connection closes it was not lost.
+    quit: bool,
+    message_buffer: MessageBuffer,
+    read_cursor: usize,
+    read_bytes: usize,
+    write_cursor: AtomicUsize,
+    // (length, data) of a partially received message. When the message buffer is used for writing,
+    // partially read messages are stored here, and then moved back into the buffer when it's time
+    // to read again.
+    partially_read_message: (usize, [u8; HEADER_SIZE + 1024]),
+}
+
+impl Connection {
+    fn new(socket: TcpStream, address: SocketAddr) -> Self {
+        Self {
+            socket,
+            address,
+            properties: false,
+            quit: false,
+            message_buffer: MessageBuffer::new(),
+            read_cursor: 0,
+            read_bytes: 0,
+            write_cursor: AtomicUsize::new(0),
+            partially_read_message: (0, [0; HEADER_SIZE + 1024]),
+        }
+    }
+
+    fn read_from_socket(&mut self) -> std::io::Result<usize> {
+        self.load_partial_message();
+        match self
+            .socket
+            .read(&mut self.message_buffer.range_from(self.read_bytes..))
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(0),
+            // Reading nothing when there is room in the buffer means the client closed the
+            // connection.
+            Ok(0) if self.read_bytes < self.message_buffer.len() => {
+                Err(std::io::ErrorKind::UnexpectedEof.into())
+            }
+            Ok(size) => {
+                self.read_bytes += size;
+                Ok(size)
+            }
+            e => e,
+        }
+    }
+
+    fn write_message<T: ClientBound + Serialize>(&self, message: &T) -> Result<(usize, usize), ()> {
+        let size = bincode::serialized_size(message).unwrap() as usize;
+        let mut cursor = self
+            .write_cursor
+            .fetch_add(size + HEADER_SIZE, Ordering::Relaxed);
+
+        if cursor + size + HEADER_SIZE > self.message_buffer.len() {
+            return Err(());
+        }
+
+        *self.message_buffer.index(cursor) = T::TYPE as u8;
+        cursor += 1;
+
+        self.message_buffer
+            .range(cursor..cursor + 4)
+            .copy_from_slice(&(size as u32).to_le_bytes());
+        cursor += 4;
+
+        bincode::serialize_into(
+            &mut self.message_buffer.range(cursor..cursor + size),
+            message,
+        )
+        .unwrap();
+
+        Ok((cursor - 5, cursor + size))
+    }
+
+    fn next_message(&mut self) -> Option<(MessageType, &[u8])> {
+        // XXX: Only less than, messages can be zero length
+        if self.read_bytes - self.read_cursor < HEADER_SIZE {
+            self.save_partial_message();
+            return None;
+        }
+
+        let message_type = self.message_buffer.index(self.read_cursor);
+        if *message_type >= MessageType::MAX as u8 {
+            // Invalid message type, return invalid message so it disconnects.
+            return Some((MessageType::MAX, &[]));
+        }
+        let message_type: MessageType = unsafe { std::mem::transmute(*message_type) };
+
+        let message_length = u32::from_le_bytes(
+            self.message_buffer
+                .range(self.read_cursor + 1..self.read_cursor + 5)
+                .try_into()
+                .unwrap(),
+        ) as usize;
+
+        if message_length > self.partially_read_message.1.len() - HEADER_SIZE {
+            // TODO: Would be useful for development to tell the client that it messed up.
+            //
+            // Messages larger than the partial buffer will cause corruption of the stream, so we
+            // return an invalid message that will fail to deserialize so that the client is
+            // disconnected.
+            return Some((message_type, &[]));
+        } else if self.read_bytes - self.read_cursor + HEADER_SIZE < message_length {
+            self.save_partial_message();
+            return None;
+        }
+
+        self.read_cursor += HEADER_SIZE;
+
+        let message = self
+            .message_buffer
+            .range(self.read_cursor..self.read_cursor + message_length);
+        self.read_cursor += message_length;
+        if self.read_cursor > self.read_bytes {
+            panic!();
+        }
+
+        return Some((message_type, message));
+    }
+
+    fn save_partial_message(&mut self) {
+        let remaining = &self.message_buffer.range(self.read_cursor..self.read_bytes);
+        let length = remaining.len();
+        self.partially_read_message.0 = length;
+        self.partially_read_message.1[..length].copy_from_slice(remaining);
+    }
+
+    fn load_partial_message(&mut self) {
+        let length = self.partially_read_message.0;
+        self.message_buffer
+            .range_to(..length)
+            .copy_from_slice(&self.partially_read_message.1[..length]);
+        self.read_cursor = 0;
+        self.read_bytes = length;
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.socket.shutdown(std::net::Shutdown::Both).ok();
+    }
+}
+
+// This represents a connection to a client that isn't ready to play.
+// While the connection has not been authorized(username) it is considered illegitimate and will be
+// disconnected if it does not provide one quickly. After it has been authorized, the client may
+// request the game assets, and is given time to load them. When it sends a 'ClientReady'
+// message, the connection is moved to the established connections.
+struct UninitializedConnection {
+    // Set until the PROXY protocol header has been read
+    awaiting_proxy_header: bool,
+    username: Option<String>,
+    account_id: Option<String>,
+    asset_upload: Option<AssetUpload>,
+    // Set when the client is ready to play, but the player's previous session has yet to be
+    // released.
+    ready: bool,
+    connection: Option<Connection>,
+}
+
+impl UninitializedConnection {
+    fn new(socket: TcpStream, address: SocketAddr, proxy_protocol: bool) -> Self {
+        Self {
+            awaiting_proxy_header: proxy_protocol,
+            username: None,
+            account_id: None,
+            asset_upload: None,
+            ready: false,
+            connection: Some(Connection::new(socket, address)),
+        }
+    }
+}
+
+// One of the responses to an asset request, it is written straight to the socket, prefixed by its
+// length. The client knows how far along it is from the length.
+struct AssetUpload {
+    // Archives of only some of the files are built when they're requested
+    preparing: Option<Task<Vec<u8>>>,
+    data: Arc<Vec<u8>>,
+    // How much has been written, including the 4 bytes of the length
+    sent: usize,
+}
+
+impl AssetUpload {
+    fn new(data: Arc<Vec<u8>>) -> Self {
+        Self {
+            preparing: None,
+            data,
+            sent: 0,
+        }
+    }
+
+    // Writes as much as the socket will take, up to the upload rate. Returns true when done.
+    fn write(&mut self, socket: &mut TcpStream) -> std::io::Result<bool> {
+        if let Some(task) = self.preparing.as_mut() {
+            let Some(data) = future::block_on(future::poll_once(task)) else {
+                return Ok(false);
+            };
+            self.data = Arc::new(data);
+            self.preparing = None;
+        }
+
+        let length = (self.data.len() as u32).to_le_bytes();
+        let total = length.len() + self.data.len();
+        let limit = (self.sent + ASSET_UPLOAD_RATE).min(total);
+
+        while self.sent < limit {
+            let remaining = if self.sent < length.len() {
+                &length[self.sent..]
+            } else {
+                &self.data[self.sent - length.len()..limit - length.len()]
+            };
+
+            match socket.write(remaining) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(written) => self.sent += written,
+                // The client hasn't caught up, the rest is written on the next tick.
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        return Ok(self.sent == total);
+    }
+}
+
+/// Verifies the join tokens clients present when they connect.
+///
+/// Clients never send their session token to servers. Before joining, they ask the account
+/// service for a join token for the server they are connecting to, identified by its server id.
+/// A server that is given a join token can not use it to join any other server as the player.
+pub trait SessionVerifier: Send + Sync {
+    /// Returns the id of the account the token belongs to, or the reason it was rejected. The
+    /// username is the name the client claims to have, it is not guaranteed to belong to the
+    /// account.
+    ///
+    /// The token must only be accepted if it was issued for `server_id`, and only once.
+    ///
+    /// This is run on the main thread, it should not block for long. Tokens that are signed by
+    /// the account service can be verified locally, without contacting it.
+    fn verify(&self, username: &str, token: &str, server_id: &str) -> Result<String, String>;
+}
+
+/// Decides how connecting players are identified. Insert it before adding the `ServerPlugin` to
+/// change it from the default offline mode.
+#[derive(Resource, Clone)]
+pub struct Authentication {
+    // Tokens are only valid if they were issued for this
+    server_id: String,
+    verifier: Option<Arc<dyn SessionVerifier>>,
+}
+
+impl Default for Authentication {
+    fn default() -> Self {
+        Self::offline()
+    }
+}
+
+impl Authentication {
+    /// Players are whoever they claim to be. The username is used as the account id.
+    pub fn offline() -> Self {
+        Self {
+            server_id: String::new(),
+            verifier: None,
+        }
+    }
+
+    /// Players must present a join token that is accepted by the verifier.
+    ///
+    /// The server id is the address players connect to, "ip:port", e.g. "203.0.113.5:42069".
+    /// Clients request their join tokens for it.
+    pub fn online(server_id: impl Into<String>, verifier: impl SessionVerifier + 'static) -> Self {
+        Self {
+            server_id: server_id.into(),
+            verifier: Some(Arc::new(verifier)),
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.verifier.is_none()
+    }
+
+    // Returns the username and account id of the player, or the reason they were rejected.
+    fn authenticate(&self, identification: &Identification) -> Result<(String, String), String> {
+        let username = identification.username.as_str();
+        let token = identification.token.as_deref();
+
+        let Some(verifier) = &self.verifier else {
+            return Ok((username.to_owned(), username.to_owned()));
+        };
+
+        let Some(token) = token else {
+            return Err(
+                "This server requires you to be logged in to an account. Log in and try again."
+                    .to_owned(),
+            );
+        };
+
+        let account_id = verifier.verify(username, token, &self.server_id)?;
+        return Ok((username.to_owned(), account_id));
+    }
+}
+
+// TODO: ClientIdentification only has a name. Until the protocol has fields for them, the client
+// appends what else the server needs to know to the name, one field per line as "key=value". A
+// newline is not a valid character in a username, so they can be split off unambiguously.
+//   token=<token>           the join token, see `SessionVerifier`
+//   properties=<version>    the client understands properties, see `PROPERTY_PREFIX`
+// Clients that don't know about this only send the username.
+struct Identification {
+    username: String,
+    token: Option<String>,
+    properties: bool,
+}
+
+impl Identification {
+    // Takes the name by value so that the fields appended to it can't be mistaken for part of the
+    // username further on.
+    fn parse(name: String) -> Result<Self, String> {
+        let mut lines = name.split('\n');
+        let username = lines.next().unwrap_or_default().trim();
+
+        if username.is_empty() || username.chars().any(|c| c.is_control()) {
+            return Err("Invalid username".to_owned());
+        }
+
+        let mut identification = Self {
+            username: username.to_owned(),
+            token: None,
+            properties: false,
+        };
+
+        for line in lines {
+            match line.trim().split_once('=') {
+                Some(("token", token)) if !token.is_empty() => {
+                    identification.token = Some(token.to_owned())
+                }
+                Some(("properties", version)) => {
+                    identification.properties = version == PROPERTY_VERSION
+                }
+                _ => (),
+            }
+        }
+
+        return Ok(identification);
+    }
+}
+
+// TODO: There are no messages for player state like the game mode. Until there are, it is sent
+// as text updates to interface paths starting with this prefix, which the client handles
+// separately from its interfaces. Clients that don't know about them fail on the unknown
+// interface path, so they are only sent to clients that announced they understand them when
+// they connected.
+//
+/// Prefix of interface paths that set a property of the client's player, e.g. if it is allowed
+/// to fly. The rest of the path is the name of the property, and the text its value as json,
+/// see `properties`. Clients send their properties the same way as text input, they are received
+/// as `ClientProperty`.
+pub const PROPERTY_PREFIX: &str = "\u{0}property:";
+
+// Bumped when properties change in ways older clients can't handle, they are then treated like
+// clients that don't understand properties at all.
+const PROPERTY_VERSION: &str = "2";
+
+/// A property of the player sent by the client, e.g. if it is sneaking.
+pub struct ClientProperty {
+    pub name: String,
+    pub value: String,
+}
+
+impl ClientProperty {
+    /// The value as the type the property has, see `properties`. None if the client sent
+    /// something else.
+    pub fn parse<T: serde::de::DeserializeOwned>(&self) -> Option<T> {
+        return serde_json::from_str(&self.value).ok();
+    }
+}
+
+/// Send this to move a player to another server, e.g. from a lobby to a game server. Listen for
+/// it to save any state the other server will need before the player leaves. The player is
+/// disconnected at the end of the tick, and the usual `NetworkEvent::Disconnected` follows.
+///
+/// Clients that understand properties move to the other server on their own, while showing the
+/// player that it is being transferred. The rest are disconnected with a message telling the
+/// player where to go.
+#[derive(Event)]
+pub struct TransferPlayer {
+    pub player_entity: Entity,
+    /// Address of the server the player is sent to, "host:port"
+    pub address: String,
+    /// Presented by the client when it connects to the other server, in place of a join token.
+    /// The other server's `SessionVerifier` must accept it. It is up to the servers to agree on
+    /// what it contains.
+    pub handoff_token: String,
+}
+
+// TODO: There is no message for transfers, they are sent as the "transfer" property until there
+// is one.
+fn transfer_players(net: Res<Server>, mut transfer_events: EventReader<TransferPlayer>) {
+    for transfer in transfer_events.read() {
+        if net.supports_properties(transfer.player_entity) {
+            net.send_property(
+                transfer.player_entity,
+                properties::TRANSFER,
+                &properties::Transfer {
+                    address: transfer.address.clone(),
+                    handoff_token: transfer.handoff_token.clone(),
+                },
+            );
+            net.disconnect_after_send(transfer.player_entity);
+        } else {
+            net.disconnect_with_message(
+                transfer.player_entity,
+                format!(
+                    "This server has moved you to {}, connect to it to continue playing.",
+                    transfer.address
+                ),
+            );
+        }
+    }
+}
+
+enum ProxyHeader {
+    // Not all of the header has arrived yet
+    Incomplete,
+    // The connection was made by the proxy itself, e.g. for health checks
+    Local,
+    // Address of the client the proxy is relaying for
+    Proxied(SocketAddr),
+}
+
+// Reads the PROXY protocol v2 header from the start of a connection, and nothing more.
+// https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+fn read_proxy_header(socket: &mut TcpStream) -> std::io::Result<ProxyHeader> {
+    const SIGNATURE: [u8; 12] = [
+        0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+    ];
+    const HEADER_LENGTH: usize = 16;
+
+    fn invalid(message: &str) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+    }
+
+    // Peek so nothing is consumed before the whole header is available.
+    let mut header = [0; HEADER_LENGTH];
+    let peeked = match socket.peek(&mut header) {
+        Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+        Ok(n) => n,
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(ProxyHeader::Incomplete),
+        Err(e) => return Err(e),
+    };
+
+    if header[..peeked.min(SIGNATURE.len())] != SIGNATURE[..peeked.min(SIGNATURE.len())] {
+        return Err(invalid("wrong signature"));
+    } else if peeked < HEADER_LENGTH {
+        return Ok(ProxyHeader::Incomplete);
+    }
+
+    let version = header[12] >> 4;
+    let command = header[12] & 0x0F;
+    let family = header[13];
+    let address_length = u16::from_be_bytes([header[14], header[15]]) as usize;
+
+    if version != 2 {
+        return Err(invalid("unsupported version"));
+    }
+
+    let mut full_header = vec![0; HEADER_LENGTH + address_length];
+    match socket.peek(&mut full_header) {
+        Ok(n) if n < full_header.len() => return Ok(ProxyHeader::Incomplete),
+        Ok(_) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(ProxyHeader::Incomplete),
+        Err(e) => return Err(e),
+    }
+    socket.read_exact(&mut full_header)?;
+
+    let addresses = &full_header[HEADER_LENGTH..];
+
+    match command {
+        0x0 => return Ok(ProxyHeader::Local),
+        0x1 => (),
+        _ => return Err(invalid("unknown command")),
+    }
+
+    // Only the source address is of interest, the rest of the block (destination address and
+    // optional extensions) is skipped.
+    match family {
+        // TCP over IPv4
+        0x11 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[0..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            return Ok(ProxyHeader::Proxied(SocketAddr::from((ip, port))));
+        }
+        // TCP over IPv6
+        0x21 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[0..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            return Ok(ProxyHeader::Proxied(SocketAddr::from((ip, port))));
+        }
+        // Unspecified, the receiver should use the real connection endpoint.
+        0x00 => return Ok(ProxyHeader::Local),
+        _ => return Err(invalid("unsupported address family")),
+    }
+}
+
+#[derive(Event)]
+pub enum NetworkEvent {
+    // Provided for symmetry, prefer listening for Added<Player>
+    Connected { entity: Entity },
+    // Signals that player connection has been disconnected. This event is only valid during
+    // PreUpdate of the tick after it is issued. After that the entity will have been despawned.
+    Disconnected { entity: Entity },
+}
+
+/// Inserted on players whose connection was lost. The player stays in the world without a
+/// connection until it reconnects or the grace period in `NetworkSettings` runs out. Messages
+/// sent to it in the meantime are dropped.
+///
+/// When the player reconnects, the new connection is handed to the same entity, it is removed
+/// and `ResumedSession` is inserted.
+#[derive(Component)]
+pub struct AwaitingReconnect {
+    timer: Timer,
+    released: bool,
+}
+
+/// Inserted on players that reconnected while they were `AwaitingReconnect`. The new client
+/// starts out knowing nothing, systems that keep clients up to date should send everything again
+/// when it is added.
+#[derive(Component)]
+pub struct ResumedSession;
+
+// Separate out the server config so the connection code can be clearer
+#[derive(SystemParam)]
+struct ServerConfig<'w> {
+    render_distance: Res<'w, RenderDistance>,
+    assets: Res<'w, Assets>,
+    models: Res<'w, Models>,
+    items: Res<'w, Items>,
+}
+
+impl ServerConfig<'_> {
+    fn to_message(&self) -> Vec<u8> {
+        let server_config = messages::ServerConfig {
+            assets_hash: self.assets.hash,
+            block_ids: Blocks::get().asset_ids(),
+            model_ids: self.models.asset_ids(),
+            item_ids: self.items.asset_ids(),
+            render_distance: self.render_distance.chunks,
+        };
+
+        encode_message(&server_config)
+    }
+}
+
+// Encodes a message so it can be written straight to the socket of a connection that hasn't
+// finished connecting.
+fn encode_message<T: ClientBound + Serialize>(message: &T) -> Vec<u8> {
+    let serialized_size = bincode::serialized_size(message).unwrap() as u32;
+    let mut serialized = Vec::new();
+    serialized.push(T::TYPE as u8);
+    serialized.extend(serialized_size.to_le_bytes());
+    serialized.extend(bincode::serialize(message).unwrap());
+    let compressed = zstd::encode_all(&serialized[..], 5).unwrap();
+    let mut encoded = Vec::from((compressed.len() as u32).to_le_bytes());
+    encoded.extend(compressed);
+
+    encoded
+}
+
+// TODO: Any error will cause disconnection. The player won't know what's wrong.
+fn handle_new_connections(
+    mut commands: Commands,
+    assets: Res<Assets>,
+    authentication: Res<Authentication>,
+    network_settings: Res<NetworkSettings>,
+    player_query: Query<(Entity, &Player, Option<&AwaitingReconnect>)>,
+    server_config: ServerConfig,
+    mut server: ResMut<Server>,
+    mut network_events: EventWriter<NetworkEvent>,
+    mut uninitialized_connections: Local<Vec<UninitializedConnection>>,
+) {
+    while let Ok((tcp_stream, socket_addr)) = server.listener.accept() {
+        // TODO: This can probably panic but I don't know when
+        tcp_stream
+            .set_nodelay(true)
+            .expect("Failed to set no_delay for a tcp connection");
+        tcp_stream
+            .set_nonblocking(true)
+            .expect("Failed setting a tcp connection to non-blocking");
+
+        uninitialized_connections.push(UninitializedConnection::new(
+            tcp_stream,
+            socket_addr,
+            network_settings.proxy_protocol,
+        ));
+    }
+
+    uninitialized_connections.retain_mut(|uninitialized| {
+        if uninitialized.ready {
+            let account_id = uninitialized.account_id.as_ref().unwrap();
+            let mut previous_session = player_query
+                .iter()
+                .filter(|(_, player, _)| &player.account_id == account_id);
+
+            let player_entity = match previous_session.next() {
+                // The player takes back the session it left behind
+                Some((entity, _, Some(awaiting_reconnect))) if !awaiting_reconnect.released => {
+                    commands
+                        .entity(entity)
+                        .remove::<(AwaitingReconnect, ResumedSession)>()
+                        .insert(ResumedSession);
+                    entity
+                }
+                // The previous session is removed at the end of the tick.
+                Some(_) => return true,
+                None => commands
+                    .spawn(DefaultPlayerBundle::new(
+                        uninitialized.username.take().unwrap(),
+                        uninitialized.account_id.take().unwrap(),
+                    ))
+                    .id(),
+            };
+
+            server
+                .connections
+                .insert(player_entity, uninitialized.connection.take().unwrap());
+
+            network_events.send(NetworkEvent::Connected {
+                entity: player_entity,
+            });
+
+            return false;
+        }
+
+        let connection = uninitialized.connection.as_mut().unwrap();
+
+        if uninitialized.awaiting_proxy_header {
+            match read_proxy_header(&mut connection.socket) {
+                Ok(ProxyHeader::Incomplete) => return true,
+                Ok(ProxyHeader::Local) => (),
+                Ok(ProxyHeader::Proxied(address)) => connection.address = address,
+                Err(e) => {
+                    error!(
+                        "Refused connection from {}, it did not send a valid PROXY protocol header: {}",
+                        connection.address, e
+                    );
+                    return false;
+                }
+            }
+            uninitialized.awaiting_proxy_header = false;
+        }
+
+        if connection.read_from_socket().is_err() {
+            return false;
+        }
+
+        if let Some(upload) = uninitialized.asset_upload.as_mut() {
+            match upload.write(&mut connection.socket) {
+                Ok(true) => uninitialized.asset_upload = None,
+                Ok(false) => (),
+                Err(_) => return false,
+            }
+        }
+
+        let Some((message_type, message)) = connection.next_message() else {
+            return true;
+        };
+
+        if uninitialized.username.is_none() {
+            let Ok(identity) = bincode::deserialize::<messages::ClientIdentification>(message)
+            else {
+                return false;
+            };
+
+            let rejection = match Identification::parse(identity.name).and_then(|identification| {
+                connection.properties = identification.properties;
+                authentication.authenticate(&identification)
+            }) {
+                Ok((username, account_id)) => {
+                    // Players that lost their connection can take over the session they left
+                    // behind.
+                    if player_query.iter().any(|(_, player, awaiting_reconnect)| {
+                        player.account_id == account_id && awaiting_reconnect.is_none()
+                    }) {
+                        Some("You are already connected to this server".to_owned())
+                    } else {
+                        uninitialized.username = Some(username);
+                        uninitialized.account_id = Some(account_id);
+                        None
+                    }
+                }
+                Err(reason) => Some(reason),
+            };
+
+            if let Some(reason) = rejection {
+                let disconnect = messages::Disconnect { message: reason };
+                connection.socket.write(&encode_message(&disconnect)).ok();
+                return false;
+            }
+
+            if connection
+                .socket
+                .write(&server_config.to_message())
+                .is_err()
+            {
+                return false;
+            }
+        } else if message_type == MessageType::AssetRequest {
+            // TODO: Need some way to bar clients from sending multiple requests. Some n attempts
+            // per day.
+            let upload = if message.is_empty() {
+                AssetUpload::new(assets.asset_message.clone())
+            } else {
+                match bincode::deserialize::<AssetDownloadRequest>(message) {
+                    Ok(AssetDownloadRequest::Manifest) => {
+                        AssetUpload::new(Arc::new(assets.manifest_message()))
+                    }
+                    Ok(AssetDownloadRequest::Files(paths)) => AssetUpload {
+                        preparing: Some(assets.partial_archive(paths)),
+                        data: Arc::default(),
+                        sent: 0,
+                    },
+                    Err(_) => return false,
+                }
+            };
+            uninitialized.asset_upload = Some(upload);
+        } else if message_type == MessageType::ClientReady {
+            // More messages might have arrived, we'll be able to handle them when the player has
+            // been spawned.
+            connection.save_partial_message();
+
+            let account_id = uninitialized.account_id.as_ref().unwrap();
+            if player_query.iter().any(|(_, player, awaiting_reconnect)| {
+                &player.account_id == account_id && awaiting_reconnect.is_none()
+            }) {
+                // It connected again while its old connection was being set up
+                let disconnect = messages::Disconnect {
+                    message: "You are already connected to this server".to_owned(),
+                };
+                connection.socket.write(&encode_message(&disconnect)).ok();
+                return false;
+            }
+
+            // The player is spawned at the start of the closure, once it is free to.
+            uninitialized.ready = true;
+        } else {
+            return false;
+        }
+
+        return true;
+    });
+}
+
+// This drops the connection, but does not despawn the entity. Despawning is delayed until
+// PreUpdate to give the application time to save the player data.
+fn disconnect_players(
+    mut commands: Commands,
+    network_settings: Res<NetworkSettings>,
+    server: ResMut<Server>,
+    player_query: Query<&Player>,
+    mut held_query: Query<&mut AwaitingReconnect>,
+    mut network_events: EventWriter<NetworkEvent>,
+) {
+    // Can't split borrows when behind a ResMut
+    let server = server.into_inner();
+
+    for connection_entity in server.to_disconnect.try_iter() {
+        if server.connections.remove(&connection_entity).is_some() {
+            network_events.send(NetworkEvent::Disconnected {
+                entity: connection_entity,
+            });
+        } else if let Ok(mut awaiting_reconnect) = held_query.get_mut(connection_entity) {
+            // Kicked while waiting, it is released right away
+            let duration = awaiting_reconnect.timer.duration();
+            awaiting_reconnect.timer.set_elapsed(duration);
+        }
+    }
+
+    for connection_entity in server.lost.try_iter() {
+        if network_settings.reconnect_grace.is_zero() {
+            if server.connections.remove(&connection_entity).is_some() {
+                network_events.send(NetworkEvent::Disconnected {
+                    entity: connection_entity,
+                });
+            }
+        } else if server.connections.remove(&connection_entity).is_some() {
+            if let Ok(player) = player_query.get(connection_entity) {
+                info!(
+                    "Lost connection to {}, keeping the player for {} seconds",
+                    player.username,
+                    network_settings.reconnect_grace.as_secs()
+                );
+            }
+            commands
+                .entity(connection_entity)
+                .insert(AwaitingReconnect {
+                    timer: Timer::new(network_settings.reconnect_grace, TimerMode::Once),
+                    released: false,
+                });
+        }
+    }
+}
+
+fn disconnect_players_after_send(
+    server: ResMut<Server>,
+    mut network_events: EventWriter<NetworkEvent>,
+) {
+    let server = server.into_inner();
+
+    for connection_entity in server.to_disconnect_after_send.try_iter() {
+        if server.connections.remove(&connection_entity).is_some() {
+            network_events.send(NetworkEvent::Disconnected {
+                entity: connection_entity,
+            });
+        }
+    }
+}
+
+// Players that didn't reconnect in time are disconnected.
+fn release_held_players(
+    time: Res<Time>,
+    mut held_query: Query<(Entity, &mut AwaitingReconnect)>,
+    mut network_events: EventWriter<NetworkEvent>,
+) {
+    for (entity, mut awaiting_reconnect) in held_query.iter_mut() {
+        if awaiting_reconnect.released {
+            continue;
+        }
+
+        awaiting_reconnect.timer.tick(time.delta());
+        if awaiting_reconnect.timer.finished() {
+            awaiting_reconnect.released = true;
+            network_events.send(NetworkEvent::Disconnected { entity });
+        }
+    }
+}
+
+fn remove_disconnected_player_entities(
+    mut commands: Commands,
+    mut network_events: EventReader<NetworkEvent>,
+) {
+    for network_event in network_events.read() {
+        if let NetworkEvent::Disconnected { entity } = network_event {
+            commands.entity(*entity).despawn_recursive();
+        }
+    }
+}
+
+fn log_connections(
+    server: Res<Server>,
+    player_query: Query<&Player>,
+    mut network_events: EventReader<NetworkEvent>,
+) {
+    for network_event in network_events.read() {
+        match network_event {
+            NetworkEvent::Connected { entity } => {
+                let player = player_query.get(*entity).unwrap();
+                let connection = server.connections.get(entity).unwrap();
+                info!(
+                    "Player connected, ip: {}, username: {}",
+                    connection.address, &player.username
+                );
+            }
+            NetworkEvent::Disconnected { entity } => {
+                let player = player_query.get(*entity).unwrap();
+                info!("Player disconnected, username: {}", &player.username);
+            }
+        }
+    }
+}
+
+#[derive(Event, Deref)]
+pub struct NetworkMessage<T> {
+    pub player_entity: Entity,
+    #[deref]
+    pub message: T,
+}
+
+// All ServerBound message events that are valid after connection initialization
+#[derive(SystemParam)]
+struct EventWriters<'w> {
+    left_click: EventWriter<'w, NetworkMessage<messages::LeftClick>>,
+    right_click: EventWriter<'w, NetworkMessage<messages::RightClick>>,
+    render_distance: EventWriter<'w, NetworkMessage<messages::RenderDistance>>,
+    player_camera_rotation: EventWriter<'w, NetworkMessage<messages::PlayerCameraRotation>>,
+    player_position: EventWriter<'w, NetworkMessage<messages::PlayerPosition>>,
+    interface_equip_item: EventWriter<'w, NetworkMessage<messages::InterfaceEquipItem>>,
+    interface_interaction: EventWriter<'w, NetworkMessage<messages::InterfaceInteraction>>,
+    interface_text_input: EventWriter<'w, NetworkMessage<messages::InterfaceTextInput>>,
+    client_property: EventWriter<'w, NetworkMessage<ClientProperty>>,
+}
+
+fn read_messages(server: ResMut<Server>, mut event_writers: EventWriters) {
+    let server = server.into_inner();
+    for (entity, connection) in server.connections.iter_mut() {
+        match connection.read_from_socket() {
+            Ok(_) => (),
+            // Clients that understand properties say when they quit, if the connection closes
+            // without it the client crashed or was killed, and might come back.
+            Err(e)
+                if e.kind() == std::io::ErrorKind::UnexpectedEof
+                    && (connection.quit || !connection.properties) =>
+            {
+                server.to_disconnect.push(*entity).unwrap();
+                continue;
+            }
+            Err(_) => {
+                server.lost.push(*entity).unwrap();
+                continue;
+            }
+        }
+
+        if connection.write_cursor.load(Ordering::Relaxed) != 0 {
+            panic!(
+                "This guards against having sent a message in a schedule you shouldn't have.\
+                You can only send messages in Update and PostUpdate"
+            );
+        }
+
+        while let Some((message_type, message_data)) = connection.next_message() {
+            match message_type {
+                MessageType::LeftClick => {
+                    if let Ok(message) = bincode::deserialize(message_data) {
+                        event_writers.left_click.send(NetworkMessage {
+                            player_entity: *entity,
+                            message,
+                        });
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                MessageType::RightClick => {
+                    if let Ok(message) = bincode::deserialize(message_data) {
+                        event_writers.right_click.send(NetworkMessage {
+                            player_entity: *entity,
+                            message,
+                        });
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                MessageType::RenderDistance => {
+                    if let Ok(message) = bincode::deserialize(message_data) {
+                        event_writers.render_distance.send(NetworkMessage {
+                            player_entity: *entity,
+                            message,
+                        });
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                MessageType::PlayerCameraRotation => {
+                    if let Ok(message) = bincode::deserialize(message_data) {
+                        event_writers.player_camera_rotation.send(NetworkMessage {
+                            player_entity: *entity,
+                            message,
+                        });
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                MessageType::PlayerPosition => {
+                    if let Ok(message) = bincode::deserialize(message_data) {
+                        event_writers.player_position.send(NetworkMessage {
+                            player_entity: *entity,
+                            message,
+                        });
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                MessageType::InterfaceEquipItem => {
+                    if let Ok(message) = bincode::deserialize(message_data) {
+                        event_writers.interface_equip_item.send(NetworkMessage {
+                            player_entity: *entity,
+                            message,
+                        });
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                MessageType::InterfaceInteraction => {
+                    if let Ok(message) = bincode::deserialize(message_data) {
+                        event_writers.interface_interaction.send(NetworkMessage {
+                            player_entity: *entity,
+                            message,
+                        });
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                MessageType::InterfaceTextInput => {
+                    if let Ok(message) =
+                        bincode::deserialize::<messages::InterfaceTextInput>(message_data)
+                    {
+                        if message.interface_path.strip_prefix(PROPERTY_PREFIX)
+                            == Some(properties::QUIT)
+                        {
+                            connection.quit = true;
+                        } else if let Some(name) =
+                            message.interface_path.strip_prefix(PROPERTY_PREFIX)
+                        {
+                            event_writers.client_property.send(NetworkMessage {
+                                player_entity: *entity,
+                                message: ClientProperty {
+                                    name: name.to_owned(),
+                                    value: message.text,
+                                },
+                            });
+                        } else {
+                            event_writers.interface_text_input.send(NetworkMessage {
+                                player_entity: *entity,
+                                message,
+                            });
+                        }
+                    } else {
+                        server.to_disconnect.push(*entity).unwrap();
+                        error!("Received {:?} from {}, but the message could not be deserialized, disconnecting client.",
+                            message_type, connection.address);
+                        break;
+                    }
+                }
+                _ => {
+                    server.to_disconnect.push(*entity).unwrap();
+                    error!(
+                        "Received invalid message type {:?} from {}, disconnecting client.",
+                        message_type, connection.address
+                    );
+                    break;
+                }
+            }
+        }
+    }
+    server.safe.store(true, Ordering::Relaxed);
+}
+
+fn send_messages(server: ResMut<Server>) {
+    let server = server.into_inner();
+    for (entity, connection) in server.connections.iter_mut() {
+        // Encode what has been written to the message buffer
+        let len = connection.write_cursor.swap(0, Ordering::Relaxed);
+
+        if len == 0 {
+            continue;
+        }
+
+        // Save first 4 bytes to store the size of the compressed bytes
+        let mut encoder = zstd::Encoder::new(&mut server.compression_buffer[4..], 5).unwrap();
+
+        encoder.set_pledged_src_size(Some(len as u64)).unwrap();
+        std::io::copy(
+            &mut connection.message_buffer.range_to(..len).as_ref(),
+            &mut encoder,
+        )
+        .unwrap();
+
+        // Use how much was left of the compression_buffer slice to determine length of what was
+        // written.
+        let remaining = encoder.finish().unwrap().len();
+        let encoded_len = (server.compression_buffer.len() - remaining - 4) as u32;
+        server.compression_buffer[..4].copy_from_slice(&encoded_len.to_le_bytes());
+
+        match connection
+            .socket
+            .write(&server.compression_buffer[..4 + encoded_len as usize])
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                // The kernel buffer is full, probably because of a slow connection. The buffer can
+                // hold a couple of megabytes so it will optimistically never occur, but if it does the
+                // client has to be disconnected as continuing would cause loss of data when the
+                // message buffer is rotated.
+                error!("Connection to player too slow, write buffer at capacity, disconnecting player.");
+                server.to_disconnect.push(*entity).unwrap();
+            }
+            Err(e) => {
+                error!("Encountered error while sending messages to player: {}", e);
+                server.lost.push(*entity).unwrap();
+            }
+            Ok(_) => (),
+        }
+    }
+    server.safe.store(false, Ordering::Relaxed);
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use bevy::math::DVec3;
+
+use crate::{
+    models::Model,
+    networking::{properties, ResumedSession, Server},
+    physics::{Mass, Velocity},
+    players::Player,
+    prelude::*,
+    utils,
+    world::{
+        ChunkSubscriptionEvent, ChunkSubscriptions, SimulationBudget, SimulationBudgetSettings,
+    },
+};
+
+// Velocity added per second for each block a leash is stretched past its length
+const LEASH_STIFFNESS: f64 = 20.0;
+// Followers slow down when they are this many seconds from where they should stop
+const FOLLOW_BRAKING_TIME: f64 = 0.25;
+
+/// Ties a physics enabled entity to a holder. It is pulled towards the holder when it gets
+/// further away than the length of the leash. Clients draw a line between the two.
+#[derive(Component)]
+pub struct Leash {
+    pub holder: Entity,
+    pub length: f64,
+    /// The leash snaps if the entity gets this far away from the holder.
+    pub break_distance: f64,
+}
+
+impl Leash {
+    pub fn new(holder: Entity, length: f64) -> Self {
+        Self {
+            holder,
+            length,
+            break_distance: length * 2.0 + 4.0,
+        }
+    }
+}
+
+/// Sent when a leash snaps, or its holder is despawned. The leash has already been removed.
+#[derive(Event)]
+pub struct LeashBroken {
+    pub entity: Entity,
+    pub holder: Entity,
+}
+
+/// Makes a physics enabled entity walk towards a target until it is `distance` away from it.
+/// Removed if the target is despawned.
+#[derive(Component)]
+pub struct Follow {
+    pub target: Entity,
+    pub distance: f64,
+    /// Blocks per second
+    pub speed: f64,
+}
+
+/// The horizontal velocity that moves something at `position` towards `target`, stopping when it
+/// is `distance` away from it. For mobs that want to steer towards something without using
+/// `Follow`, e.g. when the target is a position rather than an entity.
+pub fn steer_towards(position: DVec3, target: DVec3, speed: f64, distance: f64) -> DVec3 {
+    let offset = (target - position).with_y(0.0);
+    let remaining = offset.length() - distance;
+    if remaining <= 0.0 {
+        return DVec3::ZERO;
+    }
+
+    return offset.normalize() * speed.min(remaining / FOLLOW_BRAKING_TIME);
+}
+
+pub(super) fn apply_leashes(
+    mut commands: Commands,
+    time: Res<Time>,
+    transform_query: Query<&GlobalTransform>,
+    mut leashed_query: Query<(Entity, &Leash, &GlobalTransform, &mut Velocity), With<Mass>>,
+    mut broken_events: EventWriter<LeashBroken>,
+) {
+    for (entity, leash, transform, mut velocity) in leashed_query.iter_mut() {
+        let Ok(holder_transform) = transform_query.get(leash.holder) else {
+            commands.entity(entity).remove::<Leash>();
+            broken_events.send(LeashBroken {
+                entity,
+                holder: leash.holder,
+            });
+            continue;
+        };
+
+        let offset = holder_transform.translation() - transform.translation();
+        let distance = offset.length();
+
+        if distance > leash.break_distance {
+            commands.entity(entity).remove::<Leash>();
+            broken_events.send(LeashBroken {
+                entity,
+                holder: leash.holder,
+            });
+            continue;
+        }
+
+        if distance <= leash.length {
+            continue;
+        }
+
+        let direction = offset / distance;
+
+        // A taut leash stops the entity from moving further away
+        let away = velocity.dot(direction);
+        if away < 0.0 {
+            velocity.0 -= direction * away;
+        }
+
+        velocity.0 +=
+            direction * (distance - leash.length) * LEASH_STIFFNESS * time.delta_secs_f64();
+    }
+}
+
+pub(super) fn follow_targets(
+    mut commands: Commands,
+    budget: Res<SimulationBudget>,
+    budget_settings: Res<SimulationBudgetSettings>,
+    transform_query: Query<&GlobalTransform>,
+    mut follower_query: Query<(Entity, &Follow, &GlobalTransform, &mut Velocity), With<Mass>>,
+) {
+    for (entity, follow, transform, mut velocity) in follower_query.iter_mut() {
+        // Distant followers keep their velocity between updates when the server is overloaded.
+        if !budget.should_simulate(entity, transform.translation(), &budget_settings) {
+            continue;
+        }
+
+        let Ok(target_transform) = transform_query.get(follow.target) else {
+            commands.entity(entity).remove::<Follow>();
+            continue;
+        };
+
+        let steering = steer_towards(
+            transform.translation(),
+            target_transform.translation(),
+            follow.speed,
+            follow.distance,
+        );
+
+        if velocity.x != steering.x || velocity.z != steering.z {
+            velocity.x = steering.x;
+            velocity.z = steering.z;
+        }
+    }
+}
+
+// The leashed entity's model, or if it doesn't have one directly, like players, the model of one
+// of its children.
+fn model_id(
+    entity: Entity,
+    model_query: &Query<(), With<Model>>,
+    children_query: &Query<&Children>,
+) -> Option<u32> {
+    if model_query.contains(entity) {
+        return Some(entity.index());
+    }
+
+    return children_query
+        .get(entity)
+        .ok()?
+        .iter()
+        .find(|child| model_query.contains(**child))
+        .map(|child| child.index());
+}
+
+// Clients are told of leashes through the "leash" property, and "unleash" with the model id
+// removes it.
+pub(super) fn send_leashes(
+    net: Res<Server>,
+    chunk_subscriptions: Res<ChunkSubscriptions>,
+    model_query: Query<(), With<Model>>,
+    children_query: Query<&Children>,
+    player_query: Query<Entity, With<Player>>,
+    resumed_query: Query<(), Added<ResumedSession>>,
+    leash_query: Query<(Entity, Ref<Leash>, &GlobalTransform)>,
+    mut removed_leashes: RemovedComponents<Leash>,
+    mut chunk_subscription_events: EventReader<ChunkSubscriptionEvent>,
+    // The model ids sent for each leashed entity, kept so the removal can be sent after the
+    // entity is gone.
+    mut sent: Local<HashMap<Entity, u32>>,
+) {
+    let send = |player_entity: Entity, entity: Entity, leash: &Leash| {
+        let id = model_id(entity, &model_query, &children_query)?;
+
+        let holder = if leash.holder == player_entity {
+            None
+        } else {
+            Some(model_id(leash.holder, &model_query, &children_query)?)
+        };
+
+        net.send_property(
+            player_entity,
+            properties::LEASH,
+            &properties::Leash { id, holder },
+        );
+        return Some(id);
+    };
+
+    for entity in removed_leashes.read() {
+        let Some(id) = sent.remove(&entity) else {
+            continue;
+        };
+
+        for player_entity in player_query.iter() {
+            net.send_property(player_entity, properties::UNLEASH, &id);
+        }
+    }
+
+    for (entity, leash, transform) in leash_query.iter() {
+        if !leash.is_changed() {
+            continue;
+        }
+
+        let chunk_position =
+            utils::world_position_to_chunk_position(transform.translation().as_ivec3());
+        let Some(subscribers) = chunk_subscriptions.get_subscribers(&chunk_position) else {
+            continue;
+        };
+
+        for player_entity in subscribers.iter() {
+            if let Some(id) = send(*player_entity, entity, &leash) {
+                sent.insert(entity, id);
+            }
+        }
+    }
+
+    for chunk_subscription in chunk_subscription_events.read() {
+        for (entity, leash, transform) in leash_query.iter() {
+            let chunk_position =
+                utils::world_position_to_chunk_position(transform.translation().as_ivec3());
+            if chunk_position != chunk_subscription.chunk_position {
+                continue;
+            }
+
+            if let Some(id) = send(chunk_subscription.player_entity, entity, &leash) {
+                sent.insert(entity, id);
+            }
+        }
+    }
+
+    // A resumed session has a new client. Leashes the player holds, or is held by, are sent to it
+    // again, the rest are sent with the chunks they are in.
+    if !resumed_query.is_empty() {
+        for (entity, leash, _) in leash_query.iter() {
+            for player_entity in [entity, leash.holder] {
+                if !resumed_query.contains(player_entity) {
+                    continue;
+                }
+
+                if let Some(id) = send(player_entity, entity, &leash) {
+                    sent.insert(entity, id);
+                }
+            }
+        }
+    }
+}
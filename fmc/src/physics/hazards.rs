@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    blocks::{BlockConfig, BlockHazard, BlockId, BlockRotation, BlockState, Blocks},
+    physics::{shapes::Aabb, Collider},
+    prelude::*,
+    world::WorldMap,
+};
+
+// Margin added around the entity so that standing on, or leaning against a block counts as
+// touching it.
+const CONTACT_MARGIN: f64 = 0.01;
+
+/// Makes the entity affected by block hazards, see `blocks::BlockHazard`. Keeps track of which
+/// effects the entity is immune to.
+#[derive(Component, Default)]
+pub struct HazardImmunity {
+    // Seconds left of immunity, by effect
+    timers: HashMap<String, f64>,
+}
+
+impl HazardImmunity {
+    /// Make the entity immune to an effect for a while, e.g. after drinking a fire resistance
+    /// potion.
+    pub fn grant(&mut self, effect: &str, seconds: f64) {
+        let timer = self.timers.entry(effect.to_owned()).or_default();
+        *timer = timer.max(seconds);
+    }
+
+    pub fn is_immune(&self, effect: &str) -> bool {
+        return self.timers.contains_key(effect);
+    }
+}
+
+/// Sent when an entity is affected by a block hazard. If the entity touches several blocks with
+/// the same effect, it is only sent for the strongest of them.
+#[derive(Event)]
+pub struct HazardContact {
+    pub entity: Entity,
+    pub block_position: IVec3,
+    pub block_id: BlockId,
+    pub effect: String,
+    pub amount: f32,
+}
+
+// The hitbox of the block in world space. Blocks without a hitbox, like lava, fill the whole
+// block.
+fn block_aabbs(world_map: &WorldMap, block_position: IVec3, config: &BlockConfig) -> Vec<Aabb> {
+    let rotation = world_map
+        .get_block_state(block_position)
+        .map(BlockState::rotation)
+        .flatten()
+        .map(BlockRotation::as_quat)
+        .unwrap_or_default();
+
+    let block_transform = Transform {
+        translation: block_position.as_dvec3(),
+        rotation,
+        ..default()
+    };
+
+    match &config.hitbox {
+        Some(Collider::Aabb(aabb)) => vec![aabb.transform(&block_transform)],
+        Some(Collider::Compound(aabbs)) => aabbs
+            .iter()
+            .map(|aabb| aabb.transform(&block_transform))
+            .collect(),
+        None => vec![Aabb::from_min_max(
+            block_position.as_dvec3(),
+            block_position.as_dvec3() + 1.0,
+        )],
+    }
+}
+
+pub(super) fn apply_hazards(
+    time: Res<Time>,
+    world_map: Res<WorldMap>,
+    mut max_range: Local<Option<f64>>,
+    mut entity_query: Query<(Entity, &GlobalTransform, &Aabb, &mut HazardImmunity)>,
+    mut hazard_events: EventWriter<HazardContact>,
+) {
+    let blocks = Blocks::get();
+
+    // How far out blocks have to be checked
+    let max_range = *max_range.get_or_insert_with(|| {
+        blocks
+            .asset_ids()
+            .values()
+            .flat_map(|block_id| blocks.get_config(block_id).hazards.iter())
+            .map(|hazard| hazard.range)
+            .fold(CONTACT_MARGIN, f64::max)
+    });
+
+    let delta_time = time.delta_secs_f64();
+
+    for (entity, transform, aabb, mut immunity) in entity_query.iter_mut() {
+        immunity.timers.retain(|_, timer| {
+            *timer -= delta_time;
+            *timer > 0.0
+        });
+
+        let entity_aabb = Aabb {
+            center: aabb.center + transform.translation(),
+            half_extents: aabb.half_extents,
+        };
+
+        // The strongest hazard of each effect, ordered so the events are always sent in the same
+        // order.
+        let mut strongest: BTreeMap<&str, (IVec3, BlockId, &BlockHazard)> = BTreeMap::new();
+
+        let start = (entity_aabb.min() - max_range).floor().as_ivec3();
+        let stop = (entity_aabb.max() + max_range).floor().as_ivec3();
+        for x in start.x..=stop.x {
+            for y in start.y..=stop.y {
+                for z in start.z..=stop.z {
+                    let block_position = IVec3::new(x, y, z);
+                    let Some(block_id) = world_map.get_block(block_position) else {
+                        continue;
+                    };
+
+                    let config = blocks.get_config(&block_id);
+                    if config.hazards.is_empty() {
+                        continue;
+                    }
+
+                    let hitboxes = block_aabbs(&world_map, block_position, config);
+
+                    for hazard in config.hazards.iter() {
+                        if immunity.is_immune(&hazard.effect) {
+                            continue;
+                        }
+
+                        let reach = Aabb {
+                            center: entity_aabb.center,
+                            half_extents: entity_aabb.half_extents
+                                + hazard.range.max(CONTACT_MARGIN),
+                        };
+                        if !hitboxes
+                            .iter()
+                            .any(|block_aabb| reach.intersects(block_aabb).is_some())
+                        {
+                            continue;
+                        }
+
+                        let current = strongest.entry(&hazard.effect).or_insert((
+                            block_position,
+                            block_id,
+                            hazard,
+                        ));
+                        if hazard.amount > current.2.amount {
+                            *current = (block_position, block_id, hazard);
+                        }
+                    }
+                }
+            }
+        }
+
+        for (effect, (block_position, block_id, hazard)) in strongest {
+            immunity.grant(effect, hazard.invulnerability);
+            hazard_events.send(HazardContact {
+                entity,
+                block_position,
+                block_id,
+                effect: effect.to_owned(),
+                amount: hazard.amount,
+            });
+        }
+    }
+}
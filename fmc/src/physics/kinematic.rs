@@ -0,0 +1,208 @@
+use bevy::math::DVec3;
+use fmc_protocol::messages;
+
+use crate::{
+    blocks::Friction,
+    networking::Server,
+    physics::{shapes::Aabb, Collider, CollisionGroups, Mass, Velocity},
+    players::Player,
+    prelude::*,
+};
+
+// How far above the top of a kinematic collider the bottom of an entity can be and still ride on
+// it. Entities that rest on something float a tiny bit above it.
+const RIDE_MARGIN: f64 = 0.05;
+// Entities that are pushed are moved this much further so they don't stay overlapping.
+const PUSH_MARGIN: f64 = 0.001;
+
+/// A collider that is moved by the server instead of by physics, for moving platforms, piston
+/// heads, elevators and the like. Entities collide with it like they do with blocks, are pushed
+/// out of the way when it moves into them and are carried along when they stand on top of it.
+///
+/// Move it with [KinematicCollider::move_to], changing its transform directly teleports it
+/// without carrying or pushing anything. It can't have a parent or [Mass].
+///
+/// Players simulate their own movement, they are carried and pushed, but only collide with it on
+/// the server.
+#[derive(Component)]
+pub struct KinematicCollider {
+    /// The shape, relative to the entity's transform
+    pub collider: Collider,
+    /// The friction of its faces, same as for blocks.
+    pub friction: Friction,
+    target: Option<DVec3>,
+    // Blocks per second
+    speed: f64,
+    velocity: DVec3,
+}
+
+impl KinematicCollider {
+    pub fn new(collider: Collider, friction: Friction) -> Self {
+        Self {
+            collider,
+            friction,
+            target: None,
+            speed: 0.0,
+            velocity: DVec3::ZERO,
+        }
+    }
+
+    /// Move it in a straight line to the target at the speed, in blocks per second.
+    /// [KinematicArrived] is sent when it gets there.
+    pub fn move_to(&mut self, target: DVec3, speed: f64) {
+        self.target = Some(target);
+        self.speed = speed;
+    }
+
+    /// Stop it where it is
+    pub fn stop(&mut self) {
+        self.target = None;
+    }
+
+    pub fn is_moving(&self) -> bool {
+        return self.target.is_some();
+    }
+
+    /// How fast it moved during the last update, in blocks per second
+    pub fn velocity(&self) -> DVec3 {
+        return self.velocity;
+    }
+}
+
+/// Sent when a [KinematicCollider] reaches the target it was moved to.
+#[derive(Event)]
+pub struct KinematicArrived {
+    pub entity: Entity,
+}
+
+// If the aabb stands on top of any of the collider aabbs
+fn is_riding(aabb: &Aabb, collider_aabbs: &[Aabb]) -> bool {
+    return collider_aabbs.iter().any(|collider_aabb| {
+        let height = aabb.min().y - collider_aabb.max().y;
+        let horizontal_distance = (aabb.center - collider_aabb.center).abs();
+        let horizontal_overlap =
+            aabb.half_extents + collider_aabb.half_extents - horizontal_distance;
+
+        (0.0..=RIDE_MARGIN).contains(&height)
+            && horizontal_overlap.x > 0.0
+            && horizontal_overlap.z > 0.0
+    });
+}
+
+// The kinematic colliders move before the entities do. Entities riding on them are moved along,
+// and entities in the way are pushed out along the axis the collider moves the most on. The
+// entities then collide with where the colliders ended up in simulate_aabb_physics.
+pub(super) fn move_kinematic_colliders(
+    time: Res<Time>,
+    net: Res<Server>,
+    mut kinematic_query: Query<(Entity, &mut Transform, &mut KinematicCollider)>,
+    mut entity_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &Aabb,
+            Option<&CollisionGroups>,
+            Has<Player>,
+        ),
+        (Or<(With<Mass>, With<Player>)>, Without<KinematicCollider>),
+    >,
+    mut arrived_events: EventWriter<KinematicArrived>,
+) {
+    let delta_time = time.delta_secs_f64();
+
+    for (kinematic_entity, mut kinematic_transform, mut kinematic) in kinematic_query.iter_mut() {
+        let Some(target) = kinematic.target else {
+            if kinematic.velocity != DVec3::ZERO {
+                kinematic.velocity = DVec3::ZERO;
+            }
+            continue;
+        };
+
+        let to_target = target - kinematic_transform.translation;
+        let max_step = kinematic.speed * delta_time;
+        let movement = if to_target.length() <= max_step {
+            kinematic.target = None;
+            arrived_events.send(KinematicArrived {
+                entity: kinematic_entity,
+            });
+            to_target
+        } else {
+            to_target.normalize() * max_step
+        };
+
+        kinematic.velocity = if delta_time > 0.0 {
+            movement / delta_time
+        } else {
+            DVec3::ZERO
+        };
+
+        if movement == DVec3::ZERO {
+            continue;
+        }
+
+        let before = kinematic.collider.aabbs(&kinematic_transform);
+        kinematic_transform.translation += movement;
+        let after = kinematic.collider.aabbs(&kinematic_transform);
+
+        let push_axis =
+            if movement.x.abs() >= movement.y.abs() && movement.x.abs() >= movement.z.abs() {
+                DVec3::X
+            } else if movement.y.abs() >= movement.z.abs() {
+                DVec3::Y
+            } else {
+                DVec3::Z
+            };
+        let push_direction = push_axis * movement.dot(push_axis).signum();
+
+        for (entity, mut transform, mut velocity, aabb, collision_groups, is_player) in
+            entity_query.iter_mut()
+        {
+            if collision_groups.is_some_and(|groups| !groups.collides_with_blocks()) {
+                continue;
+            }
+
+            let mut entity_aabb = Aabb {
+                center: aabb.center + transform.translation,
+                half_extents: aabb.half_extents,
+            };
+
+            let mut offset = DVec3::ZERO;
+            if is_riding(&entity_aabb, &before) {
+                offset += movement;
+                entity_aabb.center += movement;
+            }
+
+            for collider_aabb in after.iter() {
+                let Some(overlap) = collider_aabb.intersects(&entity_aabb) else {
+                    continue;
+                };
+
+                let push = push_direction * (overlap.dot(push_axis).abs() + PUSH_MARGIN);
+                offset += push;
+                entity_aabb.center += push;
+
+                // It can't keep moving into the collider
+                if velocity.dot(push_direction) < 0.0 {
+                    velocity.0 -= push_axis * velocity.dot(push_axis);
+                }
+            }
+
+            if offset == DVec3::ZERO {
+                continue;
+            }
+
+            transform.translation += offset;
+
+            if is_player {
+                net.send_one(
+                    entity,
+                    messages::PlayerPosition {
+                        position: transform.translation,
+                        velocity: velocity.0,
+                    },
+                );
+            }
+        }
+    }
+}
@@ -10,26 +10,70 @@ use crate::{
     world::{BlockUpdate, WorldMap},
 };
 
+mod constraints;
+mod hazards;
+mod kinematic;
+mod queries;
+mod replay;
+mod sensors;
 pub mod shapes;
+mod vehicles;
+
+pub use constraints::{steer_towards, Follow, Leash, LeashBroken};
+pub use hazards::{HazardContact, HazardImmunity};
+pub use kinematic::{KinematicArrived, KinematicCollider};
+pub use queries::{CollisionGroups, QueryFilter, QueryHit, SpatialQuery};
+pub use replay::{
+    replay, PhysicsRecorder, RecordedChunk, RecordedEntity, RecordedStep, Recording, ReplayMismatch,
+};
+pub use sensors::{Sensor, SensorEnter, SensorExit};
+pub use vehicles::{find_rail, is_liquid, liquid_surface, move_along_rail};
 
 use self::shapes::Aabb;
 
-const GRAVITY: DVec3 = DVec3::new(0.0, -28.0, 0.0);
-
 pub struct PhysicsPlugin;
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ObjectMap::default()).add_systems(
-            Update,
-            (
-                simulate_aabb_physics.in_set(PhysicsSystems),
-                apply_acceleration.before(simulate_aabb_physics),
-                gravity.before(apply_acceleration),
-                buoyancy.before(apply_acceleration),
-                update_object_map,
-                trigger_update_on_block_change,
-            ),
-        );
+        app.insert_resource(ObjectMap::default())
+            .init_resource::<PhysicsConfig>()
+            .init_resource::<PhysicsRecorder>()
+            .add_event::<HazardContact>()
+            .add_event::<KinematicArrived>()
+            .add_event::<LeashBroken>()
+            .add_event::<SensorEnter>()
+            .add_event::<SensorExit>()
+            .add_systems(
+                Update,
+                (
+                    simulate_aabb_physics.in_set(PhysicsSystems),
+                    hazards::apply_hazards.after(PhysicsSystems),
+                    sensors::detect_overlaps.after(PhysicsSystems),
+                    (
+                        constraints::follow_targets,
+                        constraints::apply_leashes,
+                        kinematic::move_kinematic_colliders,
+                    )
+                        .chain()
+                        .after(apply_acceleration)
+                        .before(PhysicsSystems),
+                    constraints::send_leashes.after(PhysicsSystems),
+                    apply_acceleration.before(simulate_aabb_physics),
+                    gravity.before(apply_acceleration),
+                    buoyancy.before(apply_acceleration),
+                    update_object_map,
+                    trigger_update_on_block_change,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    replay::record_inputs
+                        .after(kinematic::move_kinematic_colliders)
+                        .before(PhysicsSystems),
+                    replay::record_results.after(PhysicsSystems),
+                )
+                    .run_if(replay::is_recording),
+            );
     }
 }
 
@@ -66,6 +110,61 @@ impl Collider {
             }
         }
     }
+
+    /// The aabbs of the collider moved by the transform
+    pub fn aabbs(&self, transform: &Transform) -> Vec<Aabb> {
+        match self {
+            Self::Aabb(aabb) => vec![aabb.transform(transform)],
+            Self::Compound(aabbs) => aabbs.iter().map(|aabb| aabb.transform(transform)).collect(),
+        }
+    }
+}
+
+/// Physics constants of the world. Players simulate their own movement, they are sent the values
+/// that apply to them when they join and when they change.
+#[derive(Resource, Clone, Copy, PartialEq)]
+pub struct PhysicsConfig {
+    /// Acceleration of everything that falls, in blocks per second squared
+    pub gravity: DVec3,
+    /// The fastest anything can fall, in blocks per second along the direction of gravity
+    pub terminal_velocity: f64,
+    /// How high a ledge can be walked up onto without jumping, 0 turns it off.
+    pub step_height: f64,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            gravity: DVec3::new(0.0, -28.0, 0.0),
+            terminal_velocity: 78.0,
+            step_height: 0.0,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    /// The config with the entity's overrides applied
+    pub fn with_override(&self, physics_override: Option<&PhysicsOverride>) -> Self {
+        let Some(physics_override) = physics_override else {
+            return *self;
+        };
+
+        return Self {
+            gravity: physics_override.gravity.unwrap_or(self.gravity),
+            terminal_velocity: physics_override
+                .terminal_velocity
+                .unwrap_or(self.terminal_velocity),
+            step_height: physics_override.step_height.unwrap_or(self.step_height),
+        };
+    }
+}
+
+/// Replaces the values of the [PhysicsConfig] for a single entity, e.g. for mobs that float.
+#[derive(Component, Default, Clone)]
+pub struct PhysicsOverride {
+    pub gravity: Option<DVec3>,
+    pub terminal_velocity: Option<f64>,
+    pub step_height: Option<f64>,
 }
 
 // For ordering systems to remove 1-frame lag
@@ -166,13 +265,44 @@ impl ObjectMap {
 fn simulate_aabb_physics(
     world_map: Res<WorldMap>,
     time: Res<Time>,
-    mut entities: Query<(&mut Transform, &mut Velocity, &Aabb), With<Mass>>,
+    physics_config: Res<PhysicsConfig>,
+    mut entities: Query<
+        (
+            &mut Transform,
+            &mut Velocity,
+            &Aabb,
+            Option<&PhysicsOverride>,
+            Option<&CollisionGroups>,
+        ),
+        With<Mass>,
+    >,
+    kinematic_query: Query<(&Transform, &KinematicCollider), Without<Mass>>,
 ) {
-    for (mut transform, mut velocity, aabb) in entities.iter_mut() {
+    let blocks = Blocks::get();
+
+    // The kinematic colliders are collided with like blocks
+    let mut obstacles: Vec<(Aabb, &Friction)> = Vec::new();
+    for (transform, kinematic) in kinematic_query.iter() {
+        for aabb in kinematic.collider.aabbs(transform) {
+            obstacles.push((aabb, &kinematic.friction));
+        }
+    }
+
+    for (mut transform, mut velocity, aabb, physics_override, collision_groups) in
+        entities.iter_mut()
+    {
         if velocity.0 == DVec3::ZERO {
             continue;
         }
 
+        // Entities that pass through blocks only move
+        if collision_groups.is_some_and(|groups| !groups.collides_with_blocks()) {
+            transform.translation += velocity.0 * time.delta_secs_f64();
+            continue;
+        }
+
+        let step_height = physics_config.with_override(physics_override).step_height;
+
         let mut friction = DVec3::ZERO;
         for directional_velocity in [
             DVec3::new(0.0, velocity.y, 0.0),
@@ -187,8 +317,6 @@ fn simulate_aabb_physics(
                 half_extents: aabb.half_extents,
             };
 
-            let blocks = Blocks::get();
-
             // Check for collisions with all blocks within the aabb.
             let mut collisions = Vec::new();
             let start = entity_aabb.min().floor().as_ivec3();
@@ -216,19 +344,47 @@ fn simulate_aabb_physics(
 
                         if overlap.cmpgt(DVec3::ZERO).all() {
                             //collisions.push((overlap, block_id));
-                            collisions.push((DVec3::from(overlap.copysign(distance)), block_id));
+                            collisions.push((
+                                DVec3::from(overlap.copysign(distance)),
+                                &blocks.get_config(&block_id).friction,
+                            ));
                         }
                     }
                 }
             }
 
+            for (obstacle_aabb, obstacle_friction) in obstacles.iter() {
+                let distance = entity_aabb.center - obstacle_aabb.center;
+                let overlap =
+                    entity_aabb.half_extents + obstacle_aabb.half_extents - distance.abs();
+
+                if overlap.cmpgt(DVec3::ZERO).all() {
+                    collisions.push((overlap.copysign(distance), *obstacle_friction));
+                }
+            }
+
+            // Walk up onto ledges that are low enough instead of stopping at them. Only while on
+            // the ground, the vertical move that came first stopped the fall if it is.
+            if directional_velocity.y == 0.0 && velocity.y == 0.0 && step_height > 0.0 {
+                if let Some(step) = step_up(
+                    &world_map,
+                    &obstacles,
+                    &entity_aabb,
+                    &collisions,
+                    step_height,
+                ) {
+                    transform.translation = pos_after_move + DVec3::Y * step;
+                    continue;
+                }
+            }
+
             // TODO: This is remnant of when I tried to do all three axes at once. It could
             // probably be made to be simpler.
             let mut move_back = DVec3::ZERO;
             let delta_time = DVec3::splat(time.delta_secs_f64());
             // Resolve the conflicts by moving the aabb the opposite way of the velocity vector on the
             // axis it takes the longest time to resolve the conflict.
-            for (collision, block_id) in collisions {
+            for (collision, friction) in collisions {
                 let backwards_time = collision / -directional_velocity;
                 // Small epsilon to delta time because of precision.
                 let valid_axes = backwards_time.cmplt(delta_time + delta_time / 100.0)
@@ -236,7 +392,7 @@ fn simulate_aabb_physics(
                 let resolution_axis =
                     DVec3::select(valid_axes, backwards_time, DVec3::NAN).max_element();
 
-                match blocks.get_config(&block_id).friction {
+                match *friction {
                     Friction::Static {
                         front,
                         back,
@@ -323,6 +479,70 @@ fn simulate_aabb_physics(
     }
 }
 
+// How far up the aabb must be moved to stand on top of the blocks it collides with, if it is
+// within the step height and there is room for it there.
+fn step_up(
+    world_map: &WorldMap,
+    obstacles: &[(Aabb, &Friction)],
+    aabb: &Aabb,
+    collisions: &[(DVec3, &Friction)],
+    step_height: f64,
+) -> Option<f64> {
+    let blocks = Blocks::get();
+
+    let mut step: f64 = 0.0;
+    for (overlap, friction) in collisions {
+        if !matches!(friction, Friction::Static { .. }) {
+            continue;
+        }
+
+        // The overlap is negative for blocks whose center is above the aabb's, they're too high.
+        if overlap.y <= 0.0 {
+            return None;
+        }
+        step = step.max(overlap.y);
+    }
+
+    if step == 0.0 || step > step_height {
+        return None;
+    }
+
+    // Small epsilon so it doesn't collide with the blocks it stepped onto
+    let step = step + 0.001;
+    let stepped_aabb = Aabb {
+        center: aabb.center + DVec3::Y * step,
+        half_extents: aabb.half_extents,
+    };
+
+    let start = stepped_aabb.min().floor().as_ivec3();
+    let stop = stepped_aabb.max().floor().as_ivec3();
+    for x in start.x..=stop.x {
+        for y in start.y..=stop.y {
+            for z in start.z..=stop.z {
+                let Some(block_id) = world_map.get_block(IVec3::new(x, y, z)) else {
+                    return None;
+                };
+
+                if matches!(
+                    blocks.get_config(&block_id).friction,
+                    Friction::Static { .. }
+                ) {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if obstacles.iter().any(|(obstacle_aabb, friction)| {
+        matches!(friction, Friction::Static { .. })
+            && stepped_aabb.intersects(obstacle_aabb).is_some()
+    }) {
+        return None;
+    }
+
+    return Some(step);
+}
+
 fn update_object_map(
     mut object_map: ResMut<ObjectMap>,
     object_query: Query<(Entity, &GlobalTransform), (With<Mass>, Changed<GlobalTransform>)>,
@@ -370,9 +590,18 @@ fn trigger_update_on_block_change(
 
 fn apply_acceleration(
     time: Res<Time>,
-    mut objects: Query<(Ref<GlobalTransform>, &mut Acceleration, &mut Velocity), With<Mass>>,
+    physics_config: Res<PhysicsConfig>,
+    mut objects: Query<
+        (
+            Ref<GlobalTransform>,
+            &mut Acceleration,
+            &mut Velocity,
+            Option<&PhysicsOverride>,
+        ),
+        With<Mass>,
+    >,
 ) {
-    for (transform, mut acceleration, mut velocity) in objects.iter_mut() {
+    for (transform, mut acceleration, mut velocity, physics_override) in objects.iter_mut() {
         if !transform.is_changed() && acceleration.0 == DVec3::ZERO && velocity.0 == DVec3::ZERO {
             // If the transform isn't modified and the object has no acceleration and
             // velocity it is considered stationary. Stationary objects are skipped until some
@@ -381,23 +610,44 @@ fn apply_acceleration(
         }
         velocity.0 += acceleration.0 * time.delta_secs_f64();
         acceleration.0 = DVec3::ZERO;
+
+        // Only the speed along gravity is limited, gravity doesn't have to point down.
+        let config = physics_config.with_override(physics_override);
+        if let Some(down) = config.gravity.try_normalize() {
+            let falling_speed = velocity.dot(down);
+            if falling_speed > config.terminal_velocity {
+                velocity.0 -= down * (falling_speed - config.terminal_velocity);
+            }
+        }
     }
 }
 
-fn gravity(mut objects: Query<&mut Acceleration, (With<Mass>, Changed<GlobalTransform>)>) {
-    for mut acceleration in objects.iter_mut() {
-        acceleration.0 += GRAVITY;
+fn gravity(
+    physics_config: Res<PhysicsConfig>,
+    mut objects: Query<
+        (&mut Acceleration, Option<&PhysicsOverride>),
+        (With<Mass>, Changed<GlobalTransform>),
+    >,
+) {
+    for (mut acceleration, physics_override) in objects.iter_mut() {
+        acceleration.0 += physics_config.with_override(physics_override).gravity;
     }
 }
 
 fn buoyancy(
     world_map: Res<WorldMap>,
+    physics_config: Res<PhysicsConfig>,
     mut objects: Query<
-        (&GlobalTransform, &mut Acceleration, &Buoyancy),
+        (
+            &GlobalTransform,
+            &mut Acceleration,
+            &Buoyancy,
+            Option<&PhysicsOverride>,
+        ),
         (With<Mass>, Changed<GlobalTransform>),
     >,
 ) {
-    for (transform, mut acceleration, buoyancy) in objects.iter_mut() {
+    for (transform, mut acceleration, buoyancy, physics_override) in objects.iter_mut() {
         let mut waterline_position = transform.translation();
         waterline_position.y += buoyancy.waterline;
 
@@ -417,9 +667,10 @@ fn buoyancy(
         //let offset_from_top_of_block = 1.0 - (waterline_position.y - block_position.y as f64);
         if buoyancy.density < friction.y && waterline_position.y < block_position.y as f64 + 1.0 {
             //if offset_from_top_of_block < 0.05 {
-            //    acceleration.0 += -GRAVITY;
+            //    acceleration.0 += -gravity;
             //} else {
-            acceleration.0 += -GRAVITY + DVec3::new(0.0, 1.0, 0.0);
+            let gravity = physics_config.with_override(physics_override).gravity;
+            acceleration.0 += -gravity + DVec3::new(0.0, 1.0, 0.0);
             //}
         }
     }
@@ -0,0 +1,298 @@
+use bevy::{ecs::system::SystemParam, math::DVec3};
+use serde::Deserialize;
+
+use crate::{
+    blocks::{BlockFace, BlockId, BlockRotation, BlockState, Blocks, Friction},
+    physics::shapes::Aabb,
+    prelude::*,
+    world::WorldMap,
+};
+
+/// Which collision layers an entity is in, and which layers it interacts with. Two things
+/// interact when each is in a layer the other interacts with. Entities without it are in
+/// [CollisionGroups::ENTITIES] and interact with everything, blocks are in
+/// [CollisionGroups::BLOCKS] and interact with everything.
+///
+/// e.g. a ghost that passes through everything but blocks is
+/// `CollisionGroups::new(CollisionGroups::ENTITIES, CollisionGroups::BLOCKS)`.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct CollisionGroups {
+    /// The layers it is in
+    pub memberships: u32,
+    /// The layers it interacts with
+    pub filter: u32,
+}
+
+impl CollisionGroups {
+    pub const BLOCKS: u32 = 1 << 0;
+    pub const ENTITIES: u32 = 1 << 1;
+    pub const PLAYERS: u32 = 1 << 2;
+    pub const PROJECTILES: u32 = 1 << 3;
+    pub const ALL: u32 = u32::MAX;
+    pub const NONE: u32 = 0;
+
+    pub const fn new(memberships: u32, filter: u32) -> Self {
+        Self {
+            memberships,
+            filter,
+        }
+    }
+
+    pub fn interacts_with(&self, other: &Self) -> bool {
+        return self.filter & other.memberships != 0 && other.filter & self.memberships != 0;
+    }
+
+    /// If it is stopped by blocks
+    pub fn collides_with_blocks(&self) -> bool {
+        return self.interacts_with(&Self::new(Self::BLOCKS, Self::ALL));
+    }
+}
+
+impl Default for CollisionGroups {
+    fn default() -> Self {
+        Self::new(Self::ENTITIES, Self::ALL)
+    }
+}
+
+/// Decides what a [SpatialQuery] can hit.
+#[derive(Clone, Default)]
+pub struct QueryFilter {
+    pub groups: CollisionGroups,
+    /// Entities that are never hit, e.g. the one that shot a projectile.
+    pub exclude: Vec<Entity>,
+}
+
+impl QueryFilter {
+    pub fn new(groups: CollisionGroups) -> Self {
+        Self {
+            groups,
+            exclude: Vec::new(),
+        }
+    }
+
+    pub fn exclude(mut self, entity: Entity) -> Self {
+        self.exclude.push(entity);
+        self
+    }
+
+    fn allows(&self, entity: Entity, groups: Option<&CollisionGroups>) -> bool {
+        return !self.exclude.contains(&entity)
+            && self
+                .groups
+                .interacts_with(groups.unwrap_or(&CollisionGroups::default()));
+    }
+}
+
+/// What a ray or a sweep hit first
+#[derive(Debug, Clone, Copy)]
+pub enum QueryHit {
+    Block {
+        position: IVec3,
+        block_id: BlockId,
+        face: BlockFace,
+        distance: f64,
+    },
+    Entity {
+        entity: Entity,
+        face: BlockFace,
+        distance: f64,
+    },
+}
+
+impl QueryHit {
+    pub fn distance(&self) -> f64 {
+        match self {
+            Self::Block { distance, .. } => *distance,
+            Self::Entity { distance, .. } => *distance,
+        }
+    }
+}
+
+/// Ray, sweep and intersection tests against blocks and the aabbs of entities, filtered by their
+/// [CollisionGroups].
+#[derive(SystemParam)]
+pub struct SpatialQuery<'w, 's> {
+    world_map: Res<'w, WorldMap>,
+    entities: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static GlobalTransform,
+            &'static Aabb,
+            Option<&'static CollisionGroups>,
+        ),
+    >,
+}
+
+impl<'w, 's> SpatialQuery<'w, 's> {
+    /// The first thing the ray hits within the max distance. The ray goes along the transform's
+    /// forward direction. Entities the ray starts inside of are not hit.
+    pub fn cast_ray(
+        &self,
+        ray_transform: &Transform,
+        max_distance: f64,
+        filter: &QueryFilter,
+    ) -> Option<QueryHit> {
+        let mut hit = self.cast_ray_blocks(ray_transform, max_distance, filter);
+
+        for (entity, transform, aabb, groups) in self.entities.iter() {
+            if !filter.allows(entity, groups) {
+                continue;
+            }
+
+            let aabb_transform = Transform::from_translation(transform.translation());
+            let Some((distance, face)) = aabb.ray_intersection(&aabb_transform, ray_transform)
+            else {
+                continue;
+            };
+
+            if distance < 0.0
+                || distance > max_distance
+                || hit.is_some_and(|hit| hit.distance() <= distance)
+            {
+                continue;
+            }
+
+            hit = Some(QueryHit::Entity {
+                entity,
+                face,
+                distance,
+            });
+        }
+
+        return hit;
+    }
+
+    fn cast_ray_blocks(
+        &self,
+        ray_transform: &Transform,
+        max_distance: f64,
+        filter: &QueryFilter,
+    ) -> Option<QueryHit> {
+        if !filter.groups.collides_with_blocks() {
+            return None;
+        }
+
+        let blocks = Blocks::get();
+
+        let mut raycast = self.world_map.raycast(ray_transform, max_distance);
+        while let Some(block_id) = raycast.next_block() {
+            let block_config = blocks.get_config(&block_id);
+            if !matches!(block_config.friction, Friction::Static { .. }) {
+                continue;
+            }
+
+            let Some(hitbox) = &block_config.hitbox else {
+                continue;
+            };
+
+            let position = raycast.position();
+            let rotation = self
+                .world_map
+                .get_block_state(position)
+                .map(BlockState::rotation)
+                .flatten()
+                .map(BlockRotation::as_quat)
+                .unwrap_or_default();
+
+            let block_transform = Transform {
+                translation: position.as_dvec3(),
+                rotation,
+                ..default()
+            };
+
+            if let Some((distance, face)) = hitbox.ray_intersection(&block_transform, ray_transform)
+            {
+                return Some(QueryHit::Block {
+                    position,
+                    block_id,
+                    face,
+                    distance,
+                });
+            }
+        }
+
+        return None;
+    }
+
+    /// The first entity the aabb hits when moved along the direction, up to the max distance.
+    /// Blocks are not swept, use `cast_ray` from the aabb's center for those.
+    pub fn sweep_aabb(
+        &self,
+        aabb: &Aabb,
+        direction: DVec3,
+        max_distance: f64,
+        filter: &QueryFilter,
+    ) -> Option<QueryHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == DVec3::ZERO {
+            return None;
+        }
+
+        // Sweeping an aabb against another is the same as casting a ray from its center against
+        // the other grown by its size.
+        let ray_transform = Transform::from_translation(aabb.center).looking_at(
+            aabb.center + direction,
+            if direction.y.abs() > 0.99 {
+                DVec3::X
+            } else {
+                DVec3::Y
+            },
+        );
+
+        let mut hit: Option<QueryHit> = None;
+        for (entity, transform, other, groups) in self.entities.iter() {
+            if !filter.allows(entity, groups) {
+                continue;
+            }
+
+            let grown = Aabb {
+                center: other.center + transform.translation(),
+                half_extents: other.half_extents + aabb.half_extents,
+            };
+
+            let Some((distance, face)) =
+                grown.ray_intersection(&Transform::IDENTITY, &ray_transform)
+            else {
+                continue;
+            };
+
+            if distance < 0.0
+                || distance > max_distance
+                || hit.is_some_and(|hit| hit.distance() <= distance)
+            {
+                continue;
+            }
+
+            hit = Some(QueryHit::Entity {
+                entity,
+                face,
+                distance,
+            });
+        }
+
+        return hit;
+    }
+
+    /// All entities whose aabbs overlap the aabb
+    pub fn intersect_aabb(&self, aabb: &Aabb, filter: &QueryFilter) -> Vec<Entity> {
+        let mut entities = Vec::new();
+        for (entity, transform, other, groups) in self.entities.iter() {
+            if !filter.allows(entity, groups) {
+                continue;
+            }
+
+            let other = Aabb {
+                center: other.center + transform.translation(),
+                half_extents: other.half_extents,
+            };
+
+            if aabb.intersects(&other).is_some() {
+                entities.push(entity);
+            }
+        }
+
+        return entities;
+    }
+}
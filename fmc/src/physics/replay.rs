@@ -0,0 +1,444 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Duration,
+};
+
+use bevy::{
+    math::DVec3,
+    time::{TimePlugin, TimeUpdateStrategy},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bevy_extensions::f64_transform::TransformPlugin,
+    blocks::{BlockId, BlockState, Friction},
+    physics::{
+        shapes::Aabb, Collider, CollisionGroups, KinematicCollider, Mass, ObjectMap, PhysicsConfig,
+        PhysicsOverride, PhysicsSystems, Velocity,
+    },
+    prelude::*,
+    utils,
+    world::{chunk::Chunk, BlockUpdate, TerrainGenerator, WorldMap},
+};
+
+/// Records what goes into each physics step and what comes out of it, so that a bug like an
+/// entity tunneling through a wall can be reproduced exactly with [replay].
+///
+/// ```ignore
+/// fn start(mut recorder: ResMut<PhysicsRecorder>) {
+///     recorder.start();
+/// }
+///
+/// fn stop(mut recorder: ResMut<PhysicsRecorder>) {
+///     if let Some(recording) = recorder.stop() {
+///         recording.save("tunneling.recording").unwrap();
+///     }
+/// }
+/// ```
+#[derive(Resource, Default)]
+pub struct PhysicsRecorder {
+    recording: Option<Recording>,
+    // Chunks that are already in the recording
+    recorded_chunks: HashSet<IVec3>,
+}
+
+impl PhysicsRecorder {
+    pub fn start(&mut self) {
+        self.recording = Some(Recording::default());
+        self.recorded_chunks.clear();
+    }
+
+    /// Stops recording and returns what was recorded
+    pub fn stop(&mut self) -> Option<Recording> {
+        self.recorded_chunks.clear();
+        return self.recording.take();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        return self.recording.is_some();
+    }
+}
+
+/// The physics steps that were recorded by the [PhysicsRecorder]
+#[derive(Serialize, Deserialize, Default)]
+pub struct Recording {
+    /// The chunks the entities moved through, as they were when first reached.
+    pub chunks: Vec<RecordedChunk>,
+    pub steps: Vec<RecordedStep>,
+}
+
+impl Recording {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let bytes = bincode::serialize(self).map_err(|e| e.to_string())?;
+        return std::fs::write(path, bytes).map_err(|e| e.to_string());
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        return bincode::deserialize(&bytes).map_err(|e| e.to_string());
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordedChunk {
+    pub position: IVec3,
+    /// The block ids are only valid for the same set of blocks as the server that recorded it.
+    pub blocks: Vec<BlockId>,
+    /// Block states by block index
+    pub block_states: Vec<(usize, u16)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordedStep {
+    /// How much time the step simulated
+    pub delta: Duration,
+    /// Blocks that were changed before the step, as position, block id and block state.
+    pub block_updates: Vec<(IVec3, BlockId, Option<u16>)>,
+    /// The aabbs of the kinematic colliders after they moved, and their friction.
+    pub kinematic_colliders: Vec<(Aabb, Friction)>,
+    /// The moving entities right before the step, after gravity and anything else that changed
+    /// their velocity.
+    pub inputs: Vec<RecordedEntity>,
+    /// The same entities right after the step
+    pub results: Vec<RecordedEntity>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedEntity {
+    /// The bits of the entity in the world that was recorded
+    pub id: u64,
+    pub translation: DVec3,
+    pub velocity: DVec3,
+    pub aabb: Aabb,
+    pub step_height: f64,
+    pub collides_with_blocks: bool,
+}
+
+/// Where a replay stopped matching its recording
+#[derive(Debug)]
+pub struct ReplayMismatch {
+    /// Index of the step in the recording
+    pub step: usize,
+    pub expected: RecordedEntity,
+    /// None if the entity wasn't simulated
+    pub actual: Option<RecordedEntity>,
+}
+
+fn record_entity(
+    entity: Entity,
+    transform: &Transform,
+    velocity: &Velocity,
+    aabb: &Aabb,
+    physics_config: &PhysicsConfig,
+    physics_override: Option<&PhysicsOverride>,
+    collision_groups: Option<&CollisionGroups>,
+) -> RecordedEntity {
+    return RecordedEntity {
+        id: entity.to_bits(),
+        translation: transform.translation,
+        velocity: velocity.0,
+        aabb: aabb.clone(),
+        step_height: physics_config.with_override(physics_override).step_height,
+        collides_with_blocks: collision_groups.map_or(true, |groups| groups.collides_with_blocks()),
+    };
+}
+
+type RecordedQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        &'static Velocity,
+        &'static Aabb,
+        Option<&'static PhysicsOverride>,
+        Option<&'static CollisionGroups>,
+    ),
+    With<Mass>,
+>;
+
+pub(super) fn is_recording(recorder: Res<PhysicsRecorder>) -> bool {
+    return recorder.is_recording();
+}
+
+// Entities that don't move are skipped by the physics step, only the ones that do are recorded.
+pub(super) fn record_inputs(
+    time: Res<Time>,
+    world_map: Res<WorldMap>,
+    physics_config: Res<PhysicsConfig>,
+    mut recorder: ResMut<PhysicsRecorder>,
+    mut block_updates: EventReader<BlockUpdate>,
+    entity_query: RecordedQuery,
+    kinematic_query: Query<(&Transform, &KinematicCollider), Without<Mass>>,
+) {
+    let recorder = recorder.as_mut();
+    let Some(recording) = recorder.recording.as_mut() else {
+        return;
+    };
+
+    let mut step = RecordedStep {
+        delta: time.delta(),
+        block_updates: block_updates
+            .read()
+            .map(|update| match update {
+                BlockUpdate::Change {
+                    position,
+                    block_id,
+                    block_state,
+                } => (
+                    *position,
+                    *block_id,
+                    block_state.map(|state| state.as_u16()),
+                ),
+            })
+            .collect(),
+        kinematic_colliders: kinematic_query
+            .iter()
+            .flat_map(|(transform, kinematic)| {
+                kinematic
+                    .collider
+                    .aabbs(transform)
+                    .into_iter()
+                    .map(|aabb| (aabb, kinematic.friction.clone()))
+            })
+            .collect(),
+        inputs: Vec::new(),
+        results: Vec::new(),
+    };
+
+    for (entity, transform, velocity, aabb, physics_override, collision_groups) in
+        entity_query.iter()
+    {
+        if !velocity.is_moving() {
+            continue;
+        }
+
+        step.inputs.push(record_entity(
+            entity,
+            transform,
+            velocity,
+            aabb,
+            &physics_config,
+            physics_override,
+            collision_groups,
+        ));
+
+        // The chunks the entity might touch this step
+        let reach = aabb.half_extents.max_element() + velocity.length() * time.delta_secs_f64();
+        let min = utils::world_position_to_chunk_position(
+            (transform.translation + aabb.center - reach - 1.0)
+                .floor()
+                .as_ivec3(),
+        );
+        let max = utils::world_position_to_chunk_position(
+            (transform.translation + aabb.center + reach + 1.0)
+                .floor()
+                .as_ivec3(),
+        );
+        for x in (min.x..=max.x).step_by(Chunk::SIZE) {
+            for y in (min.y..=max.y).step_by(Chunk::SIZE) {
+                for z in (min.z..=max.z).step_by(Chunk::SIZE) {
+                    let chunk_position = IVec3::new(x, y, z);
+                    if recorder.recorded_chunks.contains(&chunk_position) {
+                        continue;
+                    }
+
+                    let Some(chunk) = world_map.get_chunk(&chunk_position) else {
+                        continue;
+                    };
+
+                    let mut block_states: Vec<(usize, u16)> = chunk
+                        .block_state
+                        .iter()
+                        .map(|(index, state)| (*index, *state))
+                        .collect();
+                    block_states.sort_unstable();
+
+                    recording.chunks.push(RecordedChunk {
+                        position: chunk_position,
+                        blocks: chunk.blocks.clone(),
+                        block_states,
+                    });
+                    recorder.recorded_chunks.insert(chunk_position);
+                }
+            }
+        }
+    }
+
+    recording.steps.push(step);
+}
+
+pub(super) fn record_results(
+    physics_config: Res<PhysicsConfig>,
+    mut recorder: ResMut<PhysicsRecorder>,
+    entity_query: RecordedQuery,
+) {
+    let Some(step) = recorder
+        .recording
+        .as_mut()
+        .and_then(|recording| recording.steps.last_mut())
+    else {
+        return;
+    };
+
+    for input in step.inputs.iter() {
+        let Ok((entity, transform, velocity, aabb, physics_override, collision_groups)) =
+            entity_query.get(Entity::from_bits(input.id))
+        else {
+            continue;
+        };
+
+        step.results.push(record_entity(
+            entity,
+            transform,
+            velocity,
+            aabb,
+            &physics_config,
+            physics_override,
+            collision_groups,
+        ));
+    }
+}
+
+// The replay only has the recorded chunks, there is nothing to generate.
+struct NoTerrain;
+impl TerrainGenerator for NoTerrain {
+    fn generate_chunk(&self, _position: IVec3) -> Chunk {
+        return Chunk::default();
+    }
+}
+
+/// Runs the recorded physics steps again, and returns where the result first differs from what
+/// was recorded. The blocks must have been loaded first, see `blocks::Blocks`, with the same
+/// blocks as the server that made the recording.
+pub fn replay(recording: &Recording) -> Result<(), ReplayMismatch> {
+    let mut world_map = WorldMap::new(NoTerrain);
+    for recorded_chunk in recording.chunks.iter() {
+        let mut chunk = Chunk::default();
+        chunk.blocks = recorded_chunk.blocks.clone();
+        chunk.block_state = recorded_chunk.block_states.iter().copied().collect();
+        world_map.insert(recorded_chunk.position, chunk);
+    }
+
+    let mut app = App::new();
+    app.add_plugins((TimePlugin, TransformPlugin))
+        .insert_resource(world_map)
+        .insert_resource(ObjectMap::default())
+        .insert_resource(PhysicsConfig::default())
+        .add_event::<BlockUpdate>()
+        .add_systems(
+            Update,
+            (
+                super::simulate_aabb_physics.in_set(PhysicsSystems),
+                super::update_object_map,
+                super::trigger_update_on_block_change,
+            ),
+        );
+
+    // Time doesn't advance on the first update
+    app.update();
+
+    let mut entities: HashMap<u64, Entity> = HashMap::new();
+    let mut kinematic_entities: Vec<Entity> = Vec::new();
+
+    for (step_index, step) in recording.steps.iter().enumerate() {
+        let world = app.world_mut();
+        world.insert_resource(TimeUpdateStrategy::ManualDuration(step.delta));
+
+        for (position, block_id, block_state) in step.block_updates.iter() {
+            let (chunk_position, block_index) =
+                utils::world_position_to_chunk_position_and_block_index(*position);
+            let mut world_map = world.resource_mut::<WorldMap>();
+            if let Some(chunk) = world_map.get_chunk_mut(&chunk_position) {
+                chunk[block_index] = *block_id;
+                chunk.set_block_state(block_index, block_state.map(BlockState));
+            }
+
+            world.send_event(BlockUpdate::Change {
+                position: *position,
+                block_id: *block_id,
+                block_state: block_state.map(BlockState),
+            });
+        }
+
+        // They don't move during the replay, they're put where they were each step.
+        for entity in kinematic_entities.drain(..) {
+            world.despawn(entity);
+        }
+        for (aabb, friction) in step.kinematic_colliders.iter() {
+            kinematic_entities.push(
+                world
+                    .spawn((
+                        Transform::IDENTITY,
+                        KinematicCollider::new(Collider::Aabb(aabb.clone()), friction.clone()),
+                    ))
+                    .id(),
+            );
+        }
+
+        // Only what changed outside of the step is set, when the replay matches the recording
+        // nothing is.
+        for input in step.inputs.iter() {
+            let entity = *entities.entry(input.id).or_insert_with(|| {
+                world
+                    .spawn((
+                        Transform::from_translation(input.translation),
+                        GlobalTransform::default(),
+                        Mass,
+                        Velocity(input.velocity),
+                        input.aabb.clone(),
+                    ))
+                    .id()
+            });
+
+            let mut entity_mut = world.entity_mut(entity);
+            if entity_mut.get::<Transform>().unwrap().translation != input.translation {
+                entity_mut.get_mut::<Transform>().unwrap().translation = input.translation;
+            }
+            if entity_mut.get::<Velocity>().unwrap().0 != input.velocity {
+                entity_mut.get_mut::<Velocity>().unwrap().0 = input.velocity;
+            }
+            entity_mut.insert((
+                PhysicsOverride {
+                    step_height: Some(input.step_height),
+                    ..default()
+                },
+                if input.collides_with_blocks {
+                    CollisionGroups::default()
+                } else {
+                    CollisionGroups::new(CollisionGroups::ENTITIES, CollisionGroups::NONE)
+                },
+            ));
+        }
+
+        app.update();
+
+        let world = app.world_mut();
+        for expected in step.results.iter() {
+            let actual = entities.get(&expected.id).and_then(|entity| {
+                let entity_ref = world.entity(*entity);
+                Some(RecordedEntity {
+                    translation: entity_ref.get::<Transform>()?.translation,
+                    velocity: entity_ref.get::<Velocity>()?.0,
+                    ..expected.clone()
+                })
+            });
+
+            // Compared exactly, the point is to catch any difference at all.
+            let matches = actual.as_ref().is_some_and(|actual| {
+                actual.translation == expected.translation && actual.velocity == expected.velocity
+            });
+
+            if !matches {
+                return Err(ReplayMismatch {
+                    step: step_index,
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use crate::{
+    physics::{shapes::Aabb, Collider, CollisionGroups},
+    prelude::*,
+};
+
+/// A volume that detects entities instead of blocking them, for pressure plates, checkpoints,
+/// traps and the like. [SensorEnter] and [SensorExit] are sent when the aabb of an entity starts
+/// and stops overlapping it. Give it [CollisionGroups] to only detect some entities, e.g. only
+/// players.
+#[derive(Component)]
+pub struct Sensor {
+    /// The volume, relative to the sensor's transform
+    pub collider: Collider,
+    // Entities that overlapped it last update
+    overlapping: HashSet<Entity>,
+}
+
+impl Sensor {
+    pub fn new(collider: Collider) -> Self {
+        Self {
+            collider,
+            overlapping: HashSet::new(),
+        }
+    }
+
+    /// The entities that are inside the sensor
+    pub fn overlapping(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.overlapping.iter().copied()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.overlapping.contains(&entity)
+    }
+}
+
+/// Sent when an entity starts overlapping a sensor
+#[derive(Event)]
+pub struct SensorEnter {
+    pub sensor: Entity,
+    pub entity: Entity,
+}
+
+/// Sent when an entity stops overlapping a sensor, or is despawned while inside it.
+#[derive(Event)]
+pub struct SensorExit {
+    pub sensor: Entity,
+    pub entity: Entity,
+}
+
+// Every sensor is checked against every entity with an aabb. There are few sensors, and the
+// check is cheap compared to the block collisions.
+pub(super) fn detect_overlaps(
+    entity_query: Query<
+        (Entity, &GlobalTransform, &Aabb, Option<&CollisionGroups>),
+        Without<Sensor>,
+    >,
+    mut sensor_query: Query<(
+        Entity,
+        &GlobalTransform,
+        &mut Sensor,
+        Option<&CollisionGroups>,
+    )>,
+    mut enter_events: EventWriter<SensorEnter>,
+    mut exit_events: EventWriter<SensorExit>,
+) {
+    for (sensor_entity, sensor_transform, mut sensor, sensor_groups) in sensor_query.iter_mut() {
+        let sensor_groups = sensor_groups.copied().unwrap_or_default();
+
+        let sensor_aabbs = sensor.collider.aabbs(&sensor_transform.compute_transform());
+
+        let mut overlapping = HashSet::with_capacity(sensor.overlapping.len());
+        for (entity, transform, aabb, groups) in entity_query.iter() {
+            if !sensor_groups.interacts_with(&groups.copied().unwrap_or_default()) {
+                continue;
+            }
+
+            let entity_aabb = Aabb {
+                center: aabb.center + transform.translation(),
+                half_extents: aabb.half_extents,
+            };
+
+            if sensor_aabbs
+                .iter()
+                .any(|sensor_aabb| sensor_aabb.intersects(&entity_aabb).is_some())
+            {
+                overlapping.insert(entity);
+            }
+        }
+
+        // Only touched when something changed so that others can use change detection
+        if overlapping == sensor.overlapping {
+            continue;
+        }
+
+        // Sorted so the events don't come in the order the sets happen to iterate in
+        let mut entered: Vec<Entity> = overlapping
+            .difference(&sensor.overlapping)
+            .copied()
+            .collect();
+        entered.sort_unstable();
+        for entity in entered {
+            enter_events.send(SensorEnter {
+                sensor: sensor_entity,
+                entity,
+            });
+        }
+
+        let mut exited: Vec<Entity> = sensor
+            .overlapping
+            .difference(&overlapping)
+            .copied()
+            .collect();
+        exited.sort_unstable();
+        for entity in exited {
+            exit_events.send(SensorExit {
+                sensor: sensor_entity,
+                entity,
+            });
+        }
+
+        sensor.overlapping = overlapping;
+    }
+}
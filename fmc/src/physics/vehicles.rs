@@ -0,0 +1,122 @@
+use bevy::math::DVec3;
+
+use crate::{
+    blocks::{BlockRotation, BlockState, Blocks, Friction},
+    prelude::*,
+    world::WorldMap,
+};
+
+// Blocks with more vertical drag than this are deep enough to float in, same as what the client
+// considers swimmable.
+const LIQUID_DRAG: f64 = 0.4;
+
+/// If the block is a liquid vehicles can float in.
+pub fn is_liquid(world_map: &WorldMap, block_position: IVec3) -> bool {
+    let Some(block_id) = world_map.get_block(block_position) else {
+        return false;
+    };
+
+    match Blocks::get().get_config(&block_id).friction {
+        Friction::Drag(drag) => return drag.y > LIQUID_DRAG,
+        Friction::Static { .. } => return false,
+    }
+}
+
+/// Height of the surface of the liquid at the position. The position can be up to a block above
+/// the surface, so that a floating vehicle keeps track of it while it bobs.
+pub fn liquid_surface(world_map: &WorldMap, position: DVec3) -> Option<f64> {
+    let mut block_position = position.floor().as_ivec3();
+
+    if !is_liquid(world_map, block_position) {
+        block_position.y -= 1;
+        if !is_liquid(world_map, block_position) {
+            return None;
+        }
+    }
+
+    // Find the top of the liquid if the position is submerged
+    while is_liquid(world_map, block_position + IVec3::Y) {
+        block_position.y += 1;
+    }
+
+    return Some(block_position.y as f64 + 1.0);
+}
+
+/// Find the rail block a vehicle at the position is riding on, it is either the block at the
+/// position or the one below it, so that vehicles can go up and down slopes. Returns the block
+/// position and the direction the rail goes in. Vehicles can move both ways along it.
+//
+// TODO: Rails only go straight, there are no curves or junctions.
+pub fn find_rail(world_map: &WorldMap, position: DVec3) -> Option<(IVec3, DVec3)> {
+    let blocks = Blocks::get();
+    let block_position = position.floor().as_ivec3();
+
+    for block_position in [block_position, block_position - IVec3::Y] {
+        let Some(block_id) = world_map.get_block(block_position) else {
+            continue;
+        };
+
+        if !blocks.get_config(&block_id).rail {
+            continue;
+        }
+
+        let rotation = world_map
+            .get_block_state(block_position)
+            .map(BlockState::rotation)
+            .flatten()
+            .map(BlockRotation::as_quat)
+            .unwrap_or_default();
+
+        return Some((block_position, (rotation * DVec3::Z).round()));
+    }
+
+    return None;
+}
+
+/// Move a vehicle that is riding on a rail by `distance` along it, negative distances move it
+/// backwards. The vehicle is kept centered on the rail and on top of it. Returns where the
+/// vehicle ended up, and if the rail ended before it could move the full distance. `None` if the
+/// position is not on a rail.
+pub fn move_along_rail(
+    world_map: &WorldMap,
+    position: DVec3,
+    distance: f64,
+) -> Option<(DVec3, bool)> {
+    let (mut rail_position, direction) = find_rail(world_map, position)?;
+
+    // Move in steps no longer than a block so that no rail is skipped.
+    let mut remaining = distance;
+    let mut position = position;
+    while remaining != 0.0 {
+        let step = remaining.clamp(-1.0, 1.0);
+        remaining -= step;
+
+        let next = position + direction * step;
+        match find_rail(world_map, next)
+            .or_else(|| find_rail(world_map, next + DVec3::Y))
+            .filter(|(_, next_direction)| next_direction.abs() == direction.abs())
+        {
+            Some((next_rail, _)) => {
+                rail_position = next_rail;
+                position = next;
+            }
+            None => {
+                // Stop at the end of the rail
+                let center = rail_position.as_dvec3() + 0.5;
+                let end = center + direction * step.signum() * 0.5;
+                position = position + direction * (end - position).dot(direction);
+                return Some((snap_to_rail(position, rail_position, direction), true));
+            }
+        }
+    }
+
+    return Some((snap_to_rail(position, rail_position, direction), false));
+}
+
+// Center the position on the rail across its direction and put it on top of the rail block.
+fn snap_to_rail(position: DVec3, rail_position: IVec3, direction: DVec3) -> DVec3 {
+    let center = rail_position.as_dvec3() + 0.5;
+    let across = DVec3::ONE - direction.abs() - DVec3::Y;
+    let position = position - (position - center) * across;
+    return position.with_y(rail_position.y as f64);
+}
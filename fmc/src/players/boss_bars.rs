@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use crate::{
+    networking::{properties, ResumedSession, Server},
+    players::Player,
+    prelude::*,
+};
+
+// Bars at the top of the client's screen, sent as the "boss_bar" property. A null bar removes
+// it.
+pub struct BossBarPlugin;
+impl Plugin for BossBarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (insert_boss_bars, forget_sent_boss_bars, send_boss_bars).chain(),
+        );
+    }
+}
+
+/// A progress bar with a text above it, shown at the top of the screen.
+#[derive(Clone, PartialEq)]
+pub struct BossBar {
+    /// How full the bar is, from 0 to 1
+    pub progress: f32,
+    /// Hex color of the bar, e.g. "#c83232"
+    pub color: String,
+    pub text: String,
+}
+
+/// The boss bars shown to the player, e.g. the health of the boss it is fighting, or a countdown.
+/// Inserted on all players. To show a bar to everyone, set it on all of them.
+///
+/// ```ignore
+/// boss_bars.set("dragon", BossBar {
+///     progress: health.fraction(),
+///     color: "#c83232".to_owned(),
+///     text: "Dragon".to_owned(),
+/// });
+/// ...
+/// boss_bars.remove("dragon");
+/// ```
+#[derive(Component, Default)]
+pub struct BossBars {
+    bars: HashMap<String, BossBar>,
+    // What the client was last sent
+    sent: HashMap<String, BossBar>,
+}
+
+impl BossBars {
+    /// Show the bar, or update it if one with the same name is already shown.
+    pub fn set(&mut self, name: &str, boss_bar: BossBar) {
+        self.bars.insert(name.to_owned(), boss_bar);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BossBar> {
+        return self.bars.get(name);
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut BossBar> {
+        return self.bars.get_mut(name);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.bars.remove(name);
+    }
+
+    pub fn clear(&mut self) {
+        self.bars.clear();
+    }
+}
+
+fn insert_boss_bars(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
+    for player_entity in player_query.iter() {
+        commands.entity(player_entity).insert(BossBars::default());
+    }
+}
+
+// A resumed session has a new client that hasn't been sent any bars.
+fn forget_sent_boss_bars(mut player_query: Query<&mut BossBars, Added<ResumedSession>>) {
+    for mut boss_bars in player_query.iter_mut() {
+        boss_bars.sent.clear();
+    }
+}
+
+fn send_boss_bars(
+    net: Res<Server>,
+    mut player_query: Query<(Entity, &mut BossBars), Changed<BossBars>>,
+) {
+    for (player_entity, boss_bars) in player_query.iter_mut() {
+        // Reborrow to make split borrowing work.
+        let boss_bars = boss_bars.into_inner();
+
+        for (name, boss_bar) in boss_bars.bars.iter() {
+            if boss_bars.sent.get(name) == Some(boss_bar) {
+                continue;
+            }
+
+            net.send_property(
+                player_entity,
+                properties::BOSS_BAR,
+                &properties::BossBarUpdate {
+                    name: name.clone(),
+                    bar: Some(properties::BossBar {
+                        progress: boss_bar.progress,
+                        color: boss_bar.color.clone(),
+                        text: boss_bar.text.clone(),
+                    }),
+                },
+            );
+            boss_bars.sent.insert(name.clone(), boss_bar.clone());
+        }
+
+        boss_bars.sent.retain(|name, _| {
+            if boss_bars.bars.contains_key(name) {
+                return true;
+            }
+
+            net.send_property(
+                player_entity,
+                properties::BOSS_BAR,
+                &properties::BossBarUpdate {
+                    name: name.clone(),
+                    bar: None,
+                },
+            );
+            return false;
+        });
+    }
+}
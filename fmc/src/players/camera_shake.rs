@@ -0,0 +1,70 @@
+use crate::{
+    networking::{properties, Server},
+    players::Player,
+    prelude::*,
+};
+
+// Clients are told to shake the camera through the "camera_shake" property. Players can turn
+// camera shake off in their settings, it is only a visual effect.
+pub struct CameraShakePlugin;
+impl Plugin for CameraShakePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CameraShake>()
+            .add_event::<AreaCameraShake>()
+            .add_systems(Update, (shake_area, send_camera_shakes).chain());
+    }
+}
+
+/// Shake the camera of a player, e.g. when it is hurt.
+#[derive(Event)]
+pub struct CameraShake {
+    pub player_entity: Entity,
+    /// Strength of the shake, 1 is a strong shake
+    pub intensity: f32,
+    /// Seconds the shake lasts, it fades out over this time
+    pub duration: f32,
+}
+
+/// Shake the camera of all players around a position, e.g. from an explosion. The intensity
+/// falls off linearly to zero at the radius.
+#[derive(Event)]
+pub struct AreaCameraShake {
+    pub position: DVec3,
+    pub radius: f64,
+    pub intensity: f32,
+    pub duration: f32,
+}
+
+fn shake_area(
+    player_query: Query<(Entity, &GlobalTransform), With<Player>>,
+    mut area_shake_events: EventReader<AreaCameraShake>,
+    mut shake_events: EventWriter<CameraShake>,
+) {
+    for area_shake in area_shake_events.read() {
+        for (player_entity, transform) in player_query.iter() {
+            let distance = transform.translation().distance(area_shake.position);
+            if distance >= area_shake.radius {
+                continue;
+            }
+
+            shake_events.send(CameraShake {
+                player_entity,
+                intensity: area_shake.intensity * (1.0 - distance / area_shake.radius) as f32,
+                duration: area_shake.duration,
+            });
+        }
+    }
+}
+
+fn send_camera_shakes(net: Res<Server>, mut shake_events: EventReader<CameraShake>) {
+    for shake in shake_events.read() {
+        net.send_property(
+            shake.player_entity,
+            properties::CAMERA_SHAKE,
+            &properties::CameraShake {
+                intensity: shake.intensity,
+                duration: shake.duration,
+            },
+        );
+    }
+}
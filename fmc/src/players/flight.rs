@@ -0,0 +1,208 @@
+use bevy::math::DVec3;
+use fmc_protocol::messages;
+
+use crate::{
+    blocks::Blocks,
+    networking::{properties, ResumedSession, Server},
+    physics::{is_liquid, shapes::Aabb, PhysicsConfig, PhysicsOverride, Velocity},
+    players::{Player, Rider},
+    prelude::*,
+    world::WorldMap,
+};
+
+// How long a player can stay in the air without falling before they are considered to be
+// flying. A jump takes about half of this to reach its top. It is longer when gravity is weaker
+// than the default.
+const MAX_HOVER_TIME: f64 = 1.0;
+// How much of the distance gravity would have made it fall the player has to have fallen, to
+// allow for latency and knockback.
+const MIN_FALL_FRACTION: f64 = 0.5;
+
+// The player's movement is simulated by the client, the server checks that players that aren't
+// allowed to fly are affected by gravity.
+pub struct FlightPlugin;
+impl Plugin for FlightPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            check_flight.after(super::handle_player_position_updates),
+        )
+        .add_systems(Update, (insert_flight, send_flight_permission).chain());
+    }
+}
+
+/// Decides if the player is allowed to fly. The client is told when it changes, and players
+/// that fly without being allowed to are moved back to the ground.
+#[derive(Component, Default)]
+pub struct Flight {
+    pub allowed: bool,
+}
+
+// The velocity the client sends can't be trusted, whether the player is falling is decided from
+// how its position has changed.
+#[derive(Component, Default)]
+struct FlightCheck {
+    // Where the player last stood on the ground
+    last_grounded: Option<DVec3>,
+    // How long the player has been in the air
+    air_time: f64,
+    // The highest the player has been since it left the ground
+    peak: f64,
+    // How long the player had been in the air when it reached the peak
+    peak_time: f64,
+}
+
+impl FlightCheck {
+    fn land(&mut self, height: f64) {
+        self.air_time = 0.0;
+        self.peak = height;
+        self.peak_time = 0.0;
+    }
+}
+
+// How far something falls from rest in the time
+fn fall_distance(gravity: f64, terminal_velocity: f64, time: f64) -> f64 {
+    let time_to_terminal = terminal_velocity / gravity;
+    if time <= time_to_terminal {
+        return 0.5 * gravity * time * time;
+    } else {
+        return 0.5 * terminal_velocity * time_to_terminal
+            + terminal_velocity * (time - time_to_terminal);
+    }
+}
+
+fn insert_flight(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
+    for player_entity in player_query.iter() {
+        // The game mode might have already set it
+        commands
+            .entity(player_entity)
+            .insert_if_new(Flight::default())
+            .insert(FlightCheck::default());
+    }
+}
+
+fn send_flight_permission(
+    net: Res<Server>,
+    flight_query: Query<(Entity, &Flight), Or<(Changed<Flight>, Added<ResumedSession>)>>,
+) {
+    for (player_entity, flight) in flight_query.iter() {
+        net.send_property(player_entity, properties::CAN_FLY, &flight.allowed);
+    }
+}
+
+// Returns if the player is standing on something, or is inside a block that lets it move
+// vertically, a climbable block like a ladder or a liquid it can swim in. Blocks like tall grass
+// are not solid either, but they don't hold the player up.
+fn is_supported(world_map: &WorldMap, aabb: &Aabb) -> bool {
+    let blocks = Blocks::get();
+    let min = aabb.min();
+    let max = aabb.max();
+
+    for x in min.x.floor() as i32..=max.x.floor() as i32 {
+        for z in min.z.floor() as i32..=max.z.floor() as i32 {
+            // Loaded chunks are required to move, if they're missing there's nothing to check
+            // against.
+            let Some(below) = world_map.get_block(IVec3::new(x, (min.y - 0.05).floor() as i32, z))
+            else {
+                return true;
+            };
+            if blocks.get_config(&below).is_solid() {
+                return true;
+            }
+
+            for y in min.y.floor() as i32..=max.y.floor() as i32 {
+                let block_position = IVec3::new(x, y, z);
+                let Some(block_id) = world_map.get_block(block_position) else {
+                    return true;
+                };
+                if blocks.get_config(&block_id).climbable || is_liquid(world_map, block_position) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    return false;
+}
+
+fn check_flight(
+    net: Res<Server>,
+    time: Res<Time>,
+    world_map: Res<WorldMap>,
+    physics_config: Res<PhysicsConfig>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &Aabb,
+            &Flight,
+            &mut FlightCheck,
+            Option<&PhysicsOverride>,
+        ),
+        Without<Rider>,
+    >,
+) {
+    let default_gravity = PhysicsConfig::default().gravity.length();
+
+    for (player_entity, mut transform, mut velocity, aabb, flight, mut check, physics_override) in
+        player_query.iter_mut()
+    {
+        let height = transform.translation.y;
+
+        if flight.allowed || !height.is_finite() {
+            check.land(height);
+            continue;
+        }
+
+        let aabb = Aabb {
+            center: aabb.center + transform.translation,
+            half_extents: aabb.half_extents,
+        };
+
+        if is_supported(&world_map, &aabb) {
+            check.land(height);
+            check.last_grounded = Some(transform.translation);
+            continue;
+        }
+
+        check.air_time += time.delta_secs_f64();
+        if height > check.peak {
+            check.peak = height;
+            check.peak_time = check.air_time;
+        }
+
+        let config = physics_config.with_override(physics_override);
+        let gravity = config.gravity.length();
+        let max_hover_time = MAX_HOVER_TIME * (default_gravity / gravity).max(1.0);
+
+        // It can go up for as long as a jump takes, and must then fall. It is given time to
+        // start falling before it has to keep up with gravity.
+        let falling_time = check.air_time - check.peak_time - max_hover_time;
+        let rose_too_long = check.peak_time > max_hover_time;
+        let fell_too_slowly = falling_time > 0.0
+            && check.peak - height
+                < MIN_FALL_FRACTION
+                    * fall_distance(gravity, config.terminal_velocity, falling_time);
+
+        if !rose_too_long && !fell_too_slowly {
+            continue;
+        }
+
+        check.land(height);
+
+        let Some(last_grounded) = check.last_grounded else {
+            continue;
+        };
+
+        transform.translation = last_grounded;
+        velocity.0 = DVec3::ZERO;
+        net.send_one(
+            player_entity,
+            messages::PlayerPosition {
+                position: last_grounded,
+                velocity: DVec3::ZERO,
+            },
+        );
+    }
+}
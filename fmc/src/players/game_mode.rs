@@ -0,0 +1,278 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::ChatCommand,
+    items::CreativeCatalogue,
+    networking::{properties, ResumedSession, Server},
+    players::{Flight, Operator, Player},
+    prelude::*,
+};
+
+pub struct GameModePlugin;
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DefaultGameMode>()
+            .add_event::<PlayerDeath>()
+            .add_event::<DeathDrops>()
+            .add_systems(
+                Update,
+                (
+                    (
+                        insert_game_mode,
+                        handle_game_mode_commands,
+                        apply_game_mode_changes,
+                    )
+                        .chain(),
+                    send_death_drops,
+                    send_death_position,
+                ),
+            );
+    }
+}
+
+/// How a player is allowed to interact with the world. Change the component to switch the
+/// player's game mode.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GameMode {
+    #[default]
+    Survival,
+    Creative,
+    /// Like survival, but blocks can't be changed.
+    Adventure,
+    /// Flies through the world without interacting with it.
+    Spectator,
+}
+
+/// What the game mode allows. Server implementations should consult these instead of matching
+/// on the game mode.
+#[derive(Clone, Copy, Debug)]
+pub struct GameModeCapabilities {
+    /// Blocks break on the first hit, see `world::BlockPermissions::break_time`.
+    pub can_break_instantly: bool,
+    /// The player is allowed to place and break blocks. Block changes from players that aren't
+    /// are dropped, see `world::BlockPermissions`.
+    pub can_modify_blocks: bool,
+    /// The player can toggle flight.
+    pub can_fly: bool,
+    /// The player can be hurt.
+    pub takes_damage: bool,
+    /// The player's items are dropped when it dies, see `PlayerDeath`.
+    pub drops_items_on_death: bool,
+    /// The player has access to the creative catalogue, see `items::CreativeCatalogue`.
+    pub has_catalogue: bool,
+    /// The player can undo the blocks it has changed, see `world::BlockHistory`.
+    pub can_undo: bool,
+}
+
+impl GameMode {
+    pub fn capabilities(&self) -> GameModeCapabilities {
+        match self {
+            Self::Survival => GameModeCapabilities {
+                can_break_instantly: false,
+                can_modify_blocks: true,
+                can_fly: false,
+                takes_damage: true,
+                drops_items_on_death: true,
+                has_catalogue: false,
+                can_undo: false,
+            },
+            Self::Creative => GameModeCapabilities {
+                can_break_instantly: true,
+                can_modify_blocks: true,
+                can_fly: true,
+                takes_damage: false,
+                drops_items_on_death: false,
+                has_catalogue: true,
+                can_undo: true,
+            },
+            Self::Adventure => GameModeCapabilities {
+                can_break_instantly: false,
+                can_modify_blocks: false,
+                can_fly: false,
+                takes_damage: true,
+                drops_items_on_death: true,
+                has_catalogue: false,
+                can_undo: false,
+            },
+            Self::Spectator => GameModeCapabilities {
+                can_break_instantly: false,
+                can_modify_blocks: false,
+                can_fly: true,
+                takes_damage: false,
+                drops_items_on_death: false,
+                has_catalogue: false,
+                can_undo: false,
+            },
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Survival => "survival",
+            Self::Creative => "creative",
+            Self::Adventure => "adventure",
+            Self::Spectator => "spectator",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let game_mode = match name {
+            "survival" => Self::Survival,
+            "creative" => Self::Creative,
+            "adventure" => Self::Adventure,
+            "spectator" => Self::Spectator,
+            _ => return None,
+        };
+        return Some(game_mode);
+    }
+}
+
+/// Send when a player dies, before it is respawned. The game handles the death itself, this only
+/// makes the core react to it, e.g. with `DeathDrops`.
+#[derive(Event)]
+pub struct PlayerDeath {
+    pub player_entity: Entity,
+}
+
+/// Sent after a `PlayerDeath` when the player's game mode drops its items on death. Drop the
+/// items of the player's inventory when it is received.
+#[derive(Event)]
+pub struct DeathDrops {
+    pub player_entity: Entity,
+}
+
+/// The game mode players are given when they join.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct DefaultGameMode(pub GameMode);
+
+fn insert_game_mode(
+    mut commands: Commands,
+    default_game_mode: Res<DefaultGameMode>,
+    player_query: Query<Entity, (Added<Player>, Without<GameMode>)>,
+) {
+    for player_entity in player_query.iter() {
+        commands.entity(player_entity).insert(default_game_mode.0);
+    }
+}
+
+// "/gamemode <mode> [player]", only operators can use it.
+fn handle_game_mode_commands(
+    net: Res<Server>,
+    operator_query: Query<(), With<Operator>>,
+    mut player_query: Query<(Entity, &Player, &mut GameMode)>,
+    mut command_events: EventReader<ChatCommand>,
+) {
+    for command in command_events.read() {
+        if command.name != "gamemode" {
+            continue;
+        }
+
+        if !operator_query.contains(command.player_entity) {
+            command.reply(&net, "You are not allowed to change game modes.");
+            continue;
+        }
+
+        let Some(game_mode) = command
+            .args
+            .first()
+            .and_then(|name| GameMode::from_name(name))
+        else {
+            command.reply(
+                &net,
+                "Usage: /gamemode <survival|creative|adventure|spectator> [player]",
+            );
+            continue;
+        };
+
+        let target = match command.args.get(1) {
+            Some(username) => player_query
+                .iter_mut()
+                .find(|(_, player, _)| &player.username == username),
+            None => player_query.get_mut(command.player_entity).ok(),
+        };
+
+        let Some((target_entity, player, mut target_game_mode)) = target else {
+            command.reply(&net, "No player by that name.");
+            continue;
+        };
+
+        *target_game_mode = game_mode;
+
+        command.reply(
+            &net,
+            format!(
+                "Set the game mode of {} to {}",
+                player.username,
+                game_mode.name()
+            ),
+        );
+        if target_entity != command.player_entity {
+            crate::chat::send_private_message(
+                &net,
+                target_entity,
+                format!("Your game mode was set to {}", game_mode.name()),
+            );
+        }
+    }
+}
+
+fn apply_game_mode_changes(
+    mut commands: Commands,
+    net: Res<Server>,
+    player_query: Query<(Entity, &GameMode), Or<(Changed<GameMode>, Added<ResumedSession>)>>,
+) {
+    for (player_entity, game_mode) in player_query.iter() {
+        let capabilities = game_mode.capabilities();
+
+        if capabilities.has_catalogue {
+            commands.entity(player_entity).insert(CreativeCatalogue);
+        } else {
+            commands.entity(player_entity).remove::<CreativeCatalogue>();
+        }
+
+        commands.entity(player_entity).insert(Flight {
+            allowed: capabilities.can_fly,
+        });
+
+        net.send_property(player_entity, properties::GAME_MODE, game_mode.name());
+    }
+}
+
+fn send_death_drops(
+    player_query: Query<Option<&GameMode>, With<Player>>,
+    mut death_events: EventReader<PlayerDeath>,
+    mut drop_events: EventWriter<DeathDrops>,
+) {
+    for death in death_events.read() {
+        let Ok(game_mode) = player_query.get(death.player_entity) else {
+            continue;
+        };
+
+        if game_mode.map_or(true, |game_mode| {
+            game_mode.capabilities().drops_items_on_death
+        }) {
+            drop_events.send(DeathDrops {
+                player_entity: death.player_entity,
+            });
+        }
+    }
+}
+
+// Lets the client mark where the player died on its map.
+fn send_death_position(
+    net: Res<Server>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    mut death_events: EventReader<PlayerDeath>,
+) {
+    for death in death_events.read() {
+        let Ok(transform) = player_query.get(death.player_entity) else {
+            continue;
+        };
+
+        net.send_property(
+            death.player_entity,
+            properties::DEATH,
+            &transform.translation().to_array(),
+        );
+    }
+}
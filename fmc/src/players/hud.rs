@@ -0,0 +1,112 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    networking::{properties, ResumedSession, Server},
+    players::Player,
+    prelude::*,
+};
+
+// The client draws some of the hud itself, these are the parts of it the server can change. They
+// are sent as properties, see the client's hud module for the format.
+pub struct HudPlugin;
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (insert_hud, send_hud).chain());
+    }
+}
+
+/// How the client should draw the player's hud. Inserted on all players, the client resets it
+/// when it leaves the server.
+#[derive(Component, Clone, PartialEq)]
+pub struct Hud {
+    /// How many of the item boxes in the hotbar can be used, the rest are hidden. None for all of
+    /// them.
+    pub hotbar_slots: Option<usize>,
+    pub crosshair: Crosshair,
+    /// If the equipped item is shown in the player's hand
+    pub show_hand: bool,
+    /// Elements of the client's hud that are hidden, "hunger" and "minimap". Interfaces sent by
+    /// the server are hidden with their visibility instead.
+    pub hidden: BTreeSet<String>,
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Self {
+            hotbar_slots: None,
+            crosshair: Crosshair::None,
+            show_hand: true,
+            hidden: BTreeSet::new(),
+        }
+    }
+}
+
+/// A crosshair drawn by the client, for servers that don't have one in their interfaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crosshair {
+    None,
+    Dot,
+    Cross,
+}
+
+impl Crosshair {
+    fn name(&self) -> &'static str {
+        return match self {
+            Self::None => "none",
+            Self::Dot => "dot",
+            Self::Cross => "cross",
+        };
+    }
+}
+
+// The last hud that was sent to the player, only the parts that change are sent again. A resumed
+// session has a new client, so it starts over.
+#[derive(Component, Default)]
+struct SentHud(Option<Hud>);
+
+fn insert_hud(
+    mut commands: Commands,
+    player_query: Query<Entity, Or<(Added<Player>, Added<ResumedSession>)>>,
+) {
+    for player_entity in player_query.iter() {
+        commands
+            .entity(player_entity)
+            .insert_if_new(Hud::default())
+            .insert(SentHud::default());
+    }
+}
+
+fn send_hud(
+    net: Res<Server>,
+    mut player_query: Query<
+        (Entity, &Hud, &mut SentHud),
+        Or<(Changed<Hud>, Added<ResumedSession>)>,
+    >,
+) {
+    for (player_entity, hud, mut sent) in player_query.iter_mut() {
+        // The client starts out with the default, there's nothing to send until it changes.
+        let previous = sent.0.clone().unwrap_or_default();
+        if previous == *hud {
+            sent.0 = Some(hud.clone());
+            continue;
+        }
+
+        if previous.hotbar_slots != hud.hotbar_slots {
+            net.send_property(player_entity, properties::HOTBAR_SLOTS, &hud.hotbar_slots);
+        }
+
+        if previous.crosshair != hud.crosshair {
+            net.send_property(player_entity, properties::CROSSHAIR, hud.crosshair.name());
+        }
+
+        if previous.show_hand != hud.show_hand {
+            net.send_property(player_entity, properties::HAND, &hud.show_hand);
+        }
+
+        if previous.hidden != hud.hidden {
+            net.send_property(player_entity, properties::HIDDEN_HUD, &hud.hidden);
+        }
+
+        sent.0 = Some(hud.clone());
+    }
+}
@@ -0,0 +1,280 @@
+use bevy::math::DVec3;
+
+use crate::{
+    networking::{properties, ResumedSession, Server},
+    players::{GameMode, MovementState, Player},
+    prelude::*,
+};
+
+// Players grow hungry as they exert themselves. Exhaustion is accrued from sprinting and from
+// the actions games report through `Exhaust` events, and every `exhaustion_per_point` of it
+// costs one point of saturation, or hunger once the saturation is empty. The library has no
+// health, games connect it through the `HungerRegeneration` and `Starvation` events.
+pub struct HungerPlugin;
+impl Plugin for HungerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HungerSettings>()
+            .add_event::<Exhaust>()
+            .add_event::<HungerRegeneration>()
+            .add_event::<Starvation>()
+            .add_systems(
+                Update,
+                (
+                    (insert_hunger, forget_sent_hunger),
+                    (exhaust_sprinting, handle_exhaust_events),
+                    tick_hunger,
+                    send_hunger,
+                )
+                    .chain()
+                    .run_if(|settings: Res<HungerSettings>| settings.enabled),
+            );
+    }
+}
+
+/// Configuration of hunger, insert it to change the defaults. Hunger is disabled by default, games
+/// that want it set `enabled` to true.
+#[derive(Resource, Clone)]
+pub struct HungerSettings {
+    pub enabled: bool,
+    /// Hunger of a player that has eaten its fill.
+    pub max_hunger: f32,
+    /// How much exhaustion it takes to lose a point of saturation or hunger. Must be above zero,
+    /// smaller values are treated as 0.001.
+    pub exhaustion_per_point: f32,
+    /// Exhaustion per block sprinted.
+    pub sprint_exhaustion: f32,
+    /// Exhaustion from `Exhaustion::BreakBlock`.
+    pub break_block_exhaustion: f32,
+    /// Exhaustion from `Exhaustion::Attack`.
+    pub attack_exhaustion: f32,
+    /// Players regenerate health when their hunger is at or above this.
+    pub regeneration_threshold: f32,
+    /// Exhaustion from each health point regenerated.
+    pub regeneration_exhaustion: f32,
+    /// Seconds between each regenerated health point, and between each time a starving player
+    /// takes damage.
+    pub interval: f32,
+}
+
+impl Default for HungerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_hunger: 20.0,
+            exhaustion_per_point: 4.0,
+            sprint_exhaustion: 0.1,
+            break_block_exhaustion: 0.005,
+            attack_exhaustion: 0.1,
+            regeneration_threshold: 18.0,
+            regeneration_exhaustion: 6.0,
+            interval: 4.0,
+        }
+    }
+}
+
+// Lower bound of the exhaustion per point, at zero every bit of exhaustion would be endless points.
+const MIN_EXHAUSTION_PER_POINT: f32 = 0.001;
+
+/// How hungry a player is. Inserted on all players when hunger is enabled.
+#[derive(Component)]
+pub struct Hunger {
+    /// From 0.0, starving, to `HungerSettings::max_hunger`.
+    pub hunger: f32,
+    /// Spent before the hunger, food gives it in addition to hunger.
+    pub saturation: f32,
+    /// Set to true by the game while the player is missing health. Health is only regenerated,
+    /// and hunger spent on it, while it is set.
+    pub wants_regeneration: bool,
+    exhaustion: f32,
+    timer: f32,
+    // Used to measure how far the player has sprinted
+    last_position: Option<DVec3>,
+    // The hunger value the client was last told of
+    sent: Option<u32>,
+}
+
+impl Hunger {
+    pub fn new(settings: &HungerSettings) -> Self {
+        Self {
+            hunger: settings.max_hunger,
+            saturation: 5.0,
+            wants_regeneration: false,
+            exhaustion: 0.0,
+            timer: 0.0,
+            last_position: None,
+            sent: None,
+        }
+    }
+
+    /// Feed the player, e.g. when it has finished eating food. Saturation can't exceed the
+    /// hunger.
+    pub fn feed(&mut self, settings: &HungerSettings, hunger: f32, saturation: f32) {
+        self.hunger = (self.hunger + hunger).min(settings.max_hunger);
+        self.saturation = (self.saturation + saturation).min(self.hunger);
+    }
+
+    pub fn is_starving(&self) -> bool {
+        return self.hunger <= 0.0;
+    }
+
+    fn exhaust(&mut self, amount: f32) {
+        self.exhaustion += amount;
+    }
+}
+
+/// What made the player exhausted.
+pub enum Exhaustion {
+    BreakBlock,
+    Attack,
+    Custom(f32),
+}
+
+/// Send to make the player hungrier, e.g. when it breaks a block.
+#[derive(Event)]
+pub struct Exhaust {
+    pub player_entity: Entity,
+    pub exhaustion: Exhaustion,
+}
+
+/// Sent when a well fed player should regenerate a point of health. The hunger has already been
+/// spent.
+#[derive(Event)]
+pub struct HungerRegeneration {
+    pub player_entity: Entity,
+}
+
+/// Sent when a starving player should take a point of damage.
+#[derive(Event)]
+pub struct Starvation {
+    pub player_entity: Entity,
+}
+
+fn insert_hunger(
+    mut commands: Commands,
+    settings: Res<HungerSettings>,
+    player_query: Query<Entity, (Added<Player>, Without<Hunger>)>,
+) {
+    for player_entity in player_query.iter() {
+        commands
+            .entity(player_entity)
+            .insert(Hunger::new(&settings));
+    }
+}
+
+// A resumed session has a new client that hasn't been told of the hunger.
+fn forget_sent_hunger(mut player_query: Query<&mut Hunger, Added<ResumedSession>>) {
+    for mut hunger in player_query.iter_mut() {
+        hunger.sent = None;
+    }
+}
+
+// Players in game modes that can't be hurt don't grow hungry.
+fn is_exhaustible(game_mode: Option<&GameMode>) -> bool {
+    return game_mode.map_or(true, |game_mode| game_mode.capabilities().takes_damage);
+}
+
+fn exhaust_sprinting(
+    settings: Res<HungerSettings>,
+    mut player_query: Query<(&Transform, &MovementState, Option<&GameMode>, &mut Hunger)>,
+) {
+    for (transform, state, game_mode, mut hunger) in player_query.iter_mut() {
+        let position = transform.translation;
+        let Some(last_position) = hunger.last_position.replace(position) else {
+            continue;
+        };
+
+        if !state.is_sprinting || !is_exhaustible(game_mode) {
+            continue;
+        }
+
+        // Only horizontal movement, falling is not exhausting.
+        let distance = (position - last_position).with_y(0.0).length() as f32;
+        // Teleports shouldn't count
+        if distance < 10.0 {
+            hunger.exhaust(distance * settings.sprint_exhaustion);
+        }
+    }
+}
+
+fn handle_exhaust_events(
+    settings: Res<HungerSettings>,
+    mut player_query: Query<(Option<&GameMode>, &mut Hunger)>,
+    mut exhaust_events: EventReader<Exhaust>,
+) {
+    for exhaust in exhaust_events.read() {
+        let Ok((game_mode, mut hunger)) = player_query.get_mut(exhaust.player_entity) else {
+            continue;
+        };
+
+        if !is_exhaustible(game_mode) {
+            continue;
+        }
+
+        let amount = match exhaust.exhaustion {
+            Exhaustion::BreakBlock => settings.break_block_exhaustion,
+            Exhaustion::Attack => settings.attack_exhaustion,
+            Exhaustion::Custom(amount) => amount,
+        };
+        hunger.exhaust(amount);
+    }
+}
+
+fn tick_hunger(
+    time: Res<Time>,
+    settings: Res<HungerSettings>,
+    mut player_query: Query<(Entity, Option<&GameMode>, &mut Hunger)>,
+    mut regeneration_events: EventWriter<HungerRegeneration>,
+    mut starvation_events: EventWriter<Starvation>,
+) {
+    for (player_entity, game_mode, mut hunger) in player_query.iter_mut() {
+        let exhaustion_per_point = settings.exhaustion_per_point.max(MIN_EXHAUSTION_PER_POINT);
+        let points = (hunger.exhaustion / exhaustion_per_point).floor();
+        if points > 0.0 {
+            hunger.exhaustion -= points * exhaustion_per_point;
+            // Each point is taken from the saturation while there is any left, a partial point
+            // of saturation is spent as a whole one.
+            let from_saturation = points.min(hunger.saturation.ceil());
+            hunger.saturation = (hunger.saturation - points).max(0.0);
+            hunger.hunger = (hunger.hunger - (points - from_saturation)).max(0.0);
+        }
+
+        hunger.timer += time.delta_secs();
+        if hunger.timer < settings.interval {
+            continue;
+        }
+        hunger.timer = 0.0;
+
+        if hunger.is_starving() {
+            if is_exhaustible(game_mode) {
+                starvation_events.send(Starvation { player_entity });
+            }
+        } else if hunger.wants_regeneration && hunger.hunger >= settings.regeneration_threshold {
+            hunger.exhaust(settings.regeneration_exhaustion);
+            regeneration_events.send(HungerRegeneration { player_entity });
+        }
+    }
+}
+
+// The client shows the hunger and the most it can be when it is told of it.
+fn send_hunger(
+    net: Res<Server>,
+    settings: Res<HungerSettings>,
+    mut player_query: Query<(Entity, &mut Hunger)>,
+) {
+    for (player_entity, mut hunger) in player_query.iter_mut() {
+        let value = hunger.hunger.ceil() as u32;
+        if hunger.sent == Some(value) {
+            continue;
+        }
+
+        hunger.sent = Some(value);
+        net.send_property(
+            player_entity,
+            properties::HUNGER,
+            &properties::Hunger {
+                hunger: value,
+                max: settings.max_hunger.ceil() as u32,
+            },
+        );
+    }
+}
@@ -7,16 +7,66 @@ use crate::{
     blocks::{BlockFace, BlockId, BlockPosition, BlockRotation, BlockState, Blocks, Friction},
     interfaces::InterfaceNodes,
     models::ModelMap,
-    networking::{NetworkMessage, Server},
-    physics::{shapes::Aabb, Velocity},
+    networking::{NetworkMessage, ResumedSession, Server},
+    physics::{shapes::Aabb, CollisionGroups, HazardImmunity, Velocity},
     utils,
     world::{chunk::Chunk, RenderDistance, WorldMap},
 };
 
+mod boss_bars;
+mod camera_shake;
+mod flight;
+mod game_mode;
+mod hud;
+mod hunger;
+mod mounting;
+mod movement;
+mod music;
+mod notifications;
+mod reach;
+mod respawn;
+mod saves;
+mod vehicles;
+
+pub use boss_bars::{BossBar, BossBars};
+pub use camera_shake::{AreaCameraShake, CameraShake};
+pub use flight::Flight;
+pub use game_mode::{DeathDrops, DefaultGameMode, GameMode, GameModeCapabilities, PlayerDeath};
+pub use hud::{Crosshair, Hud};
+pub use hunger::{Exhaust, Exhaustion, Hunger, HungerRegeneration, HungerSettings, Starvation};
+pub use mounting::{DismountEntity, Dismounted, Mount, MountEntity, Mounted, Rider, RiderInput};
+pub use movement::{MovementAnimations, MovementState};
+pub use music::MusicTags;
+pub use notifications::Notification;
+pub use reach::{OutOfReach, Reach};
+pub use respawn::{
+    default_spawn_predicate, find_spawn_point, RespawnPoint, RespawnRequest, RespawnSettings,
+    SpawnPredicate,
+};
+pub use saves::{OfflinePlayerError, OfflinePlayerSave, OfflinePlayers, PlayerSave};
+pub use vehicles::{Vehicle, VehicleKind};
+
 pub struct PlayersPlugin;
 impl Plugin for PlayersPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, send_aabb).add_systems(
+        app.add_plugins((
+            respawn::RespawnPlugin,
+            game_mode::GameModePlugin,
+            flight::FlightPlugin,
+            movement::MovementPlugin,
+            hunger::HungerPlugin,
+            mounting::MountingPlugin,
+            vehicles::VehiclePlugin,
+            music::MusicPlugin,
+            camera_shake::CameraShakePlugin,
+            hud::HudPlugin,
+            boss_bars::BossBarPlugin,
+            notifications::NotificationPlugin,
+            reach::ReachPlugin,
+            saves::PlayerSavePlugin,
+        ))
+        .add_systems(Update, send_aabb)
+        .add_systems(
             PreUpdate,
             (
                 handle_player_position_updates,
@@ -31,9 +81,18 @@ impl Plugin for PlayersPlugin {
 
 #[derive(Component, Default)]
 pub struct Player {
+    /// Name shown to other players. Not unique over time, use the account id to identify players.
     pub username: String,
+    /// Stable identifier of the player's account. In offline mode it is the same as the
+    /// username. See `networking::Authentication`
+    pub account_id: String,
 }
 
+/// Marks players that have administrative rights, e.g. they can change blocks in the spawn
+/// protection area. Server implementations decide who is given it.
+#[derive(Component, Default)]
+pub struct Operator;
+
 // TODO: The reason for the awkward wrapping is wanting to have the camera be part of the player
 // entity. Because of this it needs to be translated wherever it is used. Would be nice with a
 // system that propagates it like with normal transforms.
@@ -72,26 +131,39 @@ pub struct DefaultPlayerBundle {
     camera: Camera,
     targets: Targets,
     aabb: Aabb,
+    collision_groups: CollisionGroups,
     interfaces: InterfaceNodes,
+    hazard_immunity: HazardImmunity,
 }
 
 impl DefaultPlayerBundle {
-    pub fn new(username: String) -> Self {
+    pub fn new(username: String, account_id: String) -> Self {
         Self {
-            player: Player { username },
-            render_distance: RenderDistance { chunks: 1 },
+            player: Player {
+                username,
+                account_id,
+            },
+            render_distance: RenderDistance {
+                chunks: 1,
+                vertical: 1,
+            },
             global_transform: GlobalTransform::default(),
             transform: Transform::default(),
             camera: Camera::default(),
             targets: Targets::default(),
             velocity: Velocity::default(),
             aabb: Aabb::from_min_max(DVec3::new(-0.3, 0.0, -0.3), DVec3::new(0.3, 1.8, 0.3)),
+            collision_groups: CollisionGroups::new(CollisionGroups::PLAYERS, CollisionGroups::ALL),
             interfaces: InterfaceNodes::default(),
+            hazard_immunity: HazardImmunity::default(),
         }
     }
 }
 
-fn send_aabb(net: Res<Server>, aabb_query: Query<(Entity, &Aabb), (Changed<Aabb>, With<Player>)>) {
+fn send_aabb(
+    net: Res<Server>,
+    aabb_query: Query<(Entity, &Aabb), (Or<(Changed<Aabb>, Added<ResumedSession>)>, With<Player>)>,
+) {
     for (entity, aabb) in aabb_query.iter() {
         net.send_one(
             entity,
@@ -133,11 +205,21 @@ fn handle_camera_rotation_updates(
 
 /// Contains what the player is looking at, sorted by the distance from the camera.
 /// The scan for targets will stop at the first entity it hits with an aabb or the first block that
-/// is solid.
+/// is solid. Only what is within the player's `Reach` is included.
 #[derive(Component, Deref, DerefMut, Debug, Default)]
-pub struct Targets(Vec<Target>);
+pub struct Targets {
+    #[deref]
+    targets: Vec<Target>,
+    // The closest thing the player looked at if it was just beyond its reach
+    out_of_reach: Option<Target>,
+}
 
 impl Targets {
+    /// What the player is looking at if it is too far away to reach, and there is nothing closer.
+    pub fn out_of_reach(&self) -> Option<&Target> {
+        return self.out_of_reach.as_ref();
+    }
+
     /// Get the first block that matches the provided condition
     pub fn get_first_block<F>(&self, f: F) -> Option<&Target>
     where
@@ -158,7 +240,7 @@ impl Targets {
     }
 }
 /// Tracks what the player is currently looking at
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Target {
     Entity {
         /// Distance to the target from the camera
@@ -195,7 +277,12 @@ impl Target {
     }
 }
 
+// How far beyond the reach the player's targets are looked for, to tell when it clicks on
+// something that is out of reach.
+const OUT_OF_REACH_MARGIN: f64 = 3.0;
+
 fn find_target(
+    reach: Res<Reach>,
     world_map: Res<WorldMap>,
     model_map: Res<ModelMap>,
     model_query: Query<(
@@ -203,13 +290,24 @@ fn find_target(
         Option<&Aabb>,
         Option<&BlockPosition>,
         &GlobalTransform,
+        Option<&CollisionGroups>,
+    )>,
+    mut player_query: Query<(
+        &mut Targets,
+        &Camera,
+        &Transform,
+        Option<&CollisionGroups>,
+        Option<&GameMode>,
     )>,
-    mut player_query: Query<(&mut Targets, &Camera, &Transform)>,
 ) {
     let blocks = Blocks::get();
 
-    for (mut targets, camera, transform) in player_query.iter_mut() {
+    for (mut targets, camera, transform, player_groups, game_mode) in player_query.iter_mut() {
+        let player_groups = player_groups.copied().unwrap_or_default();
+        let reach = reach.get(game_mode.copied().unwrap_or_default());
+
         targets.clear();
+        targets.out_of_reach = None;
 
         let camera_transform = Transform {
             translation: transform.translation + camera.translation,
@@ -234,9 +332,14 @@ fn find_target(
                     let Some(model_entities) = model_map.get_entities(&chunk_position) else {
                         continue;
                     };
-                    for (entity, maybe_aabb, maybe_block, model_transform) in
+                    for (entity, maybe_aabb, maybe_block, model_transform, groups) in
                         model_query.iter_many(model_entities)
                     {
+                        // Things the player passes through, like ghosts, can't be targeted
+                        if !player_groups.interacts_with(&groups.copied().unwrap_or_default()) {
+                            continue;
+                        }
+
                         let new_target = if let Some(block_position) = maybe_block {
                             let Some(block_id) = world_map.get_block(block_position.0) else {
                                 continue;
@@ -279,6 +382,10 @@ fn find_target(
                             continue;
                         };
 
+                        if new_target.distance() > reach + OUT_OF_REACH_MARGIN {
+                            continue;
+                        }
+
                         if new_target.distance() < min_distance {
                             min_distance = new_target.distance();
                             model_target = Some(new_target);
@@ -292,7 +399,7 @@ fn find_target(
             targets.push(model_target);
         }
 
-        let mut raycast = world_map.raycast(&camera_transform, 5.0);
+        let mut raycast = world_map.raycast(&camera_transform, reach + OUT_OF_REACH_MARGIN);
         while let Some(block_id) = raycast.next_block() {
             let block_config = blocks.get_config(&block_id);
 
@@ -342,5 +449,14 @@ fn find_target(
         }
 
         targets.sort_unstable_by(|a, b| a.distance().partial_cmp(&b.distance()).unwrap());
+
+        if let Some(index) = targets.iter().position(|target| target.distance() > reach) {
+            // Only if there is nothing within reach in front of it
+            if index == 0 {
+                let target = targets[0].clone();
+                targets.out_of_reach = Some(target);
+            }
+            targets.truncate(index);
+        }
     }
 }
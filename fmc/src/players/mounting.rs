@@ -0,0 +1,346 @@
+use bevy::{math::DVec3, transform::TransformSystem};
+use fmc_protocol::messages;
+
+use crate::{
+    networking::{
+        properties, ClientProperty, NetworkMessage, ResumedSession, Server, TransferPlayer,
+    },
+    players::{Player, RespawnPoint, Vehicle},
+    prelude::*,
+};
+
+// Entities ride mounts by having their transform follow the mount's. The server does it for
+// players too, their clients stop simulating their own movement while riding and send what
+// direction they want the mount to go instead. Games move the mount from the `RiderInput`.
+pub struct MountingPlugin;
+impl Plugin for MountingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MountEntity>()
+            .add_event::<DismountEntity>()
+            .add_event::<Mounted>()
+            .add_event::<Dismounted>()
+            .add_event::<RiderInput>()
+            .add_systems(
+                Update,
+                (
+                    (handle_rider_properties, dismount_on_respawn_and_transfer),
+                    dismount_removed,
+                    handle_dismounts,
+                    handle_mounts,
+                    resend_riding,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                follow_mounts.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// An entity that can be ridden.
+#[derive(Component)]
+pub struct Mount {
+    /// Where the rider sits, relative to the mount. It is rotated with the mount.
+    pub seat: DVec3,
+    /// Where the rider is placed when it dismounts, relative to the mount.
+    pub exit: DVec3,
+    rider: Option<Entity>,
+}
+
+impl Mount {
+    pub fn new(seat: DVec3, exit: DVec3) -> Self {
+        Self {
+            seat,
+            exit,
+            rider: None,
+        }
+    }
+
+    pub fn rider(&self) -> Option<Entity> {
+        return self.rider;
+    }
+}
+
+/// Added to entities while they ride a mount. Use `MountEntity` and `DismountEntity` to change
+/// it.
+#[derive(Component)]
+pub struct Rider {
+    mount: Entity,
+}
+
+impl Rider {
+    pub fn mount(&self) -> Entity {
+        return self.mount;
+    }
+}
+
+/// Send to make an entity ride a mount. Ignored if the mount already has a rider.
+#[derive(Event)]
+pub struct MountEntity {
+    pub rider: Entity,
+    pub mount: Entity,
+}
+
+/// Send to make an entity get off its mount.
+#[derive(Event)]
+pub struct DismountEntity {
+    pub rider: Entity,
+}
+
+/// Sent when an entity has started riding a mount.
+#[derive(Event)]
+pub struct Mounted {
+    pub rider: Entity,
+    pub mount: Entity,
+}
+
+/// Sent when an entity has stopped riding a mount. The entities might have been despawned.
+#[derive(Event)]
+pub struct Dismounted {
+    pub rider: Entity,
+    pub mount: Entity,
+}
+
+/// Movement input from a player riding a mount.
+#[derive(Event)]
+pub struct RiderInput {
+    pub rider: Entity,
+    pub mount: Entity,
+    /// The horizontal direction the player wants to go, it is zero or normalized.
+    pub direction: DVec3,
+    pub jump: bool,
+}
+
+// Players send "mount_input" with the direction they want to go, and "dismount" when they want
+// to get off.
+fn handle_rider_properties(
+    rider_query: Query<&Rider>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+    mut input_events: EventWriter<RiderInput>,
+    mut dismount_events: EventWriter<DismountEntity>,
+) {
+    for property in property_events.read() {
+        let Ok(rider) = rider_query.get(property.player_entity) else {
+            continue;
+        };
+
+        match property.name.as_str() {
+            properties::MOUNT_INPUT => {
+                let Some(input) = property.parse::<properties::MountInput>() else {
+                    continue;
+                };
+
+                input_events.send(RiderInput {
+                    rider: property.player_entity,
+                    mount: rider.mount,
+                    direction: DVec3::new(input.x, 0.0, input.z).normalize_or_zero(),
+                    jump: input.jump,
+                });
+            }
+            properties::DISMOUNT => {
+                dismount_events.send(DismountEntity {
+                    rider: property.player_entity,
+                });
+            }
+            _ => (),
+        }
+    }
+}
+
+// Players that respawn or leave for another server get off their mount. They are not moved to
+// the mount's exit, as they are going somewhere else.
+fn dismount_on_respawn_and_transfer(
+    mut commands: Commands,
+    net: Res<Server>,
+    mut mount_query: Query<&mut Mount>,
+    rider_query: Query<&Rider>,
+    mut respawn_events: EventReader<RespawnPoint>,
+    mut transfer_events: EventReader<TransferPlayer>,
+    mut dismounted_events: EventWriter<Dismounted>,
+) {
+    let players = respawn_events
+        .read()
+        .map(|respawn| respawn.player_entity)
+        .chain(
+            transfer_events
+                .read()
+                .map(|transfer| transfer.player_entity),
+        );
+
+    for player_entity in players {
+        let Ok(rider) = rider_query.get(player_entity) else {
+            continue;
+        };
+
+        if let Ok(mut mount) = mount_query.get_mut(rider.mount) {
+            mount.rider = None;
+        }
+
+        net.send_property(player_entity, properties::RIDING, &false);
+        commands.entity(player_entity).remove::<Rider>();
+        dismounted_events.send(Dismounted {
+            rider: player_entity,
+            mount: rider.mount,
+        });
+    }
+}
+
+// Clean up after mounts and riders that are despawned, or have had their components removed.
+fn dismount_removed(
+    mut commands: Commands,
+    mut mount_query: Query<&mut Mount>,
+    net: Res<Server>,
+    rider_query: Query<(Entity, &Rider, Has<Player>)>,
+    mut removed_mounts: RemovedComponents<Mount>,
+    mut removed_riders: RemovedComponents<Rider>,
+    mut dismounted_events: EventWriter<Dismounted>,
+) {
+    for mount_entity in removed_mounts.read() {
+        for (rider_entity, rider, is_player) in rider_query.iter() {
+            if rider.mount == mount_entity {
+                commands.entity(rider_entity).remove::<Rider>();
+                if is_player {
+                    net.send_property(rider_entity, properties::RIDING, &false);
+                }
+                dismounted_events.send(Dismounted {
+                    rider: rider_entity,
+                    mount: mount_entity,
+                });
+            }
+        }
+    }
+
+    for rider_entity in removed_riders.read() {
+        for mut mount in mount_query.iter_mut() {
+            if mount.rider == Some(rider_entity) {
+                mount.rider = None;
+            }
+        }
+    }
+}
+
+fn handle_dismounts(
+    mut commands: Commands,
+    net: Res<Server>,
+    mut mount_query: Query<(&mut Mount, &Transform), Without<Rider>>,
+    mut rider_query: Query<(&Rider, &mut Transform, Has<Player>)>,
+    mut dismount_events: EventReader<DismountEntity>,
+    mut dismounted_events: EventWriter<Dismounted>,
+) {
+    for dismount in dismount_events.read() {
+        let Ok((rider, mut rider_transform, is_player)) = rider_query.get_mut(dismount.rider)
+        else {
+            continue;
+        };
+
+        if let Ok((mut mount, mount_transform)) = mount_query.get_mut(rider.mount) {
+            mount.rider = None;
+            rider_transform.translation =
+                mount_transform.translation + mount_transform.rotation * mount.exit;
+        }
+
+        if is_player {
+            net.send_property(dismount.rider, properties::RIDING, &false);
+            net.send_one(
+                dismount.rider,
+                messages::PlayerPosition {
+                    position: rider_transform.translation,
+                    velocity: DVec3::ZERO,
+                },
+            );
+        }
+
+        commands.entity(dismount.rider).remove::<Rider>();
+        dismounted_events.send(Dismounted {
+            rider: dismount.rider,
+            mount: rider.mount,
+        });
+    }
+}
+
+fn handle_mounts(
+    mut commands: Commands,
+    net: Res<Server>,
+    mut mount_query: Query<&mut Mount>,
+    rider_query: Query<Has<Player>, Without<Rider>>,
+    mut mount_events: EventReader<MountEntity>,
+    mut mounted_events: EventWriter<Mounted>,
+) {
+    for mount_event in mount_events.read() {
+        if mount_event.rider == mount_event.mount {
+            continue;
+        }
+
+        let Ok(is_player) = rider_query.get(mount_event.rider) else {
+            // Already riding
+            continue;
+        };
+
+        let Ok(mut mount) = mount_query.get_mut(mount_event.mount) else {
+            continue;
+        };
+
+        if mount.rider.is_some() {
+            continue;
+        }
+
+        mount.rider = Some(mount_event.rider);
+        commands.entity(mount_event.rider).insert(Rider {
+            mount: mount_event.mount,
+        });
+
+        if is_player {
+            net.send_property(mount_event.rider, properties::RIDING, &true);
+        }
+
+        mounted_events.send(Mounted {
+            rider: mount_event.rider,
+            mount: mount_event.mount,
+        });
+    }
+}
+
+// TODO: The rider can only be offset from the mount's origin, following a bone of the mount's
+// model needs the server to know the model's animations.
+//
+// Players that ride are told their position whenever it changes, their client doesn't move them
+// while riding. Except for vehicles, which their client moves itself.
+// A resumed session has a new client that doesn't know the player is riding.
+fn resend_riding(
+    net: Res<Server>,
+    rider_query: Query<Entity, (With<Rider>, With<Player>, Added<ResumedSession>)>,
+) {
+    for player_entity in rider_query.iter() {
+        net.send_property(player_entity, properties::RIDING, &true);
+    }
+}
+
+fn follow_mounts(
+    net: Res<Server>,
+    mount_query: Query<(&Mount, &Transform, Has<Vehicle>), Without<Rider>>,
+    mut rider_query: Query<(Entity, &Rider, &mut Transform, Has<Player>)>,
+) {
+    for (rider_entity, rider, mut rider_transform, is_player) in rider_query.iter_mut() {
+        let Ok((mount, mount_transform, is_vehicle)) = mount_query.get(rider.mount) else {
+            continue;
+        };
+
+        let position = mount_transform.translation + mount_transform.rotation * mount.seat;
+        if rider_transform.translation == position {
+            continue;
+        }
+
+        rider_transform.translation = position;
+
+        if is_player && !is_vehicle {
+            net.send_one(
+                rider_entity,
+                messages::PlayerPosition {
+                    position,
+                    velocity: DVec3::ZERO,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+
+use bevy::math::DVec3;
+use fmc_protocol::messages;
+
+use crate::{
+    blocks::Blocks,
+    models::ModelAnimations,
+    networking::{properties, ClientProperty, NetworkMessage, ResumedSession, Server},
+    physics::{shapes::Aabb, PhysicsConfig, PhysicsOverride},
+    players::{Camera, Player, Rider},
+    prelude::*,
+    world::WorldMap,
+};
+
+// How much lower the camera is while sneaking.
+const SNEAK_CAMERA_OFFSET: f64 = 0.3;
+
+// Keeps track of how the players say they are moving. The client simulates the movement, the
+// server only makes sure sneaking players don't walk off edges.
+pub struct MovementPlugin;
+impl Plugin for MovementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            guard_edges.after(super::handle_player_position_updates),
+        )
+        .add_systems(
+            Update,
+            (
+                insert_movement_state,
+                handle_movement_properties,
+                (move_camera, play_movement_animations),
+            )
+                .chain(),
+        )
+        .add_systems(Update, send_physics);
+    }
+}
+
+/// How the player is moving, as told by its client.
+#[derive(Component, Default, PartialEq)]
+pub struct MovementState {
+    pub is_sneaking: bool,
+    pub is_sprinting: bool,
+}
+
+/// Animations the player's model plays while moving. Add it to the entity of the model, it must
+/// be a child of the player.
+#[derive(Component, Default)]
+pub struct MovementAnimations {
+    pub walk: Option<u32>,
+    pub sneak: Option<u32>,
+    pub sprint: Option<u32>,
+}
+
+#[derive(Component, Default)]
+struct EdgeGuard {
+    // Position of the player before the last position update
+    last_position: Option<DVec3>,
+}
+
+fn insert_movement_state(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
+    for player_entity in player_query.iter() {
+        commands
+            .entity(player_entity)
+            .insert((MovementState::default(), EdgeGuard::default()));
+    }
+}
+
+fn handle_movement_properties(
+    mut player_query: Query<&mut MovementState>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+) {
+    for property in property_events.read() {
+        let Ok(mut state) = player_query.get_mut(property.player_entity) else {
+            continue;
+        };
+
+        match property.name.as_str() {
+            properties::SNEAKING => state.is_sneaking = property.parse().unwrap_or_default(),
+            properties::SPRINTING => state.is_sprinting = property.parse().unwrap_or_default(),
+            _ => (),
+        }
+    }
+}
+
+// The client needs the physics constants to simulate the player's movement. They're sent when the
+// player joins or resumes its session, and whenever they change.
+fn send_physics(
+    net: Res<Server>,
+    physics_config: Res<PhysicsConfig>,
+    player_query: Query<(Entity, Ref<Player>, Option<Ref<PhysicsOverride>>)>,
+    resumed_query: Query<(), Added<ResumedSession>>,
+    mut removed_overrides: RemovedComponents<PhysicsOverride>,
+) {
+    let removed_overrides: HashSet<Entity> = removed_overrides.read().collect();
+
+    for (player_entity, player, physics_override) in player_query.iter() {
+        if !physics_config.is_changed()
+            && !player.is_added()
+            && !resumed_query.contains(player_entity)
+            && !physics_override
+                .as_ref()
+                .is_some_and(|physics_override| physics_override.is_changed())
+            && !removed_overrides.contains(&player_entity)
+        {
+            continue;
+        }
+
+        let config = physics_config.with_override(physics_override.as_deref());
+        net.send_property(
+            player_entity,
+            properties::GRAVITY,
+            &config.gravity.to_array(),
+        );
+        net.send_property(
+            player_entity,
+            properties::TERMINAL_VELOCITY,
+            &config.terminal_velocity,
+        );
+        net.send_property(player_entity, properties::STEP_HEIGHT, &config.step_height);
+    }
+}
+
+// The client lowers its own camera, the server does the same so that what the player targets
+// matches what it sees.
+fn move_camera(mut player_query: Query<(&MovementState, &mut Camera), Changed<MovementState>>) {
+    for (state, mut camera) in player_query.iter_mut() {
+        let default_height = Camera::default().translation.y;
+        camera.translation.y = if state.is_sneaking {
+            default_height - SNEAK_CAMERA_OFFSET
+        } else {
+            default_height
+        };
+    }
+}
+
+fn play_movement_animations(
+    state_query: Query<Ref<MovementState>>,
+    mut model_query: Query<(&Parent, &MovementAnimations, &mut ModelAnimations)>,
+) {
+    for (parent, movement_animations, mut animations) in model_query.iter_mut() {
+        let Ok(state) = state_query.get(parent.get()) else {
+            continue;
+        };
+
+        if !state.is_changed() {
+            continue;
+        }
+
+        let animation = if state.is_sneaking {
+            movement_animations.sneak
+        } else if state.is_sprinting {
+            movement_animations.sprint
+        } else {
+            movement_animations.walk
+        };
+
+        animations.play_on_move(animation.or(movement_animations.walk));
+    }
+}
+
+// If there is a solid block below the aabb, the client checks the same before it lets a sneaking
+// player move.
+fn has_floor(world_map: &WorldMap, aabb: &Aabb) -> bool {
+    let blocks = Blocks::get();
+    let min = aabb.min();
+    let max = aabb.max();
+    let y = (min.y - 0.05).floor() as i32;
+
+    for x in min.x.floor() as i32..=max.x.floor() as i32 {
+        for z in min.z.floor() as i32..=max.z.floor() as i32 {
+            match world_map.get_block(IVec3::new(x, y, z)) {
+                Some(block_id) if blocks.get_config(&block_id).is_solid() => return true,
+                Some(_) => (),
+                // Not loaded, assume the player knows better
+                None => return true,
+            }
+        }
+    }
+
+    return false;
+}
+
+// Sneaking players can't walk off edges. If the client moves a sneaking player from solid ground
+// to thin air without falling, it is moved back.
+fn guard_edges(
+    net: Res<Server>,
+    world_map: Res<WorldMap>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &Aabb,
+            &MovementState,
+            &mut EdgeGuard,
+        ),
+        Without<Rider>,
+    >,
+) {
+    for (player_entity, mut transform, aabb, state, mut guard) in player_query.iter_mut() {
+        let position = transform.translation;
+        let Some(last_position) = guard.last_position.replace(position) else {
+            continue;
+        };
+
+        if !state.is_sneaking || last_position == position || !position.is_finite() {
+            continue;
+        }
+
+        let aabb_at = |position: DVec3| Aabb {
+            center: aabb.center + position,
+            half_extents: aabb.half_extents,
+        };
+
+        // Jumping and falling is allowed, only walking off is stopped.
+        let is_level = (position.y - last_position.y).abs() < 0.01;
+        if is_level
+            && has_floor(&world_map, &aabb_at(last_position))
+            && !has_floor(&world_map, &aabb_at(position))
+        {
+            transform.translation = last_position;
+            guard.last_position = Some(last_position);
+            net.send_one(
+                player_entity,
+                messages::PlayerPosition {
+                    position: last_position,
+                    velocity: DVec3::ZERO,
+                },
+            );
+        }
+    }
+}
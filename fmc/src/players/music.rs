@@ -0,0 +1,61 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    networking::{properties, ResumedSession, Server},
+    players::Player,
+    prelude::*,
+};
+
+// Servers ship music in "assets/client/audio/music/", listed in its "music.json" with the tags
+// of the situations each track fits. The client picks tracks by the tags of the player's
+// situation, it decides "day" and "night" on its own, the game adds the rest, like the biome or
+// "combat".
+pub struct MusicPlugin;
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (insert_music_tags, send_music_tags).chain());
+    }
+}
+
+/// The situation the player is in, as far as music is concerned. Inserted on all players.
+#[derive(Component, Default)]
+pub struct MusicTags {
+    tags: BTreeSet<String>,
+}
+
+impl MusicTags {
+    pub fn insert(&mut self, tag: &str) {
+        if !self.tags.contains(tag) {
+            self.tags.insert(tag.to_owned());
+        }
+    }
+
+    pub fn remove(&mut self, tag: &str) {
+        self.tags.remove(tag);
+    }
+
+    pub fn contains(&self, tag: &str) -> bool {
+        return self.tags.contains(tag);
+    }
+
+    /// Replace all the tags, e.g. when the player enters a new biome.
+    pub fn set(&mut self, tags: &[&str]) {
+        self.tags = tags.iter().map(|tag| tag.to_string()).collect();
+    }
+}
+
+fn insert_music_tags(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
+    for player_entity in player_query.iter() {
+        commands.entity(player_entity).insert(MusicTags::default());
+    }
+}
+
+// Sent as the "music" property.
+fn send_music_tags(
+    net: Res<Server>,
+    player_query: Query<(Entity, &MusicTags), Or<(Changed<MusicTags>, Added<ResumedSession>)>>,
+) {
+    for (player_entity, music_tags) in player_query.iter() {
+        net.send_property(player_entity, properties::MUSIC, &music_tags.tags);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{
+    networking::{properties, Server},
+    players::Player,
+    prelude::*,
+};
+
+// Clients are sent notifications through the "notification" property. They are shown in a corner
+// of the screen, separate from the chat, and queued if several arrive at once.
+pub struct NotificationPlugin;
+impl Plugin for NotificationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Notification>()
+            .add_systems(Update, send_notifications);
+    }
+}
+
+/// Show a short message to a player, e.g. for an achievement, a friend joining, or a warning.
+///
+/// ```ignore
+/// notification_events.send(Notification {
+///     player_entity: Some(player_entity),
+///     icon: Some("items/diamond.png".to_owned()),
+///     title: "Achievement get!".to_owned(),
+///     body: "Find a diamond".to_owned(),
+///     duration: 5.0,
+/// });
+/// ```
+#[derive(Event, Clone)]
+pub struct Notification {
+    /// The player to show it to, None to show it to all players.
+    pub player_entity: Option<Entity>,
+    /// Path of an image relative to the client's texture directory, shown next to the text.
+    pub icon: Option<String>,
+    /// Shown on one line above the body
+    pub title: String,
+    pub body: String,
+    /// Seconds the notification is shown for
+    pub duration: f32,
+}
+
+fn send_notifications(
+    net: Res<Server>,
+    player_query: Query<Entity, With<Player>>,
+    mut notification_events: EventReader<Notification>,
+) {
+    for notification in notification_events.read() {
+        let value = properties::Notification {
+            duration: notification.duration,
+            icon: notification.icon.clone(),
+            title: notification.title.clone(),
+            body: notification.body.clone(),
+        };
+
+        if let Some(player_entity) = notification.player_entity {
+            net.send_property(player_entity, properties::NOTIFICATION, &value);
+        } else {
+            for player_entity in player_query.iter() {
+                net.send_property(player_entity, properties::NOTIFICATION, &value);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use fmc_protocol::messages;
+
+use crate::{
+    networking::NetworkMessage,
+    players::{GameMode, Target, Targets},
+    prelude::*,
+};
+
+// The player's targets are only what is within its reach, so clicks can't interact with anything
+// further away. What the player clicked on just beyond the reach is still looked for, so that the
+// server can tell when the client thinks it is within reach while the server does not.
+pub struct ReachPlugin;
+impl Plugin for ReachPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Reach>()
+            .add_event::<OutOfReach>()
+            .add_systems(
+                PreUpdate,
+                detect_out_of_reach_clicks.after(super::find_target),
+            );
+    }
+}
+
+/// How far away, measured from the camera, players can interact with blocks and entities in each
+/// game mode.
+#[derive(Resource, Debug)]
+pub struct Reach {
+    pub survival: f64,
+    pub creative: f64,
+    pub adventure: f64,
+    pub spectator: f64,
+}
+
+impl Default for Reach {
+    fn default() -> Self {
+        Self {
+            survival: 5.0,
+            creative: 6.0,
+            adventure: 5.0,
+            spectator: 5.0,
+        }
+    }
+}
+
+impl Reach {
+    pub fn get(&self, game_mode: GameMode) -> f64 {
+        match game_mode {
+            GameMode::Survival => self.survival,
+            GameMode::Creative => self.creative,
+            GameMode::Adventure => self.adventure,
+            GameMode::Spectator => self.spectator,
+        }
+    }
+}
+
+/// Sent when a player clicks on something that is too far away to reach. The click is still
+/// sent, but nothing is targeted by it. Usually caused by the player moving on the client before
+/// the server knows about it, or by a modified client.
+#[derive(Event, Debug)]
+pub struct OutOfReach {
+    pub player_entity: Entity,
+    /// What was clicked
+    pub target: Target,
+}
+
+fn detect_out_of_reach_clicks(
+    player_query: Query<&Targets>,
+    mut left_clicks: EventReader<NetworkMessage<messages::LeftClick>>,
+    mut right_clicks: EventReader<NetworkMessage<messages::RightClick>>,
+    mut out_of_reach_events: EventWriter<OutOfReach>,
+) {
+    let clicks = left_clicks
+        .read()
+        .map(|click| click.player_entity)
+        .chain(right_clicks.read().map(|click| click.player_entity));
+
+    for player_entity in clicks {
+        let Ok(targets) = player_query.get(player_entity) else {
+            continue;
+        };
+
+        if let Some(target) = targets.out_of_reach() {
+            out_of_reach_events.send(OutOfReach {
+                player_entity,
+                target: target.clone(),
+            });
+        }
+    }
+}
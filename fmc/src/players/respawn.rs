@@ -0,0 +1,359 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use bevy::{
+    math::DVec3,
+    tasks::{futures_lite::future, AsyncComputeTaskPool, Task},
+    utils::Instant,
+};
+
+use crate::{
+    blocks::{BlockConfig, BlockId, Blocks},
+    database::Database,
+    prelude::*,
+    utils,
+    world::{chunk::Chunk, BlockUpdate, TerrainGenerator, WorldMap, WorldSpawn},
+};
+
+// Searching for a spawn point is done asynchronously, as it may need to generate many chunks.
+// Found points are cached until a block that makes them valid changes.
+pub struct RespawnPlugin;
+impl Plugin for RespawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RespawnSettings>()
+            .init_resource::<SpawnPointCache>()
+            .add_event::<RespawnRequest>()
+            .add_event::<RespawnPoint>()
+            .add_systems(
+                Update,
+                (
+                    precompute_world_spawn.run_if(resource_exists_and_changed::<WorldSpawn>),
+                    start_searches,
+                    finish_searches,
+                    invalidate_cache,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// The block the player stands on, and the two blocks the player occupies.
+pub type SpawnPredicate = fn(floor: &BlockConfig, feet: &BlockConfig, head: &BlockConfig) -> bool;
+
+/// Two air blocks above a solid floor.
+pub fn default_spawn_predicate(
+    floor: &BlockConfig,
+    feet: &BlockConfig,
+    head: &BlockConfig,
+) -> bool {
+    return floor.is_solid() && feet.name == "air" && head.name == "air";
+}
+
+/// Configuration of the spawn point search, insert it to change the defaults.
+#[derive(Resource, Clone)]
+pub struct RespawnSettings {
+    /// Decides if a position is valid to spawn at.
+    pub predicate: SpawnPredicate,
+    /// How many chunk columns out from the wanted position the search goes.
+    pub search_radius: i32,
+    /// The vertical range searched for ground, in blocks. The search goes from the top and down.
+    pub search_range: (i32, i32),
+    /// How long a search may take before the fallback position is used.
+    pub timeout: Duration,
+}
+
+impl Default for RespawnSettings {
+    fn default() -> Self {
+        Self {
+            predicate: default_spawn_predicate,
+            search_radius: 4,
+            search_range: (-64, 128),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Send to find a safe place for a player to spawn. A `RespawnPoint` event is sent for the
+/// player when it has been found.
+#[derive(Event)]
+pub struct RespawnRequest {
+    pub player_entity: Entity,
+    /// The position to search around, the world spawn is used if not set.
+    pub around: Option<IVec3>,
+}
+
+/// The result of a `RespawnRequest`.
+#[derive(Event)]
+pub struct RespawnPoint {
+    pub player_entity: Entity,
+    /// Where to place the player. If no safe position could be found, this is the world spawn,
+    /// or the requested position if the world spawn wasn't safe either.
+    pub translation: DVec3,
+    /// If the spawn point was validated by the predicate.
+    pub is_safe: bool,
+}
+
+// Validated spawn points, by the position that was searched around.
+#[derive(Resource, Default)]
+struct SpawnPointCache {
+    points: HashMap<IVec3, IVec3>,
+    // Searches in progress. Multiple players may wait on the same search.
+    searches: HashMap<IVec3, (Task<Option<IVec3>>, Vec<Entity>)>,
+}
+
+fn spawn_translation(block_position: IVec3) -> DVec3 {
+    return block_position.as_dvec3() + DVec3::new(0.5, 1.0, 0.5);
+}
+
+// Makes the world spawn point available right away for the first player that joins.
+fn precompute_world_spawn(
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    settings: Res<RespawnSettings>,
+    world_spawn: Res<WorldSpawn>,
+    mut cache: ResMut<SpawnPointCache>,
+) {
+    let around = world_spawn.position;
+    if cache.points.contains_key(&around) || cache.searches.contains_key(&around) {
+        return;
+    }
+
+    let task = AsyncComputeTaskPool::get().spawn(find_spawn_point(
+        world_map.terrain_generator.clone(),
+        database.clone(),
+        settings.clone(),
+        around,
+    ));
+    cache.searches.insert(around, (task, Vec::new()));
+}
+
+fn start_searches(
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    settings: Res<RespawnSettings>,
+    world_spawn: Option<Res<WorldSpawn>>,
+    mut cache: ResMut<SpawnPointCache>,
+    mut respawn_requests: EventReader<RespawnRequest>,
+    mut respawn_points: EventWriter<RespawnPoint>,
+) {
+    for request in respawn_requests.read() {
+        let around = request
+            .around
+            .or(world_spawn.as_ref().map(|spawn| spawn.position))
+            .unwrap_or(IVec3::ZERO);
+
+        if let Some(point) = cache.points.get(&around) {
+            respawn_points.send(RespawnPoint {
+                player_entity: request.player_entity,
+                translation: spawn_translation(*point),
+                is_safe: true,
+            });
+            continue;
+        }
+
+        if let Some((_, waiting)) = cache.searches.get_mut(&around) {
+            waiting.push(request.player_entity);
+            continue;
+        }
+
+        let task = AsyncComputeTaskPool::get().spawn(find_spawn_point(
+            world_map.terrain_generator.clone(),
+            database.clone(),
+            settings.clone(),
+            around,
+        ));
+        cache
+            .searches
+            .insert(around, (task, vec![request.player_entity]));
+    }
+}
+
+fn finish_searches(
+    world_map: Res<WorldMap>,
+    settings: Res<RespawnSettings>,
+    world_spawn: Option<Res<WorldSpawn>>,
+    mut cache: ResMut<SpawnPointCache>,
+    mut respawn_points: EventWriter<RespawnPoint>,
+) {
+    let cache = cache.into_inner();
+
+    cache.searches.retain(|around, (task, waiting)| {
+        let Some(result) = future::block_on(future::poll_once(task)) else {
+            return true;
+        };
+
+        // The search only sees what has been saved to the database, the loaded chunks may have
+        // changed since. Discard the point if it is no longer valid.
+        let result = result.filter(|point| {
+            is_valid_in_loaded_world(&world_map, settings.predicate, *point).unwrap_or(true)
+        });
+
+        if let Some(point) = result {
+            cache.points.insert(*around, point);
+        }
+
+        let (translation, is_safe) = match result {
+            Some(point) => (spawn_translation(point), true),
+            None => {
+                let fallback = world_spawn.as_ref().map_or(*around, |spawn| spawn.position);
+                warn!(
+                    "Could not find a safe spawn point around {}, falling back to {}",
+                    around, fallback
+                );
+                (spawn_translation(fallback), false)
+            }
+        };
+
+        for player_entity in waiting.drain(..) {
+            respawn_points.send(RespawnPoint {
+                player_entity,
+                translation,
+                is_safe,
+            });
+        }
+
+        return false;
+    });
+}
+
+// Returns None if any of the blocks are not loaded
+fn is_valid_in_loaded_world(
+    world_map: &WorldMap,
+    predicate: SpawnPredicate,
+    point: IVec3,
+) -> Option<bool> {
+    let blocks = Blocks::get();
+    let floor = world_map.get_block(point)?;
+    let feet = world_map.get_block(point + IVec3::Y)?;
+    let head = world_map.get_block(point + IVec3::Y * 2)?;
+    return Some(predicate(
+        blocks.get_config(&floor),
+        blocks.get_config(&feet),
+        blocks.get_config(&head),
+    ));
+}
+
+// Cached points become invalid when one of the blocks they rely on changes.
+fn invalidate_cache(
+    mut cache: ResMut<SpawnPointCache>,
+    mut block_updates: EventReader<BlockUpdate>,
+) {
+    for block_update in block_updates.read() {
+        let position = match block_update {
+            BlockUpdate::Change { position, .. } => *position,
+        };
+
+        cache.points.retain(|_, point| {
+            position.x != point.x
+                || position.z != point.z
+                || position.y < point.y
+                || position.y > point.y + 2
+        });
+    }
+}
+
+// Chunks are loaded from the terrain generator and the database, as the world map can't be
+// accessed from other threads.
+async fn get_generated_block(
+    chunks: &mut HashMap<IVec3, Chunk>,
+    terrain_generator: &Arc<dyn TerrainGenerator>,
+    database: &Database,
+    position: IVec3,
+) -> BlockId {
+    let (chunk_position, index) = utils::world_position_to_chunk_position_and_block_index(position);
+    if !chunks.contains_key(&chunk_position) {
+        let (_, chunk) =
+            Chunk::load(chunk_position, terrain_generator.clone(), database.clone()).await;
+        chunks.insert(chunk_position, chunk);
+    }
+    return chunks[&chunk_position][index];
+}
+
+/// Searches outwards from the position one chunk column at a time, returning the ground of the
+/// first block column that passes the predicate. The search gives up and returns None when it
+/// runs out of columns or time.
+pub async fn find_spawn_point(
+    terrain_generator: Arc<dyn TerrainGenerator>,
+    database: Database,
+    settings: RespawnSettings,
+    around: IVec3,
+) -> Option<IVec3> {
+    let deadline = Instant::now() + settings.timeout;
+    let blocks = Blocks::get();
+
+    let mut chunks: HashMap<IVec3, Chunk> = HashMap::new();
+    let (bottom, top) = settings.search_range;
+    let center = utils::world_position_to_chunk_position(around);
+
+    for ring in 0..=settings.search_radius {
+        for x in -ring..=ring {
+            for z in -ring..=ring {
+                // Only the chunk columns on the edge of the ring, the inside has been searched.
+                if x.abs() != ring && z.abs() != ring {
+                    continue;
+                }
+
+                if Instant::now() > deadline {
+                    return None;
+                }
+
+                let column = center + IVec3::new(x, 0, z) * Chunk::SIZE as i32;
+                // Try the wanted position first, then the rest of the chunk column.
+                let mut block_columns = Vec::with_capacity(Chunk::SIZE.pow(2) + 1);
+                if ring == 0 {
+                    block_columns.push((around.x, around.z));
+                }
+                for block_x in 0..Chunk::SIZE as i32 {
+                    for block_z in 0..Chunk::SIZE as i32 {
+                        block_columns.push((column.x + block_x, column.z + block_z));
+                    }
+                }
+
+                for (block_x, block_z) in block_columns {
+                    let mut head = get_generated_block(
+                        &mut chunks,
+                        &terrain_generator,
+                        &database,
+                        IVec3::new(block_x, top, block_z),
+                    )
+                    .await;
+                    let mut feet = get_generated_block(
+                        &mut chunks,
+                        &terrain_generator,
+                        &database,
+                        IVec3::new(block_x, top - 1, block_z),
+                    )
+                    .await;
+                    for y in (bottom..top - 1).rev() {
+                        let floor = get_generated_block(
+                            &mut chunks,
+                            &terrain_generator,
+                            &database,
+                            IVec3::new(block_x, y, block_z),
+                        )
+                        .await;
+                        let floor_config = blocks.get_config(&floor);
+
+                        // Only the ground is considered, spawning in caves below it is not
+                        // wanted.
+                        if !floor_config.is_solid() {
+                            head = feet;
+                            feet = floor;
+                            continue;
+                        }
+
+                        if (settings.predicate)(
+                            floor_config,
+                            blocks.get_config(&feet),
+                            blocks.get_config(&head),
+                        ) {
+                            return Some(IVec3::new(block_x, y, block_z));
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    return None;
+}
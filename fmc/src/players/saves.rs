@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::SystemParam, prelude::*, tasks::IoTaskPool};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{database::Database, players::Player};
+
+// Every player is given a save when they connect, which the game keeps whatever data it wants in.
+// It is written to the database whenever it changes. The saves of players that are not connected
+// can be changed through `OfflinePlayers`.
+pub struct PlayerSavePlugin;
+impl Plugin for PlayerSavePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Connections>()
+            .add_systems(Update, (load_player_saves, save_player_saves).chain());
+    }
+}
+
+/// Data the game keeps about a player between sessions, e.g. their inventory or the mail they
+/// have been sent. It is made up of entries by name, so several plugins can keep their own parts of
+/// it without knowing about each other.
+///
+/// ```ignore
+/// fn give_coins(mut save_query: Query<&mut PlayerSave>) {
+///     for mut save in save_query.iter_mut() {
+///         let coins: u32 = save.get("coins").unwrap_or(0);
+///         save.set("coins", &(coins + 1));
+///     }
+/// }
+/// ```
+#[derive(Component, Serialize, Deserialize, Default, Clone, Debug)]
+pub struct PlayerSave {
+    entries: HashMap<String, serde_json::Value>,
+}
+
+impl PlayerSave {
+    /// The entry by the name, None if it doesn't exist or is not of type T
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        let value = self.entries.get(name)?;
+        return T::deserialize(value).ok();
+    }
+
+    pub fn set<T: Serialize>(&mut self, name: impl Into<String>, value: &T) {
+        match serde_json::to_value(value) {
+            Ok(value) => {
+                self.entries.insert(name.into(), value);
+            }
+            Err(e) => error!("Could not store a value in a player save: {}", e),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.remove(name);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        return self.entries.contains_key(name);
+    }
+}
+
+// How many times each account has connected since the server started, so edits of offline saves
+// can tell if the player connected while they were being made.
+#[derive(Resource, Default)]
+struct Connections(HashMap<String, u64>);
+
+/// Why the save of an offline player could not be loaded or saved
+#[derive(Debug, PartialEq, Eq)]
+pub enum OfflinePlayerError {
+    /// No player has played with that account id or username
+    NotFound,
+    /// The player is connected, change their `PlayerSave` component instead
+    Online,
+    /// The player connected after the save was loaded, it has been loaded into their
+    /// `PlayerSave` component and the offline copy is out of date.
+    Conflict,
+}
+
+impl std::fmt::Display for OfflinePlayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Self::NotFound => write!(f, "No player by that name"),
+            Self::Online => write!(f, "The player is online"),
+            Self::Conflict => write!(f, "The player connected while their save was being changed"),
+        };
+    }
+}
+
+/// The save of a player that is not connected, loaded through `OfflinePlayers`
+pub struct OfflinePlayerSave {
+    account_id: String,
+    username: String,
+    // The connection count of the account when it was loaded
+    connections: u64,
+    pub save: PlayerSave,
+}
+
+impl OfflinePlayerSave {
+    pub fn account_id(&self) -> &str {
+        return &self.account_id;
+    }
+
+    /// The username the player last played with
+    pub fn username(&self) -> &str {
+        return &self.username;
+    }
+}
+
+/// Read and change the saves of players that are not connected, e.g. to send them mail or wipe
+/// their inventory when banning them.
+///
+/// ```ignore
+/// fn wipe_inventory(offline_players: OfflinePlayers) {
+///     let Ok(mut player) = offline_players.load_by_username("griefer") else {
+///         return;
+///     };
+///     player.save.remove("inventory");
+///     offline_players.save(player).unwrap();
+/// }
+/// ```
+///
+/// A loaded save can be held for as long as needed. If the player connects before it is saved
+/// again, saving it fails with `OfflinePlayerError::Conflict` instead of overwriting what the
+/// player does while online.
+#[derive(SystemParam)]
+pub struct OfflinePlayers<'w, 's> {
+    database: Res<'w, Database>,
+    connections: Res<'w, Connections>,
+    player_query: Query<'w, 's, &'static Player>,
+}
+
+impl<'w, 's> OfflinePlayers<'w, 's> {
+    pub fn load(&self, account_id: &str) -> Result<OfflinePlayerSave, OfflinePlayerError> {
+        if self.is_online(account_id) {
+            return Err(OfflinePlayerError::Online);
+        }
+
+        let Some((username, save)) = self.database.load_player_save(account_id) else {
+            return Err(OfflinePlayerError::NotFound);
+        };
+
+        return Ok(OfflinePlayerSave {
+            account_id: account_id.to_owned(),
+            username,
+            connections: self.connection_count(account_id),
+            save,
+        });
+    }
+
+    /// Load the save of the account that last played with the username
+    pub fn load_by_username(
+        &self,
+        username: &str,
+    ) -> Result<OfflinePlayerSave, OfflinePlayerError> {
+        let Some(account_id) = self.database.find_player_account(username) else {
+            return Err(OfflinePlayerError::NotFound);
+        };
+        return self.load(&account_id);
+    }
+
+    /// Write the save to the database. This happens immediately so that the player gets the new
+    /// save if they connect right after.
+    pub fn save(&self, save: OfflinePlayerSave) -> Result<(), OfflinePlayerError> {
+        if self.is_online(&save.account_id) {
+            return Err(OfflinePlayerError::Online);
+        }
+
+        if self.connection_count(&save.account_id) != save.connections {
+            return Err(OfflinePlayerError::Conflict);
+        }
+
+        self.database
+            .save_player_save(&save.account_id, &save.username, &save.save);
+        return Ok(());
+    }
+
+    pub fn is_online(&self, account_id: &str) -> bool {
+        return self
+            .player_query
+            .iter()
+            .any(|player| player.account_id == account_id);
+    }
+
+    fn connection_count(&self, account_id: &str) -> u64 {
+        return self.connections.0.get(account_id).copied().unwrap_or(0);
+    }
+}
+
+fn load_player_saves(
+    mut commands: Commands,
+    database: Res<Database>,
+    mut connections: ResMut<Connections>,
+    player_query: Query<(Entity, &Player), Added<Player>>,
+) {
+    for (entity, player) in player_query.iter() {
+        *connections.0.entry(player.account_id.clone()).or_default() += 1;
+
+        let save = match database.load_player_save(&player.account_id) {
+            Some((username, save)) if username == player.username => save,
+            // New players and players that changed their name are saved right away, so they can
+            // be found by their current name while offline.
+            loaded => {
+                let save = loaded.map(|(_, save)| save).unwrap_or_default();
+                database.save_player_save(&player.account_id, &player.username, &save);
+                save
+            }
+        };
+        commands.entity(entity).insert(save);
+    }
+}
+
+// TODO: The saves of online players are written in the background. If an offline edit is loaded
+// right after the player disconnects it could be read before their last change is written.
+fn save_player_saves(database: Res<Database>, save_query: Query<(&Player, Ref<PlayerSave>)>) {
+    for (player, save) in save_query.iter() {
+        // Freshly loaded saves are already in the database
+        if !save.is_changed() || save.is_added() {
+            continue;
+        }
+
+        let database = database.clone();
+        let account_id = player.account_id.clone();
+        let username = player.username.clone();
+        let save = save.clone();
+        IoTaskPool::get()
+            .spawn(async move { database.save_player_save(&account_id, &username, &save) })
+            .detach();
+    }
+}
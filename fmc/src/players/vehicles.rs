@@ -0,0 +1,211 @@
+use bevy::math::DVec3;
+use fmc_protocol::messages;
+
+use crate::{
+    blocks::Blocks,
+    networking::{properties, NetworkMessage, ResumedSession, Server},
+    physics::{find_rail, Mass, Velocity},
+    players::{Mount, Mounted, Player, Rider},
+    prelude::*,
+    world::WorldMap,
+};
+
+// How much faster than its max speed a vehicle is allowed to move between two position updates
+// before it is corrected. Updates arrive unevenly, so some slack is needed.
+const SPEED_TOLERANCE: f64 = 1.5;
+// Distance a vehicle may always move, covers small corrections like snapping to a rail.
+const DISTANCE_TOLERANCE: f64 = 0.5;
+
+// Players that ride a vehicle drive it themselves. Their client simulates the vehicle, so that it
+// responds immediately, and sends its position like it does when walking. The server checks that
+// the vehicle could have gotten there, and corrects the client if it couldn't.
+pub struct VehiclePlugin;
+impl Plugin for VehiclePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (start_driving, resend_vehicles))
+            .add_systems(
+                PreUpdate,
+                validate_driver_positions.after(super::handle_player_position_updates),
+            )
+            .add_systems(PostUpdate, stop_driving);
+    }
+}
+
+/// A mount that players drive from their client instead of through `RiderInput`.
+#[derive(Component, Clone, Copy)]
+pub struct Vehicle {
+    pub kind: VehicleKind,
+    /// Speed in blocks per second the vehicle can't go faster than.
+    pub max_speed: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum VehicleKind {
+    /// Floats on liquids, slides slowly over land.
+    Boat,
+    /// Rides along rail blocks.
+    Minecart,
+}
+
+impl VehicleKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Boat => "boat",
+            Self::Minecart => "minecart",
+        }
+    }
+}
+
+// Added to vehicles while a player drives them.
+#[derive(Component)]
+struct Driven {
+    driver: Entity,
+    // When the driver last sent its position
+    last_update: f64,
+    // Physics is disabled while the client is in control, it is given back when the driver gets
+    // off.
+    had_mass: bool,
+}
+
+// The client is told what it is driving with the "vehicle" property, it simulates the vehicle's
+// position as the player's minus the seat.
+//
+// TODO: Vehicles don't turn, the seat would rotate out from under the client's prediction.
+fn start_driving(
+    mut commands: Commands,
+    net: Res<Server>,
+    time: Res<Time>,
+    vehicle_query: Query<(&Vehicle, &Mount, Has<Mass>)>,
+    player_query: Query<(), With<Player>>,
+    mut mounted_events: EventReader<Mounted>,
+) {
+    for mounted in mounted_events.read() {
+        let Ok((vehicle, mount, has_mass)) = vehicle_query.get(mounted.mount) else {
+            continue;
+        };
+
+        if !player_query.contains(mounted.rider) {
+            continue;
+        }
+
+        send_vehicle(&net, mounted.rider, vehicle, mount);
+
+        let mut entity_commands = commands.entity(mounted.mount);
+        entity_commands.insert(Driven {
+            driver: mounted.rider,
+            last_update: time.elapsed_secs_f64(),
+            had_mass: has_mass,
+        });
+        entity_commands.remove::<Mass>();
+    }
+}
+
+fn send_vehicle(net: &Server, player_entity: Entity, vehicle: &Vehicle, mount: &Mount) {
+    net.send_property(
+        player_entity,
+        properties::VEHICLE,
+        &properties::Vehicle {
+            kind: vehicle.kind.as_str().to_owned(),
+            max_speed: vehicle.max_speed,
+            seat: mount.seat.to_array(),
+        },
+    );
+}
+
+// A resumed session has a new client that doesn't know what the player is driving.
+fn resend_vehicles(
+    net: Res<Server>,
+    vehicle_query: Query<(&Vehicle, &Mount)>,
+    player_query: Query<(Entity, &Rider), (With<Player>, Added<ResumedSession>)>,
+) {
+    for (player_entity, rider) in player_query.iter() {
+        if let Ok((vehicle, mount)) = vehicle_query.get(rider.mount()) {
+            send_vehicle(&net, player_entity, vehicle, mount);
+        }
+    }
+}
+
+fn stop_driving(mut commands: Commands, vehicle_query: Query<(Entity, &Driven, &Mount)>) {
+    for (vehicle_entity, driven, mount) in vehicle_query.iter() {
+        if mount.rider() == Some(driven.driver) {
+            continue;
+        }
+
+        let mut entity_commands = commands.entity(vehicle_entity);
+        entity_commands.remove::<Driven>();
+        if driven.had_mass {
+            entity_commands.insert(Mass);
+        }
+    }
+}
+
+fn validate_driver_positions(
+    net: Res<Server>,
+    time: Res<Time>,
+    world_map: Res<WorldMap>,
+    rider_query: Query<&Rider, With<Player>>,
+    mut vehicle_query: Query<
+        (
+            &Vehicle,
+            &Mount,
+            &mut Driven,
+            &mut Transform,
+            Option<&mut Velocity>,
+        ),
+        Without<Rider>,
+    >,
+    mut position_events: EventReader<NetworkMessage<messages::PlayerPosition>>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    for position_update in position_events.read() {
+        let Ok(rider) = rider_query.get(position_update.player_entity) else {
+            continue;
+        };
+
+        let Ok((vehicle, mount, mut driven, mut transform, velocity)) =
+            vehicle_query.get_mut(rider.mount())
+        else {
+            continue;
+        };
+
+        if driven.driver != position_update.player_entity {
+            continue;
+        }
+
+        let elapsed = now - driven.last_update;
+        driven.last_update = now;
+
+        let position = position_update.position - transform.rotation * mount.seat;
+        let max_distance = vehicle.max_speed * elapsed * SPEED_TOLERANCE + DISTANCE_TOLERANCE;
+
+        let is_inside_block = world_map
+            .get_block(position.floor().as_ivec3())
+            .map(|block_id| Blocks::get().get_config(&block_id))
+            .is_some_and(|config| config.is_solid() && !config.rail);
+        // Minecarts can leave the rail, but only by falling off it
+        let is_off_rail = vehicle.kind == VehicleKind::Minecart
+            && position.y >= transform.translation.y
+            && find_rail(&world_map, position).is_none();
+
+        if !position.is_finite()
+            || position.distance(transform.translation) > max_distance
+            || is_inside_block
+            || is_off_rail
+        {
+            net.send_one(
+                position_update.player_entity,
+                messages::PlayerPosition {
+                    position: transform.translation + transform.rotation * mount.seat,
+                    velocity: DVec3::ZERO,
+                },
+            );
+            continue;
+        }
+
+        transform.translation = position;
+        if let Some(mut velocity) = velocity {
+            velocity.0 = position_update.velocity;
+        }
+    }
+}
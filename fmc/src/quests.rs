@@ -0,0 +1,571 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use fmc_protocol::messages;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    advancements::{self, AdvancementCriterion},
+    items::{Item, ItemStack, Items},
+    networking::{ResumedSession, Server},
+    players::{Notification, PlayerSave},
+    prelude::*,
+};
+
+pub const QUEST_PATH: &str = "./assets/server/quests/";
+
+/// Text container interface that lists the player's active quests and their objectives.
+pub const QUEST_LOG_INTERFACE: &str = "quests/log";
+
+// Entry in the player save the quest log is stored in.
+const SAVE_ENTRY: &str = "quests";
+
+const LOG_FONT_SIZE: f32 = 8.0;
+const QUEST_TITLE_COLOR: &str = "#ffff55";
+const OBJECTIVE_COLOR: &str = "#ffffff";
+const COMPLETED_OBJECTIVE_COLOR: &str = "#55ff55";
+
+// Quests are defined in json, one file per quest. They are started by the game, e.g. from a
+// dialogue, and completed when all their objectives are done. Objectives count the same events
+// as advancements, send `AdvancementCriterion` for them.
+pub struct QuestPlugin;
+impl Plugin for QuestPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StartQuest>()
+            .add_event::<AbandonQuest>()
+            .add_event::<QuestCompleted>()
+            .add_systems(PreStartup, load_quests)
+            .add_systems(
+                Update,
+                (
+                    (load_quest_logs, forget_sent_quest_log_lines),
+                    (start_quests, abandon_quests, count_events, check_locations),
+                    complete_quests,
+                    (save_quest_logs, send_quest_logs),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Give a quest to a player. Ignored if the player already has it, or has completed it and it
+/// can't be repeated.
+#[derive(Event)]
+pub struct StartQuest {
+    pub player_entity: Entity,
+    /// Name of the quest, its filename without the extension
+    pub quest: String,
+}
+
+/// Remove an active quest from a player, its progress is lost.
+#[derive(Event)]
+pub struct AbandonQuest {
+    pub player_entity: Entity,
+    pub quest: String,
+}
+
+/// Sent when a player completes all the objectives of a quest. The currency reward is given by
+/// the `EconomyPlugin` if it is used, the items must be given by the game, see
+/// `QuestRewards::item_stacks`.
+#[derive(Event)]
+pub struct QuestCompleted {
+    pub player_entity: Entity,
+    pub quest: String,
+}
+
+/// Quest config
+///
+/// ```json
+/// {
+///     "title": "Lumberjack",
+///     "description": "Bob needs wood for his house",
+///     "objectives": [
+///         {"type": "collect_item", "item": "oak_log", "count": 16},
+///         {"type": "reach_location", "position": [100, 64, -20], "radius": 4}
+///     ],
+///     "rewards": {
+///         "items": [{"item": "iron_axe", "count": 1}],
+///         "currency": 50
+///     }
+/// }
+/// ```
+#[derive(Deserialize)]
+pub struct Quest {
+    pub title: String,
+    pub description: String,
+    /// All must be done to complete the quest
+    pub objectives: Vec<Objective>,
+    #[serde(default)]
+    pub rewards: QuestRewards,
+    /// If the quest can be started again after it has been completed
+    #[serde(default)]
+    pub repeatable: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Objective {
+    /// Pick up the item, counted by `advancements::ITEM_PICKUP`
+    CollectItem {
+        item: String,
+        #[serde(default = "default_count")]
+        count: u32,
+    },
+    /// Kill the kind of entity, counted by `advancements::ENTITY_KILL`
+    KillEntity {
+        entity: String,
+        #[serde(default = "default_count")]
+        count: u32,
+    },
+    /// Come within the radius of the position
+    ReachLocation { position: [f64; 3], radius: f64 },
+    /// Any criterion event, the same as the criteria of advancements
+    Event {
+        event: String,
+        target: Option<String>,
+        #[serde(default = "default_count")]
+        count: u32,
+    },
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+impl Objective {
+    /// How many times the objective's event has to happen
+    pub fn count(&self) -> u32 {
+        return match self {
+            Self::CollectItem { count, .. } => *count,
+            Self::KillEntity { count, .. } => *count,
+            Self::ReachLocation { .. } => 1,
+            Self::Event { count, .. } => *count,
+        };
+    }
+
+    /// Text shown in the quest log
+    pub fn description(&self) -> String {
+        return match self {
+            Self::CollectItem { item, .. } => format!("Collect {}", item),
+            Self::KillEntity { entity, .. } => format!("Kill {}", entity),
+            Self::ReachLocation { position, .. } => format!(
+                "Go to {} {} {}",
+                position[0].round(),
+                position[1].round(),
+                position[2].round()
+            ),
+            Self::Event { event, target, .. } => match target {
+                Some(target) => format!("{} {}", event, target),
+                None => event.clone(),
+            },
+        };
+    }
+
+    fn matches(&self, criterion_event: &AdvancementCriterion) -> bool {
+        let (event, target) = match self {
+            Self::CollectItem { item, .. } => (advancements::ITEM_PICKUP, Some(item)),
+            Self::KillEntity { entity, .. } => (advancements::ENTITY_KILL, Some(entity)),
+            Self::ReachLocation { .. } => return false,
+            Self::Event { event, target, .. } => (event.as_str(), target.as_ref()),
+        };
+
+        return event == criterion_event.event
+            && target.map_or(true, |target| {
+                criterion_event.target.as_ref() == Some(target)
+            });
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct QuestRewards {
+    #[serde(default)]
+    pub items: Vec<ItemReward>,
+    /// Given through the economy, only if the `EconomyPlugin` is added.
+    #[serde(default)]
+    pub currency: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ItemReward {
+    /// Name of the item
+    pub item: String,
+    pub count: u32,
+}
+
+impl QuestRewards {
+    /// The reward items, split into stacks no larger than the items allow.
+    pub fn item_stacks(&self, items: &Items) -> Vec<ItemStack> {
+        let mut item_stacks = Vec::new();
+
+        for reward in self.items.iter() {
+            let Some(item_id) = items.get_id(&reward.item) else {
+                error!(
+                    "The quest reward '{}' is not an item, it was not given.",
+                    reward.item
+                );
+                continue;
+            };
+
+            let capacity = items.get_config(&item_id).max_stack_size.max(1);
+            let mut remaining = reward.count;
+            while remaining > 0 {
+                let size = remaining.min(capacity);
+                item_stacks.push(ItemStack::new(Item::new(item_id), size, capacity));
+                remaining -= size;
+            }
+        }
+
+        return item_stacks;
+    }
+}
+
+/// All quests, by name
+#[derive(Resource, Default)]
+pub struct Quests(HashMap<String, Quest>);
+
+impl Quests {
+    pub fn get(&self, name: &str) -> Option<&Quest> {
+        return self.0.get(name);
+    }
+}
+
+/// The quests of a player, inserted when the player joins.
+#[derive(Component, Serialize, Deserialize, Default)]
+pub struct QuestLog {
+    // Progress of each objective of the active quests. Ordered so the log is always listed the
+    // same way.
+    active: BTreeMap<String, Vec<u32>>,
+    completed: HashSet<String>,
+}
+
+impl QuestLog {
+    pub fn is_active(&self, quest: &str) -> bool {
+        return self.active.contains_key(quest);
+    }
+
+    pub fn is_completed(&self, quest: &str) -> bool {
+        return self.completed.contains(quest);
+    }
+
+    /// How many times the event of the objective has happened, None if the quest is not active.
+    pub fn objective_progress(&self, quest: &str, objective: usize) -> Option<u32> {
+        return self
+            .active
+            .get(quest)
+            .and_then(|progress| progress.get(objective))
+            .copied();
+    }
+}
+
+// How many lines of the quest log have been sent, so the ones left over when quests are removed
+// can be cleared.
+#[derive(Component, Default)]
+struct SentQuestLogLines(usize);
+
+fn load_quests(mut commands: Commands) {
+    let mut quests = Quests::default();
+
+    // Servers are not required to have any quests.
+    let Ok(directory) = std::fs::read_dir(QUEST_PATH) else {
+        commands.insert_resource(quests);
+        return;
+    };
+
+    for entry in directory {
+        let file_path = entry
+            .expect("Failed to read the filenames of the quest configs")
+            .path();
+        let name = file_path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+
+        let file = match std::fs::File::open(&file_path) {
+            Ok(f) => f,
+            Err(e) => panic!(
+                "Failed to open quest config at: {}\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        let quest: Quest = match serde_json::from_reader(&file) {
+            Ok(c) => c,
+            Err(e) => panic!(
+                "Couldn't read quest config from '{}'\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        if quest.objectives.is_empty() {
+            panic!(
+                "Failed to parse quest config at: {}\nError: The quest has no objectives.",
+                file_path.display()
+            );
+        }
+
+        quests.0.insert(name, quest);
+    }
+
+    commands.insert_resource(quests);
+}
+
+fn load_quest_logs(
+    mut commands: Commands,
+    quests: Res<Quests>,
+    player_query: Query<(Entity, &PlayerSave), Added<PlayerSave>>,
+) {
+    for (player_entity, save) in player_query.iter() {
+        let mut quest_log: QuestLog = save.get(SAVE_ENTRY).unwrap_or_default();
+
+        // The quest configs may have changed since the player last played
+        quest_log.active.retain(|name, progress| {
+            let Some(quest) = quests.get(name) else {
+                return false;
+            };
+            progress.resize(quest.objectives.len(), 0);
+            return true;
+        });
+
+        commands
+            .entity(player_entity)
+            .insert((quest_log, SentQuestLogLines::default()));
+    }
+}
+
+// A resumed session has a new client with an empty quest log.
+fn forget_sent_quest_log_lines(
+    mut player_query: Query<&mut SentQuestLogLines, Added<ResumedSession>>,
+) {
+    for mut sent_lines in player_query.iter_mut() {
+        sent_lines.0 = 0;
+    }
+}
+
+fn start_quests(
+    quests: Res<Quests>,
+    mut quest_log_query: Query<&mut QuestLog>,
+    mut start_events: EventReader<StartQuest>,
+    mut notification_events: EventWriter<Notification>,
+) {
+    for start in start_events.read() {
+        let Some(quest) = quests.get(&start.quest) else {
+            error!(
+                "Tried to start the quest '{}', but there is no quest by that name.",
+                start.quest
+            );
+            continue;
+        };
+
+        let Ok(mut quest_log) = quest_log_query.get_mut(start.player_entity) else {
+            continue;
+        };
+
+        if quest_log.is_active(&start.quest)
+            || (quest_log.is_completed(&start.quest) && !quest.repeatable)
+        {
+            continue;
+        }
+
+        quest_log
+            .active
+            .insert(start.quest.clone(), vec![0; quest.objectives.len()]);
+
+        notification_events.send(Notification {
+            player_entity: Some(start.player_entity),
+            icon: None,
+            title: "Quest started".to_owned(),
+            body: quest.title.clone(),
+            duration: 5.0,
+        });
+    }
+}
+
+fn abandon_quests(
+    mut quest_log_query: Query<&mut QuestLog>,
+    mut abandon_events: EventReader<AbandonQuest>,
+) {
+    for abandon in abandon_events.read() {
+        if let Ok(mut quest_log) = quest_log_query.get_mut(abandon.player_entity) {
+            if quest_log.is_active(&abandon.quest) {
+                quest_log.active.remove(&abandon.quest);
+            }
+        }
+    }
+}
+
+fn count_events(
+    quests: Res<Quests>,
+    mut quest_log_query: Query<&mut QuestLog>,
+    mut criterion_events: EventReader<AdvancementCriterion>,
+) {
+    for criterion_event in criterion_events.read() {
+        let Ok(mut quest_log) = quest_log_query.get_mut(criterion_event.player_entity) else {
+            continue;
+        };
+
+        // Only take the log mutably when something changes, it is saved on every change.
+        let matching: Vec<(String, usize)> = quest_log
+            .active
+            .iter()
+            .flat_map(|(name, progress)| {
+                let quest = &quests.0[name];
+                quest
+                    .objectives
+                    .iter()
+                    .enumerate()
+                    .filter(move |(index, objective)| {
+                        progress[*index] < objective.count() && objective.matches(criterion_event)
+                    })
+                    .map(move |(index, _)| (name.clone(), index))
+            })
+            .collect();
+
+        for (name, index) in matching {
+            let count = quests.0[&name].objectives[index].count();
+            let progress = &mut quest_log.active.get_mut(&name).unwrap()[index];
+            *progress = (*progress + criterion_event.amount).min(count);
+        }
+    }
+}
+
+fn check_locations(
+    quests: Res<Quests>,
+    mut player_query: Query<(&GlobalTransform, &mut QuestLog)>,
+) {
+    for (transform, mut quest_log) in player_query.iter_mut() {
+        let position = transform.translation();
+
+        let reached: Vec<(String, usize)> = quest_log
+            .active
+            .iter()
+            .flat_map(|(name, progress)| {
+                let quest = &quests.0[name];
+                quest
+                    .objectives
+                    .iter()
+                    .enumerate()
+                    .filter(move |(index, objective)| {
+                        let Objective::ReachLocation {
+                            position: target,
+                            radius,
+                        } = objective
+                        else {
+                            return false;
+                        };
+                        progress[*index] == 0
+                            && position.distance(DVec3::from_array(*target)) <= *radius
+                    })
+                    .map(move |(index, _)| (name.clone(), index))
+            })
+            .collect();
+
+        for (name, index) in reached {
+            quest_log.active.get_mut(&name).unwrap()[index] = 1;
+        }
+    }
+}
+
+fn complete_quests(
+    quests: Res<Quests>,
+    mut quest_log_query: Query<(Entity, &mut QuestLog), Changed<QuestLog>>,
+    mut completed_events: EventWriter<QuestCompleted>,
+    mut notification_events: EventWriter<Notification>,
+) {
+    for (player_entity, mut quest_log) in quest_log_query.iter_mut() {
+        let completed: Vec<String> = quest_log
+            .active
+            .iter()
+            .filter(|(name, progress)| {
+                quests.0[*name]
+                    .objectives
+                    .iter()
+                    .zip(progress.iter())
+                    .all(|(objective, progress)| *progress >= objective.count())
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in completed {
+            quest_log.active.remove(&name);
+            quest_log.completed.insert(name.clone());
+
+            notification_events.send(Notification {
+                player_entity: Some(player_entity),
+                icon: None,
+                title: "Quest completed".to_owned(),
+                body: quests.0[&name].title.clone(),
+                duration: 5.0,
+            });
+            completed_events.send(QuestCompleted {
+                player_entity,
+                quest: name,
+            });
+        }
+    }
+}
+
+fn save_quest_logs(mut player_query: Query<(Ref<QuestLog>, &mut PlayerSave)>) {
+    for (quest_log, mut save) in player_query.iter_mut() {
+        // Freshly loaded logs are already saved
+        if !quest_log.is_changed() || quest_log.is_added() {
+            continue;
+        }
+
+        save.set(SAVE_ENTRY, &*quest_log);
+    }
+}
+
+// TODO: There's no way to tell if the interface exists on the client. If the server's assets
+// don't include it the player will be disconnected when they join.
+fn send_quest_logs(
+    net: Res<Server>,
+    quests: Res<Quests>,
+    mut player_query: Query<
+        (Entity, &QuestLog, &mut SentQuestLogLines),
+        Or<(Changed<QuestLog>, Added<ResumedSession>)>,
+    >,
+) {
+    for (player_entity, quest_log, mut sent_lines) in player_query.iter_mut() {
+        let mut lines = Vec::new();
+        for (name, progress) in quest_log.active.iter() {
+            let quest = &quests.0[name];
+            lines.push((quest.title.clone(), QUEST_TITLE_COLOR));
+
+            for (objective, progress) in quest.objectives.iter().zip(progress.iter()) {
+                let count = objective.count();
+                let (text, color) = if *progress >= count {
+                    (objective.description(), COMPLETED_OBJECTIVE_COLOR)
+                } else if count > 1 {
+                    (
+                        format!("{} ({}/{})", objective.description(), progress, count),
+                        OBJECTIVE_COLOR,
+                    )
+                } else {
+                    (objective.description(), OBJECTIVE_COLOR)
+                };
+                lines.push(("  ".to_owned() + &text, color));
+            }
+        }
+
+        let line_count = lines.len();
+        // Lines that are no longer used are cleared
+        let cleared = (line_count..sent_lines.0).map(|_| (String::new(), OBJECTIVE_COLOR));
+
+        for (index, (text, color)) in lines.into_iter().chain(cleared).enumerate() {
+            net.send_one(
+                player_entity,
+                messages::InterfaceTextUpdate {
+                    interface_path: QUEST_LOG_INTERFACE.to_owned(),
+                    index: index as i32,
+                    text,
+                    font_size: LOG_FONT_SIZE,
+                    color: color.to_owned(),
+                },
+            );
+        }
+
+        sent_lines.0 = line_count;
+    }
+}
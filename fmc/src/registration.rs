@@ -0,0 +1,204 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    app::{AppExit, MainScheduleOrder},
+    ecs::schedule::{ExecutorKind, ScheduleLabel},
+};
+use indexmap::IndexMap;
+
+use crate::{database::Database, items::ITEM_CONFIG_PATH, prelude::*};
+
+// Registered blocks are written here, the block loader reads its directory recursively.
+const REGISTERED_BLOCK_PATH: &str = "./assets/client/blocks/registered/";
+// The item configs that were written the last time the server started. Item configs are read by
+// filename from a single directory on both the server and the client, so registered items can't be
+// kept apart from the files like the blocks are. They are removed by this list instead.
+const REGISTERED_ITEMS_RECORD: &str = "./assets/registered_items.json";
+
+/// Blocks and items can be registered from code during the [`Register`] schedule, in addition to
+/// the config files in the asset directory. They are written to the asset directory before the
+/// rest of the startup, so they get ids, are loaded and are sent to clients the same way as the
+/// config files.
+pub struct RegistrationPlugin;
+impl Plugin for RegistrationPlugin {
+    fn build(&self, app: &mut App) {
+        let mut register = Schedule::new(Register);
+        register.set_executor_kind(ExecutorKind::SingleThreaded);
+        let mut save = Schedule::new(SaveRegistrations);
+        save.set_executor_kind(ExecutorKind::SingleThreaded);
+
+        app.add_schedule(register)
+            .add_schedule(save)
+            .init_resource::<Registry>()
+            .add_systems(SaveRegistrations, save_registrations);
+
+        let mut order = app.world_mut().resource_mut::<MainScheduleOrder>();
+        order.insert_startup_before(PreStartup, Register);
+        order.insert_startup_before(PreStartup, SaveRegistrations);
+    }
+}
+
+/// Runs once before [`PreStartup`]. Systems that add to the [`Registry`] must be added to it.
+///
+/// ```ignore
+/// fn register_wool(mut registry: ResMut<Registry>) {
+///     for color in ["red", "green", "blue"] {
+///         registry.register_block(
+///             format!("{}_wool", color),
+///             serde_json::json!({
+///                 "extends": "wool",
+///                 "faces": { "top": format!("{}_wool.png", color) }
+///             }),
+///         );
+///     }
+/// }
+///
+/// app.add_systems(Register, register_wool);
+/// ```
+#[derive(ScheduleLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct Register;
+
+// Runs after Register, writes what was registered.
+#[derive(ScheduleLabel, Debug, Hash, PartialEq, Eq, Clone)]
+struct SaveRegistrations;
+
+/// The blocks and items registered from code, see [`Register`]
+#[derive(Resource, Default)]
+pub struct Registry {
+    blocks: IndexMap<String, serde_json::Value>,
+    items: IndexMap<String, serde_json::Value>,
+}
+
+impl Registry {
+    /// Register a block. The config is the same json as the block config files, it can extend
+    /// the templates of the config files. Its "name" field is set to the name.
+    pub fn register_block(&mut self, name: impl Into<String>, mut config: serde_json::Value) {
+        let name = name.into();
+        let Some(object) = config.as_object_mut() else {
+            panic!(
+                "The config of the registered block '{}' is not a json object",
+                name
+            );
+        };
+        object.insert("name".to_owned(), serde_json::Value::String(name.clone()));
+
+        if self.blocks.insert(name.clone(), config).is_some() {
+            panic!("The block '{}' was registered twice", name);
+        }
+    }
+
+    /// Register an item. The config is the same json as the item config files, the name is what
+    /// the item is referred to by, like the filename of the config files.
+    pub fn register_item(&mut self, name: impl Into<String>, config: serde_json::Value) {
+        let name = name.into().to_lowercase();
+        if !config.is_object() {
+            panic!(
+                "The config of the registered item '{}' is not a json object",
+                name
+            );
+        }
+
+        if self.items.insert(name.clone(), config).is_some() {
+            panic!("The item '{}' was registered twice", name);
+        }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (&String, &serde_json::Value)> {
+        return self.blocks.iter();
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = (&String, &serde_json::Value)> {
+        return self.items.iter();
+    }
+}
+
+fn save_registrations(
+    database: Res<Database>,
+    registry: Res<Registry>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    // Blocks registered on an earlier run might not be anymore
+    if Path::new(REGISTERED_BLOCK_PATH).exists() {
+        if let Err(e) = std::fs::remove_dir_all(REGISTERED_BLOCK_PATH) {
+            panic!(
+                "Could not remove the registered blocks at '{}'\nError: {}",
+                REGISTERED_BLOCK_PATH, e
+            );
+        }
+    }
+
+    if !registry.blocks.is_empty() {
+        std::fs::create_dir_all(REGISTERED_BLOCK_PATH).unwrap();
+    }
+
+    for (name, config) in registry.blocks.iter() {
+        write_config(
+            &Path::new(REGISTERED_BLOCK_PATH).join(format!("{}.json", name)),
+            config,
+        );
+    }
+
+    let previous_items: Vec<PathBuf> = std::fs::read(REGISTERED_ITEMS_RECORD)
+        .ok()
+        .and_then(|record| serde_json::from_slice(&record).ok())
+        .unwrap_or_default();
+    for path in previous_items {
+        std::fs::remove_file(path).ok();
+    }
+
+    let mut item_paths = Vec::new();
+    for (name, config) in registry.items.iter() {
+        let path = Path::new(ITEM_CONFIG_PATH).join(format!("{}.json", name));
+        if path.exists() {
+            panic!(
+                "The item '{}' was registered, but there is already a config file for it at '{}'",
+                name,
+                path.display()
+            );
+        }
+        write_config(&path, config);
+        item_paths.push(path);
+    }
+
+    if let Err(e) = std::fs::write(
+        REGISTERED_ITEMS_RECORD,
+        serde_json::to_vec(&item_paths).unwrap(),
+    ) {
+        panic!(
+            "Could not save the list of registered items to '{}'\nError: {}",
+            REGISTERED_ITEMS_RECORD, e
+        );
+    }
+
+    // Blocks that were removed are an error if they are still in the world, the flag removes them.
+    let remove_missing = std::env::args().any(|arg| arg == "--remove-missing-blocks");
+    if let Err(e) = database.save_block_ids(remove_missing) {
+        error!("{}", e);
+        exit_events.send(AppExit::error());
+        return;
+    }
+    database.save_items();
+
+    // Models are generated from the block configs, including the ones registered above, and
+    // have to exist before they are given ids.
+    crate::assets::generate_models();
+    database.save_models();
+
+    info!(
+        "Registered {} blocks and {} items",
+        registry.blocks.len(),
+        registry.items.len()
+    );
+}
+
+fn write_config(path: &Path, config: &serde_json::Value) {
+    // Pretty so they are readable when looking at what was registered
+    let json = serde_json::to_vec_pretty(config).unwrap();
+    if let Err(e) = std::fs::write(path, json) {
+        panic!(
+            "Could not write the registered config to '{}'\nError: {}",
+            path.display(),
+            e
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+
+use fmc_protocol::messages;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blocks::{BlockData, BlockPosition, Blocks},
+    items::{EquippedItem, Items},
+    networking::NetworkMessage,
+    players::{Player, Target, Targets},
+    prelude::*,
+    world::WorldMap,
+};
+
+/// Name of the item property that makes an item spawn an entity when the player uses it on a
+/// block. Its value is the name the entity was registered with in `Spawnables`.
+///
+/// ```json
+/// "properties": {
+///     "spawns": "zombie"
+/// }
+/// ```
+pub const SPAWN_ITEM_PROPERTY: &str = "spawns";
+
+// How many positions are tried for each entity a spawner spawns before it gives up.
+const SPAWN_ATTEMPTS: usize = 4;
+
+// Entities are registered by name, spawner blocks and spawn items then refer to them by that
+// name. Spawner blocks keep their configuration in their block data, so it is saved with the
+// block.
+pub struct SpawnerPlugin;
+impl Plugin for SpawnerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Spawnables>()
+            .add_event::<SpawnItemUsed>()
+            .add_systems(Update, (use_spawn_items, tick_spawners).chain())
+            .add_systems(PostUpdate, save_spawners);
+    }
+}
+
+/// Function that spawns an entity at a position, returns the spawned entity.
+pub type SpawnFunction = fn(&mut Commands, DVec3) -> Entity;
+
+/// The entities that can be spawned by spawner blocks and spawn items.
+///
+/// ```ignore
+/// fn register_zombie(mut spawnables: ResMut<Spawnables>) {
+///     spawnables.register("zombie", spawn_zombie);
+/// }
+/// ```
+#[derive(Resource, Default)]
+pub struct Spawnables {
+    functions: HashMap<String, SpawnFunction>,
+}
+
+impl Spawnables {
+    pub fn register(&mut self, name: impl Into<String>, function: SpawnFunction) {
+        self.functions.insert(name.into(), function);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        return self.functions.contains_key(name);
+    }
+
+    /// Spawn the entity registered with the name, None if there is none.
+    pub fn spawn(&self, commands: &mut Commands, name: &str, position: DVec3) -> Option<Entity> {
+        let function = self.functions.get(name)?;
+        return Some((function)(commands, position));
+    }
+}
+
+/// Configuration of a spawner block. It is inserted on the block's entity by `spawner_block`,
+/// and saved as the block's data when changed.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Spawner {
+    /// Name of the entity that is spawned, spawners without one do nothing. It is set by using a
+    /// spawn item on the spawner.
+    pub entity: Option<String>,
+    /// The spawner is only active while a player is within this many blocks of it.
+    pub activation_radius: f64,
+    /// Seconds between each time the spawner spawns.
+    pub delay: f64,
+    /// How many entities are spawned each time.
+    pub count: u32,
+    /// The spawner pauses while this many of the entities it spawned are alive.
+    pub max_alive: u32,
+    /// How far from the spawner, horizontally, entities may be placed.
+    pub range: u32,
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self {
+            entity: None,
+            activation_radius: 16.0,
+            delay: 10.0,
+            count: 2,
+            max_alive: 6,
+            range: 4,
+        }
+    }
+}
+
+// Spawner state that is not saved.
+#[derive(Component, Default)]
+struct SpawnerState {
+    elapsed: f64,
+    spawned: Vec<Entity>,
+}
+
+/// Spawn function for spawner blocks, set it on the block in the game's setup.
+///
+/// ```ignore
+/// fn setup_spawner(mut blocks: ResMut<Blocks>) {
+///     let block_id = blocks.get_id("spawner");
+///     blocks
+///         .get_config_mut(&block_id)
+///         .set_spawn_function(spawners::spawner_block);
+/// }
+/// ```
+pub fn spawner_block(entity_commands: &mut EntityCommands, block_data: Option<&BlockData>) {
+    let spawner = match block_data {
+        Some(block_data) => bincode::deserialize(&block_data.0).unwrap_or_else(|e| {
+            error!(
+                "Spawner has corrupt block data, it will be reset. Error: {}",
+                e
+            );
+            Spawner::default()
+        }),
+        None => Spawner::default(),
+    };
+
+    // Newly placed spawners get their data right away, so that the first change to it is saved.
+    if block_data.is_none() {
+        entity_commands.insert(BlockData(bincode::serialize(&spawner).unwrap()));
+    }
+
+    entity_commands.insert((spawner, SpawnerState::default()));
+}
+
+/// Sent when a player uses a spawn item, either to spawn its entity or to set the entity of a
+/// spawner. Games should remove the item from the player's inventory if it is consumed.
+#[derive(Event)]
+pub struct SpawnItemUsed {
+    pub player_entity: Entity,
+    /// The entity that was spawned, None if a spawner was changed.
+    pub spawned: Option<Entity>,
+}
+
+fn use_spawn_items(
+    mut commands: Commands,
+    items: Res<Items>,
+    spawnables: Res<Spawnables>,
+    player_query: Query<(&EquippedItem, &Targets), With<Player>>,
+    mut spawner_query: Query<&mut Spawner>,
+    mut clicks: EventReader<NetworkMessage<messages::RightClick>>,
+    mut spawn_item_events: EventWriter<SpawnItemUsed>,
+) {
+    for click in clicks.read() {
+        let Ok((equipped_item, targets)) = player_query.get(click.player_entity) else {
+            continue;
+        };
+
+        let Some(item_id) = equipped_item.0 else {
+            continue;
+        };
+
+        let Some(name) = items
+            .get_config(&item_id)
+            .properties
+            .get(SPAWN_ITEM_PROPERTY)
+            .and_then(|name| name.as_str())
+        else {
+            continue;
+        };
+
+        if !spawnables.contains(name) {
+            error!(
+                "The item '{}' spawns '{}', but no entity has been registered by that name.",
+                items.get_config(&item_id).name,
+                name
+            );
+            continue;
+        }
+
+        let Some(Target::Block {
+            block_position,
+            block_face,
+            entity,
+            ..
+        }) = targets.first()
+        else {
+            continue;
+        };
+
+        if let Some(mut spawner) = entity.and_then(|entity| spawner_query.get_mut(entity).ok()) {
+            if spawner.entity.as_deref() != Some(name) {
+                spawner.entity = Some(name.to_owned());
+                spawn_item_events.send(SpawnItemUsed {
+                    player_entity: click.player_entity,
+                    spawned: None,
+                });
+            }
+            continue;
+        }
+
+        let position =
+            block_face.shift_position(*block_position).as_dvec3() + DVec3::new(0.5, 0.0, 0.5);
+        let spawned = spawnables.spawn(&mut commands, name, position);
+        spawn_item_events.send(SpawnItemUsed {
+            player_entity: click.player_entity,
+            spawned,
+        });
+    }
+}
+
+fn tick_spawners(
+    mut commands: Commands,
+    time: Res<Time>,
+    world_map: Res<WorldMap>,
+    spawnables: Res<Spawnables>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    entity_query: Query<()>,
+    mut spawner_query: Query<(&BlockPosition, &Spawner, &mut SpawnerState)>,
+) {
+    for (block_position, spawner, mut state) in spawner_query.iter_mut() {
+        let Some(name) = &spawner.entity else {
+            continue;
+        };
+
+        let center = block_position.0.as_dvec3() + DVec3::splat(0.5);
+        let activated = player_query
+            .iter()
+            .any(|transform| transform.translation().distance(center) <= spawner.activation_radius);
+        if !activated {
+            continue;
+        }
+
+        state
+            .spawned
+            .retain(|entity| entity_query.contains(*entity));
+
+        state.elapsed += time.delta_secs_f64();
+        if state.elapsed < spawner.delay {
+            continue;
+        }
+        state.elapsed = 0.0;
+
+        let count = spawner
+            .count
+            .min(spawner.max_alive.saturating_sub(state.spawned.len() as u32));
+
+        for _ in 0..count {
+            let Some(position) = find_spawn_position(&world_map, block_position.0, spawner.range)
+            else {
+                continue;
+            };
+
+            if let Some(entity) = spawnables.spawn(&mut commands, name, position) {
+                state.spawned.push(entity);
+            }
+        }
+    }
+}
+
+// Entities are placed standing on a solid block with two non-solid blocks above it, at the same
+// height as the spawner or one block above or below.
+fn find_spawn_position(world_map: &WorldMap, spawner_position: IVec3, range: u32) -> Option<DVec3> {
+    let blocks = Blocks::get();
+    let is_solid = |position: IVec3| {
+        world_map
+            .get_block(position)
+            .map(|block_id| blocks.get_config(&block_id).is_solid())
+    };
+
+    let range = range as i32;
+    for _ in 0..SPAWN_ATTEMPTS {
+        let offset = IVec3::new(
+            rand::random::<i32>().rem_euclid(range * 2 + 1) - range,
+            rand::random::<i32>().rem_euclid(3) - 1,
+            rand::random::<i32>().rem_euclid(range * 2 + 1) - range,
+        );
+        let position = spawner_position + offset;
+
+        if is_solid(position - IVec3::Y) == Some(true)
+            && is_solid(position) == Some(false)
+            && is_solid(position + IVec3::Y) == Some(false)
+        {
+            return Some(position.as_dvec3() + DVec3::new(0.5, 0.0, 0.5));
+        }
+    }
+
+    return None;
+}
+
+fn save_spawners(mut spawner_query: Query<(Ref<Spawner>, &mut BlockData)>) {
+    for (spawner, mut block_data) in spawner_query.iter_mut() {
+        if spawner.is_changed() && !spawner.is_added() {
+            block_data.0 = bincode::serialize(&*spawner).unwrap();
+        }
+    }
+}
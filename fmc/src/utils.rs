@@ -47,6 +47,13 @@ impl Rng {
         Self { seed }
     }
 
+    /// An rng that always gives the same numbers for the same world seed, position and purpose.
+    /// Different purposes give independent streams at the same position, e.g. "trees" and
+    /// "ores" of a chunk.
+    pub fn from_position(seed: u64, position: IVec3, purpose: &str) -> Self {
+        Self::new(position_seed(seed, position, purpose))
+    }
+
     pub fn next_u32(&mut self) -> u32 {
         let seed = self.seed.wrapping_add(0x2d35_8dcc_aa6c_78a5);
         self.seed = seed;
@@ -65,3 +72,28 @@ impl Rng {
         f32::from_bits((result >> 9) | (127 << 23)) - 1.0
     }
 }
+
+/// Derive a seed from the world seed, a position and what it will be used for. The result is the
+/// same on every platform, so anything generated from it is reproducible.
+pub fn position_seed(seed: u64, position: IVec3, purpose: &str) -> u64 {
+    // FNV-1a of the purpose, std's hasher is not guaranteed to be stable between releases.
+    let mut purpose_hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in purpose.bytes() {
+        purpose_hash ^= byte as u64;
+        purpose_hash = purpose_hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    let mut hash = splitmix64(seed ^ purpose_hash);
+    for value in [position.x, position.y, position.z] {
+        hash = splitmix64(hash ^ value as u32 as u64);
+    }
+    return hash;
+}
+
+// Finalizer of splitmix64, scrambles all bits of the input.
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    return z ^ (z >> 31);
+}
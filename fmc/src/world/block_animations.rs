@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use bevy::math::{DQuat, DVec3};
+
+use crate::{
+    blocks::{BlockId, BlockState},
+    networking::{properties, Server},
+    prelude::*,
+    utils,
+};
+
+use super::{chunk_manager::ChunkSubscriptions, BlockUpdate, WorldMap};
+
+// The animations are sent to the players as the "block_animation" property, see
+// `BlockAnimation::to_property`. The block in the world is only changed when the animation is
+// over, the client keeps drawing the animated block in its last pose until then.
+pub struct BlockAnimationPlugin;
+impl Plugin for BlockAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BlockAnimation>()
+            .init_resource::<PendingBlockAnimations>()
+            .add_systems(
+                Update,
+                (send_block_animations, finish_block_animations).chain(),
+            );
+    }
+}
+
+/// A short animation of a block moving, like a piston extending, a door swinging or a drawbridge
+/// lowering. The players draw the block moving from one pose to the other, and when it is over
+/// the block in `then` is placed at the position.
+///
+/// The block that is in the world at the position is still there while the animation plays. If
+/// it is the block that is animated, like a door, replace it with air first so it isn't drawn
+/// twice.
+#[derive(Event, Clone)]
+pub struct BlockAnimation {
+    /// The block the poses are relative to
+    pub position: IVec3,
+    /// The block that is drawn moving
+    pub block_id: BlockId,
+    pub block_state: Option<BlockState>,
+    /// The point the block rotates around, relative to the position, e.g. the hinge of a door.
+    pub pivot: DVec3,
+    pub from: BlockPose,
+    pub to: BlockPose,
+    pub duration: Duration,
+    /// The block that is placed at the position when the animation is over
+    pub then: Option<(BlockId, Option<BlockState>)>,
+}
+
+impl BlockAnimation {
+    fn to_property(&self) -> properties::BlockAnimation {
+        let pose = |pose: BlockPose| properties::BlockPose {
+            translation: pose.translation.to_array(),
+            rotation: pose.rotation.to_array(),
+        };
+
+        return properties::BlockAnimation {
+            position: self.position.to_array(),
+            block_id: self.block_id,
+            block_state: self.block_state.map(|state| state.as_u16()),
+            duration: self.duration.as_secs_f32(),
+            pivot: self.pivot.to_array(),
+            from: pose(self.from),
+            to: pose(self.to),
+        };
+    }
+}
+
+/// Where an animated block is drawn, relative to where it is in the world.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockPose {
+    pub translation: DVec3,
+    /// Rotation around the animation's pivot
+    pub rotation: DQuat,
+}
+
+impl BlockPose {
+    /// Where the block is
+    pub const IDENTITY: Self = Self {
+        translation: DVec3::ZERO,
+        rotation: DQuat::IDENTITY,
+    };
+
+    pub fn from_translation(translation: DVec3) -> Self {
+        Self {
+            translation,
+            rotation: DQuat::IDENTITY,
+        }
+    }
+
+    pub fn from_rotation(rotation: DQuat) -> Self {
+        Self {
+            translation: DVec3::ZERO,
+            rotation,
+        }
+    }
+}
+
+impl Default for BlockPose {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+// Block updates waiting for their animation to end, as the time they're applied at.
+#[derive(Resource, Default)]
+struct PendingBlockAnimations(Vec<(Duration, IVec3, BlockId, Option<BlockState>)>);
+
+fn send_block_animations(
+    net: Res<Server>,
+    time: Res<Time>,
+    chunk_subscriptions: Res<ChunkSubscriptions>,
+    mut pending: ResMut<PendingBlockAnimations>,
+    mut animation_events: EventReader<BlockAnimation>,
+) {
+    for animation in animation_events.read() {
+        let chunk_position = utils::world_position_to_chunk_position(animation.position);
+        if let Some(subscribers) = chunk_subscriptions.get_subscribers(&chunk_position) {
+            let property = animation.to_property();
+            for player_entity in subscribers.iter() {
+                net.send_property(*player_entity, properties::BLOCK_ANIMATION, &property);
+            }
+        }
+
+        if let Some((block_id, block_state)) = animation.then {
+            pending.0.push((
+                time.elapsed() + animation.duration,
+                animation.position,
+                block_id,
+                block_state,
+            ));
+        }
+    }
+}
+
+fn finish_block_animations(
+    time: Res<Time>,
+    world_map: Res<WorldMap>,
+    mut pending: ResMut<PendingBlockAnimations>,
+    mut block_update_events: EventWriter<BlockUpdate>,
+) {
+    let now = time.elapsed();
+    pending
+        .0
+        .retain(|(ends_at, position, block_id, block_state)| {
+            if *ends_at > now {
+                return true;
+            }
+
+            // The chunk may have unloaded while it played
+            let chunk_position = utils::world_position_to_chunk_position(*position);
+            if world_map.contains_chunk(&chunk_position) {
+                block_update_events.send(BlockUpdate::Change {
+                    position: *position,
+                    block_id: *block_id,
+                    block_state: *block_state,
+                });
+            }
+
+            return false;
+        });
+}
@@ -0,0 +1,441 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    app::AppExit,
+    tasks::{futures_lite::future, IoTaskPool, Task},
+};
+
+use crate::{
+    blocks::{BlockId, Blocks},
+    chat::ChatCommand,
+    database::{BlockAuditEntry, Database, REMOVED_BLOCK_ID},
+    networking::Server,
+    players::{Operator, Player},
+    prelude::*,
+    utils,
+};
+
+use super::{BlockPermissions, BlockUpdate, PlayerBlockUpdate, WorldMap};
+
+// Seconds between each time the recorded changes are written to the database
+const SAVE_INTERVAL: f32 = 5.0;
+// Seconds between each time old entries are removed
+const PRUNE_INTERVAL: f32 = 3600.0;
+// How many changes "/who-placed" lists
+const WHO_PLACED_LIMIT: usize = 5;
+// Distance around the operator "/rollback" reaches when not given
+const DEFAULT_ROLLBACK_RADIUS: u32 = 32;
+
+// Block changes sent as `PlayerBlockUpdate` are logged to the "block_audit" table of the database
+// with who made them and when, so moderators can find and revert griefing. It is disabled by
+// default, enable it through `BlockAudit`.
+//
+// Commands, only for operators:
+// "/who-placed <x> <y> <z>" lists the latest changes to a block.
+// "/rollback <username> <minutes> [radius]" reverts what the player changed in the last minutes
+// around the operator. Blocks that have been changed by someone else since are left alone. Blocks
+// in chunks that aren't loaded are reverted in the database.
+//
+// Commands wait for the changes that are being saved to be written, so that they are included.
+pub struct BlockAuditPlugin;
+impl Plugin for BlockAuditPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BlockAudit>()
+            .init_resource::<PendingAudit>()
+            .init_resource::<SavingAudit>()
+            .init_resource::<QueuedAuditCommands>()
+            .insert_resource(AuditTimers {
+                save: Timer::from_seconds(SAVE_INTERVAL, TimerMode::Repeating),
+                // Prune when the server starts
+                prune: Timer::from_seconds(PRUNE_INTERVAL, TimerMode::Repeating),
+                has_pruned: false,
+            })
+            .add_systems(Update, (queue_audit_commands, run_audit_commands).chain())
+            .add_systems(
+                PostUpdate,
+                (
+                    record_block_changes.before(super::handle_block_updates),
+                    save_and_prune.after(record_block_changes),
+                ),
+            );
+    }
+}
+
+/// Settings of the block audit log.
+#[derive(Resource)]
+pub struct BlockAudit {
+    /// Log block changes made by players, off by default.
+    pub enabled: bool,
+    /// How long entries are kept before they are removed, None to keep them forever.
+    pub retention: Option<Duration>,
+}
+
+impl Default for BlockAudit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        }
+    }
+}
+
+// Changes that have not been written to the database yet
+#[derive(Resource, Default, Deref, DerefMut)]
+struct PendingAudit(Vec<BlockAuditEntry>);
+
+// Changes that are being written to the database in the background
+#[derive(Resource, Default)]
+struct SavingAudit(Vec<Task<()>>);
+
+// Commands waiting for the changes that were made before them to be saved
+#[derive(Resource, Default, Deref, DerefMut)]
+struct QueuedAuditCommands(Vec<ChatCommand>);
+
+#[derive(Resource)]
+struct AuditTimers {
+    save: Timer,
+    prune: Timer,
+    has_pruned: bool,
+}
+
+fn now() -> i64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+}
+
+fn record_block_changes(
+    audit: Res<BlockAudit>,
+    world_map: Res<WorldMap>,
+    mut pending: ResMut<PendingAudit>,
+    permissions: BlockPermissions,
+    player_query: Query<&Player>,
+    mut player_block_updates: EventReader<PlayerBlockUpdate>,
+) {
+    if !audit.enabled {
+        player_block_updates.clear();
+        return;
+    }
+
+    let time = now();
+
+    for update in player_block_updates.read() {
+        // Changes that are refused are not applied
+        if !permissions.can_modify(update.player_entity, update.position) {
+            continue;
+        }
+
+        let Ok(player) = player_query.get(update.player_entity) else {
+            continue;
+        };
+        let Some(block_id) = world_map.get_block(update.position) else {
+            continue;
+        };
+
+        pending.push(BlockAuditEntry {
+            position: update.position,
+            account_id: player.account_id.clone(),
+            username: player.username.clone(),
+            time,
+            from: (block_id, world_map.get_block_state(update.position)),
+            to: (update.block_id, update.block_state),
+        });
+    }
+}
+
+fn save_and_prune(
+    time: Res<Time>,
+    audit: Res<BlockAudit>,
+    database: Res<Database>,
+    mut timers: ResMut<AuditTimers>,
+    mut pending: ResMut<PendingAudit>,
+    mut saving: ResMut<SavingAudit>,
+    exit_events: EventReader<AppExit>,
+) {
+    if !exit_events.is_empty() {
+        if !pending.is_empty() {
+            database.save_block_audit(std::mem::take(&mut pending.0));
+        }
+        return;
+    }
+
+    saving.0.retain(|task| !task.is_finished());
+
+    timers.save.tick(time.delta());
+    if timers.save.just_finished() && !pending.is_empty() {
+        let database = database.clone();
+        let entries = std::mem::take(&mut pending.0);
+        saving
+            .0
+            .push(IoTaskPool::get().spawn(async move { database.save_block_audit(entries) }));
+    }
+
+    timers.prune.tick(time.delta());
+    if audit.enabled && (timers.prune.just_finished() || !timers.has_pruned) {
+        timers.has_pruned = true;
+        if let Some(retention) = audit.retention {
+            let database = database.clone();
+            let before = now() - retention.as_secs() as i64;
+            IoTaskPool::get()
+                .spawn(async move { database.prune_block_audit(before) })
+                .detach();
+        }
+    }
+}
+
+fn format_age(seconds: i64) -> String {
+    if seconds < 60 {
+        return format!("{} seconds ago", seconds);
+    } else if seconds < 60 * 60 {
+        return format!("{} minutes ago", seconds / 60);
+    } else if seconds < 24 * 60 * 60 {
+        return format!("{} hours ago", seconds / (60 * 60));
+    } else {
+        return format!("{} days ago", seconds / (24 * 60 * 60));
+    }
+}
+
+// Entries keep the ids of blocks that have since been removed, they have no config to name them.
+fn block_name(blocks: &Blocks, block_id: BlockId) -> &str {
+    if block_id == REMOVED_BLOCK_ID {
+        return "a removed block";
+    } else {
+        return &blocks.get_config(&block_id).name;
+    }
+}
+
+fn queue_audit_commands(
+    net: Res<Server>,
+    audit: Res<BlockAudit>,
+    database: Res<Database>,
+    mut pending: ResMut<PendingAudit>,
+    mut saving: ResMut<SavingAudit>,
+    mut queued: ResMut<QueuedAuditCommands>,
+    operator_query: Query<Has<Operator>, With<Player>>,
+    mut command_events: EventReader<ChatCommand>,
+) {
+    for command in command_events.read() {
+        if command.name != "who-placed" && command.name != "rollback" {
+            continue;
+        }
+
+        let Ok(is_operator) = operator_query.get(command.player_entity) else {
+            continue;
+        };
+
+        if !is_operator {
+            command.reply(&net, "Only operators can use this command.");
+            continue;
+        }
+
+        if !audit.enabled {
+            command.reply(&net, "The block audit is not enabled on this server.");
+            continue;
+        }
+
+        // The changes that are still waiting for the save timer are saved right away, so the
+        // command doesn't have to wait for it.
+        if !pending.is_empty() {
+            let database = database.clone();
+            let entries = std::mem::take(&mut pending.0);
+            saving
+                .0
+                .push(IoTaskPool::get().spawn(async move { database.save_block_audit(entries) }));
+        }
+
+        queued.push(ChatCommand {
+            player_entity: command.player_entity,
+            name: command.name.clone(),
+            args: command.args.clone(),
+        });
+    }
+}
+
+fn run_audit_commands(
+    net: Res<Server>,
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    mut saving: ResMut<SavingAudit>,
+    mut queued: ResMut<QueuedAuditCommands>,
+    transform_query: Query<&GlobalTransform, With<Player>>,
+    player_query: Query<&Player>,
+    mut block_updates: EventWriter<BlockUpdate>,
+) {
+    if queued.is_empty() {
+        return;
+    }
+
+    saving
+        .0
+        .retain_mut(|task| future::block_on(future::poll_once(task)).is_none());
+    if !saving.0.is_empty() {
+        return;
+    }
+
+    let blocks = Blocks::get();
+
+    for command in queued.drain(..) {
+        // The operator might have disconnected while it waited
+        let Ok(transform) = transform_query.get(command.player_entity) else {
+            continue;
+        };
+
+        if command.name == "who-placed" {
+            let coordinates: Vec<i32> = command
+                .args
+                .iter()
+                .filter_map(|arg| arg.parse::<i32>().ok())
+                .collect();
+            if command.args.len() != 3 || coordinates.len() != 3 {
+                command.reply(&net, "Usage: /who-placed <x> <y> <z>");
+                continue;
+            }
+
+            let position = IVec3::new(coordinates[0], coordinates[1], coordinates[2]);
+            let entries = database.load_block_audit_at(position, WHO_PLACED_LIMIT);
+            if entries.is_empty() {
+                command.reply(&net, "No player has changed this block.");
+                continue;
+            }
+
+            let time = now();
+            for entry in entries {
+                command.reply(
+                    &net,
+                    format!(
+                        "{} changed {} to {} {}",
+                        entry.username,
+                        block_name(blocks, entry.from.0),
+                        block_name(blocks, entry.to.0),
+                        format_age(time - entry.time)
+                    ),
+                );
+            }
+        } else {
+            // Unsigned so a negative window can't reach into the future
+            let (Some(username), Some(Ok(minutes))) = (
+                command.args.first(),
+                command.args.get(1).map(|minutes| minutes.parse::<u32>()),
+            ) else {
+                command.reply(&net, "Usage: /rollback <username> <minutes> [radius]");
+                continue;
+            };
+
+            let radius = match command.args.get(2).map(|radius| radius.parse::<u32>()) {
+                Some(Ok(radius)) => radius,
+                None => DEFAULT_ROLLBACK_RADIUS,
+                Some(Err(_)) => {
+                    command.reply(&net, "Usage: /rollback <username> <minutes> [radius]");
+                    continue;
+                }
+            };
+
+            // Usernames aren't unique over time, the changes are looked up by the account that
+            // has the name now.
+            let Some(account_id) = player_query
+                .iter()
+                .find(|player| &player.username == username)
+                .map(|player| player.account_id.clone())
+                .or_else(|| database.find_player_account(username))
+            else {
+                command.reply(&net, format!("There is no player named {}", username));
+                continue;
+            };
+
+            let center = transform.translation().floor().as_ivec3();
+            let radius = radius.min(i32::MAX as u32) as i32;
+            let entries = database.load_block_audit_by(
+                &account_id,
+                now() - minutes as i64 * 60,
+                center.saturating_sub(IVec3::splat(radius)),
+                center.saturating_add(IVec3::splat(radius)),
+            );
+
+            // Each block is put back to what it was before the first change in the window, if it
+            // is still what the player made it.
+            let mut changes: HashMap<IVec3, BlockAuditEntry> = HashMap::new();
+            for entry in entries {
+                if let Some(change) = changes.get_mut(&entry.position) {
+                    change.to = entry.to;
+                } else {
+                    changes.insert(entry.position, entry);
+                }
+            }
+
+            let mut reverted = 0;
+            let mut unloaded: HashMap<IVec3, Vec<(usize, BlockAuditEntry)>> = HashMap::new();
+            for change in changes.into_values() {
+                // The block it was is gone, there is nothing to put back.
+                if change.from.0 == REMOVED_BLOCK_ID {
+                    continue;
+                }
+
+                let Some(block_id) = world_map.get_block(change.position) else {
+                    let (chunk_position, block_index) =
+                        utils::world_position_to_chunk_position_and_block_index(change.position);
+                    unloaded
+                        .entry(chunk_position)
+                        .or_default()
+                        .push((block_index, change));
+                    continue;
+                };
+                if (block_id, world_map.get_block_state(change.position)) != change.to {
+                    continue;
+                }
+
+                block_updates.send(BlockUpdate::Change {
+                    position: change.position,
+                    block_id: change.from.0,
+                    block_state: change.from.1,
+                });
+                reverted += 1;
+            }
+
+            // Changes made by players are always saved, if the block isn't what the player made
+            // it in the database, it has been changed since.
+            let mut reverted_unloaded = 0;
+            for (chunk_position, changes) in unloaded {
+                let saved = database.load_chunk_blocks(&chunk_position);
+
+                let mut reverts = HashMap::new();
+                for (block_index, change) in changes {
+                    let Some((block_id, block_state, _)) = saved.get(&block_index) else {
+                        continue;
+                    };
+                    if (*block_id, *block_state) != change.to {
+                        continue;
+                    }
+
+                    reverts.insert(block_index, (change.from.0, change.from.1, None));
+                }
+
+                if !reverts.is_empty() {
+                    reverted_unloaded += reverts.len();
+                    database.save_chunk_blocks(&chunk_position, reverts);
+                }
+            }
+
+            if reverted_unloaded == 0 {
+                command.reply(
+                    &net,
+                    format!("Rolled back {} blocks changed by {}", reverted, username),
+                );
+            } else {
+                command.reply(
+                    &net,
+                    format!(
+                        "Rolled back {} blocks changed by {}, {} of them in chunks that aren't \
+                        loaded",
+                        reverted + reverted_unloaded,
+                        username,
+                        reverted_unloaded
+                    ),
+                );
+            }
+        }
+    }
+}
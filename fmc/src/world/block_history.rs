@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+
+use bevy::ecs::system::SystemParam;
+
+use crate::{
+    blocks::{BlockConfig, BlockId, BlockState},
+    chat::ChatCommand,
+    items::ItemConfig,
+    networking::Server,
+    players::{GameMode, Operator, Player},
+    prelude::*,
+};
+
+use super::{BlockUpdate, WorldMap, WorldSpawn};
+
+pub struct BlockHistoryPlugin;
+impl Plugin for BlockHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BlockHistorySettings>()
+            .add_event::<PlayerBlockUpdate>()
+            .add_systems(Update, (insert_block_history, handle_undo_commands).chain())
+            .add_systems(
+                PostUpdate,
+                record_player_block_updates.before(super::handle_block_updates),
+            );
+    }
+}
+
+/// Change a block on behalf of a player. Send it instead of a `BlockUpdate` when a player breaks
+/// or places a block, it is recorded in the player's `BlockHistory` so it can be undone. It is
+/// dropped if the player isn't allowed to change the block, see `BlockPermissions`.
+#[derive(Event)]
+pub struct PlayerBlockUpdate {
+    pub player_entity: Entity,
+    pub position: IVec3,
+    pub block_id: BlockId,
+    pub block_state: Option<BlockState>,
+}
+
+/// Decides which blocks players are allowed to change. Use it to check before e.g. taking a block
+/// out of the player's inventory, the `PlayerBlockUpdate` won't be applied if it isn't allowed.
+#[derive(SystemParam)]
+pub struct BlockPermissions<'w, 's> {
+    world_spawn: Option<Res<'w, WorldSpawn>>,
+    player_query: Query<'w, 's, (Option<&'static GameMode>, Has<Operator>), With<Player>>,
+}
+
+impl BlockPermissions<'_, '_> {
+    /// If the player's game mode allows it to change blocks, and the block isn't protected by
+    /// the world spawn.
+    pub fn can_modify(&self, player_entity: Entity, position: IVec3) -> bool {
+        let Ok((game_mode, is_operator)) = self.player_query.get(player_entity) else {
+            return false;
+        };
+
+        if game_mode.is_some_and(|game_mode| !game_mode.capabilities().can_modify_blocks) {
+            return false;
+        }
+
+        return self
+            .world_spawn
+            .as_ref()
+            .map_or(true, |spawn| spawn.can_modify(position, is_operator));
+    }
+
+    /// Seconds it takes the player to break the block with the tool it holds. None if the block
+    /// can't be broken, or the player isn't allowed to break it. Zero when the player's game mode
+    /// breaks blocks instantly.
+    pub fn break_time(
+        &self,
+        player_entity: Entity,
+        position: IVec3,
+        block_config: &BlockConfig,
+        tool: Option<&ItemConfig>,
+    ) -> Option<f32> {
+        if !self.can_modify(player_entity, position) {
+            return None;
+        }
+
+        let hardness = block_config.hardness?;
+
+        let (game_mode, _) = self.player_query.get(player_entity).ok()?;
+        if game_mode.is_some_and(|game_mode| game_mode.capabilities().can_break_instantly) {
+            return Some(0.0);
+        }
+
+        let efficiency = tool.map_or(1.0, |tool| tool.tool_efficiency(block_config));
+        return Some(hardness / efficiency);
+    }
+}
+
+#[derive(Resource)]
+pub struct BlockHistorySettings {
+    /// How many actions are remembered for each player, the oldest are forgotten first.
+    pub max_actions: usize,
+}
+
+impl Default for BlockHistorySettings {
+    fn default() -> Self {
+        Self { max_actions: 100 }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BlockChange {
+    position: IVec3,
+    from: (BlockId, Option<BlockState>),
+    to: (BlockId, Option<BlockState>),
+}
+
+// All the blocks a player changed during a single tick, e.g. a placed door is both of its halves.
+#[derive(Default)]
+struct Action {
+    changes: Vec<BlockChange>,
+}
+
+/// The blocks a player has changed, inserted on all players. Players whose game mode can undo
+/// walk back and forth through it with "/undo [count]" and "/redo [count]".
+#[derive(Component, Default)]
+pub struct BlockHistory {
+    undo: VecDeque<Action>,
+    redo: Vec<Action>,
+}
+
+impl BlockHistory {
+    /// Forget everything, e.g. when the player changes game mode.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+fn insert_block_history(mut commands: Commands, player_query: Query<Entity, Added<Player>>) {
+    for player_entity in player_query.iter() {
+        commands
+            .entity(player_entity)
+            .insert(BlockHistory::default());
+    }
+}
+
+fn record_player_block_updates(
+    world_map: Res<WorldMap>,
+    settings: Res<BlockHistorySettings>,
+    permissions: BlockPermissions,
+    mut history_query: Query<&mut BlockHistory>,
+    mut player_block_updates: EventReader<PlayerBlockUpdate>,
+    mut block_updates: EventWriter<BlockUpdate>,
+    // The action of each player this tick
+    mut actions: Local<Vec<(Entity, Action)>>,
+) {
+    for update in player_block_updates.read() {
+        if !permissions.can_modify(update.player_entity, update.position) {
+            continue;
+        }
+
+        // Changes to chunks that aren't loaded would panic when applied
+        let Some(block_id) = world_map.get_block(update.position) else {
+            continue;
+        };
+
+        block_updates.send(BlockUpdate::Change {
+            position: update.position,
+            block_id: update.block_id,
+            block_state: update.block_state,
+        });
+
+        let index = match actions
+            .iter()
+            .position(|(player_entity, _)| *player_entity == update.player_entity)
+        {
+            Some(index) => index,
+            None => {
+                actions.push((update.player_entity, Action::default()));
+                actions.len() - 1
+            }
+        };
+        let action = &mut actions[index].1;
+
+        let to = (update.block_id, update.block_state);
+        // A block changed twice in the same tick goes from what it was before the first change.
+        if let Some(change) = action
+            .changes
+            .iter_mut()
+            .find(|change| change.position == update.position)
+        {
+            change.to = to;
+        } else {
+            action.changes.push(BlockChange {
+                position: update.position,
+                from: (block_id, world_map.get_block_state(update.position)),
+                to,
+            });
+        }
+    }
+
+    for (player_entity, action) in actions.drain(..) {
+        let Ok(mut history) = history_query.get_mut(player_entity) else {
+            continue;
+        };
+
+        history.redo.clear();
+        history.undo.push_back(action);
+        while history.undo.len() > settings.max_actions {
+            history.undo.pop_front();
+        }
+    }
+}
+
+// "/undo [count]" and "/redo [count]". Blocks that have been changed by something else since are
+// left alone.
+fn handle_undo_commands(
+    net: Res<Server>,
+    world_map: Res<WorldMap>,
+    mut history_query: Query<(&mut BlockHistory, &GameMode, Has<Operator>)>,
+    mut command_events: EventReader<ChatCommand>,
+    mut block_updates: EventWriter<BlockUpdate>,
+) {
+    for command in command_events.read() {
+        let is_undo = match command.name.as_str() {
+            "undo" => true,
+            "redo" => false,
+            _ => continue,
+        };
+
+        let Ok((mut history, game_mode, is_operator)) =
+            history_query.get_mut(command.player_entity)
+        else {
+            continue;
+        };
+
+        if !game_mode.capabilities().can_undo && !is_operator {
+            command.reply(&net, "You can't undo in this game mode.");
+            continue;
+        }
+
+        let count = match command.args.first().map(|count| count.parse::<usize>()) {
+            Some(Ok(count)) => count,
+            None => 1,
+            Some(Err(_)) => {
+                command.reply(&net, format!("Usage: /{} [count]", command.name));
+                continue;
+            }
+        };
+
+        let mut done = 0;
+        for _ in 0..count {
+            let action = if is_undo {
+                history.undo.pop_back()
+            } else {
+                history.redo.pop()
+            };
+            let Some(action) = action else {
+                break;
+            };
+
+            for change in action.changes.iter() {
+                let (expected, target) = if is_undo {
+                    (change.to, change.from)
+                } else {
+                    (change.from, change.to)
+                };
+
+                let Some(block_id) = world_map.get_block(change.position) else {
+                    continue;
+                };
+                if (block_id, world_map.get_block_state(change.position)) != expected {
+                    continue;
+                }
+
+                block_updates.send(BlockUpdate::Change {
+                    position: change.position,
+                    block_id: target.0,
+                    block_state: target.1,
+                });
+            }
+
+            if is_undo {
+                history.redo.push(action);
+            } else {
+                history.undo.push_back(action);
+            }
+            done += 1;
+        }
+
+        if done == 0 {
+            command.reply(&net, format!("Nothing to {}.", command.name));
+        } else if is_undo {
+            command.reply(&net, format!("Undid {} actions.", done));
+        } else {
+            command.reply(&net, format!("Redid {} actions.", done));
+        }
+    }
+}
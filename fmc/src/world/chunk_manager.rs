@@ -1,5 +1,4 @@
 use bevy::{
-    math::DVec3,
     tasks::{futures_lite::future, AsyncComputeTaskPool, Task},
     utils::{HashMap, HashSet},
 };
@@ -10,7 +9,7 @@ use crate::{
     database::Database,
     models::{Model, ModelAnimations, ModelBundle, ModelVisibility},
     networking::{NetworkEvent, Server},
-    players::Player,
+    players::{Camera, Player},
     prelude::*,
     utils,
     world::{
@@ -24,8 +23,10 @@ pub struct ChunkManagerPlugin;
 impl Plugin for ChunkManagerPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<ChunkUnloadEvent>()
+            .add_event::<ChunkLoadEvent>()
             .add_event::<ChunkSubscriptionEvent>()
             .insert_resource(ChunkSubscriptions::default())
+            .init_resource::<ChunkSubscriptionSettings>()
             .add_systems(PostUpdate, add_and_remove_subscribers)
             .add_systems(
                 Update,
@@ -45,6 +46,111 @@ impl Plugin for ChunkManagerPlugin {
     }
 }
 
+/// Which chunks around the players they are subscribed to. The shape is stretched to the render
+/// distance of each player, measured in chunks from the chunk the player is in.
+#[derive(Resource)]
+pub struct ChunkSubscriptionSettings {
+    pub shape: SubscriptionShape,
+    /// Most chunks below the player that are sent, even if the render distance reaches further.
+    pub max_below: Option<u32>,
+    /// Most chunks above the player that are sent, even if the render distance reaches further.
+    pub max_above: Option<u32>,
+    /// Chunks that are more than this many chunks above or below the player are only subscribed
+    /// to once all chunks closer to the player's height are, so that the surface around the player
+    /// is sent before the caves far beneath it.
+    pub vertical_priority: Option<u32>,
+    /// Most chunks a player is subscribed to each time the search for visible chunks is done.
+    /// The search is done again every tick until there are none left, and the chunks the player
+    /// is looking towards are subscribed to first. Without a limit every chunk that is found is
+    /// subscribed to at once, and the terrain behind the player is loaded as eagerly as what is
+    /// in front of it.
+    pub max_subscriptions: Option<u32>,
+}
+
+impl Default for ChunkSubscriptionSettings {
+    fn default() -> Self {
+        Self {
+            shape: SubscriptionShape::Cube,
+            max_below: None,
+            max_above: None,
+            vertical_priority: None,
+            max_subscriptions: None,
+        }
+    }
+}
+
+impl ChunkSubscriptionSettings {
+    /// If the chunk at the offset from the player's chunk, measured in chunks, is one the player
+    /// should be subscribed to.
+    pub fn contains(&self, offset: IVec3, render_distance: &RenderDistance) -> bool {
+        if self
+            .max_below
+            .is_some_and(|max_below| offset.y < -(max_below as i32))
+            || self
+                .max_above
+                .is_some_and(|max_above| offset.y > max_above as i32)
+        {
+            return false;
+        }
+
+        let radius = render_distance.chunks as i32;
+        let vertical = render_distance.vertical as i32;
+        // (radius + 0.5)^2, the edge is less jagged than when using the radius squared.
+        let radius_squared = radius * radius + radius;
+        let horizontal = offset.x * offset.x + offset.z * offset.z;
+
+        return match self.shape {
+            SubscriptionShape::Cube => {
+                offset.x.abs() <= radius && offset.z.abs() <= radius && offset.y.abs() <= vertical
+            }
+            SubscriptionShape::Cylinder => {
+                horizontal <= radius_squared && offset.y.abs() <= vertical
+            }
+            SubscriptionShape::Sphere => {
+                let vertical_squared = (vertical as f32 + 0.5).powi(2);
+                horizontal as f32 / (radius as f32 + 0.5).powi(2)
+                    + (offset.y * offset.y) as f32 / vertical_squared
+                    <= 1.0
+            }
+        };
+    }
+
+    fn is_prioritized(&self, offset: IVec3) -> bool {
+        return self
+            .vertical_priority
+            .map_or(true, |distance| offset.y.abs() <= distance as i32);
+    }
+}
+
+// How much further away chunks behind the player are treated as compared to those in front of it
+// when choosing which to subscribe to first.
+const BEHIND_DISTANCE_FACTOR: f64 = 3.0;
+
+// Distance to a chunk, stretched the further the chunk is from the direction the camera is facing.
+fn view_weighted_distance(offset: IVec3, forward: Option<DVec3>) -> f64 {
+    let offset = offset.as_dvec3();
+    let distance = offset.length();
+    let Some(forward) = forward else {
+        return distance;
+    };
+
+    // 0 straight ahead, 1 straight behind
+    let away = (1.0 - forward.dot(offset.normalize_or_zero())) / 2.0;
+    return distance * (1.0 + away * (BEHIND_DISTANCE_FACTOR - 1.0));
+}
+
+/// The shape of the area of chunks around a player that it is subscribed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionShape {
+    Cube,
+    /// A column, it is cheaper than a cube as the corners are left out, but reaches the full
+    /// vertical render distance straight up and down.
+    Cylinder,
+    /// The least chunks for the same distance. It is flattened when the vertical render distance
+    /// is shorter than the horizontal.
+    Sphere,
+}
+
 /// The position of the chunk the player is currently in.
 #[derive(Component)]
 struct PlayerChunkOrigin(IVec3);
@@ -78,6 +184,10 @@ pub struct ChunkSubscriptionEvent {
     pub chunk_position: IVec3,
 }
 
+/// Sent when a chunk has finished loading and has been inserted into the world map.
+#[derive(Event)]
+pub struct ChunkLoadEvent(pub IVec3);
+
 // Event sent when the server should unload a chunk and its associated entities.
 #[derive(Event)]
 pub struct ChunkUnloadEvent(pub IVec3);
@@ -94,19 +204,58 @@ impl ChunkSubscriptions {
     pub fn get_subscribers(&self, chunk_position: &IVec3) -> Option<&HashSet<Entity>> {
         return self.chunk_to_subscribers.get(chunk_position);
     }
+
+    // Add something other than a player that can subscribe to chunks.
+    pub(super) fn add_subscriber(&mut self, entity: Entity) {
+        self.subscriber_to_chunks.insert(entity, HashSet::default());
+    }
+
+    // Remove a single subscription, returns true if the chunk has no subscribers left and should
+    // be unloaded.
+    pub(super) fn unsubscribe(&mut self, entity: Entity, chunk_position: IVec3) -> bool {
+        if let Some(chunks) = self.subscriber_to_chunks.get_mut(&entity) {
+            chunks.remove(&chunk_position);
+        }
+
+        let Some(subscribers) = self.chunk_to_subscribers.get_mut(&chunk_position) else {
+            return false;
+        };
+        subscribers.remove(&entity);
+
+        if subscribers.len() == 0 {
+            self.chunk_to_subscribers.remove(&chunk_position);
+            return true;
+        }
+
+        return false;
+    }
 }
 
 fn add_and_remove_subscribers(
     mut chunk_subscriptions: ResMut<ChunkSubscriptions>,
     mut network_events: EventReader<NetworkEvent>,
+    mut subscription_events: EventWriter<ChunkSubscriptionEvent>,
     mut unload_chunk_events: EventWriter<ChunkUnloadEvent>,
 ) {
     for event in network_events.read() {
         match event {
             NetworkEvent::Connected { entity } => {
-                chunk_subscriptions
-                    .subscriber_to_chunks
-                    .insert(*entity, HashSet::default());
+                if let Some(subscribed_chunks) =
+                    chunk_subscriptions.subscriber_to_chunks.get(entity)
+                {
+                    // A resumed session keeps its subscriptions, subscribing again sends the
+                    // chunks to the new client.
+                    for chunk_position in subscribed_chunks.iter() {
+                        subscription_events.send(ChunkSubscriptionEvent {
+                            player_entity: *entity,
+                            chunk_position: *chunk_position,
+                        });
+                    }
+                } else {
+                    chunk_subscriptions
+                        .subscriber_to_chunks
+                        .insert(*entity, HashSet::default());
+                }
             }
             NetworkEvent::Disconnected { entity } => {
                 let subscribed_chunks = chunk_subscriptions
@@ -133,7 +282,7 @@ fn add_and_remove_subscribers(
     }
 }
 
-fn handle_chunk_subscription_events(
+pub(super) fn handle_chunk_subscription_events(
     mut commands: Commands,
     net: Res<Server>,
     world_map: Res<WorldMap>,
@@ -182,6 +331,7 @@ fn handle_chunk_subscription_events(
 }
 
 fn unsubscribe_from_chunks(
+    settings: Res<ChunkSubscriptionSettings>,
     chunk_subscriptions: ResMut<ChunkSubscriptions>,
     mut unload_chunk_events: EventWriter<ChunkUnloadEvent>,
     player_origin_query: Query<
@@ -199,15 +349,8 @@ fn unsubscribe_from_chunks(
             .unwrap();
 
         let removed = subscribed_chunks.extract_if(|chunk_position| {
-            let distance = (*chunk_position - origin.0).abs() / Chunk::SIZE as i32;
-            if distance
-                .cmpgt(IVec3::splat(render_distance.chunks as i32))
-                .any()
-            {
-                return true;
-            } else {
-                return false;
-            }
+            let offset = (*chunk_position - origin.0) / Chunk::SIZE as i32;
+            return !settings.contains(offset, render_distance);
         });
 
         for chunk_position in removed {
@@ -250,20 +393,35 @@ struct ChunkLoadingTask(Task<(IVec3, Chunk)>);
 // Search for chunks by fanning out from the player's chunk position to find chunks that are
 // visible to it.
 fn subscribe_to_visible_chunks(
+    settings: Res<ChunkSubscriptionSettings>,
     world_map: Res<WorldMap>,
     chunk_subscriptions: Res<ChunkSubscriptions>,
     // NOTE: It's not restricted to running only when the origin is changed. Every time a new chunk
     // is loaded for a player the origin is mutably accessed to trigger the change detection.
-    changed_origin_query: Query<
-        (Entity, &PlayerChunkOrigin, &RenderDistance),
-        Changed<PlayerChunkOrigin>,
-    >,
+    origin_query: Query<(
+        Entity,
+        Ref<PlayerChunkOrigin>,
+        &RenderDistance,
+        Option<&Camera>,
+    )>,
     mut subscription_events: EventWriter<ChunkSubscriptionEvent>,
     mut queue: Local<Vec<(IVec3, ChunkFace, ChunkFace)>>,
     mut already_visited: Local<HashSet<IVec3>>,
+    mut prioritized: Local<Vec<IVec3>>,
+    mut deprioritized: Local<Vec<IVec3>>,
+    // Players that had more chunks left than they could be subscribed to in the last search.
+    mut unfinished: Local<HashSet<Entity>>,
 ) {
-    for (player_entity, chunk_origin, render_distance) in changed_origin_query.iter() {
+    let previously_unfinished = std::mem::take(&mut *unfinished);
+
+    for (player_entity, chunk_origin, render_distance, camera) in origin_query.iter() {
+        if !chunk_origin.is_changed() && !previously_unfinished.contains(&player_entity) {
+            continue;
+        }
+
         already_visited.clear();
+        prioritized.clear();
+        deprioritized.clear();
         already_visited.insert(chunk_origin.0);
 
         let subscribed_chunks = chunk_subscriptions
@@ -298,10 +456,12 @@ fn subscribe_to_visible_chunks(
         // main_face = The chunk face entered through at the start of the search.
         while let Some((chunk_position, from_face, main_face)) = queue.pop() {
             if !subscribed_chunks.contains(&chunk_position) {
-                subscription_events.send(ChunkSubscriptionEvent {
-                    player_entity,
-                    chunk_position,
-                });
+                let offset = (chunk_position - chunk_origin.0) / Chunk::SIZE as i32;
+                if settings.is_prioritized(offset) {
+                    prioritized.push(chunk_position);
+                } else {
+                    deprioritized.push(chunk_position);
+                }
             }
 
             let chunk = match world_map.get_chunk(&chunk_position) {
@@ -324,13 +484,8 @@ fn subscribe_to_visible_chunks(
 
             for to_face in surrounding {
                 let adjacent_position = to_face.shift_position(chunk_position);
-                let distance_to_adjacent =
-                    (adjacent_position - chunk_origin.0) / Chunk::SIZE as i32;
-                if distance_to_adjacent
-                    .abs()
-                    .cmpgt(IVec3::splat(render_distance.chunks as i32))
-                    .any()
-                {
+                let offset = (adjacent_position - chunk_origin.0) / Chunk::SIZE as i32;
+                if !settings.contains(offset, render_distance) {
                     continue;
                 }
 
@@ -348,6 +503,35 @@ fn subscribe_to_visible_chunks(
                 }
             }
         }
+
+        // The search is done again each time a chunk has loaded, the deprioritized chunks are
+        // found again until there are no closer chunks left to subscribe to.
+        let chunks = if !prioritized.is_empty() {
+            &mut *prioritized
+        } else {
+            &mut *deprioritized
+        };
+
+        let forward = camera.map(|camera| camera.forward());
+        chunks.sort_by(|a, b| {
+            let a = view_weighted_distance((*a - chunk_origin.0) / Chunk::SIZE as i32, forward);
+            let b = view_weighted_distance((*b - chunk_origin.0) / Chunk::SIZE as i32, forward);
+            a.total_cmp(&b)
+        });
+
+        let max_subscriptions = settings
+            .max_subscriptions
+            .map_or(usize::MAX, |max| max as usize);
+        if chunks.len() > max_subscriptions {
+            unfinished.insert(player_entity);
+        }
+
+        for chunk_position in chunks.drain(..).take(max_subscriptions) {
+            subscription_events.send(ChunkSubscriptionEvent {
+                player_entity,
+                chunk_position,
+            });
+        }
     }
 }
 
@@ -358,6 +542,7 @@ fn handle_chunk_loading_tasks(
     chunk_subscriptions: Res<ChunkSubscriptions>,
     mut origin_query: Query<&mut PlayerChunkOrigin>,
     mut chunks: Query<(Entity, &mut ChunkLoadingTask)>,
+    mut chunk_load_events: EventWriter<ChunkLoadEvent>,
 ) {
     for (entity, mut task) in chunks.iter_mut() {
         if let Some((new_chunk_position, chunk)) = future::block_on(future::poll_once(&mut task.0))
@@ -445,24 +630,9 @@ fn handle_chunk_loading_tasks(
                         }
                     }
 
-                    if let Some(model_id) = block_config.model {
-                        let mut transform = Transform::from_translation(
-                            block_position.as_dvec3() + DVec3::new(0.5, 0.0, 0.5),
-                        );
-                        if let Some(block_state) = chunk.get_block_state(&index) {
-                            if let Some(rotation) = block_state.rotation() {
-                                if let Some(mut rotation_transform) =
-                                    block_config.placement.rotation_transform
-                                {
-                                    rotation_transform
-                                        .rotate_around(DVec3::ZERO, rotation.as_quat());
-                                    transform.translation += rotation_transform.translation;
-                                    transform.rotation *= rotation_transform.rotation;
-                                    transform.scale *= rotation_transform.scale;
-                                }
-                            }
-                        }
-
+                    if let Some((model_id, transform)) =
+                        block_config.model_transform(block_position, chunk.get_block_state(&index))
+                    {
                         entity_commands.insert(ModelBundle {
                             model: Model::Asset(model_id),
                             animations: ModelAnimations::default(),
@@ -497,6 +667,8 @@ fn handle_chunk_loading_tasks(
                 );
             }
 
+            chunk_load_events.send(ChunkLoadEvent(new_chunk_position));
+
             commands.entity(entity).despawn();
         }
     }
@@ -0,0 +1,297 @@
+use bevy::{
+    tasks::IoTaskPool,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{database::Database, prelude::*, utils};
+
+use super::{
+    chunk::Chunk,
+    chunk_manager::{ChunkSubscriptions, ChunkUnloadEvent},
+    ChunkSubscriptionEvent, WorldSpawn,
+};
+
+// Key the forced chunks are saved under in the database's storage table
+const STORAGE_KEY: &str = "forced_chunks";
+// Ticket the chunks around the world spawn are forced with
+const SPAWN_TICKET: &str = "spawn";
+
+// Keeps chunks loaded no matter where the players are. The chunks are subscribed to by an entity
+// that stands in for a player, so they are loaded and unloaded the same way.
+pub struct ForcedChunksPlugin;
+impl Plugin for ForcedChunksPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ForcedChunksSettings>()
+            .init_resource::<ForcedChunks>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (
+                    force_spawn_chunks.run_if(resource_exists_and_changed::<WorldSpawn>),
+                    update_forced_chunks,
+                )
+                    .chain()
+                    .before(super::chunk_manager::handle_chunk_subscription_events),
+            );
+    }
+}
+
+#[derive(Resource)]
+pub struct ForcedChunksSettings {
+    /// Most chunks that can be forced at once, forcing more fails.
+    pub max_chunks: usize,
+    /// Chunks within this many chunks of the world spawn are kept loaded. The spawn chunks count
+    /// towards `max_chunks`. Set it to None to let them unload.
+    pub spawn_radius: Option<u32>,
+}
+
+impl Default for ForcedChunksSettings {
+    fn default() -> Self {
+        Self {
+            max_chunks: 1024,
+            spawn_radius: Some(2),
+        }
+    }
+}
+
+/// Chunks that are kept loaded and simulated regardless of where the players are, e.g. for farms
+/// and machines that should keep running while nobody is around. The forced chunks are saved in
+/// the database and forced again when the server starts.
+///
+/// Each chunk is forced with one or more tickets, a name for why it is forced. A chunk stays
+/// loaded until all of its tickets are released, so several things can force the same chunk
+/// without stepping on each other.
+///
+/// ```ignore
+/// if !forced_chunks.force(block_position, "quarry", &settings) {
+///     // Too many chunks are forced already
+/// }
+/// ...
+/// forced_chunks.release(block_position, "quarry");
+/// ```
+#[derive(Resource, Default)]
+pub struct ForcedChunks {
+    tickets: HashMap<IVec3, HashSet<String>>,
+    // Chunks whose tickets have changed since the last update. They are subscribed to or
+    // unsubscribed from depending on if they are still forced, and the tickets are saved.
+    changed: HashSet<IVec3>,
+}
+
+impl ForcedChunks {
+    /// Force the chunk that contains the position to stay loaded. Returns false if the chunk
+    /// wasn't already forced and `ForcedChunksSettings::max_chunks` has been reached.
+    pub fn force(
+        &mut self,
+        position: IVec3,
+        ticket: &str,
+        settings: &ForcedChunksSettings,
+    ) -> bool {
+        let chunk_position = utils::world_position_to_chunk_position(position);
+
+        if !self.tickets.contains_key(&chunk_position) && self.tickets.len() >= settings.max_chunks
+        {
+            return false;
+        }
+
+        self.insert(chunk_position, ticket.to_owned());
+        return true;
+    }
+
+    /// Force all chunks within `radius` chunks of the chunk that contains the position. Either
+    /// all of them are forced, or none if there isn't room for them.
+    pub fn force_area(
+        &mut self,
+        position: IVec3,
+        radius: u32,
+        ticket: &str,
+        settings: &ForcedChunksSettings,
+    ) -> bool {
+        let center = utils::world_position_to_chunk_position(position);
+        let radius = radius as i32;
+
+        let mut chunk_positions = Vec::new();
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    chunk_positions.push(center + IVec3::new(x, y, z) * Chunk::SIZE as i32);
+                }
+            }
+        }
+
+        let new = chunk_positions
+            .iter()
+            .filter(|chunk_position| !self.tickets.contains_key(*chunk_position))
+            .count();
+        if self.tickets.len() + new > settings.max_chunks {
+            return false;
+        }
+
+        for chunk_position in chunk_positions {
+            self.insert(chunk_position, ticket.to_owned());
+        }
+
+        return true;
+    }
+
+    /// Release the ticket from the chunk that contains the position. The chunk is unloaded if it
+    /// has no other tickets and no players are near it.
+    pub fn release(&mut self, position: IVec3, ticket: &str) {
+        let chunk_position = utils::world_position_to_chunk_position(position);
+
+        let Some(tickets) = self.tickets.get_mut(&chunk_position) else {
+            return;
+        };
+
+        if tickets.remove(ticket) {
+            if tickets.is_empty() {
+                self.tickets.remove(&chunk_position);
+            }
+            self.changed.insert(chunk_position);
+        }
+    }
+
+    /// Release the ticket from all the chunks it forces.
+    pub fn release_ticket(&mut self, ticket: &str) {
+        self.tickets.retain(|chunk_position, tickets| {
+            if tickets.remove(ticket) {
+                self.changed.insert(*chunk_position);
+            }
+            return !tickets.is_empty();
+        });
+    }
+
+    /// If the chunk that contains the position is forced to stay loaded.
+    pub fn is_forced(&self, position: IVec3) -> bool {
+        let chunk_position = utils::world_position_to_chunk_position(position);
+        return self.tickets.contains_key(&chunk_position);
+    }
+
+    /// The tickets the chunk that contains the position is forced with.
+    pub fn tickets(&self, position: IVec3) -> Option<&HashSet<String>> {
+        let chunk_position = utils::world_position_to_chunk_position(position);
+        return self.tickets.get(&chunk_position);
+    }
+
+    /// Positions of all the forced chunks
+    pub fn iter(&self) -> impl Iterator<Item = &IVec3> {
+        return self.tickets.keys();
+    }
+
+    /// How many chunks are forced
+    pub fn len(&self) -> usize {
+        return self.tickets.len();
+    }
+
+    fn insert(&mut self, chunk_position: IVec3, ticket: String) {
+        if self
+            .tickets
+            .entry(chunk_position)
+            .or_default()
+            .insert(ticket)
+        {
+            self.changed.insert(chunk_position);
+        }
+    }
+
+    fn save(&self, database: &Database) {
+        let saved: Vec<(IVec3, &String)> = self
+            .tickets
+            .iter()
+            .flat_map(|(chunk_position, tickets)| {
+                tickets.iter().map(|ticket| (*chunk_position, ticket))
+            })
+            .collect();
+        let data = serde_json::to_string(&saved).unwrap();
+
+        let database = database.clone();
+        IoTaskPool::get()
+            .spawn(async move { database.save_storage(STORAGE_KEY, &data) })
+            .detach();
+    }
+}
+
+// Stands in for a player in the chunk subscriptions, it is subscribed to all the forced chunks.
+#[derive(Component)]
+struct ForcedChunkLoader;
+
+fn setup(
+    mut commands: Commands,
+    database: Res<Database>,
+    mut chunk_subscriptions: ResMut<ChunkSubscriptions>,
+    mut forced_chunks: ResMut<ForcedChunks>,
+) {
+    let loader = commands.spawn(ForcedChunkLoader).id();
+    chunk_subscriptions.add_subscriber(loader);
+
+    let Some(saved) = database
+        .load_storage(STORAGE_KEY)
+        .and_then(|data| serde_json::from_str::<Vec<(IVec3, String)>>(&data).ok())
+    else {
+        return;
+    };
+
+    // The limit isn't checked for chunks that were forced before, they would be lost if it was
+    // lowered.
+    for (chunk_position, ticket) in saved {
+        forced_chunks.insert(chunk_position, ticket);
+    }
+
+    if forced_chunks.len() > 0 {
+        info!("Keeping {} forced chunks loaded", forced_chunks.len());
+    }
+}
+
+fn force_spawn_chunks(
+    settings: Res<ForcedChunksSettings>,
+    world_spawn: Res<WorldSpawn>,
+    mut forced_chunks: ResMut<ForcedChunks>,
+) {
+    forced_chunks.release_ticket(SPAWN_TICKET);
+
+    let Some(radius) = settings.spawn_radius else {
+        return;
+    };
+
+    if !forced_chunks.force_area(world_spawn.position, radius, SPAWN_TICKET, &settings) {
+        warn!(
+            "Could not keep the chunks around the world spawn loaded, more than {} chunks \
+            would be forced.",
+            settings.max_chunks
+        );
+    }
+}
+
+fn update_forced_chunks(
+    database: Res<Database>,
+    mut forced_chunks: ResMut<ForcedChunks>,
+    mut chunk_subscriptions: ResMut<ChunkSubscriptions>,
+    loader_query: Query<Entity, With<ForcedChunkLoader>>,
+    mut subscription_events: EventWriter<ChunkSubscriptionEvent>,
+    mut unload_chunk_events: EventWriter<ChunkUnloadEvent>,
+) {
+    if forced_chunks.changed.is_empty() {
+        return;
+    }
+
+    let loader = loader_query.single();
+
+    let changed = std::mem::take(&mut forced_chunks.changed);
+    for chunk_position in changed {
+        let is_subscribed = chunk_subscriptions
+            .get_subscribers(&chunk_position)
+            .is_some_and(|subscribers| subscribers.contains(&loader));
+
+        if forced_chunks.tickets.contains_key(&chunk_position) {
+            if !is_subscribed {
+                subscription_events.send(ChunkSubscriptionEvent {
+                    player_entity: loader,
+                    chunk_position,
+                });
+            }
+        } else if is_subscribed && chunk_subscriptions.unsubscribe(loader, chunk_position) {
+            unload_chunk_events.send(ChunkUnloadEvent(chunk_position));
+        }
+    }
+
+    forced_chunks.save(&database);
+}
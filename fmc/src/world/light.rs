@@ -0,0 +1,133 @@
+use crate::{blocks::Blocks, prelude::*};
+
+use super::WorldMap;
+
+/// The brightest light level
+pub const MAX_LIGHT: u8 = 15;
+
+/// How much light reaches a block, used for spawn rules like mobs that only spawn in the dark.
+/// The server doesn't keep track of light, it is computed from the blocks around when asked for,
+/// the clients do the lighting they draw themselves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LightLevel {
+    /// Light from blocks that emit light, like torches
+    pub block: u8,
+    /// Light from the sky at noon, it's dimmer the further it has to go through blocks that let
+    /// light through, like leaves and water.
+    pub sky: u8,
+}
+
+impl LightLevel {
+    /// The light when the sky is as bright as `daylight`, from 0.0 at night to 1.0 at noon. Block
+    /// light doesn't change with the time of day.
+    pub fn with_daylight(&self, daylight: f64) -> u8 {
+        let sky = (self.sky as f64 * daylight.clamp(0.0, 1.0)).round() as u8;
+        return self.block.max(sky);
+    }
+}
+
+/// The light that reaches the block at the position. Blocks in chunks that aren't loaded are
+/// treated as if they don't let light through, except for the sky above, which is open.
+pub fn light_level(world_map: &WorldMap, position: IVec3) -> LightLevel {
+    return LightLevel {
+        block: block_light(world_map, position),
+        sky: sky_light(world_map, position),
+    };
+}
+
+// Light comes straight down from the sky, dimmed by every block it passes through.
+fn sky_light(world_map: &WorldMap, mut position: IVec3) -> u8 {
+    let blocks = Blocks::get();
+
+    let mut light = MAX_LIGHT;
+    while let Some(block_id) = world_map.get_block(position) {
+        light = light.saturating_sub(blocks.get_config(&block_id).light_attenuation);
+        if light == 0 {
+            break;
+        }
+        position.y += 1;
+    }
+
+    return light;
+}
+
+// Searches outwards from the position for blocks that emit light, in the same way the clients
+// spread it, each block it passes through lowers it by at least one level. The search goes
+// through the blocks in order of how much light is lost getting there, so it stops at the first
+// level that can't be beaten.
+fn block_light(world_map: &WorldMap, position: IVec3) -> u8 {
+    let blocks = Blocks::get();
+
+    let cost = |block_id| {
+        let attenuation = blocks.get_config(&block_id).light_attenuation;
+        if attenuation >= MAX_LIGHT {
+            None
+        } else {
+            Some(attenuation.max(1))
+        }
+    };
+
+    let Some(block_id) = world_map.get_block(position) else {
+        return 0;
+    };
+
+    let emitted = blocks.get_config(&block_id).light;
+    let Some(start_cost) = cost(block_id) else {
+        return emitted;
+    };
+
+    let mut brightest = emitted;
+    // Blocks by how much light is lost between them and the position
+    let mut queue: Vec<Vec<IVec3>> = vec![Vec::new(); MAX_LIGHT as usize];
+    let mut lost = std::collections::HashMap::new();
+    queue[start_cost as usize].push(position);
+    lost.insert(position, start_cost);
+
+    for level in 1..MAX_LIGHT {
+        // Nothing further away can be brighter
+        if MAX_LIGHT - level <= brightest {
+            break;
+        }
+
+        while let Some(current) = queue[level as usize].pop() {
+            if lost.get(&current) != Some(&level) {
+                continue;
+            }
+
+            for offset in [
+                IVec3::X,
+                IVec3::NEG_X,
+                IVec3::Y,
+                IVec3::NEG_Y,
+                IVec3::Z,
+                IVec3::NEG_Z,
+            ] {
+                let neighbour = current + offset;
+                let Some(block_id) = world_map.get_block(neighbour) else {
+                    continue;
+                };
+
+                let config = blocks.get_config(&block_id);
+                brightest = brightest.max(config.light.saturating_sub(level));
+
+                let Some(neighbour_cost) = cost(block_id) else {
+                    continue;
+                };
+
+                let neighbour_lost = level + neighbour_cost;
+                if neighbour_lost >= MAX_LIGHT
+                    || lost
+                        .get(&neighbour)
+                        .is_some_and(|previous| *previous <= neighbour_lost)
+                {
+                    continue;
+                }
+
+                lost.insert(neighbour, neighbour_lost);
+                queue[neighbour_lost as usize].push(neighbour);
+            }
+        }
+    }
+
+    return brightest;
+}
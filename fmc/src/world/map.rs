@@ -26,6 +26,11 @@ impl WorldMap {
         return self.chunks.contains_key(chunk_position);
     }
 
+    /// Positions of all the loaded chunks
+    pub fn chunk_positions(&self) -> impl Iterator<Item = &IVec3> {
+        return self.chunks.keys();
+    }
+
     pub fn get_chunk(&self, position: &IVec3) -> Option<&Chunk> {
         return self.chunks.get(&position);
     }
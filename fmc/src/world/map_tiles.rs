@@ -0,0 +1,470 @@
+use std::collections::{HashMap, HashSet};
+
+use base64::Engine;
+use bevy::tasks::{futures_lite::future, IoTaskPool, Task};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blocks::Blocks,
+    database::Database,
+    networking::{properties, ResumedSession, Server},
+    players::Player,
+    prelude::*,
+    utils,
+    world::{chunk::Chunk, BlockUpdate, ChunkLoadEvent, DatabaseSyncTimer, WorldMap},
+};
+
+// How far from a player, in chunk columns, the map tiles are sent to it
+const STREAM_RADIUS: i32 = 16;
+// Most tiles looked up for each player every tick, so that joining doesn't load them all at once
+const TILES_PER_TICK: usize = 16;
+
+// Keeps a top-down overview of the world. Each chunk column that has been explored has a tile
+// describing the color and height of the topmost visible block in each of its block columns. The
+// tiles around each player are streamed to it, and sent again when they change.
+pub struct MapTilePlugin;
+impl Plugin for MapTilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MapTiles>()
+            .init_resource::<LoadingMapTiles>()
+            .add_event::<MapTileUpdate>()
+            .add_systems(
+                PostUpdate,
+                (
+                    update_tiles_from_loaded_chunks,
+                    update_tiles_from_block_updates.after(super::handle_block_updates),
+                    (insert_sent_map_tiles, send_map_tiles, send_loaded_map_tiles).chain(),
+                    save_tiles_to_database.after(super::save_block_updates_to_database),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// A top-down summary of a chunk column.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MapTile {
+    /// The color of the topmost block in each block column, indexed by x * Chunk::SIZE + z. A
+    /// column that has not been explored yet has a color with an alpha of zero.
+    pub colors: Vec<[u8; 4]>,
+    /// The height of the topmost block in each block column, indexed like 'colors'.
+    pub heights: Vec<i32>,
+}
+
+impl Default for MapTile {
+    fn default() -> Self {
+        Self {
+            colors: vec![[0; 4]; Chunk::SIZE.pow(2)],
+            heights: vec![i32::MIN; Chunk::SIZE.pow(2)],
+        }
+    }
+}
+
+impl MapTile {
+    fn index(x: usize, z: usize) -> usize {
+        return x * Chunk::SIZE + z;
+    }
+
+    /// The color and height of the topmost block at the local x,z coordinate of the tile. None
+    /// if the column is unexplored.
+    pub fn get(&self, x: usize, z: usize) -> Option<([u8; 4], i32)> {
+        let index = Self::index(x, z);
+        if self.colors[index][3] == 0 {
+            return None;
+        } else {
+            return Some((self.colors[index], self.heights[index]));
+        }
+    }
+
+    fn set(&mut self, x: usize, z: usize, surface: Option<([u8; 4], i32)>) -> bool {
+        let index = Self::index(x, z);
+        let (color, height) = surface.unwrap_or(([0; 4], i32::MIN));
+
+        if self.colors[index] == color && self.heights[index] == height {
+            return false;
+        }
+
+        self.colors[index] = color;
+        self.heights[index] = height;
+        return true;
+    }
+
+    // The tile as it is sent to the clients, the colors are replaced by indices into a palette
+    // of the colors that are used.
+    fn to_property(&self, position: IVec2) -> properties::MapTile {
+        let mut palette: Vec<[u8; 4]> = Vec::new();
+        let mut columns = Vec::with_capacity(self.colors.len() * 5);
+
+        for (color, height) in self.colors.iter().zip(self.heights.iter()) {
+            let index = match palette.iter().position(|c| c == color) {
+                Some(index) => index,
+                None => {
+                    palette.push(*color);
+                    palette.len() - 1
+                }
+            };
+            // There are only Chunk::SIZE^2 columns, so the palette can't outgrow a byte.
+            columns.push(index as u8);
+            columns.extend_from_slice(&height.to_le_bytes());
+        }
+
+        return properties::MapTile {
+            x: position.x,
+            z: position.y,
+            palette,
+            columns: base64::engine::general_purpose::STANDARD.encode(columns),
+        };
+    }
+}
+
+// TODO: Tiles are never removed from memory. They are small (2kb) but it adds up on large
+// servers. They could be dropped when all the chunks in their column are unloaded.
+//
+/// All map tiles that have been loaded, by the x,z position of the chunk column they cover.
+#[derive(Resource, Default)]
+pub struct MapTiles {
+    tiles: HashMap<IVec2, MapTile>,
+    // Tiles that have changed since they were last saved to the database.
+    changed: HashSet<IVec2>,
+}
+
+impl MapTiles {
+    pub fn get(&self, position: &IVec2) -> Option<&MapTile> {
+        return self.tiles.get(position);
+    }
+
+    fn get_or_load(&mut self, position: IVec2, database: &Database) -> &mut MapTile {
+        return self
+            .tiles
+            .entry(position)
+            .or_insert_with(|| database.load_map_tile(&position).unwrap_or_default());
+    }
+}
+
+// Tiles that are looked up for the players are read from the database in the background.
+#[derive(Resource, Default)]
+struct LoadingMapTiles {
+    // Positions that are being loaded, so that they are not read again for every player that
+    // wants them.
+    positions: HashSet<IVec2>,
+    tasks: Vec<Task<Vec<(IVec2, Option<MapTile>)>>>,
+}
+
+/// Sent when a map tile has changed. The tile can be read from the `MapTiles` resource. It is
+/// sent to the players close to it automatically.
+#[derive(Event)]
+pub struct MapTileUpdate {
+    /// x,z position of the chunk column the tile covers.
+    pub position: IVec2,
+}
+
+/// Converts a chunk or block position to the position of the map tile it belongs to.
+pub fn map_tile_position(position: IVec3) -> IVec2 {
+    let chunk_position = utils::world_position_to_chunk_position(position);
+    return IVec2::new(chunk_position.x, chunk_position.z);
+}
+
+// Find the topmost block with a map color in the block column, searching downwards from the top
+// of the chunk.
+pub(super) fn chunk_column_surface(
+    chunk: &Chunk,
+    chunk_y: i32,
+    x: usize,
+    z: usize,
+) -> Option<([u8; 4], i32)> {
+    let blocks = Blocks::get();
+
+    if chunk.is_uniform() {
+        return blocks
+            .get_config(&chunk[0])
+            .map_color()
+            .map(|color| (color, chunk_y + Chunk::SIZE as i32 - 1));
+    }
+
+    for y in (0..Chunk::SIZE).rev() {
+        if let Some(color) = blocks.get_config(&chunk[[x, y, z]]).map_color() {
+            return Some((color, chunk_y + y as i32));
+        }
+    }
+
+    return None;
+}
+
+fn update_tiles_from_loaded_chunks(
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    mut map_tiles: ResMut<MapTiles>,
+    mut chunk_load_events: EventReader<ChunkLoadEvent>,
+    mut tile_update_events: EventWriter<MapTileUpdate>,
+) {
+    for ChunkLoadEvent(chunk_position) in chunk_load_events.read() {
+        let Some(chunk) = world_map.get_chunk(chunk_position) else {
+            continue;
+        };
+
+        let tile_position = map_tile_position(*chunk_position);
+        let tile = map_tiles.get_or_load(tile_position, &database);
+
+        let mut changed = false;
+        for x in 0..Chunk::SIZE {
+            for z in 0..Chunk::SIZE {
+                let Some((color, height)) = chunk_column_surface(chunk, chunk_position.y, x, z)
+                else {
+                    continue;
+                };
+
+                // Chunks below the current surface are hidden by it. The surface is only moved
+                // down by block updates.
+                if tile
+                    .get(x, z)
+                    .is_some_and(|(_, old_height)| old_height > height)
+                {
+                    continue;
+                }
+
+                changed |= tile.set(x, z, Some((color, height)));
+            }
+        }
+
+        if changed {
+            map_tiles.changed.insert(tile_position);
+            tile_update_events.send(MapTileUpdate {
+                position: tile_position,
+            });
+        }
+    }
+}
+
+fn update_tiles_from_block_updates(
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    mut map_tiles: ResMut<MapTiles>,
+    mut block_updates: EventReader<BlockUpdate>,
+    mut tile_update_events: EventWriter<MapTileUpdate>,
+) {
+    let blocks = Blocks::get();
+
+    let mut changed_tiles = HashSet::new();
+
+    for block_update in block_updates.read() {
+        let (position, block_id) = match block_update {
+            BlockUpdate::Change {
+                position, block_id, ..
+            } => (position, block_id),
+        };
+
+        let tile_position = map_tile_position(*position);
+        let tile = map_tiles.get_or_load(tile_position, &database);
+
+        let x = (position.x & (Chunk::SIZE - 1) as i32) as usize;
+        let z = (position.z & (Chunk::SIZE - 1) as i32) as usize;
+        let current_height = tile.get(x, z).map(|(_, height)| height);
+
+        let changed = if let Some(color) = blocks.get_config(block_id).map_color() {
+            if current_height.is_some_and(|height| height > position.y) {
+                continue;
+            }
+            tile.set(x, z, Some((color, position.y)))
+        } else if current_height == Some(position.y) {
+            // The top block was removed, search downwards through the loaded chunks for the
+            // new surface.
+            let mut surface = None;
+            let mut block_position = *position - IVec3::Y;
+            while let Some(block_id) = world_map.get_block(block_position) {
+                if let Some(color) = blocks.get_config(&block_id).map_color() {
+                    surface = Some((color, block_position.y));
+                    break;
+                }
+                block_position.y -= 1;
+            }
+            tile.set(x, z, surface)
+        } else {
+            false
+        };
+
+        if changed {
+            changed_tiles.insert(tile_position);
+        }
+    }
+
+    for tile_position in changed_tiles {
+        map_tiles.changed.insert(tile_position);
+        tile_update_events.send(MapTileUpdate {
+            position: tile_position,
+        });
+    }
+}
+
+// The map tiles around the player that have been sent to it, or that were looked up and haven't
+// been explored. Those are sent when they are. A resumed session has a new client, so it starts
+// over.
+#[derive(Component, Default)]
+struct SentMapTiles(HashSet<IVec2>);
+
+fn insert_sent_map_tiles(
+    mut commands: Commands,
+    player_query: Query<Entity, Or<(Added<Player>, Added<ResumedSession>)>>,
+) {
+    for player_entity in player_query.iter() {
+        commands
+            .entity(player_entity)
+            .insert(SentMapTiles::default());
+    }
+}
+
+// TODO: There is no message for map tiles, they are sent as the "map_tile" property to clients
+// that understand properties.
+fn send_tile(net: &Server, player_entity: Entity, position: IVec2, tile: &MapTile) {
+    net.send_property(
+        player_entity,
+        properties::MAP_TILE,
+        &tile.to_property(position),
+    );
+}
+
+fn send_map_tiles(
+    net: Res<Server>,
+    database: Res<Database>,
+    map_tiles: Res<MapTiles>,
+    mut loading: ResMut<LoadingMapTiles>,
+    mut player_query: Query<(Entity, &GlobalTransform, &mut SentMapTiles), With<Player>>,
+    mut tile_update_events: EventReader<MapTileUpdate>,
+) {
+    let updated: HashSet<IVec2> = tile_update_events
+        .read()
+        .map(|update| update.position)
+        .collect();
+
+    let mut to_load = Vec::new();
+
+    for (player_entity, transform, mut sent) in player_query.iter_mut() {
+        if !net.supports_properties(player_entity) {
+            continue;
+        }
+
+        let center = map_tile_position(transform.translation().floor().as_ivec3());
+
+        // Tiles that were left behind are forgotten, they are sent again if the player comes
+        // back.
+        sent.0.retain(|position| {
+            (*position - center).abs().max_element() <= STREAM_RADIUS * Chunk::SIZE as i32
+        });
+
+        for position in updated.iter() {
+            if sent.0.contains(position) {
+                if let Some(tile) = map_tiles.get(position) {
+                    send_tile(&net, player_entity, *position, tile);
+                }
+            }
+        }
+
+        if sent.0.len() == (STREAM_RADIUS as usize * 2 + 1).pow(2) {
+            continue;
+        }
+
+        // Closest first
+        let mut looked_up = 0;
+        'outer: for distance in 0..=STREAM_RADIUS {
+            for x in -distance..=distance {
+                for z in -distance..=distance {
+                    if x.abs().max(z.abs()) != distance {
+                        continue;
+                    }
+
+                    let position = center + IVec2::new(x, z) * Chunk::SIZE as i32;
+                    if !sent.0.insert(position) {
+                        continue;
+                    }
+
+                    // Tiles that aren't in memory are sent when they have been loaded.
+                    if let Some(tile) = map_tiles.get(&position) {
+                        send_tile(&net, player_entity, position, tile);
+                    } else if loading.positions.insert(position) {
+                        to_load.push(position);
+                    }
+
+                    looked_up += 1;
+                    if looked_up == TILES_PER_TICK {
+                        break 'outer;
+                    }
+                }
+            }
+        }
+    }
+
+    if !to_load.is_empty() {
+        let database = database.clone();
+        let task = IoTaskPool::get().spawn(async move {
+            to_load
+                .into_iter()
+                .map(|position| (position, database.load_map_tile(&position)))
+                .collect()
+        });
+        loading.tasks.push(task);
+    }
+}
+
+fn send_loaded_map_tiles(
+    net: Res<Server>,
+    mut map_tiles: ResMut<MapTiles>,
+    mut loading: ResMut<LoadingMapTiles>,
+    player_query: Query<(Entity, &SentMapTiles), With<Player>>,
+) {
+    let loading = loading.into_inner();
+    let mut loaded = Vec::new();
+
+    loading.tasks.retain_mut(|task| {
+        if let Some(tiles) = future::block_on(future::poll_once(task)) {
+            loaded.extend(tiles);
+            return false;
+        } else {
+            return true;
+        }
+    });
+
+    for (position, tile) in loaded {
+        loading.positions.remove(&position);
+
+        // Tiles that haven't been explored are sent when they are.
+        let Some(tile) = tile else {
+            continue;
+        };
+
+        // A chunk in the tile might have been loaded while it was read, the tile in memory is
+        // newer.
+        let tile = map_tiles.tiles.entry(position).or_insert(tile);
+
+        for (player_entity, sent) in player_query.iter() {
+            if sent.0.contains(&position) && net.supports_properties(player_entity) {
+                send_tile(&net, player_entity, position, tile);
+            }
+        }
+    }
+}
+
+fn save_tiles_to_database(
+    database: Res<Database>,
+    sync_timer: Res<DatabaseSyncTimer>,
+    mut map_tiles: ResMut<MapTiles>,
+) {
+    if !sync_timer.just_finished() || map_tiles.changed.is_empty() {
+        return;
+    }
+
+    let map_tiles = map_tiles.into_inner();
+    let tiles = map_tiles
+        .changed
+        .drain()
+        .filter_map(|position| {
+            map_tiles
+                .tiles
+                .get(&position)
+                .map(|tile| (position, tile.clone()))
+        })
+        .collect();
+
+    let database = database.clone();
+    IoTaskPool::get()
+        .spawn(async move { database.save_map_tiles(tiles) })
+        .detach();
+}
@@ -1,30 +1,59 @@
-use std::{collections::HashMap, ops::Index};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Index,
+};
 
 use bevy::{
     app::AppExit,
-    math::DVec3,
     tasks::{futures_lite::future, IoTaskPool},
 };
 use fmc_protocol::messages;
 
 use crate::{
     bevy_extensions::f64_transform::TransformSystem,
-    blocks::{BlockFace, BlockId, BlockPosition, BlockState, Blocks},
+    blocks::{BlockData, BlockFace, BlockId, BlockPosition, BlockState, Blocks},
     database::Database,
     models::{Model, ModelAnimations, ModelBundle, ModelVisibility},
-    networking::{NetworkMessage, Server},
+    networking::{properties, ClientProperty, NetworkMessage, Server},
     prelude::*,
     utils,
 };
 
+mod block_animations;
+mod block_audit;
+mod block_history;
 pub mod chunk;
 mod chunk_manager;
+mod forced_chunks;
+mod light;
 mod map;
+mod map_tiles;
+mod preview;
+mod simulation_budget;
+mod spawn;
 mod terrain_generation;
-
-pub use chunk_manager::{ChunkSubscriptionEvent, ChunkSubscriptions};
+mod trim;
+
+pub use block_animations::{BlockAnimation, BlockPose};
+pub use block_audit::BlockAudit;
+pub use block_history::{BlockHistory, BlockHistorySettings, BlockPermissions, PlayerBlockUpdate};
+pub use chunk_manager::{
+    ChunkLoadEvent, ChunkSubscriptionEvent, ChunkSubscriptionSettings, ChunkSubscriptions,
+    SubscriptionShape,
+};
+pub use forced_chunks::{ForcedChunks, ForcedChunksSettings};
+pub use light::{light_level, LightLevel, MAX_LIGHT};
 pub use map::WorldMap;
-pub use terrain_generation::{blueprints, Surface, TerrainFeature, TerrainGenerator};
+pub use map_tiles::{map_tile_position, MapTile, MapTileUpdate, MapTiles};
+pub use preview::{preview_world, PreviewOptions, PreviewReport};
+pub use simulation_budget::{SimulationBudget, SimulationBudgetSettings};
+pub use spawn::{SetSpawn, WorldSpawn};
+pub use terrain_generation::{
+    blueprints, column_index, CaveCarver, ColumnCache, HeightDistribution, OreDistribution,
+    OreVein, Surface, TerrainFeature, TerrainGenerator, TerrainPipeline, TerrainStage,
+    TerrainStageKind, WormTunnels,
+};
+pub use trim::{trim_world, TrimOptions, TrimReport};
 
 pub struct WorldPlugin;
 
@@ -34,11 +63,30 @@ impl Plugin for WorldPlugin {
             5.0,
             TimerMode::Repeating,
         )))
-        .insert_resource(RenderDistance { chunks: 16 })
+        .insert_resource(RenderDistance {
+            chunks: 16,
+            vertical: 16,
+        })
         .add_plugins(chunk_manager::ChunkManagerPlugin)
+        .add_plugins(map_tiles::MapTilePlugin)
+        .add_plugins(spawn::SpawnPlugin)
+        .add_plugins(block_history::BlockHistoryPlugin)
+        .add_plugins(block_audit::BlockAuditPlugin)
+        .add_plugins(block_animations::BlockAnimationPlugin)
+        .add_plugins(simulation_budget::SimulationBudgetPlugin)
+        .add_plugins(forced_chunks::ForcedChunksPlugin)
+        .add_plugins(trim::TrimPlugin)
+        .add_plugins(preview::PreviewPlugin)
         .add_event::<BlockUpdate>()
         .add_event::<ChangedBlockEvent>()
-        .add_systems(Update, change_player_render_distance)
+        .add_systems(
+            Update,
+            (
+                change_player_vertical_render_distance,
+                change_player_render_distance,
+            )
+                .chain(),
+        )
         .add_systems(
             PostUpdate,
             (
@@ -58,31 +106,78 @@ impl Plugin for WorldPlugin {
 /// render distance for a player (always <= the server's).
 #[derive(Resource, Component)]
 pub struct RenderDistance {
+    /// How many chunks to the sides
     pub chunks: u32,
+    /// How many chunks up and down. Most of the chunks far above and below the player are air or
+    /// stone, it is usually less than to the sides.
+    pub vertical: u32,
 }
 
+// TODO: The RenderDistance message only has the horizontal distance, the vertical distance is
+// sent as the "vertical_render_distance" property. Clients that don't send it get the same
+// distance vertically as horizontally.
+//
+// The vertical render distance the player asked for
+#[derive(Component)]
+struct RequestedVerticalRenderDistance(u32);
+
 // The player may send a render distance than is less than the max to restrict the amount of chunks
 // rendered.
 fn change_player_render_distance(
-    net: Res<Server>,
     max_render_distance: Res<RenderDistance>,
-    mut player_render_distance_query: Query<&mut RenderDistance>,
+    mut player_render_distance_query: Query<(
+        &mut RenderDistance,
+        Option<&RequestedVerticalRenderDistance>,
+    )>,
     mut render_distance_events: EventReader<NetworkMessage<messages::RenderDistance>>,
 ) {
     for render_distance_update in render_distance_events.read() {
-        let mut render_distance = player_render_distance_query
-            .get_mut(render_distance_update.player_entity)
-            .unwrap();
-
-        if render_distance.chunks > max_render_distance.chunks {
-            error!(
-                "Player tried to set their render distance to {}, but the max allowed is {}, disconnecting.",
+        let Ok((mut render_distance, requested_vertical)) =
+            player_render_distance_query.get_mut(render_distance_update.player_entity)
+        else {
+            continue;
+        };
+
+        if render_distance_update.chunks > max_render_distance.chunks {
+            warn!(
+                "Player tried to set their render distance to {}, but the max allowed is {}.",
                 render_distance_update.chunks, max_render_distance.chunks
             );
-            net.disconnect(render_distance_update.player_entity);
         }
 
-        render_distance.chunks = render_distance_update.chunks;
+        render_distance.chunks = render_distance_update
+            .chunks
+            .min(max_render_distance.chunks);
+        render_distance.vertical = requested_vertical
+            .map_or(render_distance_update.chunks, |vertical| vertical.0)
+            .min(max_render_distance.vertical);
+    }
+}
+
+fn change_player_vertical_render_distance(
+    mut commands: Commands,
+    max_render_distance: Res<RenderDistance>,
+    mut player_render_distance_query: Query<&mut RenderDistance>,
+    mut property_events: EventReader<NetworkMessage<ClientProperty>>,
+) {
+    for property in property_events.read() {
+        if property.name != properties::VERTICAL_RENDER_DISTANCE {
+            continue;
+        }
+
+        let Some(vertical) = property.parse::<u32>() else {
+            continue;
+        };
+
+        let Ok(mut render_distance) = player_render_distance_query.get_mut(property.player_entity)
+        else {
+            continue;
+        };
+
+        render_distance.vertical = vertical.min(max_render_distance.vertical);
+        commands
+            .entity(property.player_entity)
+            .insert(RequestedVerticalRenderDistance(vertical));
     }
 }
 
@@ -157,7 +252,7 @@ pub enum BlockUpdate {
 }
 
 // Applies block updates to the world and sends them to the players.
-fn handle_block_updates(
+pub(crate) fn handle_block_updates(
     mut commands: Commands,
     net: Res<Server>,
     chunk_subsriptions: Res<chunk_manager::ChunkSubscriptions>,
@@ -196,24 +291,9 @@ fn handle_block_updates(
                         (spawn_fn)(&mut entity_commands, None);
                     }
 
-                    if let Some(model_id) = block_config.model {
-                        let mut transform = Transform::from_translation(
-                            position.as_dvec3() + DVec3::new(0.5, 0.0, 0.5),
-                        );
-                        if let Some(block_state) = block_state {
-                            if let Some(rotation) = block_state.rotation() {
-                                if let Some(mut rotation_transform) =
-                                    block_config.placement.rotation_transform
-                                {
-                                    rotation_transform
-                                        .rotate_around(DVec3::ZERO, rotation.as_quat());
-                                    transform.translation += rotation_transform.translation;
-                                    transform.rotation *= rotation_transform.rotation;
-                                    transform.scale *= rotation_transform.scale;
-                                }
-                            }
-                        }
-
+                    if let Some((model_id, transform)) =
+                        block_config.model_transform(*position, *block_state)
+                    {
                         entity_commands.insert(ModelBundle {
                             model: Model::Asset(model_id),
                             animations: ModelAnimations::default(),
@@ -260,9 +340,14 @@ fn handle_block_updates(
 #[derive(Resource, DerefMut, Deref)]
 struct DatabaseSyncTimer(Timer);
 
+// How many database syncs there are between each time the saved block updates are compacted into
+// chunk records.
+const COMPACTION_INTERVAL: u32 = 60;
+
 async fn save_blocks(
     database: Database,
-    block_updates: Vec<(IVec3, (BlockId, Option<BlockState>))>,
+    block_updates: Vec<(IVec3, (BlockId, Option<BlockState>, Option<BlockData>))>,
+    player_changed_chunks: Vec<IVec3>,
 ) {
     let mut conn = database.get_connection();
     let transaction = conn.transaction().unwrap();
@@ -270,38 +355,87 @@ async fn save_blocks(
         .prepare(
             r#"
         insert or replace into
-            blocks (x,y,z,block_id,block_state)
+            blocks (x,y,z,block_id,block_state,block_data)
         values
-            (?,?,?,?,?)
+            (?,?,?,?,?,?)
         "#,
         )
         .unwrap();
 
-    for (position, (block_id, block_state)) in block_updates {
+    for (position, (block_id, block_state, block_data)) in block_updates {
         statement
             .execute(rusqlite::params![
                 position.x,
                 position.y,
                 position.z,
                 block_id,
-                block_state.map(|state| state.0)
+                block_state.map(|state| state.0),
+                block_data.map(|data| data.0)
             ])
             .unwrap();
     }
     statement.finalize().unwrap();
+
+    let mut statement = transaction
+        .prepare("insert or ignore into player_changed_chunks values (?,?,?)")
+        .unwrap();
+    for position in player_changed_chunks {
+        statement
+            .execute([position.x, position.y, position.z])
+            .unwrap();
+    }
+    statement.finalize().unwrap();
+
     transaction
         .commit()
         .expect("Failed to write blocks to database.");
 }
 
+// Changes to the BlockData of block entities are saved along with the block. The data a block
+// entity is spawned with is already saved, it is only written again when it changes. The chunks
+// players change blocks in are remembered, so that the world can be trimmed without losing what
+// they've built.
 fn save_block_updates_to_database(
     database: Res<Database>,
     time: Res<Time>,
+    world_map: Res<WorldMap>,
+    permissions: BlockPermissions,
+    block_data_query: Query<(&BlockPosition, Ref<BlockData>), Changed<BlockData>>,
     mut block_events: EventReader<BlockUpdate>,
+    mut player_block_updates: EventReader<PlayerBlockUpdate>,
     mut sync_timer: ResMut<DatabaseSyncTimer>,
     exit_events: EventReader<AppExit>,
-    mut block_updates: Local<HashMap<IVec3, (BlockId, Option<BlockState>)>>,
+    mut block_updates: Local<HashMap<IVec3, (BlockId, Option<BlockState>, Option<BlockData>)>>,
+    mut player_changed_chunks: Local<HashSet<IVec3>>,
+    mut syncs: Local<u32>,
 ) {
+    for update in player_block_updates.read() {
+        if permissions.can_modify(update.player_entity, update.position) {
+            player_changed_chunks.insert(utils::world_position_to_chunk_position(update.position));
+        }
+    }
+
+    for (block_position, block_data) in block_data_query.iter() {
+        if block_data.is_added() {
+            continue;
+        }
+
+        let Some(block_id) = world_map.get_block(block_position.0) else {
+            continue;
+        };
+
+        block_updates.insert(
+            block_position.0,
+            (
+                block_id,
+                world_map.get_block_state(block_position.0),
+                Some((*block_data).clone()),
+            ),
+        );
+    }
+
+    // Block changes come after so that the data of a block entity that was replaced this tick
+    // is not saved to the new block.
     for event in block_events.read() {
         match event {
             BlockUpdate::Change {
@@ -309,7 +443,7 @@ fn save_block_updates_to_database(
                 block_id,
                 block_state,
             } => {
-                block_updates.insert(*position, (*block_id, *block_state));
+                block_updates.insert(*position, (*block_id, *block_state, None));
             }
         }
     }
@@ -318,14 +452,32 @@ fn save_block_updates_to_database(
     if sync_timer.just_finished() {
         let task_pool = IoTaskPool::get();
         let block_updates = block_updates.drain().collect();
+        let player_changed_chunks = player_changed_chunks.drain().collect();
         task_pool
-            .spawn(save_blocks(database.clone(), block_updates))
+            .spawn(save_blocks(
+                database.clone(),
+                block_updates,
+                player_changed_chunks,
+            ))
             .detach();
+
+        *syncs += 1;
+        if *syncs % COMPACTION_INTERVAL == 0 {
+            let database = database.clone();
+            task_pool
+                .spawn(async move { database.compact_chunks() })
+                .detach();
+        }
     }
 
     if !exit_events.is_empty() {
         let block_updates = block_updates.drain().collect();
-        future::block_on(save_blocks(database.clone(), block_updates));
+        let player_changed_chunks = player_changed_chunks.drain().collect();
+        future::block_on(save_blocks(
+            database.clone(),
+            block_updates,
+            player_changed_chunks,
+        ));
     }
 }
 
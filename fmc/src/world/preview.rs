@@ -0,0 +1,375 @@
+use std::{path::PathBuf, sync::Arc};
+
+use bevy::{
+    app::AppExit,
+    tasks::{futures_lite::future, AsyncComputeTaskPool, Task},
+};
+
+use crate::{
+    chat::{self, ChatCommand},
+    networking::Server,
+    players::{Operator, Player},
+    prelude::*,
+    utils,
+    world::{
+        chunk::Chunk, map_tiles::chunk_column_surface, TerrainGenerator, WorldMap, WorldSpawn,
+    },
+};
+
+const DEFAULT_OUTPUT: &str = "./world_preview";
+
+// Renders top-down images of the terrain the world generator produces, without loading it into
+// the world. Used to tune the generator without having to fly around in game. Each image covers
+// a square of chunk columns, there is one with the color of the surface and one with its height.
+//
+// It can be done from the command line when starting the server:
+//     --preview-world <radius>      render this many chunks around the spawn
+//     --preview-output <directory>  where the images are written, "./world_preview" by default
+// The server exits when it is done, change the seed in the server's settings to preview other
+// worlds. Or by operators with the "/preview-world <radius>" command while the server is running.
+pub struct PreviewPlugin;
+impl Plugin for PreviewPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PreviewTasks>().add_systems(
+            Update,
+            (
+                preview_from_command_line.run_if(
+                    resource_exists::<WorldSpawn>.and(resource_exists::<CommandLinePreview>),
+                ),
+                handle_preview_commands,
+                finish_previews,
+            )
+                .chain(),
+        );
+
+        if let Some(arguments) = CommandLinePreview::parse(std::env::args()) {
+            app.insert_resource(arguments);
+        }
+    }
+}
+
+/// What area `preview_world` renders, and where to.
+#[derive(Clone, Debug)]
+pub struct PreviewOptions {
+    /// The block position the images are centered on, only x and z are used.
+    pub center: IVec3,
+    /// How many chunks to render in each direction from the center
+    pub radius: u32,
+    /// How many chunks wide each image is
+    pub tile_size: u32,
+    /// The surface is searched for downwards from this height
+    pub top: i32,
+    /// Columns with no surface above this height are left transparent
+    pub bottom: i32,
+    /// Directory the images are written to, it is created if it doesn't exist.
+    pub output: PathBuf,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            center: IVec3::ZERO,
+            radius: 32,
+            tile_size: 32,
+            top: 256,
+            bottom: -64,
+            output: PathBuf::from(DEFAULT_OUTPUT),
+        }
+    }
+}
+
+/// What `preview_world` rendered
+#[derive(Default, Debug)]
+pub struct PreviewReport {
+    /// The images that were written
+    pub images: Vec<PathBuf>,
+    /// How many chunks had to be generated
+    pub generated_chunks: usize,
+}
+
+/// Render the terrain around the center to images. The images of a tile are named after the
+/// block position of their north west corner, "<x>_<z>.png" for the surface colors, and
+/// "<x>_<z>_height.png" for the heights. `progress` is called with the number of tiles that are
+/// done and the total after each tile.
+pub fn preview_world(
+    terrain_generator: &dyn TerrainGenerator,
+    options: &PreviewOptions,
+    progress: impl Fn(usize, usize),
+) -> Result<PreviewReport, String> {
+    if options.tile_size == 0 {
+        return Err("The tile size must be at least one chunk".to_owned());
+    }
+
+    if options.bottom >= options.top {
+        return Err("The bottom of the preview must be below its top".to_owned());
+    }
+
+    std::fs::create_dir_all(&options.output).map_err(|e| {
+        format!(
+            "Could not create the directory {}: {}",
+            options.output.display(),
+            e
+        )
+    })?;
+
+    let size = Chunk::SIZE as i32;
+    let center = utils::world_position_to_chunk_position(options.center);
+    let radius = options.radius as i32 * size;
+    let tile_size = options.tile_size as i32 * size;
+
+    let tile_positions: Vec<IVec2> = (center.x - radius..=center.x + radius)
+        .step_by(tile_size as usize)
+        .flat_map(|x| {
+            (center.z - radius..=center.z + radius)
+                .step_by(tile_size as usize)
+                .map(move |z| IVec2::new(x, z))
+        })
+        .collect();
+
+    let end = IVec2::new(center.x + radius + size, center.z + radius + size);
+    let top = utils::world_position_to_chunk_position(IVec3::new(0, options.top, 0)).y;
+
+    let mut report = PreviewReport::default();
+
+    for (tile_index, tile_position) in tile_positions.iter().enumerate() {
+        // Tiles at the edge are cut off at the radius
+        let width = (end.x - tile_position.x).min(tile_size) as u32;
+        let depth = (end.y - tile_position.y).min(tile_size) as u32;
+
+        let mut colors = image::RgbaImage::new(width, depth);
+        let mut heights = vec![None; (width * depth) as usize];
+
+        for chunk_x in (0..width as i32).step_by(Chunk::SIZE) {
+            for chunk_z in (0..depth as i32).step_by(Chunk::SIZE) {
+                let mut unresolved: Vec<(usize, usize)> = (0..Chunk::SIZE)
+                    .flat_map(|x| (0..Chunk::SIZE).map(move |z| (x, z)))
+                    .collect();
+
+                let mut chunk_y = top;
+                while !unresolved.is_empty() && chunk_y + size > options.bottom {
+                    let chunk_position = IVec3::new(
+                        tile_position.x + chunk_x,
+                        chunk_y,
+                        tile_position.y + chunk_z,
+                    );
+                    let chunk = terrain_generator.generate_chunk(chunk_position);
+                    report.generated_chunks += 1;
+
+                    unresolved.retain(|(x, z)| {
+                        let Some((color, height)) = chunk_column_surface(&chunk, chunk_y, *x, *z)
+                        else {
+                            return true;
+                        };
+
+                        if height < options.bottom {
+                            return true;
+                        }
+
+                        let pixel_x = chunk_x as u32 + *x as u32;
+                        let pixel_z = chunk_z as u32 + *z as u32;
+                        colors.put_pixel(pixel_x, pixel_z, image::Rgba(color));
+                        heights[(pixel_z * width + pixel_x) as usize] = Some(height);
+                        return false;
+                    });
+
+                    chunk_y -= size;
+                }
+            }
+        }
+
+        shade_by_height(&mut colors, &heights);
+
+        let height_image = image::GrayImage::from_fn(width, depth, |x, z| {
+            let height = heights[(z * width + x) as usize].unwrap_or(options.bottom);
+            let fraction = (height - options.bottom) as f32 / (options.top - options.bottom) as f32;
+            image::Luma([(fraction.clamp(0.0, 1.0) * 255.0) as u8])
+        });
+
+        let name = format!("{}_{}", tile_position.x, tile_position.y);
+        let color_path = options.output.join(format!("{}.png", name));
+        let height_path = options.output.join(format!("{}_height.png", name));
+
+        colors
+            .save(&color_path)
+            .map_err(|e| format!("Could not write {}: {}", color_path.display(), e))?;
+        height_image
+            .save(&height_path)
+            .map_err(|e| format!("Could not write {}: {}", height_path.display(), e))?;
+
+        report.images.push(color_path);
+        report.images.push(height_path);
+
+        progress(tile_index + 1, tile_positions.len());
+    }
+
+    return Ok(report);
+}
+
+// Slopes are hard to make out from the colors alone. Blocks that are higher than the block to the
+// north of them are made lighter, and lower ones darker.
+fn shade_by_height(colors: &mut image::RgbaImage, heights: &[Option<i32>]) {
+    let width = colors.width();
+
+    for (x, z, pixel) in colors.enumerate_pixels_mut() {
+        if z == 0 {
+            continue;
+        }
+
+        let (Some(height), Some(north)) = (
+            heights[(z * width + x) as usize],
+            heights[((z - 1) * width + x) as usize],
+        ) else {
+            continue;
+        };
+
+        let shade = match height.cmp(&north) {
+            std::cmp::Ordering::Greater => 1.15,
+            std::cmp::Ordering::Less => 0.85,
+            std::cmp::Ordering::Equal => continue,
+        };
+
+        for channel in pixel.0.iter_mut().take(3) {
+            *channel = (*channel as f32 * shade).min(255.0) as u8;
+        }
+    }
+}
+
+#[derive(Resource)]
+struct CommandLinePreview {
+    radius: u32,
+    output: PathBuf,
+}
+
+impl CommandLinePreview {
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut radius = None;
+        let mut output = PathBuf::from(DEFAULT_OUTPUT);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--preview-world" => {
+                    let Some(r) = args.next().and_then(|radius| radius.parse().ok()) else {
+                        panic!("--preview-world needs a radius in chunks");
+                    };
+                    radius = Some(r);
+                }
+                "--preview-output" => {
+                    let Some(directory) = args.next() else {
+                        panic!("--preview-output needs a directory");
+                    };
+                    output = PathBuf::from(directory);
+                }
+                _ => (),
+            }
+        }
+
+        return Some(Self {
+            radius: radius?,
+            output,
+        });
+    }
+}
+
+// Runs once the spawn is known, then exits
+fn preview_from_command_line(
+    mut commands: Commands,
+    world_map: Res<WorldMap>,
+    spawn: Res<WorldSpawn>,
+    preview: Res<CommandLinePreview>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    commands.remove_resource::<CommandLinePreview>();
+
+    let options = PreviewOptions {
+        center: spawn.position,
+        radius: preview.radius,
+        output: preview.output.clone(),
+        ..default()
+    };
+
+    let progress = |done, total| info!("Rendered {} of {} preview tiles", done, total);
+
+    match preview_world(world_map.terrain_generator.as_ref(), &options, progress) {
+        Ok(report) => {
+            info!(
+                "Wrote {} images to {}, {} chunks were generated",
+                report.images.len(),
+                options.output.display(),
+                report.generated_chunks
+            );
+            exit_events.send(AppExit::Success);
+        }
+        Err(e) => {
+            error!("Could not preview the world: {}", e);
+            exit_events.send(AppExit::error());
+        }
+    }
+}
+
+// Previews that were started with the command, they take too long to render within a tick.
+#[derive(Resource, Default)]
+struct PreviewTasks(Vec<(Entity, PathBuf, Task<Result<PreviewReport, String>>)>);
+
+// "/preview-world <radius>", renders the area around the player to the default directory
+fn handle_preview_commands(
+    net: Res<Server>,
+    world_map: Res<WorldMap>,
+    mut preview_tasks: ResMut<PreviewTasks>,
+    operator_query: Query<&GlobalTransform, (With<Player>, With<Operator>)>,
+    mut command_events: EventReader<ChatCommand>,
+) {
+    const USAGE: &str = "Usage: /preview-world <radius>";
+
+    for command in command_events.read() {
+        if command.name != "preview-world" {
+            continue;
+        }
+
+        let Ok(transform) = operator_query.get(command.player_entity) else {
+            command.reply(&net, "Only operators can use this command.");
+            continue;
+        };
+
+        let Some(Ok(radius)) = command.args.first().map(|radius| radius.parse()) else {
+            command.reply(&net, USAGE);
+            continue;
+        };
+
+        let options = PreviewOptions {
+            center: transform.translation().floor().as_ivec3(),
+            radius,
+            ..default()
+        };
+
+        let terrain_generator: Arc<dyn TerrainGenerator> = world_map.terrain_generator.clone();
+        let output = options.output.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            preview_world(terrain_generator.as_ref(), &options, |done, total| {
+                info!("Rendered {} of {} preview tiles", done, total)
+            })
+        });
+
+        command.reply(&net, "Rendering the world preview...");
+        preview_tasks.0.push((command.player_entity, output, task));
+    }
+}
+
+fn finish_previews(net: Res<Server>, mut preview_tasks: ResMut<PreviewTasks>) {
+    preview_tasks.0.retain_mut(|(player_entity, output, task)| {
+        let Some(result) = future::block_on(future::poll_once(task)) else {
+            return true;
+        };
+
+        let text = match result {
+            Ok(report) => format!(
+                "Wrote {} preview images to {}",
+                report.images.len(),
+                output.display()
+            ),
+            Err(e) => format!("Could not preview the world: {}", e),
+        };
+        chat::send_private_message(&net, *player_entity, text);
+
+        return false;
+    });
+}
@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+
+use bevy::{math::DVec3, utils::HashSet};
+
+use crate::{players::Player, prelude::*, utils};
+
+use super::{chunk::Chunk, ForcedChunks};
+
+pub struct SimulationBudgetPlugin;
+impl Plugin for SimulationBudgetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationBudgetSettings>()
+            .init_resource::<SimulationBudget>()
+            .add_systems(First, start_tick)
+            .add_systems(Last, end_tick);
+    }
+}
+
+/// Knobs for the `SimulationBudget`.
+#[derive(Resource)]
+pub struct SimulationBudgetSettings {
+    /// How long a tick may take before the server starts simulating less. A tick is ~16ms, the
+    /// rest is left for networking and the os.
+    pub tick_budget: Duration,
+    /// Lowest the simulation scale goes when the server is overloaded. See
+    /// `SimulationBudget::scale`
+    pub min_scale: f32,
+    /// Chunks this close to a player are always simulated every tick, and so are forced chunks.
+    pub full_rate_distance: u32,
+    /// Most ticks an entity can go without an update
+    pub max_interval: u32,
+}
+
+impl Default for SimulationBudgetSettings {
+    fn default() -> Self {
+        Self {
+            tick_budget: Duration::from_millis(12),
+            min_scale: 0.1,
+            full_rate_distance: 4,
+            max_interval: 20,
+        }
+    }
+}
+
+/// Tracks how long ticks take. When they exceed the budget, systems that consult it update
+/// distant entities less often and do fewer random ticks in distant chunks, so that an
+/// overloaded server slows down gracefully instead of falling further and further behind.
+///
+/// Mob behaviours and other costly per entity updates should skip the tick when
+/// `should_simulate` is false.
+#[derive(Resource)]
+pub struct SimulationBudget {
+    tick: u64,
+    tick_start: Instant,
+    // Smoothed duration of the ticks
+    average_tick: Duration,
+    scale: f32,
+    // Chunk positions of all the players, updated at the start of each tick
+    player_chunks: Vec<IVec3>,
+    // Copy of the forced chunks, they are simulated as if a player was in them.
+    forced_chunks: HashSet<IVec3>,
+}
+
+impl Default for SimulationBudget {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            tick_start: Instant::now(),
+            average_tick: Duration::ZERO,
+            scale: 1.0,
+            player_chunks: Vec::new(),
+            forced_chunks: HashSet::default(),
+        }
+    }
+}
+
+impl SimulationBudget {
+    /// How much of the full simulation there is room for, 1.0 when the server keeps up, down to
+    /// `SimulationBudgetSettings::min_scale` when it is overloaded.
+    pub fn scale(&self) -> f32 {
+        return self.scale;
+    }
+
+    /// The smoothed duration of the ticks
+    pub fn average_tick(&self) -> Duration {
+        return self.average_tick;
+    }
+
+    /// Distance in chunks from the chunk to the closest player, u32::MAX if there are no players.
+    pub fn player_distance(&self, chunk_position: IVec3) -> u32 {
+        return self
+            .player_chunks
+            .iter()
+            .map(|player_chunk| {
+                let distance = (*player_chunk - chunk_position).abs() / Chunk::SIZE as i32;
+                distance.max_element() as u32
+            })
+            .min()
+            .unwrap_or(u32::MAX);
+    }
+
+    /// How many ticks go between each update of something in the chunk. It is 1 everywhere while
+    /// the server keeps up, and grows with the distance to the players when it doesn't.
+    pub fn update_interval(
+        &self,
+        chunk_position: IVec3,
+        settings: &SimulationBudgetSettings,
+    ) -> u32 {
+        if self.scale >= 1.0 || self.is_full_rate(chunk_position, settings) {
+            return 1;
+        }
+
+        let distance = self.player_distance(chunk_position);
+        let beyond = (distance - settings.full_rate_distance).min(settings.max_interval) as f32;
+        let interval = 1.0 + beyond * (1.0 / self.scale - 1.0);
+        return (interval.round() as u32).clamp(1, settings.max_interval);
+    }
+
+    /// If the entity should be simulated this tick. Entities are spread out over the ticks of
+    /// their interval so that they don't all update on the same tick.
+    pub fn should_simulate(
+        &self,
+        entity: Entity,
+        position: DVec3,
+        settings: &SimulationBudgetSettings,
+    ) -> bool {
+        let chunk_position = utils::world_position_to_chunk_position(position.as_ivec3());
+        let interval = self.update_interval(chunk_position, settings) as u64;
+        return (self.tick + entity.index() as u64) % interval == 0;
+    }
+
+    /// How many random ticks a chunk should get, out of the `base` it gets when the server keeps
+    /// up.
+    pub fn random_ticks(
+        &self,
+        base: u32,
+        chunk_position: IVec3,
+        settings: &SimulationBudgetSettings,
+    ) -> u32 {
+        if self.is_full_rate(chunk_position, settings) {
+            return base;
+        }
+
+        return (base as f32 * self.scale).round() as u32;
+    }
+
+    fn is_full_rate(&self, chunk_position: IVec3, settings: &SimulationBudgetSettings) -> bool {
+        return self.forced_chunks.contains(&chunk_position)
+            || self.player_distance(chunk_position) <= settings.full_rate_distance;
+    }
+}
+
+fn start_tick(
+    mut budget: ResMut<SimulationBudget>,
+    forced_chunks: Res<ForcedChunks>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+) {
+    budget.tick_start = Instant::now();
+    budget.tick += 1;
+
+    budget.player_chunks.clear();
+    budget
+        .player_chunks
+        .extend(player_query.iter().map(|transform| {
+            utils::world_position_to_chunk_position(transform.translation().as_ivec3())
+        }));
+
+    if forced_chunks.is_changed() {
+        budget.forced_chunks.clear();
+        budget.forced_chunks.extend(forced_chunks.iter().copied());
+    }
+}
+
+fn end_tick(settings: Res<SimulationBudgetSettings>, mut budget: ResMut<SimulationBudget>) {
+    let elapsed = budget.tick_start.elapsed();
+    budget.average_tick = budget.average_tick.mul_f32(0.9) + elapsed.mul_f32(0.1);
+
+    let was_overloaded = budget.scale < 1.0;
+
+    if budget.average_tick > settings.tick_budget {
+        budget.scale = (budget.scale * 0.95).max(settings.min_scale);
+    } else if budget.average_tick < settings.tick_budget.mul_f32(0.8) {
+        budget.scale = (budget.scale * 1.02).min(1.0);
+    }
+
+    if !was_overloaded && budget.scale < 1.0 {
+        warn!(
+            "Ticks are taking {}ms on average, reducing the simulation of distant entities.",
+            budget.average_tick.as_millis()
+        );
+    } else if was_overloaded && budget.scale >= 1.0 {
+        info!("The server is keeping up again, distant entities are simulated at full rate.");
+    }
+}
@@ -0,0 +1,176 @@
+use bevy::{
+    math::DVec3,
+    tasks::{futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::Database,
+    players::{find_spawn_point, RespawnSettings},
+    prelude::*,
+    world::WorldMap,
+};
+
+// Key the spawn is saved under in the database's storage table
+const STORAGE_KEY: &str = "world_spawn";
+const DEFAULT_PROTECTION_RADIUS: u32 = 16;
+
+// The world spawn is where new players appear. The area around it is protected so that only
+// operators can change it.
+pub struct SpawnPlugin;
+impl Plugin for SpawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SetSpawn>()
+            .init_resource::<SpawnSearch>()
+            // Blocks::get is used by the spawn search, it is only available after the blocks have
+            // been moved to the static.
+            .add_systems(
+                PostStartup,
+                load_spawn.after(crate::blocks::move_blocks_resource_to_static),
+            )
+            .add_systems(Update, (set_spawn, finish_spawn_search).chain());
+    }
+}
+
+/// The spawn point of the world, saved in the world database.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct WorldSpawn {
+    /// Position of the block players spawn on top of.
+    pub position: IVec3,
+    /// Blocks closer than this on the x and z axes to the spawn can only be changed by
+    /// operators. Set it to 0 to disable protection.
+    pub protection_radius: u32,
+}
+
+impl WorldSpawn {
+    /// Where the player should be placed to stand on the spawn block.
+    pub fn translation(&self) -> DVec3 {
+        return self.position.as_dvec3() + DVec3::new(0.5, 1.0, 0.5);
+    }
+
+    /// If the block is inside the protected area around the spawn.
+    pub fn is_protected(&self, block_position: IVec3) -> bool {
+        let radius = self.protection_radius as i32;
+        let distance = (block_position - self.position).abs();
+        return distance.x < radius && distance.z < radius;
+    }
+
+    /// If a player is allowed to change the block. Block changes made by players that aren't are
+    /// dropped, see `world::BlockPermissions`.
+    pub fn can_modify(&self, block_position: IVec3, is_operator: bool) -> bool {
+        return is_operator || !self.is_protected(block_position);
+    }
+}
+
+/// Send to move the world spawn. The change is saved to the database. The spawn is moved when the
+/// search for a safe position around it finishes.
+#[derive(Event, Default)]
+pub struct SetSpawn {
+    /// Where the spawn should be, a safe position will be searched for around it. If not set the
+    /// current spawn position is used, use this to recompute it after the terrain has changed.
+    pub position: Option<IVec3>,
+    /// New protection radius, keeps the current one if not set.
+    pub protection_radius: Option<u32>,
+}
+
+fn load_spawn(
+    mut commands: Commands,
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    settings: Res<RespawnSettings>,
+) {
+    let spawn = match database
+        .load_storage(STORAGE_KEY)
+        .and_then(|data| serde_json::from_str(&data).ok())
+    {
+        Some(spawn) => spawn,
+        None => {
+            let spawn = WorldSpawn {
+                position: find_safe_spawn(&world_map, &database, &settings, IVec3::ZERO)
+                    .unwrap_or(IVec3::ZERO),
+                protection_radius: DEFAULT_PROTECTION_RADIUS,
+            };
+            database.save_storage(STORAGE_KEY, &serde_json::to_string(&spawn).unwrap());
+            spawn
+        }
+    };
+
+    info!("World spawn is at {}", spawn.position);
+
+    commands.insert_resource(spawn);
+}
+
+// The search for the position the spawn is being moved to, (wanted position, search).
+#[derive(Resource, Default)]
+struct SpawnSearch(Option<(IVec3, Task<Option<IVec3>>)>);
+
+fn set_spawn(
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    settings: Res<RespawnSettings>,
+    mut spawn: ResMut<WorldSpawn>,
+    mut search: ResMut<SpawnSearch>,
+    mut set_spawn_events: EventReader<SetSpawn>,
+) {
+    for set_spawn in set_spawn_events.read() {
+        if let Some(radius) = set_spawn.protection_radius {
+            spawn.protection_radius = radius;
+            database.save_storage(STORAGE_KEY, &serde_json::to_string(&*spawn).unwrap());
+        }
+
+        // A search that is already running is replaced, dropping the task cancels it.
+        let wanted = set_spawn.position.unwrap_or(spawn.position);
+        let task = AsyncComputeTaskPool::get().spawn(find_spawn_point(
+            world_map.terrain_generator.clone(),
+            database.clone(),
+            settings.clone(),
+            wanted,
+        ));
+        search.0 = Some((wanted, task));
+    }
+}
+
+fn finish_spawn_search(
+    database: Res<Database>,
+    mut spawn: ResMut<WorldSpawn>,
+    mut search: ResMut<SpawnSearch>,
+) {
+    let Some((wanted, task)) = search.0.as_mut() else {
+        return;
+    };
+
+    let Some(result) = future::block_on(future::poll_once(task)) else {
+        return;
+    };
+
+    match result {
+        Some(position) => spawn.position = position,
+        None => {
+            warn!(
+                "Could not find a safe spawn position around {}, using it as is.",
+                wanted
+            );
+            spawn.position = *wanted;
+        }
+    }
+
+    search.0 = None;
+
+    database.save_storage(STORAGE_KEY, &serde_json::to_string(&*spawn).unwrap());
+}
+
+// Only used when the world is created, the server can't let players in before it has a spawn
+// anyway.
+fn find_safe_spawn(
+    world_map: &WorldMap,
+    database: &Database,
+    settings: &RespawnSettings,
+    around: IVec3,
+) -> Option<IVec3> {
+    return future::block_on(find_spawn_point(
+        world_map.terrain_generator.clone(),
+        database.clone(),
+        settings.clone(),
+        around,
+    ));
+}
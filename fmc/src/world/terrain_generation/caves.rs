@@ -0,0 +1,327 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use fmc_noise::Noise;
+
+use crate::{blocks::BlockId, utils, world::chunk::Chunk};
+
+use super::TerrainStage;
+
+// How many chunks away from where it starts a worm can reach, in all directions. Longer worms are
+// cut short. Every chunk generation walks the worms of (2 * WORM_REACH + 1)^3 chunks, so it can't
+// be large.
+const WORM_REACH: i32 = 3;
+
+/// Tunnels that wind through the terrain. Each chunk is the start of a random number of them.
+#[derive(Clone)]
+pub struct WormTunnels {
+    /// Average number of tunnels that start in each chunk, e.g. 0.2 for one every fifth chunk.
+    pub per_chunk: f32,
+    /// Shortest and longest tunnel in blocks
+    pub length: (u32, u32),
+    /// Smallest and largest radius of a tunnel, the radius widens and narrows a little along it.
+    pub radius: (f32, f32),
+    /// How much the radius favours small tunnels. 1.0 picks any radius in the range equally
+    /// often, higher values make wide tunnels rarer.
+    pub radius_bias: f32,
+    /// Tunnels only start between these heights
+    pub height_range: (i32, i32),
+}
+
+impl Default for WormTunnels {
+    fn default() -> Self {
+        Self {
+            per_chunk: 0.15,
+            length: (32, 96),
+            radius: (1.5, 4.0),
+            radius_bias: 2.0,
+            height_range: (-128, 48),
+        }
+    }
+}
+
+/// A carver stage that cuts caves out of the base terrain, open caverns from 3d noise, and
+/// tunnels that wind between them.
+///
+/// ```ignore
+/// let caves = CaveCarver::new(seed, blocks.get_id("air"), [stone, dirt, sand].into())
+///     .with_caverns(Noise::perlin(0.02, seed as u32).fbm(3, 0.5, 2.0), 0.6)
+///     .with_worms(WormTunnels::default())
+///     .with_fluid_level(blocks.get_id("lava"), -100)
+///     .with_protected(blocks.get_id("water"));
+/// pipeline.with_stage(TerrainStageKind::Carvers, caves);
+/// ```
+pub struct CaveCarver {
+    seed: u64,
+    air: BlockId,
+    // Blocks the caves can be cut out of.
+    can_carve: HashSet<BlockId>,
+    // Noise of the caverns and the value above which a block is carved
+    caverns: Option<(Noise, f32)>,
+    worms: Option<WormTunnels>,
+    // Carved blocks below the height are filled with the block instead of air, checked in order.
+    fluid_levels: Vec<(i32, BlockId)>,
+    // Blocks that a cave can't be carved next to, e.g. water so that oceans and rivers don't drain
+    // into the caves below.
+    protected: HashSet<BlockId>,
+    // Caves are only carved between these heights
+    height_range: (i32, i32),
+}
+
+impl CaveCarver {
+    pub fn new(seed: u64, air: BlockId, can_carve: HashSet<BlockId>) -> Self {
+        Self {
+            seed,
+            air,
+            can_carve,
+            caverns: None,
+            worms: None,
+            fluid_levels: Vec::new(),
+            protected: HashSet::new(),
+            height_range: (i32::MIN, i32::MAX),
+        }
+    }
+
+    /// Carve out blocks where the noise is above the threshold. The noise is sampled at world
+    /// coordinates.
+    pub fn with_caverns(mut self, noise: Noise, threshold: f32) -> Self {
+        self.caverns = Some((noise, threshold));
+        return self;
+    }
+
+    pub fn with_worms(mut self, worms: WormTunnels) -> Self {
+        self.worms = Some(worms);
+        return self;
+    }
+
+    /// Fill carved blocks below the height with the block instead of air, e.g. lava at the bottom
+    /// of the world or flooded caves. When there are several, the first that applies is used.
+    pub fn with_fluid_level(mut self, block_id: BlockId, height: i32) -> Self {
+        self.fluid_levels.push((height, block_id));
+        return self;
+    }
+
+    /// Don't carve next to this block.
+    pub fn with_protected(mut self, block_id: BlockId) -> Self {
+        self.protected.insert(block_id);
+        return self;
+    }
+
+    /// Only carve between these heights
+    pub fn with_height_range(mut self, min: i32, max: i32) -> Self {
+        self.height_range = (min, max);
+        return self;
+    }
+
+    fn carve(&self, chunk_position: IVec3, chunk: &mut Chunk, x: usize, y: usize, z: usize) {
+        let world_y = chunk_position.y + y as i32;
+        if world_y < self.height_range.0 || world_y > self.height_range.1 {
+            return;
+        }
+
+        let index = x << 8 | z << 4 | y;
+        if !self.can_carve.contains(&chunk[index]) {
+            return;
+        }
+
+        if !self.protected.is_empty() {
+            // Only the neighbours inside the chunk can be checked.
+            let position = IVec3::new(x as i32, y as i32, z as i32);
+            for offset in [
+                IVec3::X,
+                IVec3::NEG_X,
+                IVec3::Y,
+                IVec3::NEG_Y,
+                IVec3::Z,
+                IVec3::NEG_Z,
+            ] {
+                let neighbour = position + offset;
+                if neighbour.cmplt(IVec3::ZERO).any()
+                    || neighbour.cmpge(IVec3::splat(Chunk::SIZE as i32)).any()
+                {
+                    continue;
+                }
+                let neighbour_index = utils::world_position_to_block_index(neighbour);
+                if self.protected.contains(&chunk[neighbour_index]) {
+                    return;
+                }
+            }
+        }
+
+        let block_id = self
+            .fluid_levels
+            .iter()
+            .find(|(height, _)| world_y < *height)
+            .map(|(_, block_id)| *block_id)
+            .unwrap_or(self.air);
+
+        chunk[index] = block_id;
+    }
+
+    fn carve_caverns(&self, chunk_position: IVec3, chunk: &mut Chunk) {
+        let Some((noise, threshold)) = &self.caverns else {
+            return;
+        };
+
+        let (values, _, _) = noise.generate_3d(
+            chunk_position.x as f32,
+            chunk_position.y as f32,
+            chunk_position.z as f32,
+            Chunk::SIZE,
+            Chunk::SIZE,
+            Chunk::SIZE,
+        );
+
+        for x in 0..Chunk::SIZE {
+            for z in 0..Chunk::SIZE {
+                for y in 0..Chunk::SIZE {
+                    let index = x << 8 | z << 4 | y;
+                    if values[index] > *threshold {
+                        self.carve(chunk_position, chunk, x, y, z);
+                    }
+                }
+            }
+        }
+    }
+
+    fn carve_worms(&self, chunk_position: IVec3, chunk: &mut Chunk) {
+        let Some(worms) = &self.worms else {
+            return;
+        };
+
+        let chunk_min = chunk_position.as_vec3();
+        let chunk_max = chunk_min + Vec3::splat(Chunk::SIZE as f32);
+
+        let max_length = (WORM_REACH as f32 * Chunk::SIZE as f32 - worms.radius.1).max(0.0);
+
+        for x in -WORM_REACH..=WORM_REACH {
+            for y in -WORM_REACH..=WORM_REACH {
+                for z in -WORM_REACH..=WORM_REACH {
+                    let origin_chunk = chunk_position + IVec3::new(x, y, z) * Chunk::SIZE as i32;
+                    if origin_chunk.y + (Chunk::SIZE as i32) < worms.height_range.0
+                        || origin_chunk.y > worms.height_range.1
+                    {
+                        continue;
+                    }
+
+                    let mut rng = utils::Rng::from_position(self.seed, origin_chunk, "cave_worms");
+
+                    // The whole part of the average is always placed, the fraction is a chance for
+                    // one more.
+                    let mut count = worms.per_chunk.floor() as u32;
+                    if rng.next_f32() < worms.per_chunk.fract() {
+                        count += 1;
+                    }
+
+                    for _ in 0..count {
+                        self.walk_worm(
+                            worms,
+                            &mut rng,
+                            origin_chunk,
+                            max_length,
+                            |center, radius| {
+                                let min = center - radius;
+                                let max = center + radius;
+                                if max.cmplt(chunk_min).any() || min.cmpge(chunk_max).any() {
+                                    return;
+                                }
+
+                                let min = (min - chunk_min).floor().max(Vec3::ZERO).as_uvec3();
+                                let max = (max - chunk_min)
+                                    .ceil()
+                                    .min(Vec3::splat(Chunk::SIZE as f32 - 1.0))
+                                    .as_uvec3();
+
+                                for x in min.x..=max.x {
+                                    for y in min.y..=max.y {
+                                        for z in min.z..=max.z {
+                                            let block_center = chunk_min
+                                                + Vec3::new(x as f32, y as f32, z as f32)
+                                                + 0.5;
+                                            if block_center.distance_squared(center)
+                                                <= radius * radius
+                                            {
+                                                self.carve(
+                                                    chunk_position,
+                                                    chunk,
+                                                    x as usize,
+                                                    y as usize,
+                                                    z as usize,
+                                                );
+                                            }
+                                        }
+                                    }
+                                }
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Walks the path of a worm, calling 'step' with the center and radius of each step along it.
+    // The path only depends on the rng, so it is the same no matter which chunk is being carved.
+    fn walk_worm(
+        &self,
+        worms: &WormTunnels,
+        rng: &mut utils::Rng,
+        origin_chunk: IVec3,
+        max_length: f32,
+        mut step: impl FnMut(Vec3, f32),
+    ) {
+        let mut position = origin_chunk.as_vec3()
+            + Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * Chunk::SIZE as f32;
+
+        let (min_length, max_worm_length) = worms.length;
+        let length =
+            min_length as f32 + rng.next_f32() * max_worm_length.saturating_sub(min_length) as f32;
+        let length = length.min(max_length) as u32;
+
+        let (min_radius, max_radius) = worms.radius;
+        let radius =
+            min_radius + rng.next_f32().powf(worms.radius_bias) * (max_radius - min_radius);
+
+        let mut yaw = rng.next_f32() * std::f32::consts::TAU;
+        let mut pitch = (rng.next_f32() - 0.5) * 0.5;
+        let mut yaw_change = 0.0;
+        let mut pitch_change = 0.0;
+        let phase = rng.next_f32() * std::f32::consts::TAU;
+
+        for i in 0..length {
+            // The direction changes smoothly, the change itself is what's random.
+            yaw_change = yaw_change * 0.9 + (rng.next_f32() - 0.5) * 0.2;
+            pitch_change = pitch_change * 0.8 + (rng.next_f32() - 0.5) * 0.1;
+            yaw += yaw_change;
+            // Tunnels mostly go sideways, flattening out when steep.
+            pitch = (pitch * 0.95 + pitch_change).clamp(-1.0, 1.0);
+
+            position += Vec3::new(
+                yaw.cos() * pitch.cos(),
+                pitch.sin(),
+                yaw.sin() * pitch.cos(),
+            );
+
+            let radius = (radius * (1.0 + 0.25 * (i as f32 * 0.15 + phase).sin())).max(min_radius);
+            step(position, radius);
+        }
+    }
+}
+
+impl TerrainStage for CaveCarver {
+    fn apply(&self, chunk_position: IVec3, chunk: &mut Chunk) {
+        // Chunks of only air, or only water, have nothing to carve.
+        if chunk.is_uniform() && !self.can_carve.contains(&chunk[0]) {
+            return;
+        }
+
+        if chunk_position.y + (Chunk::SIZE as i32) < self.height_range.0
+            || chunk_position.y > self.height_range.1
+        {
+            return;
+        }
+
+        self.carve_caverns(chunk_position, chunk);
+        self.carve_worms(chunk_position, chunk);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bevy::prelude::*;
+
+use crate::world::chunk::Chunk;
+
+/// Caches data that is the same for all chunks in a column, like the 2d noise of a heightmap.
+///
+/// Chunks are generated one at a time, but most of what decides the shape of the terrain only
+/// varies along x and z. Instead of generating the same 2d noise for every chunk in the column, it
+/// is generated once and shared between the chunks, and between the density pass and the feature
+/// placement of each chunk.
+///
+/// ```ignore
+/// struct Generator {
+///     continents: Noise,
+///     heightmaps: ColumnCache<Vec<f32>>,
+/// }
+///
+/// impl TerrainGenerator for Generator {
+///     fn generate_chunk(&self, chunk_position: IVec3) -> Chunk {
+///         let heightmap = self.heightmaps.get_or_insert_with(chunk_position, || {
+///             self.continents
+///                 .generate_2d(chunk_position.x as f32, chunk_position.z as f32, Chunk::SIZE, Chunk::SIZE)
+///         });
+///         let height = heightmap[column_index(x, z)];
+///         ...
+///     }
+/// }
+/// ```
+///
+/// Chunks are generated on many threads at once, the cache can be shared between them. The least
+/// recently used columns are dropped when it is full.
+pub struct ColumnCache<T> {
+    capacity: usize,
+    inner: Mutex<CacheInner<T>>,
+}
+
+struct CacheInner<T> {
+    columns: HashMap<IVec2, (Arc<T>, u64)>,
+    // Increases on every access, columns are stamped with it to know which was used last.
+    clock: u64,
+}
+
+impl<T> ColumnCache<T> {
+    /// A cache that holds at most `capacity` columns. A player sees
+    /// (2 * render distance + 1)^2 columns, it should be a few times that to cover all the players.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            capacity,
+            inner: Mutex::new(CacheInner {
+                columns: HashMap::with_capacity(capacity),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Get the data of the column the chunk is part of, generating it if it's not cached.
+    pub fn get_or_insert_with(
+        &self,
+        chunk_position: IVec3,
+        generate: impl FnOnce() -> T,
+    ) -> Arc<T> {
+        let column = chunk_position.xz();
+
+        if let Some(data) = self.get(chunk_position) {
+            return data;
+        }
+
+        // The lock is not held while generating so that other threads are not held up by it. Two
+        // threads may end up generating the same column, the first to finish is kept.
+        let data = Arc::new(generate());
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        if let Some((existing, last_used)) = inner.columns.get_mut(&column) {
+            *last_used = clock;
+            return existing.clone();
+        }
+
+        if inner.columns.len() >= self.capacity {
+            let oldest = inner
+                .columns
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(column, _)| *column)
+                .unwrap();
+            inner.columns.remove(&oldest);
+        }
+
+        inner.columns.insert(column, (data.clone(), clock));
+
+        return data;
+    }
+
+    /// Get the data of the column the chunk is part of if it is cached.
+    pub fn get(&self, chunk_position: IVec3) -> Option<Arc<T>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        let (data, last_used) = inner.columns.get_mut(&chunk_position.xz())?;
+        *last_used = clock;
+        return Some(data.clone());
+    }
+
+    /// Remove all cached columns
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().columns.clear();
+    }
+}
+
+/// Index of the block column at x and z in the chunk, the same order `Surface` uses.
+pub fn column_index(x: usize, z: usize) -> usize {
+    return x * Chunk::SIZE + z;
+}
@@ -13,6 +13,15 @@ use crate::{
 use super::{chunk::Chunk, WorldMap};
 
 pub mod blueprints;
+mod caves;
+mod column_cache;
+mod ores;
+mod pipeline;
+
+pub use caves::{CaveCarver, WormTunnels};
+pub use column_cache::{column_index, ColumnCache};
+pub use ores::{HeightDistribution, OreDistribution, OreVein};
+pub use pipeline::{TerrainPipeline, TerrainStage, TerrainStageKind};
 
 pub trait TerrainGenerator: Send + Sync {
     fn generate_chunk(&self, position: IVec3) -> Chunk;
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{blocks::BlockId, utils, world::chunk::Chunk};
+
+use super::{TerrainFeature, TerrainStage};
+
+/// How the height of ore veins is distributed.
+#[derive(Clone, Copy)]
+pub enum HeightDistribution {
+    /// Equally common at all heights between min and max.
+    Uniform { min: i32, max: i32 },
+    /// Most common at the peak, getting rarer towards min and max.
+    Triangular { min: i32, peak: i32, max: i32 },
+}
+
+impl HeightDistribution {
+    fn sample(&self, rng: &mut utils::Rng) -> i32 {
+        match *self {
+            HeightDistribution::Uniform { min, max } => {
+                return min + (rng.next_f32() * (max - min + 1) as f32) as i32;
+            }
+            HeightDistribution::Triangular { min, peak, max } => {
+                let (min, peak, max) = (min as f32, peak as f32, max as f32);
+                if max <= min {
+                    return min as i32;
+                }
+                let value = rng.next_f32();
+                let split = (peak - min) / (max - min);
+                let height = if value < split {
+                    min + (value * (max - min) * (peak - min)).sqrt()
+                } else {
+                    max - ((1.0 - value) * (max - min) * (max - peak)).sqrt()
+                };
+                return height.round() as i32;
+            }
+        }
+    }
+}
+
+/// A kind of ore and how it is spread through the world.
+#[derive(Clone)]
+pub struct OreVein {
+    /// The block that is placed
+    pub block: BlockId,
+    /// Smallest and largest number of blocks in a vein
+    pub size: (u32, u32),
+    /// How many veins are attempted in each column of chunks, spread over the height
+    /// distribution. Veins that end up in blocks that can't be replaced are not placed.
+    pub per_column: u32,
+    pub height: HeightDistribution,
+    /// Which blocks the ore can be placed into.
+    pub can_replace: HashSet<BlockId>,
+}
+
+/// Places ore veins as terrain features. The veins are derived from the world seed and the
+/// position of the chunk, so the same world always gets the same ores.
+///
+/// Every chunk in a column rolls the same veins and keeps those whose height falls inside it, so
+/// how many veins a chunk gets follows the height distribution no matter how tall the world is.
+/// Veins that cross into other chunks are placed as edge features.
+///
+/// ```ignore
+/// let ores = OreDistribution::new(seed).with_vein(OreVein {
+///     block: blocks.get_id("iron_ore"),
+///     size: (4, 10),
+///     per_column: 20,
+///     height: HeightDistribution::Triangular { min: -128, peak: 16, max: 64 },
+///     can_replace: [stone].into(),
+/// });
+/// pipeline.with_stage(TerrainStageKind::Features, ores);
+/// ```
+pub struct OreDistribution {
+    seed: u64,
+    veins: Vec<OreVein>,
+}
+
+impl OreDistribution {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            veins: Vec::new(),
+        }
+    }
+
+    pub fn with_vein(mut self, vein: OreVein) -> Self {
+        self.veins.push(vein);
+        return self;
+    }
+
+    /// The veins that start in the chunk.
+    pub fn features(&self, chunk_position: IVec3) -> Vec<TerrainFeature> {
+        let mut features = Vec::new();
+
+        for (vein_index, vein) in self.veins.iter().enumerate() {
+            let mut rng = utils::Rng::from_position(
+                self.seed.wrapping_add(vein_index as u64),
+                chunk_position.with_y(0),
+                "ore_veins",
+            );
+
+            for _ in 0..vein.per_column {
+                // Everything is drawn for each attempt, so that the attempts after it draw the
+                // same numbers no matter which chunk in the column is generating.
+                let x = (rng.next_f32() * Chunk::SIZE as f32) as i32;
+                let z = (rng.next_f32() * Chunk::SIZE as f32) as i32;
+                let y = vein.height.sample(&mut rng);
+                let size_range = vein.size.1.saturating_sub(vein.size.0) + 1;
+                let size = vein.size.0 + (rng.next_f32() * size_range as f32) as u32;
+                let walk_seed = ((rng.next_u32() as u64) << 32) | rng.next_u32() as u64;
+
+                if y < chunk_position.y || y >= chunk_position.y + Chunk::SIZE as i32 {
+                    continue;
+                }
+
+                let origin = IVec3::new(chunk_position.x + x, y, chunk_position.z + z);
+                features.push(vein_feature(vein, origin, size, walk_seed));
+            }
+        }
+
+        return features;
+    }
+}
+
+impl TerrainStage for OreDistribution {
+    fn apply(&self, chunk_position: IVec3, chunk: &mut Chunk) {
+        for feature in self.features(chunk_position) {
+            feature.apply(chunk_position, chunk);
+        }
+    }
+}
+
+// A vein is a random walk from the origin, one block at a time.
+fn vein_feature(vein: &OreVein, origin: IVec3, size: u32, seed: u64) -> TerrainFeature {
+    const DIRECTIONS: [IVec3; 6] = [
+        IVec3::X,
+        IVec3::NEG_X,
+        IVec3::Y,
+        IVec3::NEG_Y,
+        IVec3::Z,
+        IVec3::NEG_Z,
+    ];
+
+    let mut rng = utils::Rng::new(seed);
+    let mut terrain_feature = TerrainFeature::default();
+    terrain_feature.can_replace.extend(vein.can_replace.iter());
+
+    let mut position = origin;
+    terrain_feature.insert_block(position, vein.block);
+    for _ in 1..size {
+        position += DIRECTIONS[rng.next_u32() as usize % DIRECTIONS.len()];
+        terrain_feature.insert_block(position, vein.block);
+    }
+
+    return terrain_feature;
+}
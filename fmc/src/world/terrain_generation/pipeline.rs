@@ -0,0 +1,122 @@
+use bevy::prelude::*;
+
+use crate::world::chunk::Chunk;
+
+use super::TerrainGenerator;
+
+/// A step of terrain generation that changes a chunk after its base shape has been generated.
+///
+/// Closures can be used directly as stages.
+pub trait TerrainStage: Send + Sync {
+    fn apply(&self, chunk_position: IVec3, chunk: &mut Chunk);
+}
+
+impl<F> TerrainStage for F
+where
+    F: Fn(IVec3, &mut Chunk) + Send + Sync,
+{
+    fn apply(&self, chunk_position: IVec3, chunk: &mut Chunk) {
+        self(chunk_position, chunk)
+    }
+}
+
+/// The standard stages of terrain generation, in the order they are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TerrainStageKind {
+    /// Replace the top layers of the base shape, e.g. stone with grass and dirt, or sand by the
+    /// water.
+    Surface,
+    /// Cut caves and ravines out of the terrain.
+    Carvers,
+    /// Place ores, trees, structures and other terrain features.
+    Features,
+    /// Anything that needs to see the finished terrain.
+    PostProcess,
+}
+
+impl TerrainStageKind {
+    const ALL: [Self; 4] = [
+        Self::Surface,
+        Self::Carvers,
+        Self::Features,
+        Self::PostProcess,
+    ];
+
+    fn index(self) -> usize {
+        return self as usize;
+    }
+}
+
+/// A terrain generator put together from stages, so a game can replace a single stage, e.g. its
+/// own caves, and keep using the rest.
+///
+/// The base shape is a regular `TerrainGenerator`, it decides where there are blocks and where
+/// there is air or water. Each stage is then applied to the chunk in the order of
+/// `TerrainStageKind`.
+///
+/// ```ignore
+/// let generator = TerrainPipeline::new(BaseShape::new(seed))
+///     .with_stage(TerrainStageKind::Surface, GrassSurface::new())
+///     .with_stage(TerrainStageKind::Carvers, MyCaves::new(seed));
+/// ```
+pub struct TerrainPipeline {
+    shape: Box<dyn TerrainGenerator>,
+    stages: [Vec<Box<dyn TerrainStage>>; 4],
+}
+
+impl TerrainPipeline {
+    pub fn new(shape: impl TerrainGenerator + 'static) -> Self {
+        Self {
+            shape: Box::new(shape),
+            stages: Default::default(),
+        }
+    }
+
+    /// Replace the base shape
+    pub fn with_shape(mut self, shape: impl TerrainGenerator + 'static) -> Self {
+        self.shape = Box::new(shape);
+        return self;
+    }
+
+    /// Replace the stage of this kind with a single stage.
+    pub fn with_stage(
+        mut self,
+        kind: TerrainStageKind,
+        stage: impl TerrainStage + 'static,
+    ) -> Self {
+        self.set_stage(kind, stage);
+        return self;
+    }
+
+    /// Add a stage that is applied after the stages already of this kind, e.g. several carvers.
+    pub fn add_stage(mut self, kind: TerrainStageKind, stage: impl TerrainStage + 'static) -> Self {
+        self.stages[kind.index()].push(Box::new(stage));
+        return self;
+    }
+
+    /// Replace the stage of this kind with a single stage.
+    pub fn set_stage(&mut self, kind: TerrainStageKind, stage: impl TerrainStage + 'static) {
+        let stages = &mut self.stages[kind.index()];
+        stages.clear();
+        stages.push(Box::new(stage));
+    }
+
+    /// Remove all stages of this kind, they will be skipped.
+    pub fn remove_stage(&mut self, kind: TerrainStageKind) {
+        self.stages[kind.index()].clear();
+    }
+}
+
+impl TerrainGenerator for TerrainPipeline {
+    fn generate_chunk(&self, chunk_position: IVec3) -> Chunk {
+        let mut chunk = self.shape.generate_chunk(chunk_position);
+
+        for kind in TerrainStageKind::ALL {
+            for stage in self.stages[kind.index()].iter() {
+                stage.apply(chunk_position, &mut chunk);
+            }
+        }
+
+        return chunk;
+    }
+}
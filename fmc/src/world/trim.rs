@@ -0,0 +1,332 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use bevy::{
+    app::AppExit,
+    tasks::{futures_lite::future, IoTaskPool, Task},
+};
+
+use crate::{
+    chat::{self, ChatCommand},
+    database::Database,
+    networking::Server,
+    players::{Operator, Player},
+    prelude::*,
+    utils,
+    world::{chunk::Chunk, map_tile_position, ForcedChunks, WorldMap, WorldSpawn},
+};
+
+// Removes saved chunks from the database to make the world smaller. Chunks that are removed are
+// generated again the next time they are loaded, any changes to them are lost.
+//
+// It can be done from the command line when starting the server:
+//     --trim-outside <radius>  remove chunks further than this many chunks from the spawn
+//     --trim-untouched         remove chunks no player has changed
+//     --dry-run                only report what would be removed
+// The server exits when it is done. Or by operators with the "/trim-world" command while the
+// server is running, then it is done in the background.
+pub struct TrimPlugin;
+impl Plugin for TrimPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TrimTasks>()
+            .init_resource::<KeptChunks>()
+            .add_systems(
+                Update,
+                (
+                    trim_from_command_line.run_if(
+                        resource_exists::<WorldSpawn>.and(resource_exists::<CommandLineTrim>),
+                    ),
+                    (handle_trim_commands, update_kept_chunks, finish_trims).chain(),
+                ),
+            );
+
+        if let Some(arguments) = CommandLineTrim::parse(std::env::args()) {
+            app.insert_resource(arguments);
+        }
+    }
+}
+
+/// Which chunks `trim_world` removes. A chunk is removed if it matches any of them.
+#[derive(Default, Clone, Debug)]
+pub struct TrimOptions {
+    /// Remove chunks further than this many chunks from the center on the x or z axis
+    pub outside: Option<(IVec3, u32)>,
+    /// Remove chunks no player has changed a block in and that have no block data, see
+    /// `Database::is_changed_by_players`.
+    pub untouched: bool,
+}
+
+/// What `trim_world` removed, or would remove on a dry run
+#[derive(Default, Debug)]
+pub struct TrimReport {
+    /// The chunks that were removed
+    pub removed: Vec<IVec3>,
+    /// How many saved chunks were kept
+    pub kept: usize,
+}
+
+/// Remove the saved chunks that match the options. Chunks for which `keep` returns true are
+/// never removed, e.g. chunks that are loaded.
+pub fn trim_world(
+    database: &Database,
+    options: &TrimOptions,
+    keep: impl Fn(IVec3) -> bool,
+    dry_run: bool,
+) -> Result<TrimReport, String> {
+    if options.outside.is_none() && !options.untouched {
+        return Err("Nothing to trim, give a radius or trim untouched chunks".to_owned());
+    }
+
+    let mut report = TrimReport::default();
+    let mut removed_columns = HashSet::new();
+
+    for position in database.saved_chunks() {
+        if keep(position) {
+            report.kept += 1;
+            continue;
+        }
+
+        let outside = options.outside.is_some_and(|(center, radius)| {
+            let distance = (position - utils::world_position_to_chunk_position(center)).abs()
+                / Chunk::SIZE as i32;
+            distance.x.max(distance.z) as u32 > radius
+        });
+
+        let untouched = options.untouched
+            && !database.is_changed_by_players(&position)
+            && database
+                .load_chunk_blocks(&position)
+                .values()
+                .all(|(_, _, block_data)| block_data.is_none());
+
+        if !outside && !untouched {
+            report.kept += 1;
+            continue;
+        }
+
+        if !dry_run {
+            database.delete_chunk(&position);
+        }
+        if outside {
+            removed_columns.insert(map_tile_position(position));
+        }
+        report.removed.push(position);
+    }
+
+    // The map tiles outside the radius would show the changes that were removed. Tiles of columns
+    // that still have chunks are kept, they are updated when the chunks load.
+    if !dry_run && !removed_columns.is_empty() {
+        for position in database.saved_chunks() {
+            removed_columns.remove(&map_tile_position(position));
+        }
+        for column in removed_columns {
+            database.delete_map_tile(&column);
+        }
+    }
+
+    return Ok(report);
+}
+
+#[derive(Resource)]
+struct CommandLineTrim {
+    outside: Option<u32>,
+    untouched: bool,
+    dry_run: bool,
+}
+
+impl CommandLineTrim {
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut trim = Self {
+            outside: None,
+            untouched: false,
+            dry_run: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--trim-outside" => {
+                    let Some(radius) = args.next().and_then(|radius| radius.parse().ok()) else {
+                        panic!("--trim-outside needs a radius in chunks");
+                    };
+                    trim.outside = Some(radius);
+                }
+                "--trim-untouched" => trim.untouched = true,
+                "--dry-run" => trim.dry_run = true,
+                _ => (),
+            }
+        }
+
+        if trim.outside.is_none() && !trim.untouched {
+            return None;
+        }
+
+        return Some(trim);
+    }
+}
+
+fn format_report(report: &TrimReport, dry_run: bool) -> String {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    return format!(
+        "{} {} of {} saved chunks",
+        verb,
+        report.removed.len(),
+        report.removed.len() + report.kept
+    );
+}
+
+// Runs once the spawn is known, then exits
+fn trim_from_command_line(
+    mut commands: Commands,
+    database: Res<Database>,
+    spawn: Res<WorldSpawn>,
+    trim: Res<CommandLineTrim>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    commands.remove_resource::<CommandLineTrim>();
+
+    let options = TrimOptions {
+        outside: trim.outside.map(|radius| (spawn.position, radius)),
+        untouched: trim.untouched,
+    };
+
+    match trim_world(&database, &options, |_| false, trim.dry_run) {
+        Ok(report) => {
+            info!("{}", format_report(&report, trim.dry_run));
+            if !trim.dry_run {
+                info!("Shrinking the database file...");
+                database.vacuum();
+            }
+            exit_events.send(AppExit::Success);
+        }
+        Err(e) => {
+            error!("Could not trim the world: {}", e);
+            exit_events.send(AppExit::error());
+        }
+    }
+}
+
+// Trims started with the command, going through all the saved chunks takes too long to do within
+// a tick. The bool is if it is a dry run.
+#[derive(Resource, Default)]
+struct TrimTasks(Vec<(Entity, bool, Task<Result<TrimReport, String>>)>);
+
+// Loaded and forced chunks, the trims that are running keep them. It is updated every tick while
+// they run, as chunks can be loaded in the meantime.
+#[derive(Resource, Default, Clone)]
+struct KeptChunks(Arc<RwLock<HashSet<IVec3>>>);
+
+// "/trim-world outside <radius> [confirm]" and "/trim-world untouched [confirm]". Without
+// "confirm" it only reports what would be removed. Loaded and forced chunks are kept.
+fn handle_trim_commands(
+    net: Res<Server>,
+    database: Res<Database>,
+    world_map: Res<WorldMap>,
+    forced_chunks: Res<ForcedChunks>,
+    kept_chunks: Res<KeptChunks>,
+    mut trim_tasks: ResMut<TrimTasks>,
+    spawn: Option<Res<WorldSpawn>>,
+    operator_query: Query<(), (With<Player>, With<Operator>)>,
+    mut command_events: EventReader<ChatCommand>,
+) {
+    const USAGE: &str = "Usage: /trim-world <outside <radius>|untouched> [confirm]";
+
+    for command in command_events.read() {
+        if command.name != "trim-world" {
+            continue;
+        }
+
+        if !operator_query.contains(command.player_entity) {
+            command.reply(&net, "Only operators can use this command.");
+            continue;
+        }
+
+        let args: Vec<&str> = command.args.iter().map(String::as_str).collect();
+        let (options, confirm) = match args.as_slice() {
+            ["outside", radius, rest @ ..] => {
+                let (Ok(radius), Some(spawn)) = (radius.parse(), &spawn) else {
+                    command.reply(&net, USAGE);
+                    continue;
+                };
+                let options = TrimOptions {
+                    outside: Some((spawn.position, radius)),
+                    untouched: false,
+                };
+                (options, rest == ["confirm"])
+            }
+            ["untouched", rest @ ..] => {
+                let options = TrimOptions {
+                    outside: None,
+                    untouched: true,
+                };
+                (options, rest == ["confirm"])
+            }
+            _ => {
+                command.reply(&net, USAGE);
+                continue;
+            }
+        };
+
+        if trim_tasks.0.is_empty() {
+            collect_kept_chunks(&kept_chunks, &world_map, &forced_chunks);
+        }
+
+        let database = database.clone();
+        let kept_chunks = kept_chunks.clone();
+        let dry_run = !confirm;
+        let task = IoTaskPool::get().spawn(async move {
+            let keep = |position: IVec3| kept_chunks.0.read().unwrap().contains(&position);
+            trim_world(&database, &options, keep, dry_run)
+        });
+
+        command.reply(&net, "Trimming the world...");
+        trim_tasks.0.push((command.player_entity, dry_run, task));
+    }
+}
+
+fn collect_kept_chunks(
+    kept_chunks: &KeptChunks,
+    world_map: &WorldMap,
+    forced_chunks: &ForcedChunks,
+) {
+    let mut kept = kept_chunks.0.write().unwrap();
+    kept.clear();
+    kept.extend(world_map.chunk_positions());
+    kept.extend(forced_chunks.iter());
+}
+
+fn update_kept_chunks(
+    world_map: Res<WorldMap>,
+    forced_chunks: Res<ForcedChunks>,
+    kept_chunks: Res<KeptChunks>,
+    trim_tasks: Res<TrimTasks>,
+) {
+    if !trim_tasks.0.is_empty() {
+        collect_kept_chunks(&kept_chunks, &world_map, &forced_chunks);
+    }
+}
+
+fn finish_trims(net: Res<Server>, mut trim_tasks: ResMut<TrimTasks>) {
+    trim_tasks.0.retain_mut(|(player_entity, dry_run, task)| {
+        let Some(result) = future::block_on(future::poll_once(task)) else {
+            return true;
+        };
+
+        match result {
+            Ok(report) => {
+                chat::send_private_message(&net, *player_entity, format_report(&report, *dry_run));
+                if *dry_run && !report.removed.is_empty() {
+                    chat::send_private_message(
+                        &net,
+                        *player_entity,
+                        "Add 'confirm' to the command to remove them.",
+                    );
+                }
+            }
+            Err(e) => chat::send_private_message(&net, *player_entity, e),
+        }
+
+        return false;
+    });
+}
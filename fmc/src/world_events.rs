@@ -0,0 +1,505 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::tasks::IoTaskPool;
+use fmc_protocol::messages;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::{ChatCommand, CHAT_FONT_SIZE},
+    database::Database,
+    networking::Server,
+    players::Operator,
+    prelude::*,
+};
+
+pub const WORLD_EVENT_PATH: &str = "./assets/server/world_events/";
+
+const STORAGE_KEY: &str = "world_events";
+const ANNOUNCEMENT_COLOR: &str = "#ffaa00";
+// Seconds between each time the triggers are checked
+const TRIGGER_INTERVAL: f32 = 1.0;
+// Seconds after its time of day that a daily event can still start, e.g. if the server was
+// starting up when it was supposed to.
+const DAILY_GRACE: u64 = 60;
+
+// World events are server wide happenings like meteor showers or invasions. They are defined in
+// json, one file per event, and started by their trigger, by an operator with /event, or by the
+// game. What happens during an event is up to the game, it can listen for `WorldEventStarted` and
+// `WorldEventEnded`, and run its systems with the `world_event_active` condition in between.
+// Active events are saved, and resumed when the server restarts.
+pub struct WorldEventPlugin;
+impl Plugin for WorldEventPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StartWorldEvent>()
+            .add_event::<StopWorldEvent>()
+            .add_event::<WorldEventStarted>()
+            .add_event::<WorldEventEnded>()
+            .insert_resource(TriggerTimer(Timer::from_seconds(
+                TRIGGER_INTERVAL,
+                TimerMode::Repeating,
+            )))
+            .add_systems(PreStartup, load_world_events)
+            .add_systems(Startup, load_active_world_events)
+            .add_systems(
+                Update,
+                (
+                    (check_triggers, handle_world_event_commands),
+                    start_world_events,
+                    end_world_events,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Start a world event. Ignored if it is already active.
+#[derive(Event)]
+pub struct StartWorldEvent {
+    /// Name of the event, its filename without the extension
+    pub name: String,
+}
+
+/// End an active world event before its duration is up.
+#[derive(Event)]
+pub struct StopWorldEvent {
+    pub name: String,
+}
+
+/// Sent when a world event starts.
+#[derive(Event)]
+pub struct WorldEventStarted {
+    pub name: String,
+    /// The event was already active when the server shut down, and continues where it left off.
+    pub resumed: bool,
+}
+
+/// Sent when a world event ends, either because its duration is up or it was stopped.
+#[derive(Event)]
+pub struct WorldEventEnded {
+    pub name: String,
+}
+
+/// ```json
+/// {
+///     "start_message": "Meteors are falling from the sky!",
+///     "end_message": "The meteor shower has passed.",
+///     "duration": 300,
+///     "trigger": {
+///         "type": "chance",
+///         "interval": 3600,
+///         "chance": 0.25
+///     }
+/// }
+/// ```
+#[derive(Deserialize, Debug)]
+pub struct WorldEvent {
+    /// Shown in the chat of all players when the event starts
+    #[serde(default)]
+    pub start_message: Option<String>,
+    /// Shown in the chat of all players when the event ends
+    #[serde(default)]
+    pub end_message: Option<String>,
+    /// Seconds the event lasts, events without a duration last until they are stopped.
+    #[serde(default)]
+    pub duration: Option<u64>,
+    /// What starts the event, in addition to /event and `StartWorldEvent`.
+    #[serde(default)]
+    pub trigger: Trigger,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Only started by an operator or the game
+    #[default]
+    Manual,
+    /// Starts when this many seconds have passed since the event last ended.
+    Interval { seconds: u64 },
+    /// Every `interval` seconds there is a `chance` the event starts, from 0.0 to 1.0.
+    Chance { interval: u64, chance: f64 },
+    /// Starts every day at the time of day, in UTC.
+    Daily { hour: u32, minute: u32 },
+}
+
+/// The world events of the server, by name.
+#[derive(Resource, Default)]
+pub struct WorldEvents(HashMap<String, WorldEvent>);
+
+impl WorldEvents {
+    pub fn get(&self, name: &str) -> Option<&WorldEvent> {
+        return self.0.get(name);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        return self.0.keys();
+    }
+}
+
+/// An event that is currently going on. Times are in seconds since the unix epoch.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ActiveWorldEvent {
+    pub started: u64,
+    /// None if the event lasts until it is stopped
+    pub ends: Option<u64>,
+}
+
+impl ActiveWorldEvent {
+    /// Seconds since the event started
+    pub fn elapsed(&self) -> u64 {
+        return now().saturating_sub(self.started);
+    }
+}
+
+/// The world events that are currently active, and when each event last started and ended. It
+/// is saved whenever an event starts or ends.
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct ActiveWorldEvents {
+    active: HashMap<String, ActiveWorldEvent>,
+    last_started: HashMap<String, u64>,
+    last_ended: HashMap<String, u64>,
+}
+
+impl ActiveWorldEvents {
+    pub fn is_active(&self, name: &str) -> bool {
+        return self.active.contains_key(name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ActiveWorldEvent> {
+        return self.active.get(name);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ActiveWorldEvent)> {
+        return self.active.iter();
+    }
+
+    fn save(&self, database: &Database) {
+        let data = serde_json::to_string(self).unwrap();
+        let database = database.clone();
+        IoTaskPool::get()
+            .spawn(async move { database.save_storage(STORAGE_KEY, &data) })
+            .detach();
+    }
+}
+
+/// Run condition for systems that should only run while a world event is active.
+///
+/// ```ignore
+/// app.add_systems(Update, drop_meteors.run_if(world_event_active("meteor_shower")));
+/// ```
+pub fn world_event_active(name: &'static str) -> impl Fn(Res<ActiveWorldEvents>) -> bool {
+    return move |active_events: Res<ActiveWorldEvents>| active_events.is_active(name);
+}
+
+#[derive(Resource, Deref, DerefMut)]
+struct TriggerTimer(Timer);
+
+fn now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+}
+
+fn load_world_events(mut commands: Commands) {
+    let mut world_events = WorldEvents::default();
+
+    // Servers are not required to have any world events.
+    let Ok(directory) = std::fs::read_dir(WORLD_EVENT_PATH) else {
+        commands.insert_resource(world_events);
+        return;
+    };
+
+    for entry in directory {
+        let file_path = entry
+            .expect("Failed to read the filenames of the world event configs")
+            .path();
+        let name = file_path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+
+        let file = match std::fs::File::open(&file_path) {
+            Ok(f) => f,
+            Err(e) => panic!(
+                "Failed to open world event config at: {}\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        let world_event: WorldEvent = match serde_json::from_reader(&file) {
+            Ok(c) => c,
+            Err(e) => panic!(
+                "Couldn't read world event config from '{}'\nError: {}",
+                file_path.display(),
+                e
+            ),
+        };
+
+        if let Trigger::Daily { hour, minute } = world_event.trigger {
+            if hour >= 24 || minute >= 60 {
+                panic!(
+                    "Failed to parse world event config at: {}\nError: {:02}:{:02} is not a time of day.",
+                    file_path.display(),
+                    hour,
+                    minute
+                );
+            }
+        }
+
+        world_events.0.insert(name, world_event);
+    }
+
+    commands.insert_resource(world_events);
+}
+
+fn load_active_world_events(
+    mut commands: Commands,
+    database: Res<Database>,
+    world_events: Res<WorldEvents>,
+    mut started_events: EventWriter<WorldEventStarted>,
+) {
+    let mut active_events: ActiveWorldEvents = database
+        .load_storage(STORAGE_KEY)
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+
+    // Events that have been removed from the assets are forgotten.
+    active_events
+        .active
+        .retain(|name, _| world_events.get(name).is_some());
+
+    // Interval events count from when they are first seen, instead of starting immediately.
+    let now = now();
+    for (name, world_event) in world_events.0.iter() {
+        if matches!(world_event.trigger, Trigger::Interval { .. }) {
+            active_events.last_ended.entry(name.clone()).or_insert(now);
+        }
+    }
+
+    // Events that ended while the server was down are ended in the first update.
+    for name in active_events.active.keys() {
+        started_events.send(WorldEventStarted {
+            name: name.clone(),
+            resumed: true,
+        });
+    }
+
+    active_events.save(&database);
+    commands.insert_resource(active_events);
+}
+
+fn check_triggers(
+    time: Res<Time>,
+    world_events: Res<WorldEvents>,
+    active_events: Res<ActiveWorldEvents>,
+    mut timer: ResMut<TriggerTimer>,
+    mut start_events: EventWriter<StartWorldEvent>,
+    // When each chance triggered event was last rolled
+    mut last_rolls: Local<HashMap<String, u64>>,
+) {
+    timer.tick(time.delta());
+    if !timer.just_finished() {
+        return;
+    }
+
+    let now = now();
+
+    for (name, world_event) in world_events.0.iter() {
+        if active_events.is_active(name) {
+            continue;
+        }
+
+        let should_start = match world_event.trigger {
+            Trigger::Manual => false,
+            Trigger::Interval { seconds } => active_events
+                .last_ended
+                .get(name)
+                .map_or(true, |last_ended| now >= last_ended + seconds),
+            Trigger::Chance { interval, chance } => {
+                let last_roll = last_rolls.entry(name.clone()).or_insert(now);
+                if now >= *last_roll + interval {
+                    *last_roll = now;
+                    rand::random::<f64>() < chance
+                } else {
+                    false
+                }
+            }
+            Trigger::Daily { hour, minute } => {
+                let scheduled = now - now % 86400 + hour as u64 * 3600 + minute as u64 * 60;
+                now >= scheduled
+                    && now < scheduled + DAILY_GRACE
+                    && active_events
+                        .last_started
+                        .get(name)
+                        .map_or(true, |last_started| *last_started < scheduled)
+            }
+        };
+
+        if should_start {
+            start_events.send(StartWorldEvent { name: name.clone() });
+        }
+    }
+}
+
+fn handle_world_event_commands(
+    net: Res<Server>,
+    world_events: Res<WorldEvents>,
+    active_events: Res<ActiveWorldEvents>,
+    operator_query: Query<(), With<Operator>>,
+    mut command_events: EventReader<ChatCommand>,
+    mut start_events: EventWriter<StartWorldEvent>,
+    mut stop_events: EventWriter<StopWorldEvent>,
+) {
+    for command in command_events.read() {
+        if command.name != "event" {
+            continue;
+        }
+
+        if !operator_query.contains(command.player_entity) {
+            command.reply(&net, "You are not allowed to manage world events.");
+            continue;
+        }
+
+        match (
+            command.args.first().map(String::as_str),
+            command.args.get(1),
+        ) {
+            (Some("list"), None) => {
+                let mut names: Vec<&String> = world_events.names().collect();
+                names.sort();
+                for name in names {
+                    if let Some(active) = active_events.get(name) {
+                        command.reply(&net, format!("{} (active for {}s)", name, active.elapsed()));
+                    } else {
+                        command.reply(&net, name.clone());
+                    }
+                }
+            }
+            (Some("start"), Some(name)) => {
+                if world_events.get(name).is_none() {
+                    command.reply(&net, format!("There is no world event named '{}'", name));
+                } else if active_events.is_active(name) {
+                    command.reply(&net, format!("'{}' is already active", name));
+                } else {
+                    start_events.send(StartWorldEvent { name: name.clone() });
+                }
+            }
+            (Some("stop"), Some(name)) => {
+                if active_events.is_active(name) {
+                    stop_events.send(StopWorldEvent { name: name.clone() });
+                } else {
+                    command.reply(&net, format!("'{}' is not active", name));
+                }
+            }
+            _ => command.reply(&net, "Usage: /event <list|start|stop> [name]"),
+        }
+    }
+}
+
+fn announce(net: &Server, text: &str) {
+    net.broadcast(messages::InterfaceTextUpdate {
+        interface_path: "chat/history".to_owned(),
+        index: i32::MAX,
+        text: text.to_owned(),
+        font_size: CHAT_FONT_SIZE,
+        color: ANNOUNCEMENT_COLOR.to_owned(),
+    });
+}
+
+fn start_world_events(
+    net: Res<Server>,
+    database: Res<Database>,
+    world_events: Res<WorldEvents>,
+    mut active_events: ResMut<ActiveWorldEvents>,
+    mut start_events: EventReader<StartWorldEvent>,
+    mut started_events: EventWriter<WorldEventStarted>,
+) {
+    let mut changed = false;
+
+    for start in start_events.read() {
+        let Some(world_event) = world_events.get(&start.name) else {
+            error!(
+                "Tried to start the world event '{}', but there is no event by that name.",
+                start.name
+            );
+            continue;
+        };
+
+        if active_events.is_active(&start.name) {
+            continue;
+        }
+
+        let now = now();
+        active_events.active.insert(
+            start.name.clone(),
+            ActiveWorldEvent {
+                started: now,
+                ends: world_event.duration.map(|duration| now + duration),
+            },
+        );
+        active_events.last_started.insert(start.name.clone(), now);
+        changed = true;
+
+        if let Some(message) = &world_event.start_message {
+            announce(&net, message);
+        }
+
+        started_events.send(WorldEventStarted {
+            name: start.name.clone(),
+            resumed: false,
+        });
+    }
+
+    if changed {
+        active_events.save(&database);
+    }
+}
+
+fn end_world_events(
+    net: Res<Server>,
+    database: Res<Database>,
+    world_events: Res<WorldEvents>,
+    mut active_events: ResMut<ActiveWorldEvents>,
+    mut stop_events: EventReader<StopWorldEvent>,
+    mut ended_events: EventWriter<WorldEventEnded>,
+) {
+    let now = now();
+
+    let mut ended: Vec<String> = active_events
+        .active
+        .iter()
+        .filter(|(_, active)| active.ends.is_some_and(|ends| ends <= now))
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    for stop in stop_events.read() {
+        if active_events.is_active(&stop.name) && !ended.contains(&stop.name) {
+            ended.push(stop.name.clone());
+        }
+    }
+
+    if ended.is_empty() {
+        return;
+    }
+
+    for name in ended {
+        active_events.active.remove(&name);
+        active_events.last_ended.insert(name.clone(), now);
+
+        if let Some(message) = world_events
+            .get(&name)
+            .and_then(|world_event| world_event.end_message.as_ref())
+        {
+            announce(&net, message);
+        }
+
+        ended_events.send(WorldEventEnded { name });
+    }
+
+    active_events.save(&database);
+}
@@ -0,0 +1,377 @@
+// Imports the blocks of a Minecraft world into an fmc world database, so existing builds can be
+// moved to an fmc server. Only worlds saved by Minecraft 1.18 or later are supported.
+//
+// Minecraft block names are translated with a mapping file, a json object from Minecraft names
+// to fmc block names:
+//
+// {
+//     "minecraft:stone": "stone",
+//     "minecraft:grass_block": "grass",
+//     "minecraft:oak_log[axis=y]": "oak_log",
+//     "*": "stone"
+// }
+//
+// Names with properties are tried before the bare name, and "*" is used for any block that isn't
+// mapped. Blocks without a mapping are left out, those positions are generated as usual. Block
+// states like rotation are not imported.
+//
+// The database must have been used by the server once, so that it has the ids of the server's
+// blocks. The server should not be running while importing.
+mod nbt;
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use fmc::{
+    bevy::math::IVec3,
+    blocks::{BlockData, BlockId, BlockState},
+    database::Database,
+    utils,
+    world::chunk::Chunk,
+};
+
+#[derive(Parser)]
+#[clap(about, long_about = None)]
+struct Cli {
+    /// The "region" directory of the Minecraft world
+    region_directory: PathBuf,
+    /// Json file mapping Minecraft block names to fmc block names
+    #[arg(short, long)]
+    mapping: PathBuf,
+    /// The fmc world database
+    #[arg(short, long, default_value = "./world.sqlite")]
+    database: String,
+    /// Moves the imported blocks, must be a multiple of 16 on every axis
+    #[arg(short, long, num_args = 3, value_names = ["X", "Y", "Z"], allow_hyphen_values = true)]
+    offset: Option<Vec<i32>>,
+}
+
+// Minecraft's names for air, they are imported as fmc's air unless the mapping says otherwise.
+const AIR_NAMES: [&str; 3] = ["minecraft:air", "minecraft:cave_air", "minecraft:void_air"];
+
+type ChunkBlocks = HashMap<usize, (BlockId, Option<BlockState>, Option<BlockData>)>;
+
+struct Mapping {
+    names: HashMap<String, BlockId>,
+    fallback: Option<BlockId>,
+    // How many blocks of each Minecraft name had no mapping
+    unmapped: HashMap<String, usize>,
+}
+
+impl Mapping {
+    fn load(path: &Path, block_ids: &HashMap<String, BlockId>) -> Result<Self, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Could not open the mapping file: {}", e))?;
+        let mapping: HashMap<String, String> = serde_json::from_reader(file)
+            .map_err(|e| format!("Could not read the mapping file: {}", e))?;
+
+        let mut names = HashMap::new();
+        if let Some(air) = block_ids.get("air") {
+            for name in AIR_NAMES {
+                names.insert(name.to_owned(), *air);
+            }
+        }
+
+        let mut fallback = None;
+        for (minecraft_name, fmc_name) in mapping {
+            let Some(block_id) = block_ids.get(&fmc_name) else {
+                return Err(format!(
+                    "'{}' is mapped to '{}', but the server has no block by that name",
+                    minecraft_name, fmc_name
+                ));
+            };
+
+            if minecraft_name == "*" {
+                fallback = Some(*block_id);
+            } else {
+                names.insert(minecraft_name, *block_id);
+            }
+        }
+
+        return Ok(Self {
+            names,
+            fallback,
+            unmapped: HashMap::new(),
+        });
+    }
+
+    // Palette entries look like {Name: "minecraft:oak_log", Properties: {axis: "y"}}
+    fn block_id(&mut self, palette_entry: &nbt::Tag) -> Option<BlockId> {
+        let name = palette_entry.get("Name")?.as_str()?;
+
+        if let Some(properties) = palette_entry
+            .get("Properties")
+            .and_then(|properties| properties.as_compound())
+        {
+            let mut properties: Vec<String> = properties
+                .iter()
+                .filter_map(|(key, value)| Some(format!("{}={}", key, value.as_str()?)))
+                .collect();
+            properties.sort();
+            let full_name = format!("{}[{}]", name, properties.join(","));
+            if let Some(block_id) = self.names.get(&full_name) {
+                return Some(*block_id);
+            }
+        }
+
+        if let Some(block_id) = self.names.get(name).or(self.fallback.as_ref()) {
+            return Some(*block_id);
+        }
+
+        return None;
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let offset = match cli.offset.as_deref() {
+        Some([x, y, z]) => IVec3::new(*x, *y, *z),
+        _ => IVec3::ZERO,
+    };
+    if offset % Chunk::SIZE as i32 != IVec3::ZERO {
+        eprintln!(
+            "The offset must be a multiple of {} on every axis",
+            Chunk::SIZE
+        );
+        std::process::exit(1);
+    }
+
+    if !Path::new(&cli.database).exists() {
+        eprintln!(
+            "No database at '{}', start the server once to create it.",
+            cli.database
+        );
+        std::process::exit(1);
+    }
+    let database = Database::new(cli.database.clone());
+
+    let block_ids = database.load_block_ids();
+    if block_ids.is_empty() {
+        eprintln!("The database has no blocks, start the server once to save them.");
+        std::process::exit(1);
+    }
+
+    let mut mapping = match Mapping::load(&cli.mapping, &block_ids) {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let region_files = match std::fs::read_dir(&cli.region_directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "mca"))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            eprintln!("Could not read the region directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut chunk_count = 0;
+    for path in region_files.iter() {
+        match import_region(path, offset, &database, &mut mapping) {
+            Ok(count) => {
+                chunk_count += count;
+                println!("{}: imported {} chunks", path.display(), count);
+            }
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+
+    println!(
+        "Imported {} chunks from {} region files",
+        chunk_count,
+        region_files.len()
+    );
+
+    if !mapping.unmapped.is_empty() {
+        let mut unmapped: Vec<_> = mapping.unmapped.into_iter().collect();
+        unmapped.sort_by(|a, b| b.1.cmp(&a.1));
+        println!("These blocks were not mapped and were left out:");
+        for (name, count) in unmapped {
+            println!("    {} ({} blocks)", name, count);
+        }
+    }
+}
+
+// Region files hold 32x32 chunk columns. They start with a table of where each column is in the
+// file, in 4KiB sectors, followed by the compressed columns.
+fn import_region(
+    path: &Path,
+    offset: IVec3,
+    database: &Database,
+    mapping: &mut Mapping,
+) -> Result<usize, String> {
+    const SECTOR_SIZE: usize = 4096;
+
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    if bytes.len() < SECTOR_SIZE {
+        // Regions without any chunks can be empty
+        return Ok(0);
+    }
+
+    let mut count = 0;
+    for location in bytes[..SECTOR_SIZE].chunks_exact(4) {
+        let sector = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize;
+        if sector == 0 {
+            continue;
+        }
+
+        let start = sector * SECTOR_SIZE;
+        let Some(header) = bytes.get(start..start + 5) else {
+            return Err("A chunk is outside the file, it is corrupt".to_owned());
+        };
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let compression = header[4];
+        let Some(data) = bytes.get(start + 5..start + 4 + length) else {
+            return Err("A chunk is outside the file, it is corrupt".to_owned());
+        };
+
+        let mut decompressed = Vec::new();
+        let result = match compression {
+            1 => flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed),
+            2 => flate2::read::ZlibDecoder::new(data).read_to_end(&mut decompressed),
+            3 => {
+                decompressed.extend_from_slice(data);
+                Ok(data.len())
+            }
+            // Chunks that are too large are stored in separate files, they are rare enough that
+            // they are not supported.
+            _ => {
+                eprintln!(
+                    "Skipped a chunk with unsupported compression {}",
+                    compression
+                );
+                continue;
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Skipped a chunk that could not be decompressed: {}", e);
+            continue;
+        }
+
+        let column = nbt::read(&decompressed)?;
+        if import_column(&column, offset, database, mapping)? {
+            count += 1;
+        }
+    }
+
+    return Ok(count);
+}
+
+// Returns false if the column was not fully generated and was left out
+fn import_column(
+    column: &nbt::Tag,
+    offset: IVec3,
+    database: &Database,
+    mapping: &mut Mapping,
+) -> Result<bool, String> {
+    if column.get("Level").is_some() {
+        return Err("The world was saved by a version older than Minecraft 1.18".to_owned());
+    }
+
+    let status = column.get("Status").and_then(|status| status.as_str());
+    if !matches!(status, Some("full" | "minecraft:full")) {
+        return Ok(false);
+    }
+
+    let (Some(x), Some(z), Some(sections)) = (
+        column.get("xPos").and_then(|x| x.as_i64()),
+        column.get("zPos").and_then(|z| z.as_i64()),
+        column
+            .get("sections")
+            .and_then(|sections| sections.as_list()),
+    ) else {
+        return Err("A chunk is missing its position or sections".to_owned());
+    };
+
+    for section in sections {
+        let (Some(y), Some(block_states)) = (
+            section.get("Y").and_then(|y| y.as_i64()),
+            section.get("block_states"),
+        ) else {
+            // The sections above and below the world only have lighting
+            continue;
+        };
+
+        let origin = IVec3::new(x as i32, y as i32, z as i32) * Chunk::SIZE as i32 + offset;
+        let blocks = read_section(block_states, origin, mapping)?;
+        if !blocks.is_empty() {
+            database.save_chunk_blocks(&origin, blocks);
+        }
+    }
+
+    return Ok(true);
+}
+
+// A section is 16x16x16 blocks, stored as indices into a palette. The indices are packed into
+// longs with as few bits as the palette needs, but at least 4, and don't cross from one long into
+// the next. A section with only one block in its palette has no data.
+fn read_section(
+    block_states: &nbt::Tag,
+    origin: IVec3,
+    mapping: &mut Mapping,
+) -> Result<ChunkBlocks, String> {
+    let Some(palette) = block_states
+        .get("palette")
+        .and_then(|palette| palette.as_list())
+    else {
+        return Err("A section has no palette".to_owned());
+    };
+
+    let palette_ids: Vec<Option<BlockId>> = palette
+        .iter()
+        .map(|entry| mapping.block_id(entry))
+        .collect();
+    let data = block_states
+        .get("data")
+        .and_then(|data| data.as_long_array())
+        .unwrap_or_default();
+
+    let bits = (usize::BITS - (palette.len().max(1) - 1).leading_zeros()).max(4) as usize;
+    let per_long = 64 / bits;
+
+    let mut blocks = ChunkBlocks::new();
+    for index in 0..Chunk::SIZE.pow(3) {
+        let palette_index = if palette.len() == 1 {
+            0
+        } else {
+            let Some(long) = data.get(index / per_long) else {
+                return Err("A section has too little block data".to_owned());
+            };
+            ((*long as u64 >> ((index % per_long) * bits)) & ((1 << bits) - 1)) as usize
+        };
+
+        let Some(entry) = palette.get(palette_index) else {
+            return Err("A section refers to a block outside its palette".to_owned());
+        };
+
+        let Some(block_id) = palette_ids[palette_index] else {
+            if let Some(name) = entry.get("Name").and_then(|name| name.as_str()) {
+                *mapping.unmapped.entry(name.to_owned()).or_default() += 1;
+            }
+            continue;
+        };
+
+        // Minecraft orders the blocks y, z, x from the outermost
+        let position = IVec3::new(
+            (index % Chunk::SIZE) as i32,
+            (index / Chunk::SIZE.pow(2)) as i32,
+            (index / Chunk::SIZE % Chunk::SIZE) as i32,
+        );
+        blocks.insert(
+            utils::world_position_to_block_index(origin + position),
+            (block_id, None, None),
+        );
+    }
+
+    return Ok(blocks);
+}
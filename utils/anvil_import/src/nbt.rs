@@ -0,0 +1,194 @@
+// Minimal reader for Minecraft's NBT format, only what is needed to read the blocks of a chunk.
+// All numbers are big endian.
+use std::collections::HashMap;
+
+// Only some of the values are read, the rest are kept so the whole document can be parsed.
+#[allow(dead_code)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        return match self {
+            Tag::Compound(compound) => compound.get(name),
+            _ => None,
+        };
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        return match self {
+            Tag::Byte(value) => Some(*value as i64),
+            Tag::Short(value) => Some(*value as i64),
+            Tag::Int(value) => Some(*value as i64),
+            Tag::Long(value) => Some(*value),
+            _ => None,
+        };
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        return match self {
+            Tag::String(value) => Some(value),
+            _ => None,
+        };
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        return match self {
+            Tag::List(list) => Some(list),
+            _ => None,
+        };
+    }
+
+    pub fn as_compound(&self) -> Option<&HashMap<String, Tag>> {
+        return match self {
+            Tag::Compound(compound) => Some(compound),
+            _ => None,
+        };
+    }
+
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        return match self {
+            Tag::LongArray(array) => Some(array),
+            _ => None,
+        };
+    }
+}
+
+/// Read the root compound of an NBT document
+pub fn read(bytes: &[u8]) -> Result<Tag, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let id = reader.u8()?;
+    if id != 10 {
+        return Err(format!("The root tag is not a compound, it has id {}", id));
+    }
+    // The root's name is always empty
+    reader.string()?;
+    return reader.payload(id, 0);
+}
+
+// Deeper than this and the document is assumed to be malformed
+const MAX_DEPTH: usize = 512;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < count {
+            return Err("Unexpected end of data".to_owned());
+        }
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        return Ok(taken);
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        return Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()));
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        return Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()));
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        return Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()));
+    }
+
+    fn length(&mut self) -> Result<usize, String> {
+        let length = self.i32()?;
+        // Each element is at least a byte, a length longer than the data is malformed and would
+        // allocate too much.
+        if length < 0 || length as usize > self.bytes.len() - self.position {
+            return Err(format!("Invalid length {}", length));
+        }
+        return Ok(length as usize);
+    }
+
+    // Strings are "modified utf-8", which only differs for null and characters outside the basic
+    // plane. Neither are expected in block names.
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.i16()? as u16 as usize;
+        return Ok(String::from_utf8_lossy(self.take(length)?).into_owned());
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> Result<Tag, String> {
+        if depth > MAX_DEPTH {
+            return Err("Tags are nested too deep".to_owned());
+        }
+
+        let tag = match id {
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => Tag::Short(self.i16()?),
+            3 => Tag::Int(self.i32()?),
+            4 => Tag::Long(self.i64()?),
+            5 => Tag::Float(f32::from_bits(self.i32()? as u32)),
+            6 => Tag::Double(f64::from_bits(self.i64()? as u64)),
+            7 => {
+                let length = self.length()?;
+                Tag::ByteArray(self.take(length)?.iter().map(|b| *b as i8).collect())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let element_id = self.u8()?;
+                let length = self.length()?;
+                let mut list = Vec::with_capacity(length);
+                // Empty lists may have the end tag as their element type
+                if element_id != 0 {
+                    for _ in 0..length {
+                        list.push(self.payload(element_id, depth + 1)?);
+                    }
+                }
+                Tag::List(list)
+            }
+            10 => {
+                let mut compound = HashMap::new();
+                loop {
+                    let element_id = self.u8()?;
+                    if element_id == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    compound.insert(name, self.payload(element_id, depth + 1)?);
+                }
+                Tag::Compound(compound)
+            }
+            11 => {
+                let length = self.length()?;
+                let mut array = Vec::with_capacity(length);
+                for _ in 0..length {
+                    array.push(self.i32()?);
+                }
+                Tag::IntArray(array)
+            }
+            12 => {
+                let length = self.length()?;
+                let mut array = Vec::with_capacity(length);
+                for _ in 0..length {
+                    array.push(self.i64()?);
+                }
+                Tag::LongArray(array)
+            }
+            _ => return Err(format!("Unknown tag id {}", id)),
+        };
+
+        return Ok(tag);
+    }
+}